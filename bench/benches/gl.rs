@@ -0,0 +1,54 @@
+//! Time spent in `glFlush()` on a captured pbuffer
+
+use std::env;
+use std::time::Duration;
+
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, BenchmarkId, Criterion};
+use pw_capture_bench::*;
+use pw_capture_client::ConsumerOptions;
+
+fn bench_app(group: &mut BenchmarkGroup<WallTime>, id: BenchmarkId, app: &mut GlApp) {
+    group.bench_function(id, |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| app.frame()).sum::<Duration>())
+    });
+}
+
+fn flush(c: &mut Criterion) {
+    // read by the layer once the first pbuffer got created
+    env::set_var("PW_CAPTURE_OFFSCREEN", "1");
+    let with_layer = gl_layer_loaded();
+    if !with_layer {
+        eprintln!("libpw_capture_gl.so not preloaded, benchmarking without layer only");
+    }
+
+    let mut group = c.benchmark_group("glFlush");
+    for &(width, height) in RESOLUTIONS {
+        let size = format!("{width}x{height}");
+        let mut app = match GlApp::new(width, height) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("skipping {size}: {e:?}");
+                continue;
+            }
+        };
+        if !with_layer {
+            bench_app(&mut group, BenchmarkId::new("no_layer", &size), &mut app);
+            continue;
+        }
+        bench_app(&mut group, BenchmarkId::new("idle", &size), &mut app);
+
+        let consumer = Consumer::connect(ConsumerOptions::default(), || {
+            app.frame();
+            Ok(())
+        });
+        match consumer {
+            Ok(_consumer) => bench_app(&mut group, BenchmarkId::new("consumer", &size), &mut app),
+            Err(e) => eprintln!("skipping {size} with consumer: {e:?}"),
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, flush);
+criterion_main!(benches);
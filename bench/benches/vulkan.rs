@@ -0,0 +1,73 @@
+//! Time spent in `vkQueuePresentKHR` with and without the layer
+
+use std::time::Duration;
+
+use anyhow::Result;
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, BenchmarkId, Criterion};
+use pw_capture_bench::*;
+use pw_capture_client::ConsumerOptions;
+
+const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+
+fn bench_app(group: &mut BenchmarkGroup<WallTime>, id: BenchmarkId, app: &mut VkApp) {
+    group.bench_function(id, |b| {
+        b.iter_custom(|iters| {
+            (0..iters)
+                .map(|_| app.frame().expect("failed to present"))
+                .sum::<Duration>()
+        })
+    });
+}
+
+fn present(c: &mut Criterion) {
+    let with_layer = match target_dir().and_then(|dir| add_layer_path(&dir)) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{e}, benchmarking without layer only");
+            false
+        }
+    };
+
+    let mut group = c.benchmark_group("vkQueuePresentKHR");
+    for &(width, height) in RESOLUTIONS {
+        let size = format!("{width}x{height}");
+        let run = |group: &mut BenchmarkGroup<WallTime>, name: &str, layer: bool| -> Result<()> {
+            let mut app = VkApp::new(width, height, layer)?;
+            bench_app(group, BenchmarkId::new(name, &size), &mut app);
+            Ok(())
+        };
+        if let Err(e) = run(&mut group, "no_layer", false) {
+            eprintln!("skipping {size}: {e:?}");
+            continue;
+        }
+        if !with_layer {
+            continue;
+        }
+        if let Err(e) = run(&mut group, "idle", true) {
+            eprintln!("skipping {size} with layer: {e:?}");
+            continue;
+        }
+
+        let mut app = match VkApp::new(width, height, true) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("skipping {size} with layer: {e:?}");
+                continue;
+            }
+        };
+        let options = ConsumerOptions {
+            modifiers: vec![DRM_FORMAT_MOD_LINEAR],
+            ..Default::default()
+        };
+        let consumer = Consumer::connect(options, || app.frame().map(drop));
+        match consumer {
+            Ok(_consumer) => bench_app(&mut group, BenchmarkId::new("consumer", &size), &mut app),
+            Err(e) => eprintln!("skipping {size} with consumer: {e:?}"),
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, present);
+criterion_main!(benches);
@@ -0,0 +1,112 @@
+//! Consumer pulling frames of capture nodes created by the bench itself
+
+use std::cell::{Cell, RefCell};
+use std::process;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use pipewire as pw;
+use pw_capture_client::{ConsumerOptions, MockConsumer};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Keeps pulling frames of a capture node until dropped
+pub struct Consumer {
+    _consumer: MockConsumer,
+}
+
+impl Consumer {
+    /// Links to the newest capture node of this process, `render` is called
+    /// until the node shows up and the first frame got consumed
+    pub fn connect<F>(options: ConsumerOptions, mut render: F) -> Result<Self>
+    where
+        F: FnMut() -> Result<()>,
+    {
+        pw::init();
+        let deadline = Instant::now() + TIMEOUT;
+        let node_id = loop {
+            render()?;
+            if let Some(node_id) = own_capture_nodes()?.into_iter().max() {
+                break node_id;
+            }
+            if Instant::now() > deadline {
+                return Err(anyhow!("no capture node created"));
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        let consumer = MockConsumer::connect(node_id, options)?;
+        // frames are copied once buffers got negotiated
+        loop {
+            render()?;
+            if consumer.wait_frames(1, POLL_INTERVAL).is_ok() {
+                break;
+            }
+            if Instant::now() > deadline {
+                return Err(anyhow!("no frame consumed from node {node_id}"));
+            }
+        }
+
+        Ok(Self {
+            _consumer: consumer,
+        })
+    }
+}
+
+/// Runs main loop until the server processed all previous requests
+fn roundtrip(mainloop: &pw::main_loop::MainLoop, core: &pw::core::Core) -> Result<()> {
+    let done = Rc::new(Cell::new(false));
+    let pending = core.sync(0)?;
+    let _listener = core
+        .add_listener_local()
+        .done({
+            let done = done.clone();
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id == pw::core::PW_ID_CORE && seq == pending {
+                    done.set(true);
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+    while !done.get() {
+        mainloop.run();
+    }
+    Ok(())
+}
+
+/// Ids of capture nodes the layers created in this process
+fn own_capture_nodes() -> Result<Vec<u32>> {
+    let pid = process::id().to_string();
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+    let registry = core.get_registry()?;
+
+    let nodes = Rc::new(RefCell::new(vec![]));
+    let _registry_listener = registry
+        .add_listener_local()
+        .global({
+            let nodes = nodes.clone();
+            move |global| {
+                let Some(props) = global.props else {
+                    return;
+                };
+                if global.type_ == pw::types::ObjectType::Node
+                    && props.get(*pw::keys::MEDIA_SOFTWARE) == Some("pw-capture")
+                    && props.get(*pw::keys::APP_PROCESS_ID) == Some(pid.as_str())
+                {
+                    nodes.borrow_mut().push(global.id);
+                }
+            }
+        })
+        .register();
+    roundtrip(&mainloop, &core)?;
+
+    let nodes = nodes.take();
+    Ok(nodes)
+}
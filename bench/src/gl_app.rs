@@ -0,0 +1,145 @@
+//! GL app rendering cleared frames into an EGL pbuffer
+
+use std::ffi::CString;
+use std::fs;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use libc::c_void;
+use pw_capture_gl_sys::prelude::*;
+
+const CLEAR_COLOR: [f32; 4] = [0.2, 0.4, 0.8, 1.0];
+
+/// Whether the GL layer got preloaded into this process
+pub fn gl_layer_loaded() -> bool {
+    fs::read_to_string("/proc/self/maps").map_or(false, |maps| maps.contains("libpw_capture_gl"))
+}
+
+pub struct GlApp {
+    egl: Egl,
+    gl: Gl,
+    dpy: egl_t::EGLDisplay,
+    surface: egl_t::EGLSurface,
+    context: egl_t::EGLContext,
+    width: i32,
+    height: i32,
+}
+
+impl GlApp {
+    /// Creates a `width`x`height` pbuffer with a GLES2 context current on it
+    pub fn new(width: u32, height: u32) -> Result<Self> {
+        unsafe { Self::new_internal(width as _, height as _) }
+    }
+
+    unsafe fn new_internal(width: i32, height: i32) -> Result<Self> {
+        let lib = libc::dlopen(
+            b"libEGL.so.1\0".as_ptr() as _,
+            libc::RTLD_NOW | libc::RTLD_GLOBAL,
+        );
+        if lib.is_null() {
+            return Err(anyhow!("failed to load libEGL.so.1"));
+        }
+        // symbols resolved through dlsym() and eglGetProcAddress() get hooked
+        let egl = Egl::load_with(|name| {
+            let name = CString::new(name).unwrap();
+            libc::dlsym(lib, name.as_ptr()) as *const c_void
+        });
+        let gl = Gl::load_with(|name| {
+            let name = CString::new(name).unwrap();
+            egl.GetProcAddress(name.as_ptr()) as *const c_void
+        });
+
+        let dpy = egl.GetDisplay(ptr::null_mut());
+        if dpy.is_null() {
+            return Err(anyhow!("no EGL display"));
+        }
+        let (mut major, mut minor) = (0, 0);
+        if egl.Initialize(dpy, &mut major, &mut minor) != egl_sys::TRUE {
+            return Err(anyhow!("failed to initialize EGL"));
+        }
+
+        let config_attribs = [
+            egl_sys::SURFACE_TYPE as i32,
+            egl_sys::PBUFFER_BIT as _,
+            egl_sys::RENDERABLE_TYPE as _,
+            egl_sys::OPENGL_ES2_BIT as _,
+            egl_sys::RED_SIZE as _,
+            8,
+            egl_sys::GREEN_SIZE as _,
+            8,
+            egl_sys::BLUE_SIZE as _,
+            8,
+            egl_sys::NONE as _,
+        ];
+        let mut config: egl_t::EGLConfig = ptr::null();
+        let mut num: i32 = 0;
+        egl.ChooseConfig(dpy, config_attribs.as_ptr(), &mut config, 1, &mut num);
+        if num == 0 {
+            return Err(anyhow!("no pbuffer config"));
+        }
+
+        let surface_attribs = [
+            egl_sys::WIDTH as i32,
+            width,
+            egl_sys::HEIGHT as _,
+            height,
+            egl_sys::NONE as _,
+        ];
+        let surface = egl.CreatePbufferSurface(dpy, config, surface_attribs.as_ptr());
+        if surface.is_null() {
+            return Err(anyhow!("failed to create pbuffer"));
+        }
+
+        egl.BindAPI(egl_sys::OPENGL_ES_API);
+        let context_attribs = [
+            egl_sys::CONTEXT_CLIENT_VERSION as i32,
+            2,
+            egl_sys::NONE as _,
+        ];
+        let context = egl.CreateContext(dpy, config, ptr::null(), context_attribs.as_ptr());
+        if context.is_null() {
+            egl.DestroySurface(dpy, surface);
+            return Err(anyhow!("failed to create context"));
+        }
+        egl.MakeCurrent(dpy, surface, surface, context);
+
+        Ok(Self {
+            egl,
+            gl,
+            dpy,
+            surface,
+            context,
+            width,
+            height,
+        })
+    }
+
+    /// Renders a frame, returns the time `glFlush()` took
+    pub fn frame(&mut self) -> Duration {
+        let [r, g, b, a] = CLEAR_COLOR;
+        unsafe {
+            self.gl.Viewport(0, 0, self.width, self.height);
+            self.gl.ClearColor(r, g, b, a);
+            self.gl.Clear(gl_sys::COLOR_BUFFER_BIT);
+            let start = Instant::now();
+            self.gl.Flush();
+            start.elapsed()
+        }
+    }
+}
+
+impl Drop for GlApp {
+    fn drop(&mut self) {
+        unsafe {
+            self.egl.MakeCurrent(
+                self.dpy,
+                egl_sys::NO_SURFACE,
+                egl_sys::NO_SURFACE,
+                egl_sys::NO_CONTEXT,
+            );
+            self.egl.DestroyContext(self.dpy, self.context);
+            self.egl.DestroySurface(self.dpy, self.surface);
+        }
+    }
+}
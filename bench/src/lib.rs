@@ -0,0 +1,28 @@
+//! Harness measuring the time layers add to each frame
+
+mod consumer;
+mod gl_app;
+mod vk_app;
+
+pub use consumer::*;
+pub use gl_app::*;
+pub use vk_app::*;
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+/// Sizes frames are rendered at
+pub const RESOLUTIONS: &[(u32, u32)] = &[(1280, 720), (1920, 1080), (3840, 2160)];
+
+/// Target directory of the running bench, where layer libraries of the same
+/// profile are built into
+pub fn target_dir() -> Result<PathBuf> {
+    let exe = env::current_exe()?;
+    // benches are built into `deps` of the profile directory
+    exe.parent()
+        .and_then(Path::parent)
+        .map(Path::to_owned)
+        .ok_or_else(|| anyhow!("no target directory of {exe:?}"))
+}
@@ -0,0 +1,425 @@
+//! Vulkan app presenting cleared frames to a headless surface
+
+use core::ffi::{c_void, CStr};
+use core::slice;
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use ash::extensions::{ext, khr};
+use ash::vk;
+
+const LAYER_NAME: &str = "VK_LAYER_EH5_pwcapture";
+const VALIDATION_LAYER_NAME: &str = "VK_LAYER_KHRONOS_validation";
+const FRAMES_IN_FLIGHT: usize = 2;
+const CLEAR_COLOR: [f32; 4] = [0.2, 0.4, 0.8, 1.0];
+
+/// Makes the layer built into `target_dir` available as explicit layer, must
+/// be called before instances get created
+pub fn add_layer_path(target_dir: &Path) -> Result<()> {
+    let library = target_dir.join("libpw_capture_vk.so");
+    if !library.exists() {
+        return Err(anyhow!("{library:?} not found, build pw-capture-vk first"));
+    }
+    let dir = env::temp_dir().join(format!("pw-capture-bench-{}", process::id()));
+    fs::create_dir_all(&dir)?;
+    let manifest = format!(
+        r#"{{
+  "file_format_version": "1.2.0",
+  "layer": {{
+    "name": "{LAYER_NAME}",
+    "type": "GLOBAL",
+    "library_path": {library:?},
+    "api_version": "1.1.0",
+    "implementation_version": "1",
+    "description": "PipeWire Vulkan Capture"
+  }}
+}}"#
+    );
+    fs::write(dir.join("pw_capture_bench.json"), manifest)?;
+    env::set_var("VK_ADD_LAYER_PATH", &dir);
+    Ok(())
+}
+
+/// Validation errors reported while the app is running
+struct Validation {
+    debug_utils: ext::DebugUtils,
+    messenger: vk::DebugUtilsMessengerEXT,
+    /// Pointed to by the messenger callback
+    errors: Box<Mutex<Vec<String>>>,
+}
+
+pub struct VkApp {
+    _entry: ash::Entry,
+    instance: ash::Instance,
+    validation: Option<Validation>,
+    khr_surface: khr::Surface,
+    surface: vk::SurfaceKHR,
+    device: ash::Device,
+    khr_swapchain: khr::Swapchain,
+    swapchain: vk::SwapchainKHR,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+    /// Clearing each swapchain image
+    command_buffers: Vec<vk::CommandBuffer>,
+    /// Per frame in flight
+    acquired: Vec<vk::Semaphore>,
+    fences: Vec<vk::Fence>,
+    /// Per swapchain image
+    rendered: Vec<vk::Semaphore>,
+    frame: usize,
+}
+
+impl VkApp {
+    /// Creates a `width`x`height` swapchain, with the capture layer enabled if
+    /// `with_layer`
+    pub fn new(width: u32, height: u32, with_layer: bool) -> Result<Self> {
+        unsafe { Self::new_internal(width, height, with_layer, false) }
+    }
+
+    /// Like [`VkApp::new`], with `VK_LAYER_KHRONOS_validation` enabled below
+    /// the capture layer, so calls of both the app and the layer get
+    /// validated, see [`VkApp::validation_errors`]
+    pub fn new_validated(width: u32, height: u32, with_layer: bool) -> Result<Self> {
+        unsafe { Self::new_internal(width, height, with_layer, true) }
+    }
+
+    unsafe fn new_internal(
+        width: u32,
+        height: u32,
+        with_layer: bool,
+        validate: bool,
+    ) -> Result<Self> {
+        let entry = ash::Entry::load()?;
+        let app_name = CString::new("pw-capture-bench")?;
+        let app_info = vk::ApplicationInfo::builder()
+            .application_name(&app_name)
+            .api_version(vk::API_VERSION_1_1);
+        let layer_name = CString::new(LAYER_NAME)?;
+        let validation_layer_name = CString::new(VALIDATION_LAYER_NAME)?;
+        // layers listed first are closer to the app
+        let mut layers = vec![];
+        if with_layer {
+            layers.push(layer_name.as_ptr());
+        }
+        if validate {
+            layers.push(validation_layer_name.as_ptr());
+        }
+        let mut extensions = vec![
+            khr::Surface::name().as_ptr(),
+            ext::HeadlessSurface::name().as_ptr(),
+        ];
+        if validate {
+            extensions.push(ext::DebugUtils::name().as_ptr());
+        }
+
+        let errors = Box::new(Mutex::new(vec![]));
+        let mut messenger_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+            .message_type(
+                vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            )
+            .pfn_user_callback(Some(on_validation_message))
+            .user_data(&*errors as *const _ as *mut c_void);
+        let mut create_info = vk::InstanceCreateInfo::builder()
+            .application_info(&app_info)
+            .enabled_layer_names(&layers)
+            .enabled_extension_names(&extensions);
+        // also covers creating and destroying the instance
+        if validate {
+            create_info = create_info.push_next(&mut messenger_info);
+        }
+        let instance = entry.create_instance(&create_info, None)?;
+        let validation = if validate {
+            let debug_utils = ext::DebugUtils::new(&entry, &instance);
+            let messenger = debug_utils.create_debug_utils_messenger(&messenger_info, None)?;
+            Some(Validation {
+                debug_utils,
+                messenger,
+                errors,
+            })
+        } else {
+            None
+        };
+
+        let khr_surface = khr::Surface::new(&entry, &instance);
+        let surface = ext::HeadlessSurface::new(&entry, &instance)
+            .create_headless_surface(&vk::HeadlessSurfaceCreateInfoEXT::default(), None)?;
+
+        let (phy_device, family_index) = instance
+            .enumerate_physical_devices()?
+            .into_iter()
+            .find_map(|phy_device| {
+                instance
+                    .get_physical_device_queue_family_properties(phy_device)
+                    .iter()
+                    .enumerate()
+                    .find(|&(index, props)| {
+                        props.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+                            && khr_surface
+                                .get_physical_device_surface_support(
+                                    phy_device, index as _, surface,
+                                )
+                                .unwrap_or(false)
+                    })
+                    .map(|(index, _)| (phy_device, index as u32))
+            })
+            .ok_or_else(|| anyhow!("no device presenting to headless surfaces"))?;
+
+        let priorities = [1.0];
+        let queue_infos = [vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(family_index)
+            .queue_priorities(&priorities)
+            .build()];
+        let device_extensions = [khr::Swapchain::name().as_ptr()];
+        let device_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(&queue_infos)
+            .enabled_extension_names(&device_extensions);
+        let device = instance.create_device(phy_device, &device_info, None)?;
+        let queue = device.get_device_queue(family_index, 0);
+
+        let caps = khr_surface.get_physical_device_surface_capabilities(phy_device, surface)?;
+        let format = khr_surface
+            .get_physical_device_surface_formats(phy_device, surface)?
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow!("no surface format"))?;
+        let present_modes =
+            khr_surface.get_physical_device_surface_present_modes(phy_device, surface)?;
+        // unthrottled so frames are presented back to back
+        let present_mode = [vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX]
+            .into_iter()
+            .find(|mode| present_modes.contains(mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO);
+        let mut image_count = caps.min_image_count + 1;
+        if caps.max_image_count > 0 {
+            image_count = image_count.min(caps.max_image_count);
+        }
+
+        let khr_swapchain = khr::Swapchain::new(&instance, &device);
+        let swapchain_info = vk::SwapchainCreateInfoKHR::builder()
+            .surface(surface)
+            .min_image_count(image_count)
+            .image_format(format.format)
+            .image_color_space(format.color_space)
+            .image_extent(vk::Extent2D { width, height })
+            .image_array_layers(1)
+            .image_usage(vk::ImageUsageFlags::TRANSFER_DST)
+            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .pre_transform(caps.current_transform)
+            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .present_mode(present_mode)
+            .clipped(true);
+        let swapchain = khr_swapchain.create_swapchain(&swapchain_info, None)?;
+        let images = khr_swapchain.get_swapchain_images(swapchain)?;
+
+        let pool_info = vk::CommandPoolCreateInfo::builder().queue_family_index(family_index);
+        let command_pool = device.create_command_pool(&pool_info, None)?;
+        let buffers_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(images.len() as _);
+        let command_buffers = device.allocate_command_buffers(&buffers_info)?;
+        for (&image, &command_buffer) in images.iter().zip(&command_buffers) {
+            record_clear(&device, command_buffer, image)?;
+        }
+
+        let semaphore_info = vk::SemaphoreCreateInfo::default();
+        let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+        let mut acquired = vec![];
+        let mut fences = vec![];
+        for _ in 0..FRAMES_IN_FLIGHT {
+            acquired.push(device.create_semaphore(&semaphore_info, None)?);
+            fences.push(device.create_fence(&fence_info, None)?);
+        }
+        let mut rendered = vec![];
+        for _ in &images {
+            rendered.push(device.create_semaphore(&semaphore_info, None)?);
+        }
+
+        Ok(Self {
+            _entry: entry,
+            instance,
+            validation,
+            khr_surface,
+            surface,
+            device,
+            khr_swapchain,
+            swapchain,
+            queue,
+            command_pool,
+            command_buffers,
+            acquired,
+            fences,
+            rendered,
+            frame: 0,
+        })
+    }
+
+    /// Renders and presents a frame, returns the time `vkQueuePresentKHR`
+    /// took
+    pub fn frame(&mut self) -> Result<Duration> {
+        unsafe {
+            let fence = self.fences[self.frame];
+            self.device.wait_for_fences(&[fence], true, u64::MAX)?;
+            self.device.reset_fences(&[fence])?;
+
+            let acquired = self.acquired[self.frame];
+            let (index, _) = self.khr_swapchain.acquire_next_image(
+                self.swapchain,
+                u64::MAX,
+                acquired,
+                vk::Fence::null(),
+            )?;
+            let rendered = self.rendered[index as usize];
+            let wait_stages = [vk::PipelineStageFlags::TRANSFER];
+            let submit = vk::SubmitInfo::builder()
+                .wait_semaphores(slice::from_ref(&acquired))
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(slice::from_ref(&self.command_buffers[index as usize]))
+                .signal_semaphores(slice::from_ref(&rendered))
+                .build();
+            self.device.queue_submit(self.queue, &[submit], fence)?;
+
+            let present_info = vk::PresentInfoKHR::builder()
+                .wait_semaphores(slice::from_ref(&rendered))
+                .swapchains(slice::from_ref(&self.swapchain))
+                .image_indices(slice::from_ref(&index));
+            let start = Instant::now();
+            self.khr_swapchain
+                .queue_present(self.queue, &present_info)?;
+            let elapsed = start.elapsed();
+
+            self.frame = (self.frame + 1) % FRAMES_IN_FLIGHT;
+            Ok(elapsed)
+        }
+    }
+
+    /// Waits for all frames, e.g. before checking for validation errors
+    pub fn wait_idle(&self) -> Result<()> {
+        unsafe { self.device.device_wait_idle()? };
+        Ok(())
+    }
+
+    /// Validation errors reported so far, always empty unless created by
+    /// [`VkApp::new_validated`]
+    pub fn validation_errors(&self) -> Vec<String> {
+        match self.validation.as_ref() {
+            Some(validation) => validation.errors.lock().unwrap().clone(),
+            None => vec![],
+        }
+    }
+}
+
+impl Drop for VkApp {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            for &semaphore in self.acquired.iter().chain(&self.rendered) {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+            for &fence in &self.fences {
+                self.device.destroy_fence(fence, None);
+            }
+            self.device.destroy_command_pool(self.command_pool, None);
+            self.khr_swapchain.destroy_swapchain(self.swapchain, None);
+            self.device.destroy_device(None);
+            self.khr_surface.destroy_surface(self.surface, None);
+            if let Some(validation) = self.validation.as_ref() {
+                validation
+                    .debug_utils
+                    .destroy_debug_utils_messenger(validation.messenger, None);
+            }
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+unsafe extern "system" fn on_validation_message(
+    _severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    _types: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    let errors = &*(p_user_data as *const Mutex<Vec<String>>);
+    let data = &*p_callback_data;
+    if !data.p_message.is_null() {
+        let message = CStr::from_ptr(data.p_message).to_string_lossy();
+        eprintln!("{message}");
+        errors.lock().unwrap().push(message.into_owned());
+    }
+    vk::FALSE
+}
+
+unsafe fn record_clear(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+) -> Result<()> {
+    let range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let barrier = |old_layout, new_layout, src_access_mask, dst_access_mask| {
+        vk::ImageMemoryBarrier::builder()
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(range)
+            .build()
+    };
+
+    device.begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())?;
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[barrier(
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::TRANSFER_WRITE,
+        )],
+    );
+    device.cmd_clear_color_image(
+        command_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &vk::ClearColorValue {
+            float32: CLEAR_COLOR,
+        },
+        &[range],
+    );
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[barrier(
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::empty(),
+        )],
+    );
+    device.end_command_buffer(command_buffer)?;
+    Ok(())
+}
@@ -0,0 +1,354 @@
+#ifndef PW_CAPTURE_CLIENT_H
+#define PW_CAPTURE_CLIENT_H
+
+/* Generated by cbindgen from src/capi.rs, do not edit */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Maximum number of planes of a buffer
+ */
+#define PW_CAPTURE_MAX_PLANES 4
+
+/**
+ * Node id of streams not assigned one yet
+ */
+#define PW_CAPTURE_ID_ANY UINT32_MAX
+
+enum PwCaptureFormat
+#ifdef __cplusplus
+  : uint32_t
+#endif // __cplusplus
+ {
+  PwCaptureFormat_UNKNOWN,
+  PwCaptureFormat_ENCODED,
+  PwCaptureFormat_I420,
+  PwCaptureFormat_YV12,
+  PwCaptureFormat_YUY2,
+  PwCaptureFormat_UYVY,
+  PwCaptureFormat_AYUV,
+  PwCaptureFormat_RGBx,
+  PwCaptureFormat_BGRx,
+  PwCaptureFormat_xRGB,
+  PwCaptureFormat_xBGR,
+  PwCaptureFormat_RGBA,
+  PwCaptureFormat_BGRA,
+  PwCaptureFormat_ARGB,
+  PwCaptureFormat_ABGR,
+  PwCaptureFormat_RGB,
+  PwCaptureFormat_BGR,
+  PwCaptureFormat_Y41B,
+  PwCaptureFormat_Y42B,
+  PwCaptureFormat_YVYU,
+  PwCaptureFormat_Y444,
+  PwCaptureFormat_v210,
+  PwCaptureFormat_v216,
+  PwCaptureFormat_NV12,
+  PwCaptureFormat_NV21,
+  PwCaptureFormat_GRAY8,
+  PwCaptureFormat_GRAY16_BE,
+  PwCaptureFormat_GRAY16_LE,
+  PwCaptureFormat_v308,
+  PwCaptureFormat_RGB16,
+  PwCaptureFormat_BGR16,
+  PwCaptureFormat_RGB15,
+  PwCaptureFormat_BGR15,
+  PwCaptureFormat_UYVP,
+  PwCaptureFormat_A420,
+  PwCaptureFormat_RGB8P,
+  PwCaptureFormat_YUV9,
+  PwCaptureFormat_YVU9,
+  PwCaptureFormat_IYU1,
+  PwCaptureFormat_ARGB64,
+  PwCaptureFormat_AYUV64,
+  PwCaptureFormat_r210,
+  PwCaptureFormat_I420_10BE,
+  PwCaptureFormat_I420_10LE,
+  PwCaptureFormat_I422_10BE,
+  PwCaptureFormat_I422_10LE,
+  PwCaptureFormat_Y444_10BE,
+  PwCaptureFormat_Y444_10LE,
+  PwCaptureFormat_GBR,
+  PwCaptureFormat_GBR_10BE,
+  PwCaptureFormat_GBR_10LE,
+  PwCaptureFormat_NV16,
+  PwCaptureFormat_NV24,
+  PwCaptureFormat_NV12_64Z32,
+  PwCaptureFormat_A420_10BE,
+  PwCaptureFormat_A420_10LE,
+  PwCaptureFormat_A422_10BE,
+  PwCaptureFormat_A422_10LE,
+  PwCaptureFormat_A444_10BE,
+  PwCaptureFormat_A444_10LE,
+  PwCaptureFormat_NV61,
+  PwCaptureFormat_P010_10BE,
+  PwCaptureFormat_P010_10LE,
+  PwCaptureFormat_IYU2,
+  PwCaptureFormat_VYUY,
+  PwCaptureFormat_GBRA,
+  PwCaptureFormat_GBRA_10BE,
+  PwCaptureFormat_GBRA_10LE,
+  PwCaptureFormat_GBR_12BE,
+  PwCaptureFormat_GBR_12LE,
+  PwCaptureFormat_GBRA_12BE,
+  PwCaptureFormat_GBRA_12LE,
+  PwCaptureFormat_I420_12BE,
+  PwCaptureFormat_I420_12LE,
+  PwCaptureFormat_I422_12BE,
+  PwCaptureFormat_I422_12LE,
+  PwCaptureFormat_Y444_12BE,
+  PwCaptureFormat_Y444_12LE,
+  PwCaptureFormat_RGBA_F16,
+  PwCaptureFormat_RGBA_F32,
+  PwCaptureFormat_xRGB_210LE,
+  PwCaptureFormat_xBGR_210LE,
+  PwCaptureFormat_RGBx_102LE,
+  PwCaptureFormat_BGRx_102LE,
+  PwCaptureFormat_ARGB_210LE,
+  PwCaptureFormat_ABGR_210LE,
+  PwCaptureFormat_RGBA_102LE,
+  PwCaptureFormat_BGRA_102LE,
+};
+#ifndef __cplusplus
+typedef uint32_t PwCaptureFormat;
+#endif // __cplusplus
+
+enum PwCaptureBufferDataType
+#ifdef __cplusplus
+  : uint32_t
+#endif // __cplusplus
+ {
+  PwCaptureBufferDataType_DmaBuf,
+  PwCaptureBufferDataType_MemFd,
+};
+#ifndef __cplusplus
+typedef uint32_t PwCaptureBufferDataType;
+#endif // __cplusplus
+
+enum PwCaptureStreamState
+#ifdef __cplusplus
+  : uint32_t
+#endif // __cplusplus
+ {
+  PwCaptureStreamState_Error,
+  PwCaptureStreamState_Unconnected,
+  PwCaptureStreamState_Connecting,
+  PwCaptureStreamState_Paused,
+  PwCaptureStreamState_Streaming,
+};
+#ifndef __cplusplus
+typedef uint32_t PwCaptureStreamState;
+#endif // __cplusplus
+
+/**
+ * Connection to PipeWire or obs-vkcapture, selected by `PW_CAPTURE_BACKEND`
+ */
+typedef struct PwCaptureClient PwCaptureClient;
+
+typedef struct PwCaptureStream PwCaptureStream;
+
+/**
+ * Formats offered with the same modifiers
+ */
+typedef struct {
+  /**
+   * `PwCaptureFormat` values
+   */
+  const uint32_t *formats;
+  size_t num_formats;
+  /**
+   * DRM format modifiers, buffers are added in shared memory if empty
+   */
+  const uint64_t *modifiers;
+  size_t num_modifiers;
+} PwCaptureEnumFormat;
+
+typedef struct {
+  bool has_modifier;
+  uint64_t modifier;
+  uint32_t num_planes;
+  /**
+   * Buffers of DMA-BUF formats can also be added in shared memory
+   */
+  bool mem_fd_fallback;
+} PwCaptureFixateFormat;
+
+typedef struct {
+  int64_t fd;
+  uint32_t offset;
+  uint32_t size;
+  uint32_t stride;
+} PwCaptureBufferPlane;
+
+typedef struct {
+  bool is_dma_buf;
+  uint32_t num_planes;
+  PwCaptureBufferPlane planes[PW_CAPTURE_MAX_PLANES];
+  /**
+   * Identifies the buffer in later callbacks, e.g. a texture name
+   */
+  uint64_t user_handle;
+} PwCaptureBufferInfo;
+
+/**
+ * Counterparts of the callbacks of `StreamInfo`, `state_changed` may be
+ * `NULL`
+ */
+typedef struct {
+  /**
+   * Passed to all callbacks
+   */
+  void *user_data;
+  /**
+   * Fills `fixate` for the format and modifiers the consumer picked,
+   * returns `false` if not supported
+   */
+  bool (*fixate_format)(void *user_data,
+                        const PwCaptureEnumFormat *format,
+                        PwCaptureFixateFormat *fixate);
+  /**
+   * Fills `info` with a buffer of `data_type`, returns `false` on failure
+   */
+  bool (*add_buffer)(void *user_data, PwCaptureBufferDataType data_type, PwCaptureBufferInfo *info);
+  void (*remove_buffer)(void *user_data, uint64_t user_handle);
+  /**
+   * Called once the frame copied into the buffer gets processed,
+   * `pts` may be set to its presentation time in `CLOCK_MONOTONIC`
+   * nanoseconds, it is left negative for the time of processing
+   */
+  void (*process_buffer)(void *user_data, uint64_t user_handle, int64_t *pts);
+  /**
+   * Node id is `PW_CAPTURE_ID_ANY` until assigned
+   */
+  void (*state_changed)(void *user_data, PwCaptureStreamState state, uint32_t node_id);
+} PwCaptureStreamCallbacks;
+
+typedef struct {
+  uint32_t width;
+  uint32_t height;
+  /**
+   * In order of preference
+   */
+  const PwCaptureEnumFormat *enum_formats;
+  size_t num_enum_formats;
+  uint32_t max_buffers;
+  /**
+   * Extra node properties as `NULL` terminated key and value pairs, may be
+   * `NULL`
+   */
+  const char *const *props;
+  PwCaptureStreamCallbacks callbacks;
+} PwCaptureStreamInfo;
+
+/**
+ * Buffer dequeued to copy a frame into
+ */
+typedef struct {
+  void *handle;
+  uint64_t user_handle;
+} PwCaptureBuffer;
+
+
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Connects to the backend selected by `PW_CAPTURE_BACKEND`, returns `NULL`
+ * on failure
+ */
+PwCaptureClient *pw_capture_client_new(void);
+
+/**
+ * Terminates remaining streams and disconnects
+ *
+ * # Safety
+ *
+ * `client` must be returned by `pw_capture_client_new` or `NULL`.
+ */
+void pw_capture_client_destroy(PwCaptureClient *client);
+
+/**
+ * Creates a capture node, returns `NULL` on failure
+ *
+ * # Safety
+ *
+ * `client` must be a live client, pointers in `info` must be valid for the
+ * duration of the call and `info.callbacks.user_data` until the stream got
+ * destroyed.
+ */
+PwCaptureStream *pw_capture_stream_new(const PwCaptureClient *client,
+                                       const PwCaptureStreamInfo *info);
+
+/**
+ * Removes the capture node, its buffers get removed through callbacks
+ *
+ * # Safety
+ *
+ * `stream` must be returned by `pw_capture_stream_new` or `NULL`.
+ */
+void pw_capture_stream_destroy(PwCaptureStream *stream);
+
+/**
+ * Dequeues a buffer to copy the next frame into, returns `false` if there is
+ * none or no consumer wants a frame
+ *
+ * # Safety
+ *
+ * `stream` must be a live stream, `buffer` valid for writes.
+ */
+bool pw_capture_stream_dequeue_buffer(const PwCaptureStream *stream, PwCaptureBuffer *buffer);
+
+/**
+ * Hands the frame copied into a dequeued buffer over to consumers, the copy
+ * must have completed
+ *
+ * # Safety
+ *
+ * `stream` must be a live stream, `buffer` dequeued from it.
+ */
+bool pw_capture_stream_queue_buffer(const PwCaptureStream *stream, const PwCaptureBuffer *buffer);
+
+/**
+ * Replaces offered formats, e.g. after the source got resized
+ *
+ * # Safety
+ *
+ * `stream` must be a live stream, `enum_formats` valid for the duration of
+ * the call.
+ */
+bool pw_capture_stream_update_format(const PwCaptureStream *stream,
+                                     uint32_t width,
+                                     uint32_t height,
+                                     const PwCaptureEnumFormat *enum_formats,
+                                     size_t num_enum_formats);
+
+/**
+ * # Safety
+ *
+ * `stream` must be a live stream.
+ */
+PwCaptureStreamState pw_capture_stream_state(const PwCaptureStream *stream);
+
+/**
+ * PipeWire node id consumers connect to, `PW_CAPTURE_ID_ANY` until assigned
+ *
+ * # Safety
+ *
+ * `stream` must be a live stream.
+ */
+uint32_t pw_capture_stream_node_id(const PwCaptureStream *stream);
+
+/**
+ * Current time in `CLOCK_MONOTONIC` nanoseconds, as expected for `pts`
+ */
+int64_t pw_capture_get_pts_nanos(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* PW_CAPTURE_CLIENT_H */
@@ -0,0 +1,205 @@
+//! Per-application capture filter
+
+use crate::*;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+
+const CONFIG_FILE: &str = "pw-capture/config.toml";
+
+static APP_ALLOWED: Lazy<bool> = Lazy::new(|| {
+    if matches!(env::var("DISABLE_PW_CAPTURE").as_deref(), Ok("1")) {
+        info!("DISABLE_PW_CAPTURE set, not capturing");
+        return false;
+    }
+    let names = app_names();
+    let mut filter = AppFilter::load();
+    if let Ok(only) = env::var("ENABLE_ONLY_PW_CAPTURE") {
+        filter.allow = parse_env_patterns(&only);
+    }
+    let allowed = filter.allows(&names);
+    if allowed {
+        debug!("capturing app {:?}", names);
+    } else {
+        info!("app {:?} filtered out, not capturing", names);
+    }
+    allowed
+});
+
+/// Whether layers should capture the current process at all
+pub fn app_allowed() -> bool {
+    *APP_ALLOWED
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AppFilter {
+    /// Patterns of apps to capture, all if empty
+    pub allow: Vec<String>,
+    /// Patterns of apps to never capture
+    pub block: Vec<String>,
+}
+
+impl AppFilter {
+    pub fn parse(content: &str) -> Result<Self> {
+        let config: toml::Table = content.parse()?;
+        let apps = match config.get("apps") {
+            Some(apps) => apps.as_table().ok_or(anyhow!("apps is not a table"))?,
+            None => return Ok(Self::default()),
+        };
+        Ok(Self {
+            allow: parse_patterns(apps, "allow")?,
+            block: parse_patterns(apps, "block")?,
+        })
+    }
+
+    fn load() -> Self {
+        let Some(path) = config_path() else {
+            return Self::default();
+        };
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        debug!("loaded config from {path:?}");
+        Self::parse(&content).unwrap_or_else(|e| {
+            warn!("invalid config {path:?}: {e:#}");
+            Self::default()
+        })
+    }
+
+    /// Whether the app known by `names` gets captured
+    pub fn allows<S: AsRef<str>>(&self, names: &[S]) -> bool {
+        let matches = |patterns: &[String]| {
+            patterns.iter().any(|pattern| {
+                names
+                    .iter()
+                    .any(|name| glob_matches(pattern, name.as_ref()))
+            })
+        };
+        !matches(&self.block) && (self.allow.is_empty() || matches(&self.allow))
+    }
+}
+
+fn parse_patterns(apps: &toml::Table, key: &str) -> Result<Vec<String>> {
+    let Some(value) = apps.get(key) else {
+        return Ok(vec![]);
+    };
+    value
+        .as_array()
+        .ok_or(anyhow!("apps.{key} is not an array"))?
+        .iter()
+        .map(|pattern| match pattern.as_str() {
+            Some(pattern) => Ok(pattern.to_owned()),
+            None => Err(anyhow!("apps.{key} contains a {}", pattern.type_str())),
+        })
+        .collect()
+}
+
+/// Config file shared by all settings read from one
+pub(crate) fn config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join(CONFIG_FILE))
+}
+
+/// Comma separated patterns of an env var
+fn parse_env_patterns(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Whether `name` matches `pattern` as a whole, `*` matching any run of
+/// characters
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        // no wildcard
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Names the current process is matched by
+fn app_names() -> Vec<String> {
+    let mut names = vec![get_app_name()];
+    if let Ok(exe) = fs::read_link("/proc/self/exe") {
+        names.push(exe.to_string_lossy().into_owned());
+    }
+    // Wine sets it to the Windows path of the app
+    let arg0 = fs::read("/proc/self/cmdline").ok().and_then(|cmdline| {
+        let arg0 = cmdline.split(|&b| b == 0).next()?;
+        (!arg0.is_empty()).then(|| String::from_utf8_lossy(arg0).into_owned())
+    });
+    names.extend(arg0);
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_matches("vkcube", "vkcube"));
+        assert!(!glob_matches("vkcube", "vkcube-wayland"));
+        assert!(glob_matches("vkcube*", "vkcube-wayland"));
+        assert!(glob_matches(
+            "*/steamapps/common/*",
+            "/games/steamapps/common/a/a.x86_64"
+        ));
+        assert!(!glob_matches("*/steamapps/common/*", "/usr/bin/steam"));
+        assert!(glob_matches("*.exe", "Z:\\games\\game.exe"));
+        assert!(glob_matches("a*b*b", "abb"));
+        assert!(!glob_matches("a*b*b", "ab"));
+        assert!(glob_matches("*", ""));
+    }
+
+    #[test]
+    fn parse() {
+        let filter = AppFilter::parse(
+            "[apps]\n\
+             allow = [\"vkcube\", \"*/steamapps/common/*\"]\n\
+             block = [\"steam\"]\n",
+        )
+        .unwrap();
+        assert!(filter.allows(&["vkcube", "/usr/bin/vkcube"]));
+        assert!(filter.allows(&["game", "/games/steamapps/common/game/game"]));
+        assert!(!filter.allows(&["steam", "/usr/bin/steam"]));
+        assert!(!filter.allows(&["firefox"]));
+
+        let filter = AppFilter::parse("[apps]\nblock = [\"firefox\"]\n").unwrap();
+        assert!(filter.allows(&["vkcube"]));
+        assert!(!filter.allows(&["firefox"]));
+
+        assert_eq!(AppFilter::parse("").unwrap(), AppFilter::default());
+        assert!(AppFilter::parse("[apps]\nallow = \"vkcube\"\n").is_err());
+        assert!(AppFilter::parse("[apps]\nallow = [1]\n").is_err());
+    }
+
+    #[test]
+    fn env_patterns() {
+        assert_eq!(parse_env_patterns("vkcube"), ["vkcube"]);
+        assert_eq!(parse_env_patterns("vkcube, *.exe,,"), ["vkcube", "*.exe"]);
+        assert!(parse_env_patterns("").is_empty());
+    }
+}
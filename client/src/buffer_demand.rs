@@ -0,0 +1,142 @@
+//! Sizing of stream buffer pools by consumer activity
+
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+use crate::settings::setting;
+
+pub const IDLE_BUFFERS: u32 = 1;
+// consumers pausing for shorter than this keep their buffers
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+static MAX_BUFFERS: Lazy<Option<u32>> = Lazy::new(|| {
+    let max_buffers = match setting("PW_CAPTURE_MAX_BUFFERS") {
+        Some(value) => parse_max_buffers(&value).or_else(|| {
+            warn!("invalid PW_CAPTURE_MAX_BUFFERS {value:?}");
+            None
+        }),
+        None => None,
+    };
+    debug!("max buffers: {:?}", max_buffers);
+    max_buffers
+});
+
+/// Buffer count limit set by `PW_CAPTURE_MAX_BUFFERS`, `default` if unset
+pub fn max_buffers_from_env(default: u32) -> u32 {
+    MAX_BUFFERS.unwrap_or(default)
+}
+
+fn parse_max_buffers(value: &str) -> Option<u32> {
+    value.trim().parse::<u32>().ok().filter(|&v| v > 0)
+}
+
+#[derive(Debug)]
+pub struct BufferDemand {
+    max_buffers: u32,
+    current: u32,
+    idle_since: Option<Instant>,
+}
+
+impl BufferDemand {
+    pub fn new(max_buffers: u32) -> Self {
+        Self {
+            max_buffers,
+            current: IDLE_BUFFERS.min(max_buffers),
+            idle_since: None,
+        }
+    }
+
+    /// Number of buffers the stream should currently offer
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Records the result of a dequeue attempt, returns the new buffer count
+    /// if it changed
+    pub fn update(&mut self, dequeued: bool, now: Instant) -> Option<u32> {
+        let target = if dequeued {
+            self.idle_since = None;
+            self.max_buffers
+        } else {
+            let idle_since = *self.idle_since.get_or_insert(now);
+            if now.duration_since(idle_since) < IDLE_TIMEOUT {
+                return None;
+            }
+            IDLE_BUFFERS.min(self.max_buffers)
+        };
+        if target == self.current {
+            return None;
+        }
+        self.current = target;
+        Some(target)
+    }
+
+    /// Shrinks back to the idle buffer count at once, e.g. as consumers
+    /// disconnected, returns the new buffer count if it changed
+    pub fn reset(&mut self) -> Option<u32> {
+        self.idle_since = None;
+        let target = IDLE_BUFFERS.min(self.max_buffers);
+        if target == self.current {
+            return None;
+        }
+        self.current = target;
+        Some(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grow_and_shrink() {
+        let start = Instant::now();
+        let mut demand = BufferDemand::new(8);
+        assert_eq!(demand.current(), IDLE_BUFFERS);
+
+        assert_eq!(demand.update(false, start), None);
+        assert_eq!(demand.update(true, start), Some(8));
+        assert_eq!(demand.update(true, start), None);
+
+        assert_eq!(demand.update(false, start), None);
+        assert_eq!(demand.update(false, start + IDLE_TIMEOUT / 2), None);
+        assert_eq!(
+            demand.update(false, start + IDLE_TIMEOUT),
+            Some(IDLE_BUFFERS)
+        );
+        assert_eq!(demand.update(false, start + IDLE_TIMEOUT * 2), None);
+        assert_eq!(demand.current(), IDLE_BUFFERS);
+    }
+
+    #[test]
+    fn idle_reset_by_dequeue() {
+        let start = Instant::now();
+        let mut demand = BufferDemand::new(8);
+        demand.update(true, start);
+        demand.update(false, start);
+        demand.update(true, start + IDLE_TIMEOUT / 2);
+        assert_eq!(demand.update(false, start + IDLE_TIMEOUT), None);
+        assert_eq!(demand.current(), 8);
+    }
+
+    #[test]
+    fn reset() {
+        let start = Instant::now();
+        let mut demand = BufferDemand::new(8);
+        assert_eq!(demand.reset(), None);
+        demand.update(true, start);
+        assert_eq!(demand.reset(), Some(IDLE_BUFFERS));
+        assert_eq!(demand.update(true, start), Some(8));
+    }
+
+    #[test]
+    fn max_buffers() {
+        assert_eq!(parse_max_buffers("4"), Some(4));
+        assert_eq!(parse_max_buffers(" 64 "), Some(64));
+        assert_eq!(parse_max_buffers("0"), None);
+        assert_eq!(parse_max_buffers("-1"), None);
+        assert_eq!(parse_max_buffers("many"), None);
+    }
+}
@@ -0,0 +1,543 @@
+//! C API for frontends not written in Rust
+
+use crate::*;
+
+use core::ffi::{c_char, c_void, CStr};
+use core::ptr;
+use core::slice;
+
+use anyhow::{anyhow, Result};
+use log::error;
+use pipewire as pw;
+
+/// Maximum number of planes of a buffer
+pub const PW_CAPTURE_MAX_PLANES: usize = 4;
+/// Node id of streams not assigned one yet
+pub const PW_CAPTURE_ID_ANY: u32 = u32::MAX;
+
+/// Connection to PipeWire or obs-vkcapture, selected by `PW_CAPTURE_BACKEND`
+pub struct PwCaptureClient(Client);
+
+pub struct PwCaptureStream(Stream);
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PwCaptureStreamState {
+    Error,
+    Unconnected,
+    Connecting,
+    Paused,
+    Streaming,
+}
+
+impl From<StreamState> for PwCaptureStreamState {
+    fn from(state: StreamState) -> Self {
+        match state {
+            StreamState::Error => Self::Error,
+            StreamState::Unconnected => Self::Unconnected,
+            StreamState::Connecting => Self::Connecting,
+            StreamState::Paused => Self::Paused,
+            StreamState::Streaming => Self::Streaming,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PwCaptureBufferDataType {
+    DmaBuf,
+    MemFd,
+}
+
+impl From<BufferDataType> for PwCaptureBufferDataType {
+    fn from(data_type: BufferDataType) -> Self {
+        match data_type {
+            BufferDataType::DmaBuf => Self::DmaBuf,
+            BufferDataType::MemFd => Self::MemFd,
+        }
+    }
+}
+
+/// Formats offered with the same modifiers
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PwCaptureEnumFormat {
+    /// `PwCaptureFormat` values
+    pub formats: *const u32,
+    pub num_formats: usize,
+    /// DRM format modifiers, buffers are added in shared memory if empty
+    pub modifiers: *const u64,
+    pub num_modifiers: usize,
+}
+
+impl PwCaptureEnumFormat {
+    unsafe fn to_enum_format_info(self) -> EnumFormatInfo {
+        EnumFormatInfo {
+            formats: slice_or_empty(self.formats, self.num_formats)
+                .iter()
+                .map(|&format| Format::from(format))
+                .collect(),
+            modifiers: slice_or_empty(self.modifiers, self.num_modifiers).to_vec(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PwCaptureFixateFormat {
+    pub has_modifier: bool,
+    pub modifier: u64,
+    pub num_planes: u32,
+    /// Buffers of DMA-BUF formats can also be added in shared memory
+    pub mem_fd_fallback: bool,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PwCaptureBufferPlane {
+    pub fd: i64,
+    pub offset: u32,
+    pub size: u32,
+    pub stride: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PwCaptureBufferInfo {
+    pub is_dma_buf: bool,
+    pub num_planes: u32,
+    pub planes: [PwCaptureBufferPlane; PW_CAPTURE_MAX_PLANES],
+    /// Identifies the buffer in later callbacks, e.g. a texture name
+    pub user_handle: u64,
+}
+
+/// Buffer dequeued to copy a frame into
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PwCaptureBuffer {
+    pub handle: *mut c_void,
+    pub user_handle: u64,
+}
+
+/// Counterparts of the callbacks of `StreamInfo`, `state_changed` may be
+/// `NULL`
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PwCaptureStreamCallbacks {
+    /// Passed to all callbacks
+    pub user_data: *mut c_void,
+    /// Fills `fixate` for the format and modifiers the consumer picked,
+    /// returns `false` if not supported
+    pub fixate_format: Option<
+        unsafe extern "C" fn(
+            user_data: *mut c_void,
+            format: *const PwCaptureEnumFormat,
+            fixate: *mut PwCaptureFixateFormat,
+        ) -> bool,
+    >,
+    /// Fills `info` with a buffer of `data_type`, returns `false` on failure
+    pub add_buffer: Option<
+        unsafe extern "C" fn(
+            user_data: *mut c_void,
+            data_type: PwCaptureBufferDataType,
+            info: *mut PwCaptureBufferInfo,
+        ) -> bool,
+    >,
+    pub remove_buffer: Option<unsafe extern "C" fn(user_data: *mut c_void, user_handle: u64)>,
+    /// Called once the frame copied into the buffer gets processed,
+    /// `pts` may be set to its presentation time in `CLOCK_MONOTONIC`
+    /// nanoseconds, it is left negative for the time of processing
+    pub process_buffer:
+        Option<unsafe extern "C" fn(user_data: *mut c_void, user_handle: u64, pts: *mut i64)>,
+    /// Node id is `PW_CAPTURE_ID_ANY` until assigned
+    pub state_changed: Option<
+        unsafe extern "C" fn(user_data: *mut c_void, state: PwCaptureStreamState, node_id: u32),
+    >,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PwCaptureStreamInfo {
+    pub width: u32,
+    pub height: u32,
+    /// In order of preference
+    pub enum_formats: *const PwCaptureEnumFormat,
+    pub num_enum_formats: usize,
+    pub max_buffers: u32,
+    /// Extra node properties as `NULL` terminated key and value pairs, may be
+    /// `NULL`
+    pub props: *const *const c_char,
+    pub callbacks: PwCaptureStreamCallbacks,
+}
+
+/// Callbacks are only called from the stream thread
+#[derive(Clone, Copy)]
+struct Callbacks(PwCaptureStreamCallbacks);
+
+unsafe impl Send for Callbacks {}
+
+impl Callbacks {
+    fn fixate_format(self, info: EnumFormatInfo) -> Option<FixateFormat> {
+        let formats: Vec<u32> = info.formats.iter().map(|&format| format.into()).collect();
+        let format = PwCaptureEnumFormat {
+            formats: formats.as_ptr(),
+            num_formats: formats.len(),
+            modifiers: info.modifiers.as_ptr(),
+            num_modifiers: info.modifiers.len(),
+        };
+        let mut fixate = PwCaptureFixateFormat::default();
+        let cb = self.0.fixate_format?;
+        if !unsafe { cb(self.0.user_data, &format, &mut fixate) } {
+            return None;
+        }
+        Some(FixateFormat {
+            modifier: fixate.has_modifier.then_some(fixate.modifier),
+            num_planes: fixate.num_planes,
+            mem_fd_fallback: fixate.mem_fd_fallback,
+        })
+    }
+
+    fn add_buffer(self, data_type: BufferDataType) -> Result<BufferInfo> {
+        let cb = self.0.add_buffer.ok_or(anyhow!("no add_buffer callback"))?;
+        let mut info = PwCaptureBufferInfo::default();
+        if !unsafe { cb(self.0.user_data, data_type.into(), &mut info) } {
+            return Err(anyhow!("frontend failed to add buffer"));
+        }
+        let num_planes = info.num_planes as usize;
+        if num_planes == 0 || num_planes > PW_CAPTURE_MAX_PLANES {
+            return Err(anyhow!("invalid number of planes {num_planes}"));
+        }
+        Ok(BufferInfo {
+            is_dma_buf: info.is_dma_buf,
+            planes: info.planes[..num_planes]
+                .iter()
+                .map(|plane| BufferPlaneInfo {
+                    fd: plane.fd,
+                    offset: plane.offset,
+                    size: plane.size,
+                    stride: plane.stride,
+                })
+                .collect(),
+            user_handle: BufferUserHandle::User(info.user_handle),
+        })
+    }
+
+    fn remove_buffer(self, user_handle: BufferUserHandle) {
+        if let (Some(cb), BufferUserHandle::User(user_handle)) = (self.0.remove_buffer, user_handle)
+        {
+            unsafe { cb(self.0.user_data, user_handle) }
+        }
+    }
+
+    fn process_buffer(self, user_handle: BufferUserHandle, meta_cbs: AddBufferMetaCbs) {
+        let (Some(cb), BufferUserHandle::User(user_handle)) = (self.0.process_buffer, user_handle)
+        else {
+            return;
+        };
+        let mut pts = -1;
+        unsafe { cb(self.0.user_data, user_handle, &mut pts) };
+        match meta_cbs.set_pts {
+            Some(set_pts) if pts >= 0 => set_pts(pts),
+            _ => (),
+        }
+    }
+
+    fn state_changed(self, state: StreamState, node_id: Option<u32>) {
+        if let Some(cb) = self.0.state_changed {
+            let node_id = node_id.unwrap_or(PW_CAPTURE_ID_ANY);
+            unsafe { cb(self.0.user_data, state.into(), node_id) }
+        }
+    }
+}
+
+unsafe fn slice_or_empty<'a, T>(data: *const T, len: usize) -> &'a [T] {
+    if data.is_null() || len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+unsafe fn enum_formats(data: *const PwCaptureEnumFormat, len: usize) -> Vec<EnumFormatInfo> {
+    slice_or_empty(data, len)
+        .iter()
+        .map(|format| format.to_enum_format_info())
+        .collect()
+}
+
+unsafe fn props(mut props: *const *const c_char) -> Result<Vec<(String, String)>> {
+    let mut res = vec![];
+    if props.is_null() {
+        return Ok(res);
+    }
+    while !(*props).is_null() {
+        let value = *props.add(1);
+        if value.is_null() {
+            return Err(anyhow!("property without value"));
+        }
+        let key = CStr::from_ptr(*props).to_str()?;
+        let value = CStr::from_ptr(value).to_str()?;
+        res.push((key.to_owned(), value.to_owned()));
+        props = props.add(2);
+    }
+    Ok(res)
+}
+
+/// Connects to the backend selected by `PW_CAPTURE_BACKEND`, returns `NULL`
+/// on failure
+#[no_mangle]
+pub extern "C" fn pw_capture_client_new() -> *mut PwCaptureClient {
+    init_logger("pw-capture-client");
+    match Client::new() {
+        Ok(client) => Box::into_raw(Box::new(PwCaptureClient(client))),
+        Err(e) => {
+            error!("failed to create client: {e:?}");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Terminates remaining streams and disconnects
+///
+/// # Safety
+///
+/// `client` must be returned by `pw_capture_client_new` or `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn pw_capture_client_destroy(client: *mut PwCaptureClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Creates a capture node, returns `NULL` on failure
+///
+/// # Safety
+///
+/// `client` must be a live client, pointers in `info` must be valid for the
+/// duration of the call and `info.callbacks.user_data` until the stream got
+/// destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn pw_capture_stream_new(
+    client: *const PwCaptureClient,
+    info: *const PwCaptureStreamInfo,
+) -> *mut PwCaptureStream {
+    let res = (|| -> Result<Stream> {
+        let client = client.as_ref().ok_or(anyhow!("no client"))?;
+        let info = info.as_ref().ok_or(anyhow!("no stream info"))?;
+        let cbs = info.callbacks;
+        if cbs.fixate_format.is_none()
+            || cbs.add_buffer.is_none()
+            || cbs.remove_buffer.is_none()
+            || cbs.process_buffer.is_none()
+        {
+            return Err(anyhow!("missing stream callbacks"));
+        }
+        let cbs = Callbacks(cbs);
+        let info = StreamInfo {
+            width: info.width,
+            height: info.height,
+            enum_formats: enum_formats(info.enum_formats, info.num_enum_formats),
+            format_preference: FormatPreference::global(),
+            colorimetry: Default::default(),
+            transform: Default::default(),
+            scale: Default::default(),
+            max_buffers: info.max_buffers,
+            node_class: NodeClass::global(),
+            props: props(info.props)?,
+            fixate_format: Box::new(move |info| cbs.fixate_format(info)),
+            add_buffer: Box::new(move |data_type| cbs.add_buffer(data_type)),
+            remove_buffer: Box::new(move |user_handle| cbs.remove_buffer(user_handle)),
+            process_buffer: Box::new(move |user_handle, meta_cbs| {
+                cbs.process_buffer(user_handle, meta_cbs)
+            }),
+            state_changed: Box::new(move |state, node_id| cbs.state_changed(state, node_id)),
+        };
+        Ok(client.0.create_stream(info)?)
+    })();
+    match res {
+        Ok(stream) => Box::into_raw(Box::new(PwCaptureStream(stream))),
+        Err(e) => {
+            error!("failed to create stream: {e:?}");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Removes the capture node, its buffers get removed through callbacks
+///
+/// # Safety
+///
+/// `stream` must be returned by `pw_capture_stream_new` or `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn pw_capture_stream_destroy(stream: *mut PwCaptureStream) {
+    if !stream.is_null() {
+        drop(Box::from_raw(stream));
+    }
+}
+
+/// Dequeues a buffer to copy the next frame into, returns `false` if there is
+/// none or no consumer wants a frame
+///
+/// # Safety
+///
+/// `stream` must be a live stream, `buffer` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn pw_capture_stream_dequeue_buffer(
+    stream: *const PwCaptureStream,
+    buffer: *mut PwCaptureBuffer,
+) -> bool {
+    let (Some(stream), Some(buffer)) = (stream.as_ref(), buffer.as_mut()) else {
+        return false;
+    };
+    let dequeued = stream.0.proxy().dequeue_buffer();
+    let (handle, user_handle) = match dequeued {
+        Ok(Some((handle, BufferUserHandle::User(user_handle)))) => (handle, user_handle),
+        Ok(_) => return false,
+        Err(e) => {
+            error!("failed to dequeue buffer: {e:?}");
+            return false;
+        }
+    };
+    let handle: ptr::NonNull<pw::sys::pw_buffer> = handle.into();
+    *buffer = PwCaptureBuffer {
+        handle: handle.as_ptr() as _,
+        user_handle,
+    };
+    true
+}
+
+/// Hands the frame copied into a dequeued buffer over to consumers, the copy
+/// must have completed
+///
+/// # Safety
+///
+/// `stream` must be a live stream, `buffer` dequeued from it.
+#[no_mangle]
+pub unsafe extern "C" fn pw_capture_stream_queue_buffer(
+    stream: *const PwCaptureStream,
+    buffer: *const PwCaptureBuffer,
+) -> bool {
+    let (Some(stream), Some(buffer)) = (stream.as_ref(), buffer.as_ref()) else {
+        return false;
+    };
+    let Some(handle) = ptr::NonNull::new(buffer.handle as *mut pw::sys::pw_buffer) else {
+        return false;
+    };
+    match stream.0.proxy().queue_buffer_process(handle.into()) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("failed to queue buffer: {e:?}");
+            false
+        }
+    }
+}
+
+/// Replaces offered formats, e.g. after the source got resized
+///
+/// # Safety
+///
+/// `stream` must be a live stream, `enum_formats` valid for the duration of
+/// the call.
+#[no_mangle]
+pub unsafe extern "C" fn pw_capture_stream_update_format(
+    stream: *const PwCaptureStream,
+    width: u32,
+    height: u32,
+    enum_formats: *const PwCaptureEnumFormat,
+    num_enum_formats: usize,
+) -> bool {
+    let Some(stream) = stream.as_ref() else {
+        return false;
+    };
+    let enum_formats = self::enum_formats(enum_formats, num_enum_formats);
+    let res = stream
+        .0
+        .proxy()
+        .update_format(width, height, enum_formats, Default::default());
+    match res {
+        Ok(()) => true,
+        Err(e) => {
+            error!("failed to update format: {e:?}");
+            false
+        }
+    }
+}
+
+/// # Safety
+///
+/// `stream` must be a live stream.
+#[no_mangle]
+pub unsafe extern "C" fn pw_capture_stream_state(
+    stream: *const PwCaptureStream,
+) -> PwCaptureStreamState {
+    stream
+        .as_ref()
+        .and_then(|stream| stream.0.proxy().state().ok())
+        .map_or(PwCaptureStreamState::Error, Into::into)
+}
+
+/// PipeWire node id consumers connect to, `PW_CAPTURE_ID_ANY` until assigned
+///
+/// # Safety
+///
+/// `stream` must be a live stream.
+#[no_mangle]
+pub unsafe extern "C" fn pw_capture_stream_node_id(stream: *const PwCaptureStream) -> u32 {
+    stream
+        .as_ref()
+        .and_then(|stream| stream.0.proxy().node_id().ok())
+        .flatten()
+        .unwrap_or(PW_CAPTURE_ID_ANY)
+}
+
+/// Current time in `CLOCK_MONOTONIC` nanoseconds, as expected for `pts`
+#[no_mangle]
+pub extern "C" fn pw_capture_get_pts_nanos() -> i64 {
+    get_pts_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_props() {
+        let strings: [&[u8]; 4] = [b"media.name\0", b"game\0", b"node.nick\0", b"Game\0"];
+        let mut ptrs: Vec<*const c_char> = strings.iter().map(|s| s.as_ptr() as _).collect();
+        ptrs.push(ptr::null());
+        let res = unsafe { props(ptrs.as_ptr()) }.unwrap();
+        assert_eq!(
+            res,
+            [
+                ("media.name".to_owned(), "game".to_owned()),
+                ("node.nick".to_owned(), "Game".to_owned())
+            ]
+        );
+        assert!(unsafe { props(ptr::null()) }.unwrap().is_empty());
+
+        // value missing
+        ptrs.truncate(1);
+        ptrs.push(ptr::null());
+        assert!(unsafe { props(ptrs.as_ptr()) }.is_err());
+    }
+
+    #[test]
+    fn convert_enum_formats() {
+        let formats: [u32; 3] = [Format::BGRA.into(), Format::BGRx.into(), 0xdead];
+        let modifiers = [DRM_FORMAT_MOD_LINEAR];
+        let enum_format = PwCaptureEnumFormat {
+            formats: formats.as_ptr(),
+            num_formats: formats.len(),
+            modifiers: modifiers.as_ptr(),
+            num_modifiers: modifiers.len(),
+        };
+        let res = unsafe { enum_formats(&enum_format, 1) };
+        assert_eq!(res.len(), 1);
+        assert_eq!(
+            res[0].formats,
+            [Format::BGRA, Format::BGRx, Format::UNKNOWN]
+        );
+        assert_eq!(res[0].modifiers, modifiers);
+        assert!(unsafe { enum_formats(ptr::null(), 1) }.is_empty());
+    }
+}
@@ -2,21 +2,165 @@ use crate::*;
 
 use anyhow::Context;
 
-use std::rc::Rc;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::env;
+use std::mem;
+use std::os::fd::{BorrowedFd, RawFd};
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 use std::{cell::RefCell, fmt::Debug};
 
 use anyhow::{anyhow, Result};
-use crossbeam_channel::{bounded, Sender};
+use crossbeam_channel::{bounded, unbounded, Sender};
 use dashmap::DashMap;
 use educe::Educe;
-use log::{debug, trace};
+use log::{debug, error, info, trace, warn};
+use once_cell::sync::Lazy;
 use pipewire as pw;
 use pw::main_loop::MainLoop as PwMainLoop;
 use pw::properties::properties;
 use self_cell::self_cell;
 use trait_enumizer::{crossbeam_class, enumizer};
 
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Transport used to deliver frames to consumers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    PipeWire,
+    /// obs-vkcapture compatible unix socket protocol
+    Obs,
+}
+
+impl Backend {
+    /// Selected by `PW_CAPTURE_BACKEND`, defaults to PipeWire
+    pub fn from_env() -> Self {
+        match setting("PW_CAPTURE_BACKEND").as_deref() {
+            Some("pipewire") | None => Self::PipeWire,
+            Some("obs") => Self::Obs,
+            Some(other) => {
+                warn!("unknown backend {other:?}, fallback to PipeWire");
+                Self::PipeWire
+            }
+        }
+    }
+}
+
+/// PipeWire remote the client connects to
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Remote {
+    /// Selected by `PIPEWIRE_REMOTE`, falls back to the default socket
+    #[default]
+    Default,
+    /// Socket name in the runtime directory or an absolute socket path
+    Name(String),
+    /// Already connected socket, e.g. opened by a sandbox or portal, stays
+    /// owned by the caller
+    Fd(RawFd),
+}
+
+/// Taken out of the environment on first use, children of the app would try
+/// whatever fd of theirs got the same number otherwise
+static REMOTE_FD: Lazy<Option<RawFd>> = Lazy::new(|| {
+    let value = env::var("PW_CAPTURE_REMOTE_FD").ok()?;
+    env::remove_var("PW_CAPTURE_REMOTE_FD");
+    match value.parse::<RawFd>() {
+        Ok(fd) if fd >= 0 => Some(fd),
+        _ => {
+            warn!("invalid PW_CAPTURE_REMOTE_FD {value:?}");
+            None
+        }
+    }
+});
+
+fn is_unix_socket(fd: RawFd) -> bool {
+    unsafe {
+        let mut stat: libc::stat = mem::zeroed();
+        if libc::fstat(fd, &mut stat) != 0 || stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+            return false;
+        }
+        let mut domain: libc::c_int = 0;
+        let mut len = mem::size_of_val(&domain) as libc::socklen_t;
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_DOMAIN,
+            &mut domain as *mut _ as *mut _,
+            &mut len,
+        ) == 0
+            && domain == libc::AF_UNIX
+    }
+}
+
+impl Remote {
+    /// Selected by `PW_CAPTURE_REMOTE_FD` or `PW_CAPTURE_REMOTE`, the former
+    /// takes precedence, inside Flatpak the socket is looked up wherever the
+    /// sandbox exposes it
+    pub fn from_env() -> Self {
+        if let Some(fd) = *REMOTE_FD {
+            if is_unix_socket(fd) {
+                return Self::Fd(fd);
+            }
+            warn!("PW_CAPTURE_REMOTE_FD {fd} is not a unix socket, ignored");
+        }
+        match setting("PW_CAPTURE_REMOTE") {
+            Some(name) if !name.is_empty() => Self::Name(name),
+            _ if in_flatpak() => flatpak_remote(),
+            _ => Self::Default,
+        }
+    }
+
+    pub(crate) fn connect(&self, context: &pw::context::Context) -> Result<pw::core::Core> {
+        let core = match self {
+            Self::Default => context.connect(None)?,
+            Self::Name(name) => context.connect(Some(properties! {
+                *pw::keys::REMOTE_NAME => name.as_str(),
+            }))?,
+            Self::Fd(fd) => {
+                // PipeWire takes ownership of the socket, hand over a duplicate
+                let fd = unsafe { BorrowedFd::borrow_raw(*fd) }
+                    .try_clone_to_owned()
+                    .context("invalid remote fd")?;
+                context.connect_fd(fd, None)?
+            }
+        };
+        Ok(core)
+    }
+}
+
+pub(crate) enum MessageSender<T> {
+    PipeWire(pw::channel::Sender<T>),
+    Channel(Sender<T>),
+}
+
+impl<T> Clone for MessageSender<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::PipeWire(sender) => Self::PipeWire(sender.clone()),
+            Self::Channel(sender) => Self::Channel(sender.clone()),
+        }
+    }
+}
+
+impl<T: Debug> MessageSender<T> {
+    pub(crate) fn send(&self, msg: T) -> ClientResult<()> {
+        let unsent = match self {
+            Self::PipeWire(sender) => sender.send(msg).err(),
+            Self::Channel(sender) => sender.send(msg).err().map(|e| e.0),
+        };
+        match unsent {
+            Some(msg) => {
+                debug!("failed to send {msg:?}");
+                Err(ClientError::Send)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
 #[enumizer(
     name=ClientMessage,
     pub,
@@ -28,6 +172,21 @@ use trait_enumizer::{crossbeam_class, enumizer};
 pub trait ClientMethods {
     fn terminate(&self);
     fn create_stream(&mut self, info: StreamInfo) -> Result<Stream>;
+    fn enumerate_streams(&self) -> Result<Vec<StreamNodeInfo>>;
+    fn set_capture_enabled(&mut self, enabled: bool);
+}
+
+/// Capture node created by pw-capture, of this or any other process
+#[derive(Clone, Debug)]
+pub struct StreamNodeInfo {
+    pub id: u32,
+    pub props: HashMap<String, String>,
+}
+
+impl StreamNodeInfo {
+    pub fn serial(&self) -> Option<u64> {
+        self.props.get(*pw::keys::OBJECT_SERIAL)?.parse().ok()
+    }
 }
 
 #[derive(Clone)]
@@ -37,9 +196,19 @@ struct ClientImpl {
 
 struct ClientImplInner {
     mainloop: pw::main_loop::MainLoop,
+    context: pw::context::Context,
+    remote: Remote,
     core: pw::core::Core,
+    #[allow(unused)]
+    core_listener: Option<pw::core::Listener>,
     stream_next_id: usize,
-    stream_map: DashMap<usize, (StreamImpl, OwnedReceiver)>,
+    stream_map: DashMap<usize, (StreamImpl, OwnedReceiver, Vec<OwnedTimer>)>,
+    node_map: Rc<DashMap<u32, StreamNodeInfo>>,
+    #[allow(unused)]
+    registry: pw::registry::Registry,
+    #[allow(unused)]
+    registry_listener: pw::registry::Listener,
+    reconnect_timer: Option<OwnedTimer>,
 }
 
 type StreamMessageReceiver<'a> = pw::channel::AttachedReceiver<'a, StreamMessage>;
@@ -53,6 +222,17 @@ self_cell!(
     }
 );
 
+self_cell!(
+    struct OwnedTimer {
+        owner: PwMainLoop,
+
+        #[covariant]
+        dependent: TimerSource,
+    }
+);
+
+type TimerSource<'a> = pw::loop_::TimerSource<'a>;
+
 impl ClientMethods for ClientImpl {
     fn terminate(&self) {
         let inner = self.inner.borrow();
@@ -81,16 +261,172 @@ impl ClientMethods for ClientImpl {
 
         let mainloop = self.inner.borrow().mainloop.clone();
         let (pw_sender, pw_receiver) = pw::channel::channel::<StreamMessage>();
-        let receiver = OwnedReceiver::new(mainloop, |mainloop| {
+        let receiver = OwnedReceiver::new(mainloop.clone(), |mainloop| {
             stream_impl.attach(mainloop.loop_(), pw_receiver)
         });
+        let mut timers = vec![OwnedTimer::new(mainloop.clone(), |mainloop| {
+            stream_impl.add_stats_timer(mainloop.loop_())
+        })];
+        timers.extend(
+            OwnedTimer::try_new(mainloop, |mainloop| {
+                stream_impl.add_keepalive_timer(mainloop.loop_()).ok_or(())
+            })
+            .ok(),
+        );
+
+        if !capture_enabled() {
+            // connected once capture gets enabled
+            stream_impl.suspend();
+        }
 
         self.inner
             .borrow_mut()
             .stream_map
-            .insert(id, (stream_impl, receiver));
+            .insert(id, (stream_impl, receiver, timers));
+
+        Ok(Stream {
+            sender: MessageSender::PipeWire(pw_sender),
+            worker: Arc::new(Worker::new()?),
+        })
+    }
+
+    fn enumerate_streams(&self) -> Result<Vec<StreamNodeInfo>> {
+        let inner = self.inner.borrow();
+        let mut nodes: Vec<_> = inner
+            .node_map
+            .iter()
+            .map(|node| node.value().clone())
+            .collect();
+        nodes.sort_by_key(|node| node.id);
+        Ok(nodes)
+    }
+
+    fn set_capture_enabled(&mut self, enabled: bool) {
+        let streams: Vec<_> = self
+            .inner
+            .borrow()
+            .stream_map
+            .iter()
+            .map(|entry| entry.value().0.clone())
+            .filter(|stream_impl| stream_impl.is_suspended() == enabled)
+            .collect();
+        let core = self.inner.borrow().core.clone();
+        for stream_impl in streams {
+            if !enabled {
+                stream_impl.suspend();
+            } else if let Err(e) = stream_impl.reconnect(&core) {
+                error!("failed to reconnect stream: {e:?}");
+            }
+        }
+    }
+}
+
+impl ClientImpl {
+    /// Watches for the connection to the daemon being lost
+    fn add_core_listener(&self) -> pw::core::Listener {
+        let inner_weak = Rc::downgrade(&self.inner);
+        self.inner
+            .borrow()
+            .core
+            .add_listener_local()
+            .error(move |id, seq, res, message| {
+                if id != pw::core::PW_ID_CORE {
+                    return;
+                }
+                warn!("core error: seq {} res {} {}", seq, res, message);
+                if res != -libc::EPIPE {
+                    return;
+                }
+                if let Some(client_impl) = ClientImpl::upgrade(&inner_weak) {
+                    client_impl.schedule_reconnect();
+                }
+            })
+            .register()
+    }
+
+    fn upgrade(inner_weak: &Weak<RefCell<ClientImplInner>>) -> Option<Self> {
+        inner_weak.upgrade().map(|inner| Self { inner })
+    }
+
+    fn schedule_reconnect(&self) {
+        let mut inner = self.inner.borrow_mut();
+        if let Remote::Fd(_) = inner.remote {
+            // passed socket is closed for good, there is nothing to reconnect to
+            warn!("disconnected from PipeWire remote passed by fd");
+            return;
+        }
+        info!("disconnected from PipeWire, reconnecting");
+        if inner.reconnect_timer.is_none() {
+            let mainloop = inner.mainloop.clone();
+            let inner_weak = Rc::downgrade(&self.inner);
+            inner.reconnect_timer = Some(OwnedTimer::new(mainloop, |mainloop| {
+                mainloop.loop_().add_timer(move |_| {
+                    if let Some(client_impl) = ClientImpl::upgrade(&inner_weak) {
+                        client_impl.try_reconnect();
+                    }
+                })
+            }));
+        }
+        if let Some(timer) = inner.reconnect_timer.as_ref() {
+            let _ = timer
+                .borrow_dependent()
+                .update_timer(Some(RECONNECT_INTERVAL), Some(RECONNECT_INTERVAL));
+        }
+    }
+
+    fn try_reconnect(&self) {
+        match self.reconnect() {
+            Ok(()) => {
+                info!("reconnected to PipeWire");
+                if let Some(timer) = self.inner.borrow().reconnect_timer.as_ref() {
+                    let _ = timer.borrow_dependent().update_timer(None, None);
+                }
+            }
+            Err(e) => debug!("failed to reconnect: {e:?}"),
+        }
+    }
+
+    /// Re-establishes the connection and recreates all streams on it
+    fn reconnect(&self) -> Result<()> {
+        let core = {
+            let inner = self.inner.borrow();
+            inner.remote.connect(&inner.context)?
+        };
+        let node_map = self.inner.borrow().node_map.clone();
+        node_map.clear();
+        let (registry, registry_listener) = watch_capture_nodes(&core, node_map)?;
+
+        let old = {
+            let mut inner = self.inner.borrow_mut();
+            (
+                inner.core_listener.take(),
+                mem::replace(&mut inner.registry_listener, registry_listener),
+                mem::replace(&mut inner.registry, registry),
+                mem::replace(&mut inner.core, core),
+            )
+        };
+        let core_listener = self.add_core_listener();
+        self.inner.borrow_mut().core_listener = Some(core_listener);
+
+        let streams: Vec<_> = self
+            .inner
+            .borrow()
+            .stream_map
+            .iter()
+            .map(|entry| entry.value().0.clone())
+            // connected again once capture gets enabled
+            .filter(|stream_impl| !stream_impl.is_suspended())
+            .collect();
+        let core = self.inner.borrow().core.clone();
+        for stream_impl in streams {
+            if let Err(e) = stream_impl.reconnect(&core) {
+                error!("failed to recreate stream: {e:?}");
+            }
+        }
 
-        Ok(Stream { pw_sender })
+        // old streams are gone, now safe to disconnect
+        drop(old);
+        Ok(())
     }
 }
 
@@ -98,26 +434,27 @@ impl ClientMethods for ClientImpl {
 #[educe(Debug)]
 pub struct Stream {
     #[educe(Debug(ignore))]
-    pub(crate) pw_sender: pipewire::channel::Sender<StreamMessage>,
+    pub(crate) sender: MessageSender<StreamMessage>,
+    #[educe(Debug(ignore))]
+    pub(crate) worker: Arc<Worker>,
 }
 
 impl Stream {
-    pub fn proxy(
-        &self,
-    ) -> StreamMethodsProxy<anyhow::Error, impl Fn(StreamMessage) -> Result<(), anyhow::Error>>
-    {
-        let pw_sender = self.pw_sender.clone();
-        StreamMethodsProxy(move |msg| {
-            pw_sender
-                .send(msg)
-                .map_err(|e| anyhow!("failed to send {e:?}"))
-        })
+    /// Handle to call the stream through, e.g. after releasing locks guarding
+    /// the stream
+    pub fn proxy(&self) -> StreamProxy {
+        StreamProxy::new(self.sender.clone())
+    }
+
+    /// Worker frames should be queued on
+    pub fn worker(&self) -> Arc<Worker> {
+        self.worker.clone()
     }
 }
 
 impl Drop for Stream {
     fn drop(&mut self) {
-        let _ = self.proxy().try_terminate();
+        let _ = self.proxy().drain_and_terminate();
     }
 }
 
@@ -125,55 +462,110 @@ impl Drop for Stream {
 #[educe(Debug)]
 pub struct Client {
     #[educe(Debug(ignore))]
-    pw_sender: pw::channel::Sender<ClientMessage>,
-    pw_thread: Option<thread::JoinHandle<Result<()>>>,
+    sender: MessageSender<ClientMessage>,
+    thread: Mutex<Option<thread::JoinHandle<Result<()>>>>,
+    /// Registered with the control socket until shut down
+    toggle_cb: Mutex<Option<usize>>,
 }
 
 impl Client {
-    pub fn new() -> Result<Self> {
-        debug!("creating client");
+    pub fn new() -> ClientResult<Self> {
+        Self::with_backend(Backend::from_env())
+    }
+
+    pub fn with_backend(backend: Backend) -> ClientResult<Self> {
+        Self::with_remote(backend, Remote::from_env())
+    }
+
+    /// `remote` is only used by the PipeWire backend
+    pub fn with_remote(backend: Backend, remote: Remote) -> ClientResult<Self> {
+        debug!("creating client, backend: {backend:?}, remote: {remote:?}");
         let (done_sender, done_receiver) = bounded(1);
-        let (pw_sender, pw_receiver) = pw::channel::channel::<ClientMessage>();
-        let pw_thread = thread::spawn(move || pw_thread(done_sender, pw_receiver));
+        let (sender, thread) = match backend {
+            Backend::PipeWire => {
+                let (pw_sender, pw_receiver) = pw::channel::channel::<ClientMessage>();
+                let thread = thread::spawn(move || pw_thread(done_sender, pw_receiver, remote));
+                (MessageSender::PipeWire(pw_sender), thread)
+            }
+            Backend::Obs => {
+                let (sender, receiver) = unbounded::<ClientMessage>();
+                let thread = thread::spawn(move || obs_thread(done_sender, receiver));
+                (MessageSender::Channel(sender), thread)
+            }
+        };
+
+        if done_receiver.recv().is_err() {
+            // the thread exits right away if it failed to start
+            let e = match thread.join() {
+                Ok(Err(e)) => e,
+                _ => anyhow!("client thread panicked"),
+            };
+            let e = e.context(format!("failed to start {backend:?} backend"));
+            return Err(ClientError::Connection(e));
+        }
 
-        done_receiver
-            .recv()
-            .context("failed to connect PipeWire Server")?;
+        let proxy = {
+            let sender = sender.clone();
+            ClientMethodsProxy(move |msg| sender.send(msg))
+        };
+        let toggle_cb = add_toggle_cb(Arc::new(move |enabled| {
+            if let Err(e) = proxy.try_set_capture_enabled(enabled) {
+                debug!("failed to toggle capture: {e:?}");
+            }
+        }));
 
         Ok(Self {
-            pw_sender,
-            pw_thread: Some(pw_thread),
+            sender,
+            thread: Mutex::new(Some(thread)),
+            toggle_cb: Mutex::new(Some(toggle_cb)),
         })
     }
 
-    pub fn proxy(
+    fn methods(
         &self,
-    ) -> ClientMethodsProxy<anyhow::Error, impl Fn(ClientMessage) -> Result<(), anyhow::Error>>
-    {
-        let pw_sender = self.pw_sender.clone();
-        ClientMethodsProxy(move |msg| {
-            pw_sender
-                .send(msg)
-                .map_err(|e| anyhow!("failed to send {e:?}"))
-        })
+    ) -> ClientMethodsProxy<ClientError, impl Fn(ClientMessage) -> ClientResult<()> + '_> {
+        ClientMethodsProxy(move |msg| self.sender.send(msg))
     }
-}
 
-impl Drop for Client {
-    fn drop(&mut self) {
-        let proxy = self.proxy();
-        if proxy.try_terminate().is_err() {
+    /// Creates a capture node, removed once the returned stream is dropped
+    pub fn create_stream(&self, info: StreamInfo) -> ClientResult<Stream> {
+        try_reply(
+            self.methods().try_create_stream(info),
+            ClientError::Connection,
+        )
+    }
+
+    /// List capture nodes currently registered on the PipeWire graph
+    pub fn enumerate_streams(&self) -> ClientResult<Vec<StreamNodeInfo>> {
+        try_reply(self.methods().try_enumerate_streams(), ClientError::Remote)
+    }
+
+    /// Terminates remaining streams and waits for the client thread to exit,
+    /// for clients living in statics that never get dropped
+    pub fn shutdown(&self) {
+        // toggles wait for the client thread
+        if let Some(id) = self.toggle_cb.lock().unwrap().take() {
+            remove_toggle_cb(id);
+        }
+        if self.methods().try_terminate().is_err() {
             return;
         }
-        if let Some(th) = self.pw_thread.take() {
+        if let Some(th) = self.thread.lock().unwrap().take() {
             let _ = th.join();
         }
     }
 }
 
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
 fn pw_thread(
     done_sender: Sender<()>,
     pw_receiver: pw::channel::Receiver<ClientMessage>,
+    remote: Remote,
 ) -> Result<()> {
     let mainloop = pw::main_loop::MainLoop::new(None)?;
 
@@ -185,19 +577,47 @@ fn pw_thread(
         },
     )?;
 
-    let core = context.connect(None)?;
+    let core = remote.connect(&context)?;
 
     debug!("{:?}", core);
 
+    let node_map = Rc::new(DashMap::new());
+    let (registry, registry_listener) = watch_capture_nodes(&core, node_map.clone())?;
+
+    // report ready once existing globals have been enumerated
+    let pending = core.sync(0)?;
+    let done_sender = Cell::new(Some(done_sender));
+    let _core_listener = core
+        .add_listener_local()
+        .done(move |id, seq| {
+            if id != pw::core::PW_ID_CORE || seq.seq() != pending.seq() {
+                return;
+            }
+            if let Some(done_sender) = done_sender.take() {
+                let _ = done_sender.send(());
+            }
+        })
+        .register();
+
     let client_impl_inner = ClientImplInner {
         mainloop: mainloop.clone(),
+        context,
+        remote,
         core,
+        core_listener: None,
         stream_next_id: 0,
         stream_map: DashMap::new(),
+        node_map,
+        registry,
+        registry_listener,
+        reconnect_timer: None,
     };
-    let client_impl = RefCell::new(ClientImpl {
+    let client_impl = ClientImpl {
         inner: Rc::new(RefCell::new(client_impl_inner)),
-    });
+    };
+    let core_listener = client_impl.add_core_listener();
+    client_impl.inner.borrow_mut().core_listener = Some(core_listener);
+    let client_impl = RefCell::new(client_impl);
     let _receiver = pw_receiver.attach(mainloop.loop_(), {
         move |msg| {
             trace!("receive {:?}", msg);
@@ -205,10 +625,52 @@ fn pw_thread(
         }
     });
 
-    done_sender.send(())?;
-    drop(done_sender);
-
     mainloop.run();
 
     Ok(())
 }
+
+/// Tracks capture nodes on the registry of `core` into `node_map`
+fn watch_capture_nodes(
+    core: &pw::core::Core,
+    node_map: Rc<DashMap<u32, StreamNodeInfo>>,
+) -> Result<(pw::registry::Registry, pw::registry::Listener)> {
+    let registry = core.get_registry()?;
+    let registry_listener = registry
+        .add_listener_local()
+        .global({
+            let node_map = node_map.clone();
+            move |global| {
+                if global.type_ != pw::types::ObjectType::Node {
+                    return;
+                }
+                let props = if let Some(props) = global.props {
+                    props
+                } else {
+                    return;
+                };
+                if props.get(*pw::keys::MEDIA_SOFTWARE) != Some("pw-capture") {
+                    return;
+                }
+                let props: HashMap<_, _> = props
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect();
+                trace!("capture node {} added", global.id);
+                node_map.insert(
+                    global.id,
+                    StreamNodeInfo {
+                        id: global.id,
+                        props,
+                    },
+                );
+            }
+        })
+        .global_remove(move |id| {
+            if node_map.remove(&id).is_some() {
+                trace!("capture node {} removed", id);
+            }
+        })
+        .register();
+    Ok((registry, registry_listener))
+}
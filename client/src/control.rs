@@ -0,0 +1,228 @@
+//! Runtime capture toggle through a per-process control socket
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use once_cell::sync::Lazy;
+
+use crate::logger::{set_log_filter, LogFilter};
+use crate::settings::setting;
+
+const SOCKET_DIR: &str = "pw-capture";
+/// Commands are handled on one thread, a client not sending one in time
+/// mustn't hold off the others
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+static CAPTURE_ENABLED: AtomicBool = AtomicBool::new(true);
+
+type ToggleCb = Arc<dyn Fn(bool) + Send + Sync>;
+
+static TOGGLE_CBS: Mutex<Vec<(usize, ToggleCb)>> = Mutex::new(Vec::new());
+static NEXT_TOGGLE_ID: AtomicUsize = AtomicUsize::new(0);
+
+static CONTROL_SOCKET: Lazy<Option<PathBuf>> = Lazy::new(|| {
+    if matches!(setting("PW_CAPTURE_CONTROL").as_deref(), Some("0")) {
+        debug!("control socket disabled");
+        return None;
+    }
+    let path = control_socket_path(std::process::id())?;
+    match listen(&path) {
+        Ok(()) => {
+            debug!("control socket: {path:?}");
+            unsafe { libc::atexit(remove_control_socket) };
+            Some(path)
+        }
+        Err(e) => {
+            warn!("failed to listen on control socket {path:?}: {e}");
+            None
+        }
+    }
+});
+
+/// Whether capture is enabled, layers skip frames otherwise
+pub fn capture_enabled() -> bool {
+    CAPTURE_ENABLED.load(Ordering::Acquire)
+}
+
+/// Control socket of process `pid`, `None` if `XDG_RUNTIME_DIR` is not set
+pub fn control_socket_path(pid: u32) -> Option<PathBuf> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")?;
+    let mut path = PathBuf::from(runtime_dir);
+    path.push(SOCKET_DIR);
+    path.push(format!("{pid}.sock"));
+    Some(path)
+}
+
+/// Starts listening on the control socket once, `cb` gets called with the
+/// new state on every change until unregistered by the returned id
+pub(crate) fn add_toggle_cb(cb: ToggleCb) -> usize {
+    Lazy::force(&CONTROL_SOCKET);
+    let id = NEXT_TOGGLE_ID.fetch_add(1, Ordering::Relaxed);
+    TOGGLE_CBS.lock().unwrap().push((id, cb));
+    id
+}
+
+pub(crate) fn remove_toggle_cb(id: usize) {
+    TOGGLE_CBS.lock().unwrap().retain(|(v, _)| *v != id);
+}
+
+/// Unlinks the control socket on exit, unless left by the parent of a forked
+/// process
+extern "C" fn remove_control_socket() {
+    let Some(path) = CONTROL_SOCKET.as_ref() else {
+        return;
+    };
+    if control_socket_path(std::process::id()).as_ref() == Some(path) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn set_capture_enabled(enabled: bool) {
+    // commands are handled one at a time so clients see changes in order
+    if CAPTURE_ENABLED.swap(enabled, Ordering::AcqRel) == enabled {
+        return;
+    }
+    info!("capture {}", if enabled { "enabled" } else { "disabled" });
+    // callbacks may wait for client threads removing theirs meanwhile
+    let cbs: Vec<_> = TOGGLE_CBS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, cb)| cb.clone())
+        .collect();
+    for cb in cbs {
+        cb(enabled);
+    }
+}
+
+/// New state after `command`, `None` if unknown
+fn apply_command(command: &str, enabled: bool) -> Option<bool> {
+    match command.trim() {
+        "enable" => Some(true),
+        "disable" => Some(false),
+        "toggle" => Some(!enabled),
+        "status" => Some(enabled),
+        _ => None,
+    }
+}
+
+/// Reply to `log <spec>`
+fn apply_log_command(spec: &str) -> String {
+    match LogFilter::parse(spec) {
+        Ok(filter) => {
+            info!("log filter set to {:?}", spec.trim());
+            set_log_filter(filter);
+            "log filter set".into()
+        }
+        Err(e) => e.to_string(),
+    }
+}
+
+fn listen(path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+    // a process of the same pid in another pid namespace may still listen,
+    // the socket is left behind by an exited one otherwise
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "socket in use by another process",
+        ));
+    }
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    thread::Builder::new()
+        .name("pw-capture-control".into())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let res = stream.and_then(handle_connection);
+                if let Err(e) = res {
+                    debug!("control connection failed: {e}");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn handle_connection(mut stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut command = String::new();
+    BufReader::new(&stream).read_line(&mut command)?;
+    if let Some(spec) = command.trim_start().strip_prefix("log ") {
+        return writeln!(stream, "{}", apply_log_command(spec));
+    }
+    let reply = match apply_command(&command, capture_enabled()) {
+        Some(enabled) => {
+            set_capture_enabled(enabled);
+            if enabled {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        }
+        None => "unknown command",
+    };
+    writeln!(stream, "{reply}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(apply_command("enable\n", false), Some(true));
+        assert_eq!(apply_command("disable", true), Some(false));
+        assert_eq!(apply_command("toggle", true), Some(false));
+        assert_eq!(apply_command("toggle", false), Some(true));
+        assert_eq!(apply_command(" status ", false), Some(false));
+        assert_eq!(apply_command("restart", true), None);
+        assert_eq!(
+            apply_log_command("client=loud"),
+            "invalid log level \"loud\""
+        );
+    }
+
+    #[test]
+    fn socket_in_use() {
+        let path = env::temp_dir().join(format!("pw-capture-test-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        listen(&path).unwrap();
+        let err = listen(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        writeln!(stream, "status").unwrap();
+        let mut reply = String::new();
+        BufReader::new(&stream).read_line(&mut reply).unwrap();
+        assert_eq!(reply, "enabled\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn socket_path() {
+        let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR") else {
+            return;
+        };
+        let path = control_socket_path(42).unwrap();
+        assert_eq!(
+            path,
+            PathBuf::from(runtime_dir)
+                .join("pw-capture")
+                .join("42.sock")
+        );
+    }
+}
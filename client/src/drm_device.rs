@@ -0,0 +1,136 @@
+//! DRM device frames get exported on
+
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+use crate::settings::setting;
+
+pub const PROP_DRM_DEVICE: &str = "pw-capture.drm-device";
+pub const PROP_BUFFER_DEVICE: &str = "api.bufferfd.device";
+
+const DRI_DIR: &str = "/dev/dri";
+
+static TARGET_DEVICE: Lazy<Option<DrmDevice>> = Lazy::new(DrmDevice::target_from_env);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrmDevice {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl DrmDevice {
+    pub fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    pub fn from_dev(dev: libc::dev_t) -> Self {
+        Self::new(libc::major(dev), libc::minor(dev))
+    }
+
+    pub fn dev(self) -> libc::dev_t {
+        libc::makedev(self.major, self.minor)
+    }
+
+    /// Parses `major:minor`, or else stats a device node path
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Some((major, minor)) = value.split_once(':') {
+            return Some(Self::new(major.parse().ok()?, minor.parse().ok()?));
+        }
+        let metadata = fs::metadata(value).ok()?;
+        Some(Self::from_dev(metadata.rdev()))
+    }
+
+    fn target_from_env() -> Option<Self> {
+        let value = setting("PW_CAPTURE_DRM_DEVICE")?;
+        let device = Self::parse(&value);
+        if device.is_none() {
+            warn!("invalid PW_CAPTURE_DRM_DEVICE {value:?}");
+        }
+        debug!("target drm device: {:?}", device);
+        device
+    }
+
+    /// Device set by `PW_CAPTURE_DRM_DEVICE`
+    pub fn target() -> Option<Self> {
+        *TARGET_DEVICE
+    }
+
+    /// Whether DMA-BUFs of a device with nodes `devices` can be imported by
+    /// consumers, unknown devices are assumed to be importable
+    pub fn is_target(devices: &[Self]) -> bool {
+        match Self::target() {
+            Some(target) => devices.is_empty() || devices.contains(&target),
+            None => true,
+        }
+    }
+
+    /// Device node of this device under `/dev/dri`, render nodes are named
+    /// `renderD<minor>` and primary nodes `card<n>`
+    pub fn node_path(self) -> Option<PathBuf> {
+        self.find_node(Path::new(DRI_DIR))
+    }
+
+    fn find_node(self, dir: &Path) -> Option<PathBuf> {
+        fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| {
+                // symlinks like /dev/stdin and block devices are skipped
+                fs::symlink_metadata(path).map_or(false, |v| {
+                    v.file_type().is_char_device() && v.rdev() == self.dev()
+                })
+            })
+    }
+
+    pub fn as_props(self) -> Vec<(String, String)> {
+        let mut props = vec![(PROP_DRM_DEVICE.to_owned(), self.dev().to_string())];
+        if let Some(path) = self.node_path() {
+            props.push((
+                PROP_BUFFER_DEVICE.to_owned(),
+                path.to_string_lossy().into_owned(),
+            ));
+        }
+        props
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(DrmDevice::parse("226:128"), Some(DrmDevice::new(226, 128)));
+        assert_eq!(DrmDevice::parse(" 226:0 "), Some(DrmDevice::new(226, 0)));
+        assert_eq!(DrmDevice::parse("226:"), None);
+        assert_eq!(DrmDevice::parse("/nonexistent/renderD128"), None);
+    }
+
+    #[test]
+    fn dev() {
+        let device = DrmDevice::new(226, 128);
+        assert_eq!(DrmDevice::from_dev(device.dev()), device);
+    }
+
+    #[test]
+    fn node_path() {
+        // character devices every system has, /dev/null is 1:3
+        let null = DrmDevice::parse("/dev/null").unwrap();
+        assert_eq!(null, DrmDevice::new(1, 3));
+        assert_eq!(null.find_node(Path::new("/dev")), Some("/dev/null".into()));
+        assert_eq!(null.find_node(Path::new("/nonexistent")), None);
+
+        let unknown = DrmDevice::new(226, 999);
+        assert_eq!(unknown.node_path(), None);
+        assert_eq!(
+            unknown.as_props(),
+            [(PROP_DRM_DEVICE.to_owned(), unknown.dev().to_string())]
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Errors of the client API
+
+use std::error::Error;
+use std::fmt;
+
+use crossbeam_channel::RecvError;
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// Connecting to the PipeWire daemon or the obs-vkcapture socket, or
+    /// creating a stream on it, failed
+    Connection(anyhow::Error),
+    /// Formats or buffers could not be offered to consumers
+    Negotiation(anyhow::Error),
+    /// The client thread is gone, e.g. after shutdown
+    Send,
+    /// The client thread exited before replying
+    Recv,
+    /// The call failed on the client thread
+    Remote(anyhow::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connection(e) => write!(f, "connection failed: {e}"),
+            Self::Negotiation(e) => write!(f, "negotiation failed: {e}"),
+            Self::Send => write!(f, "client thread is gone"),
+            Self::Recv => write!(f, "client thread exited before replying"),
+            Self::Remote(e) => write!(f, "call failed: {e}"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Connection(e) | Self::Negotiation(e) | Self::Remote(e) => Some(&**e),
+            Self::Send | Self::Recv => None,
+        }
+    }
+}
+
+impl From<RecvError> for ClientError {
+    fn from(_: RecvError) -> Self {
+        Self::Recv
+    }
+}
+
+/// Reply of a call on the client thread
+pub(crate) fn reply<T>(res: Result<Result<T, RecvError>, ClientError>) -> ClientResult<T> {
+    Ok(res??)
+}
+
+/// Reply of a fallible call on the client thread, failures of the call itself
+/// are wrapped by `kind`
+pub(crate) fn try_reply<T>(
+    res: Result<Result<anyhow::Result<T>, RecvError>, ClientError>,
+    kind: fn(anyhow::Error) -> ClientError,
+) -> ClientResult<T> {
+    reply(res)?.map_err(kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+
+    #[test]
+    fn reply_errors() {
+        assert_eq!(reply(Ok(Ok(1))).unwrap(), 1);
+        assert!(matches!(
+            reply::<()>(Err(ClientError::Send)),
+            Err(ClientError::Send)
+        ));
+        assert!(matches!(
+            reply::<()>(Ok(Err(RecvError))),
+            Err(ClientError::Recv)
+        ));
+
+        let res = try_reply::<()>(Ok(Ok(Err(anyhow!("no buffers")))), ClientError::Negotiation);
+        let e = res.unwrap_err();
+        assert!(matches!(e, ClientError::Negotiation(_)));
+        assert_eq!(e.to_string(), "negotiation failed: no buffers");
+        assert_eq!(e.source().unwrap().to_string(), "no buffers");
+    }
+}
@@ -0,0 +1,155 @@
+//! Running inside Flatpak
+
+use crate::*;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+const FLATPAK_INFO: &str = "/.flatpak-info";
+const DEFAULT_REMOTE: &str = "pipewire-0";
+
+static IN_FLATPAK: Lazy<bool> = Lazy::new(|| {
+    let res = Path::new(FLATPAK_INFO).exists();
+    if res {
+        debug!("running inside Flatpak");
+    }
+    res
+});
+
+static GL_LIB_DIRS: Lazy<Vec<PathBuf>> = Lazy::new(|| {
+    let dirs = match gl_extension_dir() {
+        Some(dir) if in_flatpak() => gl_extension_lib_dirs(&dir),
+        _ => Vec::new(),
+    };
+    debug!("Flatpak GL library dirs: {:?}", dirs);
+    dirs
+});
+
+/// Whether the process runs inside a Flatpak sandbox
+pub fn in_flatpak() -> bool {
+    *IN_FLATPAK
+}
+
+/// Library directories of GL extensions mounted into the sandbox, where host
+/// GL and Vulkan drivers live, the `default` one first, empty outside Flatpak
+pub fn flatpak_gl_lib_dirs() -> &'static [PathBuf] {
+    &GL_LIB_DIRS
+}
+
+/// Remote to connect to from inside the sandbox, the socket of
+/// `PIPEWIRE_REMOTE` or the default one wherever it was exposed
+pub(crate) fn flatpak_remote() -> Remote {
+    let name = env::var("PIPEWIRE_REMOTE").unwrap_or_else(|_| DEFAULT_REMOTE.to_owned());
+    if Path::new(&name).is_absolute() {
+        return Remote::Default;
+    }
+    match find_socket(&name, &runtime_dirs()) {
+        Some(path) => {
+            debug!("Flatpak PipeWire socket: {path:?}");
+            Remote::Name(path.to_string_lossy().into_owned())
+        }
+        None => {
+            warn!(
+                "PipeWire socket {name:?} not exposed to the sandbox, grant \
+                --filesystem=xdg-run/{name} or pass PW_CAPTURE_REMOTE_FD"
+            );
+            Remote::Default
+        }
+    }
+}
+
+/// Directories libpipewire looks for sockets in, followed by the runtime
+/// directory of the user on the host, which `xdg-run` permissions expose
+/// even if the sandbox got another `XDG_RUNTIME_DIR`
+fn runtime_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = ["PIPEWIRE_RUNTIME_DIR", "XDG_RUNTIME_DIR"]
+        .iter()
+        .filter_map(env::var_os)
+        .map(PathBuf::from)
+        .collect();
+    let uid = unsafe { libc::getuid() };
+    dirs.push(PathBuf::from(format!("/run/user/{uid}")));
+    dirs
+}
+
+fn find_socket(name: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+    dirs.iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.exists())
+}
+
+/// Where GL extensions of the runtime matching this build are mounted
+fn gl_extension_dir() -> Option<PathBuf> {
+    let triplet = if cfg!(target_arch = "x86_64") {
+        "x86_64-linux-gnu"
+    } else if cfg!(target_arch = "x86") {
+        "i386-linux-gnu"
+    } else if cfg!(target_arch = "aarch64") {
+        "aarch64-linux-gnu"
+    } else if cfg!(target_arch = "arm") {
+        "arm-linux-gnueabihf"
+    } else {
+        return None;
+    };
+    Some(Path::new("/usr/lib").join(triplet).join("GL"))
+}
+
+fn gl_extension_lib_dirs(gl_dir: &Path) -> Vec<PathBuf> {
+    let mut names: Vec<_> = match fs::read_dir(gl_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name())
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+    names.sort_by_key(|name| (name != "default", name.clone()));
+    names
+        .into_iter()
+        .map(|name| gl_dir.join(name).join("lib"))
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    #[test]
+    fn sandbox_paths() {
+        let root = env::temp_dir().join(format!("pw-capture-flatpak-{}", process::id()));
+        let run = root.join("run");
+        let gl = root.join("GL");
+        for dir in [
+            &run,
+            &gl.join("nvidia-550-54").join("lib"),
+            &gl.join("default").join("lib"),
+        ] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::create_dir_all(gl.join("host")).unwrap();
+        fs::write(run.join("pipewire-0"), b"").unwrap();
+
+        let dirs = [root.join("missing"), run.clone()];
+        assert_eq!(
+            find_socket("pipewire-0", &dirs),
+            Some(run.join("pipewire-0"))
+        );
+        assert_eq!(find_socket("pipewire-1", &dirs), None);
+        assert_eq!(
+            gl_extension_lib_dirs(&gl),
+            [
+                gl.join("default").join("lib"),
+                gl.join("nvidia-550-54").join("lib"),
+            ]
+        );
+        assert!(gl_extension_lib_dirs(&root.join("missing")).is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
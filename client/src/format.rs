@@ -105,6 +105,107 @@ impl Default for Format {
     }
 }
 
+const fn fourcc_code(a: u8, b: u8, c: u8, d: u8) -> u32 {
+    (a as u32) | ((b as u32) << 8) | ((c as u32) << 16) | ((d as u32) << 24)
+}
+
+pub const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ffffffffffffff;
+
+const DRM_FORMAT_MOD_VENDOR_INTEL: u64 = 0x01;
+const DRM_FORMAT_MOD_VENDOR_AMD: u64 = 0x02;
+const AMD_FMT_MOD_DCC_SHIFT: u64 = 13;
+const AMD_FMT_MOD_DCC_RETILE_SHIFT: u64 = 14;
+
+/// Memory planes of single-plane formats laid out by `modifier`, including
+/// auxiliary planes of compression metadata and clear color, `None` if unknown
+pub const fn modifier_plane_count(modifier: u64) -> Option<usize> {
+    if modifier == DRM_FORMAT_MOD_LINEAR {
+        return Some(1);
+    }
+    let value = modifier & 0x00ff_ffff_ffff_ffff;
+    match modifier >> 56 {
+        DRM_FORMAT_MOD_VENDOR_INTEL => match value {
+            // X, Y, Yf and 4 tiling, and flat CCS of DG2, LNL and BMG
+            1 | 2 | 3 | 9 | 10 | 11 | 16 | 17 => Some(1),
+            // CCS, or clear color of DG2
+            4 | 5 | 6 | 7 | 12 | 13 | 14 => Some(2),
+            // CCS and clear color
+            8 | 15 => Some(3),
+            _ => None,
+        },
+        DRM_FORMAT_MOD_VENDOR_AMD => {
+            let dcc = (value >> AMD_FMT_MOD_DCC_SHIFT) & 1;
+            let dcc_retile = (value >> AMD_FMT_MOD_DCC_RETILE_SHIFT) & 1;
+            Some(1 + dcc as usize + dcc_retile as usize)
+        }
+        _ => None,
+    }
+}
+
+impl Format {
+    /// Equivalent DRM fourcc, DRM formats are named in little-endian word order
+    /// while SPA formats are named in memory byte order
+    pub const fn drm_fourcc(&self) -> Option<u32> {
+        let fourcc = match self {
+            Format::BGRA => fourcc_code(b'A', b'R', b'2', b'4'),
+            Format::BGRx => fourcc_code(b'X', b'R', b'2', b'4'),
+            Format::RGBA => fourcc_code(b'A', b'B', b'2', b'4'),
+            Format::RGBx => fourcc_code(b'X', b'B', b'2', b'4'),
+            Format::ARGB => fourcc_code(b'B', b'A', b'2', b'4'),
+            Format::xRGB => fourcc_code(b'B', b'X', b'2', b'4'),
+            Format::ABGR => fourcc_code(b'R', b'A', b'2', b'4'),
+            Format::xBGR => fourcc_code(b'R', b'X', b'2', b'4'),
+            Format::ARGB_210LE => fourcc_code(b'A', b'R', b'3', b'0'),
+            Format::xRGB_210LE => fourcc_code(b'X', b'R', b'3', b'0'),
+            Format::ABGR_210LE => fourcc_code(b'A', b'B', b'3', b'0'),
+            Format::xBGR_210LE => fourcc_code(b'X', b'B', b'3', b'0'),
+            Format::RGBA_F16 => fourcc_code(b'A', b'B', b'4', b'H'),
+            Format::GRAY8 => fourcc_code(b'R', b'8', b' ', b' '),
+            _ => return None,
+        };
+        Some(fourcc)
+    }
+
+    /// Variant with the alpha channel left unused, same layout in memory
+    pub const fn opaque(self) -> Self {
+        match self {
+            Format::RGBA => Format::RGBx,
+            Format::BGRA => Format::BGRx,
+            Format::ARGB => Format::xRGB,
+            Format::ABGR => Format::xBGR,
+            Format::ARGB_210LE => Format::xRGB_210LE,
+            Format::ABGR_210LE => Format::xBGR_210LE,
+            Format::RGBA_102LE => Format::RGBx_102LE,
+            Format::BGRA_102LE => Format::BGRx_102LE,
+            format => format,
+        }
+    }
+
+    pub const fn is_yuv(&self) -> bool {
+        matches!(
+            self,
+            Format::I420
+                | Format::YV12
+                | Format::YUY2
+                | Format::UYVY
+                | Format::AYUV
+                | Format::Y41B
+                | Format::Y42B
+                | Format::YVYU
+                | Format::Y444
+                | Format::NV12
+                | Format::NV21
+                | Format::NV16
+                | Format::NV61
+                | Format::NV24
+                | Format::VYUY
+                | Format::P010_10BE
+                | Format::P010_10LE
+        )
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Transfer {
     UNKNOWN,
@@ -119,9 +220,120 @@ pub enum Transfer {
     SFLOAT,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[allow(non_camel_case_types)]
+#[repr(u32)]
+pub enum ColorRange {
+    // enum value/order must be in sync with `enum spa_video_color_range`
+    #[num_enum(default)]
+    UNKNOWN,
+    FULL,    // 0-255
+    LIMITED, // 16-235
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[allow(non_camel_case_types)]
+#[repr(u32)]
+pub enum ColorMatrix {
+    // enum value/order must be in sync with `enum spa_video_color_matrix`
+    #[num_enum(default)]
+    UNKNOWN,
+    RGB,
+    FCC,
+    BT709,
+    BT601,
+    SMPTE240M,
+    BT2020,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[allow(non_camel_case_types)]
+#[repr(u32)]
+pub enum TransferFunction {
+    // enum value/order must be in sync with `enum spa_video_transfer_function`
+    #[num_enum(default)]
+    UNKNOWN,
+    GAMMA10,
+    GAMMA18,
+    GAMMA20,
+    GAMMA22,
+    BT709,
+    SMPTE240M,
+    SRGB,
+    GAMMA28,
+    LOG100,
+    LOG316,
+    BT2020_12,
+    ADOBERGB,
+    BT2020_10,
+    SMPTE2084,
+    ARIB_STD_B67,
+    BT601,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IntoPrimitive, FromPrimitive)]
+#[allow(non_camel_case_types)]
+#[repr(u32)]
+pub enum ColorPrimaries {
+    // enum value/order must be in sync with `enum spa_video_color_primaries`
+    #[num_enum(default)]
+    UNKNOWN,
+    BT709,
+    BT470M,
+    BT470BG,
+    SMPTE170M,
+    SMPTE240M,
+    FILM,
+    BT2020,
+    ADOBERGB,
+    SMPTEST428,
+    SMPTERP431,
+    SMPTEEG432,
+    EBU3213,
+}
+
+/// Color space description of frames, fields left `UNKNOWN` are not advertised
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Colorimetry {
+    pub primaries: ColorPrimaries,
+    pub transfer: TransferFunction,
+    pub matrix: ColorMatrix,
+    pub range: ColorRange,
+}
+
+impl Default for Colorimetry {
+    fn default() -> Self {
+        Self {
+            primaries: ColorPrimaries::UNKNOWN,
+            transfer: TransferFunction::UNKNOWN,
+            matrix: ColorMatrix::UNKNOWN,
+            range: ColorRange::UNKNOWN,
+        }
+    }
+}
+
+impl Colorimetry {
+    /// non-linear sRGB, the implied color space of most swapchains
+    pub const SRGB: Self = Self {
+        primaries: ColorPrimaries::BT709,
+        transfer: TransferFunction::SRGB,
+        matrix: ColorMatrix::RGB,
+        range: ColorRange::FULL,
+    };
+
+    pub const fn rgb(primaries: ColorPrimaries, transfer: TransferFunction) -> Self {
+        Self {
+            primaries,
+            transfer,
+            matrix: ColorMatrix::RGB,
+            range: ColorRange::FULL,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::Format;
+    use crate::{ColorMatrix, ColorPrimaries, ColorRange, Format, TransferFunction};
     use libspa_sys::*;
 
     #[test]
@@ -215,4 +427,154 @@ mod tests {
         assert_eq!(SPA_VIDEO_FORMAT_RGBA_102LE, Format::RGBA_102LE.into());
         assert_eq!(SPA_VIDEO_FORMAT_BGRA_102LE, Format::BGRA_102LE.into());
     }
+
+    #[test]
+    fn colorimetry_value() {
+        assert_eq!(SPA_VIDEO_COLOR_RANGE_UNKNOWN, ColorRange::UNKNOWN.into());
+        assert_eq!(SPA_VIDEO_COLOR_RANGE_0_255, ColorRange::FULL.into());
+        assert_eq!(SPA_VIDEO_COLOR_RANGE_16_235, ColorRange::LIMITED.into());
+
+        assert_eq!(SPA_VIDEO_COLOR_MATRIX_UNKNOWN, ColorMatrix::UNKNOWN.into());
+        assert_eq!(SPA_VIDEO_COLOR_MATRIX_RGB, ColorMatrix::RGB.into());
+        assert_eq!(SPA_VIDEO_COLOR_MATRIX_FCC, ColorMatrix::FCC.into());
+        assert_eq!(SPA_VIDEO_COLOR_MATRIX_BT709, ColorMatrix::BT709.into());
+        assert_eq!(SPA_VIDEO_COLOR_MATRIX_BT601, ColorMatrix::BT601.into());
+        assert_eq!(
+            SPA_VIDEO_COLOR_MATRIX_SMPTE240M,
+            ColorMatrix::SMPTE240M.into()
+        );
+        assert_eq!(SPA_VIDEO_COLOR_MATRIX_BT2020, ColorMatrix::BT2020.into());
+
+        assert_eq!(SPA_VIDEO_TRANSFER_UNKNOWN, TransferFunction::UNKNOWN.into());
+        assert_eq!(SPA_VIDEO_TRANSFER_GAMMA10, TransferFunction::GAMMA10.into());
+        assert_eq!(SPA_VIDEO_TRANSFER_GAMMA18, TransferFunction::GAMMA18.into());
+        assert_eq!(SPA_VIDEO_TRANSFER_GAMMA20, TransferFunction::GAMMA20.into());
+        assert_eq!(SPA_VIDEO_TRANSFER_GAMMA22, TransferFunction::GAMMA22.into());
+        assert_eq!(SPA_VIDEO_TRANSFER_BT709, TransferFunction::BT709.into());
+        assert_eq!(
+            SPA_VIDEO_TRANSFER_SMPTE240M,
+            TransferFunction::SMPTE240M.into()
+        );
+        assert_eq!(SPA_VIDEO_TRANSFER_SRGB, TransferFunction::SRGB.into());
+        assert_eq!(SPA_VIDEO_TRANSFER_GAMMA28, TransferFunction::GAMMA28.into());
+        assert_eq!(SPA_VIDEO_TRANSFER_LOG100, TransferFunction::LOG100.into());
+        assert_eq!(SPA_VIDEO_TRANSFER_LOG316, TransferFunction::LOG316.into());
+        assert_eq!(
+            SPA_VIDEO_TRANSFER_BT2020_12,
+            TransferFunction::BT2020_12.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_TRANSFER_ADOBERGB,
+            TransferFunction::ADOBERGB.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_TRANSFER_BT2020_10,
+            TransferFunction::BT2020_10.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_TRANSFER_SMPTE2084,
+            TransferFunction::SMPTE2084.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_TRANSFER_ARIB_STD_B67,
+            TransferFunction::ARIB_STD_B67.into()
+        );
+        assert_eq!(SPA_VIDEO_TRANSFER_BT601, TransferFunction::BT601.into());
+
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_UNKNOWN,
+            ColorPrimaries::UNKNOWN.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_BT709,
+            ColorPrimaries::BT709.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_BT470M,
+            ColorPrimaries::BT470M.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_BT470BG,
+            ColorPrimaries::BT470BG.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_SMPTE170M,
+            ColorPrimaries::SMPTE170M.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_SMPTE240M,
+            ColorPrimaries::SMPTE240M.into()
+        );
+        assert_eq!(SPA_VIDEO_COLOR_PRIMARIES_FILM, ColorPrimaries::FILM.into());
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_BT2020,
+            ColorPrimaries::BT2020.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_ADOBERGB,
+            ColorPrimaries::ADOBERGB.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_SMPTEST428,
+            ColorPrimaries::SMPTEST428.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_SMPTERP431,
+            ColorPrimaries::SMPTERP431.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_SMPTEEG432,
+            ColorPrimaries::SMPTEEG432.into()
+        );
+        assert_eq!(
+            SPA_VIDEO_COLOR_PRIMARIES_EBU3213,
+            ColorPrimaries::EBU3213.into()
+        );
+    }
+
+    #[test]
+    fn drm_fourcc() {
+        // DRM_FORMAT_ARGB8888
+        assert_eq!(Some(0x34325241), Format::BGRA.drm_fourcc());
+        // DRM_FORMAT_XBGR8888
+        assert_eq!(Some(0x34324258), Format::RGBx.drm_fourcc());
+        // DRM_FORMAT_ABGR2101010
+        assert_eq!(Some(0x30334241), Format::ABGR_210LE.drm_fourcc());
+        assert_eq!(None, Format::NV12.drm_fourcc());
+    }
+
+    #[test]
+    fn is_yuv() {
+        assert!(Format::NV12.is_yuv());
+        assert!(Format::I420.is_yuv());
+        assert!(!Format::BGRA.is_yuv());
+    }
+
+    #[test]
+    fn opaque() {
+        assert_eq!(Format::BGRx, Format::BGRA.opaque());
+        assert_eq!(Format::BGRx_102LE, Format::BGRA_102LE.opaque());
+        assert_eq!(Format::BGRx, Format::BGRx.opaque());
+        assert_eq!(Format::RGBA_F16, Format::RGBA_F16.opaque());
+    }
+
+    #[test]
+    fn modifier_planes() {
+        use crate::modifier_plane_count;
+
+        assert_eq!(Some(1), modifier_plane_count(0));
+        // I915_FORMAT_MOD_Y_TILED
+        assert_eq!(Some(1), modifier_plane_count(0x0100000000000002));
+        // I915_FORMAT_MOD_Y_TILED_GEN12_RC_CCS
+        assert_eq!(Some(2), modifier_plane_count(0x0100000000000006));
+        // I915_FORMAT_MOD_Y_TILED_GEN12_RC_CCS_CC
+        assert_eq!(Some(3), modifier_plane_count(0x0100000000000008));
+        // I915_FORMAT_MOD_4_TILED_DG2_RC_CCS
+        assert_eq!(Some(1), modifier_plane_count(0x010000000000000a));
+        // AMD tiling without and with DCC
+        assert_eq!(Some(1), modifier_plane_count(0x0200000000401919));
+        assert_eq!(Some(2), modifier_plane_count(0x0200000000403919));
+        assert_eq!(None, modifier_plane_count(0x0300000000000010));
+        assert_eq!(None, modifier_plane_count(0x0100000000000100));
+    }
 }
@@ -0,0 +1,125 @@
+//! Preferred ordering of offered formats
+
+use crate::*;
+
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+static FORMAT_PREFERENCE: Lazy<FormatPreference> = Lazy::new(FormatPreference::from_env);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FormatPreference {
+    /// Offered ahead of other formats in this order
+    pub formats: Vec<Format>,
+    /// Offer `DRM_FORMAT_MOD_LINEAR` ahead of other modifiers
+    pub prefer_linear: bool,
+}
+
+impl FormatPreference {
+    /// Parses comma separated format names as in `enum spa_video_format`
+    pub fn parse_formats(value: &str) -> Option<Vec<Format>> {
+        let last: u32 = Format::BGRA_102LE.into();
+        value
+            .split(',')
+            .map(|name| {
+                let name = name.trim();
+                (1..=last)
+                    .map(Format::from)
+                    .find(|format| format!("{format:?}").eq_ignore_ascii_case(name))
+            })
+            .collect()
+    }
+
+    fn from_env() -> Self {
+        let mut preference = Self::default();
+        if let Some(value) = setting("PW_CAPTURE_FORMATS") {
+            match Self::parse_formats(&value) {
+                Some(formats) => preference.formats = formats,
+                None => warn!("invalid PW_CAPTURE_FORMATS {value:?}"),
+            }
+        }
+        preference.prefer_linear =
+            matches!(setting("PW_CAPTURE_PREFER_LINEAR").as_deref(), Some("1"));
+        debug!("format preference: {:?}", preference);
+        preference
+    }
+
+    /// Preference set by `PW_CAPTURE_FORMATS` and `PW_CAPTURE_PREFER_LINEAR`
+    pub fn global() -> Self {
+        FORMAT_PREFERENCE.clone()
+    }
+
+    fn rank(&self, format: Format) -> usize {
+        self.formats
+            .iter()
+            .position(|&f| f == format)
+            .unwrap_or(self.formats.len())
+    }
+
+    /// Reorders `modifiers` by preference, keeping the order otherwise
+    pub fn sort_modifiers(&self, modifiers: &mut [u64]) {
+        if self.prefer_linear {
+            modifiers.sort_by_key(|&m| m != DRM_FORMAT_MOD_LINEAR);
+        }
+    }
+
+    /// Reorders formats and modifiers by preference, keeping the order
+    /// frontends offered them in otherwise
+    pub fn sort(&self, enum_formats: &mut [EnumFormatInfo]) {
+        for enum_format in enum_formats.iter_mut() {
+            enum_format.formats.sort_by_key(|&f| self.rank(f));
+            self.sort_modifiers(&mut enum_format.modifiers);
+        }
+        enum_formats.sort_by_key(|enum_format| {
+            enum_format
+                .formats
+                .first()
+                .map_or(self.formats.len(), |&f| self.rank(f))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_formats() {
+        assert_eq!(
+            FormatPreference::parse_formats("BGRx, bgra"),
+            Some(vec![Format::BGRx, Format::BGRA])
+        );
+        assert_eq!(FormatPreference::parse_formats("BGRx,foo"), None);
+        assert_eq!(FormatPreference::parse_formats("UNKNOWN"), None);
+    }
+
+    #[test]
+    fn sort() {
+        let preference = FormatPreference {
+            formats: vec![Format::BGRx, Format::NV12],
+            prefer_linear: true,
+        };
+        let mut enum_formats = vec![
+            EnumFormatInfo {
+                formats: vec![Format::BGRA, Format::BGRx],
+                modifiers: vec![DRM_FORMAT_MOD_INVALID, 42, DRM_FORMAT_MOD_LINEAR],
+            },
+            EnumFormatInfo {
+                formats: vec![Format::RGBA],
+                modifiers: vec![],
+            },
+            EnumFormatInfo {
+                formats: vec![Format::NV12],
+                modifiers: vec![DRM_FORMAT_MOD_LINEAR],
+            },
+        ];
+        preference.sort(&mut enum_formats);
+        assert_eq!(enum_formats[0].formats, [Format::BGRx, Format::BGRA]);
+        assert_eq!(
+            enum_formats[0].modifiers,
+            [DRM_FORMAT_MOD_LINEAR, DRM_FORMAT_MOD_INVALID, 42]
+        );
+        assert_eq!(enum_formats[1].formats, [Format::NV12]);
+        assert_eq!(enum_formats[2].formats, [Format::RGBA]);
+    }
+}
@@ -0,0 +1,67 @@
+//! On-screen capture indicator
+
+use log::debug;
+use once_cell::sync::Lazy;
+
+use crate::settings::setting;
+
+static INDICATOR_ENABLED: Lazy<bool> = Lazy::new(|| {
+    let enabled = matches!(setting("PW_CAPTURE_INDICATOR").as_deref(), Some("1"));
+    debug!("capture indicator enabled: {}", enabled);
+    enabled
+});
+
+/// Whether layers should draw the capture indicator
+pub fn indicator_enabled() -> bool {
+    *INDICATOR_ENABLED
+}
+
+/// Square covered by the indicator, origin at the top left corner
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndicatorRect {
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+/// Indicator position on a `width`x`height` frame, `None` if the frame is too
+/// small to fit it
+pub fn indicator_rect(width: u32, height: u32) -> Option<IndicatorRect> {
+    // keeps the dot visible on high resolutions, one dot wide margin
+    let size = (width.min(height) / 64).max(8);
+    if width < size * 3 || height < size * 3 {
+        return None;
+    }
+    Some(IndicatorRect {
+        x: width - size * 2,
+        y: size,
+        size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rect() {
+        assert_eq!(
+            indicator_rect(1920, 1080),
+            Some(IndicatorRect {
+                x: 1888,
+                y: 16,
+                size: 16
+            })
+        );
+        assert_eq!(
+            indicator_rect(320, 240),
+            Some(IndicatorRect {
+                x: 304,
+                y: 8,
+                size: 8
+            })
+        );
+        assert_eq!(indicator_rect(24, 24).map(|r| r.size), Some(8));
+        assert_eq!(indicator_rect(23, 1080), None);
+    }
+}
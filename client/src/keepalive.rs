@@ -0,0 +1,130 @@
+//! Keepalive frames while apps stop presenting
+
+use crate::*;
+
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+static KEEPALIVE_INTERVAL: Lazy<Option<Duration>> = Lazy::new(|| {
+    let interval = match setting("PW_CAPTURE_KEEPALIVE") {
+        Some(value) => parse_keepalive_rate(&value).or_else(|| {
+            warn!("invalid PW_CAPTURE_KEEPALIVE {value:?}");
+            None
+        }),
+        None => None,
+    };
+    debug!("keepalive interval: {:?}", interval);
+    interval
+});
+
+/// Interval of keepalive frames set by `PW_CAPTURE_KEEPALIVE`, `None` if
+/// disabled
+pub fn keepalive_interval() -> Option<Duration> {
+    *KEEPALIVE_INTERVAL
+}
+
+/// Frame interval of a rate in frames per second, `None` if not positive
+fn parse_keepalive_rate(value: &str) -> Option<Duration> {
+    let rate = value.trim().parse::<f64>().ok()?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return None;
+    }
+    Duration::try_from_secs_f64(1.0 / rate).ok()
+}
+
+/// Buffers tracked for re-queuing the last frame, shared with the process
+/// callback running on the data thread
+#[derive(Debug, Default)]
+pub(crate) struct KeepaliveState {
+    /// Buffer of the last frame and when it was queued
+    last_frame: Option<(BufferHandle, Instant)>,
+    /// Buffers dequeued while looking for the one of the last frame, handed
+    /// out to the frontend before dequeuing new ones
+    held: Vec<BufferHandle>,
+}
+
+impl KeepaliveState {
+    /// Records `buffer` being queued with a frame at `now`
+    pub fn record_frame(&mut self, buffer: BufferHandle, now: Instant) {
+        self.last_frame = Some((buffer, now));
+    }
+
+    /// Buffer of the last frame if it has not been followed by another one for
+    /// `interval`
+    pub fn due(&self, interval: Duration, now: Instant) -> Option<BufferHandle> {
+        self.last_frame
+            .filter(|&(_, queued)| now.saturating_duration_since(queued) >= interval)
+            .map(|(buffer, _)| buffer)
+    }
+
+    pub fn hold(&mut self, buffer: BufferHandle) {
+        self.held.push(buffer);
+    }
+
+    pub fn take_held(&mut self) -> Option<BufferHandle> {
+        self.held.pop()
+    }
+
+    /// Stops re-queuing the last frame, e.g. as it went stale while paused
+    pub fn forget_frame(&mut self) {
+        self.last_frame = None;
+    }
+
+    /// Forgets `buffer` being removed from the stream
+    pub fn remove_buffer(&mut self, buffer: BufferHandle) {
+        self.held.retain(|&v| v != buffer);
+        if matches!(self.last_frame, Some((v, _)) if v == buffer) {
+            self.last_frame = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::ptr;
+
+    use pipewire as pw;
+
+    #[test]
+    fn keepalive_rate() {
+        assert_eq!(parse_keepalive_rate("1"), Some(Duration::from_secs(1)));
+        assert_eq!(
+            parse_keepalive_rate(" 2 "),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(parse_keepalive_rate("0.5"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_keepalive_rate("0"), None);
+        assert_eq!(parse_keepalive_rate("-1"), None);
+        assert_eq!(parse_keepalive_rate("inf"), None);
+        assert_eq!(parse_keepalive_rate("fps"), None);
+    }
+
+    #[test]
+    fn keepalive_state() {
+        let interval = Duration::from_secs(1);
+        let start = Instant::now();
+        let a = BufferHandle::dangling();
+        let b = BufferHandle::from(ptr::NonNull::<pw::sys::pw_buffer>::dangling());
+
+        let mut state = KeepaliveState::default();
+        assert_eq!(state.due(interval, start), None);
+
+        state.record_frame(a, start);
+        assert_eq!(state.due(interval, start + interval / 2), None);
+        assert_eq!(state.due(interval, start + interval), Some(a));
+
+        state.hold(b);
+        state.remove_buffer(a);
+        assert_eq!(state.due(interval, start + interval), None);
+        assert_eq!(state.take_held(), Some(b));
+        assert_eq!(state.take_held(), None);
+
+        state.record_frame(b, start);
+        state.forget_frame();
+        assert_eq!(state.due(interval, start + interval), None);
+    }
+}
@@ -1,14 +1,62 @@
+mod app_filter;
+mod buffer_demand;
+#[cfg(feature = "capi")]
+mod capi;
 mod client;
+mod control;
+mod drm_device;
+mod error;
+mod flatpak;
 mod format;
+mod format_preference;
+mod indicator;
+mod keepalive;
+mod limiter;
+mod logger;
+mod node_class;
+mod obs;
+mod post_process;
+mod scale;
+mod settings;
 mod spa_utils;
+mod stats;
 mod stream;
+mod sync_file;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
+mod transform;
 mod utils;
+mod worker;
 
+pub use app_filter::*;
+pub use buffer_demand::*;
+#[cfg(feature = "capi")]
+pub use capi::*;
 pub use client::*;
+pub use control::*;
+pub use drm_device::*;
+pub use error::*;
+pub use flatpak::*;
 pub use format::*;
-pub(crate) use spa_utils::*;
+pub use format_preference::*;
+pub use indicator::*;
+pub use keepalive::*;
+pub use limiter::*;
+pub use logger::*;
+pub use node_class::*;
+pub(crate) use obs::*;
+pub use post_process::*;
+pub use scale::*;
+pub use settings::*;
+pub use spa_utils::*;
+pub use stats::*;
 pub use stream::*;
+pub use sync_file::*;
+#[cfg(any(test, feature = "testing"))]
+pub use testing::*;
+pub use transform::*;
 pub(crate) use utils::*;
+pub use worker::*;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Point {
@@ -0,0 +1,248 @@
+//! Process-wide capture budget
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+use once_cell::sync::Lazy;
+
+use crate::settings::setting;
+
+// streams are never paced below this rate
+const MIN_FRAME_RATE: f64 = 1.0;
+// weight of the latest present interval in the moving average
+const INTERVAL_SMOOTHING: f64 = 0.1;
+
+static LIMITER: Lazy<CaptureLimiter> = Lazy::new(CaptureLimiter::from_env);
+
+#[derive(Debug)]
+struct StreamUsage {
+    pixels: u64,
+    avg_interval: Option<f64>,
+    last_present: Option<Instant>,
+    last_capture: Option<Instant>,
+    min_interval: Duration,
+}
+
+/// Pixel rate budget shared by all streams, larger streams are served first
+/// and the remaining ones paced down once it runs out
+#[derive(Debug)]
+pub struct CaptureLimiter {
+    /// pixels per second, 0 means unlimited
+    budget: AtomicU64,
+    next_id: AtomicUsize,
+    streams: Mutex<HashMap<usize, StreamUsage>>,
+}
+
+impl CaptureLimiter {
+    fn new(budget: Option<u64>) -> Self {
+        Self {
+            budget: AtomicU64::new(budget.unwrap_or(0)),
+            next_id: AtomicUsize::new(0),
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Budget set by `PW_CAPTURE_MAX_PIXEL_RATE` in pixels per second
+    fn from_env() -> Self {
+        let budget = match setting("PW_CAPTURE_MAX_PIXEL_RATE") {
+            Some(value) => match value.parse::<u64>() {
+                Ok(budget) => Some(budget),
+                Err(e) => {
+                    warn!("invalid PW_CAPTURE_MAX_PIXEL_RATE {value:?}: {e}");
+                    None
+                }
+            },
+            None => None,
+        };
+        debug!("capture budget: {:?}", budget);
+        Self::new(budget)
+    }
+
+    pub fn global() -> &'static Self {
+        &LIMITER
+    }
+
+    pub fn budget(&self) -> Option<u64> {
+        match self.budget.load(Ordering::Relaxed) {
+            0 => None,
+            budget => Some(budget),
+        }
+    }
+
+    pub fn set_budget(&self, budget: Option<u64>) {
+        self.budget.store(budget.unwrap_or(0), Ordering::Relaxed);
+        let mut streams = self.streams.lock().unwrap();
+        rebalance(&mut streams, budget);
+    }
+
+    pub fn register(&'static self, width: u32, height: u32) -> LimiterHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut streams = self.streams.lock().unwrap();
+        streams.insert(
+            id,
+            StreamUsage {
+                pixels: width as u64 * height as u64,
+                avg_interval: None,
+                last_present: None,
+                last_capture: None,
+                min_interval: Duration::ZERO,
+            },
+        );
+        rebalance(&mut streams, self.budget());
+        LimiterHandle { limiter: self, id }
+    }
+}
+
+/// Grants frame rates to streams in order of size until the budget is used up
+fn rebalance(streams: &mut HashMap<usize, StreamUsage>, budget: Option<u64>) {
+    let budget = match budget {
+        Some(budget) => budget as f64,
+        None => {
+            for usage in streams.values_mut() {
+                usage.min_interval = Duration::ZERO;
+            }
+            return;
+        }
+    };
+
+    let mut streams: Vec<&mut StreamUsage> = streams.values_mut().collect();
+    streams.sort_by(|a, b| b.pixels.cmp(&a.pixels));
+
+    let mut remaining = budget;
+    for usage in streams {
+        let pixels = usage.pixels.max(1) as f64;
+        let frame_rate = usage.avg_interval.map_or(0.0, |interval| 1.0 / interval);
+        let demand = pixels * frame_rate;
+        if demand <= remaining {
+            usage.min_interval = Duration::ZERO;
+            remaining -= demand;
+            continue;
+        }
+        let rate = (remaining / pixels).max(MIN_FRAME_RATE);
+        usage.min_interval = Duration::from_secs_f64(1.0 / rate);
+        remaining = (remaining - rate * pixels).max(0.0);
+    }
+}
+
+#[derive(Debug)]
+pub struct LimiterHandle {
+    limiter: &'static CaptureLimiter,
+    id: usize,
+}
+
+impl LimiterHandle {
+    pub fn resize(&self, width: u32, height: u32) {
+        let mut streams = self.limiter.streams.lock().unwrap();
+        if let Some(usage) = streams.get_mut(&self.id) {
+            usage.pixels = width as u64 * height as u64;
+        }
+        rebalance(&mut streams, self.limiter.budget());
+    }
+
+    /// Returns `false` if the frame presented now should be skipped, the slot
+    /// is only taken by [`Self::commit`] once a buffer got dequeued for it
+    pub fn try_acquire(&self) -> bool {
+        let budget = self.limiter.budget();
+        if budget.is_none() {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut streams = self.limiter.streams.lock().unwrap();
+        let usage = match streams.get_mut(&self.id) {
+            Some(usage) => usage,
+            None => return true,
+        };
+        if let Some(last) = usage.last_present {
+            let interval = now.duration_since(last).as_secs_f64().max(1e-4);
+            usage.avg_interval = Some(match usage.avg_interval {
+                Some(avg) => avg + (interval - avg) * INTERVAL_SMOOTHING,
+                None => interval,
+            });
+        }
+        usage.last_present = Some(now);
+
+        rebalance(&mut streams, budget);
+
+        let usage = streams.get_mut(&self.id).unwrap();
+        if let Some(last) = usage.last_capture {
+            if now.duration_since(last) < usage.min_interval {
+                trace!("stream {} paced down", self.id);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Takes the slot granted by [`Self::try_acquire`] as the frame is captured
+    pub fn commit(&self) {
+        if self.limiter.budget().is_none() {
+            return;
+        }
+        let mut streams = self.limiter.streams.lock().unwrap();
+        if let Some(usage) = streams.get_mut(&self.id) {
+            usage.last_capture = Some(Instant::now());
+        }
+    }
+}
+
+impl Drop for LimiterHandle {
+    fn drop(&mut self) {
+        let mut streams = self.limiter.streams.lock().unwrap();
+        streams.remove(&self.id);
+        rebalance(&mut streams, self.limiter.budget());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(width: u64, height: u64, frame_rate: f64) -> StreamUsage {
+        StreamUsage {
+            pixels: width * height,
+            avg_interval: Some(1.0 / frame_rate),
+            last_present: None,
+            last_capture: None,
+            min_interval: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn rebalance_by_size() {
+        let mut streams = HashMap::new();
+        streams.insert(0, usage(1920, 1080, 64.0));
+        streams.insert(1, usage(640, 480, 64.0));
+
+        rebalance(&mut streams, None);
+        assert_eq!(streams[&0].min_interval, Duration::ZERO);
+        assert_eq!(streams[&1].min_interval, Duration::ZERO);
+
+        // enough for the large stream and half rate of the small one
+        rebalance(&mut streams, Some(1920 * 1080 * 64 + 640 * 480 * 32));
+        assert_eq!(streams[&0].min_interval, Duration::ZERO);
+        let interval = streams[&1].min_interval.as_secs_f64();
+        assert!((interval - 1.0 / 32.0).abs() < 1e-6);
+
+        // budget exhausted, paced down to the minimal rate
+        rebalance(&mut streams, Some(1920 * 1080 * 64));
+        assert_eq!(streams[&0].min_interval, Duration::ZERO);
+        assert_eq!(streams[&1].min_interval, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn slot_taken_on_commit() {
+        let limiter = Box::leak(Box::new(CaptureLimiter::new(Some(1))));
+        let handle = limiter.register(1920, 1080);
+
+        // frames not captured leave the slot free
+        assert!(handle.try_acquire());
+        assert!(handle.try_acquire());
+
+        handle.commit();
+        assert!(!handle.try_acquire());
+    }
+}
@@ -0,0 +1,214 @@
+//! Logger shared by the layers
+
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, RwLock};
+
+use anyhow::{anyhow, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use once_cell::sync::OnceCell;
+
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Debug;
+const CRATE_PREFIX: &str = "pw_capture_";
+
+static LOGGER: OnceCell<Logger> = OnceCell::new();
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    /// Module path prefixes and their levels, longest first
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        DEFAULT_LEVEL.into()
+    }
+}
+
+impl From<LevelFilter> for LogFilter {
+    fn from(level: LevelFilter) -> Self {
+        Self {
+            default: level,
+            directives: vec![],
+        }
+    }
+}
+
+impl LogFilter {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut filter = Self::default();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let module = module_path_prefix(module.trim());
+                    filter.directives.push((module, parse_level(level)?));
+                }
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        filter.directives.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        Ok(filter)
+    }
+
+    /// Level records of `module_path` are logged up to
+    pub fn level(&self, module_path: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(
+                |(prefix, _)| match module_path.strip_prefix(prefix.as_str()) {
+                    Some(rest) => rest.is_empty() || rest.starts_with("::"),
+                    None => false,
+                },
+            )
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+fn parse_level(value: &str) -> Result<LevelFilter> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("invalid log level {value:?}"))
+}
+
+/// `vulkan::stream` to `pw_capture_vk::stream`
+fn module_path_prefix(module: &str) -> String {
+    let module = module.replace('-', "_");
+    let module = module.strip_prefix(CRATE_PREFIX).unwrap_or(&module);
+    let (name, path) = match module.split_once("::") {
+        Some((name, path)) => (name, Some(path)),
+        None => (module, None),
+    };
+    let name = if name == "vulkan" { "vk" } else { name };
+    match path {
+        Some(path) => format!("{CRATE_PREFIX}{name}::{path}"),
+        None => format!("{CRATE_PREFIX}{name}"),
+    }
+}
+
+struct Logger {
+    name: &'static str,
+    filter: RwLock<LogFilter>,
+    file: Option<Mutex<File>>,
+}
+
+impl Logger {
+    fn from_env(name: &'static str) -> Self {
+        let filter = match env::var("PW_CAPTURE_LOG") {
+            Ok(spec) => LogFilter::parse(&spec).unwrap_or_else(|e| {
+                eprintln!("[{name}] PW_CAPTURE_LOG: {e}");
+                Default::default()
+            }),
+            Err(_) => Default::default(),
+        };
+        let file = env::var_os("PW_CAPTURE_LOG_FILE").and_then(|path| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| eprintln!("[{name}] failed to open log file {path:?}: {e}"))
+                .ok()
+        });
+        Self {
+            name,
+            filter: RwLock::new(filter),
+            file: file.map(Mutex::new),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.read().unwrap().max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        let module_path = record.module_path().unwrap_or_default();
+        if record.level() > self.filter.read().unwrap().level(module_path) {
+            return;
+        }
+        let line = format!(
+            "[{}] {:>5} [{}:{}] [{}] {}",
+            self.name,
+            record.level(),
+            record.file().unwrap_or_default(),
+            record.line().unwrap_or_default(),
+            record.target(),
+            record.args()
+        );
+        match &self.file {
+            Some(file) => {
+                let _ = writeln!(file.lock().unwrap(), "{line}");
+            }
+            None => eprintln!("{line}"),
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+/// Installs the logger configured by env vars, records are prefixed with
+/// `name`
+pub fn init_logger(name: &'static str) {
+    let logger = LOGGER.get_or_init(|| Logger::from_env(name));
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(logger.filter.read().unwrap().max_level());
+    }
+}
+
+/// Replaces the filter at runtime, e.g. once layer settings got loaded
+pub fn set_log_filter(filter: LogFilter) {
+    log::set_max_level(filter.max_level());
+    if let Some(logger) = LOGGER.get() {
+        *logger.filter.write().unwrap() = filter;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(LogFilter::parse("warn").unwrap(), LevelFilter::Warn.into());
+        assert_eq!(LogFilter::parse("").unwrap(), LogFilter::default());
+        assert!(LogFilter::parse("loud").is_err());
+        assert!(LogFilter::parse("client=loud").is_err());
+
+        let filter =
+            LogFilter::parse("vulkan=trace, client=info,client::stream=off,error").unwrap();
+        assert_eq!(filter.level("pw_capture_vk::utils"), LevelFilter::Trace);
+        assert_eq!(filter.level("pw_capture_client"), LevelFilter::Info);
+        assert_eq!(filter.level("pw_capture_client::stream"), LevelFilter::Off);
+        assert_eq!(
+            filter.level("pw_capture_client::streams"),
+            LevelFilter::Info
+        );
+        assert_eq!(filter.level("pw_capture_gl"), LevelFilter::Error);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn module_path() {
+        assert_eq!(module_path_prefix("vk"), "pw_capture_vk");
+        assert_eq!(module_path_prefix("vulkan"), "pw_capture_vk");
+        assert_eq!(module_path_prefix("pw-capture-gl"), "pw_capture_gl");
+        assert_eq!(
+            module_path_prefix("client::stream"),
+            "pw_capture_client::stream"
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Classification of capture nodes
+
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+use crate::settings::setting;
+
+const SOURCE: &str = "Video/Source";
+const STREAM_OUTPUT: &str = "Stream/Output/Video";
+const DEFAULT_ROLE: &str = "Screen";
+
+static NODE_CLASS: Lazy<NodeClass> = Lazy::new(NodeClass::from_env);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeClass {
+    /// `media.class` of the node
+    pub media_class: String,
+    /// `media.role` of the node
+    pub media_role: String,
+}
+
+impl Default for NodeClass {
+    fn default() -> Self {
+        Self {
+            media_class: SOURCE.into(),
+            media_role: DEFAULT_ROLE.into(),
+        }
+    }
+}
+
+impl NodeClass {
+    pub fn parse_media_class(value: &str) -> Option<String> {
+        match value.trim() {
+            "source" => Some(SOURCE.into()),
+            "stream" => Some(STREAM_OUTPUT.into()),
+            v if v.split('/').count() > 1 && !v.split('/').any(str::is_empty) => Some(v.into()),
+            _ => None,
+        }
+    }
+
+    fn from_env() -> Self {
+        let mut node_class = Self::default();
+        if let Some(value) = setting("PW_CAPTURE_MEDIA_CLASS") {
+            match Self::parse_media_class(&value) {
+                Some(v) => node_class.media_class = v,
+                None => warn!("invalid PW_CAPTURE_MEDIA_CLASS {value:?}"),
+            }
+        }
+        match setting("PW_CAPTURE_MEDIA_ROLE") {
+            Some(value) if !value.trim().is_empty() => node_class.media_role = value.trim().into(),
+            Some(value) => warn!("invalid PW_CAPTURE_MEDIA_ROLE {value:?}"),
+            None => (),
+        }
+        debug!("node class: {:?}", node_class);
+        node_class
+    }
+
+    /// Class set by `PW_CAPTURE_MEDIA_CLASS` and `PW_CAPTURE_MEDIA_ROLE`
+    pub fn global() -> Self {
+        NODE_CLASS.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_media_class() {
+        assert_eq!(
+            NodeClass::parse_media_class("source").as_deref(),
+            Some(SOURCE)
+        );
+        assert_eq!(
+            NodeClass::parse_media_class(" stream ").as_deref(),
+            Some(STREAM_OUTPUT)
+        );
+        assert_eq!(
+            NodeClass::parse_media_class("Video/Sink").as_deref(),
+            Some("Video/Sink")
+        );
+        assert_eq!(NodeClass::parse_media_class("Video/"), None);
+        assert_eq!(NodeClass::parse_media_class("sink"), None);
+    }
+}
@@ -0,0 +1,586 @@
+//! obs-vkcapture compatible transport
+
+use crate::*;
+
+use core::mem;
+use core::ptr;
+use core::slice;
+use std::cell::{Cell, RefCell};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use log::{debug, error, info, trace, warn};
+
+const CAPTURE_TEXTURE_DATA_TYPE: i16 = 1;
+const CAPTURE_CLIENT_DATA_TYPE: i16 = 10;
+const CAPTURE_MAX_PLANES: usize = 4;
+const CAPTURE_DATA_SIZE: usize = 128;
+
+const DEFAULT_SOCKET_PATH: &[u8] = b"\0/com/obsproject/vkcapture";
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CaptureTextureData {
+    type_: i16,
+    nfd: i16,
+    width: i32,
+    height: i32,
+    format: i32,
+    strides: [i32; CAPTURE_MAX_PLANES],
+    offsets: [i32; CAPTURE_MAX_PLANES],
+    modifier: u64,
+    winid: u32,
+    flip: u8,
+    padding: [u8; 67],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CaptureClientData {
+    type_: i16,
+    exe: [u8; 84],
+    padding: [u8; 42],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CaptureControlData {
+    capturing: u8,
+    no_modifiers: u8,
+    linear: u8,
+    map_host: u8,
+    padding: [u8; 124],
+}
+
+const _: () = assert!(mem::size_of::<CaptureTextureData>() == CAPTURE_DATA_SIZE);
+const _: () = assert!(mem::size_of::<CaptureClientData>() == CAPTURE_DATA_SIZE);
+const _: () = assert!(mem::size_of::<CaptureControlData>() == CAPTURE_DATA_SIZE);
+
+unsafe fn as_bytes<T: Copy>(data: &T) -> &[u8] {
+    slice::from_raw_parts(data as *const T as *const u8, mem::size_of::<T>())
+}
+
+fn socket_path() -> Vec<u8> {
+    setting("PW_CAPTURE_OBS_SOCKET")
+        .map(String::into_bytes)
+        .unwrap_or_else(|| DEFAULT_SOCKET_PATH.to_vec())
+}
+
+fn connect(path: &[u8]) -> io::Result<UnixStream> {
+    unsafe {
+        let mut addr: libc::sockaddr_un = mem::zeroed();
+        if path.len() >= addr.sun_path.len() {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        addr.sun_family = libc::AF_UNIX as _;
+        ptr::copy_nonoverlapping(
+            path.as_ptr(),
+            addr.sun_path.as_mut_ptr() as *mut u8,
+            path.len(),
+        );
+        let addr_len = mem::size_of::<libc::sa_family_t>() + path.len();
+
+        let fd = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let stream = UnixStream::from_raw_fd(fd);
+        let res = libc::connect(
+            fd,
+            &addr as *const _ as *const libc::sockaddr,
+            addr_len as _,
+        );
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stream)
+    }
+}
+
+fn send_with_fds(stream: &UnixStream, data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    unsafe {
+        let mut iov = libc::iovec {
+            iov_base: data.as_ptr() as *mut _,
+            iov_len: data.len(),
+        };
+        let fds_size = mem::size_of_val(fds) as u32;
+        // u64 backed for cmsghdr alignment
+        let mut cmsg_buf = vec![0u64; (libc::CMSG_SPACE(fds_size) as usize + 7) / 8];
+
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        if !fds.is_empty() {
+            msg.msg_control = cmsg_buf.as_mut_ptr() as _;
+            msg.msg_controllen = libc::CMSG_SPACE(fds_size) as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_size) as _;
+            ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        }
+
+        let res = libc::sendmsg(stream.as_raw_fd(), &msg, libc::MSG_NOSIGNAL);
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if res as usize != data.len() {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        Ok(())
+    }
+}
+
+struct ObsClientImpl {
+    running: Cell<bool>,
+}
+
+impl ClientMethods for ObsClientImpl {
+    fn terminate(&self) {
+        self.running.set(false);
+    }
+
+    fn create_stream(&mut self, info: StreamInfo) -> Result<Stream> {
+        debug!("create obs stream");
+
+        let (sender, receiver) = unbounded::<StreamMessage>();
+        thread::Builder::new()
+            .name("pw-capture-obs".into())
+            .spawn(move || obs_stream_thread(info, receiver))?;
+
+        Ok(Stream {
+            sender: MessageSender::Channel(sender),
+            worker: Arc::new(Worker::new()?),
+        })
+    }
+
+    fn enumerate_streams(&self) -> Result<Vec<StreamNodeInfo>> {
+        bail!("capture nodes are not available with obs backend")
+    }
+
+    fn set_capture_enabled(&mut self, _enabled: bool) {
+        // layers stop copying frames, the plugin keeps showing the last one
+    }
+}
+
+pub(crate) fn obs_thread(done_sender: Sender<()>, receiver: Receiver<ClientMessage>) -> Result<()> {
+    let mut client_impl = ObsClientImpl {
+        running: Cell::new(true),
+    };
+
+    done_sender.send(())?;
+    drop(done_sender);
+
+    for msg in receiver.iter() {
+        trace!("receive {:?}", msg);
+        let _ = msg.try_call_mut(&mut client_impl);
+        if !client_impl.running.get() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+struct ObsStream {
+    inner: RefCell<ObsStreamInner>,
+}
+
+struct ObsStreamInner {
+    info: StreamInfo,
+    socket: Option<UnixStream>,
+    last_connect: Option<Instant>,
+    recv_buf: Vec<u8>,
+    control: Option<CaptureControlData>,
+    buffer: Option<BufferUserHandle>,
+    limiter: LimiterHandle,
+    stats: StatsRecorder,
+    last_error: Option<String>,
+    terminated: bool,
+}
+
+impl StreamMethods for ObsStream {
+    fn terminate(&self) -> Result<()> {
+        debug!("terminate obs stream");
+        let mut inner = self.inner.borrow_mut();
+        inner.terminated = true;
+        inner.disconnect();
+        Ok(())
+    }
+
+    fn drain(&self) -> Result<bool> {
+        // the plugin reads the shared texture for as long as it is connected,
+        // there are no buffers to take back
+        Ok(true)
+    }
+
+    fn dequeue_buffer(&self) -> Option<(BufferHandle, BufferUserHandle)> {
+        let inner = self.inner.borrow();
+        if inner.buffer.is_none() || !inner.limiter.try_acquire() {
+            return None;
+        }
+        // the single exported texture is always available
+        inner.limiter.commit();
+        inner.stats.record_dequeue(Duration::ZERO, true);
+        inner
+            .buffer
+            .map(|user_handle| (BufferHandle::dangling(), user_handle))
+    }
+
+    fn queue_buffer_process(&self, _buffer: BufferHandle) -> Result<()> {
+        let inner = self.inner.borrow();
+        let user_handle = inner.buffer.ok_or(anyhow!("not capturing"))?;
+        let start = Instant::now();
+        (inner.info.process_buffer)(
+            user_handle,
+            AddBufferMetaCbs {
+                add_cursor: None,
+                add_crop: None,
+                add_damage: None,
+                set_pts: None,
+                set_seq: None,
+            },
+        );
+        inner.stats.record_copy_wait(start.elapsed());
+        inner.stats.record_process(start.elapsed());
+        Ok(())
+    }
+
+    fn update_format(
+        &self,
+        width: u32,
+        height: u32,
+        enum_formats: Vec<EnumFormatInfo>,
+        colorimetry: Colorimetry,
+    ) -> Result<()> {
+        debug!("update format, extent: {}x{}", width, height);
+        let mut inner = self.inner.borrow_mut();
+        inner.info.width = width;
+        inner.info.height = height;
+        let mut enum_formats = enum_formats;
+        inner.info.format_preference.sort(&mut enum_formats);
+        inner.info.enum_formats = enum_formats;
+        inner.info.colorimetry = colorimetry;
+        let (width, height) = inner.info.scale.apply(width, height);
+        inner.limiter.resize(width, height);
+
+        // resend texture to the server
+        if inner.buffer.is_some() {
+            if let Some(control) = inner.control {
+                if let Err(e) = inner.start_capture(&control) {
+                    error!("failed to restart obs capture: {e:?}");
+                    inner.last_error = Some(format!("failed to restart capture: {e:#}"));
+                    inner.disconnect();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn update_max_buffers(&self, max_buffers: u32) -> Result<()> {
+        // obs-vkcapture shares a single texture
+        self.inner.borrow_mut().info.max_buffers = max_buffers;
+        Ok(())
+    }
+
+    fn take_missing_buffers(&self) -> u32 {
+        0
+    }
+
+    fn renegotiate_buffers(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn update_props(&self, props: Vec<(String, String)>) -> Result<()> {
+        // obs-vkcapture identifies clients by executable only
+        let mut inner = self.inner.borrow_mut();
+        for (key, value) in props {
+            match inner.info.props.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => *v = value,
+                None => inner.info.props.push((key, value)),
+            }
+        }
+        Ok(())
+    }
+
+    fn update_transform(&self, _transform: Transform) -> Result<()> {
+        // not part of the obs-vkcapture protocol
+        Ok(())
+    }
+
+    fn update_swap_interval(&self, swap_interval: i32) -> Result<()> {
+        self.inner
+            .borrow()
+            .stats
+            .record_swap_interval(swap_interval);
+        Ok(())
+    }
+
+    fn update_window_suspended(&self, _suspended: bool) -> Result<()> {
+        // frontends send no frames meanwhile, OBS keeps showing the last one
+        Ok(())
+    }
+
+    fn node_id(&self) -> Option<u32> {
+        None
+    }
+
+    fn state(&self) -> StreamState {
+        self.inner.borrow().state()
+    }
+
+    fn stats(&self) -> StreamStats {
+        self.inner.borrow().stats.snapshot()
+    }
+
+    fn last_error(&self) -> Option<String> {
+        self.inner.borrow().last_error.clone()
+    }
+}
+
+impl ObsStreamInner {
+    fn state(&self) -> StreamState {
+        if self.buffer.is_some() {
+            StreamState::Streaming
+        } else if self.socket.is_some() {
+            StreamState::Paused
+        } else if self.terminated {
+            StreamState::Unconnected
+        } else {
+            StreamState::Connecting
+        }
+    }
+
+    fn try_connect(&mut self) -> Result<()> {
+        if matches!(self.last_connect, Some(last) if last.elapsed() < RECONNECT_INTERVAL) {
+            return Ok(());
+        }
+        self.last_connect = Some(Instant::now());
+
+        let socket = connect(&socket_path())?;
+
+        let mut client_data: CaptureClientData = unsafe { mem::zeroed() };
+        client_data.type_ = CAPTURE_CLIENT_DATA_TYPE;
+        let app_name = get_app_name();
+        let len = app_name.len().min(client_data.exe.len() - 1);
+        client_data.exe[..len].copy_from_slice(&app_name.as_bytes()[..len]);
+        send_with_fds(&socket, unsafe { as_bytes(&client_data) }, &[])?;
+
+        socket.set_nonblocking(true)?;
+        info!("connected to obs-vkcapture server");
+        self.socket = Some(socket);
+        (self.info.state_changed)(self.state(), None);
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        self.stop_capture();
+        self.recv_buf.clear();
+        self.control = None;
+        if self.socket.take().is_some() {
+            (self.info.state_changed)(self.state(), None);
+        }
+    }
+
+    fn poll(&mut self) {
+        if self.terminated {
+            return;
+        }
+        if self.socket.is_none() {
+            if let Err(e) = self.try_connect() {
+                trace!("failed to connect obs-vkcapture server: {e}");
+                return;
+            }
+        }
+        let socket = match self.socket.as_mut() {
+            Some(socket) => socket,
+            None => return,
+        };
+
+        let mut control = None;
+        let mut buf = [0u8; CAPTURE_DATA_SIZE];
+        loop {
+            match socket.read(&mut buf) {
+                Ok(0) => {
+                    info!("obs-vkcapture server disconnected");
+                    self.disconnect();
+                    return;
+                }
+                Ok(n) => self.recv_buf.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    warn!("obs-vkcapture socket error: {e}");
+                    self.disconnect();
+                    return;
+                }
+            }
+        }
+        while self.recv_buf.len() >= CAPTURE_DATA_SIZE {
+            let data: CaptureControlData =
+                unsafe { ptr::read_unaligned(self.recv_buf.as_ptr() as *const _) };
+            self.recv_buf.drain(..CAPTURE_DATA_SIZE);
+            control = Some(data);
+        }
+
+        let control = match control {
+            Some(control) => control,
+            None => return,
+        };
+        let changed = match self.control {
+            Some(prev) => {
+                prev.capturing != control.capturing
+                    || prev.no_modifiers != control.no_modifiers
+                    || prev.linear != control.linear
+            }
+            None => true,
+        };
+        self.control = Some(control);
+        if !changed {
+            return;
+        }
+
+        debug!(
+            "control capturing: {}, no_modifiers: {}, linear: {}",
+            control.capturing, control.no_modifiers, control.linear
+        );
+        if control.capturing != 0 {
+            if let Err(e) = self.start_capture(&control) {
+                error!("failed to start obs capture: {e:?}");
+                self.last_error = Some(format!("failed to start capture: {e:#}"));
+                self.disconnect();
+            }
+        } else {
+            self.stop_capture();
+        }
+    }
+
+    fn start_capture(&mut self, control: &CaptureControlData) -> Result<()> {
+        self.stop_capture();
+
+        let force_linear = control.no_modifiers != 0 || control.linear != 0;
+        let (format, fourcc, modifiers) = self
+            .info
+            .enum_formats
+            .iter()
+            .flat_map(|enum_format| {
+                enum_format
+                    .formats
+                    .iter()
+                    .map(move |format| (*format, enum_format))
+            })
+            .find_map(|(format, enum_format)| {
+                let fourcc = format.drm_fourcc()?;
+                let modifiers: Vec<u64> = if force_linear {
+                    enum_format
+                        .modifiers
+                        .iter()
+                        .copied()
+                        .filter(|&m| m == DRM_FORMAT_MOD_LINEAR)
+                        .collect()
+                } else {
+                    enum_format.modifiers.clone()
+                };
+                if modifiers.is_empty() {
+                    return None;
+                }
+                Some((format, fourcc, modifiers))
+            })
+            .ok_or(anyhow!("no dma-buf format compatible with obs-vkcapture"))?;
+
+        let fixate = (self.info.fixate_format)(EnumFormatInfo {
+            formats: vec![format],
+            modifiers,
+        })
+        .ok_or(anyhow!("failed to fixate format {format:?}"))?;
+
+        let buffer =
+            (self.info.add_buffer)(BufferDataType::DmaBuf).context("failed to add buffer")?;
+        if !buffer.is_dma_buf || buffer.planes.len() > CAPTURE_MAX_PLANES {
+            (self.info.remove_buffer)(buffer.user_handle);
+            bail!("unsupported buffer {buffer:?}");
+        }
+
+        let mut texture_data: CaptureTextureData = unsafe { mem::zeroed() };
+        texture_data.type_ = CAPTURE_TEXTURE_DATA_TYPE;
+        texture_data.nfd = buffer.planes.len() as _;
+        let (width, height) = self.info.scale.apply(self.info.width, self.info.height);
+        texture_data.width = width as _;
+        texture_data.height = height as _;
+        texture_data.format = fourcc as _;
+        texture_data.modifier = fixate.modifier.unwrap_or(DRM_FORMAT_MOD_INVALID);
+        for (idx, plane) in buffer.planes.iter().enumerate() {
+            texture_data.strides[idx] = plane.stride as _;
+            texture_data.offsets[idx] = plane.offset as _;
+        }
+        let fds: Vec<RawFd> = buffer.planes.iter().map(|plane| plane.fd as _).collect();
+
+        let socket = self.socket.as_ref().ok_or(anyhow!("not connected"))?;
+        if let Err(e) = send_with_fds(socket, unsafe { as_bytes(&texture_data) }, &fds) {
+            (self.info.remove_buffer)(buffer.user_handle);
+            return Err(e.into());
+        }
+
+        info!(
+            "obs capture started, format: {:?}, modifier: {:?}",
+            format, fixate.modifier
+        );
+        self.buffer = Some(buffer.user_handle);
+        (self.info.state_changed)(self.state(), None);
+        Ok(())
+    }
+
+    fn stop_capture(&mut self) {
+        if let Some(user_handle) = self.buffer.take() {
+            debug!("obs capture stopped");
+            (self.info.remove_buffer)(user_handle);
+            (self.info.state_changed)(self.state(), None);
+        }
+    }
+}
+
+fn obs_stream_thread(mut info: StreamInfo, receiver: Receiver<StreamMessage>) {
+    info.format_preference.sort(&mut info.enum_formats);
+    let (width, height) = info.scale.apply(info.width, info.height);
+    let limiter = CaptureLimiter::global().register(width, height);
+    let mut stream = ObsStream {
+        inner: RefCell::new(ObsStreamInner {
+            info,
+            socket: None,
+            last_connect: None,
+            recv_buf: Vec::new(),
+            control: None,
+            buffer: None,
+            limiter,
+            stats: StatsRecorder::from_env(),
+            last_error: None,
+            terminated: false,
+        }),
+    };
+
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(msg) => {
+                trace!("receive {:?}", msg);
+                let _ = msg.try_call_mut(&mut stream);
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        let mut inner = stream.inner.borrow_mut();
+        if inner.terminated {
+            break;
+        }
+        inner.poll();
+    }
+
+    let _ = stream.terminate();
+}
@@ -0,0 +1,500 @@
+//! Post-processing of captured frames
+
+use crate::*;
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+static POST_PROCESS: Lazy<Option<PostProcess>> = Lazy::new(|| {
+    let path = config_path()?;
+    let content = fs::read_to_string(&path).ok()?;
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    match PostProcess::parse(&content, dir) {
+        Ok(v) if v.is_empty() => None,
+        Ok(v) => {
+            debug!(
+                "post-processing frames, {} masks, watermark {:?}",
+                v.masks.len(),
+                v.watermark.as_ref().map(|w| (w.width, w.height))
+            );
+            Some(v)
+        }
+        Err(e) => {
+            warn!("invalid post_process in {path:?}: {e:#}");
+            None
+        }
+    }
+});
+
+/// Post-processing configured for captured frames, `None` if frames are
+/// exported untouched
+pub fn post_process() -> Option<&'static PostProcess> {
+    POST_PROCESS.as_ref()
+}
+
+/// Rectangle in texels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Horizontal run of watermark texels drawn opaque
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatermarkRun {
+    pub x: u32,
+    pub y: u32,
+    pub len: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watermark {
+    pub width: u32,
+    pub height: u32,
+    /// Texels in sRGB, top row first
+    pub rgba: Vec<u8>,
+    runs: Vec<WatermarkRun>,
+}
+
+/// Black and the watermark encoded in the format of frames in memory, once
+/// per capture rather than per frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodedTexels {
+    black: Vec<u8>,
+    watermark: Option<Vec<u8>>,
+}
+
+impl Watermark {
+    /// Parses a Netpbm PAM image of `RGB` or `RGB_ALPHA` tuples
+    pub fn parse_pam(data: &[u8]) -> Result<Self> {
+        let data = data
+            .strip_prefix(b"P7\n")
+            .ok_or(anyhow!("not a PAM image"))?;
+        let mut header = Vec::new();
+        let mut rest = data;
+        loop {
+            let end = rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .ok_or(anyhow!("truncated header"))?;
+            let line = std::str::from_utf8(&rest[..end])?.trim();
+            rest = &rest[end + 1..];
+            if line == "ENDHDR" {
+                break;
+            }
+            if !line.is_empty() && !line.starts_with('#') {
+                header.push(line.split_once(' ').unwrap_or((line, "")));
+            }
+        }
+        let field = |name: &str| {
+            header
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.trim())
+                .ok_or(anyhow!("no {name}"))
+        };
+        let width: u32 = field("WIDTH")?.parse()?;
+        let height: u32 = field("HEIGHT")?.parse()?;
+        let depth: usize = field("DEPTH")?.parse()?;
+        if field("MAXVAL")? != "255" {
+            return Err(anyhow!("only 8 bits per channel are supported"));
+        }
+        match (field("TUPLTYPE")?, depth) {
+            ("RGB", 3) | ("RGB_ALPHA", 4) => (),
+            (tuple_type, _) => return Err(anyhow!("unsupported tuple type {tuple_type}")),
+        }
+        let len = width as usize * height as usize;
+        if width == 0 || height == 0 || rest.len() < len * depth {
+            return Err(anyhow!("truncated image"));
+        }
+        let rgba: Vec<_> = rest[..len * depth]
+            .chunks_exact(depth)
+            .flat_map(|texel| [texel[0], texel[1], texel[2], *texel.get(3).unwrap_or(&255)])
+            .collect();
+        let runs = opaque_runs(&rgba, width);
+        Ok(Self {
+            width,
+            height,
+            rgba,
+            runs,
+        })
+    }
+
+    /// Runs of texels at least half opaque, row by row
+    pub fn runs(&self) -> &[WatermarkRun] {
+        &self.runs
+    }
+
+    /// Texels encoded in `format`, tightly packed, `None` if not supported
+    pub fn encode(&self, format: Format) -> Option<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.rgba.len() * 2);
+        for texel in self.rgba.chunks_exact(4) {
+            data.extend(encode_texel(format, texel.try_into().unwrap())?);
+        }
+        Some(data)
+    }
+}
+
+/// Computed once on load as watermarks get drawn every frame
+fn opaque_runs(rgba: &[u8], width: u32) -> Vec<WatermarkRun> {
+    let mut runs = Vec::new();
+    for (y, row) in rgba.chunks_exact(width as usize * 4).enumerate() {
+        let mut start = None;
+        for x in 0..=width as usize {
+            let opaque = x < width as usize && row[x * 4 + 3] >= 128;
+            match (start, opaque) {
+                (None, true) => start = Some(x),
+                (Some(begin), false) => {
+                    runs.push(WatermarkRun {
+                        x: begin as _,
+                        y: y as _,
+                        len: (x - begin) as _,
+                    });
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+    }
+    runs
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PostProcess {
+    /// Rectangles to black out, `[x, y, width, height]` as fractions of the
+    /// frame
+    pub masks: Vec<[f32; 4]>,
+    pub watermark: Option<Watermark>,
+}
+
+impl PostProcess {
+    /// Parses the `post_process` table of a config file in `config_dir`
+    pub fn parse(content: &str, config_dir: &Path) -> Result<Self> {
+        let config: toml::Table = content.parse()?;
+        let table = match config.get("post_process") {
+            Some(v) => v.as_table().ok_or(anyhow!("post_process is not a table"))?,
+            None => return Ok(Self::default()),
+        };
+        let masks = match table.get("masks") {
+            Some(v) => v
+                .as_array()
+                .ok_or(anyhow!("masks is not an array"))?
+                .iter()
+                .map(parse_mask)
+                .collect::<Result<_>>()?,
+            None => vec![],
+        };
+        let watermark = match table.get("watermark") {
+            Some(v) => {
+                let path = config_dir.join(v.as_str().ok_or(anyhow!("watermark is not a path"))?);
+                let data = fs::read(&path).map_err(|e| anyhow!("{path:?}: {e}"))?;
+                let watermark =
+                    Watermark::parse_pam(&data).map_err(|e| anyhow!("{path:?}: {e:#}"))?;
+                Some(watermark)
+            }
+            None => None,
+        };
+        Ok(Self { masks, watermark })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.masks.is_empty() && self.watermark.is_none()
+    }
+
+    /// Masks within frames of `width` x `height`, empty ones left out
+    pub fn mask_rects(&self, width: u32, height: u32) -> Vec<PixelRect> {
+        let scale = |value: f32, size: u32| (value * size as f32).round() as u32;
+        self.masks
+            .iter()
+            .filter_map(|&[x, y, w, h]| {
+                let (left, top) = (scale(x, width), scale(y, height));
+                let right = scale(x + w, width).min(width);
+                let bottom = scale(y + h, height).min(height);
+                (right > left && bottom > top).then_some(PixelRect {
+                    x: left,
+                    y: top,
+                    width: right - left,
+                    height: bottom - top,
+                })
+            })
+            .collect()
+    }
+
+    /// Where the watermark goes in frames of `width` x `height`, `None` if
+    /// there is none or it does not fit
+    pub fn watermark_rect(&self, width: u32, height: u32) -> Option<PixelRect> {
+        let watermark = self.watermark.as_ref()?;
+        // same margin as the indicator dot
+        let margin = (width.min(height) / 64).max(8);
+        if width < watermark.width + margin * 2 || height < watermark.height + margin * 2 {
+            return None;
+        }
+        Some(PixelRect {
+            x: width - watermark.width - margin,
+            y: height - watermark.height - margin,
+            width: watermark.width,
+            height: watermark.height,
+        })
+    }
+
+    /// Texels to post-process frames of `format` in memory with, `None` if
+    /// `format` is not supported
+    pub fn encode(&self, format: Format) -> Option<EncodedTexels> {
+        let black = encode_texel(format, [0, 0, 0, 255])?;
+        let watermark = match &self.watermark {
+            Some(watermark) => Some(watermark.encode(format)?),
+            None => None,
+        };
+        Some(EncodedTexels { black, watermark })
+    }
+
+    /// Applies masks and the watermark to a frame in memory, rows `stride`
+    /// bytes apart, top row first, with `texels` encoded in its format
+    pub fn apply(
+        &self,
+        texels: &EncodedTexels,
+        data: &mut [u8],
+        width: u32,
+        height: u32,
+        stride: usize,
+    ) {
+        let black = &texels.black;
+        let texel_size = black.len();
+        let mut fill = |x: u32, y: u32, texels: &[u8]| {
+            let offset = y as usize * stride + x as usize * texel_size;
+            if let Some(dst) = data.get_mut(offset..offset + texels.len()) {
+                dst.copy_from_slice(texels);
+            }
+        };
+
+        for rect in self.mask_rects(width, height) {
+            let row = black.repeat(rect.width as usize);
+            for y in rect.y..rect.y + rect.height {
+                fill(rect.x, y, &row);
+            }
+        }
+        let (watermark, encoded, rect) = match (
+            &self.watermark,
+            &texels.watermark,
+            self.watermark_rect(width, height),
+        ) {
+            (Some(watermark), Some(encoded), Some(rect)) => (watermark, encoded, rect),
+            _ => return,
+        };
+        for run in watermark.runs() {
+            let offset = (run.y * watermark.width + run.x) as usize * texel_size;
+            let texels = &encoded[offset..offset + run.len as usize * texel_size];
+            fill(rect.x + run.x, rect.y + run.y, texels);
+        }
+    }
+}
+
+fn parse_mask(value: &toml::Value) -> Result<[f32; 4]> {
+    let values = value
+        .as_array()
+        .filter(|v| v.len() == 4)
+        .ok_or(anyhow!("masks must be [x, y, width, height]"))?;
+    let mut mask = [0f32; 4];
+    for (dst, value) in mask.iter_mut().zip(values) {
+        let value = value
+            .as_float()
+            .or_else(|| value.as_integer().map(|v| v as f64))
+            .filter(|v| (0.0..=1.0).contains(v))
+            .ok_or(anyhow!("mask values must be fractions of the frame"))?;
+        *dst = value as f32;
+    }
+    Ok(mask)
+}
+
+/// sRGB texel encoded in `format`, `None` if not supported
+pub fn encode_texel(format: Format, [r, g, b, a]: [u8; 4]) -> Option<Vec<u8>> {
+    let unorm10 = |v: u8| v as u32 * 1023 / 255;
+    let word = |v: u32| Some(v.to_le_bytes().to_vec());
+    match format {
+        Format::RGBA => Some(vec![r, g, b, a]),
+        Format::RGBx => Some(vec![r, g, b, 255]),
+        Format::BGRA => Some(vec![b, g, r, a]),
+        Format::BGRx => Some(vec![b, g, r, 255]),
+        Format::ARGB => Some(vec![a, r, g, b]),
+        Format::xRGB => Some(vec![255, r, g, b]),
+        Format::ABGR => Some(vec![a, b, g, r]),
+        Format::xBGR => Some(vec![255, b, g, r]),
+        Format::RGB => Some(vec![r, g, b]),
+        Format::BGR => Some(vec![b, g, r]),
+        Format::RGBA_102LE | Format::RGBx_102LE | Format::xBGR_210LE | Format::ABGR_210LE => {
+            word(3 << 30 | unorm10(b) << 20 | unorm10(g) << 10 | unorm10(r))
+        }
+        Format::BGRA_102LE | Format::BGRx_102LE | Format::xRGB_210LE | Format::ARGB_210LE => {
+            word(3 << 30 | unorm10(r) << 20 | unorm10(g) << 10 | unorm10(b))
+        }
+        Format::RGBA_F16 => {
+            // half float frames are linear
+            let linear = |v: u8| {
+                let v = v as f32 / 255.0;
+                match v <= 0.04045 {
+                    true => v / 12.92,
+                    false => ((v + 0.055) / 1.055).powf(2.4),
+                }
+            };
+            let channels = [linear(r), linear(g), linear(b), 1.0];
+            Some(
+                channels
+                    .iter()
+                    .flat_map(|&v| f16_bits(v).to_le_bytes())
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Half float of `value` in `0..=1`
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.clamp(0.0, 1.0).to_bits();
+    let exp = (bits >> 23) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if exp < -10 {
+        return 0;
+    }
+    if exp <= 0 {
+        // subnormal, rounded half up
+        let value = (mantissa | 0x80_0000) >> (13 - exp);
+        return ((value + 1) >> 1) as u16;
+    }
+    ((exp as u32) << 10 | (mantissa + 0x1000) >> 13) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pam(width: u32, height: u32, alpha: &[u8]) -> Vec<u8> {
+        let mut data = format!(
+            "P7\nWIDTH {width}\nHEIGHT {height}\nDEPTH 4\nMAXVAL 255\n\
+             # comment\nTUPLTYPE RGB_ALPHA\nENDHDR\n"
+        )
+        .into_bytes();
+        for &a in alpha {
+            data.extend([10, 20, 30, a]);
+        }
+        data
+    }
+
+    #[test]
+    fn parse() {
+        let dir = Path::new("/nonexistent");
+        let config = PostProcess::parse(
+            "[post_process]\nmasks = [[0, 0.5, 0.25, 0.5], [0.9, 0.9, 0.5, 0.5]]\n",
+            dir,
+        )
+        .unwrap();
+        assert_eq!(config.masks, [[0.0, 0.5, 0.25, 0.5], [0.9, 0.9, 0.5, 0.5]]);
+        assert_eq!(
+            config.mask_rects(1920, 1080),
+            [
+                PixelRect {
+                    x: 0,
+                    y: 540,
+                    width: 480,
+                    height: 540
+                },
+                // clipped to the frame
+                PixelRect {
+                    x: 1728,
+                    y: 972,
+                    width: 192,
+                    height: 108
+                },
+            ]
+        );
+        assert!(config.watermark_rect(1920, 1080).is_none());
+
+        assert!(PostProcess::parse("[apps]\nblock = []\n", dir)
+            .unwrap()
+            .is_empty());
+        assert!(PostProcess::parse("[post_process]\nmasks = [[0, 0, 2, 1]]\n", dir).is_err());
+        assert!(PostProcess::parse("[post_process]\nmasks = [[0, 0, 1]]\n", dir).is_err());
+        assert!(PostProcess::parse("[post_process]\nwatermark = \"logo.pam\"\n", dir).is_err());
+    }
+
+    #[test]
+    fn watermark() {
+        let watermark = Watermark::parse_pam(&pam(3, 2, &[255, 0, 128, 200, 200, 127])).unwrap();
+        assert_eq!((watermark.width, watermark.height), (3, 2));
+        assert_eq!(&watermark.rgba[..4], [10, 20, 30, 255]);
+        assert_eq!(
+            watermark.runs(),
+            [
+                WatermarkRun { x: 0, y: 0, len: 1 },
+                WatermarkRun { x: 2, y: 0, len: 1 },
+                WatermarkRun { x: 0, y: 1, len: 2 },
+            ]
+        );
+        assert!(Watermark::parse_pam(&pam(3, 2, &[255; 5])).is_err());
+        assert!(Watermark::parse_pam(b"P6\n3 2\n255\n").is_err());
+
+        let config = PostProcess {
+            masks: vec![],
+            watermark: Some(watermark),
+        };
+        assert_eq!(
+            config.watermark_rect(640, 480),
+            Some(PixelRect {
+                x: 629,
+                y: 470,
+                width: 3,
+                height: 2
+            })
+        );
+        assert_eq!(config.watermark_rect(16, 16), None);
+    }
+
+    #[test]
+    fn apply() {
+        let config = PostProcess {
+            masks: vec![[0.0, 0.0, 0.5, 0.5]],
+            watermark: Some(Watermark::parse_pam(&pam(2, 1, &[255, 0])).unwrap()),
+        };
+        let (width, height, stride) = (20, 20, 20 * 4 + 8);
+        let mut data = vec![0xffu8; stride * height as usize];
+        let texels = config.encode(Format::BGRA).unwrap();
+        config.apply(&texels, &mut data, width, height, stride);
+        assert_eq!(&data[..4], [0, 0, 0, 255]);
+        assert_eq!(
+            &data[9 * stride + 9 * 4..][..8],
+            [0, 0, 0, 255, 0xff, 0xff, 0xff, 0xff]
+        );
+        // watermark at the bottom right, 8 texels margin
+        let offset = 11 * stride + 10 * 4;
+        assert_eq!(
+            &data[offset..offset + 8],
+            [30, 20, 10, 255, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(config.encode(Format::NV12), None);
+    }
+
+    #[test]
+    fn texels() {
+        assert_eq!(
+            encode_texel(Format::xRGB, [1, 2, 3, 4]),
+            Some(vec![255, 1, 2, 3])
+        );
+        assert_eq!(
+            encode_texel(Format::RGBA_102LE, [255, 0, 0, 255]),
+            Some(0xc000_03ffu32.to_le_bytes().to_vec())
+        );
+        assert_eq!(
+            encode_texel(Format::RGBA_F16, [0, 0, 255, 0]),
+            Some(vec![0, 0, 0, 0, 0x00, 0x3c, 0x00, 0x3c])
+        );
+        assert_eq!(encode_texel(Format::NV12, [0; 4]), None);
+        assert_eq!(f16_bits(0.5), 0x3800);
+        assert_eq!(f16_bits(1.0 / 65536.0), 0x0100);
+    }
+}
@@ -0,0 +1,220 @@
+//! Downscaling of exported frames
+
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+use crate::settings::setting;
+
+static SCALE: Lazy<Scale> = Lazy::new(Scale::from_env);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Scale {
+    /// Export at the size of the source
+    #[default]
+    Native,
+    /// Scale both dimensions by a factor in `(0, 1)`
+    Factor(f64),
+    /// Scale down to at most this height
+    MaxHeight(u32),
+    /// Export at this width and height, letterboxing frames
+    Fixed(u32, u32),
+}
+
+/// Area of the exported frame the source is scaled into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    /// Part of the region the `visible` area at the top left of a `from`
+    /// sized source is scaled into
+    pub fn visible(self, from: (u32, u32), visible: (u32, u32)) -> Self {
+        let scale = |v: u32, from: u32, to: u32| {
+            if from == 0 {
+                return to;
+            }
+            (v.min(from) as u64 * to as u64 / from as u64) as u32
+        };
+        Self {
+            width: scale(visible.0, from.0, self.width),
+            height: scale(visible.1, from.1, self.height),
+            ..self
+        }
+    }
+}
+
+impl Scale {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Some((width, height)) = value.split_once('x') {
+            return match (width.parse::<u32>(), height.parse::<u32>()) {
+                (Ok(width), Ok(height)) if width > 0 && height > 0 => {
+                    Some(Self::Fixed(width, height))
+                }
+                _ => None,
+            };
+        }
+        if let Some(height) = value.strip_suffix('p') {
+            return match height.parse::<u32>() {
+                Ok(height) if height > 0 => Some(Self::MaxHeight(height)),
+                _ => None,
+            };
+        }
+        match value.parse::<f64>() {
+            Ok(factor) if factor == 1.0 => Some(Self::Native),
+            Ok(factor) if factor > 0.0 && factor < 1.0 => Some(Self::Factor(factor)),
+            _ => None,
+        }
+    }
+
+    fn from_env() -> Self {
+        let scale = match setting("PW_CAPTURE_SCALE") {
+            Some(value) => Self::parse(&value).unwrap_or_else(|| {
+                warn!("invalid PW_CAPTURE_SCALE {value:?}");
+                Self::Native
+            }),
+            None => Self::Native,
+        };
+        debug!("capture scale: {:?}", scale);
+        scale
+    }
+
+    /// Scale set by `PW_CAPTURE_SCALE`
+    pub fn global() -> Self {
+        *SCALE
+    }
+
+    /// Exported size of a `width`x`height` source, scaled sizes are rounded to
+    /// even numbers so chroma subsampled formats stay usable
+    pub fn apply(self, width: u32, height: u32) -> (u32, u32) {
+        let factor = match self {
+            Self::Factor(factor) => factor,
+            Self::MaxHeight(max) if height > max => max as f64 / height as f64,
+            Self::Fixed(width, height) => return (width, height),
+            _ => return (width, height),
+        };
+        let scale = |v: u32| ((v as f64 * factor / 2.0).round() as u32 * 2).clamp(v.min(2), v);
+        (scale(width), scale(height))
+    }
+
+    /// Area of the exported frame a `width`x`height` source is scaled into,
+    /// all of it unless letterboxed
+    pub fn region(self, width: u32, height: u32) -> Region {
+        let (export_width, export_height) = self.apply(width, height);
+        let full = Region {
+            x: 0,
+            y: 0,
+            width: export_width,
+            height: export_height,
+        };
+        if !matches!(self, Self::Fixed(..)) || width == 0 || height == 0 {
+            return full;
+        }
+        let factor = f64::min(
+            export_width as f64 / width as f64,
+            export_height as f64 / height as f64,
+        );
+        let fit = |v: u32, max: u32| ((v as f64 * factor / 2.0).round() as u32 * 2).clamp(1, max);
+        let (width, height) = (fit(width, export_width), fit(height, export_height));
+        Region {
+            x: (export_width - width) / 2,
+            y: (export_height - height) / 2,
+            width,
+            height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(Scale::parse("0.5"), Some(Scale::Factor(0.5)));
+        assert_eq!(Scale::parse("1"), Some(Scale::Native));
+        assert_eq!(Scale::parse(" 1080p "), Some(Scale::MaxHeight(1080)));
+        assert_eq!(Scale::parse("2"), None);
+        assert_eq!(Scale::parse("0"), None);
+        assert_eq!(Scale::parse("0p"), None);
+        assert_eq!(Scale::parse("p"), None);
+        assert_eq!(Scale::parse("1920x1080"), Some(Scale::Fixed(1920, 1080)));
+        assert_eq!(Scale::parse("1920x0"), None);
+        assert_eq!(Scale::parse("x1080"), None);
+    }
+
+    #[test]
+    fn apply() {
+        assert_eq!(Scale::Native.apply(3840, 2160), (3840, 2160));
+        assert_eq!(Scale::Factor(0.5).apply(3840, 2160), (1920, 1080));
+        assert_eq!(Scale::Factor(0.5).apply(1366, 768), (684, 384));
+        assert_eq!(Scale::MaxHeight(1080).apply(3840, 2160), (1920, 1080));
+        assert_eq!(Scale::MaxHeight(1080).apply(1280, 720), (1280, 720));
+        assert_eq!(Scale::Factor(0.1).apply(4, 4), (2, 2));
+        assert_eq!(Scale::Fixed(1920, 1080).apply(800, 600), (1920, 1080));
+    }
+
+    #[test]
+    fn region() {
+        let full = |width, height| Region {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        assert_eq!(Scale::Native.region(1366, 768), full(1366, 768));
+        assert_eq!(Scale::MaxHeight(1080).region(3840, 2160), full(1920, 1080));
+        assert_eq!(
+            Scale::Fixed(1920, 1080).region(3840, 2160),
+            full(1920, 1080)
+        );
+        // pillarboxed
+        assert_eq!(
+            Scale::Fixed(1920, 1080).region(800, 600),
+            Region {
+                x: 240,
+                y: 0,
+                width: 1440,
+                height: 1080,
+            }
+        );
+        // letterboxed
+        assert_eq!(
+            Scale::Fixed(1920, 1080).region(2560, 1080),
+            Region {
+                x: 0,
+                y: 135,
+                width: 1920,
+                height: 810,
+            }
+        );
+        assert_eq!(Scale::Fixed(1920, 1080).region(0, 0), full(1920, 1080));
+    }
+
+    #[test]
+    fn visible() {
+        let region = Region {
+            x: 240,
+            y: 0,
+            width: 1440,
+            height: 1080,
+        };
+        assert_eq!(region.visible((800, 600), (800, 600)), region);
+        assert_eq!(
+            region.visible((800, 600), (400, 300)),
+            Region {
+                x: 240,
+                y: 0,
+                width: 720,
+                height: 540,
+            }
+        );
+        // visible area larger than the source
+        assert_eq!(region.visible((800, 600), (1000, 600)), region);
+        assert_eq!(region.visible((0, 0), (0, 0)), region);
+    }
+}
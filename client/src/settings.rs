@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::RwLock;
+
+use once_cell::sync::Lazy;
+
+static DEFAULTS: Lazy<RwLock<HashMap<String, String>>> = Lazy::new(Default::default);
+
+/// Value of setting `name` like `PW_CAPTURE_SCALE`, taken from the environment
+/// unless only set through [`set_setting_defaults`]
+pub fn setting(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .or_else(|| DEFAULTS.read().unwrap().get(name).cloned())
+}
+
+/// Sets values settings take if not set in the environment, e.g. of Vulkan
+/// layer settings, later ones of the same name win
+pub fn set_setting_defaults(settings: impl IntoIterator<Item = (String, String)>) {
+    let mut defaults = DEFAULTS.write().unwrap();
+    for (name, value) in settings {
+        defaults.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        set_setting_defaults([
+            ("PW_CAPTURE_TEST_DEFAULT".into(), "file".into()),
+            ("PW_CAPTURE_TEST_DEFAULT".into(), "app".into()),
+            ("PW_CAPTURE_TEST_ENV".into(), "app".into()),
+        ]);
+        env::set_var("PW_CAPTURE_TEST_ENV", "env");
+        assert_eq!(setting("PW_CAPTURE_TEST_DEFAULT").as_deref(), Some("app"));
+        assert_eq!(setting("PW_CAPTURE_TEST_ENV").as_deref(), Some("env"));
+        assert_eq!(setting("PW_CAPTURE_TEST_UNSET"), None);
+    }
+}
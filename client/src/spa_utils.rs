@@ -36,13 +36,205 @@ pub(crate) unsafe fn spa_buffer_find_meta_data<T>(
     ptr::null_mut()
 }
 
-pub(crate) fn spa_pod_serialize<P: serialize::PodSerialize + ?Sized>(value: &P) -> Result<Vec<u8>> {
+/// Array metadata like `SPA_META_VideoDamage`, empty if absent
+pub(crate) unsafe fn spa_buffer_find_meta_slice<'a, T>(
+    buffer: *mut libspa_sys::spa_buffer,
+    type_: u32,
+) -> &'a mut [T] {
+    let buffer = &*buffer;
+    let metas = slice::from_raw_parts_mut(buffer.metas, buffer.n_metas as _);
+    match metas.iter().find(|meta| meta.type_ == type_) {
+        Some(meta) if !meta.data.is_null() => {
+            slice::from_raw_parts_mut(meta.data as _, meta.size as usize / mem::size_of::<T>())
+        }
+        _ => &mut [],
+    }
+}
+
+pub fn spa_pod_serialize<P: serialize::PodSerialize + ?Sized>(value: &P) -> Result<Vec<u8>> {
     let res = serialize::PodSerializer::serialize(Cursor::new(Vec::new()), value)?
         .0
         .into_inner();
     Ok(res)
 }
 
+/// Parses a pod serialized with [`spa_pod_serialize`] or received from
+/// PipeWire
+pub fn spa_pod_deserialize(bytes: &[u8]) -> Result<Value> {
+    let (_, value) = deserialize::PodDeserializer::deserialize_from::<Value>(bytes)
+        .map_err(|e| anyhow!("error parsing pod {e:?}"))?;
+    Ok(value)
+}
+
+/// Properties of `value` if it is an object of `type_`
+pub fn object_properties(value: Value, type_: u32) -> Result<Vec<Property>> {
+    let Value::Object(obj) = value else {
+        return Err(anyhow!("{:?} is not a object", value));
+    };
+    if obj.type_ != type_ {
+        return Err(anyhow!("{:?} is not of type {}", obj, type_));
+    }
+    Ok(obj.properties)
+}
+
+fn property(key: u32, value: Value) -> Property {
+    Property {
+        key,
+        flags: PropertyFlags::empty(),
+        value,
+    }
+}
+
+/// `SPA_PARAM_Meta` param of metadata a stream provides
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetaInfo {
+    /// `SPA_META_*` type
+    pub type_: u32,
+    /// Size of the metadata in bytes
+    pub size: usize,
+}
+
+impl MetaInfo {
+    pub const fn header() -> Self {
+        Self {
+            type_: spa_sys::SPA_META_Header,
+            size: mem::size_of::<spa_sys::spa_meta_header>(),
+        }
+    }
+
+    /// Visible region of the video frame
+    pub const fn video_crop() -> Self {
+        Self {
+            type_: spa_sys::SPA_META_VideoCrop,
+            size: mem::size_of::<spa_sys::spa_meta_region>(),
+        }
+    }
+
+    /// Regions of the video frame changed since the previous one, up to
+    /// `max_regions`
+    pub const fn video_damage(max_regions: usize) -> Self {
+        Self {
+            type_: spa_sys::SPA_META_VideoDamage,
+            size: mem::size_of::<spa_sys::spa_meta_region>() * max_regions,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        Value::Object(Object {
+            type_: spa_sys::SPA_TYPE_OBJECT_ParamMeta,
+            id: spa_sys::SPA_PARAM_Meta,
+            properties: vec![
+                property(spa_sys::SPA_PARAM_META_type, Value::Id(Id(self.type_))),
+                property(spa_sys::SPA_PARAM_META_size, Value::Int(self.size as _)),
+            ],
+        })
+    }
+}
+
+impl TryFrom<Value> for MetaInfo {
+    type Error = anyhow::Error;
+    fn try_from(value: Value) -> Result<Self> {
+        let mut type_ = None;
+        let mut size = None;
+        for Property { key, value, .. } in
+            object_properties(value, spa_sys::SPA_TYPE_OBJECT_ParamMeta)?
+        {
+            match (key, value) {
+                (spa_sys::SPA_PARAM_META_type, Value::Id(v)) => type_ = Some(v.0),
+                (spa_sys::SPA_PARAM_META_size, Value::Int(v)) => size = Some(v as _),
+                _ => continue,
+            }
+        }
+        Ok(Self {
+            type_: type_.ok_or(anyhow!("no meta type"))?,
+            size: size.ok_or(anyhow!("no meta size"))?,
+        })
+    }
+}
+
+/// `SPA_PARAM_Latency` param reporting latency of a port
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LatencyInfo {
+    /// `SPA_DIRECTION_*` the latency applies to
+    pub direction: u32,
+    pub min_quantum: f32,
+    pub max_quantum: f32,
+    pub min_rate: i32,
+    pub max_rate: i32,
+    pub min_ns: i64,
+    pub max_ns: i64,
+}
+
+impl LatencyInfo {
+    pub fn to_value(&self) -> Value {
+        Value::Object(Object {
+            type_: spa_sys::SPA_TYPE_OBJECT_ParamLatency,
+            id: spa_sys::SPA_PARAM_Latency,
+            properties: vec![
+                property(
+                    spa_sys::SPA_PARAM_LATENCY_direction,
+                    Value::Id(Id(self.direction)),
+                ),
+                property(
+                    spa_sys::SPA_PARAM_LATENCY_minQuantum,
+                    Value::Float(self.min_quantum),
+                ),
+                property(
+                    spa_sys::SPA_PARAM_LATENCY_maxQuantum,
+                    Value::Float(self.max_quantum),
+                ),
+                property(
+                    spa_sys::SPA_PARAM_LATENCY_minRate,
+                    Value::Int(self.min_rate),
+                ),
+                property(
+                    spa_sys::SPA_PARAM_LATENCY_maxRate,
+                    Value::Int(self.max_rate),
+                ),
+                property(spa_sys::SPA_PARAM_LATENCY_minNs, Value::Long(self.min_ns)),
+                property(spa_sys::SPA_PARAM_LATENCY_maxNs, Value::Long(self.max_ns)),
+            ],
+        })
+    }
+}
+
+impl TryFrom<Value> for LatencyInfo {
+    type Error = anyhow::Error;
+    fn try_from(value: Value) -> Result<Self> {
+        let mut info = Self::default();
+        let mut direction = None;
+        for Property { key, value, .. } in
+            object_properties(value, spa_sys::SPA_TYPE_OBJECT_ParamLatency)?
+        {
+            // absent keys are zero like `spa_latency_parse()` leaves them
+            match (key, value) {
+                (spa_sys::SPA_PARAM_LATENCY_direction, Value::Id(v)) => direction = Some(v.0),
+                (spa_sys::SPA_PARAM_LATENCY_minQuantum, Value::Float(v)) => info.min_quantum = v,
+                (spa_sys::SPA_PARAM_LATENCY_maxQuantum, Value::Float(v)) => info.max_quantum = v,
+                (spa_sys::SPA_PARAM_LATENCY_minRate, Value::Int(v)) => info.min_rate = v,
+                (spa_sys::SPA_PARAM_LATENCY_maxRate, Value::Int(v)) => info.max_rate = v,
+                (spa_sys::SPA_PARAM_LATENCY_minNs, Value::Long(v)) => info.min_ns = v,
+                (spa_sys::SPA_PARAM_LATENCY_maxNs, Value::Long(v)) => info.max_ns = v,
+                _ => continue,
+            }
+        }
+        info.direction = direction.ok_or(anyhow!("no latency direction"))?;
+        Ok(info)
+    }
+}
+
+/// `SPA_PARAM_Props` param setting `SPA_PROP_*` keys to values
+pub fn props_param(props: impl IntoIterator<Item = (u32, Value)>) -> Value {
+    Value::Object(Object {
+        type_: spa_sys::SPA_TYPE_OBJECT_Props,
+        id: spa_sys::SPA_PARAM_Props,
+        properties: props
+            .into_iter()
+            .map(|(key, value)| property(key, value))
+            .collect(),
+    })
+}
+
 pub(crate) fn choice_collect<T>(choice: ChoiceEnum<T>) -> Vec<T>
 where
     T: CanonicalFixedSizedPod,
@@ -87,16 +279,10 @@ pub(crate) fn value_collect_long(value: Value) -> Vec<i64> {
 impl TryFrom<Value> for VideoRawInfo {
     type Error = anyhow::Error;
     fn try_from(value: Value) -> Result<Self> {
-        let obj = if let Value::Object(obj) = value {
-            obj
-        } else {
-            return Err(anyhow!("{:?} is not a object", value));
-        };
-        if obj.type_ != spa_sys::SPA_TYPE_OBJECT_Format {
-            return Err(anyhow!("{:?} is not a format", obj));
-        }
         let mut info = VideoRawInfo::default();
-        for Property { key, flags, value } in obj.properties {
+        for Property { key, flags, value } in
+            object_properties(value, spa_sys::SPA_TYPE_OBJECT_Format)?
+        {
             match key {
                 spa_sys::SPA_FORMAT_VIDEO_format => {
                     info.format = Format::from(
@@ -127,3 +313,89 @@ impl From<Point> for spa_sys::spa_point {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(value: &Value) -> Value {
+        spa_pod_deserialize(&spa_pod_serialize(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn meta_round_trip() {
+        for meta in [
+            MetaInfo::header(),
+            MetaInfo::video_crop(),
+            MetaInfo::video_damage(4),
+        ] {
+            let value = round_trip(&meta.to_value());
+            assert_eq!(value, meta.to_value());
+            assert_eq!(MetaInfo::try_from(value).unwrap(), meta);
+        }
+        assert_eq!(
+            MetaInfo::video_crop().size,
+            mem::size_of::<spa_sys::spa_meta_region>()
+        );
+    }
+
+    #[test]
+    fn latency_round_trip() {
+        let latency = LatencyInfo {
+            direction: spa_sys::SPA_DIRECTION_OUTPUT,
+            min_quantum: 1.0,
+            max_quantum: 2.5,
+            min_rate: 0,
+            max_rate: 512,
+            min_ns: 1_000_000,
+            max_ns: 16_666_667,
+        };
+        let value = round_trip(&latency.to_value());
+        assert_eq!(LatencyInfo::try_from(value).unwrap(), latency);
+        assert!(LatencyInfo::try_from(MetaInfo::header().to_value()).is_err());
+    }
+
+    #[test]
+    fn props_round_trip() {
+        let props = props_param([
+            (spa_sys::SPA_PROP_volume, Value::Float(0.5)),
+            (spa_sys::SPA_PROP_mute, Value::Bool(true)),
+        ]);
+        let value = round_trip(&props);
+        assert_eq!(value, props);
+        let properties = object_properties(value, spa_sys::SPA_TYPE_OBJECT_Props).unwrap();
+        assert_eq!(properties.len(), 2);
+        assert_eq!(properties[1].key, spa_sys::SPA_PROP_mute);
+        assert_eq!(properties[1].value, Value::Bool(true));
+    }
+
+    #[test]
+    fn video_raw_info() {
+        let format = Value::Object(Object {
+            type_: spa_sys::SPA_TYPE_OBJECT_Format,
+            id: spa_sys::SPA_PARAM_EnumFormat,
+            properties: vec![
+                property(
+                    spa_sys::SPA_FORMAT_VIDEO_format,
+                    Value::Id(Id(Format::BGRA.into())),
+                ),
+                Property {
+                    key: spa_sys::SPA_FORMAT_VIDEO_modifier,
+                    flags: PropertyFlags::MANDATORY | PropertyFlags::DONT_FIXATE,
+                    value: Value::Choice(ChoiceValue::Long(Choice(
+                        ChoiceFlags::empty(),
+                        ChoiceEnum::Enum {
+                            default: 1,
+                            alternatives: vec![2, 3],
+                        },
+                    ))),
+                },
+            ],
+        });
+        let info = VideoRawInfo::try_from(round_trip(&format)).unwrap();
+        assert_eq!(info.format, Format::BGRA);
+        assert!(info.dont_fixate_modifier);
+        assert_eq!(info.modifiers, vec![1, 2, 3]);
+        assert!(VideoRawInfo::try_from(props_param([])).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! Per-stream timing statistics
+
+use crate::*;
+
+use core::mem;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+/// Change of latency not worth renegotiating the graph over
+const LATENCY_THRESHOLD: Duration = Duration::from_millis(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimingStats {
+    pub count: u64,
+    pub total: Duration,
+    pub last: Duration,
+    pub max: Duration,
+}
+
+impl TimingStats {
+    fn record(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total += duration;
+        self.last = duration;
+        self.max = self.max.max(duration);
+    }
+
+    pub fn avg(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.total.as_nanos() / self.count as u128) as u64)
+    }
+}
+
+impl fmt::Display for TimingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "avg {:?} max {:?} last {:?}",
+            self.avg(),
+            self.max,
+            self.last
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Time spent dequeuing a buffer
+    pub dequeue: TimingStats,
+    /// Time the process callback waits for the copy or blit to complete on GPU
+    pub copy_wait: TimingStats,
+    /// Time from `queue_buffer_process` until the buffer is queued to PipeWire
+    pub process: TimingStats,
+    /// Time from dequeuing a buffer as the app presents until it is queued to
+    /// PipeWire, including waits for the copy
+    pub latency: TimingStats,
+    /// Frames no buffer was available for
+    pub missed: u64,
+    /// Swap interval the app requested, 0 if it renders uncapped
+    pub swap_interval: Option<i32>,
+    /// Size frames are exported in, once a format got negotiated
+    pub size: Option<(u32, u32)>,
+    /// DRM format modifier of exported buffers, if negotiated with one
+    pub modifier: Option<u64>,
+}
+
+impl fmt::Display for StreamStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dequeue: {}, copy wait: {}, process: {}, latency: {}, frames: {}, missed: {}",
+            self.dequeue,
+            self.copy_wait,
+            self.process,
+            self.latency,
+            self.process.count,
+            self.missed
+        )?;
+        if let Some((width, height)) = self.size {
+            write!(f, ", size: {width}x{height}")?;
+        }
+        if let Some(modifier) = self.modifier {
+            write!(f, ", modifier: {modifier:#x}")?;
+        }
+        if let Some(swap_interval) = self.swap_interval {
+            write!(f, ", swap interval: {swap_interval}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct RecorderState {
+    stats: StreamStats,
+    last_log: Option<Instant>,
+    /// When buffers not queued yet got dequeued
+    dequeued: HashMap<BufferHandle, Instant>,
+}
+
+#[derive(Debug)]
+pub(crate) struct StatsRecorder {
+    log_interval: Option<Duration>,
+    state: Mutex<RecorderState>,
+}
+
+impl StatsRecorder {
+    fn new(log_interval: Option<Duration>) -> Self {
+        Self {
+            log_interval,
+            state: Mutex::new(RecorderState {
+                stats: StreamStats::default(),
+                last_log: None,
+                dequeued: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Log interval set by `PW_CAPTURE_STATS_INTERVAL` in seconds
+    pub(crate) fn from_env() -> Self {
+        let log_interval = match setting("PW_CAPTURE_STATS_INTERVAL") {
+            Some(value) => match value.parse::<f64>() {
+                Ok(secs) if secs > 0.0 => Some(Duration::from_secs_f64(secs)),
+                _ => {
+                    warn!("invalid PW_CAPTURE_STATS_INTERVAL {value:?}");
+                    None
+                }
+            },
+            None => None,
+        };
+        Self::new(log_interval)
+    }
+
+    pub(crate) fn record_dequeue(&self, duration: Duration, dequeued: bool) {
+        let mut state = self.state.lock().unwrap();
+        if dequeued {
+            state.stats.dequeue.record(duration);
+        } else {
+            state.stats.missed += 1;
+        }
+    }
+
+    /// Remembers `buffer` got dequeued at `now` for its latency
+    pub(crate) fn record_dequeued(&self, buffer: BufferHandle, now: Instant) {
+        self.state.lock().unwrap().dequeued.insert(buffer, now);
+    }
+
+    /// Records latency of `buffer` queued to PipeWire at `now`
+    pub(crate) fn record_queued(&self, buffer: BufferHandle, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(dequeued) = state.dequeued.remove(&buffer) {
+            let latency = now.saturating_duration_since(dequeued);
+            state.stats.latency.record(latency);
+        }
+    }
+
+    pub(crate) fn record_copy_wait(&self, duration: Duration) {
+        self.state.lock().unwrap().stats.copy_wait.record(duration);
+    }
+
+    pub(crate) fn record_swap_interval(&self, swap_interval: i32) {
+        self.state.lock().unwrap().stats.swap_interval = Some(swap_interval);
+    }
+
+    pub(crate) fn record_format(&self, size: (u32, u32), modifier: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.stats.size = Some(size);
+        state.stats.modifier = modifier;
+    }
+
+    pub(crate) fn record_process(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.stats.process.record(duration);
+
+        let interval = match self.log_interval {
+            Some(v) => v,
+            None => return,
+        };
+        let now = Instant::now();
+        match state.last_log {
+            Some(last) if now.duration_since(last) < interval => (),
+            Some(_) => {
+                info!("stream stats: {}", state.stats);
+                state.last_log = Some(now);
+            }
+            None => state.last_log = Some(now),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> StreamStats {
+        self.state.lock().unwrap().stats
+    }
+}
+
+/// Rate of frames processed between updates
+#[derive(Debug, Default)]
+pub(crate) struct FpsCounter {
+    last: Option<(Instant, u64)>,
+}
+
+impl FpsCounter {
+    /// Frames per second since the last update given `frames` processed so
+    /// far, `None` on the first update
+    pub fn update(&mut self, frames: u64, now: Instant) -> Option<f64> {
+        let last = self.last.replace((now, frames));
+        let (at, last_frames) = last?;
+        let elapsed = now.saturating_duration_since(at).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        Some(frames.saturating_sub(last_frames) as f64 / elapsed)
+    }
+}
+
+/// Latency advertised to the graph, averaged over frames processed between
+/// updates
+#[derive(Debug, Default)]
+pub(crate) struct LatencyTracker {
+    last: TimingStats,
+    advertised: Option<Duration>,
+}
+
+impl LatencyTracker {
+    /// Latency to advertise given `latency` of frames processed so far,
+    /// `None` if no frame got processed since the last update or the latency
+    /// did not change notably
+    pub fn update(&mut self, latency: &TimingStats) -> Option<Duration> {
+        let last = mem::replace(&mut self.last, *latency);
+        let count = latency.count.saturating_sub(last.count);
+        if count == 0 {
+            return None;
+        }
+        let total = latency.total.saturating_sub(last.total);
+        let avg = Duration::from_nanos((total.as_nanos() / count as u128) as u64);
+        if let Some(advertised) = self.advertised {
+            let diff = if avg > advertised {
+                avg - advertised
+            } else {
+                advertised - avg
+            };
+            if diff < LATENCY_THRESHOLD {
+                return None;
+            }
+        }
+        self.advertised = Some(avg);
+        Some(avg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timing_stats() {
+        let mut timing = TimingStats::default();
+        assert_eq!(timing.avg(), Duration::ZERO);
+
+        timing.record(Duration::from_millis(2));
+        timing.record(Duration::from_millis(6));
+        timing.record(Duration::from_millis(4));
+        assert_eq!(timing.count, 3);
+        assert_eq!(timing.avg(), Duration::from_millis(4));
+        assert_eq!(timing.max, Duration::from_millis(6));
+        assert_eq!(timing.last, Duration::from_millis(4));
+
+        // count beyond u32
+        timing.count = u32::MAX as u64 + 1;
+        timing.total = Duration::from_secs(u32::MAX as u64 + 1);
+        assert_eq!(timing.avg(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn record_missed() {
+        let recorder = StatsRecorder::new(None);
+        recorder.record_dequeue(Duration::from_micros(10), true);
+        recorder.record_dequeue(Duration::ZERO, false);
+        let stats = recorder.snapshot();
+        assert_eq!(stats.dequeue.count, 1);
+        assert_eq!(stats.missed, 1);
+    }
+
+    #[test]
+    fn record_swap_interval() {
+        let recorder = StatsRecorder::new(None);
+        assert!(!recorder.snapshot().to_string().contains("swap interval"));
+        recorder.record_swap_interval(0);
+        let stats = recorder.snapshot();
+        assert_eq!(stats.swap_interval, Some(0));
+        assert!(stats.to_string().ends_with(", swap interval: 0"));
+    }
+
+    #[test]
+    fn record_format() {
+        let recorder = StatsRecorder::new(None);
+        recorder.record_format((1920, 1080), Some(0x100));
+        let stats = recorder.snapshot();
+        assert_eq!(stats.size, Some((1920, 1080)));
+        assert_eq!(stats.modifier, Some(0x100));
+        assert!(stats
+            .to_string()
+            .ends_with(", size: 1920x1080, modifier: 0x100"));
+    }
+
+    #[test]
+    fn record_latency() {
+        let recorder = StatsRecorder::new(None);
+        let start = Instant::now();
+        let buffer = BufferHandle::dangling();
+        recorder.record_queued(buffer, start);
+        assert_eq!(recorder.snapshot().latency.count, 0);
+
+        recorder.record_dequeued(buffer, start);
+        recorder.record_queued(buffer, start + Duration::from_millis(5));
+        recorder.record_queued(buffer, start + Duration::from_millis(9));
+        let stats = recorder.snapshot();
+        assert_eq!(stats.latency.count, 1);
+        assert_eq!(stats.latency.last, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn latency_tracker() {
+        let mut timing = TimingStats::default();
+        let mut tracker = LatencyTracker::default();
+        assert_eq!(tracker.update(&timing), None);
+
+        timing.record(Duration::from_millis(4));
+        timing.record(Duration::from_millis(6));
+        assert_eq!(tracker.update(&timing), Some(Duration::from_millis(5)));
+        assert_eq!(tracker.update(&timing), None);
+
+        // averaged over frames since the last update only
+        timing.record(Duration::from_micros(5500));
+        assert_eq!(tracker.update(&timing), None);
+        timing.record(Duration::from_millis(8));
+        assert_eq!(tracker.update(&timing), Some(Duration::from_millis(8)));
+
+        // frames since the last update beyond u32
+        timing.count += u32::MAX as u64 + 1;
+        timing.total += Duration::from_millis(2) * u32::MAX + Duration::from_millis(2);
+        assert_eq!(tracker.update(&timing), Some(Duration::from_millis(2)));
+    }
+
+    #[test]
+    fn fps_counter() {
+        let start = Instant::now();
+        let mut fps = FpsCounter::default();
+        assert_eq!(fps.update(10, start), None);
+        assert_eq!(fps.update(70, start + Duration::from_secs(1)), Some(60.0));
+        assert_eq!(fps.update(70, start + Duration::from_secs(2)), Some(0.0));
+        assert_eq!(fps.update(100, start + Duration::from_secs(2)), None);
+    }
+}
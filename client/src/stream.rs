@@ -4,25 +4,68 @@ use core::mem;
 use core::ptr;
 use core::slice;
 use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
-use std::sync::Arc;
-use std::{cell::RefCell, fmt::Debug};
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Debug,
+};
 
 use anyhow::{anyhow, Result};
 #[cfg(feature = "ash")]
 use ash::vk;
-use crossbeam_channel::{bounded, Sender};
+use crossbeam_channel::{bounded, Receiver, Sender, TryRecvError};
 use educe::Educe;
 use libspa::pod::Pod;
 use log::{debug, error, info, trace, warn};
+use once_cell::sync::Lazy;
 use pipewire as pw;
 use pw::properties::properties;
 use trait_enumizer::{crossbeam_class, enumizer};
 
 // allows 4 frames latency of buffer processing
 const MAX_PROCESS_BUFFERS: usize = 4;
+// buffers allocated by consumers not asking for a specific number
+const DEFAULT_BUFFERS: u32 = 8;
 const MAX_CURSOR_WIDTH: usize = 64;
 const MAX_CURSOR_BPP: usize = 4;
 const MAX_CURSOR_BITMAP_SIZE: usize = MAX_CURSOR_WIDTH * MAX_CURSOR_WIDTH * MAX_CURSOR_BPP;
+// damage beyond that is merged into its bounding box
+const MAX_DAMAGE_REGIONS: usize = 16;
+// frame statistics are published as node properties this often
+const STATS_PROPS_INTERVAL: Duration = Duration::from_secs(1);
+// consumers return buffers within a few frames unless stalled
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(5);
+const PROP_FRAMES: &str = "pw-capture.frames";
+const PROP_MISSED_FRAMES: &str = "pw-capture.missed-frames";
+const PROP_COPY_WAIT_US: &str = "pw-capture.copy-wait-us";
+const PROP_FPS: &str = "pw-capture.fps";
+const PROP_RESOLUTION: &str = "pw-capture.resolution";
+const PROP_MODIFIER: &str = "pw-capture.modifier";
+const PROP_WINDOW_TITLE: &str = "pw-capture.window.title";
+const PROP_WINDOW_APP_ID: &str = "pw-capture.window.app-id";
+const PROP_LAST_ERROR: &str = "pw-capture.last-error";
+const PROP_SWAP_INTERVAL: &str = "pw-capture.swap-interval";
+const PROP_WINDOW_SUSPENDED: &str = "pw-capture.window.suspended";
+
+static ON_DEMAND: Lazy<bool> = Lazy::new(|| {
+    let enabled = matches!(setting("PW_CAPTURE_ON_DEMAND").as_deref(), Some("1"));
+    debug!("on-demand capture enabled: {}", enabled);
+    enabled
+});
+
+/// Set by `PW_CAPTURE_ON_DEMAND=1`, streams don't drive the graph and frames
+/// are only copied once a consumer asked for one, so slow consumers like
+/// thumbnailers don't cause a copy on every present
+pub fn on_demand_enabled() -> bool {
+    *ON_DEMAND
+}
 
 #[enumizer(
     name=StreamMessage,
@@ -33,9 +76,202 @@ const MAX_CURSOR_BITMAP_SIZE: usize = MAX_CURSOR_WIDTH * MAX_CURSOR_WIDTH * MAX_
     enum_attr[derive(Debug)],
 )]
 pub trait StreamMethods {
+    /// Disconnects right away, use [`StreamProxy::drain_and_terminate`]
+    /// unless consumers are known to hold no buffers
     fn terminate(&self) -> Result<()>;
+    /// Stops handing out buffers and takes back those consumers returned,
+    /// `true` once consumers hold none of them anymore
+    fn drain(&self) -> Result<bool>;
     fn dequeue_buffer(&self) -> Option<(BufferHandle, BufferUserHandle)>;
     fn queue_buffer_process(&self, buffer: BufferHandle) -> Result<()>;
+    /// Replaces offered formats, consumers renegotiate and buffers get re-added
+    fn update_format(
+        &self,
+        width: u32,
+        height: u32,
+        enum_formats: Vec<EnumFormatInfo>,
+        colorimetry: Colorimetry,
+    ) -> Result<()>;
+    /// Changes the number of buffers consumers may allocate, consumers
+    /// renegotiate if it changed
+    fn update_max_buffers(&self, max_buffers: u32) -> Result<()>;
+    /// Number of buffers `add_buffer` failed to provide since last call,
+    /// frontends allocating ahead can grow their pools by that
+    fn take_missing_buffers(&self) -> u32;
+    /// Makes consumers re-add all buffers of the fixated format, e.g. after
+    /// frontend grew its pool, without renegotiating the format
+    fn renegotiate_buffers(&self) -> Result<()>;
+    /// Sets node properties, e.g. after the captured window got renamed
+    fn update_props(&self, props: Vec<(String, String)>) -> Result<()>;
+    /// Sets the transform frames carry, e.g. after the surface got rotated
+    fn update_transform(&self, transform: Transform) -> Result<()>;
+    /// Publishes the swap interval the app requested, 0 if not vsynced
+    fn update_swap_interval(&self, swap_interval: i32) -> Result<()>;
+    /// Pauses the stream while the captured window is minimized or hidden
+    /// otherwise, frontends send no frames meanwhile
+    fn update_window_suspended(&self, suspended: bool) -> Result<()>;
+    /// PipeWire node id consumers connect to, once assigned
+    fn node_id(&self) -> Option<u32>;
+    fn state(&self) -> StreamState;
+    fn stats(&self) -> StreamStats;
+    /// Why the stream last failed to produce frames, e.g. buffers the
+    /// frontend could not add
+    fn last_error(&self) -> Option<String>;
+}
+
+/// Calls [`StreamMethods`] of a stream on the client thread from any thread,
+/// each call waits for its reply
+#[derive(Clone)]
+pub struct StreamProxy {
+    sender: MessageSender<StreamMessage>,
+}
+
+impl StreamProxy {
+    pub(crate) fn new(sender: MessageSender<StreamMessage>) -> Self {
+        Self { sender }
+    }
+
+    fn methods(
+        &self,
+    ) -> StreamMethodsProxy<ClientError, impl Fn(StreamMessage) -> ClientResult<()> + '_> {
+        StreamMethodsProxy(move |msg| self.sender.send(msg))
+    }
+
+    pub fn terminate(&self) -> ClientResult<()> {
+        try_reply(self.methods().try_terminate(), ClientError::Remote)
+    }
+
+    pub fn drain(&self) -> ClientResult<bool> {
+        try_reply(self.methods().try_drain(), ClientError::Remote)
+    }
+
+    /// Waits up to 100 ms for consumers to return buffers they hold, then
+    /// disconnects, so frontends can free the memory behind buffers once this
+    /// returns without consumers failing on frames in flight
+    pub fn drain_and_terminate(&self) -> ClientResult<()> {
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        loop {
+            match self.drain() {
+                Ok(true) => break,
+                Ok(false) if Instant::now() < deadline => thread::sleep(DRAIN_POLL_INTERVAL),
+                Ok(false) => {
+                    debug!("consumers still hold buffers, disconnecting anyway");
+                    break;
+                }
+                Err(e @ (ClientError::Send | ClientError::Recv)) => return Err(e),
+                Err(e) => {
+                    debug!("failed to drain stream: {e:?}");
+                    break;
+                }
+            }
+        }
+        self.terminate()
+    }
+
+    pub fn dequeue_buffer(&self) -> ClientResult<Option<(BufferHandle, BufferUserHandle)>> {
+        reply(self.methods().try_dequeue_buffer())
+    }
+
+    pub fn queue_buffer_process(&self, buffer: BufferHandle) -> ClientResult<()> {
+        try_reply(
+            self.methods().try_queue_buffer_process(buffer),
+            ClientError::Remote,
+        )
+    }
+
+    pub fn update_format(
+        &self,
+        width: u32,
+        height: u32,
+        enum_formats: Vec<EnumFormatInfo>,
+        colorimetry: Colorimetry,
+    ) -> ClientResult<()> {
+        let res = self
+            .methods()
+            .try_update_format(width, height, enum_formats, colorimetry);
+        try_reply(res, ClientError::Negotiation)
+    }
+
+    pub fn update_max_buffers(&self, max_buffers: u32) -> ClientResult<()> {
+        try_reply(
+            self.methods().try_update_max_buffers(max_buffers),
+            ClientError::Negotiation,
+        )
+    }
+
+    pub fn take_missing_buffers(&self) -> ClientResult<u32> {
+        reply(self.methods().try_take_missing_buffers())
+    }
+
+    pub fn renegotiate_buffers(&self) -> ClientResult<()> {
+        try_reply(
+            self.methods().try_renegotiate_buffers(),
+            ClientError::Negotiation,
+        )
+    }
+
+    pub fn update_props(&self, props: Vec<(String, String)>) -> ClientResult<()> {
+        try_reply(self.methods().try_update_props(props), ClientError::Remote)
+    }
+
+    pub fn update_transform(&self, transform: Transform) -> ClientResult<()> {
+        try_reply(
+            self.methods().try_update_transform(transform),
+            ClientError::Remote,
+        )
+    }
+
+    pub fn update_swap_interval(&self, swap_interval: i32) -> ClientResult<()> {
+        try_reply(
+            self.methods().try_update_swap_interval(swap_interval),
+            ClientError::Remote,
+        )
+    }
+
+    pub fn update_window_suspended(&self, suspended: bool) -> ClientResult<()> {
+        try_reply(
+            self.methods().try_update_window_suspended(suspended),
+            ClientError::Remote,
+        )
+    }
+
+    pub fn node_id(&self) -> ClientResult<Option<u32>> {
+        reply(self.methods().try_node_id())
+    }
+
+    pub fn state(&self) -> ClientResult<StreamState> {
+        reply(self.methods().try_state())
+    }
+
+    pub fn stats(&self) -> ClientResult<StreamStats> {
+        reply(self.methods().try_stats())
+    }
+
+    pub fn last_error(&self) -> ClientResult<Option<String>> {
+        reply(self.methods().try_last_error())
+    }
+}
+
+/// Frames are only exported while `Streaming`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamState {
+    Error,
+    Unconnected,
+    Connecting,
+    Paused,
+    Streaming,
+}
+
+impl From<&pw::stream::StreamState> for StreamState {
+    fn from(state: &pw::stream::StreamState) -> Self {
+        match state {
+            pw::stream::StreamState::Error(_) => Self::Error,
+            pw::stream::StreamState::Unconnected => Self::Unconnected,
+            pw::stream::StreamState::Connecting => Self::Connecting,
+            pw::stream::StreamState::Paused => Self::Paused,
+            pw::stream::StreamState::Streaming => Self::Streaming,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -44,40 +280,114 @@ pub struct EnumFormatInfo {
     pub modifiers: Vec<u64>,
 }
 
+impl EnumFormatInfo {
+    /// Modifiers of `modifiers` offered along with `format` in
+    /// `enum_formats`, i.e. ones the exporting device supports. Consumers
+    /// setting formats of their own may pass modifiers of the device they
+    /// import on instead.
+    pub fn offered_modifiers(enum_formats: &[Self], format: Format, modifiers: &[u64]) -> Vec<u64> {
+        let offered: Vec<u64> = enum_formats
+            .iter()
+            .filter(|enum_format| enum_format.formats.contains(&format))
+            .flat_map(|enum_format| enum_format.modifiers.iter().copied())
+            .collect();
+        modifiers
+            .iter()
+            .copied()
+            .filter(|modifier| offered.contains(modifier))
+            .collect()
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct FixateFormat {
     pub modifier: Option<u64>,
     pub num_planes: u32,
+    /// Buffers of DMA-BUF formats can also be added in shared memory, for
+    /// consumers not importing DMA-BUFs
+    pub mem_fd_fallback: bool,
+}
+
+/// Memory buffers get added in, as accepted by the consumer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferDataType {
+    DmaBuf,
+    MemFd,
+}
+
+impl BufferDataType {
+    /// Picks from the `SPA_DATA_*` flags consumers accept, DMA-BUFs first
+    fn from_flags(flags: u32) -> Option<Self> {
+        if flags & (1 << spa_sys::SPA_DATA_DmaBuf) != 0 {
+            Some(Self::DmaBuf)
+        } else if flags & (1 << spa_sys::SPA_DATA_MemFd) != 0 {
+            Some(Self::MemFd)
+        } else {
+            None
+        }
+    }
 }
 
 pub struct AddBufferMetaCbs<'a> {
     pub add_cursor: Option<Box<dyn FnOnce(BufferCursorInfo) + 'a>>,
+    /// Visible region of the frame, e.g. excluding letterboxing. Frames are
+    /// not cropped unless set.
+    pub add_crop: Option<Box<dyn FnOnce(Region) + 'a>>,
+    /// Regions of the frame changed since the previous one of the stream.
+    /// Frames are damaged as a whole unless set.
+    pub add_damage: Option<Box<dyn FnOnce(&[Region]) + 'a>>,
+    /// Overrides presentation timestamp of the frame, in `CLOCK_MONOTONIC`
+    /// nanoseconds as returned by [`get_pts_nanos`]. Defaults to the time the
+    /// buffer got processed.
+    pub set_pts: Option<Box<dyn FnOnce(i64) + 'a>>,
+    /// Overrides sequence number of the frame, e.g. to match frames of
+    /// streams captured together. Defaults to a counter of the stream.
+    pub set_seq: Option<Box<dyn FnOnce(u64) + 'a>>,
 }
 
 type ProcessBufferCb = Box<dyn Fn(BufferUserHandle, AddBufferMetaCbs) + Send>;
+type StateChangedCb = Box<dyn Fn(StreamState, Option<u32>) + Send>;
 
 #[derive(Educe)]
 #[educe(Debug)]
 pub struct StreamInfo {
+    /// Size of the source, frames are exported at `scale.apply(width, height)`
     pub width: u32,
     pub height: u32,
     pub enum_formats: Vec<EnumFormatInfo>,
+    /// Reorders `enum_formats` and modifiers consumers fixate to
+    pub format_preference: FormatPreference,
+    pub colorimetry: Colorimetry,
+    /// Transform the source got rendered with
+    pub transform: Transform,
+    /// Scaling done by the frontend when copying frames
+    pub scale: Scale,
     pub max_buffers: u32,
+    /// Media class and role the capture node is announced with
+    pub node_class: NodeClass,
+    /// Extra properties of the capture node
+    pub props: Vec<(String, String)>,
     #[educe(Debug(ignore))]
     pub fixate_format: Box<dyn Fn(EnumFormatInfo) -> Option<FixateFormat> + Send>,
+    /// Adds a buffer of the data type the consumer picked. Failing makes the
+    /// buffer invalid, the stream errors out if no buffer could be added at
+    /// all
     #[educe(Debug(ignore))]
-    pub add_buffer: Box<dyn Fn() -> Option<BufferInfo> + Send>,
+    pub add_buffer: Box<dyn Fn(BufferDataType) -> Result<BufferInfo> + Send>,
     #[educe(Debug(ignore))]
     pub remove_buffer: Box<dyn Fn(BufferUserHandle) + Send>,
     #[educe(Debug(ignore))]
     pub process_buffer: ProcessBufferCb,
+    /// Called from the stream thread with new state and node id
+    #[educe(Debug(ignore))]
+    pub state_changed: StateChangedCb,
 }
 
 mod buffer_handle {
     use super::*;
     use core::num::NonZeroUsize;
 
-    #[derive(Clone, Copy, Hash, Debug)]
+    #[derive(Clone, Copy, Hash, Debug, PartialEq, Eq)]
     pub struct BufferHandle(NonZeroUsize);
 
     impl From<ptr::NonNull<pw::sys::pw_buffer>> for BufferHandle {
@@ -92,6 +402,13 @@ mod buffer_handle {
             unsafe { ptr::NonNull::new_unchecked(value.0.get() as *mut pw::sys::pw_buffer) }
         }
     }
+
+    impl BufferHandle {
+        /// handle for backends without a PipeWire buffer behind it
+        pub(crate) const fn dangling() -> Self {
+            Self(unsafe { NonZeroUsize::new_unchecked(1) })
+        }
+    }
 }
 pub use buffer_handle::*;
 
@@ -110,13 +427,32 @@ pub struct BufferInfo {
     pub user_handle: BufferUserHandle,
 }
 
+impl BufferInfo {
+    /// `SPA_DATA_*` type of the planes
+    fn data_type(&self) -> u32 {
+        if self.is_dma_buf {
+            libspa_sys::SPA_DATA_DmaBuf
+        } else {
+            libspa_sys::SPA_DATA_MemFd
+        }
+    }
+}
+
 #[non_exhaustive]
 #[derive(Clone, Copy, Hash, Debug)]
 pub enum BufferUserHandle {
     #[cfg(feature = "ash")]
     VkImage(vk::Image),
+    #[cfg(feature = "ash")]
+    VkBuffer(vk::Buffer),
     #[cfg(feature = "frontend_gl")]
     Texture(u32),
+    /// Handle of buffers added through the C API
+    #[cfg(feature = "capi")]
+    User(u64),
+    /// memfd of buffers allocated by tests
+    #[cfg(any(test, feature = "testing"))]
+    Test(i32),
 }
 
 #[derive(Clone, Debug)]
@@ -136,19 +472,98 @@ pub struct BufferCursorInfo<'a> {
     pub bitmap: Option<BufferBitmap<'a>>,
 }
 
+impl BufferCursorInfo<'_> {
+    /// Maps position on a `from` sized source onto region `to` of the
+    /// exported frame, bitmap is left as is
+    pub fn scale_position(mut self, from: (u32, u32), to: Region) -> Self {
+        if from != (to.width, to.height) && from.0 > 0 && from.1 > 0 {
+            self.position.x = (self.position.x as i64 * to.width as i64 / from.0 as i64) as _;
+            self.position.y = (self.position.y as i64 * to.height as i64 / from.1 as i64) as _;
+        }
+        self.position.x += to.x as i32;
+        self.position.y += to.y as i32;
+        self
+    }
+}
+
+// shared by listeners of the recreated streams
+struct StreamCallbacks {
+    fixate_format: Box<dyn Fn(EnumFormatInfo) -> Option<FixateFormat> + Send>,
+    add_buffer: Box<dyn Fn(BufferDataType) -> Result<BufferInfo> + Send>,
+    remove_buffer: Box<dyn Fn(BufferUserHandle) + Send>,
+    process_buffer: ProcessBufferCb,
+    state_changed: StateChangedCb,
+}
+
+/// Buffer passed on to the process callback
+struct QueuedBuffer {
+    buffer: BufferHandle,
+    queued: Instant,
+    /// Re-queued last frame, not copied again by the frontend
+    keepalive: bool,
+}
+
 #[derive(Default)]
 struct StreamData {
+    /// Frames of the source so far, including missed ones
     seq: u64,
+    /// Missed frames accounted for in `seq`
+    missed: u64,
     cursor_id: u32,
+    /// Exported size, frames not telling their damage are damaged as a whole
+    width: u32,
+    height: u32,
 }
 
 struct StreamImplInner {
     stream: pw::stream::Stream,
+    node_class: NodeClass,
+    props: Vec<(String, String)>,
     #[allow(unused)]
     listener: Option<pw::stream::StreamListener<StreamData>>,
+    /// Exported size
+    width: u32,
+    height: u32,
+    scale: Scale,
     enum_formats: Vec<EnumFormatInfo>,
+    format_preference: FormatPreference,
+    colorimetry: Colorimetry,
+    transform: Arc<AtomicU32>,
     max_buffers: u32,
-    buffer_sender: Sender<BufferHandle>,
+    missing_buffers: Arc<AtomicU32>,
+    /// Buffers currently added with frontend memory behind them
+    valid_buffers: Arc<AtomicU32>,
+    /// Buffers currently added without, the stream fails if it starts
+    /// streaming with these only
+    invalid_buffers: Arc<AtomicU32>,
+    last_error: Arc<Mutex<Option<String>>>,
+    buffer_sender: Sender<QueuedBuffer>,
+    /// Streams not driving the graph copy frames only when this is set by a
+    /// process call of the consumer
+    on_demand: bool,
+    frame_requested: Arc<AtomicBool>,
+    limiter: LimiterHandle,
+    stats: Arc<StatsRecorder>,
+    /// Interval of re-queuing the last frame while the app is not presenting
+    keepalive_interval: Option<Duration>,
+    keepalive: Arc<Mutex<KeepaliveState>>,
+    /// Disconnected while capture is disabled
+    suspended: bool,
+    /// Inactive while the captured window is hidden
+    window_suspended: bool,
+    /// Buffers queued to consumers and not dequeued again since, shared with
+    /// the process callback
+    in_flight: Arc<Mutex<HashSet<BufferHandle>>>,
+    /// Buffers dequeued and not processed yet, shared with the callbacks.
+    /// Buffers removed meanwhile, e.g. as consumers re-fixated the format,
+    /// drop out so frames copied into them are not queued.
+    dequeued: Arc<Mutex<HashSet<BufferHandle>>>,
+    /// Format and fixation last applied, to tell re-fixations apart and to
+    /// offer buffers again
+    fixated: Cell<Option<(Format, FixateFormat)>>,
+    /// Set once draining before termination, no buffers are handed out
+    draining: bool,
+    callbacks: Rc<StreamCallbacks>,
     on_terminate: Option<Box<dyn FnOnce()>>,
 }
 
@@ -157,11 +572,14 @@ pub(crate) struct StreamImpl {
     inner: Arc<RefCell<StreamImplInner>>,
 }
 
-pub(crate) fn build_stream_params(max_buffers: u32, blocks: u32, is_dma_buf: bool) -> Vec<Vec<u8>> {
-    let data_type_flag = if is_dma_buf {
-        1 << spa_sys::SPA_DATA_DmaBuf
-    } else {
-        1 << spa_sys::SPA_DATA_MemFd
+pub(crate) fn build_stream_params(max_buffers: u32, fixate_info: &FixateFormat) -> Vec<Vec<u8>> {
+    // consumers pick one of them, see `BufferDataType::from_flags`
+    let data_type_flags = match fixate_info.modifier {
+        Some(_) if fixate_info.mem_fd_fallback => {
+            (1 << spa_sys::SPA_DATA_DmaBuf) | (1 << spa_sys::SPA_DATA_MemFd)
+        }
+        Some(_) => 1 << spa_sys::SPA_DATA_DmaBuf,
+        None => 1 << spa_sys::SPA_DATA_MemFd,
     };
     let buffers = Value::Object(Object {
         type_: spa_sys::SPA_TYPE_OBJECT_ParamBuffers,
@@ -173,7 +591,7 @@ pub(crate) fn build_stream_params(max_buffers: u32, blocks: u32, is_dma_buf: boo
                 value: Value::Choice(ChoiceValue::Int(Choice(
                     ChoiceFlags::empty(),
                     ChoiceEnum::Range {
-                        default: 8,
+                        default: DEFAULT_BUFFERS.min(max_buffers) as _,
                         min: 1,
                         max: max_buffers as _,
                     },
@@ -182,7 +600,7 @@ pub(crate) fn build_stream_params(max_buffers: u32, blocks: u32, is_dma_buf: boo
             Property {
                 key: spa_sys::SPA_PARAM_BUFFERS_blocks,
                 flags: PropertyFlags::empty(),
-                value: Value::Int(blocks.max(1) as _),
+                value: Value::Int(fixate_info.num_planes.max(1) as _),
             },
             Property {
                 key: spa_sys::SPA_PARAM_BUFFERS_dataType,
@@ -190,7 +608,7 @@ pub(crate) fn build_stream_params(max_buffers: u32, blocks: u32, is_dma_buf: boo
                 value: Value::Choice(ChoiceValue::Int(Choice(
                     ChoiceFlags::empty(),
                     ChoiceEnum::Flags {
-                        default: data_type_flag,
+                        default: data_type_flags,
                         flags: vec![],
                     },
                 ))),
@@ -198,45 +616,25 @@ pub(crate) fn build_stream_params(max_buffers: u32, blocks: u32, is_dma_buf: boo
         ],
     });
 
-    let meta_header = Value::Object(Object {
-        type_: spa_sys::SPA_TYPE_OBJECT_ParamMeta,
-        id: spa_sys::SPA_PARAM_Meta,
-        properties: vec![
-            Property {
-                key: spa_sys::SPA_PARAM_META_type,
-                flags: PropertyFlags::empty(),
-                value: Value::Id(Id(spa_sys::SPA_META_Header)),
-            },
-            Property {
-                key: spa_sys::SPA_PARAM_META_size,
-                flags: PropertyFlags::empty(),
-                value: Value::Int(mem::size_of::<spa_sys::spa_meta_header>() as _),
-            },
-        ],
-    });
-
-    let cursor_meta_size = mem::size_of::<spa_sys::spa_meta_cursor>()
-        + mem::size_of::<spa_sys::spa_meta_bitmap>()
-        + MAX_CURSOR_BITMAP_SIZE;
-
-    let meta_cursor = Value::Object(Object {
-        type_: spa_sys::SPA_TYPE_OBJECT_ParamMeta,
-        id: spa_sys::SPA_PARAM_Meta,
-        properties: vec![
-            Property {
-                key: spa_sys::SPA_PARAM_META_type,
-                flags: PropertyFlags::empty(),
-                value: Value::Id(Id(spa_sys::SPA_META_Cursor)),
-            },
-            Property {
-                key: spa_sys::SPA_PARAM_META_size,
-                flags: PropertyFlags::empty(),
-                value: Value::Int(cursor_meta_size as _),
-            },
-        ],
-    });
+    let meta_cursor = MetaInfo {
+        type_: spa_sys::SPA_META_Cursor,
+        size: mem::size_of::<spa_sys::spa_meta_cursor>()
+            + mem::size_of::<spa_sys::spa_meta_bitmap>()
+            + MAX_CURSOR_BITMAP_SIZE,
+    };
+    let meta_transform = MetaInfo {
+        type_: SPA_META_VIDEO_TRANSFORM,
+        size: mem::size_of::<u32>(),
+    };
 
-    let params = &[buffers, meta_header, meta_cursor];
+    let params = &[
+        buffers,
+        MetaInfo::header().to_value(),
+        meta_cursor.to_value(),
+        meta_transform.to_value(),
+        MetaInfo::video_crop().to_value(),
+        MetaInfo::video_damage(MAX_DAMAGE_REGIONS).to_value(),
+    ];
     params
         .iter()
         .map(|value| -> Result<Vec<u8>> { spa_pod_serialize(value) })
@@ -249,6 +647,7 @@ pub(crate) fn build_format(
     height: u32,
     formats: &[Format],
     modifiers: &[u64],
+    colorimetry: &Colorimetry,
     fixate: bool,
 ) -> Result<Vec<u8>> {
     assert!(!formats.is_empty());
@@ -293,6 +692,47 @@ pub(crate) fn build_format(
         },
     ];
 
+    // YUV formats are converted with BT.709 matrix in limited range
+    let colorimetry = if formats.iter().all(|f| f.is_yuv()) {
+        Colorimetry {
+            matrix: ColorMatrix::BT709,
+            range: ColorRange::LIMITED,
+            ..*colorimetry
+        }
+    } else {
+        *colorimetry
+    };
+
+    let color_props: [(u32, u32); 4] = [
+        (
+            spa_sys::SPA_FORMAT_VIDEO_colorRange,
+            colorimetry.range.into(),
+        ),
+        (
+            spa_sys::SPA_FORMAT_VIDEO_colorMatrix,
+            colorimetry.matrix.into(),
+        ),
+        (
+            spa_sys::SPA_FORMAT_VIDEO_transferFunction,
+            colorimetry.transfer.into(),
+        ),
+        (
+            spa_sys::SPA_FORMAT_VIDEO_colorPrimaries,
+            colorimetry.primaries.into(),
+        ),
+    ];
+    for (key, value) in color_props {
+        // leave unknown fields out so consumers can apply their own defaults
+        if value == 0 {
+            continue;
+        }
+        properties.push(Property {
+            key,
+            flags: PropertyFlags::empty(),
+            value: Value::Id(Id(value)),
+        });
+    }
+
     if modifiers.len() > 0 {
         let prop = if fixate {
             Property {
@@ -332,18 +772,65 @@ impl StreamMethods for StreamImpl {
         Ok(())
     }
 
+    fn drain(&self) -> Result<bool> {
+        let mut inner = self.inner.borrow_mut();
+        if !inner.draining {
+            debug!("drain stream");
+            inner.draining = true;
+            // frames not processed yet are dropped
+            inner.buffer_sender = bounded::<QueuedBuffer>(0).0;
+            inner.keepalive.lock().unwrap().forget_frame();
+            inner.stream.flush(true)?;
+        }
+        match inner.stream.state() {
+            pw::stream::StreamState::Streaming => (),
+            _ => return Ok(true),
+        }
+        let mut in_flight = inner.in_flight.lock().unwrap();
+        while let Some(buffer) = ptr::NonNull::new(unsafe { inner.stream.dequeue_raw_buffer() }) {
+            // kept until removed on disconnect
+            in_flight.remove(&BufferHandle::from(buffer));
+        }
+        if in_flight.is_empty() {
+            return Ok(true);
+        }
+        trace!("{} buffers still held by consumers", in_flight.len());
+        // returned buffers are recycled by the next cycle of the graph
+        if inner.stream.is_driving() {
+            inner.stream.trigger_process()?;
+        }
+        Ok(false)
+    }
+
     fn dequeue_buffer(&self) -> Option<(BufferHandle, BufferUserHandle)> {
         let inner = self.inner.borrow();
         let stream = &inner.stream;
         match inner.stream.state() {
-            pw::stream::StreamState::Streaming => (),
+            pw::stream::StreamState::Streaming if !inner.draining => (),
             _ => return None,
         }
-        if !inner.stream.is_driving() {
+        if inner.on_demand {
+            if !inner.frame_requested.load(Ordering::Acquire) {
+                return None;
+            }
+        } else if !inner.stream.is_driving() {
+            return None;
+        }
+        if !inner.limiter.try_acquire() {
             return None;
         }
         unsafe {
-            let buffer = ptr::NonNull::new(stream.dequeue_raw_buffer());
+            let start = Instant::now();
+            let held = inner.keepalive.lock().unwrap().take_held();
+            let buffer = held.map(ptr::NonNull::from).or_else(|| {
+                let buffer = ptr::NonNull::new(stream.dequeue_raw_buffer())?;
+                let handle = BufferHandle::from(buffer);
+                inner.in_flight.lock().unwrap().remove(&handle);
+                Some(buffer)
+            });
+            inner
+                .stats
+                .record_dequeue(start.elapsed(), buffer.is_some());
             let buffer = if let Some(v) = buffer {
                 v
             } else {
@@ -357,30 +844,205 @@ impl StreamMethods for StreamImpl {
                 stream.queue_raw_buffer(buffer.as_ptr());
                 return None;
             };
+            inner.limiter.commit();
+            inner.dequeued.lock().unwrap().insert(buffer.into());
+            // requested frame is served, the next process call asks again
+            inner.frame_requested.store(false, Ordering::Release);
+            inner.stats.record_dequeued(buffer.into(), start);
             Some((buffer.into(), *user_data))
         }
     }
 
     fn queue_buffer_process(&self, buffer: BufferHandle) -> Result<()> {
-        if self.inner.borrow().stream.is_driving() {
-            self.inner
-                .borrow()
+        let inner = self.inner.borrow();
+        if inner.on_demand {
+            // picked up by the next process call of the graph
+            inner
+                .buffer_sender
+                .send(QueuedBuffer {
+                    buffer,
+                    queued: Instant::now(),
+                    keepalive: false,
+                })
+                .map_err(|e| anyhow!("{e:?}"))?;
+        } else if inner.stream.is_driving() {
+            inner
                 .buffer_sender
-                .send(buffer)
+                .send(QueuedBuffer {
+                    buffer,
+                    queued: Instant::now(),
+                    keepalive: false,
+                })
                 .map_err(|e| anyhow!("{e:?}"))?;
 
-            self.inner.borrow().stream.trigger_process()?;
+            inner.stream.trigger_process()?;
         }
         Ok(())
     }
+
+    fn update_format(
+        &self,
+        width: u32,
+        height: u32,
+        enum_formats: Vec<EnumFormatInfo>,
+        colorimetry: Colorimetry,
+    ) -> Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        let (width, height) = inner.scale.apply(width, height);
+        debug!("update format, extent: {}x{}", width, height);
+        inner.width = width;
+        inner.height = height;
+        let mut enum_formats = enum_formats;
+        inner.format_preference.sort(&mut enum_formats);
+        inner.enum_formats = enum_formats;
+        inner.colorimetry = colorimetry;
+        inner.limiter.resize(width, height);
+        inner.renegotiate()
+    }
+
+    fn update_max_buffers(&self, max_buffers: u32) -> Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.max_buffers == max_buffers {
+            return Ok(());
+        }
+        debug!("update max buffers: {}", max_buffers);
+        inner.max_buffers = max_buffers;
+        inner.renegotiate()
+    }
+
+    fn take_missing_buffers(&self) -> u32 {
+        self.inner
+            .borrow()
+            .missing_buffers
+            .swap(0, Ordering::AcqRel)
+    }
+
+    fn renegotiate_buffers(&self) -> Result<()> {
+        debug!("renegotiate buffers");
+        self.inner.borrow().update_buffers_param()
+    }
+
+    fn update_props(&self, props: Vec<(String, String)>) -> Result<()> {
+        debug!("update props: {:?}", props);
+        let mut inner = self.inner.borrow_mut();
+        let mut dict = pw::properties::Properties::new();
+        for (key, value) in props {
+            dict.insert(key.as_str(), value.as_str());
+            // kept for streams recreated on reconnect
+            match inner.props.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => *v = value,
+                None => inner.props.push((key, value)),
+            }
+        }
+        unsafe {
+            pw::sys::pw_stream_update_properties(
+                inner.stream.as_raw_ptr(),
+                dict.dict().as_raw_ptr(),
+            );
+        }
+        Ok(())
+    }
+
+    fn update_transform(&self, transform: Transform) -> Result<()> {
+        debug!("update transform: {:?}", transform);
+        self.inner
+            .borrow()
+            .transform
+            .store(transform.into(), Ordering::Release);
+        Ok(())
+    }
+
+    fn update_swap_interval(&self, swap_interval: i32) -> Result<()> {
+        self.inner
+            .borrow()
+            .stats
+            .record_swap_interval(swap_interval);
+        self.update_props(vec![(
+            PROP_SWAP_INTERVAL.to_owned(),
+            swap_interval.to_string(),
+        )])
+    }
+
+    fn update_window_suspended(&self, suspended: bool) -> Result<()> {
+        if self.inner.borrow().window_suspended == suspended {
+            return Ok(());
+        }
+        debug!("window suspended: {suspended}");
+        self.inner.borrow_mut().window_suspended = suspended;
+        self.inner.borrow().stream.set_active(!suspended)?;
+        self.update_props(vec![(
+            PROP_WINDOW_SUSPENDED.to_owned(),
+            suspended.to_string(),
+        )])
+    }
+
+    fn node_id(&self) -> Option<u32> {
+        node_id(&self.inner.borrow().stream)
+    }
+
+    fn state(&self) -> StreamState {
+        (&self.inner.borrow().stream.state()).into()
+    }
+
+    fn stats(&self) -> StreamStats {
+        self.inner.borrow().stats.snapshot()
+    }
+
+    fn last_error(&self) -> Option<String> {
+        self.inner.borrow().last_error.lock().unwrap().clone()
+    }
+}
+
+impl StreamImplInner {
+    /// Offers formats again, consumers renegotiate and buffers get re-added
+    fn renegotiate(&self) -> Result<()> {
+        let params = self.enum_format_params();
+        let mut params = params
+            .iter()
+            .map(|p| Pod::from_bytes(p).expect("not a valid Pod"))
+            .collect::<Vec<_>>();
+        self.stream.update_params(&mut params)?;
+        Ok(())
+    }
+
+    /// Offers buffers of the fixated format again, consumers re-add them
+    /// without renegotiating the format
+    fn update_buffers_param(&self) -> Result<()> {
+        let Some((_, fixate_info)) = self.fixated.get() else {
+            // buffers get added once a format is fixated
+            return Ok(());
+        };
+        let params = build_stream_params(self.max_buffers, &fixate_info);
+        let mut params = params
+            .iter()
+            .map(|p| Pod::from_bytes(p).expect("not a valid Pod"))
+            .collect::<Vec<_>>();
+        self.stream.update_params(&mut params)?;
+        Ok(())
+    }
+
+    fn enum_format_params(&self) -> Vec<Vec<u8>> {
+        self.enum_formats
+            .iter()
+            .map(|enum_format| {
+                build_format(
+                    self.width,
+                    self.height,
+                    &enum_format.formats,
+                    &enum_format.modifiers,
+                    &self.colorimetry,
+                    false,
+                )
+                .unwrap()
+            })
+            .collect()
+    }
 }
 
 unsafe fn on_param_changed(
     inner: &StreamImplInner,
     id: u32,
     param: Option<&Pod>,
-    width: u32,
-    height: u32,
     fixate_format: &Box<dyn Fn(EnumFormatInfo) -> Option<FixateFormat> + Send>,
 ) {
     debug!("param changed: id {}", id);
@@ -390,11 +1052,10 @@ unsafe fn on_param_changed(
     if id != spa_sys::SPA_PARAM_Format {
         return;
     }
-    let pod = deserialize::PodDeserializer::deserialize_from::<Value>(parma.as_bytes());
-    let pod = match pod {
-        Ok((_, v)) => v,
+    let pod = match spa_pod_deserialize(parma.as_bytes()) {
+        Ok(v) => v,
         Err(e) => {
-            debug!("error parsing pod {:?}", e);
+            debug!("{e}");
             return;
         }
     };
@@ -409,15 +1070,31 @@ unsafe fn on_param_changed(
     debug!("{raw_info:?}");
 
     debug!("fixating");
+    let mut modifiers = EnumFormatInfo::offered_modifiers(
+        &inner.enum_formats,
+        raw_info.format,
+        &raw_info.modifiers,
+    );
+    if modifiers.is_empty() && !raw_info.modifiers.is_empty() {
+        let error = format!(
+            "consumer modifiers {:?} not offered by the exporting device",
+            raw_info.modifiers
+        );
+        error!("{error}");
+        report_error(&inner.stream, &inner.last_error, error, false);
+        return;
+    }
+    inner.format_preference.sort_modifiers(&mut modifiers);
     let fixate_info = fixate_format(EnumFormatInfo {
         formats: vec![raw_info.format],
-        modifiers: raw_info.modifiers.clone(),
+        modifiers,
     });
     let fixate_info = if let Some(v) = fixate_info {
         v
     } else {
-        error!("no compatible format");
-        // XXX: re-update params?
+        let error = format!("no compatible format for {raw_info:?}");
+        error!("{error}");
+        report_error(&inner.stream, &inner.last_error, error, false);
         return;
     };
     debug!("fixate to {:?}", fixate_info);
@@ -428,23 +1105,16 @@ unsafe fn on_param_changed(
         debug!("has modifier");
         let fixate_modifier = fixate_info.modifier.unwrap();
         if raw_info.dont_fixate_modifier {
-            let mut params =
-                vec![
-                    build_format(width, height, &[raw_info.format], &[fixate_modifier], true)
-                        .unwrap(),
-                ];
-            for enum_format in &inner.enum_formats {
-                params.push(
-                    build_format(
-                        width,
-                        height,
-                        &enum_format.formats,
-                        &enum_format.modifiers,
-                        false,
-                    )
-                    .unwrap(),
-                )
-            }
+            let mut params = vec![build_format(
+                inner.width,
+                inner.height,
+                &[raw_info.format],
+                &[fixate_modifier],
+                &inner.colorimetry,
+                true,
+            )
+            .unwrap()];
+            params.extend(inner.enum_format_params());
             let mut params = params
                 .iter()
                 .map(|p| Pod::from_bytes(p).expect("not a valid Pod"))
@@ -457,11 +1127,25 @@ unsafe fn on_param_changed(
         debug!("no modifier");
     }
 
-    let params = build_stream_params(
-        inner.max_buffers,
-        fixate_info.num_planes,
-        fixate_info.modifier.is_some(),
-    );
+    // consumers re-fixate while streaming, e.g. falling back to another
+    // modifier after failing to import buffers, which get replaced by ones
+    // the frontend adds for the new format
+    let fixated = (raw_info.format, fixate_info.modifier);
+    match inner.fixated.replace(Some((raw_info.format, fixate_info))) {
+        Some((format, previous)) if (format, previous.modifier) != fixated => {
+            info!(
+                "re-fixated from {:?} to {:?}",
+                (format, previous.modifier),
+                fixated
+            )
+        }
+        _ => (),
+    }
+    inner
+        .stats
+        .record_format((inner.width, inner.height), fixate_info.modifier);
+
+    let params = build_stream_params(inner.max_buffers, &fixate_info);
     let mut params = params
         .iter()
         .map(|p| Pod::from_bytes(p).expect("not a valid Pod"))
@@ -470,9 +1154,39 @@ unsafe fn on_param_changed(
     let _ = stream.update_params(&mut params);
 }
 
+/// Records `error` for [`StreamMethods::last_error`] and publishes it as node
+/// property, `fatal` ones put the stream into error state
+fn report_error(
+    stream: &pw::stream::StreamRef,
+    last_error: &Mutex<Option<String>>,
+    error: String,
+    fatal: bool,
+) {
+    let props = properties! {
+        PROP_LAST_ERROR => error.as_str(),
+    };
+    unsafe {
+        pw::sys::pw_stream_update_properties(stream.as_raw_ptr(), props.dict().as_raw_ptr());
+    }
+    if fatal {
+        // consumers see the message as node error
+        let message = CString::new(error.replace('\0', "")).unwrap();
+        unsafe {
+            pw::sys::pw_stream_set_error(stream.as_raw_ptr(), -libc::EIO, message.as_ptr());
+        }
+    }
+    *last_error.lock().unwrap() = Some(error);
+}
+
 unsafe fn on_add_buffer(
+    stream: &pw::stream::StreamRef,
     buffer: *mut pw::sys::pw_buffer,
-    add_buffer: &Box<dyn Fn() -> Option<BufferInfo> + Send>,
+    add_buffer: &Box<dyn Fn(BufferDataType) -> Result<BufferInfo> + Send>,
+    remove_buffer: &Box<dyn Fn(BufferUserHandle) + Send>,
+    missing_buffers: &AtomicU32,
+    valid_buffers: &AtomicU32,
+    invalid_buffers: &AtomicU32,
+    last_error: &Mutex<Option<String>>,
 ) {
     debug!("add buffer");
     let mut buffer = ptr::NonNull::new(buffer).unwrap();
@@ -483,25 +1197,41 @@ unsafe fn on_add_buffer(
     let datas = slice::from_raw_parts_mut(spa_buffer.datas, spa_buffer.n_datas as _);
     // let metas = slice::from_raw_parts_mut(spa_buffer.metas, spa_buffer.n_metas as _);
 
-    let info = add_buffer();
-    let info = if let Some(info) = info {
-        info
-    } else {
-        error!("failed to add buffer, mark invalid");
-        for data in datas {
-            data.fd = -1;
-            data.data = ptr::null_mut();
-            data.type_ = libspa_sys::SPA_DATA_Invalid;
+    // allocated buffers carry the data types the consumer accepts
+    let accepted = datas.first().map_or(0, |data| data.type_);
+    let info = match BufferDataType::from_flags(accepted) {
+        Some(data_type) => add_buffer(data_type),
+        None => Err(anyhow!("no supported data type in {accepted:#x}")),
+    }
+    .and_then(|info| {
+        // frontends allocating ahead may not be able to honor the choice
+        let data_type = info.data_type();
+        if accepted & (1 << data_type) != 0 {
+            return Ok(info);
+        }
+        remove_buffer(info.user_handle);
+        Err(anyhow!("data type {data_type} not accepted by consumer"))
+    });
+    let info = match info {
+        Ok(info) => info,
+        Err(e) => {
+            missing_buffers.fetch_add(1, Ordering::AcqRel);
+            for data in datas {
+                data.fd = -1;
+                data.data = ptr::null_mut();
+                data.type_ = libspa_sys::SPA_DATA_Invalid;
+            }
+            // frontends short of buffers still export frames with the
+            // others, whether there are any is known once streaming
+            invalid_buffers.fetch_add(1, Ordering::AcqRel);
+            let error = format!("failed to add buffer: {e:#}");
+            warn!("{error}, mark invalid");
+            report_error(stream, last_error, error, false);
+            return;
         }
-        return;
-    };
-
-    let data_type = if info.is_dma_buf {
-        libspa_sys::SPA_DATA_DmaBuf
-    } else {
-        libspa_sys::SPA_DATA_MemFd
     };
 
+    let data_type = info.data_type();
     assert_eq!(spa_buffer.n_datas, info.planes.len() as _);
     for (data, plane) in datas.iter_mut().zip(&info.planes) {
         let chunk = &mut *data.chunk;
@@ -523,6 +1253,7 @@ unsafe fn on_add_buffer(
     }
     *(user_data as *mut BufferUserHandle) = info.user_handle;
     pw_buffer.user_data = user_data as _;
+    valid_buffers.fetch_add(1, Ordering::AcqRel);
 
     debug!("added buffer");
 }
@@ -530,21 +1261,40 @@ unsafe fn on_add_buffer(
 unsafe fn on_remove_buffer(
     buffer: *mut pw::sys::pw_buffer,
     remove_buffer: &Box<dyn Fn(BufferUserHandle) + Send>,
+    valid_buffers: &AtomicU32,
+    invalid_buffers: &AtomicU32,
+    keepalive: &Mutex<KeepaliveState>,
+    in_flight: &Mutex<HashSet<BufferHandle>>,
+    dequeued: &Mutex<HashSet<BufferHandle>>,
 ) {
     debug!("remove buffer");
     let mut buffer = ptr::NonNull::new(buffer).unwrap();
+    let handle = BufferHandle::from(buffer);
+    keepalive.lock().unwrap().remove_buffer(handle);
+    in_flight.lock().unwrap().remove(&handle);
+    dequeued.lock().unwrap().remove(&handle);
 
     let pw_buffer = buffer.as_mut();
     let user_data = pw_buffer.user_data as *mut BufferUserHandle;
     if user_data.is_null() {
+        invalid_buffers.fetch_sub(1, Ordering::AcqRel);
         return;
     }
     remove_buffer(*user_data);
     dealloc(user_data as _, Layout::new::<BufferUserHandle>());
+    valid_buffers.fetch_sub(1, Ordering::AcqRel);
+}
+
+fn node_id(stream: &pw::stream::StreamRef) -> Option<u32> {
+    match stream.node_id() {
+        spa_sys::SPA_ID_INVALID => None,
+        id => Some(id),
+    }
 }
 
+/// Current `CLOCK_MONOTONIC` time in nanoseconds, the clock of buffer pts
 #[inline]
-fn get_pts_nanos() -> i64 {
+pub fn get_pts_nanos() -> i64 {
     let mut ts = libc::timespec {
         tv_sec: 0,
         tv_nsec: 0,
@@ -606,11 +1356,68 @@ unsafe fn fill_cursor_meta(
     }
 }
 
+/// Empty regions tell consumers not to crop
+fn fill_crop_meta(meta: &mut libspa_sys::spa_meta_region, crop: Option<Region>) {
+    let crop = crop.unwrap_or(Region {
+        x: 0,
+        y: 0,
+        width: 0,
+        height: 0,
+    });
+    meta.region.position.x = crop.x as _;
+    meta.region.position.y = crop.y as _;
+    meta.region.size.width = crop.width;
+    meta.region.size.height = crop.height;
+}
+
+/// Regions end at the first empty one or the end of the meta, damage not
+/// fitting is merged into its bounding box
+fn fill_damage_meta(meta: &mut [libspa_sys::spa_meta_region], damage: &[Region]) {
+    let damage = damage.iter().filter(|v| v.width > 0 && v.height > 0);
+    let regions = if damage.clone().count() > meta.len() {
+        let (x0, y0, x1, y1) = damage.fold((u32::MAX, u32::MAX, 0, 0), |(x0, y0, x1, y1), v| {
+            (
+                x0.min(v.x),
+                y0.min(v.y),
+                x1.max(v.x + v.width),
+                y1.max(v.y + v.height),
+            )
+        });
+        vec![Region {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        }]
+    } else {
+        damage.copied().collect()
+    };
+    for (i, meta) in meta.iter_mut().enumerate() {
+        let region = regions.get(i).copied().unwrap_or(Region {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        });
+        meta.region.position.x = region.x as _;
+        meta.region.position.y = region.y as _;
+        meta.region.size.width = region.width;
+        meta.region.size.height = region.height;
+        if regions.len() <= i {
+            break;
+        }
+    }
+}
+
 unsafe fn on_process_buffer(
     stream: &pw::stream::StreamRef,
     data: &mut StreamData,
     buffer: BufferHandle,
     user_process: &ProcessBufferCb,
+    stats: &StatsRecorder,
+    transform: u32,
+    keepalive: bool,
+    in_flight: &Mutex<HashSet<BufferHandle>>,
 ) {
     let pw_buffer = ptr::NonNull::from(buffer).as_mut();
 
@@ -624,6 +1431,19 @@ unsafe fn on_process_buffer(
         libspa_sys::SPA_META_Cursor,
     );
 
+    let video_transform =
+        spa_buffer_find_meta_data::<u32>(pw_buffer.buffer, SPA_META_VIDEO_TRANSFORM);
+
+    let video_crop = spa_buffer_find_meta_data::<libspa_sys::spa_meta_region>(
+        pw_buffer.buffer,
+        libspa_sys::SPA_META_VideoCrop,
+    );
+
+    let video_damage = spa_buffer_find_meta_slice::<libspa_sys::spa_meta_region>(
+        pw_buffer.buffer,
+        libspa_sys::SPA_META_VideoDamage,
+    );
+
     let user_data = pw_buffer.user_data as *mut BufferUserHandle;
     if user_data.is_null() {
         error!("buffer broken no user data");
@@ -631,93 +1451,348 @@ unsafe fn on_process_buffer(
     };
 
     let mut cursor_meta_filled = false;
-    user_process(
-        *user_data,
-        AddBufferMetaCbs {
-            add_cursor: if cursor.is_null() {
-                None
-            } else {
-                Some(Box::new(|info| {
-                    fill_cursor_meta(&mut data.cursor_id, cursor, Some(info));
-                    cursor_meta_filled = true;
-                }))
+    let mut crop = None;
+    let mut damage = None;
+    let mut pts = None;
+    let mut seq = None;
+    if keepalive {
+        // buffer still holds the last frame, its cursor and crop
+        cursor_meta_filled = true;
+    } else {
+        let start = Instant::now();
+        user_process(
+            *user_data,
+            AddBufferMetaCbs {
+                add_cursor: if cursor.is_null() {
+                    None
+                } else {
+                    Some(Box::new(|info| {
+                        fill_cursor_meta(&mut data.cursor_id, cursor, Some(info));
+                        cursor_meta_filled = true;
+                    }))
+                },
+                add_crop: if video_crop.is_null() {
+                    None
+                } else {
+                    Some(Box::new(|v| crop = Some(v)))
+                },
+                add_damage: if video_damage.is_empty() {
+                    None
+                } else {
+                    Some(Box::new(|v| damage = Some(v.to_vec())))
+                },
+                set_pts: if header.is_null() {
+                    None
+                } else {
+                    Some(Box::new(|v| pts = Some(v)))
+                },
+                set_seq: if header.is_null() {
+                    None
+                } else {
+                    Some(Box::new(|v| seq = Some(v)))
+                },
             },
-        },
-    );
+        );
+        stats.record_copy_wait(start.elapsed());
+    }
+
+    // frames missed since the last one leave a gap in sequence numbers
+    let snapshot = stats.snapshot();
+    let missed = snapshot.missed.saturating_sub(data.missed);
+    data.missed = snapshot.missed;
+    data.seq += missed;
 
     if !header.is_null() {
         let header = &mut *header;
-        header.flags = 0;
-        header.pts = get_pts_nanos();
+        header.flags = if missed > 0 {
+            libspa_sys::SPA_META_HEADER_FLAG_DISCONT
+        } else {
+            0
+        };
+        header.pts = pts.unwrap_or_else(get_pts_nanos);
         // header.pts = -1;
         header.offset = 0;
-        header.seq = data.seq;
+        header.seq = seq.unwrap_or(data.seq);
         header.dts_offset = 0;
     }
     data.seq += 1;
 
+    if !video_transform.is_null() {
+        *video_transform = transform;
+    }
+
+    if !video_crop.is_null() && !keepalive {
+        fill_crop_meta(&mut *video_crop, crop);
+    }
+
+    if keepalive {
+        // nothing changed since the frame the buffer still holds
+        fill_damage_meta(video_damage, &[]);
+    } else {
+        let whole = Region {
+            x: 0,
+            y: 0,
+            width: data.width,
+            height: data.height,
+        };
+        fill_damage_meta(video_damage, damage.as_deref().unwrap_or(&[whole]));
+    }
+
     if !cursor.is_null() && !cursor_meta_filled {
         fill_cursor_meta(&mut data.cursor_id, cursor, None);
     }
 
     pw_buffer.size = 1;
 
+    in_flight.lock().unwrap().insert(buffer);
     stream.queue_raw_buffer(pw_buffer);
 }
 
+/// Publishes frame statistics as node properties for tools like pw-dump
+fn update_stats_props(stream: &pw::stream::StreamRef, stats: &StreamStats, fps: Option<f64>) {
+    let frames = (stats.process.count + stats.missed).to_string();
+    let missed = stats.missed.to_string();
+    let copy_wait = stats.copy_wait.avg().as_micros().to_string();
+    let mut props = properties! {
+        PROP_FRAMES => frames.as_str(),
+        PROP_MISSED_FRAMES => missed.as_str(),
+        PROP_COPY_WAIT_US => copy_wait.as_str(),
+    };
+    if let Some(fps) = fps {
+        props.insert(PROP_FPS, format!("{fps:.1}"));
+    }
+    if let Some((width, height)) = stats.size {
+        props.insert(PROP_RESOLUTION, format!("{width}x{height}"));
+    }
+    if let Some(modifier) = stats.modifier {
+        props.insert(PROP_MODIFIER, format!("{modifier:#x}"));
+    }
+    unsafe {
+        pw::sys::pw_stream_update_properties(stream.as_raw_ptr(), props.dict().as_raw_ptr());
+    }
+}
+
+/// Advertises time frames take from present until they are queued as
+/// latency of the captured source, consumers like OBS compensate for it
+fn update_latency_param(stream: &pw::stream::StreamRef, latency: Duration) -> Result<()> {
+    trace!("advertising latency {latency:?}");
+    let ns = latency.as_nanos() as i64;
+    // capture latency accumulates downstream from sources
+    let info = LatencyInfo {
+        direction: spa_sys::SPA_DIRECTION_INPUT,
+        min_ns: ns,
+        max_ns: ns,
+        ..Default::default()
+    };
+    let param = spa_pod_serialize(&info.to_value())?;
+    let mut params = [Pod::from_bytes(&param).ok_or(anyhow!("not a valid Pod"))?];
+    stream.update_params(&mut params)?;
+    Ok(())
+}
+
+/// Node properties of the captured window, consumers listing nodes show the
+/// title along with the app name
+pub fn window_props(title: Option<&str>, app_id: Option<&str>) -> Vec<(String, String)> {
+    let mut props = vec![];
+    if let Some(title) = title {
+        let description = format!("{} - {} (pw-capture)", get_app_name(), title);
+        props.push((pw::keys::MEDIA_NAME.to_string(), title.to_owned()));
+        props.push((pw::keys::NODE_DESCRIPTION.to_string(), description));
+        props.push((PROP_WINDOW_TITLE.to_owned(), title.to_owned()));
+    }
+    if let Some(app_id) = app_id {
+        props.push((PROP_WINDOW_APP_ID.to_owned(), app_id.to_owned()));
+    }
+    props
+}
+
+fn new_pw_stream(
+    core: &pw::core::Core,
+    node_class: &NodeClass,
+    extra_props: &[(String, String)],
+) -> Result<pw::stream::Stream> {
+    let name = format!("{} (pw-capture)", get_app_name());
+    let mut props = properties! {
+        *pw::keys::MEDIA_TYPE => "Video",
+        *pw::keys::MEDIA_CATEGORY => "Capture",
+        *pw::keys::MEDIA_ROLE => node_class.media_role.as_str(),
+        *pw::keys::MEDIA_CLASS => node_class.media_class.as_str(),
+        *pw::keys::MEDIA_SOFTWARE => "pw-capture",
+        *pw::keys::NODE_WANT_DRIVER => "false",
+        *pw::keys::NODE_DESCRIPTION => name.as_str(),
+    };
+    for (key, value) in app_props().iter().chain(extra_props) {
+        props.insert(key.as_str(), value.as_str());
+    }
+    let stream = pw::stream::Stream::new(core, name.as_str(), props)?;
+    Ok(stream)
+}
+
 impl StreamImpl {
     pub(crate) fn new(
         core: &pw::core::Core,
         info: StreamInfo,
         on_terminate: Box<dyn FnOnce()>,
     ) -> Result<Self> {
-        let name = format!("{} (pw-capture)", get_app_name());
-        let stream = pw::stream::Stream::new(
-            core,
-            name.as_str(),
-            properties! {
-                *pw::keys::MEDIA_TYPE => "Video",
-                *pw::keys::MEDIA_CATEGORY => "Capture",
-                *pw::keys::MEDIA_ROLE => "Screen",
-                *pw::keys::MEDIA_CLASS => "Video/Source",
-                *pw::keys::MEDIA_SOFTWARE => "pw-capture",
-                *pw::keys::NODE_WANT_DRIVER => "false",
-                *pw::keys::NODE_DESCRIPTION => name.as_str(),
-            },
-        )?;
+        let stream = new_pw_stream(core, &info.node_class, &info.props)?;
 
-        let (buffer_sender, buffer_receiver) = bounded::<BufferHandle>(MAX_PROCESS_BUFFERS);
+        let (buffer_sender, buffer_receiver) = bounded::<QueuedBuffer>(MAX_PROCESS_BUFFERS);
 
+        let (width, height) = info.scale.apply(info.width, info.height);
+        let mut enum_formats = info.enum_formats;
+        info.format_preference.sort(&mut enum_formats);
         let inner = StreamImplInner {
             stream,
+            node_class: info.node_class,
+            props: info.props,
             listener: None,
-            enum_formats: info.enum_formats,
+            width,
+            height,
+            scale: info.scale,
+            enum_formats,
+            format_preference: info.format_preference,
+            colorimetry: info.colorimetry,
+            transform: Arc::new(AtomicU32::new(info.transform.into())),
             max_buffers: info.max_buffers,
+            missing_buffers: Default::default(),
+            valid_buffers: Default::default(),
+            invalid_buffers: Default::default(),
+            last_error: Default::default(),
             buffer_sender,
+            on_demand: on_demand_enabled(),
+            frame_requested: Default::default(),
+            limiter: CaptureLimiter::global().register(width, height),
+            stats: Arc::new(StatsRecorder::from_env()),
+            // consumers of on-demand streams pull frames at their own pace
+            keepalive_interval: keepalive_interval().filter(|_| !on_demand_enabled()),
+            keepalive: Default::default(),
+            suspended: false,
+            window_suspended: false,
+            in_flight: Default::default(),
+            dequeued: Default::default(),
+            fixated: Cell::new(None),
+            draining: false,
+            callbacks: Rc::new(StreamCallbacks {
+                fixate_format: info.fixate_format,
+                add_buffer: info.add_buffer,
+                remove_buffer: info.remove_buffer,
+                process_buffer: info.process_buffer,
+                state_changed: info.state_changed,
+            }),
             on_terminate: Some(on_terminate),
         };
         let stream_impl = StreamImpl {
             inner: Arc::new(RefCell::new(inner)),
         };
+        stream_impl.connect(buffer_receiver)?;
+
+        Ok(stream_impl)
+    }
+
+    /// Recreates the PipeWire stream on `core`, e.g. after the daemon restarted
+    pub(crate) fn reconnect(&self, core: &pw::core::Core) -> Result<()> {
+        debug!("reconnect stream");
+        let stream = {
+            let inner = self.inner.borrow();
+            new_pw_stream(core, &inner.node_class, &inner.props)?
+        };
+        let (buffer_sender, buffer_receiver) = bounded::<QueuedBuffer>(MAX_PROCESS_BUFFERS);
+
+        let (old_stream, old_listener) = {
+            let mut inner = self.inner.borrow_mut();
+            inner.buffer_sender = buffer_sender;
+            *inner.keepalive.lock().unwrap() = Default::default();
+            inner.in_flight.lock().unwrap().clear();
+            inner.dequeued.lock().unwrap().clear();
+            inner.fixated.set(None);
+            inner.suspended = false;
+            (
+                mem::replace(&mut inner.stream, stream),
+                inner.listener.take(),
+            )
+        };
+        // buffers are removed on disconnect, keep listener till then
+        let _ = old_stream.disconnect();
+        drop(old_stream);
+        drop(old_listener);
+
+        self.connect(buffer_receiver)
+    }
+
+    /// Disconnects the stream until reconnected, its buffers get removed and
+    /// the node goes away
+    pub(crate) fn suspend(&self) {
+        debug!("suspend stream");
+        {
+            let mut inner = self.inner.borrow_mut();
+            // frames queued meanwhile are dropped
+            inner.buffer_sender = bounded::<QueuedBuffer>(0).0;
+            *inner.keepalive.lock().unwrap() = Default::default();
+            inner.in_flight.lock().unwrap().clear();
+            inner.dequeued.lock().unwrap().clear();
+            inner.fixated.set(None);
+            inner.suspended = true;
+        }
+        // buffers are removed on disconnect, keep listener till then
+        let _ = self.inner.borrow().stream.disconnect();
+        self.inner.borrow_mut().listener.take();
+    }
+
+    pub(crate) fn is_suspended(&self) -> bool {
+        self.inner.borrow().suspended
+    }
 
-        let listener = stream_impl
+    fn connect(&self, buffer_receiver: Receiver<QueuedBuffer>) -> Result<()> {
+        let callbacks = self.inner.borrow().callbacks.clone();
+        let stats = self.inner.borrow().stats.clone();
+        let missing_buffers = self.inner.borrow().missing_buffers.clone();
+        let valid_buffers = self.inner.borrow().valid_buffers.clone();
+        let invalid_buffers = self.inner.borrow().invalid_buffers.clone();
+        let last_error = self.inner.borrow().last_error.clone();
+        let transform = self.inner.borrow().transform.clone();
+        let on_demand = self.inner.borrow().on_demand;
+        let frame_requested = self.inner.borrow().frame_requested.clone();
+        let keepalive = self.inner.borrow().keepalive.clone();
+        let in_flight = self.inner.borrow().in_flight.clone();
+        let dequeued = self.inner.borrow().dequeued.clone();
+
+        let listener = self
             .inner
             .borrow_mut()
             .stream
             .add_local_listener_with_user_data(StreamData {
                 seq: 0,
+                missed: 0,
                 cursor_id: 1,
+                width: 0,
+                height: 0,
             })
             .state_changed({
                 let buffer_receiver = buffer_receiver.clone();
+                let callbacks = callbacks.clone();
+                let keepalive = keepalive.clone();
+                let valid_buffers = valid_buffers.clone();
+                let invalid_buffers = invalid_buffers.clone();
+                let last_error = last_error.clone();
                 move |stream, _data, old, new| {
                     info!("stream state changed: {:?} -> {:?}", old, new);
+                    (callbacks.state_changed)((&new).into(), node_id(stream));
                     match new {
+                        // the whole pool got added by now
+                        pw::stream::StreamState::Streaming
+                            if valid_buffers.load(Ordering::Acquire) == 0
+                                && invalid_buffers.load(Ordering::Acquire) > 0 =>
+                        {
+                            let error = last_error.lock().unwrap().clone().unwrap_or_default();
+                            error!("{error}, no buffer to export frames with");
+                            report_error(stream, &last_error, error, true);
+                        }
                         pw::stream::StreamState::Paused => {
                             let _ = stream.flush(false);
                             for _ in buffer_receiver.try_iter() {
                                 // drain buffer channel, in case buffer was not processed
                             }
+                            keepalive.lock().unwrap().forget_frame();
                         }
                         pw::stream::StreamState::Error(e) => error!("stream error: {}", e),
                         _ => (),
@@ -725,64 +1800,211 @@ impl StreamImpl {
                 }
             })
             .param_changed({
-                let stream_impl = stream_impl.clone();
-                move |_stream, _data, id, param| unsafe {
-                    on_param_changed(
-                        &stream_impl.inner.borrow(),
-                        id,
-                        param,
-                        info.width,
-                        info.height,
-                        &info.fixate_format,
-                    )
+                let stream_impl = self.clone();
+                let callbacks = callbacks.clone();
+                move |_stream, data, id, param| unsafe {
+                    let inner = stream_impl.inner.borrow();
+                    data.width = inner.width;
+                    data.height = inner.height;
+                    on_param_changed(&inner, id, param, &callbacks.fixate_format)
                 }
             })
-            .add_buffer(move |_stream, _data, buffer| unsafe {
-                on_add_buffer(buffer, &info.add_buffer)
+            .add_buffer({
+                let callbacks = callbacks.clone();
+                let valid_buffers = valid_buffers.clone();
+                let invalid_buffers = invalid_buffers.clone();
+                move |stream, _data, buffer| unsafe {
+                    on_add_buffer(
+                        stream,
+                        buffer,
+                        &callbacks.add_buffer,
+                        &callbacks.remove_buffer,
+                        &missing_buffers,
+                        &valid_buffers,
+                        &invalid_buffers,
+                        &last_error,
+                    )
+                }
             })
-            .remove_buffer(move |_stream, _data, buffer| unsafe {
-                on_remove_buffer(buffer, &info.remove_buffer)
+            .remove_buffer({
+                let callbacks = callbacks.clone();
+                let keepalive = keepalive.clone();
+                let in_flight = in_flight.clone();
+                let dequeued = dequeued.clone();
+                move |_stream, _data, buffer| unsafe {
+                    on_remove_buffer(
+                        buffer,
+                        &callbacks.remove_buffer,
+                        &valid_buffers,
+                        &invalid_buffers,
+                        &keepalive,
+                        &in_flight,
+                        &dequeued,
+                    )
+                }
             })
             .process(move |stream, data| unsafe {
-                if let Ok(buffer) = buffer_receiver.try_recv() {
-                    on_process_buffer(stream, data, buffer, &info.process_buffer);
-                } else {
-                    warn!("unscheduled process call");
+                match buffer_receiver.try_recv() {
+                    // removed since dequeued, e.g. on re-fixation
+                    Ok(queued) if !dequeued.lock().unwrap().remove(&queued.buffer) => {
+                        debug!("{:?} removed, dropping its frame", queued.buffer)
+                    }
+                    Ok(queued) => {
+                        let transform = transform.load(Ordering::Acquire);
+                        on_process_buffer(
+                            stream,
+                            data,
+                            queued.buffer,
+                            &callbacks.process_buffer,
+                            &stats,
+                            transform,
+                            queued.keepalive,
+                            &in_flight,
+                        );
+                        keepalive
+                            .lock()
+                            .unwrap()
+                            .record_frame(queued.buffer, Instant::now());
+                        if !queued.keepalive {
+                            stats.record_process(queued.queued.elapsed());
+                            stats.record_queued(queued.buffer, Instant::now());
+                        }
+                    }
+                    Err(TryRecvError::Empty) if !on_demand => warn!("unscheduled process call"),
+                    // draining streams get cycles triggered without frames
+                    _ => (),
+                }
+                if on_demand {
+                    // consumer pulled, copy on next present
+                    frame_requested.store(true, Ordering::Release);
                 }
             })
             .register()?;
 
-        let mut params = vec![];
-        for enum_format in &stream_impl.inner.borrow().enum_formats {
-            params.push(
-                build_format(
-                    info.width,
-                    info.height,
-                    &enum_format.formats,
-                    &enum_format.modifiers,
-                    false,
-                )
-                .unwrap(),
-            )
-        }
+        let params = self.inner.borrow().enum_format_params();
         let mut params = params
             .iter()
             .map(|p| Pod::from_bytes(p).expect("not a valid Pod"))
             .collect::<Vec<_>>();
 
-        stream_impl.inner.borrow().stream.connect(
+        let mut flags =
+            pw::stream::StreamFlags::ALLOC_BUFFERS | pw::stream::StreamFlags::RT_PROCESS;
+        if !on_demand {
+            flags |= pw::stream::StreamFlags::DRIVER | pw::stream::StreamFlags::TRIGGER;
+        }
+        // reconnected while the window is hidden
+        if self.inner.borrow().window_suspended {
+            flags |= pw::stream::StreamFlags::INACTIVE;
+        }
+        self.inner.borrow().stream.connect(
             spa::utils::Direction::Output,
             None,
-            pw::stream::StreamFlags::DRIVER
-                | pw::stream::StreamFlags::ALLOC_BUFFERS
-                | pw::stream::StreamFlags::RT_PROCESS
-                | pw::stream::StreamFlags::TRIGGER,
+            flags,
             &mut params,
         )?;
 
-        stream_impl.inner.borrow_mut().listener = Some(listener);
+        self.inner.borrow_mut().listener = Some(listener);
 
-        Ok(stream_impl)
+        Ok(())
+    }
+
+    /// Timer re-queuing the last frame while the app is not presenting, `None`
+    /// if keepalive is disabled
+    pub(crate) fn add_keepalive_timer<'a>(
+        &self,
+        loop_: &'a pw::loop_::LoopRef,
+    ) -> Option<pw::loop_::TimerSource<'a>> {
+        let interval = self.inner.borrow().keepalive_interval?;
+        let inner_weak = Arc::downgrade(&self.inner);
+        let timer = loop_.add_timer(move |_| {
+            if let Some(inner) = inner_weak.upgrade() {
+                if let Err(e) = (StreamImpl { inner }).keepalive(interval) {
+                    debug!("failed to queue keepalive frame: {e:?}");
+                }
+            }
+        });
+        let _ = timer.update_timer(Some(interval), Some(interval));
+        Some(timer)
+    }
+
+    /// Timer publishing frame statistics as node properties, also while the
+    /// app is not presenting
+    pub(crate) fn add_stats_timer<'a>(
+        &self,
+        loop_: &'a pw::loop_::LoopRef,
+    ) -> pw::loop_::TimerSource<'a> {
+        let inner_weak = Arc::downgrade(&self.inner);
+        let fps = RefCell::new(FpsCounter::default());
+        let latency = RefCell::new(LatencyTracker::default());
+        let timer = loop_.add_timer(move |_| {
+            let Some(inner) = inner_weak.upgrade() else {
+                return;
+            };
+            let inner = inner.borrow();
+            if let pw::stream::StreamState::Unconnected = inner.stream.state() {
+                return;
+            }
+            let stats = inner.stats.snapshot();
+            let fps = fps.borrow_mut().update(stats.process.count, Instant::now());
+            update_stats_props(&inner.stream, &stats, fps);
+            if let Some(latency) = latency.borrow_mut().update(&stats.latency) {
+                if let Err(e) = update_latency_param(&inner.stream, latency) {
+                    debug!("failed to advertise latency: {e:?}");
+                }
+            }
+        });
+        let _ = timer.update_timer(Some(STATS_PROPS_INTERVAL), Some(STATS_PROPS_INTERVAL));
+        timer
+    }
+
+    /// Re-queues the buffer of the last frame once no frame was queued for
+    /// `interval`
+    fn keepalive(&self, interval: Duration) -> Result<()> {
+        let inner = self.inner.borrow();
+        match inner.stream.state() {
+            pw::stream::StreamState::Streaming if !inner.draining => (),
+            _ => return Ok(()),
+        }
+        if !inner.stream.is_driving() {
+            return Ok(());
+        }
+        let mut keepalive = inner.keepalive.lock().unwrap();
+        let last = match keepalive.due(interval, Instant::now()) {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        // buffers come back from consumers in any order, others are held for
+        // the frontend's next frames
+        loop {
+            let buffer = match ptr::NonNull::new(unsafe { inner.stream.dequeue_raw_buffer() }) {
+                Some(v) => BufferHandle::from(v),
+                None => {
+                    trace!("last frame still in use by consumer");
+                    return Ok(());
+                }
+            };
+            inner.in_flight.lock().unwrap().remove(&buffer);
+            if buffer == last {
+                break;
+            }
+            keepalive.hold(buffer);
+        }
+        let queued = QueuedBuffer {
+            buffer: last,
+            queued: Instant::now(),
+            keepalive: true,
+        };
+        inner.dequeued.lock().unwrap().insert(last);
+        if inner.buffer_sender.try_send(queued).is_err() {
+            // frames are pending anyway
+            inner.dequeued.lock().unwrap().remove(&last);
+            keepalive.hold(last);
+            return Ok(());
+        }
+        drop(keepalive);
+        trace!("queue keepalive frame");
+        inner.stream.trigger_process()?;
+        Ok(())
     }
 
     pub(crate) fn attach<'a>(
@@ -803,3 +2025,30 @@ impl StreamImpl {
         receiver
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crop_meta() {
+        let mut meta: libspa_sys::spa_meta_region = unsafe { mem::zeroed() };
+        let crop = Region {
+            x: 240,
+            y: 0,
+            width: 1440,
+            height: 1080,
+        };
+        fill_crop_meta(&mut meta, Some(crop));
+        assert_eq!((meta.region.position.x, meta.region.position.y), (240, 0));
+        assert_eq!(
+            (meta.region.size.width, meta.region.size.height),
+            (1440, 1080)
+        );
+
+        // stale crop of the buffer's previous frame gets cleared
+        fill_crop_meta(&mut meta, None);
+        assert_eq!((meta.region.position.x, meta.region.position.y), (0, 0));
+        assert_eq!((meta.region.size.width, meta.region.size.height), (0, 0));
+    }
+}
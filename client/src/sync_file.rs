@@ -0,0 +1,77 @@
+//! Attaching GPU fences to DMA-BUFs
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+
+use crate::settings::setting;
+
+const DMA_BUF_BASE: u8 = b'b';
+const DMA_BUF_SYNC_WRITE: u32 = 2 << 0;
+
+#[repr(C)]
+struct DmaBufImportSyncFile {
+    flags: u32,
+    fd: i32,
+}
+
+const fn iow(ty: u8, nr: u8, size: usize) -> u64 {
+    (1 << 30) | ((size as u64) << 16) | ((ty as u64) << 8) | nr as u64
+}
+
+const DMA_BUF_IOCTL_IMPORT_SYNC_FILE: u64 =
+    iow(DMA_BUF_BASE, 3, std::mem::size_of::<DmaBufImportSyncFile>());
+
+static SYNC_FILE_ENABLED: Lazy<AtomicBool> = Lazy::new(|| {
+    let enabled = !matches!(setting("PW_CAPTURE_SYNC_FILE").as_deref(), Some("0"));
+    debug!("sync file enabled: {}", enabled);
+    AtomicBool::new(enabled)
+});
+
+/// Whether producers should attach sync files to exported buffers, turns
+/// `false` once the kernel turned out to not support it
+pub fn sync_file_enabled() -> bool {
+    SYNC_FILE_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Adds `sync_file` as write fence to `dma_buf`, `sync_file` is not consumed
+pub fn dma_buf_import_sync_file(dma_buf: RawFd, sync_file: RawFd) -> io::Result<()> {
+    let arg = DmaBufImportSyncFile {
+        flags: DMA_BUF_SYNC_WRITE,
+        fd: sync_file,
+    };
+    let res = unsafe { libc::ioctl(dma_buf, DMA_BUF_IOCTL_IMPORT_SYNC_FILE as _, &arg) };
+    if res == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::ENOTTY) {
+        warn!("kernel does not support attaching sync file to DMA-BUF, disabled");
+        SYNC_FILE_ENABLED.store(false, Ordering::Relaxed);
+    }
+    Err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ioctl_number() {
+        assert_eq!(DMA_BUF_IOCTL_IMPORT_SYNC_FILE, 0x40086203);
+    }
+
+    #[test]
+    fn import_non_dma_buf() {
+        let mut fds = [-1; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        assert!(dma_buf_import_sync_file(fds[0], fds[1]).is_err());
+        unsafe {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+        }
+    }
+}
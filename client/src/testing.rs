@@ -0,0 +1,675 @@
+//! Mock consumer for exercising capture streams without a compositor
+
+use crate::*;
+
+use core::mem;
+use core::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use log::{debug, warn};
+use pipewire as pw;
+use pw::properties::properties;
+
+// cursor meta sizes consumers accept, in bitmap dimensions
+const MIN_CURSOR_META: usize = 1;
+const MAX_CURSOR_META: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub struct ConsumerOptions {
+    pub formats: Vec<Format>,
+    /// DMA-BUF modifiers the consumer imports, memfd buffers if empty
+    pub modifiers: Vec<u64>,
+    /// Takes buffers of formats with a modifier as DMA-BUF, only memfd
+    /// otherwise like consumers reading frames on CPU
+    pub dma_buf: bool,
+    pub max_width: u32,
+    pub max_height: u32,
+}
+
+impl Default for ConsumerOptions {
+    fn default() -> Self {
+        Self {
+            formats: vec![Format::BGRx, Format::BGRA, Format::RGBx, Format::RGBA],
+            modifiers: vec![],
+            dma_buf: true,
+            max_width: 8192,
+            max_height: 8192,
+        }
+    }
+}
+
+/// Format the consumer and capture node agreed on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NegotiatedFormat {
+    pub format: Format,
+    pub modifier: Option<u64>,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConsumedPlane {
+    pub offset: u32,
+    pub size: u32,
+    pub stride: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsumedFrame {
+    /// `SPA_DATA_*` type of the first plane
+    pub data_type: u32,
+    pub planes: Vec<ConsumedPlane>,
+    /// Sequence number and flags of the header meta, if negotiated
+    pub seq: Option<u64>,
+    pub flags: u32,
+    /// Id of the cursor meta, 0 if the cursor is hidden or not negotiated
+    pub cursor_id: u32,
+}
+
+#[derive(Clone, Debug)]
+pub enum ConsumerEvent {
+    State(StreamState),
+    Format(NegotiatedFormat),
+    Frame(ConsumedFrame),
+}
+
+pub struct MockConsumer {
+    quit_sender: pw::channel::Sender<()>,
+    receiver: Receiver<ConsumerEvent>,
+    thread: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl MockConsumer {
+    /// Links a consumer to capture node `node_id` on the remote selected by
+    /// [`Remote::from_env`]
+    pub fn connect(node_id: u32, options: ConsumerOptions) -> Result<Self> {
+        let (quit_sender, quit_receiver) = pw::channel::channel::<()>();
+        let (sender, receiver) = unbounded();
+        let (done_sender, done_receiver) = bounded(1);
+        let thread = thread::spawn(move || {
+            consumer_thread(node_id, options, sender, done_sender, quit_receiver)
+        });
+
+        if done_receiver.recv().is_err() {
+            return Err(match thread.join() {
+                Ok(Err(e)) => e,
+                _ => anyhow!("consumer thread exited"),
+            });
+        }
+
+        Ok(Self {
+            quit_sender,
+            receiver,
+            thread: Some(thread),
+        })
+    }
+
+    /// Waits for the next event `f` maps to a value, skipping others
+    pub fn wait_for<T>(
+        &self,
+        timeout: Duration,
+        mut f: impl FnMut(ConsumerEvent) -> Option<T>,
+    ) -> Result<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let event = self
+                .receiver
+                .recv_timeout(remaining)
+                .map_err(|e| anyhow!("no matching consumer event: {e}"))?;
+            if let Some(value) = f(event) {
+                return Ok(value);
+            }
+        }
+    }
+
+    pub fn wait_state(&self, state: StreamState, timeout: Duration) -> Result<()> {
+        self.wait_for(timeout, |event| match event {
+            ConsumerEvent::State(v) if v == state => Some(()),
+            _ => None,
+        })
+    }
+
+    pub fn wait_format(&self, timeout: Duration) -> Result<NegotiatedFormat> {
+        self.wait_for(timeout, |event| match event {
+            ConsumerEvent::Format(format) => Some(format),
+            _ => None,
+        })
+    }
+
+    /// Collects `count` frames, failing if they do not arrive in `timeout`
+    pub fn wait_frames(&self, count: usize, timeout: Duration) -> Result<Vec<ConsumedFrame>> {
+        let mut frames = Vec::with_capacity(count);
+        while frames.len() < count {
+            frames.push(self.wait_for(timeout, |event| match event {
+                ConsumerEvent::Frame(frame) => Some(frame),
+                _ => None,
+            })?);
+        }
+        Ok(frames)
+    }
+}
+
+impl Drop for MockConsumer {
+    fn drop(&mut self) {
+        let _ = self.quit_sender.send(());
+        if let Some(th) = self.thread.take() {
+            let _ = th.join();
+        }
+    }
+}
+
+fn consumer_thread(
+    node_id: u32,
+    options: ConsumerOptions,
+    sender: Sender<ConsumerEvent>,
+    done_sender: Sender<()>,
+    quit_receiver: pw::channel::Receiver<()>,
+) -> Result<()> {
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = Remote::from_env().connect(&context)?;
+
+    let stream = pw::stream::Stream::new(
+        &core,
+        "pw-capture-mock-consumer",
+        properties! {
+            *pw::keys::MEDIA_TYPE => "Video",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "Screen",
+        },
+    )?;
+
+    let dma_buf = options.dma_buf;
+    let _listener = stream
+        .add_local_listener_with_user_data(sender)
+        .state_changed(|_stream, sender, old, new| {
+            debug!("consumer state changed: {:?} -> {:?}", old, new);
+            let _ = sender.send(ConsumerEvent::State((&new).into()));
+        })
+        .param_changed(move |stream, sender, id, param| {
+            let Some(param) = param else {
+                return;
+            };
+            if id != spa_sys::SPA_PARAM_Format {
+                return;
+            }
+            let format = match parse_format(param) {
+                Ok(Some(v)) => v,
+                // modifier gets fixated by the capture node first
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("consumer failed to parse format: {e:?}");
+                    return;
+                }
+            };
+            debug!("consumer format: {format:?}");
+            let params = build_consumer_params(format.modifier.is_some() && dma_buf);
+            let mut params = params
+                .iter()
+                .map(|p| Pod::from_bytes(p).expect("not a valid Pod"))
+                .collect::<Vec<_>>();
+            let _ = stream.update_params(&mut params);
+            let _ = sender.send(ConsumerEvent::Format(format));
+        })
+        .process(|stream, sender| unsafe {
+            let buffer = stream.dequeue_raw_buffer();
+            if buffer.is_null() {
+                return;
+            }
+            let frame = read_frame(buffer);
+            stream.queue_raw_buffer(buffer);
+            let _ = sender.send(ConsumerEvent::Frame(frame));
+        })
+        .register()?;
+
+    let param = build_consumer_format(&options)?;
+    let mut params = [Pod::from_bytes(&param).expect("not a valid Pod")];
+    stream.connect(
+        spa::utils::Direction::Input,
+        Some(node_id),
+        pw::stream::StreamFlags::AUTOCONNECT | pw::stream::StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    let _receiver = quit_receiver.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+
+    let _ = done_sender.send(());
+    mainloop.run();
+    let _ = stream.disconnect();
+
+    Ok(())
+}
+
+/// `None` while the modifier is left for the capture node to fixate
+fn parse_format(param: &Pod) -> Result<Option<NegotiatedFormat>> {
+    let value = spa_pod_deserialize(param.as_bytes())?;
+    let raw_info = VideoRawInfo::try_from(value.clone())?;
+    if raw_info.dont_fixate_modifier {
+        return Ok(None);
+    }
+    let Value::Object(obj) = value else {
+        unreachable!()
+    };
+    let size = obj.properties.iter().find_map(|prop| match prop.value {
+        Value::Rectangle(size) if prop.key == spa_sys::SPA_FORMAT_VIDEO_size => Some(size),
+        _ => None,
+    });
+    let size = size.ok_or(anyhow!("no size"))?;
+    Ok(Some(NegotiatedFormat {
+        format: raw_info.format,
+        modifier: raw_info.modifiers.first().copied(),
+        width: size.width,
+        height: size.height,
+    }))
+}
+
+fn build_consumer_format(options: &ConsumerOptions) -> Result<Vec<u8>> {
+    assert!(!options.formats.is_empty());
+
+    let mut properties = vec![
+        Property {
+            key: spa_sys::SPA_FORMAT_mediaType,
+            flags: PropertyFlags::empty(),
+            value: Value::Id(Id(spa_sys::SPA_MEDIA_TYPE_video)),
+        },
+        Property {
+            key: spa_sys::SPA_FORMAT_mediaSubtype,
+            flags: PropertyFlags::empty(),
+            value: Value::Id(Id(spa_sys::SPA_MEDIA_SUBTYPE_raw)),
+        },
+        Property {
+            key: spa_sys::SPA_FORMAT_VIDEO_format,
+            flags: PropertyFlags::empty(),
+            value: Value::Choice(ChoiceValue::Id(Choice(
+                ChoiceFlags::empty(),
+                ChoiceEnum::Enum {
+                    default: Id(options.formats[0].into()),
+                    alternatives: options.formats.iter().map(|&f| Id(f.into())).collect(),
+                },
+            ))),
+        },
+        Property {
+            key: spa_sys::SPA_FORMAT_VIDEO_size,
+            flags: PropertyFlags::empty(),
+            value: Value::Choice(ChoiceValue::Rectangle(Choice(
+                ChoiceFlags::empty(),
+                ChoiceEnum::Range {
+                    default: Rectangle {
+                        width: 320,
+                        height: 240,
+                    },
+                    min: Rectangle {
+                        width: 1,
+                        height: 1,
+                    },
+                    max: Rectangle {
+                        width: options.max_width,
+                        height: options.max_height,
+                    },
+                },
+            ))),
+        },
+        Property {
+            key: spa_sys::SPA_FORMAT_VIDEO_framerate,
+            flags: PropertyFlags::empty(),
+            value: Value::Choice(ChoiceValue::Fraction(Choice(
+                ChoiceFlags::empty(),
+                ChoiceEnum::Range {
+                    default: Fraction { num: 0, denom: 1 },
+                    min: Fraction { num: 0, denom: 1 },
+                    max: Fraction { num: 360, denom: 1 },
+                },
+            ))),
+        },
+    ];
+
+    if let Some(&default) = options.modifiers.first() {
+        properties.push(Property {
+            key: spa_sys::SPA_FORMAT_VIDEO_modifier,
+            flags: PropertyFlags::MANDATORY | PropertyFlags::DONT_FIXATE,
+            value: Value::Choice(ChoiceValue::Long(Choice(
+                ChoiceFlags::empty(),
+                ChoiceEnum::Enum {
+                    default: default as _,
+                    alternatives: options.modifiers.iter().map(|&m| m as _).collect(),
+                },
+            ))),
+        });
+    }
+
+    spa_pod_serialize(&Value::Object(Object {
+        type_: spa_sys::SPA_TYPE_OBJECT_Format,
+        id: spa_sys::SPA_PARAM_EnumFormat,
+        properties,
+    }))
+}
+
+fn cursor_meta_size(width: usize) -> i32 {
+    (mem::size_of::<spa_sys::spa_meta_cursor>()
+        + mem::size_of::<spa_sys::spa_meta_bitmap>()
+        + width * width * 4) as _
+}
+
+fn build_consumer_params(is_dma_buf: bool) -> Vec<Vec<u8>> {
+    let data_type_flags = if is_dma_buf {
+        1 << spa_sys::SPA_DATA_DmaBuf
+    } else {
+        (1 << spa_sys::SPA_DATA_MemFd) | (1 << spa_sys::SPA_DATA_MemPtr)
+    };
+    let buffers = Value::Object(Object {
+        type_: spa_sys::SPA_TYPE_OBJECT_ParamBuffers,
+        id: spa_sys::SPA_PARAM_Buffers,
+        properties: vec![Property {
+            key: spa_sys::SPA_PARAM_BUFFERS_dataType,
+            flags: PropertyFlags::empty(),
+            value: Value::Choice(ChoiceValue::Int(Choice(
+                ChoiceFlags::empty(),
+                ChoiceEnum::Flags {
+                    default: data_type_flags,
+                    flags: vec![],
+                },
+            ))),
+        }],
+    });
+
+    let meta_header = Value::Object(Object {
+        type_: spa_sys::SPA_TYPE_OBJECT_ParamMeta,
+        id: spa_sys::SPA_PARAM_Meta,
+        properties: vec![
+            Property {
+                key: spa_sys::SPA_PARAM_META_type,
+                flags: PropertyFlags::empty(),
+                value: Value::Id(Id(spa_sys::SPA_META_Header)),
+            },
+            Property {
+                key: spa_sys::SPA_PARAM_META_size,
+                flags: PropertyFlags::empty(),
+                value: Value::Int(mem::size_of::<spa_sys::spa_meta_header>() as _),
+            },
+        ],
+    });
+
+    let meta_cursor = Value::Object(Object {
+        type_: spa_sys::SPA_TYPE_OBJECT_ParamMeta,
+        id: spa_sys::SPA_PARAM_Meta,
+        properties: vec![
+            Property {
+                key: spa_sys::SPA_PARAM_META_type,
+                flags: PropertyFlags::empty(),
+                value: Value::Id(Id(spa_sys::SPA_META_Cursor)),
+            },
+            Property {
+                key: spa_sys::SPA_PARAM_META_size,
+                flags: PropertyFlags::empty(),
+                value: Value::Choice(ChoiceValue::Int(Choice(
+                    ChoiceFlags::empty(),
+                    ChoiceEnum::Range {
+                        default: cursor_meta_size(64),
+                        min: cursor_meta_size(MIN_CURSOR_META),
+                        max: cursor_meta_size(MAX_CURSOR_META),
+                    },
+                ))),
+            },
+        ],
+    });
+
+    [buffers, meta_header, meta_cursor]
+        .iter()
+        .map(|value| -> Result<Vec<u8>> { spa_pod_serialize(value) })
+        .collect::<Result<Vec<_>>>()
+        .unwrap_or_default()
+}
+
+unsafe fn read_frame(buffer: *mut pw::sys::pw_buffer) -> ConsumedFrame {
+    let spa_buffer = (*buffer).buffer;
+    let header =
+        spa_buffer_find_meta_data::<spa_sys::spa_meta_header>(spa_buffer, spa_sys::SPA_META_Header);
+    let cursor =
+        spa_buffer_find_meta_data::<spa_sys::spa_meta_cursor>(spa_buffer, spa_sys::SPA_META_Cursor);
+    let datas = slice::from_raw_parts((*spa_buffer).datas, (*spa_buffer).n_datas as _);
+
+    ConsumedFrame {
+        data_type: datas.first().map_or(spa_sys::SPA_DATA_Invalid, |d| d.type_),
+        planes: datas
+            .iter()
+            .map(|data| {
+                let chunk = &*data.chunk;
+                ConsumedPlane {
+                    offset: chunk.offset,
+                    size: chunk.size,
+                    stride: chunk.stride,
+                }
+            })
+            .collect(),
+        seq: header.as_ref().map(|h| h.seq),
+        flags: header.as_ref().map_or(0, |h| h.flags),
+        cursor_id: cursor.as_ref().map_or(0, |c| c.id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 64;
+    const HEIGHT: u32 = 48;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn memfd_stream_info() -> StreamInfo {
+        StreamInfo {
+            width: WIDTH,
+            height: HEIGHT,
+            enum_formats: vec![EnumFormatInfo {
+                formats: vec![Format::BGRx],
+                modifiers: vec![],
+            }],
+            format_preference: Default::default(),
+            colorimetry: Default::default(),
+            transform: Default::default(),
+            scale: Default::default(),
+            max_buffers: 4,
+            node_class: Default::default(),
+            props: vec![],
+            fixate_format: Box::new(|info| {
+                info.modifiers.is_empty().then_some(FixateFormat {
+                    modifier: None,
+                    num_planes: 1,
+                    mem_fd_fallback: false,
+                })
+            }),
+            add_buffer: Box::new(|_| unsafe {
+                let size = WIDTH * HEIGHT * 4;
+                let fd = libc::memfd_create(b"pw-capture-test\0".as_ptr() as _, libc::MFD_CLOEXEC);
+                if fd < 0 || libc::ftruncate(fd, size as _) < 0 {
+                    return Err(anyhow!("failed to create memfd"));
+                }
+                Ok(BufferInfo {
+                    is_dma_buf: false,
+                    planes: vec![BufferPlaneInfo {
+                        fd: fd as _,
+                        offset: 0,
+                        size,
+                        stride: WIDTH * 4,
+                    }],
+                    user_handle: BufferUserHandle::Test(fd),
+                })
+            }),
+            remove_buffer: Box::new(|handle| {
+                #[allow(irrefutable_let_patterns)]
+                if let BufferUserHandle::Test(fd) = handle {
+                    unsafe { libc::close(fd) };
+                }
+            }),
+            process_buffer: Box::new(|_, _| {}),
+            state_changed: Box::new(|_, _| {}),
+        }
+    }
+
+    /// Links a consumer of `options` to a stream of `info`
+    fn link(
+        client: &Client,
+        info: StreamInfo,
+        options: ConsumerOptions,
+    ) -> (Stream, MockConsumer, NegotiatedFormat) {
+        let stream = client.create_stream(info).unwrap();
+        let proxy = stream.proxy();
+
+        let deadline = Instant::now() + TIMEOUT;
+        let node_id = loop {
+            if let Some(node_id) = proxy.node_id().unwrap() {
+                break node_id;
+            }
+            assert!(Instant::now() < deadline, "node id not assigned");
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        let consumer = MockConsumer::connect(node_id, options).unwrap();
+        let format = consumer.wait_format(TIMEOUT).unwrap();
+        (stream, consumer, format)
+    }
+
+    /// Streams frames of `info` to a consumer of `options` until it got
+    /// `count` of them
+    fn consume(
+        client: &Client,
+        info: StreamInfo,
+        options: ConsumerOptions,
+        count: usize,
+    ) -> (NegotiatedFormat, Vec<ConsumedFrame>) {
+        let (stream, consumer, format) = link(client, info, options);
+        let proxy = stream.proxy();
+
+        let deadline = Instant::now() + TIMEOUT;
+        let mut frames = vec![];
+        while frames.len() < count {
+            assert!(Instant::now() < deadline, "frames not consumed");
+            if let Some((buffer, _)) = proxy.dequeue_buffer().unwrap() {
+                proxy.queue_buffer_process(buffer).unwrap();
+            }
+            if let Ok(frame) = consumer.wait_frames(1, Duration::from_millis(50)) {
+                frames.extend(frame);
+            }
+        }
+        (format, frames)
+    }
+
+    #[test]
+    fn negotiate_memfd() {
+        let Ok(client) = Client::new() else {
+            // no PipeWire daemon to test against
+            return;
+        };
+        let (format, frames) = consume(&client, memfd_stream_info(), ConsumerOptions::default(), 3);
+        assert_eq!(
+            format,
+            NegotiatedFormat {
+                format: Format::BGRx,
+                modifier: None,
+                width: WIDTH,
+                height: HEIGHT,
+            }
+        );
+
+        for frame in &frames {
+            assert_eq!(frame.data_type, spa_sys::SPA_DATA_MemFd);
+            assert_eq!(frame.planes[0].stride, (WIDTH * 4) as i32);
+        }
+        let seqs: Vec<_> = frames.iter().filter_map(|f| f.seq).collect();
+        assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn negotiate_memfd_fallback() {
+        let Ok(client) = Client::new() else {
+            return;
+        };
+        let info = StreamInfo {
+            enum_formats: vec![EnumFormatInfo {
+                formats: vec![Format::BGRx],
+                modifiers: vec![DRM_FORMAT_MOD_LINEAR],
+            }],
+            fixate_format: Box::new(|info| {
+                info.modifiers.first().map(|&modifier| FixateFormat {
+                    modifier: Some(modifier),
+                    num_planes: 1,
+                    mem_fd_fallback: true,
+                })
+            }),
+            ..memfd_stream_info()
+        };
+        let options = ConsumerOptions {
+            modifiers: vec![DRM_FORMAT_MOD_LINEAR],
+            dma_buf: false,
+            ..Default::default()
+        };
+        let (format, frames) = consume(&client, info, options, 1);
+        assert_eq!(format.modifier, Some(DRM_FORMAT_MOD_LINEAR));
+        assert_eq!(frames[0].data_type, spa_sys::SPA_DATA_MemFd);
+    }
+
+    #[test]
+    fn enumerate_streams() {
+        let Ok(client) = Client::new() else {
+            return;
+        };
+        let (stream, _consumer, _) = link(&client, memfd_stream_info(), Default::default());
+        let node_id = stream.proxy().node_id().unwrap().unwrap();
+
+        // the registry announces nodes asynchronously
+        let listed = |client: &Client| {
+            let nodes = client.enumerate_streams().unwrap();
+            nodes.into_iter().find(|node| node.id == node_id)
+        };
+        let deadline = Instant::now() + TIMEOUT;
+        let node = loop {
+            if let Some(node) = listed(&client) {
+                break node;
+            }
+            assert!(Instant::now() < deadline, "node not listed");
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(
+            node.props.get("media.software").map(String::as_str),
+            Some("pw-capture")
+        );
+        assert!(node.serial().is_some());
+
+        drop(stream);
+        while listed(&client).is_some() {
+            assert!(Instant::now() < deadline, "node still listed");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn drain_before_terminate() {
+        let Ok(client) = Client::new() else {
+            return;
+        };
+        let (stream, consumer, _) = link(&client, memfd_stream_info(), Default::default());
+        let proxy = stream.proxy();
+
+        let deadline = Instant::now() + TIMEOUT;
+        while consumer.wait_frames(1, Duration::from_millis(50)).is_err() {
+            assert!(Instant::now() < deadline, "frames not consumed");
+            if let Some((buffer, _)) = proxy.dequeue_buffer().unwrap() {
+                proxy.queue_buffer_process(buffer).unwrap();
+            }
+        }
+
+        // the consumer hands buffers back right after reading them
+        while !proxy.drain().unwrap() {
+            assert!(Instant::now() < deadline, "buffers not returned");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(proxy.dequeue_buffer().unwrap().is_none());
+        proxy.drain_and_terminate().unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+//! Orientation of exported frames
+
+// not in headers of PipeWire older than 0.3.53
+pub(crate) const SPA_META_VIDEO_TRANSFORM: u32 = 8;
+
+/// Transform applied to the content of frames, rotations are counter-clockwise
+/// as in `wl_output_transform`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum Transform {
+    #[default]
+    Normal = 0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    Flipped,
+    Flipped90,
+    Flipped180,
+    Flipped270,
+}
+
+impl Transform {
+    /// From a `wl_output_transform` value, which SPA transforms mirror
+    pub fn from_wl(value: i32) -> Option<Self> {
+        let transform = match value {
+            0 => Self::Normal,
+            1 => Self::Rotate90,
+            2 => Self::Rotate180,
+            3 => Self::Rotate270,
+            4 => Self::Flipped,
+            5 => Self::Flipped90,
+            6 => Self::Flipped180,
+            7 => Self::Flipped270,
+            _ => return None,
+        };
+        Some(transform)
+    }
+
+    /// Whether width and height of frames are swapped on display
+    pub fn is_transposed(self) -> bool {
+        matches!(
+            self,
+            Self::Rotate90 | Self::Rotate270 | Self::Flipped90 | Self::Flipped270
+        )
+    }
+}
+
+impl From<Transform> for u32 {
+    fn from(value: Transform) -> Self {
+        value as _
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_wl() {
+        for value in 0..8 {
+            let transform = Transform::from_wl(value).unwrap();
+            assert_eq!(u32::from(transform), value as u32);
+        }
+        assert_eq!(Transform::from_wl(8), None);
+        assert_eq!(Transform::from_wl(-1), None);
+        assert!(Transform::Flipped270.is_transposed());
+        assert!(!Transform::Rotate180.is_transposed());
+    }
+}
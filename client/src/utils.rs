@@ -1,4 +1,40 @@
+use std::env;
 use std::fs;
+use std::process;
+
+use once_cell::sync::Lazy;
+use pipewire as pw;
+
+use crate::settings::setting;
+
+const PROP_EXE: &str = "pw-capture.exe";
+const PROP_ARGV0: &str = "pw-capture.argv0";
+const PROP_CMDLINE: &str = "pw-capture.cmdline";
+
+static APP_PROPS: Lazy<Vec<(String, String)>> = Lazy::new(|| {
+    let mut props = vec![
+        (pw::keys::APP_NAME.to_string(), get_app_name()),
+        (
+            pw::keys::APP_PROCESS_ID.to_string(),
+            process::id().to_string(),
+        ),
+    ];
+    if let Some(program_name) = get_program_name() {
+        props.push((pw::keys::APP_PROCESS_BINARY.to_string(), program_name));
+    }
+    if let Ok(exe) = fs::read_link("/proc/self/exe") {
+        props.push((PROP_EXE.to_owned(), exe.to_string_lossy().into_owned()));
+    }
+    let args = get_args().unwrap_or_default();
+    if let Some(argv0) = args.first() {
+        props.push((PROP_ARGV0.to_owned(), argv0.clone()));
+    }
+    // arguments may carry secrets, while nodes are visible to all clients
+    if matches!(setting("PW_CAPTURE_CMDLINE").as_deref(), Some("1")) {
+        props.push((PROP_CMDLINE.to_owned(), args.join(" ")));
+    }
+    props
+});
 
 fn get_program_name() -> Option<String> {
     let program = fs::read_link("/proc/self/exe").ok()?;
@@ -6,6 +42,17 @@ fn get_program_name() -> Option<String> {
     Some(basename.to_string_lossy().to_string())
 }
 
+/// Arguments of the process, starting with argv[0]
+fn get_args() -> Option<Vec<String>> {
+    let cmdline = fs::read("/proc/self/cmdline").ok()?;
+    let args = cmdline
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    Some(args)
+}
+
 fn get_command_name() -> Option<String> {
     let mut f = fs::read_to_string("/proc/self/comm").ok()?;
     f.pop();
@@ -22,6 +69,12 @@ pub fn get_app_name() -> String {
     get_command_name().unwrap_or_else(|| String::from("unknown"))
 }
 
+/// Node properties describing the capturing process, so capture pickers can
+/// tell apart apps beyond their name
+pub fn app_props() -> &'static [(String, String)] {
+    &APP_PROPS
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -43,4 +96,17 @@ mod tests {
         let res = get_app_name();
         assert!(!res.is_empty())
     }
+
+    #[test]
+    fn process_props() {
+        let args = get_args().unwrap();
+        assert!(!args.is_empty());
+        let props = app_props();
+        let pid = process::id().to_string();
+        assert!(props.contains(&(pw::keys::APP_PROCESS_ID.to_string(), pid)));
+        assert!(props.contains(&(PROP_ARGV0.to_owned(), args[0].clone())));
+        if env::var_os("PW_CAPTURE_CMDLINE").is_none() {
+            assert!(props.iter().all(|(key, _)| key != PROP_CMDLINE));
+        }
+    }
 }
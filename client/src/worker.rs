@@ -0,0 +1,82 @@
+use std::thread::{self, JoinHandle};
+
+use anyhow::Result;
+use crossbeam_channel::{bounded, unbounded, Sender};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Thread of a stream handing frames over to consumers, so presenting never
+/// blocks on the copy or the backend thread
+pub struct Worker {
+    sender: Option<Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    pub fn new() -> Result<Self> {
+        let (sender, receiver) = unbounded::<Job>();
+        let thread = thread::Builder::new()
+            .name("pw-capture-worker".into())
+            .spawn(move || {
+                for job in receiver {
+                    job();
+                }
+            })?;
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Runs `job` after previously submitted ones
+    pub fn submit(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(sender) = self.sender.as_ref() {
+            let _ = sender.send(Box::new(job));
+        }
+    }
+
+    /// Blocks until previously submitted jobs completed, e.g. before
+    /// destroying resources they use
+    pub fn flush(&self) {
+        let (done_sender, done_receiver) = bounded(1);
+        self.submit(move || {
+            let _ = done_sender.send(());
+        });
+        let _ = done_receiver.recv();
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(thread) = self.thread.take() {
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn run_in_order() {
+        let worker = Worker::new().unwrap();
+        let order = Arc::new(Mutex::new(vec![]));
+        for i in 0..8 {
+            let order = order.clone();
+            worker.submit(move || order.lock().unwrap().push(i));
+        }
+        worker.flush();
+        assert_eq!(*order.lock().unwrap(), (0..8).collect::<Vec<_>>());
+
+        let order_clone = order.clone();
+        worker.submit(move || order_clone.lock().unwrap().push(8));
+        drop(worker);
+        assert_eq!(order.lock().unwrap().len(), 9);
+    }
+}
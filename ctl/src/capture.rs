@@ -0,0 +1,326 @@
+//! Consumer stream receiving frames of a capture node
+
+use crate::format_info::FormatSummary;
+
+use core::ptr;
+use core::slice;
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+use libspa::buffer::Data;
+use libspa::pod::serialize::PodSerializer;
+use libspa::pod::{ChoiceValue, Object, Pod, Property, PropertyFlags, Value};
+use libspa::utils::{Choice, ChoiceEnum, ChoiceFlags, Direction, Id};
+use libspa_sys as spa_sys;
+use pipewire as pw;
+use pw::properties::properties;
+use pw_capture_client::Format;
+
+/// Formats with 4 bytes per pixel that can be converted to RGBA
+const FORMATS: [Format; 4] = [Format::BGRA, Format::RGBA, Format::BGRx, Format::RGBx];
+// buffers can only be mapped with linear layout
+const DRM_FORMAT_MOD_LINEAR: i64 = 0;
+
+const DMA_BUF_SYNC_READ: u64 = 1 << 0;
+const DMA_BUF_SYNC_START: u64 = 0 << 2;
+const DMA_BUF_SYNC_END: u64 = 1 << 2;
+// _IOW('b', 0, struct dma_buf_sync)
+const DMA_BUF_IOCTL_SYNC: u64 = 0x40086200;
+
+pub struct Frame {
+    pub format: Format,
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed rows, 4 bytes per pixel
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = self.data.clone();
+        for px in rgba.chunks_exact_mut(4) {
+            match self.format {
+                Format::BGRA => px.swap(0, 2),
+                Format::BGRx => {
+                    px.swap(0, 2);
+                    px[3] = 0xff;
+                }
+                Format::RGBx => px[3] = 0xff,
+                _ => (),
+            }
+        }
+        rgba
+    }
+}
+
+/// Linear DMA-BUFs if `modifier` is set, shared memory otherwise
+fn build_enum_format(modifier: bool) -> Result<Vec<u8>> {
+    let mut properties = vec![
+        Property {
+            key: spa_sys::SPA_FORMAT_mediaType,
+            flags: PropertyFlags::empty(),
+            value: Value::Id(Id(spa_sys::SPA_MEDIA_TYPE_video)),
+        },
+        Property {
+            key: spa_sys::SPA_FORMAT_mediaSubtype,
+            flags: PropertyFlags::empty(),
+            value: Value::Id(Id(spa_sys::SPA_MEDIA_SUBTYPE_raw)),
+        },
+        Property {
+            key: spa_sys::SPA_FORMAT_VIDEO_format,
+            flags: PropertyFlags::empty(),
+            value: Value::Choice(ChoiceValue::Id(Choice(
+                ChoiceFlags::empty(),
+                ChoiceEnum::Enum {
+                    default: Id(FORMATS[0].into()),
+                    alternatives: FORMATS.iter().map(|&f| Id(f.into())).collect(),
+                },
+            ))),
+        },
+    ];
+    if modifier {
+        properties.push(Property {
+            key: spa_sys::SPA_FORMAT_VIDEO_modifier,
+            flags: PropertyFlags::MANDATORY | PropertyFlags::DONT_FIXATE,
+            value: Value::Choice(ChoiceValue::Long(Choice(
+                ChoiceFlags::empty(),
+                ChoiceEnum::Enum {
+                    default: DRM_FORMAT_MOD_LINEAR,
+                    alternatives: vec![DRM_FORMAT_MOD_LINEAR],
+                },
+            ))),
+        });
+    }
+    let value = Value::Object(Object {
+        type_: spa_sys::SPA_TYPE_OBJECT_Format,
+        id: spa_sys::SPA_PARAM_EnumFormat,
+        properties,
+    });
+    Ok(PodSerializer::serialize(Cursor::new(Vec::new()), &value)?
+        .0
+        .into_inner())
+}
+
+unsafe fn dma_buf_sync(fd: i32, flags: u64) {
+    let _ = libc::ioctl(fd, DMA_BUF_IOCTL_SYNC as _, &flags);
+}
+
+/// Copies rows of the first plane out of mapped or mappable `data`
+unsafe fn read_frame(data: &Data, format: Format, width: u32, height: u32) -> Result<Frame> {
+    let raw = data.as_raw();
+    let chunk = data.chunk();
+    if width == 0 || height == 0 {
+        return Err(anyhow!("empty frame"));
+    }
+    let row_size = width as usize * 4;
+    let stride = chunk.stride() as usize;
+    if stride < row_size {
+        return Err(anyhow!("invalid stride {}", stride));
+    }
+    let size = chunk.offset() as usize + stride * (height as usize - 1) + row_size;
+    if size > raw.maxsize as usize {
+        return Err(anyhow!("buffer size {} too small", raw.maxsize));
+    }
+
+    let is_dma_buf = raw.type_ == spa_sys::SPA_DATA_DmaBuf;
+    let (map, map_size, base) = if !raw.data.is_null() {
+        (ptr::null_mut(), 0, raw.data as *const u8)
+    } else if is_dma_buf || raw.type_ == spa_sys::SPA_DATA_MemFd {
+        let map_size = raw.mapoffset as usize + raw.maxsize as usize;
+        let map = libc::mmap(
+            ptr::null_mut(),
+            map_size,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            raw.fd as _,
+            0,
+        );
+        if map == libc::MAP_FAILED {
+            return Err(anyhow!(
+                "failed to map buffer: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        (map, map_size, (map as *const u8).add(raw.mapoffset as _))
+    } else {
+        return Err(anyhow!("unsupported data type {}", raw.type_));
+    };
+
+    if is_dma_buf {
+        dma_buf_sync(raw.fd as _, DMA_BUF_SYNC_START | DMA_BUF_SYNC_READ);
+    }
+    let src = slice::from_raw_parts(
+        base.add(chunk.offset() as _),
+        size - chunk.offset() as usize,
+    );
+    let mut frame_data = Vec::with_capacity(row_size * height as usize);
+    for row in 0..height as usize {
+        frame_data.extend_from_slice(&src[row * stride..row * stride + row_size]);
+    }
+    if is_dma_buf {
+        dma_buf_sync(raw.fd as _, DMA_BUF_SYNC_END | DMA_BUF_SYNC_READ);
+    }
+    if !map.is_null() {
+        libc::munmap(map, map_size);
+    }
+
+    Ok(Frame {
+        format,
+        width,
+        height,
+        data: frame_data,
+    })
+}
+
+#[derive(Default)]
+struct StreamData {
+    format: Option<(Format, u32, u32)>,
+}
+
+/// Connects to capture node `node_id` and calls `on_frame` with each received
+/// frame until it returns `false`
+pub fn capture<F>(node_id: u32, mut on_frame: F) -> Result<()>
+where
+    F: FnMut(Frame) -> Result<bool> + 'static,
+{
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    let stream = pw::stream::Stream::new(
+        &core,
+        "pw-capture-ctl",
+        properties! {
+            *pw::keys::MEDIA_TYPE => "Video",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "Screen",
+        },
+    )?;
+
+    let error = Rc::new(RefCell::new(None::<anyhow::Error>));
+
+    let _listener = stream
+        .add_local_listener_with_user_data(StreamData::default())
+        .state_changed({
+            let mainloop = mainloop.clone();
+            let error = error.clone();
+            move |_stream, _data, old, new| match new {
+                pw::stream::StreamState::Error(e) => {
+                    *error.borrow_mut() = Some(anyhow!("stream error: {}", e));
+                    mainloop.quit();
+                }
+                pw::stream::StreamState::Unconnected => {
+                    *error.borrow_mut() = Some(anyhow!("stream disconnected"));
+                    mainloop.quit();
+                }
+                _ => eprintln!("stream state: {:?} -> {:?}", old, new),
+            }
+        })
+        .param_changed(move |_stream, data, id, param| {
+            if id != spa_sys::SPA_PARAM_Format {
+                return;
+            }
+            let Some(param) = param else {
+                data.format = None;
+                return;
+            };
+            let Some(summary) = FormatSummary::parse(param.as_bytes()) else {
+                return;
+            };
+            if let (Some(&format), Some((width, height))) = (summary.formats.first(), summary.size)
+            {
+                eprintln!(
+                    "negotiated format: {:?} {}x{} modifiers {:?}",
+                    format, width, height, summary.modifiers
+                );
+                data.format = Some((format, width, height));
+            }
+        })
+        .process({
+            let mainloop = mainloop.clone();
+            let error = error.clone();
+            move |stream, data| {
+                let Some((format, width, height)) = data.format else {
+                    return;
+                };
+                let Some(mut buffer) = stream.dequeue_buffer() else {
+                    return;
+                };
+                let res = match buffer.datas_mut().first() {
+                    Some(first) => unsafe { read_frame(first, format, width, height) },
+                    None => return,
+                };
+                // queue buffer back before handling the frame
+                drop(buffer);
+
+                match res.and_then(&mut on_frame) {
+                    Ok(true) => (),
+                    Ok(false) => mainloop.quit(),
+                    Err(e) => {
+                        *error.borrow_mut() = Some(e);
+                        mainloop.quit();
+                    }
+                }
+            }
+        })
+        .register()?;
+
+    // nodes exporting shared memory only match the format without modifier
+    let params = [build_enum_format(true)?, build_enum_format(false)?];
+    let mut params = params
+        .iter()
+        .map(|p| Pod::from_bytes(p).ok_or(anyhow!("invalid pod")))
+        .collect::<Result<Vec<_>>>()?;
+    stream.connect(
+        Direction::Input,
+        Some(node_id),
+        pw::stream::StreamFlags::AUTOCONNECT | pw::stream::StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    mainloop.run();
+
+    let error = error.borrow_mut().take();
+    let _ = stream.disconnect();
+    match error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_to_rgba() {
+        let frame = Frame {
+            format: Format::BGRx,
+            width: 1,
+            height: 1,
+            data: vec![1, 2, 3, 0],
+        };
+        assert_eq!(frame.to_rgba(), [3, 2, 1, 0xff]);
+
+        let frame = Frame {
+            format: Format::RGBA,
+            ..frame
+        };
+        assert_eq!(frame.to_rgba(), [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn enum_format() {
+        let pod = build_enum_format(true).unwrap();
+        let summary = FormatSummary::parse(&pod).unwrap();
+        assert_eq!(summary.formats, FORMATS);
+        assert_eq!(summary.modifiers, [DRM_FORMAT_MOD_LINEAR as u64]);
+
+        let pod = build_enum_format(false).unwrap();
+        let summary = FormatSummary::parse(&pod).unwrap();
+        assert_eq!(summary.formats, FORMATS);
+        assert!(summary.modifiers.is_empty());
+    }
+}
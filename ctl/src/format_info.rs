@@ -0,0 +1,139 @@
+use libspa::pod::deserialize::PodDeserializer;
+use libspa::pod::{CanonicalFixedSizedPod, ChoiceValue, Object, Value, ValueArray};
+use libspa::utils::ChoiceEnum;
+use libspa_sys as spa_sys;
+use pw_capture_client::Format;
+
+/// Video format of a stream, as offered or negotiated
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FormatSummary {
+    pub formats: Vec<Format>,
+    pub size: Option<(u32, u32)>,
+    pub modifiers: Vec<u64>,
+}
+
+fn choice_values<T: CanonicalFixedSizedPod + PartialEq>(choice: ChoiceEnum<T>) -> Vec<T> {
+    match choice {
+        ChoiceEnum::None(value) => vec![value],
+        ChoiceEnum::Range { default, .. } | ChoiceEnum::Step { default, .. } => vec![default],
+        ChoiceEnum::Enum {
+            default,
+            mut alternatives,
+        } => {
+            // default is repeated as first alternative
+            alternatives.retain(|v| v != &default);
+            alternatives.insert(0, default);
+            alternatives
+        }
+        ChoiceEnum::Flags { default, .. } => vec![default],
+    }
+}
+
+impl FormatSummary {
+    pub fn parse(pod: &[u8]) -> Option<Self> {
+        let (_, value) = PodDeserializer::deserialize_from::<Value>(pod).ok()?;
+        let Value::Object(Object {
+            type_, properties, ..
+        }) = value
+        else {
+            return None;
+        };
+        if type_ != spa_sys::SPA_TYPE_OBJECT_Format {
+            return None;
+        }
+
+        let mut summary = Self::default();
+        for prop in properties {
+            match (prop.key, prop.value) {
+                (spa_sys::SPA_FORMAT_VIDEO_format, Value::Id(id)) => {
+                    summary.formats = vec![id.0.into()];
+                }
+                (spa_sys::SPA_FORMAT_VIDEO_format, Value::Choice(ChoiceValue::Id(choice))) => {
+                    summary.formats = choice_values(choice.1)
+                        .into_iter()
+                        .map(|id| id.0.into())
+                        .collect();
+                }
+                (spa_sys::SPA_FORMAT_VIDEO_size, Value::Rectangle(size)) => {
+                    summary.size = Some((size.width, size.height));
+                }
+                (spa_sys::SPA_FORMAT_VIDEO_size, Value::Choice(ChoiceValue::Rectangle(choice))) => {
+                    let size = match choice.1 {
+                        ChoiceEnum::None(size)
+                        | ChoiceEnum::Range { default: size, .. }
+                        | ChoiceEnum::Step { default: size, .. }
+                        | ChoiceEnum::Enum { default: size, .. }
+                        | ChoiceEnum::Flags { default: size, .. } => size,
+                    };
+                    summary.size = Some((size.width, size.height));
+                }
+                (spa_sys::SPA_FORMAT_VIDEO_modifier, Value::Long(modifier)) => {
+                    summary.modifiers = vec![modifier as _];
+                }
+                (spa_sys::SPA_FORMAT_VIDEO_modifier, Value::Choice(ChoiceValue::Long(choice))) => {
+                    summary.modifiers = choice_values(choice.1)
+                        .into_iter()
+                        .map(|m| m as _)
+                        .collect();
+                }
+                (spa_sys::SPA_FORMAT_VIDEO_modifier, Value::ValueArray(ValueArray::Long(v))) => {
+                    summary.modifiers = v.into_iter().map(|m| m as _).collect();
+                }
+                _ => (),
+            }
+        }
+        Some(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libspa::pod::serialize::PodSerializer;
+    use libspa::pod::{Property, PropertyFlags};
+    use libspa::utils::{Choice, ChoiceFlags, Id, Rectangle};
+    use std::io::Cursor;
+
+    #[test]
+    fn parse_format() {
+        let value = Value::Object(Object {
+            type_: spa_sys::SPA_TYPE_OBJECT_Format,
+            id: spa_sys::SPA_PARAM_EnumFormat,
+            properties: vec![
+                Property {
+                    key: spa_sys::SPA_FORMAT_VIDEO_format,
+                    flags: PropertyFlags::empty(),
+                    value: Value::Choice(ChoiceValue::Id(Choice(
+                        ChoiceFlags::empty(),
+                        ChoiceEnum::Enum {
+                            default: Id(Format::BGRA.into()),
+                            alternatives: vec![Id(Format::BGRA.into()), Id(Format::RGBA.into())],
+                        },
+                    ))),
+                },
+                Property {
+                    key: spa_sys::SPA_FORMAT_VIDEO_size,
+                    flags: PropertyFlags::empty(),
+                    value: Value::Rectangle(Rectangle {
+                        width: 1920,
+                        height: 1080,
+                    }),
+                },
+                Property {
+                    key: spa_sys::SPA_FORMAT_VIDEO_modifier,
+                    flags: PropertyFlags::MANDATORY,
+                    value: Value::Long(0),
+                },
+            ],
+        });
+        let pod = PodSerializer::serialize(Cursor::new(Vec::new()), &value)
+            .unwrap()
+            .0
+            .into_inner();
+
+        let summary = FormatSummary::parse(&pod).unwrap();
+        assert_eq!(summary.formats, [Format::BGRA, Format::RGBA]);
+        assert_eq!(summary.size, Some((1920, 1080)));
+        assert_eq!(summary.modifiers, [0]);
+    }
+}
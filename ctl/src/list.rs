@@ -0,0 +1,142 @@
+//! Listing capture nodes along with their formats
+
+use crate::format_info::FormatSummary;
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use anyhow::Result;
+use libspa::param::ParamType;
+use pipewire as pw;
+use pw::properties::Properties;
+use pw::registry::GlobalObject;
+use pw_capture_client::{Backend, Client, Remote};
+
+#[derive(Debug, Default)]
+pub struct CaptureNode {
+    pub id: u32,
+    pub name: String,
+    /// Negotiated format, if linked to a consumer
+    pub format: Option<FormatSummary>,
+    /// First offered format otherwise
+    pub enum_format: Option<FormatSummary>,
+}
+
+/// Runs main loop until the server processed all previous requests
+fn roundtrip(mainloop: &pw::main_loop::MainLoop, core: &pw::core::Core) -> Result<()> {
+    let done = Rc::new(Cell::new(false));
+    let pending = core.sync(0)?;
+    let _listener = core
+        .add_listener_local()
+        .done({
+            let done = done.clone();
+            let mainloop = mainloop.clone();
+            move |id, seq| {
+                if id == pw::core::PW_ID_CORE && seq == pending {
+                    done.set(true);
+                    mainloop.quit();
+                }
+            }
+        })
+        .register();
+    while !done.get() {
+        mainloop.run();
+    }
+    Ok(())
+}
+
+pub fn list_capture_nodes() -> Result<Vec<CaptureNode>> {
+    // same remote as of the connection below
+    let client = Client::with_remote(Backend::PipeWire, Remote::Default)?;
+    let streams = client.enumerate_streams()?;
+    drop(client);
+
+    let nodes: BTreeMap<_, _> = streams
+        .into_iter()
+        .map(|stream| {
+            let name = stream
+                .props
+                .get(*pw::keys::NODE_DESCRIPTION)
+                .or_else(|| stream.props.get(*pw::keys::NODE_NAME))
+                .cloned()
+                .unwrap_or_default();
+            let node = CaptureNode {
+                id: stream.id,
+                name,
+                ..Default::default()
+            };
+            (stream.id, node)
+        })
+        .collect();
+    let nodes = Rc::new(RefCell::new(nodes));
+
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+    let registry = core.get_registry()?;
+
+    // output ports of capture nodes carry their formats
+    let ports = Rc::new(RefCell::new(Vec::<(u32, GlobalObject<Properties>)>::new()));
+    let _registry_listener = registry
+        .add_listener_local()
+        .global({
+            let nodes = nodes.clone();
+            let ports = ports.clone();
+            move |global| {
+                if global.type_ != pw::types::ObjectType::Port {
+                    return;
+                }
+                let Some(props) = global.props else {
+                    return;
+                };
+                if props.get(*pw::keys::PORT_DIRECTION) != Some("out") {
+                    return;
+                }
+                let node_id = props.get(*pw::keys::NODE_ID).and_then(|id| id.parse().ok());
+                match node_id {
+                    Some(node_id) if nodes.borrow().contains_key(&node_id) => {
+                        ports.borrow_mut().push((node_id, global.to_owned()));
+                    }
+                    _ => (),
+                }
+            }
+        })
+        .register();
+    roundtrip(&mainloop, &core)?;
+
+    let mut port_proxies = Vec::new();
+    for (node_id, global) in ports.borrow().iter() {
+        let port: pw::port::Port = registry.bind(global)?;
+        let listener = port
+            .add_listener_local()
+            .param({
+                let nodes = nodes.clone();
+                let node_id = *node_id;
+                move |_seq, id, _index, _next, param| {
+                    let Some(param) = param else {
+                        return;
+                    };
+                    let mut nodes = nodes.borrow_mut();
+                    let Some(node) = nodes.get_mut(&node_id) else {
+                        return;
+                    };
+                    let summary = FormatSummary::parse(param.as_bytes());
+                    if id == ParamType::Format {
+                        node.format = summary;
+                    } else if id == ParamType::EnumFormat && node.enum_format.is_none() {
+                        node.enum_format = summary;
+                    }
+                }
+            })
+            .register();
+        port.enum_params(0, Some(ParamType::EnumFormat), 0, u32::MAX);
+        port.enum_params(0, Some(ParamType::Format), 0, u32::MAX);
+        port_proxies.push((port, listener));
+    }
+    roundtrip(&mainloop, &core)?;
+    drop(port_proxies);
+
+    let nodes = nodes.take();
+    Ok(nodes.into_values().collect())
+}
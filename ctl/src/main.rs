@@ -0,0 +1,170 @@
+//! Command line tool inspecting streams of pw-capture
+
+mod capture;
+mod format_info;
+mod list;
+mod png;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::net::UnixStream;
+use std::process::ExitCode;
+
+use anyhow::{anyhow, Context, Result};
+use pipewire as pw;
+
+const USAGE: &str = "\
+Usage: pw-capture-ctl <command> [args]
+
+Commands:
+  list                    List capture nodes with their formats
+  snapshot <node> <file>  Save one frame of node as PNG
+  dump <node>             Write raw frames of node to stdout, frames are
+                          tightly packed rows of 4 bytes per pixel,
+                          format is printed to stderr
+  control <pid> [cmd]     Enable, disable or toggle capture of a process,
+                          cmd being enable, disable, toggle or status, or
+                          set its log filter with log <filter>
+";
+
+fn format_modifiers(modifiers: &[u64]) -> String {
+    let modifiers: Vec<_> = modifiers.iter().map(|m| format!("{:#x}", m)).collect();
+    modifiers.join(",")
+}
+
+fn list() -> Result<()> {
+    let nodes = list::list_capture_nodes()?;
+    println!(
+        "{:>6}  {:<12}  {:<16}  {:<20}  NAME",
+        "ID", "SIZE", "FORMAT", "MODIFIER"
+    );
+    for node in nodes {
+        let (summary, state) = match (node.format, node.enum_format) {
+            (Some(format), _) => (format, ""),
+            (None, Some(enum_format)) => (enum_format, " (idle)"),
+            (None, None) => Default::default(),
+        };
+        let size = match summary.size {
+            Some((width, height)) => format!("{}x{}", width, height),
+            None => "-".into(),
+        };
+        let format = match summary.formats.first() {
+            Some(format) => format!("{:?}", format),
+            None => "-".into(),
+        };
+        println!(
+            "{:>6}  {:<12}  {:<16}  {:<20}  {}{}",
+            node.id,
+            size,
+            format,
+            format_modifiers(&summary.modifiers),
+            node.name,
+            state
+        );
+    }
+    Ok(())
+}
+
+fn snapshot(node_id: u32, path: &str) -> Result<()> {
+    let path = path.to_string();
+    capture::capture(node_id, move |frame| {
+        let file = File::create(&path).with_context(|| format!("failed to create {}", path))?;
+        png::write_rgba(
+            BufWriter::new(file),
+            frame.width,
+            frame.height,
+            &frame.to_rgba(),
+        )?;
+        eprintln!("saved {}x{} frame to {}", frame.width, frame.height, path);
+        Ok(false)
+    })
+}
+
+fn dump(node_id: u32) -> Result<()> {
+    let mut last_format = None;
+    capture::capture(node_id, move |frame| {
+        let format = Some((frame.format, frame.width, frame.height));
+        if last_format != format {
+            eprintln!(
+                "frame format: {:?} {}x{}",
+                frame.format, frame.width, frame.height
+            );
+            last_format = format;
+        }
+        let mut stdout = io::stdout().lock();
+        match stdout.write_all(&frame.data).and_then(|_| stdout.flush()) {
+            Ok(()) => Ok(true),
+            // reader went away
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    })
+}
+
+fn control(pid: u32, command: &str) -> Result<()> {
+    let path =
+        pw_capture_client::control_socket_path(pid).ok_or(anyhow!("XDG_RUNTIME_DIR not set"))?;
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+    writeln!(stream, "{}", command)?;
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    println!("{}", reply.trim_end());
+    Ok(())
+}
+
+fn parse_node_id(arg: Option<String>) -> Result<u32> {
+    let arg = arg.ok_or(anyhow!("missing node id"))?;
+    arg.parse()
+        .with_context(|| format!("invalid node id {:?}", arg))
+}
+
+fn run() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let command = args.next();
+    match command.as_deref() {
+        Some("list") => list(),
+        Some("snapshot") => {
+            let node_id = parse_node_id(args.next())?;
+            let path = args.next().ok_or(anyhow!("missing file"))?;
+            snapshot(node_id, &path)
+        }
+        Some("dump") => dump(parse_node_id(args.next())?),
+        Some("control") => {
+            let pid = args.next().ok_or(anyhow!("missing pid"))?;
+            let pid = pid
+                .parse()
+                .with_context(|| format!("invalid pid {:?}", pid))?;
+            let command: Vec<_> = args.collect();
+            if command.is_empty() {
+                control(pid, "status")
+            } else {
+                control(pid, &command.join(" "))
+            }
+        }
+        Some("-h" | "--help" | "help") => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => {
+            eprint!("{}", USAGE);
+            Err(anyhow!("invalid command"))
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // listing creates a client, which isn't a capturing process to control
+    env::set_var("PW_CAPTURE_CONTROL", "0");
+    pw::init();
+    let res = run();
+    unsafe { pw::deinit() };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Minimal PNG encoder for 8-bit RGBA images
+
+use std::io::{self, Write};
+
+use miniz_oxide::deflate::compress_to_vec_zlib;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const BIT_DEPTH: u8 = 8;
+const COLOR_TYPE_RGBA: u8 = 6;
+const COMPRESSION_LEVEL: u8 = 6;
+
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for part in parts {
+        for &byte in *part {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    0xedb8_8320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+    }
+    !crc
+}
+
+fn write_chunk<W: Write>(w: &mut W, type_: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(type_)?;
+    w.write_all(data)?;
+    w.write_all(&crc32(&[type_, data]).to_be_bytes())
+}
+
+/// Writes `pixels` of tightly packed RGBA rows as PNG
+pub fn write_rgba<W: Write>(mut w: W, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    let row_size = width as usize * 4;
+    assert_eq!(pixels.len(), row_size * height as usize);
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // compression, filter and interlace methods
+    header.extend_from_slice(&[BIT_DEPTH, COLOR_TYPE_RGBA, 0, 0, 0]);
+
+    // every scanline starts with filter type, none here
+    let mut scanlines = Vec::with_capacity((row_size + 1) * height as usize);
+    for row in pixels.chunks_exact(row_size.max(1)) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+    let data = compress_to_vec_zlib(&scanlines, COMPRESSION_LEVEL);
+
+    w.write_all(&SIGNATURE)?;
+    write_chunk(&mut w, b"IHDR", &header)?;
+    write_chunk(&mut w, b"IDAT", &data)?;
+    write_chunk(&mut w, b"IEND", &[])?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(&[b"123456789"]), 0xcbf4_3926);
+        assert_eq!(crc32(&[b"1234", b"56789"]), 0xcbf4_3926);
+        assert_eq!(crc32(&[b"IEND"]), 0xae42_6082);
+    }
+
+    #[test]
+    fn encode() {
+        let pixels = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut out = Vec::new();
+        write_rgba(&mut out, 2, 1, &pixels).unwrap();
+
+        assert_eq!(out[..8], SIGNATURE);
+        assert_eq!(out[8..12], 13u32.to_be_bytes());
+        assert_eq!(&out[12..16], b"IHDR");
+        assert_eq!(out[16..20], 2u32.to_be_bytes());
+        assert_eq!(out[20..24], 1u32.to_be_bytes());
+        assert_eq!(out[24..26], [BIT_DEPTH, COLOR_TYPE_RGBA]);
+
+        let idat = 8 + 12 + 13;
+        let len = u32::from_be_bytes(out[idat..idat + 4].try_into().unwrap()) as usize;
+        assert_eq!(&out[idat + 4..idat + 8], b"IDAT");
+        let data = decompress_to_vec_zlib(&out[idat + 8..idat + 8 + len]).unwrap();
+        assert_eq!(data, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        assert_eq!(out[out.len() - 12..out.len() - 4], *b"\0\0\0\0IEND");
+    }
+}
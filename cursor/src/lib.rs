@@ -15,6 +15,30 @@ use pw_capture_client as client;
 
 pub trait CursorManager: Send + Sync {
     fn snapshot_cursor(&self, serial: u64) -> Result<Box<dyn CursorSnapshot>>;
+    /// returns title, app id, buffer transform and visibility of the window if
+    /// changed since `serial`
+    fn window_info(&self, _serial: u64) -> Option<WindowInfo> {
+        None
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WindowInfo {
+    /// bumped on every change, 0 if never set
+    pub serial: u64,
+    pub title: Option<String>,
+    pub app_id: Option<String>,
+    /// `wl_output_transform` the window content is rendered with
+    pub buffer_transform: i32,
+    /// minimized or hidden otherwise, frames of the window would be stale
+    pub suspended: bool,
+}
+
+#[cfg(feature = "pw-capture-client")]
+impl WindowInfo {
+    pub fn as_props(&self) -> Vec<(String, String)> {
+        client::window_props(self.title.as_deref(), self.app_id.as_deref())
+    }
 }
 
 pub trait CursorSnapshot {
@@ -8,11 +8,13 @@ use sys::*;
 use wl_lib::*;
 
 use crate::utils::*;
-use crate::{CursorManager, CursorSnapshot};
+use crate::{CursorManager, CursorSnapshot, WindowInfo};
 
 use core::ffi::{c_int, c_void, CStr};
+use core::mem;
 use core::ptr;
 use core::slice;
+use core::sync::atomic::{AtomicU64, Ordering};
 use std::alloc::{alloc, dealloc, Layout};
 use std::os::fd::RawFd;
 use std::sync::RwLock;
@@ -21,7 +23,11 @@ use anyhow::{anyhow, Result};
 use dashmap::DashMap;
 use log::{debug, trace, warn};
 
-pub use wl_lib::WlHandle;
+pub use wl_lib::{WlHandle, WlSig, WlSignatureIter};
+
+/// `xdg_toplevel.state` of windows minimized or fully occluded, sent by
+/// compositors since xdg-shell version 6
+const XDG_TOPLEVEL_STATE_SUSPENDED: u32 = 9;
 
 struct RegistryState {
     #[allow(unused)]
@@ -34,16 +40,86 @@ struct GlobalState {
     name: String,
 }
 
-struct SurfaceBuffer {
-    buffer: WlHandle,
+/// Double-buffered surface attributes, applied on commit
+#[derive(Clone, Copy)]
+struct SurfaceAttrs {
+    buffer: Option<WlHandle>,
     scale: i32,
+    /// wl_output_transform of set_buffer_transform
+    transform: i32,
+    /// wp_viewport source rectangle in surface coordinates before scaling
+    viewport_source: Option<[wl_fixed_t; 4]>,
+    /// wp_viewport destination size in surface coordinates
+    viewport_destination: Option<(i32, i32)>,
+}
+
+impl Default for SurfaceAttrs {
+    fn default() -> Self {
+        Self {
+            buffer: None,
+            scale: 1,
+            transform: 0,
+            viewport_source: None,
+            viewport_destination: None,
+        }
+    }
+}
+
+impl SurfaceAttrs {
+    /// Maps surface-local position to buffer pixels, `buffer_size` is the
+    /// size of attached buffer if known, `fractional_scale` the preferred
+    /// scale of wp_fractional_scale_v1 in 120ths
+    fn surface_to_buffer(
+        &self,
+        x: f64,
+        y: f64,
+        buffer_size: Option<(i32, i32)>,
+        fractional_scale: Option<u32>,
+    ) -> (f64, f64) {
+        let scale = self.scale as f64;
+        if let Some([src_x, src_y, src_w, src_h]) = self.viewport_source {
+            let (src_w, src_h) = (src_w.to_num::<f64>(), src_h.to_num::<f64>());
+            let (dst_w, dst_h) = match self.viewport_destination {
+                Some((w, h)) => (w as f64, h as f64),
+                None => (src_w, src_h),
+            };
+            return (
+                (src_x.to_num::<f64>() + x * src_w / dst_w) * scale,
+                (src_y.to_num::<f64>() + y * src_h / dst_h) * scale,
+            );
+        }
+        if let Some((dst_w, dst_h)) = self.viewport_destination {
+            // whole buffer is stretched to destination
+            if let Some((width, height)) = buffer_size {
+                return (
+                    x * width as f64 / dst_w as f64,
+                    y * height as f64 / dst_h as f64,
+                );
+            }
+            // fractionally scaled clients render at preferred scale
+            if let Some(fractional_scale) = fractional_scale {
+                let scale = fractional_scale as f64 / 120.0;
+                return (x * scale, y * scale);
+            }
+        }
+        (x * scale, y * scale)
+    }
 }
 
+/// Bumped whenever a pointer enters a surface or sets its cursor, starts at 1
+/// as snapshots of serial 0 always come with a bitmap
+static CURSOR_SERIAL: AtomicU64 = AtomicU64::new(1);
+
 struct SurfacePointer {
     #[allow(unused)]
     pointer: WlHandle,
     serial: u32,
+    /// Changes along with the cursor surface
+    cursor_serial: u64,
     cursor_surface: Option<WlHandle>,
+    /// Set by set_cursor with a null surface, the compositor draws its own
+    /// cursor while none got set
+    cursor_hidden: bool,
     hotspot_x: i32,
     hotspot_y: i32,
     motion_time: u32,
@@ -53,9 +129,29 @@ struct SurfacePointer {
 
 struct SurfaceState {
     g_compositor: WlHandle,
-    active_buffer: RwLock<Option<SurfaceBuffer>>,
-    pending_buffer: RwLock<Option<SurfaceBuffer>>,
+    active: RwLock<SurfaceAttrs>,
+    pending: RwLock<SurfaceAttrs>,
+    /// Last wp_fractional_scale_v1.preferred_scale
+    fractional_scale: RwLock<Option<u32>>,
     entered_pointer: RwLock<Option<SurfacePointer>>,
+    /// Set by the xdg_toplevel of the surface, kept when it gets unmapped
+    window: RwLock<WindowInfo>,
+}
+
+struct ViewportState {
+    surface: WlHandle,
+}
+
+struct XdgSurfaceState {
+    surface: WlHandle,
+}
+
+struct XdgToplevelState {
+    surface: WlHandle,
+}
+
+struct FractionalScaleState {
+    surface: WlHandle,
 }
 
 struct ShmPoolState {
@@ -74,6 +170,11 @@ struct ShmBufferState {
     format: u32,
 }
 
+struct DmabufBufferState {
+    width: i32,
+    height: i32,
+}
+
 struct PointerState {
     #[allow(unused)]
     g_seat: WlHandle,
@@ -88,7 +189,12 @@ pub struct WlIntercept {
     surface_map: DashMap<WlHandle, SurfaceState>,
     shm_pool_map: DashMap<WlHandle, ShmPoolState>,
     shm_buffer_map: DashMap<WlHandle, ShmBufferState>,
+    dmabuf_buffer_map: DashMap<WlHandle, DmabufBufferState>,
+    viewport_map: DashMap<WlHandle, ViewportState>,
+    fractional_scale_map: DashMap<WlHandle, FractionalScaleState>,
     pointer_map: DashMap<WlHandle, PointerState>,
+    xdg_surface_map: DashMap<WlHandle, XdgSurfaceState>,
+    xdg_toplevel_map: DashMap<WlHandle, XdgToplevelState>,
 }
 
 pub struct WlCursorManager {
@@ -126,7 +232,12 @@ impl WlIntercept {
             surface_map: DashMap::new(),
             shm_pool_map: DashMap::new(),
             shm_buffer_map: DashMap::new(),
+            dmabuf_buffer_map: DashMap::new(),
+            viewport_map: DashMap::new(),
+            fractional_scale_map: DashMap::new(),
             pointer_map: DashMap::new(),
+            xdg_surface_map: DashMap::new(),
+            xdg_toplevel_map: DashMap::new(),
         })
     }
 }
@@ -139,6 +250,10 @@ impl CursorManager for WlCursorManager {
             .ok_or(anyhow!("failed to snapshot cursor"))?;
         Ok(Box::new(snap))
     }
+
+    fn window_info(&self, serial: u64) -> Option<WindowInfo> {
+        self.intercept.window_info(serial, self.surface)
+    }
 }
 
 impl CursorSnapshot for WlCursorSnapshot {
@@ -248,32 +363,74 @@ impl WlIntercept {
         })
     }
 
+    fn buffer_size(&self, buffer: WlHandle) -> Option<(i32, i32)> {
+        if let Some(state) = self.shm_buffer_map.get(&buffer) {
+            return Some((state.width, state.height));
+        }
+        let state = self.dmabuf_buffer_map.get(&buffer)?;
+        Some((state.width, state.height))
+    }
+
+    /// Copies the shm buffer attached to the cursor surface of `pointer`
+    fn copy_cursor_bitmap(&self, pointer: &SurfacePointer) -> Option<BitmapInfo> {
+        let cursor_surface = self.surface_map.get(&pointer.cursor_surface?)?;
+        let cursor_buffer = cursor_surface.active.read().unwrap().buffer?;
+        let buffer = self.shm_buffer_map.get(&cursor_buffer)?;
+        unsafe { self.copy_surface_buffer(&buffer) }
+    }
+
+    /// Snapshots the cursor over `surface`, the position is known from pointer
+    /// events alone so apps that leave the cursor to the compositor still
+    /// report one, without a bitmap consumers can draw their own cursor. Apps
+    /// hiding the cursor report an empty bitmap.
     pub fn snapshot_cursor(&self, serial: u64, surface: WlHandle) -> Option<WlCursorSnapshot> {
         let surface = self.surface_map.get(&surface)?;
         let pointer = surface.entered_pointer.read().unwrap();
         let pointer = pointer.as_ref()?;
-        let surface = self.surface_map.get(&pointer.cursor_surface?)?;
-        let surface_buffer = surface.active_buffer.read().unwrap();
-        let surface_buffer = surface_buffer.as_ref()?;
-        let buffer = self.shm_buffer_map.get(&surface_buffer.buffer)?;
 
-        let bitmap = if serial != pointer.serial as u64 || serial == 0 {
-            unsafe { self.copy_surface_buffer(&buffer) }
+        let bitmap = if serial != pointer.cursor_serial || serial == 0 {
+            if pointer.cursor_hidden {
+                // consumers stop drawing the cursor on empty bitmaps
+                Some(BitmapInfo {
+                    width: 0,
+                    height: 0,
+                    bpp: 4,
+                    format: 0,
+                    data: vec![],
+                })
+            } else {
+                self.copy_cursor_bitmap(pointer)
+            }
         } else {
             None
         };
 
-        let scale = surface_buffer.scale;
-        let x: i32 = pointer.surface_x.checked_mul_int(scale)?.round().to_num();
-        let y: i32 = pointer.surface_y.checked_mul_int(scale)?.round().to_num();
+        // captured frames are buffers of the surface, not of its logical size
+        let attrs = *surface.active.read().unwrap();
+        let buffer_size = attrs.buffer.and_then(|v| self.buffer_size(v));
+        let fractional_scale = *surface.fractional_scale.read().unwrap();
+        let (x, y) = attrs.surface_to_buffer(
+            pointer.surface_x.to_num(),
+            pointer.surface_y.to_num(),
+            buffer_size,
+            fractional_scale,
+        );
         Some(WlCursorSnapshot {
-            serial: pointer.serial as _,
+            serial: pointer.cursor_serial,
             entered: true,
-            position: (x, y),
+            position: (x.round() as _, y.round() as _),
             hotspot: (pointer.hotspot_x, pointer.hotspot_y),
             bitmap,
         })
     }
+
+    /// Title and app id the xdg_toplevel of `surface` set, its buffer
+    /// transform and whether it is suspended, if changed since `serial`
+    pub fn window_info(&self, serial: u64, surface: WlHandle) -> Option<WindowInfo> {
+        let surface = self.surface_map.get(&surface)?;
+        let window = surface.window.read().unwrap();
+        (window.serial != serial).then(|| window.clone())
+    }
 }
 
 impl WlIntercept {
@@ -293,9 +450,11 @@ impl WlIntercept {
             surface,
             SurfaceState {
                 g_compositor,
-                active_buffer: RwLock::new(None),
-                pending_buffer: RwLock::new(None),
+                active: RwLock::new(Default::default()),
+                pending: RwLock::new(Default::default()),
+                fractional_scale: RwLock::new(None),
                 entered_pointer: RwLock::new(None),
+                window: RwLock::new(Default::default()),
             },
         );
     }
@@ -334,39 +493,147 @@ impl WlIntercept {
         Some(())
     }
 
-    fn m_surface_attach(&self, surface: WlHandle, buffer: WlHandle) -> Option<()> {
+    fn m_surface_attach(&self, surface: WlHandle, buffer: Option<WlHandle>) -> Option<()> {
         let surface = self.surface_map.get(&surface)?;
-        let mut pending_buffer = surface.pending_buffer.write().unwrap();
-        if let Some(state) = pending_buffer.as_mut() {
-            state.buffer = buffer;
-        } else {
-            *pending_buffer = Some(SurfaceBuffer {
-                buffer: unsafe { WlHandle::from_raw(0) },
-                scale: 1,
-            })
-        }
+        surface.pending.write().unwrap().buffer = buffer;
         Some(())
     }
 
     fn m_surface_set_buffer_scale(&self, surface: WlHandle, scale: i32) -> Option<()> {
         let surface = self.surface_map.get(&surface)?;
-        let mut pending_buffer = surface.pending_buffer.write().unwrap();
-        if let Some(state) = pending_buffer.as_mut() {
-            state.scale = scale;
-        } else {
-            *pending_buffer = Some(SurfaceBuffer {
-                buffer: unsafe { WlHandle::from_raw(0) },
-                scale,
-            })
-        }
+        surface.pending.write().unwrap().scale = scale;
         Some(())
     }
 
-    fn m_surface_commit(&self, surface: WlHandle) -> Option<()> {
+    fn m_surface_set_buffer_transform(&self, surface: WlHandle, transform: i32) -> Option<()> {
         let surface = self.surface_map.get(&surface)?;
-        let mut pending_buffer = surface.pending_buffer.write().unwrap();
-        let mut active_buffer = surface.active_buffer.write().unwrap();
-        *active_buffer = pending_buffer.take();
+        surface.pending.write().unwrap().transform = transform;
+        Some(())
+    }
+
+    fn m_surface_commit(&self, surface_handle: WlHandle) -> Option<()> {
+        let surface = self.surface_map.get(&surface_handle)?;
+        // attributes not set again are kept on next commit
+        let pending = *surface.pending.read().unwrap();
+        let mut window = surface.window.write().unwrap();
+        if window.buffer_transform != pending.transform {
+            debug!(
+                "buffer transform of {:?}: {}",
+                surface_handle, pending.transform
+            );
+            window.buffer_transform = pending.transform;
+            window.serial += 1;
+        }
+        *surface.active.write().unwrap() = pending;
+        Some(())
+    }
+
+    fn m_linux_buffer_params_create_immed(&self, width: i32, height: i32, buffer: WlHandle) {
+        self.dmabuf_buffer_map
+            .insert(buffer, DmabufBufferState { width, height });
+    }
+
+    fn m_viewporter_get_viewport(&self, viewport: WlHandle, surface: WlHandle) {
+        self.viewport_map
+            .insert(viewport, ViewportState { surface });
+    }
+
+    fn m_viewport_set_source(
+        &self,
+        viewport: WlHandle,
+        source: Option<[wl_fixed_t; 4]>,
+    ) -> Option<()> {
+        let viewport = self.viewport_map.get(&viewport)?;
+        let surface = self.surface_map.get(&viewport.surface)?;
+        surface.pending.write().unwrap().viewport_source = source;
+        Some(())
+    }
+
+    fn m_viewport_set_destination(
+        &self,
+        viewport: WlHandle,
+        destination: Option<(i32, i32)>,
+    ) -> Option<()> {
+        let viewport = self.viewport_map.get(&viewport)?;
+        let surface = self.surface_map.get(&viewport.surface)?;
+        surface.pending.write().unwrap().viewport_destination = destination;
+        Some(())
+    }
+
+    fn m_viewport_destroy(&self, viewport: WlHandle) -> Option<()> {
+        // viewport is removed from the surface on next commit
+        let (_, viewport) = self.viewport_map.remove(&viewport)?;
+        let surface = self.surface_map.get(&viewport.surface)?;
+        let mut pending = surface.pending.write().unwrap();
+        pending.viewport_source = None;
+        pending.viewport_destination = None;
+        Some(())
+    }
+
+    fn m_fractional_scale_manager_get_fractional_scale(
+        &self,
+        fractional_scale: WlHandle,
+        surface: WlHandle,
+    ) {
+        self.fractional_scale_map
+            .insert(fractional_scale, FractionalScaleState { surface });
+    }
+
+    fn m_fractional_scale_destroy(&self, fractional_scale: WlHandle) -> Option<()> {
+        let (_, fractional_scale) = self.fractional_scale_map.remove(&fractional_scale)?;
+        let surface = self.surface_map.get(&fractional_scale.surface)?;
+        *surface.fractional_scale.write().unwrap() = None;
+        Some(())
+    }
+
+    fn m_wm_base_get_xdg_surface(&self, xdg_surface: WlHandle, surface: WlHandle) {
+        self.xdg_surface_map
+            .insert(xdg_surface, XdgSurfaceState { surface });
+    }
+
+    fn m_xdg_surface_get_toplevel(&self, xdg_surface: WlHandle, toplevel: WlHandle) -> Option<()> {
+        let xdg_surface = self.xdg_surface_map.get(&xdg_surface)?;
+        self.xdg_toplevel_map.insert(
+            toplevel,
+            XdgToplevelState {
+                surface: xdg_surface.surface,
+            },
+        );
+        Some(())
+    }
+
+    fn m_xdg_toplevel_set_window(
+        &self,
+        toplevel: WlHandle,
+        title: Option<String>,
+        app_id: Option<String>,
+    ) -> Option<()> {
+        let toplevel = self.xdg_toplevel_map.get(&toplevel)?;
+        let surface = self.surface_map.get(&toplevel.surface)?;
+        let mut window = surface.window.write().unwrap();
+        if title.is_some() && window.title != title {
+            debug!("window title of {:?}: {:?}", toplevel.surface, title);
+            window.title = title;
+            window.serial += 1;
+        }
+        if app_id.is_some() && window.app_id != app_id {
+            debug!("window app id of {:?}: {:?}", toplevel.surface, app_id);
+            window.app_id = app_id;
+            window.serial += 1;
+        }
+        Some(())
+    }
+
+    fn e_xdg_toplevel_configure(&self, toplevel: WlHandle, states: &[u32]) -> Option<()> {
+        let toplevel = self.xdg_toplevel_map.get(&toplevel)?;
+        let surface = self.surface_map.get(&toplevel.surface)?;
+        let suspended = states.contains(&XDG_TOPLEVEL_STATE_SUSPENDED);
+        let mut window = surface.window.write().unwrap();
+        if window.suspended != suspended {
+            debug!("window {:?} suspended: {}", toplevel.surface, suspended);
+            window.suspended = suspended;
+            window.serial += 1;
+        }
         Some(())
     }
 
@@ -384,7 +651,7 @@ impl WlIntercept {
         &self,
         pointer: WlHandle,
         serial: u32,
-        cursor_surface: WlHandle,
+        cursor_surface: Option<WlHandle>,
         hotspot_x: i32,
         hotspot_y: i32,
     ) -> Option<()> {
@@ -399,12 +666,25 @@ impl WlIntercept {
                 serial, pointer.serial
             );
         }
-        pointer.cursor_surface = Some(cursor_surface);
+        pointer.cursor_serial = CURSOR_SERIAL.fetch_add(1, Ordering::Relaxed);
+        pointer.cursor_surface = cursor_surface;
+        pointer.cursor_hidden = cursor_surface.is_none();
         pointer.hotspot_x = hotspot_x;
         pointer.hotspot_y = hotspot_y;
         Some(())
     }
 
+    fn e_fractional_scale_preferred_scale(
+        &self,
+        fractional_scale: WlHandle,
+        scale: u32,
+    ) -> Option<()> {
+        let fractional_scale = self.fractional_scale_map.get(&fractional_scale)?;
+        let surface = self.surface_map.get(&fractional_scale.surface)?;
+        *surface.fractional_scale.write().unwrap() = Some(scale);
+        Some(())
+    }
+
     fn m_pointer_release(&self, pointer: WlHandle) {
         self.pointer_map.remove(&pointer);
     }
@@ -422,7 +702,9 @@ impl WlIntercept {
         *entered_pointer = Some(SurfacePointer {
             pointer,
             serial,
+            cursor_serial: CURSOR_SERIAL.fetch_add(1, Ordering::Relaxed),
             cursor_surface: None,
+            cursor_hidden: false,
             hotspot_x: 0,
             hotspot_y: 0,
             motion_time: 0,
@@ -525,31 +807,101 @@ impl WlIntercept {
             }
             ("wl_buffer", "destroy") => {
                 self.shm_buffer_map.remove(&proxy);
+                self.dmabuf_buffer_map.remove(&proxy);
+            }
+            ("zwp_linux_buffer_params_v1", "create_immed") => {
+                let new_proxy = args[0].o;
+                let width = args[1].i;
+                let height = args[2].i;
+                self.m_linux_buffer_params_create_immed(width, height, wlhandle!(new_proxy as _));
             }
             ("wl_surface", "attach") => {
                 let buffer = args[0].o;
-                self.m_surface_attach(proxy, wlhandle!(buffer as _));
+                let buffer = (!buffer.is_null()).then(|| wlhandle!(buffer as _));
+                self.m_surface_attach(proxy, buffer);
             }
             ("wl_surface", "set_buffer_scale") => {
                 let scale = args[0].i;
                 self.m_surface_set_buffer_scale(proxy, scale);
             }
+            ("wl_surface", "set_buffer_transform") => {
+                let transform = args[0].i;
+                self.m_surface_set_buffer_transform(proxy, transform);
+            }
             ("wl_surface", "commit") => {
                 self.m_surface_commit(proxy);
             }
             ("wl_surface", "destroy") => {
                 self.surface_map.remove(&proxy);
             }
+            ("wp_viewporter", "get_viewport") => {
+                let new_proxy = args[0].o;
+                let surface = args[1].o;
+                self.m_viewporter_get_viewport(wlhandle!(new_proxy as _), wlhandle!(surface as _));
+            }
+            ("wp_viewport", "set_source") => {
+                let source = [args[0].f, args[1].f, args[2].f, args[3].f];
+                // all -1 unsets the source rectangle
+                let unset = source.iter().all(|&v| v == wl_fixed_t::from_num(-1));
+                self.m_viewport_set_source(proxy, (!unset).then_some(source));
+            }
+            ("wp_viewport", "set_destination") => {
+                let width = args[0].i;
+                let height = args[1].i;
+                let unset = width == -1 && height == -1;
+                self.m_viewport_set_destination(proxy, (!unset).then_some((width, height)));
+            }
+            ("wp_viewport", "destroy") => {
+                self.m_viewport_destroy(proxy);
+            }
+            ("wp_fractional_scale_manager_v1", "get_fractional_scale") => {
+                let new_proxy = args[0].o;
+                let surface = args[1].o;
+                self.m_fractional_scale_manager_get_fractional_scale(
+                    wlhandle!(new_proxy as _),
+                    wlhandle!(surface as _),
+                );
+            }
+            ("wp_fractional_scale_v1", "destroy") => {
+                self.m_fractional_scale_destroy(proxy);
+            }
+            ("xdg_wm_base", "get_xdg_surface") => {
+                let new_proxy = args[0].o;
+                let surface = args[1].o;
+                self.m_wm_base_get_xdg_surface(wlhandle!(new_proxy as _), wlhandle!(surface as _));
+            }
+            ("xdg_surface", "get_toplevel") => {
+                let new_proxy = args[0].o;
+                self.m_xdg_surface_get_toplevel(proxy, wlhandle!(new_proxy as _));
+            }
+            ("xdg_surface", "destroy") => {
+                self.xdg_surface_map.remove(&proxy);
+            }
+            ("xdg_toplevel", "set_title") => {
+                let title = (!args[0].s.is_null())
+                    .then(|| CStr::from_ptr(args[0].s).to_string_lossy().to_string());
+                self.m_xdg_toplevel_set_window(proxy, title, None);
+            }
+            ("xdg_toplevel", "set_app_id") => {
+                let app_id = (!args[0].s.is_null())
+                    .then(|| CStr::from_ptr(args[0].s).to_string_lossy().to_string());
+                self.m_xdg_toplevel_set_window(proxy, None, app_id);
+            }
+            ("xdg_toplevel", "destroy") => {
+                self.xdg_toplevel_map.remove(&proxy);
+            }
             ("wl_seat", "get_pointer") => {
                 let new_proxy = args[0].o;
                 self.m_seat_get_pointer(proxy, wlhandle!(new_proxy as _));
             }
             ("wl_pointer", "set_cursor") => {
                 let serial = args[0].u;
+                // null surface hides the cursor
                 let surface = args[1].o;
+                let surface = (!surface.is_null()).then(|| wlhandle!(surface as _));
                 let hot_x = args[2].i;
                 let hot_y = args[3].i;
-                self.m_pointer_set_cursor(proxy, serial, wlhandle!(surface as _), hot_x, hot_y);
+                self.m_pointer_set_cursor(proxy, serial, surface, hot_x, hot_y);
             }
             ("wl_pointer", "release") => {
                 self.m_pointer_release(proxy);
@@ -582,7 +934,10 @@ impl WlIntercept {
 
     unsafe fn collect_event_filter(&self, interface: &wl_interface) -> bool {
         let interface_name = CStr::from_ptr(interface.name).to_string_lossy();
-        interface_name.as_ref() == "wl_pointer"
+        matches!(
+            interface_name.as_ref(),
+            "wl_pointer" | "wp_fractional_scale_v1" | "xdg_toplevel"
+        )
     }
 
     unsafe fn collect_event(
@@ -617,6 +972,20 @@ impl WlIntercept {
                 let surface = args[1].o;
                 self.e_pointer_leave(proxy, serial, wlhandle!(surface as _));
             }
+            ("wp_fractional_scale_v1", "preferred_scale") => {
+                let scale = args[0].u;
+                self.e_fractional_scale_preferred_scale(proxy, scale);
+            }
+            ("xdg_toplevel", "configure") => {
+                let states = args[2].a;
+                let states = if states.is_null() || (*states).data.is_null() {
+                    &[]
+                } else {
+                    let len = (*states).size / mem::size_of::<u32>();
+                    slice::from_raw_parts((*states).data as *const u32, len)
+                };
+                self.e_xdg_toplevel_configure(proxy, states);
+            }
             _ => (),
         }
     }
@@ -1,42 +1,386 @@
-use crate::{CursorManager, CursorSnapshot, OwnedMem};
+use crate::utils::*;
+use crate::{CursorManager, CursorSnapshot, OwnedMem, WindowInfo};
 
-use core::ffi::c_void;
+use core::ffi::{c_char, c_int, c_void, CStr};
+use core::mem;
 use core::ptr;
 use core::slice;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::os::unix::ffi::OsStringExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
 
 use anyhow::{anyhow, Result};
+use fixed::types::extra::U16;
+use fixed::FixedI32;
+use log::{debug, warn};
+use once_cell::sync::Lazy;
 use xcb_dl::ffi as xcb_t;
 use xcb_dl::Xcb;
 use xcb_dl::XcbXfixes;
+use xcb_dl::XcbXinput;
 use xcb_t::xcb_connection_t;
 
+#[allow(non_camel_case_types)]
+type fp1616 = FixedI32<U16>;
+
+/// `Virtual core pointer`, used if the client pointer can not be queried
+const CORE_POINTER_ID: u16 = 2;
+
+/// How long the event thread sleeps before checking whether the connection
+/// is still in use
+const EVENT_POLL_TIMEOUT_MS: c_int = 500;
+
+// events selected on windows of the app, only reported to the connection of
+// the layer that selected them
+const XCB_CW_EVENT_MASK: u32 = 1 << 11;
+const XCB_EVENT_MASK_VISIBILITY_CHANGE: u32 = 1 << 16;
+const XCB_EVENT_MASK_STRUCTURE_NOTIFY: u32 = 1 << 17;
+const XCB_VISIBILITY_NOTIFY: u8 = 15;
+const XCB_UNMAP_NOTIFY: u8 = 18;
+const XCB_MAP_NOTIFY: u8 = 19;
+const XCB_VISIBILITY_FULLY_OBSCURED: u8 = 2;
+const XCB_MAP_STATE_VIEWABLE: u8 = 2;
+
+/// Connections of the layer by display, see [`XcbConnection`]
+static CONNECTIONS: Lazy<Mutex<HashMap<CString, Weak<XcbConnection>>>> =
+    Lazy::new(Default::default);
+
+#[allow(non_upper_case_globals)]
+static XDisplayString: Lazy<Option<unsafe extern "C" fn(dpy: *mut c_void) -> *const c_char>> =
+    Lazy::new(|| unsafe {
+        let h = dlopen(&[cstr!(b"libX11.so.6\0"), cstr!(b"libX11.so\0")])?;
+        let sym = libc::dlsym(h, cstr!(b"XDisplayString\0").as_ptr());
+        if sym.is_null() {
+            return None;
+        }
+        Some(mem::transmute(sym))
+    });
+
+/// Connection opened by the layer
+struct ConnectionInner {
+    conn: usize,
+    xcb: Xcb,
+    display: CString,
+    closed: AtomicBool,
+    /// Windows visibility events are selected on, updated by the event thread
+    windows: Mutex<HashMap<u32, WindowVisibility>>,
+}
+
+/// Visibility of a window on a connection of the layer
+#[derive(Default)]
+struct WindowVisibility {
+    /// Managers of the window
+    refs: usize,
+    /// Bumped once the window got hidden or shown
+    serial: u64,
+    /// Unmapped, e.g. minimized by the window manager
+    unmapped: bool,
+    /// Reported fully obscured, only without a compositing manager
+    obscured: bool,
+}
+
+impl WindowVisibility {
+    fn hidden(&self) -> bool {
+        self.unmapped || self.obscured
+    }
+
+    fn update(&mut self, f: impl FnOnce(&mut Self)) {
+        let hidden = self.hidden();
+        f(self);
+        if hidden != self.hidden() {
+            self.serial += 1;
+        }
+    }
+}
+
+/// Connection of the layer to an X server, shared by all windows on the same
+/// display and closed once the last of them is gone.
+///
+/// Connections of the app can not be used from the threads cursors are
+/// snapshotted on without errors of the layer ending up in the event queue of
+/// the app, so the layer connects on its own. Nothing reads events of that
+/// connection otherwise, a background thread drains them, i.e. errors of
+/// requests on windows already destroyed and map and visibility changes of
+/// windows captured, and closes the connection once done.
+struct XcbConnection {
+    inner: Arc<ConnectionInner>,
+}
+
+/// XInput2 extension and the master pointer of the connection
+struct XInput {
+    xinput: XcbXinput,
+    device_id: u16,
+}
+
 pub struct XcbWindow {
     conn: usize,
-    to_close_conn: bool,
+    /// Keeps the connection open if opened by the layer
+    shared: Option<Arc<XcbConnection>>,
     window: u32,
     xcb: Xcb,
     xfixes: XcbXfixes,
+    /// Queries pointer position relative to the window if XInput2 is available
+    xinput: Option<XInput>,
 }
 
 pub struct XcbCursor {
     geometry: OwnedMem<xcb_t::xcb_get_geometry_reply_t>,
     translate_coordinates: OwnedMem<xcb_t::xcb_translate_coordinates_reply_t>,
     cursor_image: OwnedMem<xcb_t::xcb_xfixes_get_cursor_image_reply_t>,
+    /// Window relative position queried through XInput2
+    pointer: Option<(i32, i32)>,
     pixels: Option<ptr::NonNull<u8>>,
     serial: u64,
 }
 
-impl XcbWindow {
-    unsafe fn new_internal(conn: *mut xcb_connection_t, window: u32) -> Result<Self> {
+impl XInput {
+    unsafe fn new(conn: *mut xcb_connection_t) -> Result<Self> {
+        let xinput = XcbXinput::load_loose()?;
+
+        let cookie = xinput.xcb_input_xi_query_version_unchecked(conn, 2, 0);
+        let reply = xinput.xcb_input_xi_query_version_reply(conn, cookie, ptr::null_mut());
+        let reply = OwnedMem::new(reply).ok_or(anyhow!("query xinput version failed"))?;
+        if reply.as_ref().major_version < 2 {
+            return Err(anyhow!("XInput2 not supported"));
+        }
+
+        // pointer of this client, i.e. the one the app sees
+        let cookie = xinput.xcb_input_xi_get_client_pointer_unchecked(conn, 0);
+        let reply = xinput.xcb_input_xi_get_client_pointer_reply(conn, cookie, ptr::null_mut());
+        let device_id = match OwnedMem::new(reply) {
+            Some(reply) if reply.as_ref().deviceid != 0 => reply.as_ref().deviceid,
+            _ => CORE_POINTER_ID,
+        };
+
+        Ok(Self { xinput, device_id })
+    }
+}
+
+impl XcbConnection {
+    /// Connection to `display`, opened unless already shared and working
+    unsafe fn get(display: &CStr) -> Result<Arc<Self>> {
+        let key = display_key(display);
+        let mut connections = CONNECTIONS.lock().unwrap();
+        connections.retain(|_, v| v.strong_count() > 0);
+        let shared = connections.get(&key).and_then(Weak::upgrade);
+        if let Some(v) = shared.filter(|v| !v.inner.broken()) {
+            return Ok(v);
+        }
+        let res = Arc::new(Self::connect(display)?);
+        connections.insert(key, Arc::downgrade(&res));
+        Ok(res)
+    }
+
+    unsafe fn connect(display: &CStr) -> Result<Self> {
         let xcb = Xcb::load_loose()?;
-        let xfixes = XcbXfixes::load_loose()?;
+        let conn = xcb.xcb_connect(display.as_ptr(), ptr::null_mut());
+        if xcb.xcb_connection_has_error(conn) != 0 {
+            xcb.xcb_disconnect(conn);
+            return Err(anyhow!("failed to connect to X display {display:?}"));
+        }
+        debug!("connected to X display {display:?}");
+        let inner = Arc::new(ConnectionInner {
+            conn: conn as _,
+            xcb,
+            display: display.to_owned(),
+            closed: AtomicBool::new(false),
+            windows: Default::default(),
+        });
+        let thread_inner = inner.clone();
+        thread::Builder::new()
+            .name("pw-capture-xcb".into())
+            .spawn(move || thread_inner.drain_events())?;
+        Ok(Self { inner })
+    }
+}
+
+impl Drop for XcbConnection {
+    fn drop(&mut self) {
+        // not joined, the event thread disconnects within
+        // `EVENT_POLL_TIMEOUT_MS`
+        self.inner.closed.store(true, Ordering::Release);
+    }
+}
+
+impl ConnectionInner {
+    fn broken(&self) -> bool {
+        unsafe { self.xcb.xcb_connection_has_error(self.conn as _) != 0 }
+    }
+
+    /// Selects map and visibility events on `window`, tracking whether it is
+    /// hidden until [`unwatch_window`](Self::unwatch_window)
+    unsafe fn watch_window(&self, window: u32) {
+        let conn = self.conn as *mut xcb_connection_t;
+        let mut windows = self.windows.lock().unwrap();
+        let visibility = windows.entry(window).or_default();
+        visibility.refs += 1;
+        if visibility.refs > 1 {
+            return;
+        }
+        let mask = [XCB_EVENT_MASK_STRUCTURE_NOTIFY | XCB_EVENT_MASK_VISIBILITY_CHANGE];
+        self.xcb
+            .xcb_change_window_attributes(conn, window, XCB_CW_EVENT_MASK, mask.as_ptr() as _);
+        // events only tell changes from here on
+        let cookie = self.xcb.xcb_get_window_attributes_unchecked(conn, window);
+        let reply = self
+            .xcb
+            .xcb_get_window_attributes_reply(conn, cookie, ptr::null_mut());
+        if let Some(reply) = OwnedMem::new(reply) {
+            let unmapped = reply.as_ref().map_state != XCB_MAP_STATE_VIEWABLE;
+            visibility.update(|v| v.unmapped = unmapped);
+        }
+    }
 
-        let (conn, to_close_conn) = if conn.is_null() {
-            let conn = xcb.xcb_connect(ptr::null(), ptr::null_mut());
-            (conn, true)
-        } else {
-            (conn, false)
+    fn unwatch_window(&self, window: u32) {
+        let mut windows = self.windows.lock().unwrap();
+        if let Some(visibility) = windows.get_mut(&window) {
+            visibility.refs -= 1;
+            if visibility.refs == 0 {
+                windows.remove(&window);
+            }
+        }
+    }
+
+    /// Applies map and visibility events to windows watched
+    unsafe fn on_event(&self, event: *const xcb_t::xcb_generic_event_t) {
+        let (window, unmapped, obscured) = match (*event).response_type & 0x7f {
+            XCB_UNMAP_NOTIFY => {
+                let event = &*(event as *const xcb_t::xcb_unmap_notify_event_t);
+                (event.window, Some(true), None)
+            }
+            XCB_MAP_NOTIFY => {
+                let event = &*(event as *const xcb_t::xcb_map_notify_event_t);
+                (event.window, Some(false), None)
+            }
+            XCB_VISIBILITY_NOTIFY => {
+                let event = &*(event as *const xcb_t::xcb_visibility_notify_event_t);
+                let obscured = event.state == XCB_VISIBILITY_FULLY_OBSCURED;
+                (event.window, None, Some(obscured))
+            }
+            _ => return,
         };
+        let mut windows = self.windows.lock().unwrap();
+        if let Some(visibility) = windows.get_mut(&window) {
+            visibility.update(|v| {
+                v.unmapped = unmapped.unwrap_or(v.unmapped);
+                v.obscured = obscured.unwrap_or(v.obscured);
+            });
+            debug!("window {window:#x} hidden: {}", visibility.hidden());
+        }
+    }
+
+    /// Frees events and errors until the connection is no longer used or
+    /// broken
+    fn drain_events(&self) {
+        let conn = self.conn as *mut xcb_connection_t;
+        unsafe {
+            let fd = self.xcb.xcb_get_file_descriptor(conn);
+            while !self.closed.load(Ordering::Acquire) {
+                let mut pollfd = libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                libc::poll(&mut pollfd, 1, EVENT_POLL_TIMEOUT_MS);
+                loop {
+                    let event = self.xcb.xcb_poll_for_event(conn);
+                    if event.is_null() {
+                        break;
+                    }
+                    if (*event).response_type == 0 {
+                        let error = &*(event as *const xcb_t::xcb_generic_error_t);
+                        debug!("X error {} on {:?}", error.error_code, self.display);
+                    } else {
+                        self.on_event(event);
+                    }
+                    libc::free(event as _);
+                }
+                if self.broken() {
+                    warn!("connection to X display {:?} broke", self.display);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionInner {
+    fn drop(&mut self) {
+        debug!("disconnecting from X display {:?}", self.display);
+        unsafe { self.xcb.xcb_disconnect(self.conn as _) }
+    }
+}
+
+/// `DISPLAY` the app was started with
+fn default_display() -> Option<CString> {
+    let display = env::var_os("DISPLAY")?;
+    CString::new(display.into_vec()).ok()
+}
+
+/// Display without the screen number, as connections are not bound to screens
+fn display_key(display: &CStr) -> CString {
+    let bytes = display.to_bytes();
+    let host_len = bytes.iter().rposition(|&c| c == b':').unwrap_or(0);
+    let len = bytes[host_len..]
+        .iter()
+        .position(|&c| c == b'.')
+        .map_or(bytes.len(), |v| host_len + v);
+    CString::new(&bytes[..len]).unwrap()
+}
+
+/// Display an app connection of `xcb` was opened to, from the address of the
+/// X server it is connected to
+unsafe fn peer_display(xcb: &Xcb, conn: *mut xcb_connection_t) -> Option<CString> {
+    let fd = xcb.xcb_get_file_descriptor(conn);
+    let mut addr: libc::sockaddr_storage = mem::zeroed();
+    let mut len = mem::size_of_val(&addr) as libc::socklen_t;
+    if libc::getpeername(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) != 0 {
+        return None;
+    }
+    let display = match addr.ss_family as c_int {
+        libc::AF_UNIX => {
+            let addr = &*(&addr as *const _ as *const libc::sockaddr_un);
+            let path_len = (len as usize).saturating_sub(mem::size_of::<libc::sa_family_t>());
+            let path: Vec<u8> = addr.sun_path[..path_len.min(addr.sun_path.len())]
+                .iter()
+                .map(|&c| c as u8)
+                .collect();
+            // abstract sockets start with a NUL
+            let path = path.strip_prefix(b"\0").unwrap_or(&path);
+            let path = path.split(|&c| c == 0).next()?;
+            let number = path.strip_prefix(b"/tmp/.X11-unix/X")?;
+            format!(":{}", std::str::from_utf8(number).ok()?)
+        }
+        libc::AF_INET => {
+            let addr = &*(&addr as *const _ as *const libc::sockaddr_in);
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            let number = u16::from_be(addr.sin_port).checked_sub(6000)?;
+            format!("{ip}:{number}")
+        }
+        libc::AF_INET6 => {
+            let addr = &*(&addr as *const _ as *const libc::sockaddr_in6);
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            let number = u16::from_be(addr.sin6_port).checked_sub(6000)?;
+            format!("[{ip}]:{number}")
+        }
+        _ => return None,
+    };
+    CString::new(display).ok()
+}
+
+impl XcbWindow {
+    unsafe fn new_internal(
+        conn: *mut xcb_connection_t,
+        shared: Option<Arc<XcbConnection>>,
+        window: u32,
+    ) -> Result<Self> {
+        let xcb = Xcb::load_loose()?;
+        let xfixes = XcbXfixes::load_loose()?;
 
         let cookie = xfixes.xcb_xfixes_query_version_unchecked(conn, 6, 0);
         let reply = xfixes.xcb_xfixes_query_version_reply(conn, cookie, ptr::null_mut());
@@ -47,33 +391,104 @@ impl XcbWindow {
         let reply = xcb.xcb_get_geometry_reply(conn as _, geometry_cookie, ptr::null_mut());
         let _geometry = OwnedMem::new(reply).ok_or(anyhow!("xcb_get_geometry failed"))?;
 
+        let xinput = XInput::new(conn)
+            .map_err(|e| debug!("pointer position from xfixes, no xinput: {e:?}"))
+            .ok();
+
+        // events of app connections are the app's to read
+        if let Some(shared) = shared.as_ref() {
+            shared.inner.watch_window(window);
+        }
+
         Ok(Self {
             conn: conn as _,
-            to_close_conn,
+            shared,
             window,
             xcb,
             xfixes,
+            xinput,
         })
     }
 
+    /// Queries on `conn` directly, which must only be used by the calling
+    /// thread and stay open as long as the returned manager
     pub unsafe fn new(conn: ptr::NonNull<c_void>, window: u32) -> Result<Self> {
-        Self::new_internal(conn.as_ptr() as _, window)
+        Self::new_internal(conn.as_ptr() as _, None, window)
+    }
+
+    /// Queries on the connection of the layer to `display`, falling back to
+    /// `DISPLAY` if unknown or `window` does not exist there
+    pub unsafe fn for_display(display: Option<&CStr>, window: u32) -> Result<Self> {
+        let mut displays: Vec<CString> = display.map(CStr::to_owned).into_iter().collect();
+        if let Some(default) = default_display() {
+            if !displays
+                .iter()
+                .any(|v| display_key(v) == display_key(&default))
+            {
+                displays.push(default);
+            }
+        }
+
+        let mut res = Err(anyhow!("no X display to find window {window:#x} on"));
+        for display in displays {
+            res = XcbConnection::get(&display).and_then(|shared| {
+                let conn = shared.inner.conn as _;
+                Self::new_internal(conn, Some(shared), window)
+            });
+            match res {
+                Ok(_) => break,
+                Err(ref e) => debug!("window {window:#x} not on X display {display:?}: {e:?}"),
+            }
+        }
+        res
+    }
+
+    /// Queries on the connection of the layer to the display Xlib `dpy` of the
+    /// app was opened to
+    pub unsafe fn for_xlib_display(dpy: ptr::NonNull<c_void>, window: u32) -> Result<Self> {
+        let display = XDisplayString
+            .map(|f| f(dpy.as_ptr()))
+            .filter(|v| !v.is_null())
+            .map(|v| CStr::from_ptr(v));
+        Self::for_display(display, window)
+    }
+
+    /// Queries on the connection of the layer to the display XCB connection
+    /// `conn` of the app was opened to
+    pub unsafe fn for_xcb_connection(conn: ptr::NonNull<c_void>, window: u32) -> Result<Self> {
+        let xcb = Xcb::load_loose()?;
+        let display = peer_display(&xcb, conn.as_ptr() as _);
+        Self::for_display(display.as_deref(), window)
     }
 
+    /// Queries on the connection of the layer to `DISPLAY`
     pub unsafe fn new_connection(window: u32) -> Result<Self> {
-        Self::new_internal(ptr::null_mut(), window)
+        Self::for_display(None, window)
     }
 }
 
 impl Drop for XcbWindow {
     fn drop(&mut self) {
-        if self.to_close_conn {
-            unsafe { self.xcb.xcb_disconnect(self.conn as _) }
+        if let Some(shared) = self.shared.as_ref() {
+            shared.inner.unwatch_window(self.window);
         }
     }
 }
 
 impl CursorManager for XcbWindow {
+    /// Whether the window is hidden if changed since `serial`, windows queried
+    /// on connections of the app are never reported hidden
+    fn window_info(&self, serial: u64) -> Option<WindowInfo> {
+        let shared = self.shared.as_ref()?;
+        let windows = shared.inner.windows.lock().unwrap();
+        let visibility = windows.get(&self.window)?;
+        (visibility.serial != serial).then(|| WindowInfo {
+            serial: visibility.serial,
+            suspended: visibility.hidden(),
+            ..Default::default()
+        })
+    }
+
     fn snapshot_cursor(&self, serial: u64) -> Result<Box<dyn CursorSnapshot>> {
         let serial = (serial & u32::MAX as u64) as u32;
         unsafe {
@@ -100,6 +515,13 @@ impl CursorManager for XcbWindow {
             let cursor_cookie = self
                 .xfixes
                 .xcb_xfixes_get_cursor_image_unchecked(self.conn as _);
+            let pointer_cookie = self.xinput.as_ref().map(|x| {
+                x.xinput.xcb_input_xi_query_pointer_unchecked(
+                    self.conn as _,
+                    self.window,
+                    x.device_id,
+                )
+            });
 
             let reply = self.xcb.xcb_translate_coordinates_reply(
                 self.conn as _,
@@ -117,6 +539,29 @@ impl CursorManager for XcbWindow {
             let cursor_image =
                 OwnedMem::new(reply).ok_or(anyhow!("xcb_xfixes_get_cursor_image failed"))?;
 
+            // unlike the global position of xfixes, follows the pointer even
+            // if the cursor shape stays the same
+            let pointer = self
+                .xinput
+                .as_ref()
+                .zip(pointer_cookie)
+                .and_then(|(x, cookie)| {
+                    let reply = x.xinput.xcb_input_xi_query_pointer_reply(
+                        self.conn as _,
+                        cookie,
+                        ptr::null_mut(),
+                    );
+                    let reply = OwnedMem::new(reply)?;
+                    let reply = reply.as_ref();
+                    if reply.same_screen == 0 {
+                        return None;
+                    }
+                    Some((
+                        fp1616::from_bits(reply.win_x).to_num(),
+                        fp1616::from_bits(reply.win_y).to_num(),
+                    ))
+                });
+
             let image = self
                 .xfixes
                 .xcb_xfixes_get_cursor_image_cursor_image(cursor_image.as_ptr());
@@ -134,6 +579,7 @@ impl CursorManager for XcbWindow {
                 geometry,
                 translate_coordinates,
                 cursor_image,
+                pointer,
                 pixels,
                 serial: curr_serial,
             }))
@@ -161,6 +607,9 @@ impl CursorSnapshot for XcbCursor {
     }
 
     fn position(&self) -> (i32, i32) {
+        if let Some(pointer) = self.pointer {
+            return pointer;
+        }
         unsafe {
             let cursor_image = self.cursor_image.as_ref();
             let translate = self.translate_coordinates.as_ref();
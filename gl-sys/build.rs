@@ -34,14 +34,14 @@ fn main() {
         Profile::Core,
         Fallbacks::All,
         [
-            // "EGL_ANDROID_native_fence_sync",
+            "EGL_ANDROID_native_fence_sync",
             // "EGL_EXT_buffer_age",
             // "EGL_EXT_create_context_robustness",
             // "EGL_EXT_device_base",
-            // "EGL_EXT_device_drm",
+            "EGL_EXT_device_drm",
             // "EGL_EXT_device_drm_render_node",
             // "EGL_EXT_device_enumeration",
-            // "EGL_EXT_device_query",
+            "EGL_EXT_device_query",
             // "EGL_EXT_device_query_name",
             // "EGL_EXT_pixel_format_float",
             "EGL_EXT_platform_base",
@@ -52,6 +52,7 @@ fn main() {
             // "EGL_KHR_create_context",
             // "EGL_KHR_create_context_no_error",
             "EGL_KHR_fence_sync",
+            "EGL_KHR_partial_update",
             // "EGL_KHR_platform_android",
             // "EGL_KHR_platform_gbm",
             "EGL_KHR_platform_wayland",
@@ -73,7 +74,7 @@ fn main() {
         Fallbacks::All,
         [
             // "GLX_ARB_context_flush_control",
-            // "GLX_ARB_create_context",
+            "GLX_ARB_create_context",
             // "GLX_ARB_create_context_no_error",
             // "GLX_ARB_create_context_profile",
             // "GLX_ARB_create_context_robustness",
@@ -86,8 +87,8 @@ fn main() {
             "GLX_EXT_framebuffer_sRGB",
             "GLX_EXT_swap_control",
             "GLX_EXT_texture_from_pixmap",
-            // "GLX_MESA_swap_control",
-            // "GLX_SGI_swap_control",
+            "GLX_MESA_swap_control",
+            "GLX_SGI_swap_control",
             "GLX_OML_sync_control",
         ],
     )
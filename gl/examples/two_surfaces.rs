@@ -0,0 +1,93 @@
+//! Renders two EGL pbuffers with one context, each captured as a solid color
+
+use std::ffi::CString;
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+
+use libc::c_void;
+use pw_capture_gl_sys::prelude::*;
+
+const WIDTH: i32 = 320;
+const HEIGHT: i32 = 240;
+const COLORS: [[f32; 4]; 2] = [[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]];
+
+fn main() {
+    unsafe { run() }
+}
+
+unsafe fn run() {
+    let lib = libc::dlopen(
+        b"libEGL.so.1\0".as_ptr() as _,
+        libc::RTLD_NOW | libc::RTLD_GLOBAL,
+    );
+    assert!(!lib.is_null(), "failed to load libEGL.so.1");
+    // symbols resolved through dlsym() and eglGetProcAddress() get hooked
+    let egl = Egl::load_with(|name| {
+        let name = CString::new(name).unwrap();
+        libc::dlsym(lib, name.as_ptr()) as *const c_void
+    });
+    let gl = Gl::load_with(|name| {
+        let name = CString::new(name).unwrap();
+        egl.GetProcAddress(name.as_ptr()) as *const c_void
+    });
+
+    let dpy = egl.GetDisplay(ptr::null_mut());
+    assert!(!dpy.is_null(), "no EGL display");
+    let (mut major, mut minor) = (0, 0);
+    assert_eq!(egl.Initialize(dpy, &mut major, &mut minor), egl_sys::TRUE);
+    println!("EGL {major}.{minor}");
+
+    let config_attribs = [
+        egl_sys::SURFACE_TYPE as i32,
+        egl_sys::PBUFFER_BIT as _,
+        egl_sys::RENDERABLE_TYPE as _,
+        egl_sys::OPENGL_ES2_BIT as _,
+        egl_sys::RED_SIZE as _,
+        8,
+        egl_sys::GREEN_SIZE as _,
+        8,
+        egl_sys::BLUE_SIZE as _,
+        8,
+        egl_sys::NONE as _,
+    ];
+    let mut config: egl_t::EGLConfig = ptr::null();
+    let mut num: i32 = 0;
+    egl.ChooseConfig(dpy, config_attribs.as_ptr(), &mut config, 1, &mut num);
+    assert!(num > 0, "no pbuffer config");
+
+    let surface_attribs = [
+        egl_sys::WIDTH as i32,
+        WIDTH,
+        egl_sys::HEIGHT as _,
+        HEIGHT,
+        egl_sys::NONE as _,
+    ];
+    let surfaces = [(); 2].map(|_| {
+        let surface = egl.CreatePbufferSurface(dpy, config, surface_attribs.as_ptr());
+        assert!(!surface.is_null(), "failed to create pbuffer");
+        surface
+    });
+
+    egl.BindAPI(egl_sys::OPENGL_ES_API);
+    let context_attribs = [
+        egl_sys::CONTEXT_CLIENT_VERSION as i32,
+        2,
+        egl_sys::NONE as _,
+    ];
+    let context = egl.CreateContext(dpy, config, ptr::null(), context_attribs.as_ptr());
+    assert!(!context.is_null(), "failed to create context");
+
+    println!("rendering, capture nodes are created on first frames");
+    loop {
+        for (&surface, [r, g, b, a]) in surfaces.iter().zip(COLORS) {
+            egl.MakeCurrent(dpy, surface, surface, context);
+            gl.Viewport(0, 0, WIDTH, HEIGHT);
+            gl.ClearColor(r, g, b, a);
+            gl.Clear(gl_sys::COLOR_BUFFER_BIT);
+            // offscreen surfaces are captured on glFlush()
+            gl.Flush();
+        }
+        thread::sleep(Duration::from_millis(16));
+    }
+}
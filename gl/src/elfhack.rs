@@ -47,12 +47,68 @@ pub type ElfAddress = u64;
 #[cfg(target_pointer_width = "32")]
 pub type ElfAddress = u32;
 
+// relocation types of GOT slots holding addresses of functions, i.e. PLT
+// entries and address-taken or `-fno-plt` calls
+#[cfg(target_arch = "x86_64")]
+const GOT_RELOCATION_TYPES: &[u32] = &[
+    6, // R_X86_64_GLOB_DAT
+    7, // R_X86_64_JUMP_SLOT
+];
+#[cfg(target_arch = "x86")]
+const GOT_RELOCATION_TYPES: &[u32] = &[
+    6, // R_386_GLOB_DAT
+    7, // R_386_JMP_SLOT
+];
+#[cfg(target_arch = "aarch64")]
+const GOT_RELOCATION_TYPES: &[u32] = &[
+    1025, // R_AARCH64_GLOB_DAT
+    1026, // R_AARCH64_JUMP_SLOT
+];
+#[cfg(target_arch = "arm")]
+const GOT_RELOCATION_TYPES: &[u32] = &[
+    21, // R_ARM_GLOB_DAT
+    22, // R_ARM_JUMP_SLOT
+];
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "aarch64",
+    target_arch = "arm"
+)))]
+const GOT_RELOCATION_TYPES: &[u32] = &[];
+
+enum RelocationTable {
+    Rel(&'static [u8]),
+    Rela(&'static [u8]),
+}
+
+impl RelocationTable {
+    /// Offset, symbol index and type of each relocation
+    fn entries(&self) -> Box<dyn Iterator<Item = (ElfAddress, u32, u32)>> {
+        match *self {
+            Self::Rel(data) => Box::new(
+                elf::relocation::RelIterator::new(NativeEndian, ELF_CLASS, data)
+                    .map(|rel| (rel.r_offset as ElfAddress, rel.r_sym, rel.r_type)),
+            ),
+            Self::Rela(data) => Box::new(
+                elf::relocation::RelaIterator::new(NativeEndian, ELF_CLASS, data)
+                    .map(|rela| (rela.r_offset as ElfAddress, rela.r_sym, rela.r_type)),
+            ),
+        }
+    }
+}
+
 pub struct ObjectInfo {
     relocation: ElfAddress,
     gnu_hash_table: Option<elf::hash::GnuHashTable<'static, NativeEndian>>,
     sysv_hash_table: Option<elf::hash::SysVHashTable<'static, NativeEndian>>,
     string_table: elf::string_table::StringTable<'static>,
     symbol_table: elf::symbol::SymbolTable<'static, NativeEndian>,
+    relocation_tables: Vec<RelocationTable>,
+    /// Made read-only once relocated, i.e. GOT slots of objects linked with
+    /// `-z relro -z now`. Page aligned as protected by ld.so, the partial page
+    /// PT_GNU_RELRO ends in stays writable.
+    relro_range: Option<Range<ElfAddress>>,
 }
 
 impl ObjectInfo {
@@ -78,6 +134,93 @@ impl ObjectInfo {
         }
         Some(addr)
     }
+
+    /// Rewrites GOT slots of functions the object imports, `replace` gets the
+    /// symbol name and the address a slot holds and returns the address to
+    /// write instead, returns number of slots rewritten
+    ///
+    /// Slots of lazily bound functions not called yet hold addresses of PLT
+    /// stubs, which are overwritten all the same.
+    pub unsafe fn rewrite_got<F>(&self, mut replace: F) -> usize
+    where
+        F: FnMut(&str, *mut c_void) -> Option<*mut c_void>,
+    {
+        let mut count = 0;
+        for table in &self.relocation_tables {
+            for (offset, sym, r_type) in table.entries() {
+                if sym == 0 || !GOT_RELOCATION_TYPES.contains(&r_type) {
+                    continue;
+                }
+                let name = self
+                    .symbol_table
+                    .get(sym as usize)
+                    .and_then(|symbol| self.string_table.get(symbol.st_name as usize));
+                let name = match name {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+                let slot = (self.relocation + offset) as *mut *mut c_void;
+                let new = match replace(name, slot.read()) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                if self.write_slot(slot, new) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    unsafe fn write_slot(&self, slot: *mut *mut c_void, value: *mut c_void) -> bool {
+        let addr = slot as ElfAddress;
+        let relro = self
+            .relro_range
+            .as_ref()
+            .map_or(false, |v| v.contains(&addr));
+        if !relro {
+            slot.write(value);
+            return true;
+        }
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE) as ElfAddress;
+        let page = (addr & !(page_size - 1)) as *mut c_void;
+        if libc::mprotect(page, page_size as _, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+            return false;
+        }
+        slot.write(value);
+        libc::mprotect(page, page_size as _, libc::PROT_READ);
+        true
+    }
+}
+
+/// Whether `addr` lies within a loaded segment of the object
+pub unsafe fn object_contains(info: &dl_phdr_info, addr: usize) -> bool {
+    let addr = addr as ElfAddress;
+    let phdrs = slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as _);
+    phdrs.iter().any(|phdr| {
+        let start = info.dlpi_addr + phdr.p_vaddr;
+        phdr.p_type == libc::PT_LOAD && (start..start + phdr.p_memsz).contains(&addr)
+    })
+}
+
+/// Rewrites GOT slots of objects already loaded and accepted by `filter`, so
+/// functions they resolved before getting hooked through `dlsym` or symbol
+/// interposition get hooked as well, returns number of slots rewritten
+pub unsafe fn rewrite_loaded_got<P, F>(mut filter: P, mut replace: F) -> usize
+where
+    P: FnMut(&dl_phdr_info) -> bool,
+    F: FnMut(&str, *mut c_void) -> Option<*mut c_void>,
+{
+    let mut count = 0;
+    dl_iterate_phdr(|info| {
+        if filter(info) {
+            if let Some(obj) = ObjectInfo::new(info) {
+                count += obj.rewrite_got(&mut replace);
+            }
+        }
+        ControlFlow::Continue(())
+    });
+    count
 }
 
 unsafe fn parse_dl_phdr_info(info: &dl_phdr_info) -> Option<ObjectInfo> {
@@ -95,6 +238,15 @@ unsafe fn parse_dl_phdr_info(info: &dl_phdr_info) -> Option<ObjectInfo> {
     if mem_range.is_empty() {
         return None;
     }
+    let page_mask = !(libc::sysconf(libc::_SC_PAGESIZE) as ElfAddress - 1);
+    let relro_range = phdrs
+        .iter()
+        .find(|phdr| phdr.p_type == libc::PT_GNU_RELRO)
+        .map(|phdr| {
+            let start = relocation + phdr.p_vaddr;
+            (start & page_mask)..((start + phdr.p_memsz) & page_mask)
+        })
+        .filter(|v| !v.is_empty());
 
     let mut dyn_table: Option<elf::dynamic::DynamicTable<'static, elf::endian::NativeEndian>> =
         None;
@@ -119,9 +271,21 @@ unsafe fn parse_dl_phdr_info(info: &dl_phdr_info) -> Option<ObjectInfo> {
     let mut sysv_hash_table: Option<elf::hash::SysVHashTable<'static, NativeEndian>> = None;
     let mut string_table: Option<elf::string_table::StringTable<'static>> = None;
     let mut symbol_table: Option<elf::symbol::SymbolTable<'static, NativeEndian>> = None;
+    let mut jmp_rel: Option<&'static [u8]> = None;
+    let mut rela: Option<&'static [u8]> = None;
+    let mut rel: Option<&'static [u8]> = None;
+    let (mut jmp_rel_size, mut rela_size, mut rel_size) = (0, 0, 0);
+    let mut jmp_rel_is_rela = cfg!(target_pointer_width = "64");
 
     for elf_dyn in dyn_table {
         let d_tag = elf_dyn.d_tag;
+        match d_tag {
+            elf::abi::DT_PLTRELSZ => jmp_rel_size = elf_dyn.d_val() as usize,
+            elf::abi::DT_PLTREL => jmp_rel_is_rela = elf_dyn.d_val() as i64 == elf::abi::DT_RELA,
+            elf::abi::DT_RELASZ => rela_size = elf_dyn.d_val() as usize,
+            elf::abi::DT_RELSZ => rel_size = elf_dyn.d_val() as usize,
+            _ => (),
+        }
         let start = elf_dyn.d_ptr() as ElfAddress;
         if !mem_range.contains(&start) {
             continue;
@@ -139,10 +303,25 @@ unsafe fn parse_dl_phdr_info(info: &dl_phdr_info) -> Option<ObjectInfo> {
             elf::abi::DT_SYMTAB => {
                 symbol_table = Some(elf::symbol::SymbolTable::new(NativeEndian, ELF_CLASS, data))
             }
+            elf::abi::DT_JMPREL => jmp_rel = Some(data),
+            elf::abi::DT_RELA => rela = Some(data),
+            elf::abi::DT_REL => rel = Some(data),
             _ => (),
         }
     }
 
+    let table = |data: Option<&'static [u8]>, size: usize| data.map(|v| &v[..size.min(v.len())]);
+    let mut relocation_tables = Vec::new();
+    if let Some(data) = table(jmp_rel, jmp_rel_size) {
+        relocation_tables.push(if jmp_rel_is_rela {
+            RelocationTable::Rela(data)
+        } else {
+            RelocationTable::Rel(data)
+        });
+    }
+    relocation_tables.extend(table(rela, rela_size).map(RelocationTable::Rela));
+    relocation_tables.extend(table(rel, rel_size).map(RelocationTable::Rel));
+
     if gnu_hash_table.is_none() && sysv_hash_table.is_none() {
         return None;
     }
@@ -153,6 +332,8 @@ unsafe fn parse_dl_phdr_info(info: &dl_phdr_info) -> Option<ObjectInfo> {
         sysv_hash_table,
         string_table: string_table?,
         symbol_table: symbol_table?,
+        relocation_tables,
+        relro_range,
     })
 }
 
@@ -225,6 +406,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn rewrite_got() {
+        unsafe extern "C" fn fake_getppid() -> libc::pid_t {
+            -42
+        }
+        // GOT loads are assumed invariant, slots are read anew per call
+        #[inline(never)]
+        fn getppid() -> libc::pid_t {
+            unsafe { libc::getppid() }
+        }
+
+        let own = rewrite_got as usize;
+        let mut orig: Option<*mut c_void> = None;
+        let mut rewrite = |new: Option<*mut c_void>| unsafe {
+            rewrite_loaded_got(
+                |info| object_contains(info, own),
+                |name, current| {
+                    if name != "getppid" {
+                        return None;
+                    }
+                    orig.get_or_insert(current);
+                    new.or(orig)
+                },
+            )
+        };
+
+        // slots of the test binary only, calls from libstd are left alone
+        let count = rewrite(Some(fake_getppid as _));
+        if count == 0 {
+            // called through a copy relocation or statically linked
+            return;
+        }
+        assert_eq!(getppid(), -42);
+        assert_eq!(rewrite(None), count);
+        assert_ne!(getppid(), -42);
+    }
+
     #[test]
     #[ignore = "verbose"]
     fn iterate_phdr_print() {
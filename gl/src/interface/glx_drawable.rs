@@ -0,0 +1,141 @@
+//! Garbage collection of GLX drawables destroyed behind the layer's back
+
+use super::*;
+
+use crate::utils::*;
+
+use core::cell::Cell;
+use core::ffi::{c_int, c_void};
+use core::mem;
+use core::ptr;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use function_name::named;
+use once_cell::sync::Lazy;
+
+const VALIDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+static DISPLAY_STATES: Lazy<Mutex<HashMap<GlHandle, DisplayState>>> = Lazy::new(Default::default);
+
+static PREV_ERROR_HANDLER: Lazy<XErrorHandler> = Lazy::new(|| unsafe {
+    let x11 = X11_LIB.as_ref()?;
+    (x11.XSetErrorHandler)(Some(handle_x_error))
+});
+
+thread_local! {
+    /// Display whose errors about dead drawables this thread swallows
+    static TRAPPED_DISPLAY: Cell<*mut c_void> = const { Cell::new(ptr::null_mut()) };
+}
+
+#[derive(Default)]
+struct DisplayState {
+    /// Resources X errors reported as invalid window or drawable, drained on
+    /// validation
+    dead_drawables: Vec<u64>,
+    last_validated: Option<Instant>,
+}
+
+/// Installs the X error handler collecting dead drawables, once
+pub fn install_x_error_handler() {
+    Lazy::force(&PREV_ERROR_HANDLER);
+}
+
+/// Runs `f` with X errors of `dpy` about dead drawables swallowed instead of
+/// reaching the app's error handler
+pub unsafe fn trap_x_errors<R>(dpy: *const c_void, f: impl FnOnce() -> R) -> R {
+    let prev = TRAPPED_DISPLAY.with(|trapped| trapped.replace(dpy as _));
+    let res = f();
+    // errors of requests issued by `f` arrive asynchronously
+    if let Some(x11) = X11_LIB.as_ref() {
+        (x11.XSync)(dpy as _, 0);
+    }
+    TRAPPED_DISPLAY.with(|trapped| trapped.set(prev));
+    res
+}
+
+#[named]
+unsafe extern "C" fn handle_x_error(dpy: *mut c_void, event: *mut XErrorEvent) -> c_int {
+    let error = &*event;
+    if matches!(error.error_code, X_BAD_WINDOW | X_BAD_DRAWABLE) {
+        debug!("drawable {:#x} is gone: {:?}", error.resourceid, error);
+        // surfaces may be locked by this thread, they are looked up later
+        DISPLAY_STATES
+            .lock()
+            .unwrap()
+            .entry(glhandle!(dpy))
+            .or_default()
+            .dead_drawables
+            .push(error.resourceid as _);
+        if TRAPPED_DISPLAY.with(Cell::get) == dpy {
+            return 0;
+        }
+    }
+    match Lazy::get(&PREV_ERROR_HANDLER).copied().flatten() {
+        Some(prev) => prev(dpy, event),
+        None => 0,
+    }
+}
+
+/// Whether X `drawable` exists on the server of `dpy`. GLX-only drawables,
+/// i.e. GLXWindows, GLXPixmaps and pbuffers, are not X drawables.
+pub unsafe fn x_drawable_alive(dpy: *const c_void, drawable: u32) -> bool {
+    let x11 = if let Some(v) = X11_LIB.as_ref() {
+        v
+    } else {
+        // unable to tell
+        return true;
+    };
+    let conn = (x11.XGetXCBConnection)(dpy as _);
+    let cookie = (x11.xcb_get_geometry)(conn, drawable);
+    let mut error: *mut c_void = ptr::null_mut();
+    let reply = (x11.xcb_get_geometry_reply)(conn, cookie, &mut error);
+    let alive = !reply.is_null();
+    libc::free(reply as _);
+    libc::free(error);
+    alive
+}
+
+/// Surfaces of `dpy` whose X drawable is gone, at most once per
+/// `VALIDATE_INTERVAL`, e.g. windows destroyed with XDestroyWindow() without
+/// glXDestroyWindow() being called
+#[named]
+pub unsafe fn collect_dead_surfaces(dpy: *const c_void) -> Vec<GlHandle> {
+    let display = glhandle!(dpy);
+    let reported = {
+        let mut display_states = DISPLAY_STATES.lock().unwrap();
+        let state = display_states.entry(display).or_default();
+        let now = Instant::now();
+        if state
+            .last_validated
+            .map_or(false, |at| now - at < VALIDATE_INTERVAL)
+        {
+            return vec![];
+        }
+        state.last_validated = Some(now);
+        mem::take(&mut state.dead_drawables)
+    };
+
+    let candidates: Vec<_> = SURFACE_MAP
+        .iter()
+        .filter(|ly_surface| {
+            matches!(ly_surface.native, NativeIface::Glx) && ly_surface.display == display
+        })
+        .filter_map(|ly_surface| Some((ly_surface.surface, ly_surface.x_drawable?)))
+        .collect();
+
+    candidates
+        .into_iter()
+        .filter(|&(surface, x_drawable)| {
+            let dead = reported.contains(&surface.as_raw())
+                || reported.contains(&(x_drawable as u64))
+                || !x_drawable_alive(dpy, x_drawable);
+            if dead {
+                debug!("X drawable {:#x} of {:?} is gone", x_drawable, surface);
+            }
+            dead
+        })
+        .map(|(surface, _)| surface)
+        .collect()
+}
@@ -6,23 +6,32 @@ use crate::utils::*;
 use core::ffi::CStr;
 use core::ptr;
 use core::slice;
-use core::sync::atomic::{self, AtomicU64};
+use core::sync::atomic::{self, AtomicBool, AtomicU64};
 use std::collections::VecDeque;
+use std::ffi::CString;
+use std::os::unix::io::RawFd;
 use std::result::Result::Ok;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::Instant;
 
 use anyhow::{anyhow, Result};
 use client::BufferPlaneInfo;
 use dashmap::DashMap;
 use function_name::named;
 use libc::{c_char, c_void};
+use once_cell::sync::Lazy;
 use pw_capture_client as client;
 use pw_capture_cursor as local_cursor;
 use pw_capture_cursor::CursorManager;
 use pw_capture_gl_sys::prelude::*;
 use sentinel::SSlice;
 
+// textures are created as consumers ask for them, up to
+// `PW_CAPTURE_MAX_BUFFERS` if set
 const MAX_BUFFERS: u32 = 32;
+/// Node properties of the GL implementation the app renders with
+const PROP_GL_VENDOR: &str = "pw-capture.gl.vendor";
+const PROP_GL_RENDERER: &str = "pw-capture.gl.renderer";
 
 #[named]
 #[inline(never)]
@@ -67,18 +76,84 @@ unsafe fn do_intercept(name: &CStr) -> Option<*mut c_void> {
         b"dlsym" => impl_dlsym as _,
         b"dlvsym" => impl_dlvsym as _,
         b"wl_proxy_marshal_array_flags" => impl_wl_proxy_marshal_array_flags as _,
-        #[cfg(feature = "nightly")]
+        #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+        b"wl_proxy_marshal_flags" => pw_capture_wl_proxy_marshal_flags as _,
+        #[cfg(all(
+            feature = "nightly",
+            not(any(target_arch = "x86_64", target_arch = "aarch64"))
+        ))]
         b"wl_proxy_marshal_flags" => impl_wl_proxy_marshal_flags as _,
         b"wl_proxy_create" => impl_wl_proxy_create as _,
         b"wl_proxy_add_listener" => impl_wl_proxy_add_listener as _,
         b"wl_proxy_add_dispatcher" => impl_wl_proxy_add_dispatcher as _,
         b"wl_proxy_get_listener" => impl_wl_proxy_get_listener as _,
         b"wl_proxy_destroy" => impl_wl_proxy_destroy as _,
-        _ => do_intercept_egl(name).or_else(|| do_intercept_glx(name))?,
+        _ => do_intercept_egl(name)
+            .or_else(|| do_intercept_glx(name))
+            .or_else(|| do_intercept_gl(name))?,
     };
     Some(pfn)
 }
 
+/// Objects of the graphics stack, whose own Wayland requests are not the app's
+/// to capture and whose GOT is left alone
+const GRAPHICS_STACK_OBJECTS: &[&str] = &[
+    "libwayland-",
+    "libEGL",
+    "libGL",
+    "libOpenGL",
+    "libgbm.so",
+    "libgallium",
+    "_dri.so",
+    "libnvidia-",
+    "libvulkan",
+];
+
+/// Rewrites GOT slots of `wl_proxy_*` functions in objects of the app loaded
+/// before the layer, which resolved them to libwayland-client without going
+/// through `dlsym` or symbol interposition, returns number of slots rewritten
+#[named]
+pub unsafe fn hook_loaded_wl_proxy() -> usize {
+    if WL_INTERCEPT.is_none() {
+        return 0;
+    }
+    let own = hook_loaded_wl_proxy as usize;
+    let count = rewrite_loaded_got(
+        |info| {
+            let name = CStr::from_ptr(info.dlpi_name).to_string_lossy();
+            let file_name = name.rsplit('/').next().unwrap_or_default();
+            !GRAPHICS_STACK_OBJECTS.iter().any(|v| file_name.contains(v))
+                && !object_contains(info, own)
+        },
+        |name, current| {
+            if !name.starts_with("wl_proxy_") {
+                return None;
+            }
+            let name = CString::new(name).ok()?;
+            let pfn = do_intercept(&name)?;
+            trace!("{}: {:?} -> {:?}", name.to_string_lossy(), current, pfn);
+            Some(pfn)
+        },
+    );
+    debug!("rewrote {count} GOT slots of wl_proxy functions");
+    count
+}
+
+#[named]
+unsafe fn do_intercept_gl(name: &CStr) -> Option<*mut c_void> {
+    // called far more often than buffer swaps, only hooked if asked for
+    if !*OFFSCREEN_CAPTURE {
+        return None;
+    }
+    let pfn: *mut c_void = match name.to_bytes() {
+        b"glFlush" => impl_glFlush as _,
+        b"glFinish" => impl_glFinish as _,
+        _ => return None,
+    };
+    debug!("address: {:?} proc: {}", pfn, name.to_string_lossy());
+    Some(pfn)
+}
+
 #[named]
 unsafe fn do_intercept_glx(name: &CStr) -> Option<*mut c_void> {
     if name.to_string_lossy().starts_with("glX") {
@@ -89,8 +164,19 @@ unsafe fn do_intercept_glx(name: &CStr) -> Option<*mut c_void> {
         b"glXGetProcAddressARB" => impl_glXGetProcAddressARB as _,
         b"glXSwapBuffers" => impl_glXSwapBuffers as _,
         b"glXSwapBuffersMscOML" => impl_glXSwapBuffersMscOML as _,
+        b"glXSwapIntervalEXT" => impl_glXSwapIntervalEXT as _,
+        b"glXSwapIntervalSGI" => impl_glXSwapIntervalSGI as _,
+        b"glXSwapIntervalMESA" => impl_glXSwapIntervalMESA as _,
+        b"glXCreateWindow" => impl_glXCreateWindow as _,
         b"glXDestroyWindow" => impl_glXDestroyWindow as _,
+        b"glXCreateContext" => impl_glXCreateContext as _,
+        b"glXCreateNewContext" => impl_glXCreateNewContext as _,
+        b"glXCreateContextAttribsARB" => impl_glXCreateContextAttribsARB as _,
+        b"glXCreatePbuffer" => impl_glXCreatePbuffer as _,
+        b"glXDestroyPbuffer" => impl_glXDestroyPbuffer as _,
         b"glXDestroyContext" => impl_glXDestroyContext as _,
+        b"glXMakeCurrent" => impl_glXMakeCurrent as _,
+        b"glXMakeContextCurrent" => impl_glXMakeContextCurrent as _,
         _ => return None,
     };
     debug!("address: {:?} proc: {}", pfn, name.to_string_lossy());
@@ -110,11 +196,16 @@ unsafe fn do_intercept_egl(name: &CStr) -> Option<*mut c_void> {
         b"eglCreateWindowSurface" => impl_eglCreateWindowSurface as _,
         b"eglCreatePlatformWindowSurface" => impl_eglCreatePlatformWindowSurface as _,
         b"eglCreatePlatformWindowSurfaceEXT" => impl_eglCreatePlatformWindowSurfaceEXT as _,
+        b"eglCreatePbufferSurface" => impl_eglCreatePbufferSurface as _,
         b"eglSwapBuffers" => impl_eglSwapBuffers as _,
         b"eglSwapBuffersWithDamageEXT" => impl_eglSwapBuffersWithDamageEXT as _,
         b"eglSwapBuffersWithDamageKHR" => impl_eglSwapBuffersWithDamageKHR as _,
+        b"eglSetDamageRegionKHR" => impl_eglSetDamageRegionKHR as _,
+        b"eglSwapInterval" => impl_eglSwapInterval as _,
         b"eglDestroySurface" => impl_eglDestroySurface as _,
+        b"eglCreateContext" => impl_eglCreateContext as _,
         b"eglDestroyContext" => impl_eglDestroyContext as _,
+        b"eglMakeCurrent" => impl_eglMakeCurrent as _,
         b"eglTerminate" => impl_eglTerminate as _,
         _ => return None,
     };
@@ -134,7 +225,8 @@ pub unsafe extern "C" fn impl_glXGetProcAddress(proc_name: *const c_char) -> *mu
     if orig.is_null() {
         return ptr::null_mut();
     }
-    if let Some(v) = do_intercept_glx(CStr::from_ptr(proc_name)) {
+    let name = CStr::from_ptr(proc_name);
+    if let Some(v) = do_intercept_glx(name).or_else(|| do_intercept_gl(name)) {
         return v;
     }
     orig as _
@@ -152,7 +244,8 @@ pub unsafe extern "C" fn impl_glXGetProcAddressARB(proc_name: *const c_char) ->
     if orig.is_null() {
         return ptr::null_mut();
     }
-    if let Some(v) = do_intercept_glx(CStr::from_ptr(proc_name)) {
+    let name = CStr::from_ptr(proc_name);
+    if let Some(v) = do_intercept_glx(name).or_else(|| do_intercept_gl(name)) {
         return v;
     }
     orig as _
@@ -166,10 +259,13 @@ pub unsafe extern "C" fn impl_glXSwapBuffers(
 ) {
     let glx = glx();
 
-    try_capture(NativeIface::Glx, dpy as _, drawable as _);
+    trap_x_errors(dpy as _, || {
+        collect_dead_glx_surfaces(dpy as _);
+        try_capture(NativeIface::Glx, dpy as _, drawable as _, None);
 
-    let mut val: u32 = 2;
-    glx.QueryDrawable(dpy, drawable, glx_sys::TEXTURE_FORMAT_EXT as _, &mut val);
+        let mut val: u32 = 2;
+        glx.QueryDrawable(dpy, drawable, glx_sys::TEXTURE_FORMAT_EXT as _, &mut val);
+    });
 
     glx.SwapBuffers(dpy, drawable)
 }
@@ -185,21 +281,159 @@ pub unsafe extern "C" fn impl_glXSwapBuffersMscOML(
 ) -> i64 {
     let glx = glx();
 
-    try_capture(NativeIface::Glx, dpy as _, drawable as _);
+    trap_x_errors(dpy as _, || {
+        collect_dead_glx_surfaces(dpy as _);
+        try_capture(NativeIface::Glx, dpy as _, drawable as _, None);
+    });
 
     glx.SwapBuffersMscOML(dpy, drawable, target_msc, divisor, remainder)
 }
 
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXSwapIntervalEXT(
+    dpy: *mut glx_t::Display,
+    drawable: glx_t::GLXDrawable,
+    interval: i32,
+) {
+    let glx = glx();
+
+    glx.SwapIntervalEXT(dpy, drawable, interval);
+    set_swap_interval(NativeIface::Glx, dpy as _, drawable as _, interval);
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXSwapIntervalSGI(interval: i32) -> i32 {
+    let glx = glx();
+
+    // applies to current drawable
+    let res = glx.SwapIntervalSGI(interval);
+    if res == 0 {
+        let (dpy, drawable) = (glx.GetCurrentDisplay(), glx.GetCurrentDrawable());
+        set_swap_interval(NativeIface::Glx, dpy as _, drawable as _, interval);
+    }
+    res
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXSwapIntervalMESA(interval: u32) -> i32 {
+    let glx = glx();
+
+    // applies to current drawable
+    let res = glx.SwapIntervalMESA(interval);
+    if res == 0 {
+        let (dpy, drawable) = (glx.GetCurrentDisplay(), glx.GetCurrentDrawable());
+        set_swap_interval(NativeIface::Glx, dpy as _, drawable as _, interval as _);
+    }
+    res
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXCreateWindow(
+    dpy: *mut glx_t::Display,
+    config: glx_t::GLXFBConfig,
+    win: glx_t::Window,
+    attrib_list: *const i32,
+) -> glx_t::GLXWindow {
+    let glx = glx();
+
+    let glx_window = glx.CreateWindow(dpy, config, win, attrib_list);
+    if glx_window != 0 {
+        GLX_WINDOW_MAP.insert(glhandle!(glx_window as *const c_void), win as _);
+        trap_x_errors(dpy as _, || {
+            try_init_surface(NativeIface::Glx, dpy as _, glx_window as _, None);
+        });
+    }
+    glx_window
+}
+
 #[allow(non_snake_case)]
 #[inline(never)]
 pub unsafe extern "C" fn impl_glXDestroyWindow(dpy: *mut glx_t::Display, win: glx_t::GLXWindow) {
     let glx = glx();
 
     destroy_surface(dpy as _, win as _);
+    GLX_WINDOW_MAP.remove(&glhandle!(win as *const c_void));
 
     glx.DestroyWindow(dpy, win)
 }
 
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXCreatePbuffer(
+    dpy: *mut glx_t::Display,
+    config: glx_t::GLXFBConfig,
+    attrib_list: *const i32,
+) -> glx_t::GLXPbuffer {
+    let glx = glx();
+
+    let pbuffer = glx.CreatePbuffer(dpy, config, attrib_list);
+    if *OFFSCREEN_CAPTURE && pbuffer != 0 {
+        init_offscreen_surface(NativeIface::Glx, dpy as _, pbuffer as _);
+    }
+    pbuffer
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXDestroyPbuffer(dpy: *mut glx_t::Display, pbuf: glx_t::GLXPbuffer) {
+    let glx = glx();
+
+    destroy_surface(dpy as _, pbuf as _);
+
+    glx.DestroyPbuffer(dpy, pbuf)
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXCreateContext(
+    dpy: *mut glx_t::Display,
+    vis: *mut glx_t::XVisualInfo,
+    share_list: glx_t::GLXContext,
+    direct: glx_t::Bool,
+) -> glx_t::GLXContext {
+    let glx = glx();
+
+    let ctx = glx.CreateContext(dpy, vis, share_list, direct);
+    add_context(ctx as _, share_list as _);
+    ctx
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXCreateNewContext(
+    dpy: *mut glx_t::Display,
+    config: glx_t::GLXFBConfig,
+    render_type: i32,
+    share_list: glx_t::GLXContext,
+    direct: glx_t::Bool,
+) -> glx_t::GLXContext {
+    let glx = glx();
+
+    let ctx = glx.CreateNewContext(dpy, config, render_type, share_list, direct);
+    add_context(ctx as _, share_list as _);
+    ctx
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXCreateContextAttribsARB(
+    dpy: *mut glx_t::Display,
+    config: glx_t::GLXFBConfig,
+    share_context: glx_t::GLXContext,
+    direct: glx_t::Bool,
+    attrib_list: *const i32,
+) -> glx_t::GLXContext {
+    let glx = glx();
+
+    let ctx = glx.CreateContextAttribsARB(dpy, config, share_context, direct, attrib_list);
+    add_context(ctx as _, share_context as _);
+    ctx
+}
+
 #[allow(non_snake_case)]
 #[inline(never)]
 pub unsafe extern "C" fn impl_glXDestroyContext(dpy: *mut glx_t::Display, ctx: glx_t::GLXContext) {
@@ -210,6 +444,44 @@ pub unsafe extern "C" fn impl_glXDestroyContext(dpy: *mut glx_t::Display, ctx: g
     glx.DestroyContext(dpy, ctx)
 }
 
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXMakeCurrent(
+    dpy: *mut glx_t::Display,
+    drawable: glx_t::GLXDrawable,
+    ctx: glx_t::GLXContext,
+) -> glx_t::Bool {
+    let glx = glx();
+
+    let res = glx.MakeCurrent(dpy, drawable, ctx);
+    if res != 0 && !ctx.is_null() {
+        trap_x_errors(dpy as _, || {
+            try_prepare_capture(NativeIface::Glx, dpy as _, drawable as _)
+        });
+    }
+    res
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glXMakeContextCurrent(
+    dpy: *mut glx_t::Display,
+    draw: glx_t::GLXDrawable,
+    read: glx_t::GLXDrawable,
+    ctx: glx_t::GLXContext,
+) -> glx_t::Bool {
+    let glx = glx();
+
+    let res = glx.MakeContextCurrent(dpy, draw, read, ctx);
+    // frames are read from the read drawable, which must be the swapped one
+    if res != 0 && !ctx.is_null() && draw == read {
+        trap_x_errors(dpy as _, || {
+            try_prepare_capture(NativeIface::Glx, dpy as _, draw as _)
+        });
+    }
+    res
+}
+
 #[allow(non_snake_case)]
 #[inline(never)]
 pub unsafe extern "C" fn impl_eglGetProcAddress(proc_name: *const c_char) -> *mut c_void {
@@ -222,7 +494,8 @@ pub unsafe extern "C" fn impl_eglGetProcAddress(proc_name: *const c_char) -> *mu
     if orig.is_null() {
         return ptr::null_mut();
     }
-    if let Some(v) = do_intercept_egl(CStr::from_ptr(proc_name)) {
+    let name = CStr::from_ptr(proc_name);
+    if let Some(v) = do_intercept_egl(name).or_else(|| do_intercept_gl(name)) {
         return v;
     }
     orig as _
@@ -236,6 +509,9 @@ unsafe fn add_egl_display(
     if dpy == egl_sys::NO_DISPLAY {
         return;
     }
+    if platform == Some(EglPlatform::Wayland) {
+        Lazy::force(&WL_GOT_HOOKED);
+    }
     let ly_display = LayerDisplay {
         egl_display: Some(EglDisplay {
             platform_display: glhandle!(native_display),
@@ -365,10 +641,59 @@ pub unsafe extern "C" fn impl_eglCreatePlatformWindowSurfaceEXT(
     surface
 }
 
-unsafe fn egl_swap_buffer(egl: &Egl, dpy: egl_t::EGLDisplay, surface: egl_t::EGLSurface) {
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_eglCreatePbufferSurface(
+    dpy: egl_t::EGLDisplay,
+    config: egl_t::EGLConfig,
+    attrib_list: *const i32,
+) -> egl_t::EGLSurface {
+    let egl = egl();
+
+    let surface = egl.CreatePbufferSurface(dpy, config, attrib_list);
+    if *OFFSCREEN_CAPTURE && surface != egl_sys::NO_SURFACE {
+        init_offscreen_surface(NativeIface::Egl, dpy, surface);
+    }
+    surface
+}
+
+/// Damage rectangles of EGL, `None` if the whole surface is damaged
+unsafe fn egl_damage_rects(
+    rects: *const egl_t::EGLint,
+    n_rects: egl_t::EGLint,
+) -> Option<Vec<client::Region>> {
+    if rects.is_null() || n_rects <= 0 {
+        return None;
+    }
+    let rects = slice::from_raw_parts(rects, n_rects as usize * 4);
+    let rects = rects
+        .chunks_exact(4)
+        .map(|v| client::Region {
+            x: v[0].max(0) as _,
+            y: v[1].max(0) as _,
+            width: v[2].max(0) as _,
+            height: v[3].max(0) as _,
+        })
+        .collect();
+    Some(rects)
+}
+
+unsafe fn egl_swap_buffer(
+    egl: &Egl,
+    dpy: egl_t::EGLDisplay,
+    surface: egl_t::EGLSurface,
+    damage: Option<Vec<client::Region>>,
+) {
+    // the damage region of partial updates covers what changed in the
+    // frame, along with what changed in the buffer since it got shown
+    let damage_region = SURFACE_MAP.get(&glhandle!(surface)).and_then(|ly_surface| {
+        let ly_capture = ly_surface.capture.as_ref()?;
+        ly_capture.damage.lock().unwrap().take_region()
+    });
     let api = egl.QueryAPI();
     if api == egl_sys::OPENGL_API || api == egl_sys::OPENGL_ES_API {
-        try_capture(NativeIface::Egl, dpy, surface);
+        let damage = damage.or(damage_region);
+        try_capture(NativeIface::Egl, dpy, surface, damage.as_deref());
     }
 }
 
@@ -379,7 +704,7 @@ pub unsafe extern "C" fn impl_eglSwapBuffers(
     surface: egl_t::EGLSurface,
 ) -> egl_t::EGLBoolean {
     let egl = egl();
-    egl_swap_buffer(egl, dpy, surface);
+    egl_swap_buffer(egl, dpy, surface, None);
     egl.SwapBuffers(dpy, surface)
 }
 
@@ -392,7 +717,7 @@ pub unsafe extern "C" fn impl_eglSwapBuffersWithDamageEXT(
     n_rects: egl_t::EGLint,
 ) -> egl_t::EGLBoolean {
     let egl = egl();
-    egl_swap_buffer(egl, dpy, surface);
+    egl_swap_buffer(egl, dpy, surface, egl_damage_rects(rects, n_rects));
     egl.SwapBuffersWithDamageEXT(dpy, surface, rects, n_rects)
 }
 
@@ -405,10 +730,50 @@ pub unsafe extern "C" fn impl_eglSwapBuffersWithDamageKHR(
     n_rects: egl_t::EGLint,
 ) -> egl_t::EGLBoolean {
     let egl = egl();
-    egl_swap_buffer(egl, dpy, surface);
+    egl_swap_buffer(egl, dpy, surface, egl_damage_rects(rects, n_rects));
     egl.SwapBuffersWithDamageKHR(dpy, surface, rects, n_rects)
 }
 
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_eglSetDamageRegionKHR(
+    dpy: egl_t::EGLDisplay,
+    surface: egl_t::EGLSurface,
+    rects: *mut egl_t::EGLint,
+    n_rects: egl_t::EGLint,
+) -> egl_t::EGLBoolean {
+    let egl = egl();
+
+    let res = egl.SetDamageRegionKHR(dpy, surface, rects, n_rects);
+    if res == egl_sys::TRUE {
+        if let Some(ly_surface) = SURFACE_MAP.get(&glhandle!(surface)) {
+            // frames before capture got set up are damaged as a whole anyway
+            if let Some(ly_capture) = ly_surface.capture.as_ref() {
+                let region = egl_damage_rects(rects, n_rects);
+                ly_capture.damage.lock().unwrap().set_region(region);
+            }
+        }
+    }
+    res
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_eglSwapInterval(
+    dpy: egl_t::EGLDisplay,
+    interval: egl_t::EGLint,
+) -> egl_t::EGLBoolean {
+    let egl = egl();
+
+    // applies to draw surface of current context
+    let res = egl.SwapInterval(dpy, interval);
+    if res == egl_sys::TRUE {
+        let surface = egl.GetCurrentSurface(egl_sys::DRAW as _);
+        set_swap_interval(NativeIface::Egl, dpy, surface, interval);
+    }
+    res
+}
+
 #[allow(non_snake_case)]
 #[inline(never)]
 pub unsafe extern "C" fn impl_eglDestroySurface(
@@ -422,6 +787,21 @@ pub unsafe extern "C" fn impl_eglDestroySurface(
     egl.DestroySurface(dpy, surface)
 }
 
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_eglCreateContext(
+    dpy: egl_t::EGLDisplay,
+    config: egl_t::EGLConfig,
+    share_context: egl_t::EGLContext,
+    attrib_list: *const i32,
+) -> egl_t::EGLContext {
+    let egl = egl();
+
+    let ctx = egl.CreateContext(dpy, config, share_context, attrib_list);
+    add_context(ctx as _, share_context as _);
+    ctx
+}
+
 #[allow(non_snake_case)]
 #[inline(never)]
 pub unsafe extern "C" fn impl_eglDestroyContext(
@@ -435,6 +815,42 @@ pub unsafe extern "C" fn impl_eglDestroyContext(
     egl.DestroyContext(dpy, ctx)
 }
 
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_eglMakeCurrent(
+    dpy: egl_t::EGLDisplay,
+    draw: egl_t::EGLSurface,
+    read: egl_t::EGLSurface,
+    ctx: egl_t::EGLContext,
+) -> egl_t::EGLBoolean {
+    let egl = egl();
+
+    let res = egl.MakeCurrent(dpy, draw, read, ctx);
+    if res == egl_sys::TRUE && ctx != egl_sys::NO_CONTEXT && draw == read {
+        let api = egl.QueryAPI();
+        if api == egl_sys::OPENGL_API || api == egl_sys::OPENGL_ES_API {
+            try_prepare_capture(NativeIface::Egl, dpy, draw);
+        }
+    }
+    res
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glFlush() {
+    if let Some(native) = capture_offscreen() {
+        gl(native).Flush()
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline(never)]
+pub unsafe extern "C" fn impl_glFinish() {
+    if let Some(native) = capture_offscreen() {
+        gl(native).Finish()
+    }
+}
+
 #[allow(non_snake_case)]
 #[inline(never)]
 pub unsafe extern "C" fn impl_eglTerminate(dpy: egl_t::EGLDisplay) -> egl_t::EGLBoolean {
@@ -445,103 +861,620 @@ pub unsafe extern "C" fn impl_eglTerminate(dpy: egl_t::EGLDisplay) -> egl_t::EGL
     egl.Terminate(dpy)
 }
 
+#[named]
 unsafe fn capture(
     native: NativeIface,
     dpy: *const c_void,
     ly_capture: &LayerCapture,
+    offscreen: bool,
+    frame: Option<u64>,
 ) -> Result<()> {
-    let gl = gl(native);
-
     let stream = ly_capture.stream.proxy();
 
-    let (buffer, user_handle) = if let Some(v) = stream.try_dequeue_buffer()?? {
-        v
+    let streaming = ly_capture.streaming.load(atomic::Ordering::Acquire);
+    let dequeued = if streaming {
+        stream.dequeue_buffer()?
     } else {
-        return Ok(());
+        None
     };
+    let max_buffers = {
+        let mut buffer_demand = ly_capture.buffer_demand.lock().unwrap();
+        // consumers that disconnected removed all their buffers, textures are
+        // released right away instead of after being idle for a while
+        if !streaming && ly_capture.mapped_textures.is_empty() {
+            buffer_demand.reset()
+        } else {
+            buffer_demand.update(dequeued.is_some(), Instant::now())
+        }
+    };
+    if let Some((buffer, user_handle)) = dequeued {
+        let texture = match user_handle {
+            client::BufferUserHandle::Texture(v) => v,
+            _ => unreachable!(),
+        };
+        copy_frame(native, dpy, ly_capture, texture, frame);
+        // backend waits for the copy before handing the frame to consumers
+        ly_capture.stream.worker().submit(move || {
+            if let Err(e) = stream.queue_buffer_process(buffer) {
+                error!("failed to queue frame: {e:?}");
+            }
+        });
+    }
+    // drawn after the copy so captured frames never contain it, offscreen
+    // surfaces are never shown so they don't get one
+    if streaming && client::indicator_enabled() && !offscreen {
+        draw_indicator(native, ly_capture);
+    }
+
+    resize_texture_pool(native, dpy, ly_capture, max_buffers)
+}
+
+/// Publishes title and app id of the window once changed, so consumers can
+/// tell streams of the app apart, the transform its buffers are rendered with
+/// and whether it is hidden
+#[named]
+fn update_window_props(ly_surface: &LayerSurface, ly_capture: &LayerCapture) -> Result<()> {
+    let serial = ly_capture.window_serial.load(atomic::Ordering::Acquire);
+    let info = match ly_surface.cursor_manager.as_ref() {
+        Some(cursor_manager) => cursor_manager.window_info(serial),
+        None => None,
+    };
+    let info = match info {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    ly_capture
+        .window_serial
+        .store(info.serial, atomic::Ordering::Release);
+    debug!("window changed: {:?}", info);
+    let stream = ly_capture.stream.proxy();
+    let transform = client::Transform::from_wl(info.buffer_transform).unwrap_or_default();
+    stream.update_transform(transform)?;
+    ly_capture
+        .window_suspended
+        .store(info.suspended, atomic::Ordering::Release);
+    stream.update_window_suspended(info.suspended)?;
+    Ok(stream.update_props(info.as_props())?)
+}
+
+/// Records swap interval the app set for `surface`, published once captured
+/// so users can tell vsynced apps from ones rendering uncapped
+#[named]
+unsafe fn set_swap_interval(
+    native: NativeIface,
+    dpy: *const c_void,
+    surface: *const c_void,
+    interval: i32,
+) {
+    if surface.is_null() {
+        return;
+    }
+    debug!("surface:{:?} swap interval:{}", surface, interval);
+    // GLX drawables are only tracked from their first buffer swap on
+    if native == NativeIface::Glx {
+        try_init_surface(native, dpy, surface, None);
+    }
+    let stream = match SURFACE_MAP.get_mut(&glhandle!(surface)) {
+        Some(mut ly_surface) => {
+            ly_surface.swap_interval = Some(interval);
+            ly_surface.capture.as_ref().map(|v| v.stream.proxy())
+        }
+        None => None,
+    };
+    // the stream thread looks surfaces up, call it with the map unlocked
+    if let Some(stream) = stream {
+        update_swap_interval(&stream, interval);
+    }
+}
+
+#[named]
+fn update_swap_interval(stream: &client::StreamProxy, interval: i32) {
+    if let Err(e) = stream.update_swap_interval(interval) {
+        warn!("failed to update swap interval: {e:?}");
+    }
+}
+
+/// Textures can only be created on the capturing thread, so buffers consumers
+/// failed to get are created here and consumers re-add them afterwards. Free
+/// textures beyond current buffer demand are released. Consumers may allocate
+/// `max_buffers` from then on if changed.
+#[named]
+unsafe fn resize_texture_pool(
+    native: NativeIface,
+    dpy: *const c_void,
+    ly_capture: &LayerCapture,
+    max_buffers: Option<u32>,
+) -> Result<()> {
+    let stream = ly_capture.stream.proxy();
+    let limit = ly_capture.buffer_demand.lock().unwrap().current() as usize;
+    let missing = stream.take_missing_buffers()? as usize;
+    let mut free_textures = ly_capture.free_textures.lock().unwrap();
+    let total = free_textures.len() + ly_capture.mapped_textures.len();
+    let target = (total + missing).min(limit);
+    let grow = target > total;
+    if grow {
+        debug!("growing texture pool: {} -> {}", total, target);
+        let (format, modifier, num_planes, textures) = create_target_textures(
+            native,
+            dpy,
+            ly_capture.export_width,
+            ly_capture.export_height,
+            (target - total) as _,
+            ly_capture.fb_format,
+            ly_capture.use_read_pixels,
+        )?;
+        if (format, modifier, num_planes) != ly_capture.export_format {
+            return Err(anyhow!("exported texture format changed"));
+        }
+        free_textures.extend(textures);
+    } else {
+        // buffers still in use are released once consumers removed them
+        let excess = (total - target).min(free_textures.len());
+        free_textures.truncate(free_textures.len() - excess);
+    }
+    let free = free_textures.len();
+    if free + ly_capture.mapped_textures.len() != total {
+        let size = free_textures.iter().map(ExportTexture::size).sum::<u64>()
+            + ly_capture
+                .mapped_textures
+                .iter()
+                .map(|v| v.size())
+                .sum::<u64>();
+        debug!(
+            "texture pool: {} mapped, {} free, limit {}, {} MiB",
+            ly_capture.mapped_textures.len(),
+            free,
+            limit,
+            size >> 20
+        );
+    }
+    drop(free_textures);
+
+    if let Some(max_buffers) = max_buffers {
+        debug!("buffer demand: {}", max_buffers);
+        stream.update_max_buffers(max_buffers)?;
+    } else if grow {
+        stream.renegotiate_buffers()?;
+    }
+    Ok(())
+}
+
+/// Copies current back buffer, `frame` of the damage tracker, into exported
+/// `texture`
+unsafe fn copy_frame(
+    native: NativeIface,
+    dpy: *const c_void,
+    ly_capture: &LayerCapture,
+    texture: u32,
+    frame: Option<u64>,
+) {
+    let gl = gl(native);
+    let _state = GlStateGuard::new(gl, ly_capture.gles2);
+
     let width = ly_capture.width;
     let height = ly_capture.height;
-
-    let texture = match user_handle {
-        client::BufferUserHandle::Texture(v) => v,
-        _ => unreachable!(),
+    let export_width = ly_capture.export_width;
+    let export_height = ly_capture.export_height;
+    let region = ly_capture.export_region;
+    let damage = ly_capture.damage.lock().unwrap().copy(texture, frame);
+
+    let memfd_map = match ly_capture.mapped_textures.get(&texture).as_deref() {
+        Some(ExportTexture {
+            image: TextureImage::MemFd { map, .. },
+            ..
+        }) => Some(*map),
+        _ => None,
     };
+    if let Some(map) = memfd_map {
+        let dst: *mut u8 = map.as_ptr::<u8>() as _;
+        read_pixels(
+            gl,
+            ly_capture.gles2,
+            width,
+            height,
+            ly_capture.read_buffer,
+            dst,
+        );
+        // frames in shared memory are post-processed on the CPU
+        if let (Some(config), Some(texels)) = (
+            client::post_process(),
+            ly_capture.post_process_texels.as_ref(),
+        ) {
+            let stride = width as usize * 4;
+            let data = slice::from_raw_parts_mut(dst, stride * height as usize);
+            config.apply(texels, data, width, height, stride);
+        }
+        return;
+    }
 
-    let mut prev_read_fbo: i32 = 0;
-    let mut prev_draw_fbo: i32 = 0;
-    let mut prev_texture: i32 = 0;
-    let prev_srgb: u8 = gl.IsEnabled(gl_sys::FRAMEBUFFER_SRGB);
-    gl.GetIntegerv(gl_sys::READ_FRAMEBUFFER_BINDING, &mut prev_read_fbo);
-    gl.GetIntegerv(gl_sys::DRAW_FRAMEBUFFER_BINDING, &mut prev_draw_fbo);
-    gl.GetIntegerv(gl_sys::TEXTURE_BINDING_2D, &mut prev_texture);
-    {
-        if prev_srgb != 0 {
-            gl.Disable(gl_sys::FRAMEBUFFER_SRGB);
+    if let Some(shader_copy) = ly_capture.shader_copy.as_ref() {
+        shader_copy.copy(texture);
+        post_process_texture(gl, ly_capture, texture);
+        // GL sync objects might be absent from GLES2 contexts
+        let sync = match native {
+            NativeIface::Egl => FenceSync::new_egl(dpy),
+            _ => FenceSync::new(native, dpy),
+        };
+        if attach_native_fence(dpy, ly_capture, texture) {
+            // consumers wait for the copy through implicit sync
+        } else if let Some(sync) = sync {
+            ly_capture.sync_objects.insert(texture, sync);
+        } else {
+            gl.Finish();
         }
-        let mut fbo: u32 = 0;
-        gl.GenFramebuffers(1, &mut fbo);
+        return;
+    }
 
-        gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, 0);
+    gl.Disable(gl_sys::FRAMEBUFFER_SRGB);
+    // blits are clipped by the scissor box
+    gl.Disable(gl_sys::SCISSOR_TEST);
+    // framebuffer objects are per context, so one is created on every copy
+    // instead of being shared by contexts of the surface
+    let mut fbo: u32 = 0;
+    gl.GenFramebuffers(1, &mut fbo);
+
+    gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, 0);
+    gl.BindFramebuffer(gl_sys::DRAW_FRAMEBUFFER, fbo);
+    gl.BindTexture(gl_sys::TEXTURE_2D, texture);
+    gl.FramebufferTexture2D(
+        gl_sys::DRAW_FRAMEBUFFER,
+        gl_sys::COLOR_ATTACHMENT0,
+        gl_sys::TEXTURE_2D,
+        texture,
+        0,
+    );
+
+    if gl.ReadBuffer.is_loaded() {
+        gl.ReadBuffer(ly_capture.read_buffer);
+    } else {
+        unimplemented!()
+    }
+
+    if gl.DrawBuffers.is_loaded() {
+        let buffers = &[gl_sys::COLOR_ATTACHMENT0];
+        gl.DrawBuffers(1, buffers.as_ptr());
+    } else if gl.DrawBuffer.is_loaded() {
+        gl.DrawBuffer(gl_sys::COLOR_ATTACHMENT0);
+    } else {
+        unimplemented!()
+    }
+
+    // resolving blits can neither scale nor flip, resolve into a
+    // single-sampled renderbuffer first and blit from there
+    let mut resolve_fbo: u32 = 0;
+    if let Some(resolve_buffer) = ly_capture.resolve_buffer.as_ref() {
+        gl.GenFramebuffers(1, &mut resolve_fbo);
+        gl.BindFramebuffer(gl_sys::DRAW_FRAMEBUFFER, resolve_fbo);
+        gl.FramebufferRenderbuffer(
+            gl_sys::DRAW_FRAMEBUFFER,
+            gl_sys::COLOR_ATTACHMENT0,
+            gl_sys::RENDERBUFFER,
+            resolve_buffer.renderbuffer,
+        );
+        gl.BlitFramebuffer(
+            0,
+            0,
+            width as _,
+            height as _,
+            0,
+            0,
+            width as _,
+            height as _,
+            gl_sys::COLOR_BUFFER_BIT,
+            gl_sys::NEAREST,
+        );
+        gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, resolve_fbo);
+        gl.ReadBuffer(gl_sys::COLOR_ATTACHMENT0);
         gl.BindFramebuffer(gl_sys::DRAW_FRAMEBUFFER, fbo);
-        gl.BindTexture(gl_sys::TEXTURE_2D, texture);
+    }
+
+    if let Some(damage) = damage {
+        // the texture holds an earlier frame, only what changed since is
+        // copied, scissor state is restored by the state guard
+        let damage = scale_damage(damage, (width, height), region);
+        gl.Enable(gl_sys::SCISSOR_TEST);
+        gl.Scissor(
+            damage.x as _,
+            damage.y as _,
+            damage.width as _,
+            damage.height as _,
+        );
+    }
+
+    if (region.width, region.height) != (export_width, export_height) {
+        // clear color and mask are restored by the state guard
+        gl.ClearColor(0.0, 0.0, 0.0, 1.0);
+        gl.ColorMask(gl_sys::TRUE, gl_sys::TRUE, gl_sys::TRUE, gl_sys::TRUE);
+        gl.Clear(gl_sys::COLOR_BUFFER_BIT);
+    }
+
+    if gl.BlitFramebuffer.is_loaded() {
+        let filter = if (width, height) == (region.width, region.height) {
+            gl_sys::NEAREST
+        } else {
+            gl_sys::LINEAR
+        };
+        // flipped, rows of exported textures start at the top
+        gl.BlitFramebuffer(
+            0,
+            0,
+            width as _,
+            height as _,
+            region.x as _,
+            (region.y + region.height) as _,
+            (region.x + region.width) as _,
+            region.y as _,
+            gl_sys::COLOR_BUFFER_BIT,
+            filter,
+        );
+    } else {
+        unimplemented!()
+    }
+    post_process_texture(gl, ly_capture, texture);
+
+    if attach_native_fence(dpy, ly_capture, texture) {
+        // consumers wait for the copy through implicit sync
+    } else if let Some(sync) = FenceSync::new(native, dpy) {
+        ly_capture.sync_objects.insert(texture, sync);
+    } else {
+        gl.Finish();
+    }
+
+    gl.DeleteFramebuffers(1, &fbo);
+    if resolve_fbo != 0 {
+        gl.DeleteFramebuffers(1, &resolve_fbo);
+    }
+}
+
+/// Blacks out masks and copies runs of the watermark onto exported `texture`
+/// once a frame got copied into it, changed state is restored by the guard of
+/// the caller. Clears and copies work the same on GL and GLES2.
+unsafe fn post_process_texture(gl: &Gl, ly_capture: &LayerCapture, texture: u32) {
+    let config = match client::post_process() {
+        Some(v) => v,
+        None => return,
+    };
+    let region = ly_capture.export_region;
+    let masks = config.mask_rects(region.width, region.height);
+    let watermark = ly_capture
+        .watermark
+        .as_ref()
+        .zip(config.watermark.as_ref())
+        .zip(config.watermark_rect(region.width, region.height));
+    if masks.is_empty() && watermark.is_none() {
+        return;
+    }
+
+    let mut fbo: u32 = 0;
+    gl.GenFramebuffers(1, &mut fbo);
+    gl.BindFramebuffer(gl_sys::FRAMEBUFFER, fbo);
+    gl.FramebufferTexture2D(
+        gl_sys::FRAMEBUFFER,
+        gl_sys::COLOR_ATTACHMENT0,
+        gl_sys::TEXTURE_2D,
+        texture,
+        0,
+    );
+
+    // rows of exported textures start at the top, as do masks
+    gl.Enable(gl_sys::SCISSOR_TEST);
+    gl.ColorMask(gl_sys::TRUE, gl_sys::TRUE, gl_sys::TRUE, gl_sys::TRUE);
+    gl.ClearColor(0.0, 0.0, 0.0, 1.0);
+    for rect in masks {
+        gl.Scissor(
+            (region.x + rect.x) as _,
+            (region.y + rect.y) as _,
+            rect.width as _,
+            rect.height as _,
+        );
+        gl.Clear(gl_sys::COLOR_BUFFER_BIT);
+    }
+    gl.Disable(gl_sys::SCISSOR_TEST);
+
+    if let Some(((watermark_texture, watermark), rect)) = watermark {
+        // copied from the watermark texture as read framebuffer
         gl.FramebufferTexture2D(
-            gl_sys::DRAW_FRAMEBUFFER,
+            gl_sys::FRAMEBUFFER,
             gl_sys::COLOR_ATTACHMENT0,
             gl_sys::TEXTURE_2D,
-            texture,
+            watermark_texture.texture,
             0,
         );
+        gl.BindTexture(gl_sys::TEXTURE_2D, texture);
+        for run in watermark.runs() {
+            gl.CopyTexSubImage2D(
+                gl_sys::TEXTURE_2D,
+                0,
+                (region.x + rect.x + run.x) as _,
+                (region.y + rect.y + run.y) as _,
+                run.x as _,
+                run.y as _,
+                run.len as _,
+                1,
+            );
+        }
+    }
 
-        if gl.ReadBuffer.is_loaded() {
-            gl.ReadBuffer(gl_sys::BACK);
-        } else {
-            unimplemented!()
+    gl.DeleteFramebuffers(1, &fbo);
+}
+
+/// Attaches a native fence of the copy into `texture` to its DMA-BUF, `false`
+/// if the copy has to be fenced otherwise
+#[named]
+unsafe fn attach_native_fence(dpy: *const c_void, ly_capture: &LayerCapture, texture: u32) -> bool {
+    if !(ly_capture.native_fence_sync && client::sync_file_enabled()) {
+        return false;
+    }
+    // planes share the same DMA-BUF
+    let dma_buf = match ly_capture.mapped_textures.get(&texture) {
+        Some(export_texture) => match export_texture.planes.first() {
+            Some(plane) => plane.fd as RawFd,
+            None => return false,
+        },
+        None => return false,
+    };
+    let sync_file = match dup_native_fence_fd(dpy) {
+        Some(v) => v,
+        None => return false,
+    };
+    let res = client::dma_buf_import_sync_file(dma_buf, sync_file);
+    libc::close(sync_file);
+    match res {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("failed to attach sync file: {e}");
+            false
         }
+    }
+}
 
-        if gl.DrawBuffers.is_loaded() {
-            let buffers = &[gl_sys::COLOR_ATTACHMENT0];
-            gl.DrawBuffers(1, buffers.as_ptr());
-        } else if gl.DrawBuffer.is_loaded() {
-            gl.DrawBuffer(gl_sys::COLOR_ATTACHMENT0);
-        } else {
-            unimplemented!()
+/// Draws the capture indicator onto current back buffer by clearing a scissor
+/// box, which works the same on GL and GLES without touching app programs
+unsafe fn draw_indicator(native: NativeIface, ly_capture: &LayerCapture) {
+    let gl = gl(native);
+
+    let rect = match client::indicator_rect(ly_capture.width, ly_capture.height) {
+        Some(v) => v,
+        None => return,
+    };
+    let _state = GlStateGuard::new(gl, ly_capture.gles2);
+
+    // GLES2 contexts copied through shaders lack separate draw framebuffers
+    let target = if ly_capture.shader_copy.is_some() {
+        gl_sys::FRAMEBUFFER
+    } else {
+        gl_sys::DRAW_FRAMEBUFFER
+    };
+    gl.BindFramebuffer(target, 0);
+    gl.Enable(gl_sys::SCISSOR_TEST);
+    // GL framebuffers are bottom-up
+    gl.Scissor(
+        rect.x as _,
+        (ly_capture.height - rect.y - rect.size) as _,
+        rect.size as _,
+        rect.size as _,
+    );
+    gl.ColorMask(gl_sys::TRUE, gl_sys::TRUE, gl_sys::TRUE, gl_sys::TRUE);
+    gl.ClearColor(1.0, 0.0, 0.0, 1.0);
+    gl.Clear(gl_sys::COLOR_BUFFER_BIT);
+}
+
+/// Reads `read_buffer` of the current context into top-down BGRx rows at `dst`,
+/// changed state is restored by the guard of the caller
+unsafe fn read_pixels(
+    gl: &Gl,
+    gles2: bool,
+    width: u32,
+    height: u32,
+    read_buffer: u32,
+    dst: *mut u8,
+) {
+    let stride = width as usize * 4;
+
+    // GLES2 only reads the back buffer of its single framebuffer binding and
+    // lacks pixel buffers
+    if gles2 {
+        gl.BindFramebuffer(gl_sys::FRAMEBUFFER, 0);
+    } else {
+        gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, 0);
+        if gl.ReadBuffer.is_loaded() {
+            gl.ReadBuffer(read_buffer);
         }
+        gl.BindBuffer(gl_sys::PIXEL_PACK_BUFFER, 0);
+        gl.PixelStorei(gl_sys::PACK_ROW_LENGTH, 0);
+    }
+    gl.PixelStorei(gl_sys::PACK_ALIGNMENT, 4);
 
-        if gl.BlitFramebuffer.is_loaded() {
-            gl.BlitFramebuffer(
-                0,
-                0,
-                width as _,
-                height as _,
-                0,
-                height as _,
-                width as _,
-                0 as _,
-                gl_sys::COLOR_BUFFER_BIT,
-                gl_sys::NEAREST,
-            );
-        } else {
-            unimplemented!()
+    let mut pixels = vec![0u8; stride * height as usize];
+    gl.ReadPixels(
+        0,
+        0,
+        width as _,
+        height as _,
+        gl_sys::BGRA,
+        gl_sys::UNSIGNED_BYTE,
+        pixels.as_mut_ptr() as _,
+    );
+    // rows of GL framebuffers are bottom-up
+    let dst = slice::from_raw_parts_mut(dst, pixels.len());
+    for (dst_row, src_row) in dst
+        .chunks_exact_mut(stride)
+        .zip(pixels.chunks_exact(stride).rev())
+    {
+        dst_row.copy_from_slice(src_row);
+    }
+}
+
+unsafe fn query_surface_colorimetry(
+    native: NativeIface,
+    dpy: *const c_void,
+    surface: *const c_void,
+) -> client::Colorimetry {
+    match native {
+        NativeIface::Egl => {
+            let egl = egl();
+            let mut colorspace: i32 = egl_sys::GL_COLORSPACE_LINEAR as _;
+            egl.QuerySurface(dpy, surface, egl_sys::GL_COLORSPACE as _, &mut colorspace);
+            egl_colorspace_get_colorimetry(colorspace)
         }
+        NativeIface::Glx => client::Colorimetry::default(),
+    }
+}
 
-        if let Some(sync) = FenceSync::new(native, dpy) {
-            ly_capture.sync_objects.insert(texture, sync);
-        } else {
-            gl.Finish();
+/// Determines framebuffer format from the config the surface was created with
+unsafe fn query_framebuffer_format(
+    native: NativeIface,
+    dpy: *const c_void,
+    surface: *const c_void,
+) -> FramebufferFormat {
+    let red_size = match native {
+        NativeIface::Egl => {
+            let egl = egl();
+            let mut config_id: i32 = 0;
+            egl.QuerySurface(dpy, surface, egl_sys::CONFIG_ID as _, &mut config_id);
+            let attrib_list = [egl_sys::CONFIG_ID as i32, config_id, egl_sys::NONE as _];
+            let mut config: egl_t::EGLConfig = ptr::null();
+            let mut num: i32 = 0;
+            egl.ChooseConfig(dpy, attrib_list.as_ptr(), &mut config, 1, &mut num);
+            if num < 1 {
+                return FramebufferFormat::default();
+            }
+            let mut red_size: i32 = 0;
+            egl.GetConfigAttrib(dpy, config, egl_sys::RED_SIZE as _, &mut red_size);
+            red_size
         }
-
-        gl.DeleteFramebuffers(1, &fbo);
-    }
-    gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, prev_read_fbo as _);
-    gl.BindFramebuffer(gl_sys::DRAW_FRAMEBUFFER, prev_draw_fbo as _);
-    gl.BindTexture(gl_sys::TEXTURE_2D, prev_texture as _);
-    if prev_srgb != 0 {
-        gl.Enable(gl_sys::FRAMEBUFFER_SRGB);
-    } else {
-        gl.Disable(gl_sys::FRAMEBUFFER_SRGB);
+        NativeIface::Glx => {
+            let glx = glx();
+            let x11 = match X11_LIB.as_ref() {
+                Some(v) => v,
+                None => return FramebufferFormat::default(),
+            };
+            let mut config_id: u32 = 0;
+            glx.QueryDrawable(
+                dpy as _,
+                surface as _,
+                glx_sys::FBCONFIG_ID as _,
+                &mut config_id,
+            );
+            let attrib_list = [glx_sys::FBCONFIG_ID as i32, config_id as _, 0];
+            let screen = (x11.XDefaultScreen)(dpy as _);
+            let mut num = 0;
+            let fb_configs = glx.ChooseFBConfig(dpy as _, screen, attrib_list.as_ptr(), &mut num);
+            if num <= 0 || fb_configs.is_null() {
+                return FramebufferFormat::default();
+            }
+            let mut red_size = 0;
+            glx.GetFBConfigAttrib(dpy as _, *fb_configs, glx_sys::RED_SIZE as _, &mut red_size);
+            (x11.XFree)(fb_configs as _);
+            red_size
+        }
+    };
+    // 16-bit default framebuffers are floating point in practice
+    match red_size {
+        10 => FramebufferFormat::Rgb10A2,
+        16 => FramebufferFormat::Rgba16F,
+        _ => FramebufferFormat::Rgba8,
     }
-
-    stream.try_queue_buffer_process(buffer)??
 }
 
 unsafe fn query_surface_extent(
@@ -570,7 +1503,19 @@ unsafe fn query_surface_extent(
 }
 
 #[named]
-unsafe fn try_capture(native: NativeIface, dpy: *const c_void, surface: *const c_void) {
+unsafe fn try_capture(
+    native: NativeIface,
+    dpy: *const c_void,
+    surface: *const c_void,
+    damage: Option<&[client::Region]>,
+) {
+    if !client::app_allowed() || !client::capture_enabled() {
+        return;
+    }
+    if !is_current_surface(native, dpy, surface) {
+        trace!("{:?} is not current, not capturing", surface);
+        return;
+    }
     let surface_handle = glhandle!(surface);
     if let Some(ly_display) = SURFACE_MAP.get(&surface_handle) {
         if !ly_display.capture_valid {
@@ -580,8 +1525,108 @@ unsafe fn try_capture(native: NativeIface, dpy: *const c_void, surface: *const c
         try_init_surface(native, dpy, surface, None);
     };
 
+    let (capture_lock, frame) = match SURFACE_MAP.get(&surface_handle) {
+        Some(ly_surface) => {
+            // frames skipped below still add to damage of exported textures
+            let frame = ly_surface
+                .capture
+                .as_ref()
+                .map(|v| v.damage.lock().unwrap().push(damage));
+            (ly_surface.capture_lock.clone(), frame)
+        }
+        None => return,
+    };
+    // frames swapped while another thread copies one are skipped rather than
+    // stalling the swap
+    let _capture_guard = match capture_lock.try_lock() {
+        Ok(v) => v,
+        Err(TryLockError::WouldBlock) => {
+            trace!("{:?} is being captured by another thread", surface);
+            return;
+        }
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+    };
+
+    if !init_capture(native, dpy, surface) {
+        return;
+    }
+    if let Some(ly_surface) = SURFACE_MAP.get(&surface_handle) {
+        let ly_capture = ly_surface.capture.as_ref().unwrap();
+        if let Err(e) = update_window_props(&ly_surface, ly_capture) {
+            warn!("failed to update window properties: {e:?}");
+        }
+        // frames of hidden windows are stale or garbage
+        if ly_capture.window_suspended.load(atomic::Ordering::Acquire) {
+            trace!("window suspended, skipping frame");
+            return;
+        }
+        if let Err(e) = capture(native, dpy, ly_capture, ly_surface.offscreen, frame) {
+            warn!("capture error: {e:?}");
+        }
+    } else {
+        error!("surface data not exist")
+    }
+}
+
+/// Creates the capture of a window surface once a context got made current to
+/// it, so its node exists before the first frame is swapped, e.g. for apps
+/// rendering a single frame. Only surfaces known from their creation are
+/// prepared, X windows rendered to without a GLXWindow are still set up on
+/// their first swap.
+#[named]
+unsafe fn try_prepare_capture(native: NativeIface, dpy: *const c_void, surface: *const c_void) {
+    if !client::app_allowed() || !client::capture_enabled() {
+        return;
+    }
+    let surface_handle = glhandle!(surface);
+    let capture_lock = match SURFACE_MAP.get(&surface_handle) {
+        Some(ly_surface)
+            if ly_surface.capture_valid
+                && !ly_surface.offscreen
+                && ly_surface.capture.is_none() =>
+        {
+            ly_surface.capture_lock.clone()
+        }
+        _ => return,
+    };
+    let _capture_guard = match capture_lock.try_lock() {
+        Ok(v) => v,
+        Err(TryLockError::WouldBlock) => return,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+    };
+
+    debug!("preparing capture of {:?}", surface);
+    if !init_capture(native, dpy, surface) {
+        return;
+    }
+    if let Some(ly_surface) = SURFACE_MAP.get(&surface_handle) {
+        let ly_capture = ly_surface.capture.as_ref().unwrap();
+        if let Err(e) = update_window_props(&ly_surface, ly_capture) {
+            warn!("failed to update window properties: {e:?}");
+        }
+    }
+}
+
+/// Creates or resizes the capture of `surface` for the current context,
+/// returns whether it is ready. Surfaces failing to are no longer captured.
+#[named]
+unsafe fn init_capture(native: NativeIface, dpy: *const c_void, surface: *const c_void) -> bool {
+    let surface_handle = glhandle!(surface);
+    // resources of the capture can't be used by contexts not sharing them,
+    // frames of such contexts are skipped
+    let share_group = get_current_context(native).map(get_share_group);
+    if let Some(ly_surface) = SURFACE_MAP.get(&surface_handle) {
+        match ly_surface.capture.as_ref() {
+            Some(ly_capture) if Some(ly_capture.share_group) != share_group => {
+                trace!("{:?} is current to another share group", surface);
+                return false;
+            }
+            _ => (),
+        }
+    }
+
     match try_init_capture(native, dpy, surface) {
-        Ok(()) => (),
+        Ok(()) => true,
         Err(e) => {
             if let Some(mut ly_surface) = SURFACE_MAP.get_mut(&surface_handle) {
                 ly_surface.capture_valid = false;
@@ -590,16 +1635,91 @@ unsafe fn try_capture(native: NativeIface, dpy: *const c_void, surface: *const c
                 drop(capture);
             }
             warn!("failed to init capture context: {e:?}");
-            return;
+            false
         }
     }
-    if let Some(ly_surface) = SURFACE_MAP.get(&surface_handle) {
-        if let Err(e) = capture(native, dpy, ly_surface.capture.as_ref().unwrap()) {
-            warn!("capture error: {e:?}");
+}
+
+/// Whether `surface` is both draw and read surface of the context current to
+/// this thread, on `dpy` for EGL as surface handles are per display. Frames
+/// are copied from the default framebuffer, i.e. the read surface, while GLX
+/// apps may swap drawables other than the current one, apps rendering several
+/// surfaces with one context may keep another one current and multithreaded
+/// apps may swap from a thread the surface is not current to.
+unsafe fn is_current_surface(
+    native: NativeIface,
+    dpy: *const c_void,
+    surface: *const c_void,
+) -> bool {
+    match native {
+        NativeIface::Egl => {
+            let egl = egl();
+            egl.GetCurrentDisplay() == dpy
+                && egl.GetCurrentSurface(egl_sys::DRAW as _) == surface
+                && egl.GetCurrentSurface(egl_sys::READ as _) == surface
+        }
+        NativeIface::Glx => {
+            let glx = glx();
+            let drawable = surface as glx_t::GLXDrawable;
+            // GLX 1.2 lacks separate read drawables
+            glx.GetCurrentDrawable() == drawable
+                && (!glx.GetCurrentReadDrawable.is_loaded()
+                    || glx.GetCurrentReadDrawable() == drawable)
+        }
+    }
+}
+
+/// Current context along with its display and draw surface
+unsafe fn get_current_draw_surface() -> Option<(NativeIface, *const c_void, *const c_void)> {
+    // EGL is always set up by eglGetDisplay() before any context exists
+    if let Some((_, egl)) = Lazy::get(&GL_EGL).and_then(Option::as_ref) {
+        if !egl.GetCurrentContext().is_null() {
+            let dpy = egl.GetCurrentDisplay();
+            let surface = egl.GetCurrentSurface(egl_sys::DRAW as _);
+            return Some((NativeIface::Egl, dpy, surface));
         }
-    } else {
-        error!("surface data not exist")
     }
+    let (_, glx) = GL_GLX.as_ref()?;
+    if glx.GetCurrentContext().is_null() {
+        return None;
+    }
+    let dpy = glx.GetCurrentDisplay();
+    let drawable = glx.GetCurrentDrawable();
+    Some((NativeIface::Glx, dpy as _, drawable as _))
+}
+
+/// Captures current draw surface if it is an offscreen one, returns native
+/// interface of current context to forward the call to
+unsafe fn capture_offscreen() -> Option<NativeIface> {
+    let (native, dpy, surface) = get_current_draw_surface()?;
+    let offscreen = SURFACE_MAP
+        .get(&glhandle!(surface))
+        .map_or(false, |ly_surface| ly_surface.offscreen);
+    if offscreen {
+        try_capture(native, dpy, surface, None);
+    }
+    Some(native)
+}
+
+/// Records share group of a newly created context
+unsafe fn add_context(ctx: *const c_void, share_context: *const c_void) {
+    if ctx.is_null() || share_context.is_null() {
+        return;
+    }
+    let share_group = get_share_group(glhandle!(share_context));
+    SHARE_GROUP_MAP.insert(share_group, share_group);
+    SHARE_GROUP_MAP.insert(glhandle!(ctx), share_group);
+}
+
+/// Contexts not sharing objects form a share group of their own
+fn get_share_group(ctx: GlHandle) -> GlHandle {
+    SHARE_GROUP_MAP.get(&ctx).map_or(ctx, |v| *v)
+}
+
+/// Whether the context current to this thread shares objects of
+/// `share_group`, only then they can be deleted
+pub unsafe fn is_share_group_current(native: NativeIface, share_group: GlHandle) -> bool {
+    get_current_context(native).map(get_share_group) == Some(share_group)
 }
 
 unsafe fn get_current_context(native: NativeIface) -> Option<GlHandle> {
@@ -621,14 +1741,19 @@ unsafe fn get_current_context(native: NativeIface) -> Option<GlHandle> {
 
 #[named]
 unsafe fn create_xcb_cursor_manager(
-    _dpy: Option<*const c_void>,
-    _dpy_is_xcb: bool,
+    dpy: Option<*const c_void>,
+    dpy_is_xcb: bool,
     window: u32,
 ) -> Option<Box<dyn CursorManager + Send + Sync>> {
-    // create a new connection as we will use the connection in another thread,
-    // the drawback is it only connects to the default display or `DISPLAY`
-    // so it might not connect to actually server of window
-    match local_cursor::XcbWindow::new_connection(window) {
+    // the connection of the app is not used as cursors are queried in another
+    // thread, connections of the layer are shared per display of the app
+    let dpy = dpy.and_then(|v| ptr::NonNull::new(v as *mut c_void));
+    let res = match dpy {
+        Some(conn) if dpy_is_xcb => local_cursor::XcbWindow::for_xcb_connection(conn, window),
+        Some(dpy) => local_cursor::XcbWindow::for_xlib_display(dpy, window),
+        None => local_cursor::XcbWindow::new_connection(window),
+    };
+    match res {
         Ok(m) => Some(Box::new(m)),
         Err(e) => {
             warn!("failed to create xcb cursor manager: {e:?}");
@@ -655,8 +1780,23 @@ unsafe fn try_init_surface(
         native, dpy, surface, platform_surface
     );
 
+    // GLXWindows are no X windows, the ones they were created for are
+    let x_drawable = match native {
+        NativeIface::Glx => {
+            install_x_error_handler();
+            let window = GLX_WINDOW_MAP
+                .get(&surface_handle)
+                .map_or(surface as u32, |v| *v);
+            // not validating GLX-only drawables unknown to the layer
+            x_drawable_alive(dpy, window).then_some(window)
+        }
+        _ => None,
+    };
+
     let platform_surface = match native {
-        NativeIface::Glx => Some(glhandle!(surface)),
+        NativeIface::Glx => {
+            Some(x_drawable.map_or(surface_handle, |v| glhandle!(v as *const c_void)))
+        }
         _ => platform_surface.map(|v| glhandle!(v)),
     };
 
@@ -750,8 +1890,138 @@ unsafe fn try_init_surface(
         cursor_manager,
         capture_valid: true,
         capture: None,
+        offscreen: false,
+        swap_interval: None,
+        x_drawable,
+        capture_lock: Default::default(),
+    };
+    // another thread may have been first
+    SURFACE_MAP.insert_if_absent(surface_handle, ly_surface);
+}
+
+/// Tracks a pbuffer to be captured on glFlush()/glFinish()
+#[named]
+unsafe fn init_offscreen_surface(native: NativeIface, dpy: *const c_void, surface: *const c_void) {
+    debug!(
+        "native:{:?}, display:{:?}, offscreen surface:{:?}",
+        native, dpy, surface
+    );
+    let surface_handle = glhandle!(surface);
+    let ly_surface = LayerSurface {
+        native,
+        platform_surface: None,
+        display: glhandle!(dpy),
+        surface: surface_handle,
+        cursor_manager: None,
+        capture_valid: true,
+        capture: None,
+        offscreen: true,
+        swap_interval: None,
+        x_drawable: None,
+        capture_lock: Default::default(),
+    };
+    SURFACE_MAP.insert_if_absent(surface_handle, ly_surface);
+}
+
+/// Color buffer the default framebuffer reads from, `FRONT` on single
+/// buffered pbuffers
+unsafe fn get_default_read_buffer(gl: &Gl) -> u32 {
+    let mut prev_read_fbo: i32 = 0;
+    let mut read_buffer: i32 = 0;
+    gl.GetIntegerv(gl_sys::READ_FRAMEBUFFER_BINDING, &mut prev_read_fbo);
+    gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, 0);
+    gl.GetIntegerv(gl_sys::READ_BUFFER, &mut read_buffer);
+    gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, prev_read_fbo as _);
+    read_buffer as _
+}
+
+/// Whether the NVIDIA proprietary driver backs current context, its EGL
+/// (EGLStreams based before GBM support) lacks EGL_MESA_image_dma_buf_export
+unsafe fn gl_is_nvidia(gl: &Gl) -> bool {
+    let vendor = gl.GetString(gl_sys::VENDOR);
+    if vendor.is_null() {
+        return false;
+    }
+    CStr::from_ptr(vendor as _)
+        .to_bytes()
+        .starts_with(b"NVIDIA")
+}
+
+unsafe fn gl_context_props(gl: &Gl) -> Vec<(String, String)> {
+    [
+        (PROP_GL_VENDOR, gl_sys::VENDOR),
+        (PROP_GL_RENDERER, gl_sys::RENDERER),
+    ]
+    .into_iter()
+    .filter_map(|(key, name)| {
+        let value = gl.GetString(name);
+        if value.is_null() {
+            return None;
+        }
+        let value = CStr::from_ptr(value as _).to_string_lossy().into_owned();
+        Some((key.to_owned(), value))
+    })
+    .collect()
+}
+
+/// `GL_RGB565`, missing from the GL 3.0 bindings
+const GL_RGB565: u32 = 0x8D62;
+
+/// Internal format of the default framebuffer color buffer `read_buffer`,
+/// resolving blits on GLES fail unless the formats match exactly, e.g. RGB8
+/// or RGB565 of configs without alpha
+unsafe fn get_default_color_format(gl: &Gl, read_buffer: u32, fb_format: FramebufferFormat) -> u32 {
+    // GLES names color buffers of the default framebuffer `BACK` only
+    let attachment = match read_buffer {
+        _ if gl_is_gles(gl) => gl_sys::BACK,
+        gl_sys::FRONT => gl_sys::FRONT_LEFT,
+        _ => gl_sys::BACK_LEFT,
+    };
+    let mut prev_read_fbo: i32 = 0;
+    gl.GetIntegerv(gl_sys::READ_FRAMEBUFFER_BINDING, &mut prev_read_fbo);
+    gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, 0);
+    let param = |pname| {
+        let mut value: i32 = 0;
+        gl.GetFramebufferAttachmentParameteriv(
+            gl_sys::READ_FRAMEBUFFER,
+            attachment,
+            pname,
+            &mut value,
+        );
+        value
     };
-    SURFACE_MAP.insert(surface_handle, ly_surface);
+    let sizes = (
+        param(gl_sys::FRAMEBUFFER_ATTACHMENT_RED_SIZE),
+        param(gl_sys::FRAMEBUFFER_ATTACHMENT_GREEN_SIZE),
+        param(gl_sys::FRAMEBUFFER_ATTACHMENT_BLUE_SIZE),
+        param(gl_sys::FRAMEBUFFER_ATTACHMENT_ALPHA_SIZE),
+    );
+    let component_type = param(gl_sys::FRAMEBUFFER_ATTACHMENT_COMPONENT_TYPE);
+    gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, prev_read_fbo as _);
+    match (sizes, component_type as u32) {
+        ((5, 6, 5, 0), _) => GL_RGB565,
+        ((8, 8, 8, 0), _) => gl_sys::RGB8,
+        ((8, 8, 8, 8), _) => gl_sys::RGBA8,
+        ((10, 10, 10, _), _) => gl_sys::RGB10_A2,
+        ((16, 16, 16, _), gl_sys::FLOAT) => gl_sys::RGBA16F,
+        // unknown, going by the surface config
+        _ => match fb_format {
+            FramebufferFormat::Rgba8 => gl_sys::RGBA8,
+            FramebufferFormat::Rgb10A2 => gl_sys::RGB10_A2,
+            FramebufferFormat::Rgba16F => gl_sys::RGBA16F,
+        },
+    }
+}
+
+/// Sample count of the default framebuffer, 0 if not multisampled
+unsafe fn get_default_samples(gl: &Gl) -> i32 {
+    let mut prev_draw_fbo: i32 = 0;
+    let mut samples: i32 = 0;
+    gl.GetIntegerv(gl_sys::DRAW_FRAMEBUFFER_BINDING, &mut prev_draw_fbo);
+    gl.BindFramebuffer(gl_sys::DRAW_FRAMEBUFFER, 0);
+    gl.GetIntegerv(gl_sys::SAMPLES, &mut samples);
+    gl.BindFramebuffer(gl_sys::DRAW_FRAMEBUFFER, prev_draw_fbo as _);
+    samples
 }
 
 #[named]
@@ -762,40 +2032,172 @@ unsafe fn try_init_capture(
 ) -> Result<()> {
     let handle = glhandle!(surface);
     let context = get_current_context(native).ok_or(anyhow!("no context"))?;
+    let share_group = get_share_group(context);
     let (width, height) = query_surface_extent(native, dpy, surface);
     let gl = gl(native);
 
-    if !(gl.BlitFramebuffer.is_loaded()
+    let can_blit = gl.BlitFramebuffer.is_loaded()
         && gl.ReadBuffer.is_loaded()
-        && (gl.DrawBuffer.is_loaded() || gl.DrawBuffers.is_loaded()))
-    {
+        && (gl.DrawBuffer.is_loaded() || gl.DrawBuffers.is_loaded());
+    let gles2 = gl_is_gles2(gl);
+    let use_shader_copy = !can_blit || gles2;
+    if use_shader_copy && !(gl.CreateProgram.is_loaded() && gl.CopyTexSubImage2D.is_loaded()) {
         return Err(anyhow!("missing required GL methods"));
     }
 
+    let offscreen = SURFACE_MAP
+        .get(&handle)
+        .map_or(false, |ly_surface| ly_surface.offscreen);
     if let Some(mut ly_surface) = SURFACE_MAP.get_mut(&handle) {
         if let Some(ly_capture) = ly_surface.capture.as_ref() {
-            if ly_capture.context != context {
-                return Err(anyhow!("context switching not supported"));
+            // textures, programs and sync objects are shared, framebuffer
+            // objects are not but get created on every copy anyway
+            if ly_capture.share_group != share_group {
+                return Err(anyhow!("switching to context of another share group"));
             }
             if ly_capture.width == width && ly_capture.height == height {
                 return Ok(());
             }
         }
         if let Some(ly_capture) = ly_surface.capture.take() {
-            // IMPORTANT: drop the write lock first as try_terminate()
-            // would call into callbacks that requires read lock to surface item
+            // IMPORTANT: drop the write lock first as terminating would call
+            // into callbacks that requires read lock to surface item
             drop(ly_surface);
-            let _ = ly_capture.stream.proxy().try_terminate();
+            ly_capture.stream.worker().flush();
+            let _ = ly_capture.stream.proxy().drain_and_terminate();
         }
     } else {
         return Err(anyhow!("surface not exist"));
     }
 
-    info!("{:?}: {}x{}", native, width, height);
+    let colorimetry = query_surface_colorimetry(native, dpy, surface);
+    let mut fb_format = query_framebuffer_format(native, dpy, surface);
+    let surface_fb_format = fb_format;
+    info!(
+        "{:?}: {}x{} {:?} {:?}",
+        native, width, height, fb_format, colorimetry
+    );
+
+    // only blitting scales
+    let mut scale = if use_shader_copy {
+        client::Scale::Native
+    } else {
+        client::Scale::global()
+    };
+    let (mut export_width, mut export_height) = scale.apply(width, height);
+
+    let drm_devices = match native {
+        NativeIface::Egl => egl_get_drm_devices(dpy),
+        NativeIface::Glx => vec![],
+    };
+    let host_export = !client::DrmDevice::is_target(&drm_devices);
+    debug!("drm devices: {drm_devices:?}, host export: {host_export}");
+
+    let buffer_demand = client::BufferDemand::new(client::max_buffers_from_env(MAX_BUFFERS));
+    let mut use_read_pixels = false;
+    let mut res = if host_export {
+        Err(anyhow!("consumers import on another device"))
+    } else {
+        create_target_textures(
+            native,
+            dpy,
+            export_width,
+            export_height,
+            buffer_demand.current(),
+            fb_format,
+            false,
+        )
+    };
+    while let (false, Err(e), Some(fallback)) = (host_export, &res, fb_format.fallback()) {
+        warn!("failed to export {fb_format:?} textures, falling back to {fallback:?}: {e:?}");
+        fb_format = fallback;
+        res = create_target_textures(
+            native,
+            dpy,
+            export_width,
+            export_height,
+            buffer_demand.current(),
+            fb_format,
+            false,
+        );
+    }
+    let (format, modifier, num_planes, textures) = match res {
+        Ok(v) => v,
+        Err(e) if native == NativeIface::Glx || gl_is_nvidia(gl) || host_export => {
+            warn!("failed to export DMA-BUF, falling back to glReadPixels: {e:?}");
+            use_read_pixels = true;
+            // frames are read back as 8-bit BGRx
+            fb_format = FramebufferFormat::Rgba8;
+            scale = client::Scale::Native;
+            (export_width, export_height) = (width, height);
+            let num = buffer_demand.current();
+            create_target_textures(native, dpy, width, height, num, fb_format, true)?
+        }
+        Err(e) => return Err(e),
+    };
+    if (export_width, export_height) != (width, height) {
+        info!("scaling to {}x{}", export_width, export_height);
+    }
+
+    // shader copies read whatever the default framebuffer reads from
+    let read_buffer = if offscreen && !use_shader_copy {
+        get_default_read_buffer(gl)
+    } else {
+        gl_sys::BACK
+    };
+
+    let shader_copy = if use_shader_copy && !use_read_pixels {
+        info!("BlitFramebuffer not usable, copying with shader");
+        Some(ShaderCopy::new(native, share_group, width, height)?)
+    } else {
+        None
+    };
+
+    // glReadPixels resolves by itself
+    let samples = get_default_samples(gl);
+    let resolve_buffer = if samples > 0 && !use_shader_copy && !use_read_pixels {
+        let internal_format = get_default_color_format(gl, read_buffer, surface_fb_format);
+        info!("resolving {samples}x multisampled framebuffer into {internal_format:#x}");
+        Some(ResolveBuffer::new(
+            native,
+            share_group,
+            width,
+            height,
+            internal_format,
+        ))
+    } else {
+        None
+    };
+
+    // frames read into shared memory are post-processed on the CPU, as 8-bit
+    // BGRx
+    let watermark = match client::post_process().and_then(|v| v.watermark.as_ref()) {
+        Some(watermark) if !use_read_pixels => {
+            WatermarkTexture::new(native, share_group, gles2, watermark, fb_format)
+        }
+        _ => None,
+    };
+    let post_process_texels = match client::post_process() {
+        Some(config) if use_read_pixels => config.encode(client::Format::BGRx),
+        _ => None,
+    };
+
+    let native_fence_sync = matches!(native, NativeIface::Egl)
+        && !use_read_pixels
+        && client::sync_file_enabled()
+        && egl_has_extension(dpy, "EGL_ANDROID_native_fence_sync");
+    if native_fence_sync {
+        debug!("attaching native fences to exported textures");
+    }
 
-    let (format, modifier, num_planes, textures) =
-        create_target_textures(native, dpy, width, height, MAX_BUFFERS)?;
+    let mut props = gl_context_props(gl);
+    // frames in shared memory are not bound to a device
+    match drm_devices.first() {
+        Some(drm_device) if !use_read_pixels => props.extend(drm_device.as_props()),
+        _ => (),
+    }
 
+    let streaming = Arc::new(AtomicBool::new(false));
     let stream = create_stream(
         handle,
         format,
@@ -804,19 +2206,48 @@ unsafe fn try_init_capture(
         textures.len() as _,
         width as _,
         height as _,
+        colorimetry,
+        scale,
+        props,
+        streaming.clone(),
     )?;
 
     let ly_capture = LayerCapture {
-        context,
+        share_group,
         cursor_serial: AtomicU64::new(0),
+        window_serial: AtomicU64::new(0),
+        window_suspended: AtomicBool::new(false),
         width,
         height,
+        export_width,
+        export_height,
+        export_region: scale.region(width, height),
         stream,
+        streaming,
+        export_format: (format, modifier, num_planes),
+        use_read_pixels,
+        read_buffer,
+        gles2,
+        fb_format,
+        buffer_demand: Mutex::new(buffer_demand),
         free_textures: Mutex::new(textures),
         mapped_textures: DashMap::new(),
         sync_objects: DashMap::new(),
+        native_fence_sync,
+        shader_copy,
+        resolve_buffer,
+        watermark,
+        post_process_texels,
+        damage: Default::default(),
     };
 
+    let swap_interval = match SURFACE_MAP.get(&handle) {
+        Some(ly_surface) => ly_surface.swap_interval,
+        None => return Err(anyhow!("surface not exist")),
+    };
+    if let Some(interval) = swap_interval {
+        update_swap_interval(&ly_capture.stream.proxy(), interval);
+    }
     if let Some(mut ly_surface) = SURFACE_MAP.get_mut(&handle) {
         ly_surface.capture_valid = true;
         ly_surface.capture = Some(ly_capture);
@@ -838,17 +2269,33 @@ unsafe fn destroy_surface(dpy: *const c_void, surface: *const c_void) {
             }
             if let Some(context) = get_current_context(ly_surface.native) {
                 if let Some(ly_capture) = &ly_surface.capture {
-                    if ly_capture.context == context {
+                    let share_group = get_share_group(context);
+                    if ly_capture.share_group == share_group {
                         break 'outer;
                     }
-                    warn!("context changed: {:?} -> {:?}", ly_capture.context, context);
+                    warn!(
+                        "share group changed: {:?} -> {:?}",
+                        ly_capture.share_group, share_group
+                    );
                 }
             }
         }
         return;
     }
-    if let Some((_, _ly_surface)) = SURFACE_MAP.remove(&glhandle!(surface)) {
-        // extra destroy work
+    if let Some((_, ly_surface)) = SURFACE_MAP.remove(&glhandle!(surface)) {
+        // queued frames refer to textures of the capture
+        if let Some(ly_capture) = ly_surface.capture.as_ref() {
+            ly_capture.stream.worker().flush();
+        }
+    }
+}
+
+/// Destroys GLX surfaces of `dpy` of which the app destroyed the X window
+/// without glXDestroyWindow()
+unsafe fn collect_dead_glx_surfaces(dpy: *const c_void) {
+    for surface in collect_dead_surfaces(dpy) {
+        destroy_surface(dpy, surface.as_ptr());
+        GLX_WINDOW_MAP.remove(&surface);
     }
 }
 
@@ -856,11 +2303,17 @@ unsafe fn destroy_surface(dpy: *const c_void, surface: *const c_void) {
 unsafe fn destroy_context(dpy: *const c_void, ctx: *const c_void) {
     debug!("destroying context {:?}", ctx);
     let ctx = glhandle!(ctx);
+    let share_group = get_share_group(ctx);
+    SHARE_GROUP_MAP.remove(&ctx);
+    // objects live on as long as any context of the share group does
+    if SHARE_GROUP_MAP.iter().any(|v| *v.value() == share_group) {
+        return;
+    }
     let to_destroy = SURFACE_MAP
         .iter()
         .filter_map(|ly_surface| {
             if let Some(ly_capture) = &ly_surface.capture {
-                if ly_capture.context == ctx {
+                if ly_capture.share_group == share_group {
                     return Some(*ly_surface.key());
                 }
             }
@@ -877,12 +2330,14 @@ const DRM_FORMAT_ABGR8888: i32 = 0x34324241;
 const DRM_FORMAT_XBGR8888: i32 = 0x34324258;
 const DRM_FORMAT_ABGR2101010: i32 = 0x30334241;
 const DRM_FORMAT_XBGR2101010: i32 = 0x30334258;
+const DRM_FORMAT_ABGR16161616F: i32 = 0x48344241;
 
 unsafe fn egl_export_dmabuf(
     dpy: *const c_void,
     width: u32,
     height: u32,
     texture: u32,
+    fb_format: FramebufferFormat,
 ) -> Result<(client::Format, u64, TextureImage, Vec<BufferPlaneInfo>)> {
     let (gl, egl) = GL_EGL.as_ref().unwrap();
     if !(egl.ExportDMABUFImageQueryMESA.is_loaded() && egl.ExportDMABUFImageMESA.is_loaded()) {
@@ -890,27 +2345,24 @@ unsafe fn egl_export_dmabuf(
     }
 
     gl.BindTexture(gl_sys::TEXTURE_2D, texture);
-    // TODO: allows other compatible formats,
-    // how to determine format of current render buffers?
-    // glGetRenderbufferParameteriv of GL_RENDERBUFFER_INTERNAL_FORMAT
-    // glxQueryDrawable of GLX_TEXTURE_FORMAT_EXT
-    // eglQuerySurface of EGL_TEXTURE_FORMAT
-    // all returns receiver variable unmodified
-    //
-    // Possible solution: hook to all functions setting formats then recoding
+    let (internal_format, type_) = match fb_format {
+        FramebufferFormat::Rgba8 => (gl_sys::RGBA, gl_sys::UNSIGNED_BYTE),
+        FramebufferFormat::Rgb10A2 => (gl_sys::RGB10_A2, gl_sys::UNSIGNED_INT_2_10_10_10_REV),
+        FramebufferFormat::Rgba16F => (gl_sys::RGBA16F, gl_sys::HALF_FLOAT),
+    };
     gl.TexImage2D(
         gl_sys::TEXTURE_2D,
         0,
-        gl_sys::RGBA as _,
+        internal_format as _,
         width as _,
         height as _,
         0,
         gl_sys::RGBA,
-        gl_sys::UNSIGNED_BYTE,
+        type_,
         ptr::null(),
     );
     let image = if egl.CreateImage.is_loaded() {
-        egl.CreateImageKHR(
+        egl.CreateImage(
             dpy,
             egl.GetCurrentContext(),
             egl_sys::GL_TEXTURE_2D,
@@ -918,7 +2370,7 @@ unsafe fn egl_export_dmabuf(
             ptr::null(),
         )
     } else if egl.CreateImageKHR.is_loaded() {
-        egl.CreateImage(
+        egl.CreateImageKHR(
             dpy,
             egl.GetCurrentContext(),
             egl_sys::GL_TEXTURE_2D,
@@ -970,6 +2422,7 @@ unsafe fn egl_export_dmabuf(
             DRM_FORMAT_XBGR8888 => client::Format::RGBx,
             DRM_FORMAT_ABGR2101010 => client::Format::ABGR_210LE,
             DRM_FORMAT_XBGR2101010 => client::Format::xBGR_210LE,
+            DRM_FORMAT_ABGR16161616F => client::Format::RGBA_F16,
             _ => return Err(anyhow!("unhandled DRM format {:#x}", fourcc)),
         };
         return Ok((format, modifier, image, planes));
@@ -985,6 +2438,7 @@ unsafe fn glx_export_dmabuf(
     width: u32,
     height: u32,
     texture: u32,
+    fb_format: FramebufferFormat,
 ) -> Result<(client::Format, u64, TextureImage, Vec<BufferPlaneInfo>)> {
     let (gl, glx) = GL_GLX.as_ref().unwrap();
     let x11 = X11_LIB
@@ -1000,8 +2454,27 @@ unsafe fn glx_export_dmabuf(
         return Err(anyhow!("no xcb connection"));
     }
 
+    let (bind_to_texture, texture_format, color_size, alpha_size) = match fb_format {
+        FramebufferFormat::Rgba8 => (
+            glx_sys::BIND_TO_TEXTURE_RGBA_EXT,
+            glx_sys::TEXTURE_FORMAT_RGBA_EXT,
+            8,
+            8,
+        ),
+        // depth 30 pixmaps have no alpha
+        FramebufferFormat::Rgb10A2 => (
+            glx_sys::BIND_TO_TEXTURE_RGB_EXT,
+            glx_sys::TEXTURE_FORMAT_RGB_EXT,
+            10,
+            0,
+        ),
+        // X servers know no pixmap depth for half floats
+        FramebufferFormat::Rgba16F => {
+            return Err(anyhow!("no pixmap format for {:?}", fb_format));
+        }
+    };
     let attrib_list = SSlice::<_>::from_slice(&[
-        glx_sys::BIND_TO_TEXTURE_RGBA_EXT,
+        bind_to_texture,
         1,
         glx_sys::DRAWABLE_TYPE,
         glx_sys::PIXMAP_BIT,
@@ -1012,13 +2485,13 @@ unsafe fn glx_export_dmabuf(
         glx_sys::DOUBLEBUFFER,
         0,
         glx_sys::RED_SIZE,
-        8,
+        color_size,
         glx_sys::GREEN_SIZE,
-        8,
+        color_size,
         glx_sys::BLUE_SIZE,
-        8,
+        color_size,
         glx_sys::ALPHA_SIZE,
-        8,
+        alpha_size,
         0,
     ])
     .unwrap();
@@ -1027,19 +2500,30 @@ unsafe fn glx_export_dmabuf(
     if num <= 0 || fb_configs.is_null() {
         return Err(anyhow!("no available framebuffer config"));
     }
+    // pixmap must match depth of the config
+    let mut depth = 0;
+    glx.GetFBConfigAttrib(dpy as _, *fb_configs, glx_sys::BUFFER_SIZE as _, &mut depth);
+    if pixmap_format(fb_format, depth as _, 32).is_none() {
+        (x11.XFree)(fb_configs as _);
+        return Err(anyhow!(
+            "unhandled pixmap depth {} for {:?}",
+            depth,
+            fb_format
+        ));
+    }
 
     let attrib_list = SSlice::<_>::from_slice(&[
         glx_sys::TEXTURE_TARGET_EXT,
         glx_sys::TEXTURE_2D_EXT,
         glx_sys::TEXTURE_FORMAT_EXT,
-        glx_sys::TEXTURE_FORMAT_RGBA_EXT,
+        texture_format,
         glx_sys::MIPMAP_TEXTURE_EXT,
         0,
         0,
     ])
     .unwrap();
 
-    let x_pixmap = (x11.XCreatePixmap)(dpy as _, root, width, height, 32);
+    let x_pixmap = (x11.XCreatePixmap)(dpy as _, root, width, height, depth as _);
     let glx_pixmap = glx.CreatePixmap(dpy as _, *fb_configs, x_pixmap, attrib_list.as_ptr() as _);
     (x11.XFree)(fb_configs as _);
 
@@ -1066,27 +2550,63 @@ unsafe fn glx_export_dmabuf(
         let strides = slice::from_raw_parts(strides, reply.nfd as _);
         let offsets = slice::from_raw_parts(offsets, reply.nfd as _);
         let modifier = reply.modifier;
+        let (pixmap_depth, bpp) = (reply.depth, reply.bpp);
 
-        let image = TextureImage::Pixmap {
-            glx_pixmap: GlHandle::from_raw(glx_pixmap as _),
-            x_pixmap: GlHandle::from_raw(x_pixmap as _),
-        };
-
-        let planes = fds
+        let planes: Vec<_> = fds
             .iter()
             .enumerate()
             .map(|(i, &fd)| client::BufferPlaneInfo {
                 fd: fd as _,
                 offset: offsets[i],
-                size: height * strides[i],
+                // auxiliary planes, e.g. CCS of Intel, are not sized by rows
+                // of pixels, they span the rest of the buffer
+                size: match i {
+                    0 => height * strides[i],
+                    _ => (libc::lseek(fd, 0, libc::SEEK_END).max(0) as u32)
+                        .saturating_sub(offsets[i]),
+                },
                 stride: strides[i],
             })
             .collect();
 
         libc::free(reply as *mut _ as _);
 
-        // XXX: why it's BGR instead of RGB?
-        return Ok((client::Format::BGRA, modifier, image, planes));
+        let close_planes = || {
+            for plane in &planes {
+                libc::close(plane.fd as _);
+            }
+        };
+        let format = match pixmap_format(fb_format, pixmap_depth, bpp) {
+            Some(v) => v,
+            None => {
+                close_planes();
+                break 'outer anyhow!(
+                    "unhandled pixmap depth {} bpp {} for {:?}",
+                    pixmap_depth,
+                    bpp,
+                    fb_format
+                );
+            }
+        };
+        // aux planes of compressed modifiers missing from the reply would
+        // leave consumers with garbage
+        if let Some(count) = client::modifier_plane_count(modifier) {
+            if count != planes.len() {
+                close_planes();
+                break 'outer anyhow!(
+                    "modifier {:#x} has {} planes, pixmap {}",
+                    modifier,
+                    count,
+                    planes.len()
+                );
+            }
+        }
+
+        let image = TextureImage::Pixmap {
+            glx_pixmap: GlHandle::from_raw(glx_pixmap as _),
+            x_pixmap: GlHandle::from_raw(x_pixmap as _),
+        };
+        return Ok((format, modifier, image, planes));
     };
 
     glx.DestroyPixmap(dpy as _, glx_pixmap);
@@ -1095,6 +2615,66 @@ unsafe fn glx_export_dmabuf(
     Err(err)
 }
 
+/// Format of DRI3 buffers of pixmaps of `depth` and `bpp` bound as
+/// `fb_format`, X pixmaps are ARGB in native byte order, i.e. BGRA in memory
+fn pixmap_format(fb_format: FramebufferFormat, depth: u8, bpp: u8) -> Option<client::Format> {
+    let format = match (fb_format, depth, bpp) {
+        (FramebufferFormat::Rgba8, 24, 32) => client::Format::BGRx,
+        (FramebufferFormat::Rgba8, 32, 32) => client::Format::BGRA,
+        (FramebufferFormat::Rgb10A2, 30, 32) => client::Format::xRGB_210LE,
+        (FramebufferFormat::Rgb10A2, 32, 32) => client::Format::ARGB_210LE,
+        _ => return None,
+    };
+    Some(format)
+}
+
+/// Shared memory for X servers without DRI3, e.g. some Xwayland setups, frames
+/// are read back with glReadPixels
+unsafe fn export_memfd(
+    width: u32,
+    height: u32,
+) -> Result<(client::Format, TextureImage, Vec<BufferPlaneInfo>)> {
+    let stride = width * 4;
+    let size = stride * height;
+    let fd = libc::memfd_create(b"pw-capture-gl\0".as_ptr() as _, libc::MFD_CLOEXEC);
+    if fd < 0 {
+        return Err(anyhow!(
+            "failed to create memfd: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    if libc::ftruncate(fd, size as _) < 0 {
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        return Err(anyhow!("failed to resize memfd: {err}"));
+    }
+    let map = libc::mmap(
+        ptr::null_mut(),
+        size as _,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        fd,
+        0,
+    );
+    if map == libc::MAP_FAILED {
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        return Err(anyhow!("failed to map memfd: {err}"));
+    }
+
+    let image = TextureImage::MemFd {
+        map: GlHandle::from_ptr(map),
+        size: size as _,
+    };
+    let planes = vec![client::BufferPlaneInfo {
+        fd: fd as _,
+        offset: 0,
+        size,
+        stride,
+    }];
+    Ok((client::Format::BGRx, image, planes))
+}
+
 #[named]
 unsafe fn create_target_textures(
     native: NativeIface,
@@ -1102,16 +2682,23 @@ unsafe fn create_target_textures(
     width: u32,
     height: u32,
     num: u32,
-) -> Result<(client::Format, u64, usize, VecDeque<ExportTexture>)> {
+    fb_format: FramebufferFormat,
+    memfd: bool,
+) -> Result<(client::Format, Option<u64>, usize, VecDeque<ExportTexture>)> {
     let gl = gl(native);
     let mut textures: Vec<u32> = vec![0; num as usize];
 
-    let mut prev_texture: i32 = 0;
-    gl.GetIntegerv(gl_sys::TEXTURE_BINDING_2D, &mut prev_texture);
+    let gles2 = gl_is_gles2(gl);
+    let state = GlStateGuard::new(gl, gles2);
+    // TexImage2D() would source texels from a bound unpack buffer, which
+    // GLES2 lacks
+    if !gles2 {
+        gl.BindBuffer(gl_sys::PIXEL_UNPACK_BUFFER, 0);
+    }
 
     gl.GenTextures(num as _, textures.as_mut_ptr());
 
-    let mut export_format: Option<(client::Format, u64, usize)> = None;
+    let mut export_format: Option<(client::Format, Option<u64>, usize)> = None;
     let res = textures
         .iter()
         .map(|&texture| -> Result<_> {
@@ -1127,9 +2714,15 @@ unsafe fn create_target_textures(
                 gl_sys::NEAREST as _,
             );
 
-            let (client_format, modifier, image, planes) = match native {
-                NativeIface::Egl => egl_export_dmabuf(dpy, width, height, texture)?,
-                NativeIface::Glx => glx_export_dmabuf(dpy, width, height, texture)?,
+            let (client_format, modifier, image, planes) = if memfd {
+                let (format, image, planes) = export_memfd(width, height)?;
+                (format, None, image, planes)
+            } else {
+                let (format, modifier, image, planes) = match native {
+                    NativeIface::Egl => egl_export_dmabuf(dpy, width, height, texture, fb_format)?,
+                    NativeIface::Glx => glx_export_dmabuf(dpy, width, height, texture, fb_format)?,
+                };
+                (format, Some(modifier), image, planes)
             };
             if let Some(format) = export_format {
                 assert_eq!(client_format, format.0);
@@ -1147,20 +2740,19 @@ unsafe fn create_target_textures(
                 image,
             };
             debug!(
-                "{:?} format:{:?} modifier:{:#x}",
+                "{:?} format:{:?} modifier:{:?}",
                 res, client_format, modifier
             );
             Ok(res)
         })
         .collect::<Result<VecDeque<_>>>();
+    drop(state);
 
     let res = res.map_err(|e| {
         gl.DeleteTextures(num as _, textures.as_mut_ptr());
         e
     })?;
 
-    gl.BindTexture(gl_sys::TEXTURE_2D, prev_texture as _);
-
     let (client_format, modifier, num_planes) =
         export_format.ok_or(anyhow!("no image exported"))?;
 
@@ -1187,7 +2779,7 @@ fn on_add_buffer(surface: GlHandle) -> Result<client::BufferInfo> {
     let texture = export_texture.texture;
 
     let res = client::BufferInfo {
-        is_dma_buf: true,
+        is_dma_buf: !matches!(export_texture.image, TextureImage::MemFd { .. }),
         planes: export_texture.planes.clone(),
         user_handle: client::BufferUserHandle::Texture(texture),
     };
@@ -1216,6 +2808,7 @@ fn on_remove_buffer(surface: GlHandle, user_handle: client::BufferUserHandle) ->
         .mapped_textures
         .remove(&texture)
         .ok_or(anyhow!("texture already unmapped"))?;
+    ly_capture.damage.lock().unwrap().forget(texture);
 
     ly_capture
         .free_textures
@@ -1257,12 +2850,41 @@ fn on_process_buffer(
                 );
 
                 if let Some(info) = snap.as_cursor_info(old_serial != snap.serial()) {
-                    add_cursor(info)
+                    add_cursor(info.scale_position(
+                        (ly_capture.width, ly_capture.height),
+                        ly_capture.export_region,
+                    ))
                 }
             }
         }
     }
 
+    if let Some(add_crop) = add_meta_cbs.add_crop {
+        // letterboxed frames are shown without the bars
+        let region = ly_capture.export_region;
+        if (region.width, region.height) != (ly_capture.export_width, ly_capture.export_height) {
+            add_crop(region);
+        }
+    }
+
+    if let Some(add_damage) = add_meta_cbs.add_damage {
+        let damage = ly_capture.damage.lock().unwrap().stream_damage(texture);
+        let region = match damage {
+            Some(v) => scale_damage(
+                v,
+                (ly_capture.width, ly_capture.height),
+                ly_capture.export_region,
+            ),
+            None => client::Region {
+                x: 0,
+                y: 0,
+                width: ly_capture.export_width,
+                height: ly_capture.export_height,
+            },
+        };
+        add_damage(&[region]);
+    }
+
     if let Some((_, sync)) = ly_capture.sync_objects.remove(&texture) {
         drop(ly_surface);
         unsafe { sync.wait() };
@@ -1277,43 +2899,60 @@ fn on_process_buffer(
 fn create_stream(
     surface: GlHandle,
     format: client::Format,
-    modifier: u64,
+    modifier: Option<u64>,
     num_planes: u32,
     max_buffers: u32,
     width: u32,
     height: u32,
+    colorimetry: client::Colorimetry,
+    scale: client::Scale,
+    props: Vec<(String, String)>,
+    streaming: Arc<AtomicBool>,
 ) -> Result<client::Stream> {
     let stream_info = client::StreamInfo {
         width,
         height,
         enum_formats: vec![client::EnumFormatInfo {
             formats: vec![format],
-            modifiers: vec![modifier],
+            modifiers: modifier.into_iter().collect(),
         }],
+        colorimetry,
+        // set once the window reports its buffer transform
+        transform: client::Transform::Normal,
+        scale,
         max_buffers,
+        format_preference: client::FormatPreference::global(),
+        node_class: client::NodeClass::global(),
+        props,
         fixate_format: Box::new(move |enum_format| {
             info!("fixate format: {:?}", enum_format);
             let fixate_format = *enum_format.formats.first()?;
-            let fixate_modifier = *enum_format.modifiers.first()?;
+            let fixate_modifier = enum_format.modifiers.first().copied();
             if fixate_format != format || fixate_modifier != modifier {
                 return None;
             }
             Some(client::FixateFormat {
-                modifier: Some(modifier),
+                modifier,
                 num_planes,
+                mem_fd_fallback: false,
             })
         }),
-        add_buffer: Box::new(move || on_add_buffer(surface).ok()),
+        // textures are exported ahead in a single data type
+        add_buffer: Box::new(move |_| on_add_buffer(surface)),
         remove_buffer: Box::new(move |user_handle| {
             let _ = on_remove_buffer(surface, user_handle);
         }),
         process_buffer: Box::new(move |user_handle, add_meta_cbs| {
             let _ = on_process_buffer(surface, user_handle, add_meta_cbs);
         }),
+        state_changed: Box::new(move |state, node_id| {
+            info!("{:?} stream node {:?}: {:?}", surface, node_id, state);
+            streaming.store(
+                state == client::StreamState::Streaming,
+                atomic::Ordering::Release,
+            );
+        }),
     };
-    CLIENT
-        .as_ref()
-        .ok_or(anyhow!("failed to get client"))?
-        .proxy()
-        .try_create_stream(stream_info)??
+    let client = CLIENT.as_ref().ok_or(anyhow!("failed to get client"))?;
+    Ok(client.create_stream(stream_info)?)
 }
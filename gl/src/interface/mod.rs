@@ -1,12 +1,20 @@
+mod glx_drawable;
 mod implementation;
+mod shader_copy;
 mod state;
+mod state_guard;
 mod types;
 mod wl_impl;
+mod wl_varargs;
 
+use glx_drawable::*;
 use implementation::*;
+use shader_copy::*;
 use state::*;
+use state_guard::*;
 use types::*;
 use wl_impl::*;
+use wl_varargs::*;
 
 use crate::elfhack::*;
 
@@ -36,6 +44,7 @@ const _: DlvsymFunc = dlvsym;
 pub use wl_impl::me_eh5_pw_capture_get_wl_cursor_manager;
 pub use wl_impl::me_eh5_pw_capture_release_wl_cursor_manager;
 pub use wl_impl::me_eh5_pw_capture_wl_cursor_snapshot;
+pub use wl_impl::me_eh5_pw_capture_wl_window_info;
 
 #[no_mangle]
 pub unsafe extern "C" fn wl_proxy_marshal_array_flags(
@@ -49,7 +58,10 @@ pub unsafe extern "C" fn wl_proxy_marshal_array_flags(
     impl_wl_proxy_marshal_array_flags(proxy, opcode, interface, version, flags, args)
 }
 
-#[cfg(feature = "nightly")]
+#[cfg(all(
+    feature = "nightly",
+    not(any(target_arch = "x86_64", target_arch = "aarch64"))
+))]
 pub use wl_impl::wl_proxy_marshal_flags;
 
 #[no_mangle]
@@ -115,16 +127,99 @@ pub unsafe extern "C" fn glXSwapBuffersMscOML(
     impl_glXSwapBuffersMscOML(dpy, drawable, target_msc, divisor, remainder)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn glXSwapIntervalEXT(
+    dpy: *mut glx_t::Display,
+    drawable: glx_t::GLXDrawable,
+    interval: i32,
+) {
+    impl_glXSwapIntervalEXT(dpy, drawable, interval)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn glXSwapIntervalSGI(interval: i32) -> i32 {
+    impl_glXSwapIntervalSGI(interval)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn glXSwapIntervalMESA(interval: u32) -> i32 {
+    impl_glXSwapIntervalMESA(interval)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn glXCreateWindow(
+    dpy: *mut glx_t::Display,
+    config: glx_t::GLXFBConfig,
+    win: glx_t::Window,
+    attrib_list: *const i32,
+) -> glx_t::GLXWindow {
+    impl_glXCreateWindow(dpy, config, win, attrib_list)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn glXDestroyWindow(dpy: *mut glx_t::Display, win: glx_t::GLXWindow) {
     impl_glXDestroyWindow(dpy, win)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn glXCreatePbuffer(
+    dpy: *mut glx_t::Display,
+    config: glx_t::GLXFBConfig,
+    attrib_list: *const i32,
+) -> glx_t::GLXPbuffer {
+    impl_glXCreatePbuffer(dpy, config, attrib_list)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn glXDestroyPbuffer(dpy: *mut glx_t::Display, pbuf: glx_t::GLXPbuffer) {
+    impl_glXDestroyPbuffer(dpy, pbuf)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn glXCreateContext(
+    dpy: *mut glx_t::Display,
+    vis: *mut glx_t::XVisualInfo,
+    share_list: glx_t::GLXContext,
+    direct: glx_t::Bool,
+) -> glx_t::GLXContext {
+    impl_glXCreateContext(dpy, vis, share_list, direct)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn glXCreateNewContext(
+    dpy: *mut glx_t::Display,
+    config: glx_t::GLXFBConfig,
+    render_type: i32,
+    share_list: glx_t::GLXContext,
+    direct: glx_t::Bool,
+) -> glx_t::GLXContext {
+    impl_glXCreateNewContext(dpy, config, render_type, share_list, direct)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn glXDestroyContext(dpy: *mut glx_t::Display, ctx: glx_t::GLXContext) {
     impl_glXDestroyContext(dpy, ctx)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn glXMakeCurrent(
+    dpy: *mut glx_t::Display,
+    drawable: glx_t::GLXDrawable,
+    ctx: glx_t::GLXContext,
+) -> glx_t::Bool {
+    impl_glXMakeCurrent(dpy, drawable, ctx)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn glXMakeContextCurrent(
+    dpy: *mut glx_t::Display,
+    draw: glx_t::GLXDrawable,
+    read: glx_t::GLXDrawable,
+    ctx: glx_t::GLXContext,
+) -> glx_t::Bool {
+    impl_glXMakeContextCurrent(dpy, draw, read, ctx)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn eglGetProcAddress(proc_name: *const c_char) -> *mut c_void {
     impl_eglGetProcAddress(proc_name)
@@ -185,6 +280,15 @@ pub unsafe extern "C" fn eglCreatePlatformWindowSurfaceEXT(
     impl_eglCreatePlatformWindowSurfaceEXT(dpy, config, native_window, attrib_list)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn eglCreatePbufferSurface(
+    dpy: egl_t::EGLDisplay,
+    config: egl_t::EGLConfig,
+    attrib_list: *const i32,
+) -> egl_t::EGLSurface {
+    impl_eglCreatePbufferSurface(dpy, config, attrib_list)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn eglSwapBuffers(
     dpy: egl_t::EGLDisplay,
@@ -213,6 +317,24 @@ pub unsafe extern "C" fn eglSwapBuffersWithDamageKHR(
     impl_eglSwapBuffersWithDamageKHR(dpy, surface, rects, n_rects)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn eglSetDamageRegionKHR(
+    dpy: egl_t::EGLDisplay,
+    surface: egl_t::EGLSurface,
+    rects: *mut egl_t::EGLint,
+    n_rects: egl_t::EGLint,
+) -> egl_t::EGLBoolean {
+    impl_eglSetDamageRegionKHR(dpy, surface, rects, n_rects)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn eglSwapInterval(
+    dpy: egl_t::EGLDisplay,
+    interval: egl_t::EGLint,
+) -> egl_t::EGLBoolean {
+    impl_eglSwapInterval(dpy, interval)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn eglDestroySurface(
     dpy: egl_t::EGLDisplay,
@@ -221,6 +343,16 @@ pub unsafe extern "C" fn eglDestroySurface(
     impl_eglDestroySurface(dpy, surface)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn eglCreateContext(
+    dpy: egl_t::EGLDisplay,
+    config: egl_t::EGLConfig,
+    share_context: egl_t::EGLContext,
+    attrib_list: *const i32,
+) -> egl_t::EGLContext {
+    impl_eglCreateContext(dpy, config, share_context, attrib_list)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn eglDestroyContext(
     dpy: egl_t::EGLDisplay,
@@ -229,6 +361,16 @@ pub unsafe extern "C" fn eglDestroyContext(
     impl_eglDestroyContext(dpy, ctx)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn eglMakeCurrent(
+    dpy: egl_t::EGLDisplay,
+    draw: egl_t::EGLSurface,
+    read: egl_t::EGLSurface,
+    ctx: egl_t::EGLContext,
+) -> egl_t::EGLBoolean {
+    impl_eglMakeCurrent(dpy, draw, read, ctx)
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn eglTerminate(dpy: egl_t::EGLDisplay) -> egl_t::EGLBoolean {
     impl_eglTerminate(dpy)
@@ -0,0 +1,323 @@
+use super::*;
+
+use crate::utils::*;
+
+use core::ffi::CStr;
+use core::ptr;
+
+use anyhow::{anyhow, Result};
+use function_name::named;
+use libc::c_void;
+use pw_capture_gl_sys::prelude::*;
+
+const VERTEX_SHADER: &[u8] = b"#version 100
+attribute vec2 a_position;
+varying vec2 v_texcoord;
+void main() {
+    // flip vertically as BlitFramebuffer does
+    v_texcoord = vec2(a_position.x, -a_position.y) * 0.5 + 0.5;
+    gl_Position = vec4(a_position, 0.0, 1.0);
+}
+\0";
+
+const FRAGMENT_SHADER: &[u8] = b"#version 100
+precision mediump float;
+uniform sampler2D u_texture;
+varying vec2 v_texcoord;
+void main() {
+    gl_FragColor = texture2D(u_texture, v_texcoord);
+}
+\0";
+
+const QUAD_VERTICES: [f32; 8] = [-1.0, -1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0];
+
+const SAVED_CAPS: [gl_t::GLenum; 5] = [
+    gl_sys::BLEND,
+    gl_sys::CULL_FACE,
+    gl_sys::DEPTH_TEST,
+    gl_sys::SCISSOR_TEST,
+    gl_sys::STENCIL_TEST,
+];
+
+/// Whether current context is OpenGL ES of any version
+pub unsafe fn gl_is_gles(gl: &Gl) -> bool {
+    let version = gl.GetString(gl_sys::VERSION);
+    !version.is_null()
+        && CStr::from_ptr(version as _)
+            .to_bytes()
+            .starts_with(b"OpenGL ES")
+}
+
+/// Whether current context is OpenGL ES 2, e.g. lacks BlitFramebuffer
+pub unsafe fn gl_is_gles2(gl: &Gl) -> bool {
+    let version = gl.GetString(gl_sys::VERSION);
+    if version.is_null() {
+        return false;
+    }
+    let version = CStr::from_ptr(version as _).to_bytes();
+    match version.strip_prefix(b"OpenGL ES ") {
+        Some(v) => matches!(v.first(), Some(&major) if major < b'3'),
+        None => false,
+    }
+}
+
+unsafe fn compile_shader(gl: &Gl, type_: gl_t::GLenum, source: &[u8]) -> Result<u32> {
+    let shader = gl.CreateShader(type_);
+    let sources = [source.as_ptr() as *const gl_t::GLchar];
+    gl.ShaderSource(shader, 1, sources.as_ptr(), ptr::null());
+    gl.CompileShader(shader);
+
+    let mut status: i32 = 0;
+    gl.GetShaderiv(shader, gl_sys::COMPILE_STATUS, &mut status);
+    if status == 0 {
+        let mut log = [0u8; 512];
+        gl.GetShaderInfoLog(
+            shader,
+            log.len() as _,
+            ptr::null_mut(),
+            log.as_mut_ptr() as _,
+        );
+        gl.DeleteShader(shader);
+        let log = CStr::from_ptr(log.as_ptr() as _);
+        return Err(anyhow!("failed to compile shader: {:?}", log));
+    }
+    Ok(shader)
+}
+
+/// Copies back buffer with a textured fullscreen quad, for contexts without
+/// BlitFramebuffer, i.e. GLES2
+pub struct ShaderCopy {
+    native: NativeIface,
+    /// Share group of the context the program and texture got created with
+    share_group: GlHandle,
+    program: u32,
+    u_texture: i32,
+    src_texture: u32,
+    width: u32,
+    height: u32,
+}
+
+impl ShaderCopy {
+    #[named]
+    pub unsafe fn new(
+        native: NativeIface,
+        share_group: GlHandle,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let gl = gl(native);
+
+        let vs = compile_shader(gl, gl_sys::VERTEX_SHADER, VERTEX_SHADER)?;
+        let fs = match compile_shader(gl, gl_sys::FRAGMENT_SHADER, FRAGMENT_SHADER) {
+            Ok(v) => v,
+            Err(e) => {
+                gl.DeleteShader(vs);
+                return Err(e);
+            }
+        };
+
+        let program = gl.CreateProgram();
+        gl.AttachShader(program, vs);
+        gl.AttachShader(program, fs);
+        gl.BindAttribLocation(program, 0, cstr!(b"a_position\0").as_ptr());
+        gl.LinkProgram(program);
+        gl.DetachShader(program, vs);
+        gl.DetachShader(program, fs);
+        gl.DeleteShader(vs);
+        gl.DeleteShader(fs);
+
+        let mut status: i32 = 0;
+        gl.GetProgramiv(program, gl_sys::LINK_STATUS, &mut status);
+        if status == 0 {
+            gl.DeleteProgram(program);
+            return Err(anyhow!("failed to link program"));
+        }
+        let u_texture = gl.GetUniformLocation(program, cstr!(b"u_texture\0").as_ptr());
+
+        let mut prev_fbo: i32 = 0;
+        let mut prev_texture: i32 = 0;
+        gl.GetIntegerv(gl_sys::FRAMEBUFFER_BINDING, &mut prev_fbo);
+        gl.GetIntegerv(gl_sys::TEXTURE_BINDING_2D, &mut prev_texture);
+
+        // CopyTexSubImage2D requires texture components to be a subset of
+        // the ones of the back buffer
+        let mut alpha_bits: i32 = 0;
+        gl.BindFramebuffer(gl_sys::FRAMEBUFFER, 0);
+        gl.GetIntegerv(gl_sys::ALPHA_BITS, &mut alpha_bits);
+        let format = if alpha_bits > 0 {
+            gl_sys::RGBA
+        } else {
+            gl_sys::RGB
+        };
+        debug!("back buffer alpha bits: {}", alpha_bits);
+
+        let mut src_texture: u32 = 0;
+        gl.GenTextures(1, &mut src_texture);
+        gl.BindTexture(gl_sys::TEXTURE_2D, src_texture);
+        for (pname, param) in [
+            (gl_sys::TEXTURE_MIN_FILTER, gl_sys::NEAREST),
+            (gl_sys::TEXTURE_MAG_FILTER, gl_sys::NEAREST),
+            (gl_sys::TEXTURE_WRAP_S, gl_sys::CLAMP_TO_EDGE),
+            (gl_sys::TEXTURE_WRAP_T, gl_sys::CLAMP_TO_EDGE),
+        ] {
+            gl.TexParameteri(gl_sys::TEXTURE_2D, pname, param as _);
+        }
+        gl.TexImage2D(
+            gl_sys::TEXTURE_2D,
+            0,
+            format as _,
+            width as _,
+            height as _,
+            0,
+            format,
+            gl_sys::UNSIGNED_BYTE,
+            ptr::null(),
+        );
+
+        gl.BindTexture(gl_sys::TEXTURE_2D, prev_texture as _);
+        gl.BindFramebuffer(gl_sys::FRAMEBUFFER, prev_fbo as _);
+
+        Ok(Self {
+            native,
+            share_group,
+            program,
+            u_texture,
+            src_texture,
+            width,
+            height,
+        })
+    }
+
+    /// Copies back buffer into `texture`, framebuffer and texture bindings,
+    /// viewport and color mask are restored by the [`GlStateGuard`] of the
+    /// caller, which leaves `TEXTURE0` active
+    pub unsafe fn copy(&self, texture: u32) {
+        let gl = gl(self.native);
+
+        let mut prev_program: i32 = 0;
+        let mut prev_array_buffer: i32 = 0;
+        gl.GetIntegerv(gl_sys::CURRENT_PROGRAM, &mut prev_program);
+        gl.GetIntegerv(gl_sys::ARRAY_BUFFER_BINDING, &mut prev_array_buffer);
+        let prev_caps = SAVED_CAPS.map(|cap| gl.IsEnabled(cap));
+
+        let mut prev_attrib_enabled: i32 = 0;
+        let mut prev_attrib_size: i32 = 0;
+        let mut prev_attrib_type: i32 = 0;
+        let mut prev_attrib_normalized: i32 = 0;
+        let mut prev_attrib_stride: i32 = 0;
+        let mut prev_attrib_buffer: i32 = 0;
+        let mut prev_attrib_pointer: *mut c_void = ptr::null_mut();
+        gl.GetVertexAttribiv(
+            0,
+            gl_sys::VERTEX_ATTRIB_ARRAY_ENABLED,
+            &mut prev_attrib_enabled,
+        );
+        gl.GetVertexAttribiv(0, gl_sys::VERTEX_ATTRIB_ARRAY_SIZE, &mut prev_attrib_size);
+        gl.GetVertexAttribiv(0, gl_sys::VERTEX_ATTRIB_ARRAY_TYPE, &mut prev_attrib_type);
+        gl.GetVertexAttribiv(
+            0,
+            gl_sys::VERTEX_ATTRIB_ARRAY_NORMALIZED,
+            &mut prev_attrib_normalized,
+        );
+        gl.GetVertexAttribiv(
+            0,
+            gl_sys::VERTEX_ATTRIB_ARRAY_STRIDE,
+            &mut prev_attrib_stride,
+        );
+        gl.GetVertexAttribiv(
+            0,
+            gl_sys::VERTEX_ATTRIB_ARRAY_BUFFER_BINDING,
+            &mut prev_attrib_buffer,
+        );
+        gl.GetVertexAttribPointerv(
+            0,
+            gl_sys::VERTEX_ATTRIB_ARRAY_POINTER,
+            &mut prev_attrib_pointer,
+        );
+
+        {
+            gl.BindFramebuffer(gl_sys::FRAMEBUFFER, 0);
+            gl.BindTexture(gl_sys::TEXTURE_2D, self.src_texture);
+            gl.CopyTexSubImage2D(
+                gl_sys::TEXTURE_2D,
+                0,
+                0,
+                0,
+                0,
+                0,
+                self.width as _,
+                self.height as _,
+            );
+
+            let mut fbo: u32 = 0;
+            gl.GenFramebuffers(1, &mut fbo);
+            gl.BindFramebuffer(gl_sys::FRAMEBUFFER, fbo);
+            gl.FramebufferTexture2D(
+                gl_sys::FRAMEBUFFER,
+                gl_sys::COLOR_ATTACHMENT0,
+                gl_sys::TEXTURE_2D,
+                texture,
+                0,
+            );
+
+            for cap in SAVED_CAPS {
+                gl.Disable(cap);
+            }
+            gl.ColorMask(1, 1, 1, 1);
+            gl.Viewport(0, 0, self.width as _, self.height as _);
+
+            gl.UseProgram(self.program);
+            gl.Uniform1i(self.u_texture, 0);
+            gl.BindBuffer(gl_sys::ARRAY_BUFFER, 0);
+            gl.VertexAttribPointer(
+                0,
+                2,
+                gl_sys::FLOAT,
+                gl_sys::FALSE,
+                0,
+                QUAD_VERTICES.as_ptr() as _,
+            );
+            gl.EnableVertexAttribArray(0);
+            gl.DrawArrays(gl_sys::TRIANGLE_STRIP, 0, 4);
+
+            gl.DeleteFramebuffers(1, &fbo);
+        }
+        gl.BindBuffer(gl_sys::ARRAY_BUFFER, prev_attrib_buffer as _);
+        gl.VertexAttribPointer(
+            0,
+            prev_attrib_size,
+            prev_attrib_type as _,
+            prev_attrib_normalized as _,
+            prev_attrib_stride,
+            prev_attrib_pointer,
+        );
+        if prev_attrib_enabled == 0 {
+            gl.DisableVertexAttribArray(0);
+        }
+        gl.BindBuffer(gl_sys::ARRAY_BUFFER, prev_array_buffer as _);
+        gl.UseProgram(prev_program as _);
+        for (cap, enabled) in SAVED_CAPS.into_iter().zip(prev_caps) {
+            if enabled != 0 {
+                gl.Enable(cap);
+            }
+        }
+    }
+}
+
+impl Drop for ShaderCopy {
+    #[named]
+    fn drop(&mut self) {
+        unsafe {
+            // deleting through a context of another share group would delete
+            // its objects of the same names, objects are freed along with the
+            // last context of their share group anyway
+            if !is_share_group_current(self.native, self.share_group) {
+                debug!("share group not current, leaving program and texture");
+                return;
+            }
+            let gl = gl(self.native);
+            gl.DeleteProgram(self.program);
+            gl.DeleteTextures(1, &self.src_texture);
+        }
+    }
+}
@@ -7,13 +7,14 @@ use core::ffi::CStr;
 use core::mem;
 use core::ptr;
 use std::ffi::CString;
+use std::os::unix::io::RawFd;
 
-use dashmap::DashMap;
 use libc::RTLD_NEXT;
 use libc::{c_char, c_void};
 use once_cell::sync::Lazy;
 use pw_capture_client as client;
 use pw_capture_cursor::WlIntercept;
+use pw_capture_registry::{report_leaks, HandleTable};
 
 pub static GLOBAL_INIT: Lazy<()> = Lazy::new(init_logger);
 
@@ -96,15 +97,70 @@ pub static WL_INTERCEPT: Lazy<Option<WlIntercept>> = Lazy::new(|| unsafe {
         .ok()
 });
 
+/// `wl_proxy_*` functions of objects loaded before the layer get hooked once
+/// an EGL display on Wayland is created
+pub static WL_GOT_HOOKED: Lazy<usize> = Lazy::new(|| unsafe { hook_loaded_wl_proxy() });
+
 pub static CLIENT: Lazy<Option<client::Client>> = Lazy::new(|| {
     Lazy::force(&GLOBAL_INIT);
-    client::Client::new()
+    let client = client::Client::new()
         .map_err(|e| error!(target:"client init", "failed to create client: {e:?}"))
-        .ok()
+        .ok();
+    if client.is_some() {
+        unsafe { libc::atexit(shutdown_at_exit) };
+    }
+    client
+});
+
+/// Terminates streams and the client thread on exit or when the layer gets
+/// unloaded, streams of surfaces never destroyed would otherwise be torn
+/// down racing with the rest of the process
+extern "C" fn shutdown_at_exit() {
+    report_leaks(&[&DISPLAY_MAP, &SURFACE_MAP]);
+    let streams: Vec<_> = SURFACE_MAP
+        .iter()
+        .filter_map(|ly_surface| {
+            let stream = &ly_surface.capture.as_ref()?.stream;
+            Some((stream.proxy(), stream.worker()))
+        })
+        .collect();
+    for (stream, worker) in streams {
+        worker.flush();
+        let _ = stream.drain_and_terminate();
+    }
+    if let Some(client) = CLIENT.as_ref() {
+        client.shutdown();
+    }
+}
+
+/// Set by `PW_CAPTURE_OFFSCREEN=1`, pbuffers are captured on glFlush() and
+/// glFinish() for apps rendering without a window
+pub static OFFSCREEN_CAPTURE: Lazy<bool> = Lazy::new(|| {
+    let enabled = matches!(
+        client::setting("PW_CAPTURE_OFFSCREEN").as_deref(),
+        Some("1")
+    );
+    log::debug!("offscreen capture: {}", enabled);
+    enabled
 });
 
-pub static DISPLAY_MAP: Lazy<DashMap<GlHandle, LayerDisplay>> = Lazy::new(DashMap::new);
-pub static SURFACE_MAP: Lazy<DashMap<GlHandle, LayerSurface>> = Lazy::new(DashMap::new);
+pub static DISPLAY_MAP: HandleTable<GlHandle, LayerDisplay> =
+    HandleTable::with_on_remove("display", on_display_removed);
+/// Contexts created sharing objects with another one, mapped to the first
+/// context of their share group
+pub static SHARE_GROUP_MAP: HandleTable<GlHandle, GlHandle> = HandleTable::new("share group");
+pub static SURFACE_MAP: HandleTable<GlHandle, LayerSurface> = HandleTable::new("surface");
+/// GLXWindows mapped to X windows they were created for
+pub static GLX_WINDOW_MAP: HandleTable<GlHandle, u32> = HandleTable::new("GLX window");
+
+/// Terminating a display implicitly destroys its surfaces, which is legal
+/// but leaves their captures to exit
+fn on_display_removed(display: &GlHandle, _: &LayerDisplay) {
+    let surfaces = SURFACE_MAP.iter().filter(|v| v.display == *display).count();
+    if surfaces > 0 {
+        log::debug!("display terminated with {surfaces} surface(s) alive");
+    }
+}
 
 #[inline]
 pub fn glx() -> &'static Glx {
@@ -134,21 +190,27 @@ impl FenceSync {
                 sync: glhandle!(sync),
             }
         } else if let NativeIface::Egl = native {
-            let egl = egl();
-            if egl.CreateSync.is_loaded() {
-                let sync = egl.CreateSync(dpy, egl_sys::SYNC_FENCE, ptr::null());
-                Self::Egl {
-                    dpy: glhandle!(dpy),
-                    sync: glhandle!(sync),
-                }
-            } else if egl.CreateSyncKHR.is_loaded() {
-                let sync = egl.CreateSyncKHR(dpy, egl_sys::SYNC_FENCE, ptr::null());
-                Self::EglKhr {
-                    dpy: glhandle!(dpy),
-                    sync: glhandle!(sync),
-                }
-            } else {
-                return None;
+            return Self::new_egl(dpy);
+        } else {
+            return None;
+        };
+        Some(sync)
+    }
+
+    /// EGL fence sync, usable without GL sync objects
+    pub unsafe fn new_egl(dpy: *const c_void) -> Option<Self> {
+        let egl = egl();
+        let sync = if egl.CreateSync.is_loaded() {
+            let sync = egl.CreateSync(dpy, egl_sys::SYNC_FENCE, ptr::null());
+            Self::Egl {
+                dpy: glhandle!(dpy),
+                sync: glhandle!(sync),
+            }
+        } else if egl.CreateSyncKHR.is_loaded() {
+            let sync = egl.CreateSyncKHR(dpy, egl_sys::SYNC_FENCE, ptr::null());
+            Self::EglKhr {
+                dpy: glhandle!(dpy),
+                sync: glhandle!(sync),
             }
         } else {
             return None;
@@ -170,3 +232,70 @@ impl FenceSync {
         }
     }
 }
+
+/// Whether `dpy` supports EGL extension `name`
+pub unsafe fn egl_has_extension(dpy: *const c_void, name: &str) -> bool {
+    let extensions = egl().QueryString(dpy, egl_sys::EXTENSIONS as _);
+    if extensions.is_null() {
+        return false;
+    }
+    CStr::from_ptr(extensions)
+        .to_string_lossy()
+        .split_ascii_whitespace()
+        .any(|v| v == name)
+}
+
+// EGL_EXT_device_drm_render_node, not covered by generated bindings
+const EGL_DRM_RENDER_NODE_FILE_EXT: i32 = 0x3377;
+
+/// Render and primary node of the device `dpy` renders on, render node first,
+/// requires `EGL_EXT_device_query` and `EGL_EXT_device_drm`
+pub unsafe fn egl_get_drm_devices(dpy: *const c_void) -> Vec<client::DrmDevice> {
+    let egl = egl();
+    if !(egl.QueryDisplayAttribEXT.is_loaded() && egl.QueryDeviceStringEXT.is_loaded()) {
+        return vec![];
+    }
+    let mut device: egl_t::EGLAttrib = 0;
+    if egl.QueryDisplayAttribEXT(dpy, egl_sys::DEVICE_EXT as _, &mut device) != egl_sys::TRUE {
+        return vec![];
+    }
+    // render nodes are unknown to drivers lacking EGL_EXT_device_drm_render_node
+    let mut devices = vec![];
+    for name in [
+        EGL_DRM_RENDER_NODE_FILE_EXT,
+        egl_sys::DRM_DEVICE_FILE_EXT as _,
+    ] {
+        let path = egl.QueryDeviceStringEXT(device as _, name);
+        if path.is_null() {
+            continue;
+        }
+        let path = CStr::from_ptr(path).to_string_lossy();
+        if let Some(v) = client::DrmDevice::parse(&path) {
+            devices.push(v);
+        }
+    }
+    devices
+}
+
+/// Sync file of a native fence signaled once GL commands issued so far
+/// completed, requires `EGL_ANDROID_native_fence_sync`
+pub unsafe fn dup_native_fence_fd(dpy: *const c_void) -> Option<RawFd> {
+    let egl = egl();
+    if !(egl.CreateSyncKHR.is_loaded() && egl.DupNativeFenceFDANDROID.is_loaded()) {
+        return None;
+    }
+    let attribs = [
+        egl_sys::SYNC_NATIVE_FENCE_FD_ANDROID as _,
+        egl_sys::NO_NATIVE_FENCE_FD_ANDROID,
+        egl_sys::NONE as _,
+    ];
+    let sync = egl.CreateSyncKHR(dpy, egl_sys::SYNC_NATIVE_FENCE_ANDROID, attribs.as_ptr());
+    if sync.is_null() {
+        return None;
+    }
+    // the fence gets its sync file once flushed
+    gl(NativeIface::Egl).Flush();
+    let fd = egl.DupNativeFenceFDANDROID(dpy, sync);
+    egl.DestroySyncKHR(dpy, sync);
+    (fd != egl_sys::NO_NATIVE_FENCE_FD_ANDROID).then_some(fd)
+}
@@ -0,0 +1,169 @@
+//! Saving and restoring GL state around captures
+
+use super::*;
+
+use pw_capture_gl_sys::prelude::*;
+
+/// GL state the capture path touches
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct GlState {
+    read_fbo: i32,
+    draw_fbo: i32,
+    /// Read buffer of the default framebuffer
+    read_buffer: Option<i32>,
+    active_texture: i32,
+    /// `TEXTURE_2D` binding of texture unit `TEXTURE0`
+    texture: i32,
+    pack_buffer: Option<i32>,
+    unpack_buffer: Option<i32>,
+    pack_alignment: i32,
+    pack_row_length: Option<i32>,
+    srgb: Option<bool>,
+    scissor_test: bool,
+    scissor_box: [i32; 4],
+    viewport: [i32; 4],
+    clear_color: [f32; 4],
+    color_mask: [u8; 4],
+}
+
+impl GlState {
+    /// Queries current state, OpenGL ES 2 contexts lack separate read and
+    /// draw framebuffers, read buffers, pixel buffers and sRGB control
+    unsafe fn query(gl: &Gl, gles2: bool) -> Self {
+        let get_int = |pname: gl_t::GLenum| {
+            let mut value: i32 = 0;
+            gl.GetIntegerv(pname, &mut value);
+            value
+        };
+
+        let (read_fbo, draw_fbo) = if gles2 {
+            let fbo = get_int(gl_sys::FRAMEBUFFER_BINDING);
+            (fbo, fbo)
+        } else {
+            (
+                get_int(gl_sys::READ_FRAMEBUFFER_BINDING),
+                get_int(gl_sys::DRAW_FRAMEBUFFER_BINDING),
+            )
+        };
+        // read buffer is state of the current surface
+        let read_buffer = if !gles2 && gl.ReadBuffer.is_loaded() {
+            gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, 0);
+            let read_buffer = get_int(gl_sys::READ_BUFFER);
+            gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, read_fbo as _);
+            Some(read_buffer)
+        } else {
+            None
+        };
+
+        let active_texture = get_int(gl_sys::ACTIVE_TEXTURE);
+        gl.ActiveTexture(gl_sys::TEXTURE0);
+        let texture = get_int(gl_sys::TEXTURE_BINDING_2D);
+        gl.ActiveTexture(active_texture as _);
+
+        let mut scissor_box = [0i32; 4];
+        let mut viewport = [0i32; 4];
+        let mut clear_color = [0f32; 4];
+        let mut color_mask = [0u8; 4];
+        gl.GetIntegerv(gl_sys::SCISSOR_BOX, scissor_box.as_mut_ptr());
+        gl.GetIntegerv(gl_sys::VIEWPORT, viewport.as_mut_ptr());
+        gl.GetFloatv(gl_sys::COLOR_CLEAR_VALUE, clear_color.as_mut_ptr());
+        gl.GetBooleanv(gl_sys::COLOR_WRITEMASK, color_mask.as_mut_ptr());
+
+        Self {
+            read_fbo,
+            draw_fbo,
+            read_buffer,
+            active_texture,
+            texture,
+            pack_buffer: (!gles2).then(|| get_int(gl_sys::PIXEL_PACK_BUFFER_BINDING)),
+            unpack_buffer: (!gles2).then(|| get_int(gl_sys::PIXEL_UNPACK_BUFFER_BINDING)),
+            pack_alignment: get_int(gl_sys::PACK_ALIGNMENT),
+            pack_row_length: (!gles2).then(|| get_int(gl_sys::PACK_ROW_LENGTH)),
+            srgb: (!gles2).then(|| gl.IsEnabled(gl_sys::FRAMEBUFFER_SRGB) != 0),
+            scissor_test: gl.IsEnabled(gl_sys::SCISSOR_TEST) != 0,
+            scissor_box,
+            viewport,
+            clear_color,
+            color_mask,
+        }
+    }
+
+    unsafe fn apply(&self, gl: &Gl) {
+        let set_cap = |cap: gl_t::GLenum, enabled: bool| {
+            if enabled {
+                gl.Enable(cap);
+            } else {
+                gl.Disable(cap);
+            }
+        };
+
+        if let Some(read_buffer) = self.read_buffer {
+            gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, 0);
+            gl.ReadBuffer(read_buffer as _);
+        }
+        if self.read_fbo == self.draw_fbo {
+            gl.BindFramebuffer(gl_sys::FRAMEBUFFER, self.draw_fbo as _);
+        } else {
+            gl.BindFramebuffer(gl_sys::READ_FRAMEBUFFER, self.read_fbo as _);
+            gl.BindFramebuffer(gl_sys::DRAW_FRAMEBUFFER, self.draw_fbo as _);
+        }
+
+        gl.ActiveTexture(gl_sys::TEXTURE0);
+        gl.BindTexture(gl_sys::TEXTURE_2D, self.texture as _);
+        gl.ActiveTexture(self.active_texture as _);
+
+        if let Some(pack_buffer) = self.pack_buffer {
+            gl.BindBuffer(gl_sys::PIXEL_PACK_BUFFER, pack_buffer as _);
+        }
+        if let Some(unpack_buffer) = self.unpack_buffer {
+            gl.BindBuffer(gl_sys::PIXEL_UNPACK_BUFFER, unpack_buffer as _);
+        }
+        gl.PixelStorei(gl_sys::PACK_ALIGNMENT, self.pack_alignment);
+        if let Some(pack_row_length) = self.pack_row_length {
+            gl.PixelStorei(gl_sys::PACK_ROW_LENGTH, pack_row_length);
+        }
+
+        if let Some(srgb) = self.srgb {
+            set_cap(gl_sys::FRAMEBUFFER_SRGB, srgb);
+        }
+        set_cap(gl_sys::SCISSOR_TEST, self.scissor_test);
+        let [x, y, width, height] = self.scissor_box;
+        gl.Scissor(x, y, width, height);
+        let [x, y, width, height] = self.viewport;
+        gl.Viewport(x, y, width, height);
+        let [r, g, b, a] = self.clear_color;
+        gl.ClearColor(r, g, b, a);
+        let [r, g, b, a] = self.color_mask;
+        gl.ColorMask(r, g, b, a);
+    }
+}
+
+/// Saves GL state the capture path touches and restores it once dropped,
+/// leaving texture unit `TEXTURE0` active meanwhile
+pub struct GlStateGuard<'a> {
+    gl: &'a Gl,
+    gles2: bool,
+    saved: GlState,
+}
+
+impl<'a> GlStateGuard<'a> {
+    pub unsafe fn new(gl: &'a Gl, gles2: bool) -> Self {
+        let saved = GlState::query(gl, gles2);
+        gl.ActiveTexture(gl_sys::TEXTURE0);
+        Self { gl, gles2, saved }
+    }
+}
+
+impl<'a> Drop for GlStateGuard<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            self.saved.apply(self.gl);
+            // queries stall some drivers, so restored state is only verified
+            // in debug builds
+            if cfg!(debug_assertions) && !std::thread::panicking() {
+                let restored = GlState::query(self.gl, self.gles2);
+                debug_assert_eq!(restored, self.saved, "GL state not restored");
+            }
+        }
+    }
+}
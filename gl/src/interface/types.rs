@@ -3,11 +3,12 @@ use crate::utils::*;
 use super::*;
 
 use core::fmt::Debug;
-use core::sync::atomic::AtomicU64;
+use core::sync::atomic::{AtomicBool, AtomicU64};
 use std::collections::VecDeque;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use dashmap::DashMap;
+use function_name::named;
 use pw_capture_client as client;
 use pw_capture_cursor::CursorManager;
 use pw_capture_gl_sys::prelude::*;
@@ -52,6 +53,30 @@ pub enum NativeIface {
     Egl,
 }
 
+/// Color depth of the surface framebuffer, exported textures match it
+///
+/// sRGB framebuffers store encoded values that are copied as is, so they
+/// export as UNORM textures of the same depth.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FramebufferFormat {
+    #[default]
+    Rgba8,
+    Rgb10A2,
+    /// Half float, e.g. of HDR or scRGB surfaces
+    Rgba16F,
+}
+
+impl FramebufferFormat {
+    /// Format of less depth exported if textures of this one can't be
+    pub fn fallback(self) -> Option<Self> {
+        match self {
+            Self::Rgba16F => Some(Self::Rgb10A2),
+            Self::Rgb10A2 => Some(Self::Rgba8),
+            Self::Rgba8 => None,
+        }
+    }
+}
+
 pub enum FenceSync {
     Gl { native: NativeIface, sync: GlHandle },
     Egl { dpy: GlHandle, sync: GlHandle },
@@ -83,6 +108,11 @@ pub enum TextureImage {
         glx_pixmap: GlHandle,
         x_pixmap: GlHandle,
     },
+    /// Shared memory filled by glReadPixels, texture is left unused
+    MemFd {
+        map: GlHandle,
+        size: usize,
+    },
 }
 
 #[derive(Debug)]
@@ -94,6 +124,13 @@ pub struct ExportTexture {
     pub image: TextureImage,
 }
 
+impl ExportTexture {
+    /// Bytes of video memory the texture holds
+    pub fn size(&self) -> u64 {
+        self.planes.iter().map(|plane| plane.size as u64).sum()
+    }
+}
+
 impl Drop for ExportTexture {
     fn drop(&mut self) {
         unsafe {
@@ -121,8 +158,168 @@ impl Drop for ExportTexture {
                     glx.DestroyPixmap(dpy as _, glx_pixmap.as_ptr::<c_void>() as _);
                     (x11.XFreePixmap)(dpy as _, x_pixmap.as_ptr::<c_void>() as _);
                 }
+                TextureImage::MemFd { map, size } => {
+                    libc::munmap(map.as_ptr::<c_void>() as _, size);
+                }
+            }
+
+            gl(self.native).DeleteTextures(1, &self.texture);
+        }
+    }
+}
+
+/// Single-sampled renderbuffer multisampled default framebuffers get
+/// resolved into, as resolving blits can neither scale nor flip
+pub struct ResolveBuffer {
+    pub native: NativeIface,
+    /// Share group of the context the renderbuffer got created in
+    pub share_group: GlHandle,
+    pub renderbuffer: u32,
+}
+
+impl ResolveBuffer {
+    pub unsafe fn new(
+        native: NativeIface,
+        share_group: GlHandle,
+        width: u32,
+        height: u32,
+        internal_format: u32,
+    ) -> Self {
+        let gl = gl(native);
+        let mut prev_renderbuffer: i32 = 0;
+        gl.GetIntegerv(gl_sys::RENDERBUFFER_BINDING, &mut prev_renderbuffer);
+        let mut renderbuffer: u32 = 0;
+        gl.GenRenderbuffers(1, &mut renderbuffer);
+        gl.BindRenderbuffer(gl_sys::RENDERBUFFER, renderbuffer);
+        gl.RenderbufferStorage(
+            gl_sys::RENDERBUFFER,
+            internal_format,
+            width as _,
+            height as _,
+        );
+        gl.BindRenderbuffer(gl_sys::RENDERBUFFER, prev_renderbuffer as _);
+        Self {
+            native,
+            share_group,
+            renderbuffer,
+        }
+    }
+}
+
+impl Drop for ResolveBuffer {
+    #[named]
+    fn drop(&mut self) {
+        unsafe {
+            // same as of shader copies, renderbuffers of another share group
+            // may have the same name
+            if !is_share_group_current(self.native, self.share_group) {
+                debug!("share group not current, leaving resolve buffer");
+                return;
             }
+            gl(self.native).DeleteRenderbuffers(1, &self.renderbuffer);
+        }
+    }
+}
+
+/// Watermark of post-processing uploaded once in the format of exported
+/// textures, runs of it are copied onto them
+pub struct WatermarkTexture {
+    pub native: NativeIface,
+    /// Share group of the context the texture got created in
+    pub share_group: GlHandle,
+    pub texture: u32,
+}
+
+impl WatermarkTexture {
+    pub unsafe fn new(
+        native: NativeIface,
+        share_group: GlHandle,
+        gles2: bool,
+        watermark: &client::Watermark,
+        fb_format: FramebufferFormat,
+    ) -> Option<Self> {
+        // glCopyTexSubImage2D doesn't convert between fixed and floating
+        // point formats, upload texels encoded as exported textures are
+        let (internal_format, type_, texels) = match fb_format {
+            FramebufferFormat::Rgba8 => (gl_sys::RGBA, gl_sys::UNSIGNED_BYTE, None),
+            FramebufferFormat::Rgb10A2 => (
+                gl_sys::RGB10_A2,
+                gl_sys::UNSIGNED_INT_2_10_10_10_REV,
+                Some(watermark.encode(client::Format::xBGR_210LE)?),
+            ),
+            FramebufferFormat::Rgba16F => (
+                gl_sys::RGBA16F,
+                gl_sys::HALF_FLOAT,
+                Some(watermark.encode(client::Format::RGBA_F16)?),
+            ),
+        };
+        let texels = texels.as_deref().unwrap_or(&watermark.rgba);
+        let gl = gl(native);
+        let get_int = |pname: gl_t::GLenum| {
+            let mut value: i32 = 0;
+            gl.GetIntegerv(pname, &mut value);
+            value
+        };
+        let prev_texture = get_int(gl_sys::TEXTURE_BINDING_2D);
+        let prev_alignment = get_int(gl_sys::UNPACK_ALIGNMENT);
+        // pixel buffers and row lengths are not available on GLES2
+        let prev_unpack = (!gles2).then(|| {
+            let unpack = (
+                get_int(gl_sys::PIXEL_UNPACK_BUFFER_BINDING),
+                get_int(gl_sys::UNPACK_ROW_LENGTH),
+            );
+            gl.BindBuffer(gl_sys::PIXEL_UNPACK_BUFFER, 0);
+            gl.PixelStorei(gl_sys::UNPACK_ROW_LENGTH, 0);
+            unpack
+        });
+        gl.PixelStorei(gl_sys::UNPACK_ALIGNMENT, 4);
+
+        let mut texture: u32 = 0;
+        gl.GenTextures(1, &mut texture);
+        gl.BindTexture(gl_sys::TEXTURE_2D, texture);
+        for (pname, param) in [
+            (gl_sys::TEXTURE_MIN_FILTER, gl_sys::NEAREST),
+            (gl_sys::TEXTURE_MAG_FILTER, gl_sys::NEAREST),
+        ] {
+            gl.TexParameteri(gl_sys::TEXTURE_2D, pname, param as _);
+        }
+        // rows top first, as of exported textures
+        gl.TexImage2D(
+            gl_sys::TEXTURE_2D,
+            0,
+            internal_format as _,
+            watermark.width as _,
+            watermark.height as _,
+            0,
+            gl_sys::RGBA,
+            type_,
+            texels.as_ptr() as _,
+        );
 
+        gl.BindTexture(gl_sys::TEXTURE_2D, prev_texture as _);
+        gl.PixelStorei(gl_sys::UNPACK_ALIGNMENT, prev_alignment);
+        if let Some((buffer, row_length)) = prev_unpack {
+            gl.BindBuffer(gl_sys::PIXEL_UNPACK_BUFFER, buffer as _);
+            gl.PixelStorei(gl_sys::UNPACK_ROW_LENGTH, row_length);
+        }
+        Some(Self {
+            native,
+            share_group,
+            texture,
+        })
+    }
+}
+
+impl Drop for WatermarkTexture {
+    #[named]
+    fn drop(&mut self) {
+        unsafe {
+            // same as of shader copies, textures of another share group may
+            // have the same name
+            if !is_share_group_current(self.native, self.share_group) {
+                debug!("share group not current, leaving watermark texture");
+                return;
+            }
             gl(self.native).DeleteTextures(1, &self.texture);
         }
     }
@@ -146,15 +343,59 @@ pub struct LayerSurface {
     pub cursor_manager: Option<Box<dyn CursorManager + Sync + Send>>,
     pub capture_valid: bool,
     pub capture: Option<LayerCapture>,
+    /// Pbuffer captured on glFlush()/glFinish() instead of buffer swaps
+    pub offscreen: bool,
+    /// Swap interval the app set, published once captured
+    pub swap_interval: Option<i32>,
+    /// X window backing a GLX surface, checked for being alive as apps may
+    /// destroy it without glXDestroyWindow()
+    pub x_drawable: Option<u32>,
+    /// Held while capturing, apps like emulators may swap buffers of a
+    /// surface from several threads
+    pub capture_lock: Arc<Mutex<()>>,
 }
 
 pub struct LayerCapture {
-    pub context: GlHandle,
+    /// Share group of contexts capture resources live in
+    pub share_group: GlHandle,
     pub width: u32,
     pub height: u32,
+    /// Size of exported textures, differs from surface size if downscaled
+    pub export_width: u32,
+    pub export_height: u32,
+    /// Where frames are blitted to within exported textures, smaller than
+    /// them if letterboxed
+    pub export_region: client::Region,
     pub cursor_serial: AtomicU64,
+    /// Serial of window title, app id and visibility last published
+    pub window_serial: AtomicU64,
+    /// Set while the window is minimized or hidden, frames are not captured
+    pub window_suspended: AtomicBool,
     pub stream: client::Stream,
+    /// Set while stream is streaming, no buffer can be dequeued otherwise
+    pub streaming: Arc<AtomicBool>,
+    /// Format, modifier and plane count shared by all exported textures
+    pub export_format: (client::Format, Option<u64>, usize),
+    /// Textures are filled by glReadPixels instead of exported as DMA-BUF
+    pub use_read_pixels: bool,
+    /// Color buffer of the default framebuffer frames are copied from
+    pub read_buffer: u32,
+    /// Context is OpenGL ES 2, saved GL state differs
+    pub gles2: bool,
+    pub fb_format: FramebufferFormat,
+    pub buffer_demand: Mutex<client::BufferDemand>,
     pub free_textures: Mutex<VecDeque<ExportTexture>>,
     pub mapped_textures: DashMap<u32, ExportTexture>,
     pub sync_objects: DashMap<u32, FenceSync>,
+    /// Copies into exported DMA-BUFs are fenced by sync files attached to
+    /// them instead of sync objects waited on CPU
+    pub native_fence_sync: bool,
+    pub shader_copy: Option<ShaderCopy>,
+    /// Set if the default framebuffer is multisampled
+    pub resolve_buffer: Option<ResolveBuffer>,
+    /// Set if a watermark is drawn onto exported textures
+    pub watermark: Option<WatermarkTexture>,
+    /// Set if frames read into shared memory are post-processed
+    pub post_process_texels: Option<client::EncodedTexels>,
+    pub damage: Mutex<DamageTracker>,
 }
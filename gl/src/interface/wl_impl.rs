@@ -3,7 +3,7 @@ use super::*;
 use core::ffi::c_int;
 
 use pw_capture_cursor::wl_sys::*;
-use pw_capture_cursor::{CursorManager, CursorSnapshot, WlCursorManager};
+use pw_capture_cursor::{CursorManager, CursorSnapshot, WindowInfo, WlCursorManager};
 
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
@@ -54,6 +54,18 @@ pub unsafe fn me_eh5_pw_capture_wl_cursor_snapshot(
     Some(snap)
 }
 
+#[no_mangle]
+pub unsafe fn me_eh5_pw_capture_wl_window_info(
+    cursor_manager: usize,
+    serial: u64,
+) -> Option<WindowInfo> {
+    if cursor_manager == 0 {
+        return None;
+    }
+    let manager = CURSOR_MANAGER_MAP.get(&cursor_manager)?;
+    manager.window_info(serial)
+}
+
 #[inline(never)]
 pub unsafe extern "C" fn impl_wl_proxy_marshal_array_flags(
     proxy: *mut wl_proxy,
@@ -110,7 +122,10 @@ pub unsafe extern "C" fn impl_wl_proxy_destroy(proxy: *mut wl_proxy) {
     wl_intercept.intercept_wl_proxy_destroy(proxy)
 }
 
-#[cfg(feature = "nightly")]
+#[cfg(all(
+    feature = "nightly",
+    not(any(target_arch = "x86_64", target_arch = "aarch64"))
+))]
 #[no_mangle]
 pub unsafe extern "C" fn wl_proxy_marshal_flags(
     proxy: *mut wl_proxy,
@@ -118,16 +133,19 @@ pub unsafe extern "C" fn wl_proxy_marshal_flags(
     interface: *const wl_interface,
     version: u32,
     flags: u32,
-    args: ...
+    mut args: ...
 ) -> *mut wl_proxy {
     let proxy_interface = &**(proxy as *mut *const wl_interface);
     let method = &*proxy_interface.methods.offset(opcode as _);
-    let mut args = VaListToWlArgumentIter::new(method.signature, args).collect::<Vec<_>>();
+    let mut args = wl_arguments_from_varargs(method.signature, &mut args);
 
     impl_wl_proxy_marshal_array_flags(proxy, opcode, interface, version, flags, args.as_mut_ptr())
 }
 
-#[cfg(feature = "nightly")]
+#[cfg(all(
+    feature = "nightly",
+    not(any(target_arch = "x86_64", target_arch = "aarch64"))
+))]
 #[inline(never)]
 pub unsafe extern "C" fn impl_wl_proxy_marshal_flags(
     proxy: *mut wl_proxy,
@@ -135,11 +153,11 @@ pub unsafe extern "C" fn impl_wl_proxy_marshal_flags(
     interface: *const wl_interface,
     version: u32,
     flags: u32,
-    args: ...
+    mut args: ...
 ) -> *mut wl_proxy {
     let proxy_interface = &**(proxy as *mut *const wl_interface);
     let method = &*proxy_interface.methods.offset(opcode as _);
-    let mut args = VaListToWlArgumentIter::new(method.signature, args).collect::<Vec<_>>();
+    let mut args = wl_arguments_from_varargs(method.signature, &mut args);
 
     impl_wl_proxy_marshal_array_flags(proxy, opcode, interface, version, flags, args.as_mut_ptr())
 }
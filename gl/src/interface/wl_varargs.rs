@@ -0,0 +1,252 @@
+use super::*;
+
+use pw_capture_cursor::{WlSig, WlSignatureIter};
+
+/// Reads variadic arguments of `wl_proxy_marshal_flags()`
+pub trait WlVarArgs {
+    unsafe fn next_int(&mut self) -> i32;
+    unsafe fn next_ptr(&mut self) -> *mut c_void;
+}
+
+/// Arguments of a request with `signature` read off `args`
+pub unsafe fn wl_arguments_from_varargs(
+    signature: *const c_char,
+    args: &mut impl WlVarArgs,
+) -> Vec<wl_argument> {
+    WlSignatureIter::new(signature)
+        .map(|sig| match sig {
+            WlSig::Int => wl_argument { i: args.next_int() },
+            WlSig::UInt => wl_argument {
+                u: args.next_int() as u32,
+            },
+            WlSig::Fixed => wl_argument {
+                f: wl_fixed_t::from_bits(args.next_int()),
+            },
+            WlSig::String => wl_argument {
+                s: args.next_ptr() as _,
+            },
+            // pointer on client side, usually NULL as the proxy is created
+            // by libwayland
+            WlSig::Object | WlSig::NewId => wl_argument {
+                o: args.next_ptr() as _,
+            },
+            WlSig::Array => wl_argument {
+                a: args.next_ptr() as _,
+            },
+            WlSig::Fd => wl_argument { h: args.next_int() },
+        })
+        .collect()
+}
+
+#[cfg(all(
+    feature = "nightly",
+    not(any(target_arch = "x86_64", target_arch = "aarch64"))
+))]
+impl<'f> WlVarArgs for core::ffi::VaList<'f> {
+    unsafe fn next_int(&mut self) -> i32 {
+        self.next_arg::<i32>()
+    }
+
+    unsafe fn next_ptr(&mut self) -> *mut c_void {
+        self.next_arg::<*mut c_void>()
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use shim::*;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod shim {
+    use super::*;
+
+    /// Variadic arguments spilled by the shim, first the ones passed in
+    /// registers then the ones passed on the stack
+    #[repr(C)]
+    struct SpilledVarArgs {
+        regs: *const usize,
+        num_regs: usize,
+        stack: *const usize,
+    }
+
+    struct SpilledVarArgsIter<'a> {
+        args: &'a SpilledVarArgs,
+        index: usize,
+    }
+
+    impl<'a> SpilledVarArgsIter<'a> {
+        unsafe fn next_word(&mut self) -> usize {
+            let args = self.args;
+            let word = if self.index < args.num_regs {
+                *args.regs.add(self.index)
+            } else {
+                *args.stack.add(self.index - args.num_regs)
+            };
+            self.index += 1;
+            word
+        }
+    }
+
+    impl<'a> WlVarArgs for SpilledVarArgsIter<'a> {
+        unsafe fn next_int(&mut self) -> i32 {
+            // upper bits of 32-bit arguments are unspecified
+            self.next_word() as i32
+        }
+
+        unsafe fn next_ptr(&mut self) -> *mut c_void {
+            self.next_word() as _
+        }
+    }
+
+    #[inline(never)]
+    unsafe extern "C" fn marshal_flags_spilled(
+        proxy: *mut wl_proxy,
+        opcode: u32,
+        interface: *const wl_interface,
+        version: u32,
+        flags: u32,
+        args: *const SpilledVarArgs,
+    ) -> *mut wl_proxy {
+        let proxy_interface = &**(proxy as *mut *const wl_interface);
+        let method = &*proxy_interface.methods.offset(opcode as _);
+        let mut args = SpilledVarArgsIter {
+            args: &*args,
+            index: 0,
+        };
+        let mut args = wl_arguments_from_varargs(method.signature, &mut args);
+
+        impl_wl_proxy_marshal_array_flags(
+            proxy,
+            opcode,
+            interface,
+            version,
+            flags,
+            args.as_mut_ptr(),
+        )
+    }
+
+    // stable Rust can not define the variadic `wl_proxy_marshal_flags()`, the
+    // shims spill argument registers to memory for `marshal_flags_spilled`.
+    // Wayland requests take no floating point arguments, so variadic ones are
+    // all passed in integer registers and then on the stack, a word each.
+
+    // 5 named arguments in rdi, rsi, rdx, rcx and r8, the first variadic one
+    // in r9 and the rest on the stack above the return address
+    #[cfg(target_arch = "x86_64")]
+    core::arch::global_asm!(
+        ".pushsection .text.wl_proxy_marshal_flags,\"ax\",@progbits",
+        ".globl wl_proxy_marshal_flags",
+        ".type wl_proxy_marshal_flags,@function",
+        ".globl pw_capture_wl_proxy_marshal_flags",
+        ".hidden pw_capture_wl_proxy_marshal_flags",
+        ".type pw_capture_wl_proxy_marshal_flags,@function",
+        "wl_proxy_marshal_flags:",
+        "pw_capture_wl_proxy_marshal_flags:",
+        ".cfi_startproc",
+        "push rbp",
+        ".cfi_def_cfa_offset 16",
+        ".cfi_offset rbp, -16",
+        "mov rbp, rsp",
+        ".cfi_def_cfa_register rbp",
+        // r9, then `SpilledVarArgs` at rsp + 8
+        "sub rsp, 32",
+        "mov qword ptr [rsp], r9",
+        "mov qword ptr [rsp + 8], rsp",
+        "mov qword ptr [rsp + 16], 1",
+        "lea rax, [rbp + 16]",
+        "mov qword ptr [rsp + 24], rax",
+        "lea r9, [rsp + 8]",
+        "call {marshal}",
+        "leave",
+        ".cfi_def_cfa rsp, 8",
+        "ret",
+        ".cfi_endproc",
+        ".size wl_proxy_marshal_flags, .-wl_proxy_marshal_flags",
+        ".size pw_capture_wl_proxy_marshal_flags, .-pw_capture_wl_proxy_marshal_flags",
+        ".popsection",
+        marshal = sym marshal_flags_spilled,
+    );
+
+    // 5 named arguments in x0-x4, the first 3 variadic ones in x5-x7 and the
+    // rest on the stack in 8 byte slots
+    #[cfg(target_arch = "aarch64")]
+    core::arch::global_asm!(
+        ".pushsection .text.wl_proxy_marshal_flags,\"ax\",@progbits",
+        ".globl wl_proxy_marshal_flags",
+        ".type wl_proxy_marshal_flags,@function",
+        ".globl pw_capture_wl_proxy_marshal_flags",
+        ".hidden pw_capture_wl_proxy_marshal_flags",
+        ".type pw_capture_wl_proxy_marshal_flags,@function",
+        "wl_proxy_marshal_flags:",
+        "pw_capture_wl_proxy_marshal_flags:",
+        ".cfi_startproc",
+        "stp x29, x30, [sp, #-64]!",
+        ".cfi_def_cfa_offset 64",
+        ".cfi_offset x29, -64",
+        ".cfi_offset x30, -56",
+        "mov x29, sp",
+        // x5-x7 at sp + 16, then `SpilledVarArgs` at sp + 40
+        "stp x5, x6, [sp, #16]",
+        "str x7, [sp, #32]",
+        "add x9, sp, #16",
+        "mov x10, #3",
+        "stp x9, x10, [sp, #40]",
+        "add x9, sp, #64",
+        "str x9, [sp, #56]",
+        "add x5, sp, #40",
+        "bl {marshal}",
+        "ldp x29, x30, [sp], #64",
+        ".cfi_def_cfa_offset 0",
+        ".cfi_restore x29",
+        ".cfi_restore x30",
+        "ret",
+        ".cfi_endproc",
+        ".size wl_proxy_marshal_flags, .-wl_proxy_marshal_flags",
+        ".size pw_capture_wl_proxy_marshal_flags, .-pw_capture_wl_proxy_marshal_flags",
+        ".popsection",
+        marshal = sym marshal_flags_spilled,
+    );
+
+    extern "C" {
+        /// Hidden alias of the shim, not interposed by
+        /// `wl_proxy_marshal_flags()` of libwayland
+        pub fn pw_capture_wl_proxy_marshal_flags(
+            proxy: *mut wl_proxy,
+            opcode: u32,
+            interface: *const wl_interface,
+            version: u32,
+            flags: u32,
+            ...
+        ) -> *mut wl_proxy;
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn spilled_varargs() {
+            let regs = [1usize, (-2i32) as u32 as usize | 0xdead_0000_0000];
+            let stack = [0x100usize, 0, 3 << 8, 0x200, 4];
+            let args = SpilledVarArgs {
+                regs: regs.as_ptr(),
+                num_regs: regs.len(),
+                stack: stack.as_ptr(),
+            };
+            let mut iter = SpilledVarArgsIter {
+                args: &args,
+                index: 0,
+            };
+            let args = unsafe { wl_arguments_from_varargs(b"2uisnfah\0".as_ptr() as _, &mut iter) };
+            assert_eq!(args.len(), 7);
+            unsafe {
+                assert_eq!(args[0].u, 1);
+                assert_eq!(args[1].i, -2);
+                assert_eq!(args[2].s as usize, 0x100);
+                assert!(args[3].o.is_null());
+                assert_eq!(args[4].f, wl_fixed_t::from_num(3));
+                assert_eq!(args[5].a as usize, 0x200);
+                assert_eq!(args[6].h, 4);
+            }
+        }
+    }
+}
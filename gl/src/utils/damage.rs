@@ -0,0 +1,227 @@
+use std::collections::{HashMap, VecDeque};
+
+use pw_capture_client::Region;
+
+/// Frames damage is kept for, textures last copied to before are copied as a
+/// whole
+const MAX_HISTORY: usize = 16;
+
+const EMPTY: Region = Region {
+    x: 0,
+    y: 0,
+    width: 0,
+    height: 0,
+};
+
+/// Damage of swapped frames as bounding boxes in surface coordinates, origin
+/// at the bottom left as in EGL, so only what changed since a texture was last
+/// copied into gets copied again
+#[derive(Default)]
+pub struct DamageTracker {
+    /// Frames swapped so far
+    frame: u64,
+    /// Damage of the latest frames, `None` if damaged as a whole
+    history: VecDeque<Option<Region>>,
+    /// Frame last copied into each exported texture
+    textures: HashMap<u32, u64>,
+    /// Frame last copied into any texture, i.e. queued to the stream
+    last_copied: Option<u64>,
+    /// Damage since the previous frame of the stream, of the frame in each
+    /// exported texture
+    stream_damage: HashMap<u32, Option<Region>>,
+    /// Set by eglSetDamageRegionKHR() for the frame being drawn
+    region: Option<Vec<Region>>,
+}
+
+impl DamageTracker {
+    /// Records a swapped frame with `rects` damaged, `None` if damaged as a
+    /// whole. Returns the frame, to be passed to [`copy`](Self::copy) as it
+    /// may race with swaps of other threads.
+    pub fn push(&mut self, rects: Option<&[Region]>) -> u64 {
+        let damage = rects
+            .filter(|v| !v.is_empty())
+            .map(|v| v.iter().copied().fold(EMPTY, union));
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(damage);
+        self.frame += 1;
+        self.frame
+    }
+
+    /// Sets the damage region of the frame being drawn, `None` if all of it
+    pub fn set_region(&mut self, rects: Option<Vec<Region>>) {
+        self.region = rects;
+    }
+
+    /// Takes the damage region of the swapped frame
+    pub fn take_region(&mut self) -> Option<Vec<Region>> {
+        self.region.take()
+    }
+
+    /// Region to copy `frame` into `texture` from, `None` for all of it
+    pub fn copy(&mut self, texture: u32, frame: Option<u64>) -> Option<Region> {
+        let frame = match frame {
+            Some(v) => v,
+            None => {
+                // frames before capture got set up are not tracked
+                self.textures.remove(&texture);
+                self.stream_damage.remove(&texture);
+                self.last_copied = None;
+                return None;
+            }
+        };
+        let stream_damage = self.damage(self.last_copied, frame);
+        self.stream_damage.insert(texture, stream_damage);
+        self.last_copied = Some(frame);
+        let since = self.textures.insert(texture, frame);
+        self.damage(since, frame)
+    }
+
+    /// Damage of the frame in `texture` since the previous one of the
+    /// stream, `None` if damaged as a whole
+    pub fn stream_damage(&self, texture: u32) -> Option<Region> {
+        self.stream_damage.get(&texture).copied().flatten()
+    }
+
+    /// Forgets `texture`, its name may be reused
+    pub fn forget(&mut self, texture: u32) {
+        self.textures.remove(&texture);
+        self.stream_damage.remove(&texture);
+    }
+
+    /// Damage of frames after `since` up to `frame`
+    fn damage(&self, since: Option<u64>, frame: u64) -> Option<Region> {
+        let since = since?;
+        let first = self.frame + 1 - self.history.len() as u64;
+        if since + 1 < first || frame > self.frame || since > frame {
+            return None;
+        }
+        let start = (since + 1 - first) as usize;
+        let end = (frame + 1 - first) as usize;
+        self.history
+            .range(start..end)
+            .try_fold(EMPTY, |acc, v| Some(union(acc, (*v)?)))
+    }
+}
+
+/// Bounding box of `a` and `b`, empty regions are ignored
+fn union(a: Region, b: Region) -> Region {
+    if a.width == 0 || a.height == 0 {
+        return b;
+    }
+    if b.width == 0 || b.height == 0 {
+        return a;
+    }
+    let x = a.x.min(b.x);
+    let y = a.y.min(b.y);
+    Region {
+        x,
+        y,
+        width: (a.x + a.width).max(b.x + b.width) - x,
+        height: (a.y + a.height).max(b.y + b.height) - y,
+    }
+}
+
+/// Maps `damage` of a `from` sized surface onto region `to` of exported
+/// frames, whose rows start at the top. Grows by a texel while scaling, as
+/// filtering samples neighbours.
+pub fn scale_damage(damage: Region, from: (u32, u32), to: Region) -> Region {
+    let (width, height) = from;
+    if damage.width == 0 || damage.height == 0 || width == 0 || height == 0 {
+        return EMPTY;
+    }
+    let margin = u32::from((width, height) != (to.width, to.height));
+    let x0 = damage.x.saturating_sub(margin).min(width) as u64;
+    let y0 = damage.y.saturating_sub(margin).min(height) as u64;
+    let x1 = (damage.x + damage.width + margin).min(width) as u64;
+    let y1 = (damage.y + damage.height + margin).min(height) as u64;
+    // rounded outwards
+    let scale_down = |v: u64, to: u32, from: u32| (v * to as u64 / from as u64) as u32;
+    let scale_up =
+        |v: u64, to: u32, from: u32| ((v * to as u64 + from as u64 - 1) / from as u64) as u32;
+    let left = scale_down(x0, to.width, width);
+    let right = scale_up(x1, to.width, width);
+    // flipped
+    let top = to.height - scale_up(y1, to.height, height);
+    let bottom = to.height - scale_down(y0, to.height, height);
+    Region {
+        x: to.x + left,
+        y: to.y + top,
+        width: right - left,
+        height: bottom - top,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn track_damage() {
+        let mut tracker = DamageTracker::default();
+        let frame = tracker.push(Some(&[region(0, 0, 10, 10)]));
+        // first copy into a texture
+        assert_eq!(tracker.copy(1, Some(frame)), None);
+        assert_eq!(tracker.stream_damage(1), None);
+
+        let frame = tracker.push(Some(&[region(10, 10, 10, 10), region(30, 0, 5, 5)]));
+        assert_eq!(tracker.copy(1, Some(frame)), Some(region(10, 0, 25, 20)));
+        assert_eq!(tracker.stream_damage(1), Some(region(10, 0, 25, 20)));
+
+        // skipped frame
+        tracker.push(Some(&[region(50, 50, 1, 1)]));
+        let frame = tracker.push(Some(&[region(0, 0, 1, 1)]));
+        assert_eq!(tracker.copy(2, Some(frame)), None);
+        assert_eq!(tracker.stream_damage(2), Some(region(0, 0, 51, 51)));
+        let frame = tracker.push(Some(&[]));
+        assert_eq!(tracker.copy(1, Some(frame)), None);
+
+        // texture one frame behind, frame without damage
+        let frame = tracker.push(Some(&[EMPTY]));
+        assert_eq!(tracker.copy(1, Some(frame)), Some(EMPTY));
+
+        for _ in 0..MAX_HISTORY {
+            tracker.push(Some(&[region(0, 0, 1, 1)]));
+        }
+        let frame = tracker.push(Some(&[region(0, 0, 1, 1)]));
+        assert_eq!(tracker.copy(1, Some(frame)), None);
+
+        tracker.forget(1);
+        assert_eq!(tracker.stream_damage(1), None);
+        assert_eq!(tracker.copy(1, None), None);
+        let frame = tracker.push(Some(&[region(0, 0, 1, 1)]));
+        assert_eq!(tracker.copy(1, Some(frame)), None);
+
+        // damage region is taken once
+        tracker.set_region(Some(vec![region(0, 0, 2, 2)]));
+        assert_eq!(tracker.take_region(), Some(vec![region(0, 0, 2, 2)]));
+        assert_eq!(tracker.take_region(), None);
+    }
+
+    #[test]
+    fn scale() {
+        let to = region(0, 0, 100, 100);
+        assert_eq!(
+            scale_damage(region(10, 0, 20, 10), (100, 100), to),
+            region(10, 90, 20, 10)
+        );
+        assert_eq!(scale_damage(EMPTY, (100, 100), to), EMPTY);
+        // downscaled and letterboxed
+        let to = region(10, 0, 50, 50);
+        assert_eq!(
+            scale_damage(region(10, 0, 20, 10), (100, 100), to),
+            region(14, 44, 12, 6)
+        );
+        assert_eq!(scale_damage(region(0, 0, 100, 100), (100, 100), to), to);
+    }
+}
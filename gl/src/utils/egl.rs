@@ -4,10 +4,20 @@ use core::ffi::{c_int, c_uchar, c_void};
 use core::ptr;
 use std::env;
 
+use pw_capture_client::{ColorPrimaries, Colorimetry, TransferFunction};
 use pw_capture_gl_sys::prelude::egl_sys;
 
 const EGL_DEFAULT_DISPLAY: *mut c_void = ptr::null_mut();
 
+// EGL_EXT_gl_colorspace_* values, not covered by generated bindings
+const EGL_GL_COLORSPACE_BT2020_LINEAR_EXT: i32 = 0x333F;
+const EGL_GL_COLORSPACE_BT2020_PQ_EXT: i32 = 0x3340;
+const EGL_GL_COLORSPACE_SCRGB_LINEAR_EXT: i32 = 0x3350;
+const EGL_GL_COLORSPACE_SCRGB_EXT: i32 = 0x3351;
+const EGL_GL_COLORSPACE_DISPLAY_P3_LINEAR_EXT: i32 = 0x3362;
+const EGL_GL_COLORSPACE_DISPLAY_P3_EXT: i32 = 0x3363;
+const EGL_GL_COLORSPACE_BT2020_HLG_EXT: i32 = 0x3540;
+
 #[derive(PartialEq, Eq, Debug)]
 pub enum EglPlatform {
     X11,
@@ -124,6 +134,26 @@ pub unsafe fn wl_egl_window_get_wl_surface(window: *mut wl_egl_window_v3) -> *mu
     }
 }
 
+/// maps value of `EGL_GL_COLORSPACE` surface attribute
+pub fn egl_colorspace_get_colorimetry(colorspace: i32) -> Colorimetry {
+    use ColorPrimaries as P;
+    use TransferFunction as T;
+    match colorspace {
+        // GL_COLORSPACE_LINEAR (the default) only disables sRGB encoding on write,
+        // apps render already encoded values so the content is still sRGB
+        v if v == egl_sys::GL_COLORSPACE_SRGB as i32 => Colorimetry::SRGB,
+        v if v == egl_sys::GL_COLORSPACE_LINEAR as i32 => Colorimetry::SRGB,
+        EGL_GL_COLORSPACE_SCRGB_EXT => Colorimetry::rgb(P::BT709, T::SRGB),
+        EGL_GL_COLORSPACE_SCRGB_LINEAR_EXT => Colorimetry::rgb(P::BT709, T::GAMMA10),
+        EGL_GL_COLORSPACE_DISPLAY_P3_EXT => Colorimetry::rgb(P::SMPTEEG432, T::SRGB),
+        EGL_GL_COLORSPACE_DISPLAY_P3_LINEAR_EXT => Colorimetry::rgb(P::SMPTEEG432, T::GAMMA10),
+        EGL_GL_COLORSPACE_BT2020_LINEAR_EXT => Colorimetry::rgb(P::BT2020, T::GAMMA10),
+        EGL_GL_COLORSPACE_BT2020_PQ_EXT => Colorimetry::rgb(P::BT2020, T::SMPTE2084),
+        EGL_GL_COLORSPACE_BT2020_HLG_EXT => Colorimetry::rgb(P::BT2020, T::ARIB_STD_B67),
+        _ => Colorimetry::default(),
+    }
+}
+
 pub unsafe fn egl_get_native_platform(native_display: *mut c_void) -> Option<EglPlatform> {
     if let Some(plat) = egl_get_native_platform_from_env() {
         return Some(plat);
@@ -1,9 +1,15 @@
+mod damage;
 mod egl;
 mod logger;
 mod x11_lib;
 
 use core::ffi::{c_void, CStr};
+use std::ffi::{CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
 
+use pw_capture_client as client;
+
+pub use damage::*;
 pub use egl::*;
 pub use logger::*;
 pub use x11_lib::*;
@@ -31,6 +37,22 @@ pub unsafe fn dlopen(filenames: &[&CStr]) -> Option<*mut c_void> {
             return Some(h);
         }
     }
+    // the loader only finds libraries of Flatpak GL extensions the ld.so
+    // cache of the runtime lists
+    for dir in client::flatpak_gl_lib_dirs() {
+        for filename in filenames {
+            let path = dir.join(OsStr::from_bytes(filename.to_bytes()));
+            let path = match CString::new(path.as_os_str().as_bytes()) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let h = libc::dlopen(path.as_ptr(), libc::RTLD_LAZY);
+            if !h.is_null() {
+                log::debug!("loaded {path:?}");
+                return Some(h);
+            }
+        }
+    }
     if !filenames.is_empty() {
         log::warn!(
             "failed to load {}",
@@ -29,6 +29,49 @@ pub struct xcb_dri3_buffers_from_pixmap_reply_t {
     pub _pad1: [u8; 6],
 }
 
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct xcb_get_geometry_cookie_t {
+    pub sequence: c_uint,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_camel_case_types)]
+pub struct xcb_get_geometry_reply_t {
+    pub response_type: u8,
+    pub depth: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub root: u32,
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    pub border_width: u16,
+    pub _pad0: [u8; 2],
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_snake_case)]
+pub struct XErrorEvent {
+    pub type_: c_int,
+    pub display: *mut c_void,
+    pub resourceid: c_ulong,
+    pub serial: c_ulong,
+    pub error_code: u8,
+    pub request_code: u8,
+    pub minor_code: u8,
+}
+
+pub const X_BAD_WINDOW: u8 = 3;
+pub const X_BAD_DRAWABLE: u8 = 9;
+
+#[allow(non_camel_case_types)]
+pub type XErrorHandler =
+    Option<unsafe extern "C" fn(dpy: *mut c_void, event: *mut XErrorEvent) -> c_int>;
+
 #[allow(non_snake_case)]
 pub struct X11Lib {
     pub XDefaultRootWindow: unsafe extern "C" fn(dpy: *mut c_void) -> c_ulong,
@@ -43,6 +86,15 @@ pub struct X11Lib {
     pub XFreePixmap: unsafe extern "C" fn(dpy: *mut c_void, pixmap: c_ulong) -> c_int,
     pub XFree: unsafe extern "C" fn(m: *mut c_void) -> c_int,
     pub XGetXCBConnection: unsafe extern "C" fn(dpy: *mut c_void) -> *mut c_void,
+    pub XSetErrorHandler: unsafe extern "C" fn(handler: XErrorHandler) -> XErrorHandler,
+    pub XSync: unsafe extern "C" fn(dpy: *mut c_void, discard: c_int) -> c_int,
+    pub xcb_get_geometry:
+        unsafe extern "C" fn(xcb_conn: *mut c_void, drawable: u32) -> xcb_get_geometry_cookie_t,
+    pub xcb_get_geometry_reply: unsafe extern "C" fn(
+        xcb_conn: *mut c_void,
+        cookie: xcb_get_geometry_cookie_t,
+        *mut *mut c_void,
+    ) -> *mut xcb_get_geometry_reply_t,
     pub xcb_dri3_buffers_from_pixmap: unsafe extern "C" fn(
         xcb_conn: *mut c_void,
         pixmap: xcb_pixmap_t,
@@ -90,6 +142,7 @@ impl X11Lib {
 
         let xlib = dlopen(&[cstr!(b"libX11.so.6\0"), cstr!(b"libX11.so\0")])?;
         let xlib_xcb = dlopen(&[cstr!(b"libX11-xcb.so.1\0"), cstr!(b"libX11-xcb.so\0")])?;
+        let xcb = dlopen(&[cstr!(b"libxcb.so.1\0"), cstr!(b"libxcb.so\0")])?;
         let dri3 = dlopen(&[cstr!(b"libxcb-dri3.so.0\0"), cstr!(b"libxcb-dri3.so\0")])?;
 
         Some(construct!(
@@ -99,6 +152,10 @@ impl X11Lib {
             XFreePixmap: xlib,
             XFree: xlib,
             XGetXCBConnection: xlib_xcb,
+            XSetErrorHandler: xlib,
+            XSync: xlib,
+            xcb_get_geometry: xcb,
+            xcb_get_geometry_reply: xcb,
             xcb_dri3_buffers_from_pixmap: dri3,
             xcb_dri3_buffers_from_pixmap_reply: dri3,
             xcb_dri3_buffers_from_pixmap_reply_fds: dri3,
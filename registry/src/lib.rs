@@ -0,0 +1,174 @@
+//! Handle tables of layer state
+
+use core::fmt::Debug;
+use core::hash::Hash;
+
+use dashmap::iter::Iter;
+use dashmap::mapref::one::{Ref, RefMut};
+use dashmap::DashMap;
+use log::{debug, info};
+use once_cell::sync::Lazy;
+
+pub type RemoveHook<K, V> = fn(&K, &V);
+
+/// Thread-safe table of handles to their layer state, entries only leave it
+/// through [`HandleTable::remove`] or get replaced by [`HandleTable::insert`]
+pub struct HandleTable<K, V> {
+    name: &'static str,
+    map: Lazy<DashMap<K, V>>,
+    on_remove: Option<RemoveHook<K, V>>,
+}
+
+impl<K: Eq + Hash, V> HandleTable<K, V> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            map: Lazy::new(DashMap::new),
+            on_remove: None,
+        }
+    }
+
+    /// Table calling `on_remove` with every entry removed or replaced, after
+    /// it left the table
+    pub const fn with_on_remove(name: &'static str, on_remove: RemoveHook<K, V>) -> Self {
+        Self {
+            name,
+            map: Lazy::new(DashMap::new),
+            on_remove: Some(on_remove),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn get(&self, key: &K) -> Option<Ref<'_, K, V>> {
+        self.map.get(key)
+    }
+
+    pub fn get_mut(&self, key: &K) -> Option<RefMut<'_, K, V>> {
+        self.map.get_mut(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.map.iter()
+    }
+
+    /// Inserts `value`, the entry it replaces is returned after going through
+    /// `on_remove`
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        K: Clone,
+    {
+        let old = self.map.insert(key.clone(), value)?;
+        if let Some(on_remove) = self.on_remove {
+            on_remove(&key, &old);
+        }
+        Some(old)
+    }
+
+    /// Inserts `value` unless `key` is taken, returns whether it was
+    pub fn insert_if_absent(&self, key: K, value: V) -> bool {
+        let mut inserted = false;
+        self.map.entry(key).or_insert_with(|| {
+            inserted = true;
+            value
+        });
+        inserted
+    }
+
+    pub fn remove(&self, key: &K) -> Option<(K, V)> {
+        let entry = self.map.remove(key)?;
+        if let Some(on_remove) = self.on_remove {
+            on_remove(&entry.0, &entry.1);
+        }
+        Some(entry)
+    }
+}
+
+/// Table checked by [`report_leaks`]
+pub trait Table: Sync {
+    fn name(&self) -> &'static str;
+    /// Handles left in the table, formatted for logging
+    fn handles(&self) -> Vec<String>;
+}
+
+impl<K, V> Table for HandleTable<K, V>
+where
+    K: Eq + Hash + Debug + Send + Sync,
+    V: Send + Sync,
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn handles(&self) -> Vec<String> {
+        self.map.iter().map(|e| format!("{:?}", e.key())).collect()
+    }
+}
+
+/// Logs handles left in `tables` and returns their count, apps commonly
+/// leave objects to process teardown so this is informational only
+pub fn report_leaks(tables: &[&dyn Table]) -> usize {
+    let mut count = 0;
+    for table in tables {
+        let handles = table.handles();
+        if handles.is_empty() {
+            continue;
+        }
+        info!(
+            "{} {} handle(s) never destroyed: {}",
+            handles.len(),
+            table.name(),
+            handles.join(", ")
+        );
+        count += handles.len();
+    }
+    if count == 0 {
+        debug!("no handles leaked");
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    static REMOVED: AtomicU32 = AtomicU32::new(0);
+    static PARENTS: HandleTable<u32, ()> = HandleTable::new("parent");
+    static CHILDREN: HandleTable<u32, u32> = HandleTable::with_on_remove("child", |_, parent| {
+        REMOVED.fetch_add(1, Ordering::Relaxed);
+        assert!(
+            PARENTS.contains_key(parent),
+            "parent destroyed before child"
+        );
+    });
+
+    #[test]
+    fn remove_hook_and_leaks() {
+        PARENTS.insert(1, ());
+        CHILDREN.insert(10, 1);
+        CHILDREN.insert(11, 1);
+        assert_eq!(report_leaks(&[&PARENTS, &CHILDREN]), 3);
+
+        assert_eq!(CHILDREN.remove(&10), Some((10, 1)));
+        assert_eq!(CHILDREN.remove(&10), None);
+        assert_eq!(REMOVED.load(Ordering::Relaxed), 1);
+        assert_eq!(CHILDREN.handles(), vec!["11".to_owned()]);
+
+        assert!(!CHILDREN.insert_if_absent(11, 2));
+        assert_eq!(CHILDREN.insert(11, 1), Some(1));
+        assert_eq!(REMOVED.load(Ordering::Relaxed), 2);
+
+        CHILDREN.remove(&11);
+        PARENTS.remove(&1);
+        assert_eq!(REMOVED.load(Ordering::Relaxed), 3);
+        assert_eq!(report_leaks(&[&PARENTS, &CHILDREN]), 0);
+    }
+}
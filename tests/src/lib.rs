@@ -0,0 +1,112 @@
+//! Layers running under `VK_LAYER_KHRONOS_validation`
+
+use std::sync::Mutex;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use pw_capture_bench::*;
+
+pub const WIDTH: u32 = 640;
+pub const HEIGHT: u32 = 480;
+/// Frames rendered per case, every swapchain image gets captured repeatedly
+pub const FRAMES: usize = 60;
+
+/// Whether the layer built alongside the tests got added to the layer path
+static LAYER: Lazy<bool> = Lazy::new(|| match target_dir().and_then(|dir| add_layer_path(&dir)) {
+    Ok(()) => true,
+    Err(e) => {
+        eprintln!("{e}, skipping layer cases");
+        false
+    }
+});
+
+/// Consumers link to the newest capture node of the process, so cases run
+/// one at a time
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Validated app, `None` if the environment can not run it or the layer is
+/// not available
+pub fn validated_app(with_layer: bool) -> Option<VkApp> {
+    if with_layer && !*LAYER {
+        return None;
+    }
+    // the app alone tells whether the environment is usable at all, failing
+    // with the layer enabled is the layer's fault
+    if let Err(e) = VkApp::new_validated(WIDTH, HEIGHT, false) {
+        eprintln!("skipping, failed to create validated app: {e:?}");
+        return None;
+    }
+    Some(VkApp::new_validated(WIDTH, HEIGHT, with_layer).expect("failed to create app with layer"))
+}
+
+/// Renders `FRAMES` frames, returns validation errors reported so far
+pub fn render(app: &mut VkApp) -> Result<Vec<String>> {
+    for _ in 0..FRAMES {
+        app.frame()?;
+    }
+    app.wait_idle()?;
+    Ok(app.validation_errors())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pw_capture_client::ConsumerOptions;
+
+    const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+
+    fn assert_valid(errors: Vec<String>) {
+        assert!(
+            errors.is_empty(),
+            "{} validation errors:\n{}",
+            errors.len(),
+            errors.join("\n")
+        );
+    }
+
+    #[test]
+    fn app_is_valid() {
+        let _serial = SERIAL.lock().unwrap();
+        let Some(mut app) = validated_app(false) else {
+            return;
+        };
+        assert_valid(render(&mut app).unwrap());
+    }
+
+    #[test]
+    fn idle_layer_is_valid() {
+        let _serial = SERIAL.lock().unwrap();
+        let Some(mut app) = validated_app(true) else {
+            return;
+        };
+        assert_valid(render(&mut app).unwrap());
+    }
+
+    #[test]
+    fn capture_is_valid() {
+        let _serial = SERIAL.lock().unwrap();
+        let dma_buf = ConsumerOptions {
+            modifiers: vec![DRM_FORMAT_MOD_LINEAR],
+            ..Default::default()
+        };
+        let memfd = ConsumerOptions {
+            dma_buf: false,
+            ..Default::default()
+        };
+        for options in [dma_buf, memfd] {
+            let Some(mut app) = validated_app(true) else {
+                return;
+            };
+            let consumer = Consumer::connect(options, || app.frame().map(drop));
+            let _consumer = match consumer {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("skipping, no consumer linked: {e:?}");
+                    return;
+                }
+            };
+            assert_valid(render(&mut app).unwrap());
+        }
+    }
+}
@@ -2,31 +2,72 @@ mod utils;
 use utils::*;
 
 use pw_capture_client as client;
-use pw_capture_cursor::{self as local_cursor, CursorManager, CursorSnapshot};
+use pw_capture_cursor::{self as local_cursor, CursorManager, CursorSnapshot, WindowInfo};
+use pw_capture_registry::{report_leaks, HandleTable};
 
 use core::ffi::{c_char, c_void, CStr};
 use core::mem;
 use core::ptr;
 use core::result::Result::{Err, Ok};
 use core::slice;
-use core::sync::atomic::{self, AtomicU64};
+use core::sync::atomic::{self, AtomicBool, AtomicU64, AtomicUsize};
 use std::collections::HashSet;
 use std::ffi::CString;
-use std::time::Instant;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
-use ash::extensions::khr;
-use ash::vk;
+use ash::extensions::{ext, khr};
+use ash::vk::{self, Handle};
 use ash_layer::*;
 use dashmap::DashMap;
 use function_name::named;
 
-use once_cell::sync::{Lazy, OnceCell};
+use once_cell::sync::Lazy;
 
 const MAX_BUFFERS: u32 = 128;
+/// Copy command buffers kept per swapchain image, commands of the least
+/// recently used pair of images get recorded again beyond that
+const COPY_COMMANDS_PER_IMAGE: usize = 8;
+/// Frames not presented in time are stamped with the time they got processed
+const PRESENT_WAIT_TIMEOUT: u64 = 100_000_000;
+/// Bounds how long stream workers wait for copies before queuing frames anyway
+const COPY_WAIT_TIMEOUT: u64 = 1_000_000_000;
+/// Presents of a swapchain that may wait on semaphores of the layer besides
+/// one per image
+const MAX_FRAMES_IN_FLIGHT: usize = 2;
+/// Node property marking streams of swapchains presenting directly to a display
+const PROP_DIRECT_DISPLAY: &str = "pw-capture.direct-display";
+/// Node property marking streams of swapchains on headless surfaces
+const PROP_HEADLESS: &str = "pw-capture.headless";
+/// Node property marking streams of depth attachments
+const PROP_DEPTH: &str = "pw-capture.depth";
+/// Node properties of `VkApplicationInfo` the app created its instance with
+const PROP_APPLICATION_NAME: &str = "pw-capture.vulkan.application-name";
+const PROP_ENGINE_NAME: &str = "pw-capture.vulkan.engine-name";
+/// Debug utils names of objects the layer creates
+const EXPORT_IMAGE_NAME: &CStr =
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"pw-capture export image\0") };
+const EXPORT_MEMORY_NAME: &CStr =
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"pw-capture export memory\0") };
+const COPY_COMMANDS_NAME: &CStr =
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"pw-capture copy commands\0") };
+const SYNC_FILE_SEMAPHORE_NAME: &CStr =
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"pw-capture sync file semaphore\0") };
+const SHARED_PRESENT_SEMAPHORE_NAME: &CStr =
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"pw-capture shared present semaphore\0") };
+const TIMESTAMP_POOL_NAME: &CStr =
+    unsafe { CStr::from_bytes_with_nul_unchecked(b"pw-capture copy timestamp\0") };
 
 struct LayerInstanceValid {
     khr_phy_props2: khr::GetPhysicalDeviceProperties2,
+    /// Loaded if optional instance extensions got enabled, sync files are not
+    /// exported otherwise
+    khr_semaphore_caps: Option<vk::KhrExternalSemaphoreCapabilitiesFn>,
+    /// Set if optional instance extensions got enabled, DMA-BUF export
+    /// depends on them
+    external_memory_caps: bool,
+    ext_calibrated_timestamps: ext::CalibratedTimestamps,
 }
 
 struct LayerInstance {
@@ -35,11 +76,29 @@ struct LayerInstance {
     xlib_surface: khr::XlibSurface,
     xcb_surface: khr::XcbSurface,
     wayland_surface: khr::WaylandSurface,
+    khr_display: khr::Display,
+    ext_headless_surface: ext::HeadlessSurface,
+    khr_surface_caps2: khr::GetSurfaceCapabilities2,
     valid: Option<LayerInstanceValid>,
+    /// Cleared by the `enable` layer setting
+    enabled: bool,
+    /// Node properties of the app and engine names the instance was created
+    /// with, e.g. to tell apart games running in the same Wine prefix
+    app_props: Vec<(String, String)>,
+    /// Set if the app enabled `VK_EXT_debug_utils`
+    debug_utils: bool,
 }
 
 struct LayerDeviceValid {
     khr_memfd: khr::ExternalMemoryFd,
+    /// Loaded if exporting semaphores as sync file is supported
+    khr_semaphore_fd: Option<khr::ExternalSemaphoreFd>,
+    /// Loaded if presents can be waited for, frames are stamped with the
+    /// time they got presented
+    khr_present_wait: Option<khr::PresentWait>,
+    /// Loaded if GPU timestamps can be calibrated, frames are stamped with
+    /// the time their copy started otherwise
+    gpu_clock: Option<Arc<GpuClock>>,
     // ext_modifier: ext::ImageDrmFormatModifier,
 }
 
@@ -48,10 +107,49 @@ struct LayerDevice {
     phy_device: vk::PhysicalDevice,
     ash_device: ash::Device,
     khr_swapchain: khr::Swapchain,
+    /// Vulkan 1.3 or `VK_KHR_synchronization2` submit of next layer
+    queue_submit2: Option<vk::PFN_vkQueueSubmit2>,
+    /// Vulkan 1.2 or `VK_KHR_create_renderpass2` command of next layer,
+    /// loaded if depth capture is enabled
+    create_render_pass2: Option<vk::PFN_vkCreateRenderPass2>,
+    /// Tracked if the app enabled timeline semaphores
+    timeline: Option<Arc<TimelineSemaphores>>,
+    /// Loaded if the app enabled `VK_GOOGLE_display_timing`
+    get_refresh_cycle_duration: Option<vk::PFN_vkGetRefreshCycleDurationGOOGLE>,
+    /// Render and primary node, empty if unknown
+    drm_devices: Vec<client::DrmDevice>,
+    /// Frames are copied into shared memory as consumers import DMA-BUFs on
+    /// another device
+    host_export: bool,
+    /// Callbacks the app created the device with, also used for objects the
+    /// layer creates on it
+    allocator: Allocator,
     queues: Vec<vk::Queue>,
+    /// Tracked if depth capture is enabled
+    depth_images: Option<DepthImages>,
+    /// Loaded if depth capture is enabled, attachments can be picked by name,
+    /// or the app enabled `VK_EXT_debug_utils`
+    set_debug_utils_object_name: Option<vk::PFN_vkSetDebugUtilsObjectNameEXT>,
+    /// Set if the app enabled `VK_EXT_debug_utils`, objects the layer creates
+    /// are named so validation messages tell them apart from objects of the
+    /// app
+    debug_utils: bool,
     valid: Option<LayerDeviceValid>,
 }
 
+/// Commands of next layer hooked on command buffers, forwarded even if the
+/// rest of the device state can not be found
+#[derive(Clone, Copy)]
+struct DeviceDispatch {
+    device: vk::Device,
+    cmd_pipeline_barrier: vk::PFN_vkCmdPipelineBarrier,
+    cmd_begin_render_pass: vk::PFN_vkCmdBeginRenderPass,
+    /// Vulkan 1.2 or `VK_KHR_create_renderpass2` and Vulkan 1.3 or
+    /// `VK_KHR_synchronization2` commands, loaded if depth capture is enabled
+    cmd_begin_render_pass2: Option<vk::PFN_vkCmdBeginRenderPass2>,
+    cmd_pipeline_barrier2: Option<vk::PFN_vkCmdPipelineBarrier2>,
+}
+
 #[allow(unused)]
 struct LayerQueue {
     device: vk::Device,
@@ -61,17 +159,16 @@ struct LayerQueue {
 }
 
 struct LayerSurface {
-    #[allow(unused)]
     instance: vk::Instance,
-    #[allow(unused)]
+    platform: SurfacePlatform,
     cursor_manager: Option<Box<dyn CursorManager + Send + Sync>>,
     wl_cursor_manager: usize,
 }
 
 struct ImageData {
-    semaphores: Vec<vk::Semaphore>,
-    fence: FenceState,
-    seq: usize,
+    /// Signaled along with the present semaphore, exported as sync file
+    sync_file_semaphore: Option<vk::Semaphore>,
+    sync: ImageSync,
 }
 
 struct ExportImage {
@@ -79,57 +176,281 @@ struct ExportImage {
     image: vk::Image,
     memory: vk::DeviceMemory,
     fds: Vec<(i32, vk::SubresourceLayout)>,
-    src_image: (vk::Image, usize),
+    /// Source image and sequence number of the copy on it
+    src_image: (vk::Image, u64),
+    /// Time the frame copied from `src_image` got presented, stamped by the
+    /// stream worker, or estimated by the GPU timestamp of the copy
+    present_pts: Option<i64>,
+    /// Timestamp written as the copy starts, queried by the stream worker
+    timestamp_pool: Option<vk::QueryPool>,
+    /// Copy fence is attached to the DMA-BUF, consumers wait for it instead
+    sync_file_attached: bool,
+    nv12_target: Option<Nv12Target>,
+    /// Set if frames are copied into shared memory
+    host_map: Option<HostMap>,
+    /// Sequence number shared with the depth frame copied along with it
+    frame_seq: Option<u64>,
 }
 
-#[derive(Default)]
 struct ExportData {
+    src_format: vk::Format,
+    extent: vk::Extent2D,
+    /// Extent of export images, smaller than `extent` if downscaled
+    export_extent: vk::Extent2D,
+    /// Where frames are blitted to within export images, smaller than
+    /// `export_extent` if letterboxed
+    export_region: vk::Rect2D,
     format: vk::Format,
     queue: vk::Queue,
     queue_family_index: u32,
-    command_pool: vk::CommandPool,
-    command_buffers: Vec<vk::CommandBuffer>,
+    /// Of `queue_family_index`, no timestamps are written if zero
+    timestamp_valid_bits: u32,
+    /// Copy commands recorded per swapchain image and export image
+    copy_cache: Mutex<CopyCache>,
     modifier: Option<u64>,
     num_planes: u32,
+    nv12: Option<Nv12Converter>,
+    /// Swaps channels of formats the device can not blit between
+    swizzle: Option<SwizzleConverter>,
+    /// Applies masks and the watermark to export images after the copy
+    post_processor: Option<PostProcessor>,
+    indicator: Option<Indicator>,
+    /// Copies depth attachments along with frames on `queue`
+    depth_copier: Option<DepthCopier>,
+}
+
+/// Swapchain the stream captures from, retargeted on swapchain recreation
+#[derive(Clone)]
+struct StreamTarget(Arc<AtomicU64>);
+
+impl StreamTarget {
+    fn new(swapchain: vk::SwapchainKHR) -> Self {
+        Self(Arc::new(AtomicU64::new(swapchain.as_raw())))
+    }
+
+    fn get(&self) -> vk::SwapchainKHR {
+        vk::SwapchainKHR::from_raw(self.0.load(atomic::Ordering::Acquire))
+    }
+
+    fn set(&self, swapchain: vk::SwapchainKHR) {
+        self.0.store(swapchain.as_raw(), atomic::Ordering::Release)
+    }
+}
+
+/// Swapchain of `VK_KHR_shared_presentable_image`, whose single image is
+/// shared with the presentation engine and rendered to while shown
+struct SharedPresent {
+    /// Under `SHARED_CONTINUOUS_REFRESH_KHR` the image gets updated without
+    /// being presented again, so it is captured after submits instead
+    continuous: bool,
+    /// Queue the image was last presented from, app updates the image by
+    /// submitting to it
+    queue: Mutex<Option<vk::Queue>>,
+    /// Signaled after submits to `queue` for captures to wait on, null unless
+    /// `continuous`
+    semaphore: vk::Semaphore,
 }
 
 struct LayerSwapchain {
-    #[allow(unused)]
     device: vk::Device,
     #[allow(unused)]
     surface: vk::SurfaceKHR,
     format: vk::Format,
+    color_space: vk::ColorSpaceKHR,
     extent: vk::Extent2D,
+    /// Part of `extent` the surface showed at creation, smaller if the
+    /// swapchain is larger than the surface
+    visible_extent: vk::Extent2D,
+    /// Usage swapchain images were created with
+    image_usage: vk::ImageUsageFlags,
+    /// Images shared concurrently are not owned by any queue family
+    sharing_mode: vk::SharingMode,
     images: Vec<vk::Image>,
     stream: Option<client::Stream>,
+    stream_target: StreamTarget,
+    /// Set while stream is streaming, no buffer can be dequeued otherwise
+    streaming: Arc<AtomicBool>,
+    buffer_demand: Mutex<client::BufferDemand>,
     image_datas: DashMap<vk::Image, ImageData>,
+    /// Signaled by copies and indicator draws for presents to wait on
+    present_semaphores: Mutex<SemaphorePool>,
     export_images: DashMap<vk::Image, ExportImage>,
     export_data: Option<ExportData>,
     cursor_serial: AtomicU64,
+    /// Serial of window title, app id and visibility last published
+    window_serial: AtomicU64,
+    /// Set while the window is minimized or hidden, frames are not captured
+    window_suspended: AtomicBool,
+    /// Last present id, assigned by the layer or the app
+    present_id: AtomicU64,
+    /// Whether the swapchain no longer matches its surface
+    stale: StaleState,
+    /// Present mode of the last present, apps may switch between those the
+    /// swapchain got created with through `VK_EXT_swapchain_maintenance1`
+    present_mode: Mutex<vk::PresentModeKHR>,
+    refresh_duration: Option<Duration>,
+    /// Limits captures of swapchains presenting faster than the display,
+    /// follows `present_mode`
+    pacer: Mutex<Option<FramePacer>>,
+    shared_present: Option<SharedPresent>,
+    /// Set if depth capture is enabled
+    depth: Option<DepthStream>,
+}
+
+/// Stream of the depth attachment captured along with frames of a swapchain
+struct DepthStream {
+    stream: client::Stream,
+    /// Set while stream is streaming, no buffer can be dequeued otherwise
+    streaming: Arc<AtomicBool>,
+    /// Shared with callbacks of the stream
+    buffers: Arc<DashMap<vk::Buffer, DepthBuffer>>,
+    /// Sequence number of the next frame, shared by both streams so
+    /// consumers can pair them
+    next_seq: AtomicU64,
+}
+
+impl LayerDevice {
+    /// Names `object` created by the layer if the app enabled
+    /// `VK_EXT_debug_utils`
+    unsafe fn set_object_name<T: Handle>(&self, object: T, name: &CStr) {
+        let set_debug_utils_object_name = match self.set_debug_utils_object_name {
+            Some(v) if self.debug_utils => v,
+            _ => return,
+        };
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(T::TYPE)
+            .object_handle(object.as_raw())
+            .object_name(name);
+        let _ = set_debug_utils_object_name(self.ash_device.handle(), &*name_info);
+    }
+}
+
+impl LayerSwapchain {
+    /// Queue family owning an image presented on a queue of `present_family`,
+    /// barriers transfer ownership from it to `export_family` and back unless
+    /// both are the same
+    fn owner_queue_family(&self, present_family: u32, export_family: u32) -> u32 {
+        match self.sharing_mode {
+            // explicit family indices of concurrent images are invalid
+            vk::SharingMode::CONCURRENT => export_family,
+            _ => present_family,
+        }
+    }
+
+    /// Layout images are in while being captured
+    fn present_layout(&self) -> vk::ImageLayout {
+        match self.shared_present {
+            Some(_) => vk::ImageLayout::SHARED_PRESENT_KHR,
+            None => vk::ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
+    /// Paces captures for `present_mode` a frame got presented with
+    #[named]
+    fn set_present_mode(&self, present_mode: vk::PresentModeKHR) {
+        let mut current = self.present_mode.lock().unwrap();
+        if *current == present_mode {
+            return;
+        }
+        debug!(
+            "present mode switched from {:?} to {:?}",
+            *current, present_mode
+        );
+        *current = present_mode;
+        *self.pacer.lock().unwrap() = FramePacer::new(present_mode, self.refresh_duration);
+    }
 }
 
 static LOGGING: Lazy<()> = Lazy::new(init_logger);
 
 static CLIENT: Lazy<Option<client::Client>> = Lazy::new(|| {
-    client::Client::new()
+    let client = client::Client::new()
         .map_err(|e| error!(target:"client init", "failed to create client: {e:?}"))
-        .ok()
+        .ok();
+    if client.is_some() {
+        unsafe { libc::atexit(shutdown_at_exit) };
+    }
+    client
 });
 
-static GIPA: OnceCell<vk::PFN_vkGetInstanceProcAddr> = OnceCell::new();
-static ENTRY: OnceCell<ash::Entry> = OnceCell::new();
+/// Capture is disabled for the process, e.g. by `DISABLE_PW_CAPTURE=1`. The
+/// layer then only passes instance and device creation and destruction down
+/// the chain, never connecting to PipeWire.
+static PASSTHROUGH: Lazy<bool> = Lazy::new(|| !client::app_allowed());
 
 // DashMap ensures thread-safely
-static INSTANCE_MAP: Lazy<DashMap<vk::Instance, LayerInstance>> = Lazy::new(DashMap::new);
-static PHY_TO_INSTANCE_MAP: Lazy<DashMap<vk::PhysicalDevice, vk::Instance>> =
-    Lazy::new(DashMap::new);
-static GDPA_MAP: Lazy<DashMap<vk::Device, vk::PFN_vkGetDeviceProcAddr>> =
-    Lazy::new(DashMap::new);
-static DEVICE_MAP: Lazy<DashMap<vk::Device, LayerDevice>> = Lazy::new(DashMap::new);
-static QUEUE_MAP: Lazy<DashMap<vk::Queue, LayerQueue>> = Lazy::new(DashMap::new);
-static SURFACE_MAP: Lazy<DashMap<vk::SurfaceKHR, LayerSurface>> = Lazy::new(DashMap::new);
-static SWAPCHAIN_MAP: Lazy<DashMap<vk::SwapchainKHR, LayerSwapchain>> =
-    Lazy::new(DashMap::new);
+/// Next `vkGetInstanceProcAddr` of each instance, instances created alongside
+/// each other, e.g. by an OpenXR runtime next to the app, may come with
+/// different layer chains
+static GIPA_MAP: HandleTable<vk::Instance, vk::PFN_vkGetInstanceProcAddr> =
+    HandleTable::new("instance proc addr");
+/// Next `vkGetInstanceProcAddr` of the latest instance created, for global
+/// commands queried with a null or foreign instance
+static LAST_GIPA: Mutex<Option<vk::PFN_vkGetInstanceProcAddr>> = Mutex::new(None);
+static INSTANCE_MAP: HandleTable<vk::Instance, LayerInstance> =
+    HandleTable::with_on_remove("instance", on_instance_removed);
+static PHY_TO_INSTANCE_MAP: HandleTable<vk::PhysicalDevice, vk::Instance> =
+    HandleTable::new("physical device");
+static GDPA_MAP: HandleTable<vk::Device, vk::PFN_vkGetDeviceProcAddr> =
+    HandleTable::new("device proc addr");
+static DEVICE_MAP: HandleTable<vk::Device, LayerDevice> =
+    HandleTable::with_on_remove("device", on_device_removed);
+static QUEUE_MAP: HandleTable<vk::Queue, LayerQueue> = HandleTable::new("queue");
+/// Device and next layer of each dispatch table, command buffers are looked
+/// up by theirs
+static DISPATCH_MAP: HandleTable<usize, DeviceDispatch> = HandleTable::new("device dispatch table");
+static SURFACE_MAP: HandleTable<vk::SurfaceKHR, LayerSurface> = HandleTable::new("surface");
+static SWAPCHAIN_MAP: HandleTable<vk::SwapchainKHR, LayerSwapchain> = HandleTable::new("swapchain");
+/// Number of captured swapchains of `SHARED_CONTINUOUS_REFRESH_KHR`, submits
+/// are not looked at while there is none
+static CONTINUOUS_SWAPCHAINS: AtomicUsize = AtomicUsize::new(0);
+
+/// Objects of an instance or device destroyed before them are invalid, their
+/// state and streams are leaked
+#[named]
+fn on_instance_removed(instance: &vk::Instance, _: &LayerInstance) {
+    let devices = DEVICE_MAP
+        .iter()
+        .filter(|v| v.instance == *instance)
+        .count();
+    let surfaces = SURFACE_MAP
+        .iter()
+        .filter(|v| v.instance == *instance)
+        .count();
+    if devices + surfaces > 0 {
+        warn!("instance destroyed with {devices} device(s) and {surfaces} surface(s) alive");
+    }
+}
+
+#[named]
+fn on_device_removed(device: &vk::Device, _: &LayerDevice) {
+    let swapchains = SWAPCHAIN_MAP.iter().filter(|v| v.device == *device).count();
+    if swapchains > 0 {
+        warn!("device destroyed with {swapchains} swapchain(s) alive");
+    }
+}
+
+/// Terminates streams and the client thread on exit or when the layer gets
+/// unloaded, apps exiting without destroying their swapchains would otherwise
+/// leave nodes and exported buffers to racy process teardown
+extern "C" fn shutdown_at_exit() {
+    report_leaks(&[&INSTANCE_MAP, &DEVICE_MAP, &SURFACE_MAP, &SWAPCHAIN_MAP]);
+    let streams: Vec<_> = SWAPCHAIN_MAP
+        .iter()
+        .filter_map(|ly_swapchain| {
+            let stream = ly_swapchain.stream.as_ref()?;
+            Some((stream.proxy(), stream.worker()))
+        })
+        .collect();
+    // removing buffers closes their exported fds
+    for (stream, worker) in streams {
+        worker.flush();
+        let _ = stream.drain_and_terminate();
+    }
+    if let Some(client) = CLIENT.as_ref() {
+        client.shutdown();
+    }
+}
 
 macro_rules! map_err {
     ($e:expr) => {{
@@ -172,6 +493,14 @@ pub unsafe fn me_eh5_pw_capture_wl_cursor_snapshot(
     None
 }
 
+#[no_mangle]
+pub unsafe fn me_eh5_pw_capture_wl_window_info(
+    _cursor_manager: usize,
+    _serial: u64,
+) -> Option<WindowInfo> {
+    None
+}
+
 #[no_mangle]
 #[doc = "https://vulkan.lunarg.com/doc/view/1.3.236.0/linux/LoaderLayerInterface.html#user-content-layer-interface-version-2"]
 #[named]
@@ -179,6 +508,9 @@ pub unsafe extern "system" fn vkNegotiateLoaderLayerInterfaceVersion(
     p_version_struct: *mut NegotiateLayerInterface,
 ) -> vk::Result {
     Lazy::force(&LOGGING);
+    if *PASSTHROUGH {
+        debug!("capture disabled, passing through");
+    }
 
     let version_struct = &mut *p_version_struct;
     debug!(
@@ -224,17 +556,28 @@ unsafe extern "system" fn pwcap_vkGetInstanceProcAddr(
         return mem::transmute(pfn);
     }
 
-    let gipa = GIPA.get()?;
+    let gipa = next_gipa(instance)?;
 
     // for extension command, return NULL if next layer does not support given command
     let res = gipa(instance, p_name)?;
+    if *PASSTHROUGH {
+        return Some(res);
+    }
 
     'outer: {
         let pfn: *const () = match name.to_bytes() {
             b"vkCreateXlibSurfaceKHR" => pwcap_vkCreateXlibSurfaceKHR as _,
             b"vkCreateXcbSurfaceKHR" => pwcap_vkCreateXcbSurfaceKHR as _,
             b"vkCreateWaylandSurfaceKHR" => pwcap_vkCreateWaylandSurfaceKHR as _,
+            b"vkCreateDisplayPlaneSurfaceKHR" => pwcap_vkCreateDisplayPlaneSurfaceKHR as _,
+            b"vkCreateHeadlessSurfaceEXT" => pwcap_vkCreateHeadlessSurfaceEXT as _,
             b"vkDestroySurfaceKHR" => pwcap_vkDestroySurfaceKHR as _,
+            b"vkGetPhysicalDeviceSurfaceFormatsKHR" if prefer_export_formats_enabled() => {
+                pwcap_vkGetPhysicalDeviceSurfaceFormatsKHR as _
+            }
+            b"vkGetPhysicalDeviceSurfaceFormats2KHR" if prefer_export_formats_enabled() => {
+                pwcap_vkGetPhysicalDeviceSurfaceFormats2KHR as _
+            }
             _ => break 'outer,
         };
         debug!(
@@ -273,6 +616,9 @@ unsafe extern "system" fn pwcap_vkGetDeviceProcAddr(
     let gdpa = GDPA_MAP.get(&device)?;
     // for extension command, return NULL if next layer does not support given command
     let res = gdpa(device, p_name)?;
+    if *PASSTHROUGH {
+        return Some(res);
+    }
 
     'outer: {
         let pfn: *const () = match name.to_bytes() {
@@ -281,6 +627,33 @@ unsafe extern "system" fn pwcap_vkGetDeviceProcAddr(
             b"vkAcquireNextImageKHR" => pwcap_vkAcquireNextImageKHR as _,
             b"vkAcquireNextImage2KHR" => pwcap_vkAcquireNextImage2KHR as _,
             b"vkQueuePresentKHR" => pwcap_vkQueuePresentKHR as _,
+            b"vkCreateSemaphore" => pwcap_vkCreateSemaphore as _,
+            b"vkDestroySemaphore" => pwcap_vkDestroySemaphore as _,
+            b"vkQueueSubmit" => pwcap_vkQueueSubmit as _,
+            b"vkQueueSubmit2" | b"vkQueueSubmit2KHR" => pwcap_vkQueueSubmit2 as _,
+            b"vkCreateImage" if depth_capture().is_some() => pwcap_vkCreateImage as _,
+            b"vkDestroyImage" if depth_capture().is_some() => pwcap_vkDestroyImage as _,
+            b"vkSetDebugUtilsObjectNameEXT" if depth_capture().is_some() => {
+                pwcap_vkSetDebugUtilsObjectNameEXT as _
+            }
+            // layouts of depth attachments are tracked
+            b"vkCreateImageView" if depth_capture().is_some() => pwcap_vkCreateImageView as _,
+            b"vkDestroyImageView" if depth_capture().is_some() => pwcap_vkDestroyImageView as _,
+            b"vkCreateRenderPass" if depth_capture().is_some() => pwcap_vkCreateRenderPass as _,
+            b"vkCreateRenderPass2" | b"vkCreateRenderPass2KHR" if depth_capture().is_some() => {
+                pwcap_vkCreateRenderPass2 as _
+            }
+            b"vkDestroyRenderPass" if depth_capture().is_some() => pwcap_vkDestroyRenderPass as _,
+            b"vkCreateFramebuffer" if depth_capture().is_some() => pwcap_vkCreateFramebuffer as _,
+            b"vkDestroyFramebuffer" if depth_capture().is_some() => pwcap_vkDestroyFramebuffer as _,
+            b"vkCmdPipelineBarrier" if depth_capture().is_some() => pwcap_vkCmdPipelineBarrier as _,
+            b"vkCmdPipelineBarrier2" | b"vkCmdPipelineBarrier2KHR" if depth_capture().is_some() => {
+                pwcap_vkCmdPipelineBarrier2 as _
+            }
+            b"vkCmdBeginRenderPass" if depth_capture().is_some() => pwcap_vkCmdBeginRenderPass as _,
+            b"vkCmdBeginRenderPass2" | b"vkCmdBeginRenderPass2KHR" if depth_capture().is_some() => {
+                pwcap_vkCmdBeginRenderPass2 as _
+            }
             _ => break 'outer,
         };
         debug!(
@@ -297,10 +670,16 @@ const _: vk::PFN_vkGetDeviceProcAddr = pwcap_vkGetDeviceProcAddr;
 
 const LAYER_INSTANCE_EXTENSIONS: &[&CStr] = &[
     vk::KhrSurfaceFn::name(),
-    vk::KhrExternalMemoryCapabilitiesFn::name(),
     vk::KhrGetPhysicalDeviceProperties2Fn::name(),
 ];
 
+// dropped if the instance fails to be created with them, frames are then
+// copied into shared memory without sync files
+const LAYER_OPTIONAL_INSTANCE_EXTENSIONS: &[&CStr] = &[
+    vk::KhrExternalMemoryCapabilitiesFn::name(),
+    vk::KhrExternalSemaphoreCapabilitiesFn::name(),
+];
+
 #[no_mangle]
 #[named]
 unsafe extern "system" fn pwcap_vkCreateInstance(
@@ -319,6 +698,9 @@ unsafe extern "system" fn pwcap_vkCreateInstance(
 
     debug!("creating instance");
 
+    let settings = LayerSettings::load(create_info.p_next);
+    settings.apply();
+
     let layer_info = chain_info.u.p_layer_info.read();
     chain_info.u.p_layer_info = layer_info.p_next;
 
@@ -327,31 +709,58 @@ unsafe extern "system" fn pwcap_vkCreateInstance(
         .expect("broken layer info");
     debug!("GIPA: {:?}", gipa as *const ());
 
+    *LAST_GIPA.lock().unwrap() = Some(gipa);
+
     let name = CStr::from_bytes_with_nul_unchecked(b"vkCreateInstance\0");
     let create_instance: vk::PFN_vkCreateInstance =
         mem::transmute(gipa(vk::Instance::null(), name.as_ptr()));
 
-    let mut extensions: HashSet<CString> = slice::from_raw_parts(
+    if *PASSTHROUGH {
+        let res = create_instance(p_create_info, p_allocator, p_instance);
+        if res == vk::Result::SUCCESS {
+            GIPA_MAP.insert(*p_instance, gipa);
+        }
+        return res;
+    }
+
+    let app_extensions: HashSet<CString> = slice::from_raw_parts(
         create_info.pp_enabled_extension_names,
         create_info.enabled_extension_count as _,
     )
     .iter()
     .map(|&ptr| CStr::from_ptr(ptr).to_owned())
     .collect();
-    // extra extensions used by layer
-    for &name in LAYER_INSTANCE_EXTENSIONS {
-        extensions.insert(name.to_owned());
-    }
-    debug!("instance extensions: {:?}", extensions);
-    let extensions_data: Vec<*const i8> = extensions.iter().map(|ext| ext.as_ptr()).collect();
+    let create_instance_ext = |optional: bool, p_instance: *mut vk::Instance| {
+        // extra extensions used by layer
+        let mut extensions = app_extensions.clone();
+        let layer_extensions = if optional {
+            LAYER_OPTIONAL_INSTANCE_EXTENSIONS
+        } else {
+            &[]
+        };
+        for &name in LAYER_INSTANCE_EXTENSIONS.iter().chain(layer_extensions) {
+            extensions.insert(name.to_owned());
+        }
+        debug!("instance extensions: {:?}", extensions);
+        let extensions_data: Vec<*const i8> = extensions.iter().map(|ext| ext.as_ptr()).collect();
 
-    let mut create_info_ext = create_info;
-    create_info_ext.enabled_extension_count = extensions_data.len() as _;
-    create_info_ext.pp_enabled_extension_names = extensions_data.as_ptr();
+        let mut create_info_ext = create_info;
+        create_info_ext.enabled_extension_count = extensions_data.len() as _;
+        create_info_ext.pp_enabled_extension_names = extensions_data.as_ptr();
+        create_instance(&create_info_ext, p_allocator, p_instance)
+    };
 
-    let res = create_instance(&create_info_ext, p_allocator, p_instance);
+    let mut optional_extensions = true;
+    let mut res = create_instance_ext(optional_extensions, p_instance);
+    if res != vk::Result::SUCCESS {
+        warn!("failed to create instance with optional layer extensions: {res}");
+        *p_instance = vk::Instance::null();
+        optional_extensions = false;
+        res = create_instance_ext(optional_extensions, p_instance);
+    }
     let valid = res == vk::Result::SUCCESS;
     if !valid {
+        warn!("failed to create instance with layer extensions, not capturing: {res}");
         *p_instance = vk::Instance::null();
         let res = create_instance(&create_info, p_allocator, p_instance);
         if res != vk::Result::SUCCESS {
@@ -365,15 +774,13 @@ unsafe extern "system" fn pwcap_vkCreateInstance(
 
     // IMPORTANT: this should be put before any code executing dispatch_next_vkGetInstanceProcAddr
     //            i.e. ash::Instance::load and khr::Surface::new
-    let _ = GIPA.set(gipa);
+    GIPA_MAP.insert(instance, gipa);
 
     let entry = ash::Entry::from_static_fn(vk::StaticFn {
         // IMPORTANT: this make sure the layer provided device specific vkGetDeviceProcAddr is used instead of
         //            the instance specific one get from vkGetInstanceProcAddr, as the later would somehow crashes on execution.
         get_instance_proc_addr: dispatch_next_vkGetInstanceProcAddr,
     });
-    let _ = ENTRY.set(entry.clone());
-
     let ash_instance = ash::Instance::load(entry.static_fn(), instance);
 
     let phy_devices = ash_instance.enumerate_physical_devices().unwrap();
@@ -383,7 +790,18 @@ unsafe extern "system" fn pwcap_vkCreateInstance(
 
     let valid = if valid {
         let khr_phy_props2 = khr::GetPhysicalDeviceProperties2::new(&entry, &ash_instance);
-        Some(LayerInstanceValid { khr_phy_props2 })
+        let khr_semaphore_caps = optional_extensions.then(|| {
+            vk::KhrExternalSemaphoreCapabilitiesFn::load(|name| {
+                mem::transmute(entry.get_instance_proc_addr(instance, name.as_ptr()))
+            })
+        });
+        let ext_calibrated_timestamps = ext::CalibratedTimestamps::new(&entry, &ash_instance);
+        Some(LayerInstanceValid {
+            khr_phy_props2,
+            khr_semaphore_caps,
+            external_memory_caps: optional_extensions,
+            ext_calibrated_timestamps,
+        })
     } else {
         None
     };
@@ -392,6 +810,9 @@ unsafe extern "system" fn pwcap_vkCreateInstance(
     let xlib_surface = khr::XlibSurface::new(&entry, &ash_instance);
     let xcb_surface = khr::XcbSurface::new(&entry, &ash_instance);
     let wayland_surface = khr::WaylandSurface::new(&entry, &ash_instance);
+    let khr_display = khr::Display::new(&entry, &ash_instance);
+    let ext_headless_surface = ext::HeadlessSurface::new(&entry, &ash_instance);
+    let khr_surface_caps2 = khr::GetSurfaceCapabilities2::new(&entry, &ash_instance);
 
     INSTANCE_MAP.insert(
         instance,
@@ -401,7 +822,13 @@ unsafe extern "system" fn pwcap_vkCreateInstance(
             xlib_surface,
             xcb_surface,
             wayland_surface,
+            khr_display,
+            ext_headless_surface,
+            khr_surface_caps2,
             valid,
+            enabled: settings.enabled() && client::app_allowed(),
+            app_props: application_info_props(create_info.p_application_info),
+            debug_utils: app_extensions.contains(ext::DebugUtils::name()),
         },
     );
 
@@ -409,12 +836,42 @@ unsafe extern "system" fn pwcap_vkCreateInstance(
 }
 const _: vk::PFN_vkCreateInstance = pwcap_vkCreateInstance;
 
+unsafe fn application_info_props(info: *const vk::ApplicationInfo) -> Vec<(String, String)> {
+    let info = match info.as_ref() {
+        Some(v) => v,
+        None => return vec![],
+    };
+    [
+        (PROP_APPLICATION_NAME, info.p_application_name),
+        (PROP_ENGINE_NAME, info.p_engine_name),
+    ]
+    .into_iter()
+    .filter(|(_, name)| !name.is_null())
+    .map(|(key, name)| {
+        let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+        (key.to_owned(), name)
+    })
+    .filter(|(_, name)| !name.is_empty())
+    .collect()
+}
+
 #[named]
 unsafe fn destroy_instance(
     instance: vk::Instance,
     p_allocator: *const vk::AllocationCallbacks,
 ) -> Result<()> {
     debug!("destroying instance");
+    if *PASSTHROUGH {
+        let (_, gipa) = GIPA_MAP
+            .remove(&instance)
+            .ok_or(vk::Result::ERROR_UNKNOWN)?;
+        let name = CStr::from_bytes_with_nul_unchecked(b"vkDestroyInstance\0");
+        let next_destroy_instance: vk::PFN_vkDestroyInstance =
+            mem::transmute(gipa(instance, name.as_ptr()));
+        next_destroy_instance(instance, p_allocator);
+        return Ok(());
+    }
+    GIPA_MAP.remove(&instance);
     let (_, ly_instance) = INSTANCE_MAP
         .remove(&instance)
         .ok_or(vk::Result::ERROR_UNKNOWN)?;
@@ -445,14 +902,73 @@ const LAYER_DEVICE_EXTENSIONS: &[&CStr] = &[
     vk::KhrBindMemory2Fn::name(),
     vk::KhrImageFormatListFn::name(),
     vk::KhrMaintenance1Fn::name(),
+    vk::KhrMaintenance2Fn::name(),
     vk::KhrGetMemoryRequirements2Fn::name(),
     vk::KhrSamplerYcbcrConversionFn::name(),
+    vk::KhrSwapchainFn::name(),
+];
+
+// enabled if supported, frames are copied into shared memory instead of
+// exported as DMA-BUF without them
+const LAYER_DMA_BUF_DEVICE_EXTENSIONS: &[&CStr] = &[
     vk::ExtImageDrmFormatModifierFn::name(),
     vk::KhrExternalMemoryFn::name(),
     vk::KhrExternalMemoryFdFn::name(),
-    vk::KhrSwapchainFn::name(),
 ];
 
+// enabled if supported, for attaching sync file to exported buffers
+const LAYER_OPTIONAL_DEVICE_EXTENSIONS: &[&CStr] = &[
+    vk::KhrExternalSemaphoreFn::name(),
+    vk::KhrExternalSemaphoreFdFn::name(),
+];
+
+// enabled if supported, for stamping frames with the time they got presented
+const LAYER_PRESENT_TIMING_DEVICE_EXTENSIONS: &[&CStr] =
+    &[vk::KhrPresentIdFn::name(), vk::KhrPresentWaitFn::name()];
+
+unsafe fn supports_present_timing(
+    ly_instance_valid: &LayerInstanceValid,
+    phy_device: vk::PhysicalDevice,
+) -> bool {
+    let mut present_id = vk::PhysicalDevicePresentIdFeaturesKHR::default();
+    let mut present_wait = vk::PhysicalDevicePresentWaitFeaturesKHR::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut present_id)
+        .push_next(&mut present_wait);
+    ly_instance_valid
+        .khr_phy_props2
+        .get_physical_device_features2(phy_device, &mut features);
+    present_id.present_id == vk::TRUE && present_wait.present_wait == vk::TRUE
+}
+
+unsafe fn supports_calibrated_timestamps(
+    ly_instance_valid: &LayerInstanceValid,
+    phy_device: vk::PhysicalDevice,
+) -> bool {
+    ly_instance_valid
+        .ext_calibrated_timestamps
+        .get_physical_device_calibrateable_time_domains(phy_device)
+        .map_or(false, |domains| GpuClock::supports(&domains))
+}
+
+unsafe fn supports_sync_file_export(
+    ly_instance_valid: &LayerInstanceValid,
+    phy_device: vk::PhysicalDevice,
+) -> bool {
+    let khr_semaphore_caps = match ly_instance_valid.khr_semaphore_caps.as_ref() {
+        Some(v) => v,
+        None => return false,
+    };
+    let info = vk::PhysicalDeviceExternalSemaphoreInfo::builder()
+        .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+    let mut props = vk::ExternalSemaphoreProperties::default();
+    let get_props = khr_semaphore_caps.get_physical_device_external_semaphore_properties_khr;
+    get_props(phy_device, &*info, &mut props);
+    props
+        .external_semaphore_features
+        .contains(vk::ExternalSemaphoreFeatureFlags::EXPORTABLE)
+}
+
 #[no_mangle]
 #[named]
 unsafe extern "system" fn pwcap_vkCreateDevice(
@@ -463,6 +979,10 @@ unsafe extern "system" fn pwcap_vkCreateDevice(
 ) -> vk::Result {
     debug!("creating device");
 
+    if *PASSTHROUGH {
+        return passthrough_create_device(physical_device, p_create_info, p_allocator, p_device);
+    }
+
     let instance = *PHY_TO_INSTANCE_MAP.get(&physical_device).unwrap();
     let layer_instance = INSTANCE_MAP.get(&instance).unwrap();
     let ash_instance = &layer_instance.ash_instance;
@@ -483,27 +1003,159 @@ unsafe extern "system" fn pwcap_vkCreateDevice(
         .pfn_next_get_device_proc_addr
         .expect("broken layer info");
 
-    let mut extensions: HashSet<CString> = slice::from_raw_parts(
+    let app_extensions: HashSet<CString> = slice::from_raw_parts(
         create_info.pp_enabled_extension_names,
         create_info.enabled_extension_count as _,
     )
     .iter()
     .map(|&ptr| CStr::from_ptr(ptr).to_owned())
     .collect();
-    // extra extensions used by layer
-    for &name in LAYER_DEVICE_EXTENSIONS {
-        extensions.insert(name.to_owned());
-    }
-    debug!("{:?}", extensions);
-    let extensions_data: Vec<*const i8> = extensions.iter().map(|ext| ext.as_ptr()).collect();
-
-    let mut create_info_ext = create_info;
-    create_info_ext.enabled_extension_count = extensions_data.len() as _;
-    create_info_ext.pp_enabled_extension_names = extensions_data.as_ptr();
+    let supported_extensions: HashSet<CString> = ash_instance
+        .enumerate_device_extension_properties(physical_device)
+        .unwrap_or_default()
+        .iter()
+        .map(|props| CStr::from_ptr(props.extension_name.as_ptr()).to_owned())
+        .collect();
+    // capture is disabled without any of the extra extensions used by layer
+    let required = LAYER_DEVICE_EXTENSIONS
+        .iter()
+        .all(|&name| supported_extensions.contains(name));
+    let mut dma_buf = match layer_instance.valid.as_ref() {
+        Some(valid) => {
+            valid.external_memory_caps
+                && LAYER_DMA_BUF_DEVICE_EXTENSIONS
+                    .iter()
+                    .all(|&name| supported_extensions.contains(name))
+        }
+        None => false,
+    };
+    debug!("DMA-BUF export: {}", dma_buf);
+    let mut sync_file = match layer_instance.valid.as_ref() {
+        Some(valid) => {
+            LAYER_OPTIONAL_DEVICE_EXTENSIONS
+                .iter()
+                .all(|&name| supported_extensions.contains(name))
+                && supports_sync_file_export(valid, physical_device)
+        }
+        None => false,
+    };
+    debug!("sync file export: {}", sync_file);
+    // features might have been enabled by the app already
+    let app_present_id = find_in_chain::<vk::PhysicalDevicePresentIdFeaturesKHR>(
+        create_info.p_next,
+        vk::StructureType::PHYSICAL_DEVICE_PRESENT_ID_FEATURES_KHR,
+    );
+    let app_present_wait = find_in_chain::<vk::PhysicalDevicePresentWaitFeaturesKHR>(
+        create_info.p_next,
+        vk::StructureType::PHYSICAL_DEVICE_PRESENT_WAIT_FEATURES_KHR,
+    );
+    let mut present_timing = match layer_instance.valid.as_ref() {
+        Some(valid) => {
+            LAYER_PRESENT_TIMING_DEVICE_EXTENSIONS
+                .iter()
+                .all(|&name| supported_extensions.contains(name))
+                && supports_present_timing(valid, physical_device)
+                && match (app_present_id, app_present_wait) {
+                    (None, None) => true,
+                    (Some(id), Some(wait)) => {
+                        id.present_id == vk::TRUE && wait.present_wait == vk::TRUE
+                    }
+                    _ => false,
+                }
+        }
+        None => false,
+    };
+    debug!("present timing: {}", present_timing);
+    let mut calibrated_timestamps = match layer_instance.valid.as_ref() {
+        Some(valid) => {
+            supported_extensions.contains(ext::CalibratedTimestamps::name())
+                && supports_calibrated_timestamps(valid, physical_device)
+        }
+        None => false,
+    };
+    debug!("calibrated timestamps: {}", calibrated_timestamps);
+    let app_vk12_features = find_in_chain::<vk::PhysicalDeviceVulkan12Features>(
+        create_info.p_next,
+        vk::StructureType::PHYSICAL_DEVICE_VULKAN_1_2_FEATURES,
+    );
+    let app_timeline_features = find_in_chain::<vk::PhysicalDeviceTimelineSemaphoreFeatures>(
+        create_info.p_next,
+        vk::StructureType::PHYSICAL_DEVICE_TIMELINE_SEMAPHORE_FEATURES,
+    );
+    let timeline_semaphore = app_vk12_features.map(|v| v.timeline_semaphore) == Some(vk::TRUE)
+        || app_timeline_features.map(|v| v.timeline_semaphore) == Some(vk::TRUE);
+    debug!("timeline semaphore: {}", timeline_semaphore);
+
+    let mut present_id_features = vk::PhysicalDevicePresentIdFeaturesKHR {
+        p_next: create_info.p_next as _,
+        present_id: vk::TRUE,
+        ..Default::default()
+    };
+    let present_wait_features = vk::PhysicalDevicePresentWaitFeaturesKHR {
+        p_next: &mut present_id_features as *mut _ as _,
+        present_wait: vk::TRUE,
+        ..Default::default()
+    };
+    let create_device_ext = |dma_buf: bool,
+                             sync_file: bool,
+                             present_timing: bool,
+                             calibrated_timestamps: bool,
+                             p_device: *mut vk::Device| {
+        // extra extensions used by layer
+        let mut extensions = app_extensions.clone();
+        let mut layer_extensions = LAYER_DEVICE_EXTENSIONS.to_vec();
+        if dma_buf {
+            layer_extensions.extend(LAYER_DMA_BUF_DEVICE_EXTENSIONS);
+        }
+        if sync_file {
+            layer_extensions.extend(LAYER_OPTIONAL_DEVICE_EXTENSIONS);
+        }
+        if present_timing {
+            layer_extensions.extend(LAYER_PRESENT_TIMING_DEVICE_EXTENSIONS);
+        }
+        if calibrated_timestamps {
+            layer_extensions.push(ext::CalibratedTimestamps::name());
+        }
+        for name in layer_extensions {
+            extensions.insert(name.to_owned());
+        }
+        debug!("{:?}", extensions);
+        let extensions_data: Vec<*const i8> = extensions.iter().map(|ext| ext.as_ptr()).collect();
+
+        let mut create_info_ext = create_info;
+        create_info_ext.enabled_extension_count = extensions_data.len() as _;
+        create_info_ext.pp_enabled_extension_names = extensions_data.as_ptr();
+        if present_timing && app_present_id.is_none() {
+            create_info_ext.p_next = &present_wait_features as *const _ as _;
+        }
+        (instance_fn.create_device)(physical_device, &create_info_ext, p_allocator, p_device)
+    };
 
-    let res = (instance_fn.create_device)(physical_device, &create_info_ext, p_allocator, p_device);
+    let mut res = vk::Result::ERROR_EXTENSION_NOT_PRESENT;
+    if required {
+        res = create_device_ext(
+            dma_buf,
+            sync_file,
+            present_timing,
+            calibrated_timestamps,
+            p_device,
+        );
+        if res != vk::Result::SUCCESS
+            && (dma_buf || sync_file || present_timing || calibrated_timestamps)
+        {
+            // degraded, frames are copied into shared memory
+            warn!("failed to create device with optional layer extensions: {res}");
+            *p_device = vk::Device::null();
+            dma_buf = false;
+            sync_file = false;
+            present_timing = false;
+            calibrated_timestamps = false;
+            res = create_device_ext(false, false, false, false, p_device);
+        }
+    }
     let valid = res == vk::Result::SUCCESS;
     if !valid {
+        warn!("failed to create device with layer extensions, not capturing: {res}");
         *p_device = vk::Device::null();
         let res = (instance_fn.create_device)(physical_device, &create_info, p_allocator, p_device);
         if res != vk::Result::SUCCESS {
@@ -523,11 +1175,90 @@ unsafe extern "system" fn pwcap_vkCreateDevice(
 
     let khr_swapchain = khr::Swapchain::new(ash_instance, &ash_device);
 
+    let load_device_fn = |name: &CStr| -> *const c_void {
+        match dispatch_next_vkGetDeviceProcAddr(device, name.as_ptr()) {
+            Some(pfn) => pfn as _,
+            None => ptr::null(),
+        }
+    };
+    // core command or the one of the extension it got promoted from
+    let load_promoted_fn = |names: [&[u8]; 2]| {
+        names
+            .into_iter()
+            .map(|v| load_device_fn(CStr::from_bytes_with_nul_unchecked(v)))
+            .find(|pfn| !pfn.is_null())
+    };
+    let queue_submit2 = load_promoted_fn([b"vkQueueSubmit2\0", b"vkQueueSubmit2KHR\0"])
+        .map(|pfn| mem::transmute::<_, vk::PFN_vkQueueSubmit2>(pfn));
+    let (create_render_pass2, cmd_begin_render_pass2, cmd_pipeline_barrier2) =
+        if depth_capture().is_some() {
+            (
+                load_promoted_fn([b"vkCreateRenderPass2\0", b"vkCreateRenderPass2KHR\0"])
+                    .map(|pfn| mem::transmute::<_, vk::PFN_vkCreateRenderPass2>(pfn)),
+                load_promoted_fn([b"vkCmdBeginRenderPass2\0", b"vkCmdBeginRenderPass2KHR\0"])
+                    .map(|pfn| mem::transmute::<_, vk::PFN_vkCmdBeginRenderPass2>(pfn)),
+                load_promoted_fn([b"vkCmdPipelineBarrier2\0", b"vkCmdPipelineBarrier2KHR\0"])
+                    .map(|pfn| mem::transmute::<_, vk::PFN_vkCmdPipelineBarrier2>(pfn)),
+            )
+        } else {
+            (None, None, None)
+        };
+    let get_refresh_cycle_duration = if app_extensions.contains(vk::GoogleDisplayTimingFn::name()) {
+        let name = CStr::from_bytes_with_nul_unchecked(b"vkGetRefreshCycleDurationGOOGLE\0");
+        Some(load_device_fn(name))
+            .filter(|pfn| !pfn.is_null())
+            .map(|pfn| mem::transmute::<_, vk::PFN_vkGetRefreshCycleDurationGOOGLE>(pfn))
+    } else {
+        None
+    };
+    let set_debug_utils_object_name = if depth_capture().is_some() || layer_instance.debug_utils {
+        let name = CStr::from_bytes_with_nul_unchecked(b"vkSetDebugUtilsObjectNameEXT\0");
+        Some(load_device_fn(name))
+            .filter(|pfn| !pfn.is_null())
+            .map(|pfn| mem::transmute::<_, vk::PFN_vkSetDebugUtilsObjectNameEXT>(pfn))
+    } else {
+        None
+    };
+    let timeline = if timeline_semaphore {
+        Some(Arc::new(TimelineSemaphores::load(device, load_device_fn)))
+    } else {
+        None
+    };
+    let drm_devices = match layer_instance.valid.as_ref() {
+        Some(valid) if supported_extensions.contains(vk::ExtPhysicalDeviceDrmFn::name()) => {
+            get_drm_devices(&valid.khr_phy_props2, physical_device)
+        }
+        _ => vec![],
+    };
+    let host_export = !dma_buf || !client::DrmDevice::is_target(&drm_devices);
+    debug!("drm devices: {drm_devices:?}, host export: {host_export}");
+
     let valid = if valid {
         let khr_memfd = khr::ExternalMemoryFd::new(ash_instance, &ash_device);
+        let khr_semaphore_fd = if sync_file {
+            Some(khr::ExternalSemaphoreFd::new(ash_instance, &ash_device))
+        } else {
+            None
+        };
+        let khr_present_wait = if present_timing {
+            Some(khr::PresentWait::new(ash_instance, &ash_device))
+        } else {
+            None
+        };
+        let gpu_clock = if calibrated_timestamps {
+            let limits = ash_instance
+                .get_physical_device_properties(physical_device)
+                .limits;
+            GpuClock::load(device, load_device_fn, limits.timestamp_period).map(Arc::new)
+        } else {
+            None
+        };
         // let ext_modifier = ext::ImageDrmFormatModifier::new(ash_instance, &ash_device);
         Some(LayerDeviceValid {
             khr_memfd,
+            khr_semaphore_fd,
+            khr_present_wait,
+            gpu_clock,
             // ext_modifier,
         })
     } else {
@@ -570,6 +1301,16 @@ unsafe extern "system" fn pwcap_vkCreateDevice(
         }
     }
 
+    DISPATCH_MAP.insert(
+        *(device.as_raw() as *const usize),
+        DeviceDispatch {
+            device,
+            cmd_pipeline_barrier: ash_device.fp_v1_0().cmd_pipeline_barrier,
+            cmd_begin_render_pass: ash_device.fp_v1_0().cmd_begin_render_pass,
+            cmd_begin_render_pass2,
+            cmd_pipeline_barrier2,
+        },
+    );
     DEVICE_MAP.insert(
         device,
         LayerDevice {
@@ -577,7 +1318,17 @@ unsafe extern "system" fn pwcap_vkCreateDevice(
             phy_device: physical_device,
             ash_device,
             khr_swapchain,
+            queue_submit2,
+            create_render_pass2,
+            timeline,
+            get_refresh_cycle_duration,
+            drm_devices,
+            host_export,
+            allocator: Allocator::from_raw(p_allocator),
             queues,
+            depth_images: depth_capture().map(|_| DepthImages::default()),
+            set_debug_utils_object_name,
+            debug_utils: layer_instance.debug_utils,
             valid,
         },
     );
@@ -586,12 +1337,57 @@ unsafe extern "system" fn pwcap_vkCreateDevice(
 }
 const _: vk::PFN_vkCreateDevice = pwcap_vkCreateDevice;
 
+/// Creates a device without any layer state, only remembering the next
+/// `vkGetDeviceProcAddr` to dispatch to
+unsafe fn passthrough_create_device(
+    physical_device: vk::PhysicalDevice,
+    p_create_info: *const vk::DeviceCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_device: *mut vk::Device,
+) -> vk::Result {
+    let chain_info = get_device_chain_info(&*p_create_info, LayerFunction::LAYER_LINK_INFO);
+    let chain_info = if let Some(mut v) = chain_info {
+        v.as_mut()
+    } else {
+        return vk::Result::ERROR_INITIALIZATION_FAILED;
+    };
+
+    let layer_info = chain_info.u.p_layer_info.read();
+    chain_info.u.p_layer_info = layer_info.p_next;
+
+    let gipa = layer_info
+        .pfn_next_get_instance_proc_addr
+        .expect("broken layer info");
+    let gdpa = layer_info
+        .pfn_next_get_device_proc_addr
+        .expect("broken layer info");
+
+    let name = CStr::from_bytes_with_nul_unchecked(b"vkCreateDevice\0");
+    let create_device: vk::PFN_vkCreateDevice =
+        mem::transmute(gipa(vk::Instance::null(), name.as_ptr()));
+    let res = create_device(physical_device, p_create_info, p_allocator, p_device);
+    if res == vk::Result::SUCCESS {
+        GDPA_MAP.insert(*p_device, gdpa);
+    }
+    res
+}
+
 #[named]
 unsafe fn destroy_device(
     device: vk::Device,
     p_allocator: *const vk::AllocationCallbacks,
 ) -> Result<()> {
     debug!("destroying device");
+    if *PASSTHROUGH {
+        let (_, gdpa) = GDPA_MAP
+            .remove(&device)
+            .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+        let name = CStr::from_bytes_with_nul_unchecked(b"vkDestroyDevice\0");
+        let next_destroy_device: vk::PFN_vkDestroyDevice =
+            mem::transmute(gdpa(device, name.as_ptr()));
+        next_destroy_device(device, p_allocator);
+        return Ok(());
+    }
     GDPA_MAP.remove(&device);
     let (_, ly_device) = DEVICE_MAP
         .remove(&device)
@@ -600,6 +1396,10 @@ unsafe fn destroy_device(
     for queue in ly_device.queues {
         QUEUE_MAP.remove(&queue);
     }
+    DISPATCH_MAP.remove(&*(device.as_raw() as *const usize));
+    if let Some(timeline) = ly_device.timeline.as_ref() {
+        timeline.destroy();
+    }
 
     (ly_device.ash_device.fp_v1_0().destroy_device)(device, p_allocator);
     Ok(())
@@ -636,11 +1436,26 @@ unsafe extern "system" fn dispatch_next_vkGetInstanceProcAddr(
         };
         return mem::transmute(pfn);
     }
-    let gipa = GIPA.get()?;
+    // global commands, only loaded by ash::Entry, are never called
+    let gipa = next_gipa(instance)?;
     gipa(instance, p_name)
 }
 const _: vk::PFN_vkGetInstanceProcAddr = dispatch_next_vkGetInstanceProcAddr;
 
+/// Next `vkGetInstanceProcAddr` to query `instance` through, the one of the
+/// latest instance created or the loader's for null or unknown instances
+unsafe fn next_gipa(instance: vk::Instance) -> Option<vk::PFN_vkGetInstanceProcAddr> {
+    if let Some(gipa) = GIPA_MAP.get(&instance) {
+        return Some(*gipa);
+    }
+    if let Some(gipa) = *LAST_GIPA.lock().unwrap() {
+        return Some(gipa);
+    }
+    let name = CStr::from_bytes_with_nul_unchecked(b"vkGetInstanceProcAddr\0");
+    let gipa = libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr());
+    (!gipa.is_null()).then(|| mem::transmute(gipa))
+}
+
 #[no_mangle]
 unsafe extern "system" fn dispatch_next_vkGetDeviceProcAddr(
     device: vk::Device,
@@ -669,8 +1484,11 @@ unsafe fn init_surface(
     let mut wl_cursor_manager = 0;
     let cursor_manager: Option<Box<dyn CursorManager + Send + Sync>> = 'outer: {
         match raw_handle {
-            SurfaceRawHandle::Xlib { dpy: _, window } => {
-                let m = local_cursor::XcbWindow::new_connection(window as _);
+            SurfaceRawHandle::Xlib { dpy, window } => {
+                let m = match ptr::NonNull::new(dpy) {
+                    Some(dpy) => local_cursor::XcbWindow::for_xlib_display(dpy, window as _),
+                    None => local_cursor::XcbWindow::new_connection(window as _),
+                };
                 match m {
                     Ok(m) => break 'outer Some(Box::new(m)),
                     Err(e) => {
@@ -678,11 +1496,11 @@ unsafe fn init_surface(
                     }
                 }
             }
-            SurfaceRawHandle::Xcb {
-                connection: _,
-                window,
-            } => {
-                let m = local_cursor::XcbWindow::new_connection(window);
+            SurfaceRawHandle::Xcb { connection, window } => {
+                let m = match ptr::NonNull::new(connection) {
+                    Some(conn) => local_cursor::XcbWindow::for_xcb_connection(conn, window),
+                    None => local_cursor::XcbWindow::new_connection(window),
+                };
                 match m {
                     Ok(m) => break 'outer Some(Box::new(m)),
                     Err(e) => {
@@ -693,12 +1511,15 @@ unsafe fn init_surface(
             SurfaceRawHandle::Wayland { display, surface } => {
                 wl_cursor_manager = me_eh5_pw_capture_get_wl_cursor_manager(display, surface);
             }
+            // no window system to query cursor from
+            SurfaceRawHandle::Display { .. } | SurfaceRawHandle::Headless => (),
         };
         break 'outer None;
     };
 
     let ly_surface = LayerSurface {
         instance,
+        platform: raw_handle.into(),
         cursor_manager,
         wl_cursor_manager,
     };
@@ -807,6 +1628,64 @@ unsafe extern "system" fn pwcap_vkCreateWaylandSurfaceKHR(
 }
 const _: vk::PFN_vkCreateWaylandSurfaceKHR = pwcap_vkCreateWaylandSurfaceKHR;
 
+#[no_mangle]
+unsafe extern "system" fn pwcap_vkCreateDisplayPlaneSurfaceKHR(
+    instance: vk::Instance,
+    p_create_info: *const vk::DisplaySurfaceCreateInfoKHR,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_surface: *mut vk::SurfaceKHR,
+) -> vk::Result {
+    let ly_instance = if let Some(v) = INSTANCE_MAP.get(&instance) {
+        v
+    } else {
+        return vk::Result::ERROR_INITIALIZATION_FAILED;
+    };
+
+    let create_surface = ly_instance
+        .khr_display
+        .fp()
+        .create_display_plane_surface_khr;
+    let res = create_surface(instance, p_create_info, p_allocator, p_surface);
+    if res == vk::Result::SUCCESS {
+        let info = *p_create_info;
+        init_surface(
+            instance,
+            *p_surface,
+            SurfaceRawHandle::Display {
+                mode: info.display_mode,
+                plane_index: info.plane_index,
+            },
+        )
+    }
+    res
+}
+const _: vk::PFN_vkCreateDisplayPlaneSurfaceKHR = pwcap_vkCreateDisplayPlaneSurfaceKHR;
+
+#[no_mangle]
+unsafe extern "system" fn pwcap_vkCreateHeadlessSurfaceEXT(
+    instance: vk::Instance,
+    p_create_info: *const vk::HeadlessSurfaceCreateInfoEXT,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_surface: *mut vk::SurfaceKHR,
+) -> vk::Result {
+    let ly_instance = if let Some(v) = INSTANCE_MAP.get(&instance) {
+        v
+    } else {
+        return vk::Result::ERROR_INITIALIZATION_FAILED;
+    };
+
+    let create_surface = ly_instance
+        .ext_headless_surface
+        .fp()
+        .create_headless_surface_ext;
+    let res = create_surface(instance, p_create_info, p_allocator, p_surface);
+    if res == vk::Result::SUCCESS {
+        init_surface(instance, *p_surface, SurfaceRawHandle::Headless)
+    }
+    res
+}
+const _: vk::PFN_vkCreateHeadlessSurfaceEXT = pwcap_vkCreateHeadlessSurfaceEXT;
+
 #[no_mangle]
 unsafe extern "system" fn pwcap_vkDestroySurfaceKHR(
     instance: vk::Instance,
@@ -829,8 +1708,113 @@ unsafe extern "system" fn pwcap_vkDestroySurfaceKHR(
 }
 const _: vk::PFN_vkDestroySurfaceKHR = pwcap_vkDestroySurfaceKHR;
 
+/// Whether swapchain images of `format` can be copied into exported images of
+/// the same format
+unsafe fn is_exportable(
+    ly_instance: &LayerInstance,
+    phy_device: vk::PhysicalDevice,
+    format: vk::Format,
+) -> bool {
+    let valid = match ly_instance.valid.as_ref() {
+        Some(v) => v,
+        None => return false,
+    };
+    get_supported_modifiers(
+        &valid.khr_phy_props2,
+        phy_device,
+        format,
+        vk::ImageUsageFlags::TRANSFER_DST,
+        vk::FormatFeatureFlags::TRANSFER_DST,
+        vk::ImageCreateFlags::empty(),
+        &[],
+    )
+    .map_or(false, |modifiers| !modifiers.is_empty())
+}
+
+#[no_mangle]
 #[named]
-unsafe fn on_fixate_format(
+unsafe extern "system" fn pwcap_vkGetPhysicalDeviceSurfaceFormatsKHR(
+    physical_device: vk::PhysicalDevice,
+    surface: vk::SurfaceKHR,
+    p_surface_format_count: *mut u32,
+    p_surface_formats: *mut vk::SurfaceFormatKHR,
+) -> vk::Result {
+    let ly_instance = match PHY_TO_INSTANCE_MAP
+        .get(&physical_device)
+        .and_then(|instance| INSTANCE_MAP.get(&*instance))
+    {
+        Some(v) => v,
+        None => return vk::Result::ERROR_INITIALIZATION_FAILED,
+    };
+
+    let get_formats = ly_instance
+        .khr_surface
+        .fp()
+        .get_physical_device_surface_formats_khr;
+    let res = get_formats(
+        physical_device,
+        surface,
+        p_surface_format_count,
+        p_surface_formats,
+    );
+    if matches!(res, vk::Result::SUCCESS | vk::Result::INCOMPLETE) && !p_surface_formats.is_null() {
+        let formats = slice::from_raw_parts_mut(p_surface_formats, *p_surface_format_count as _);
+        sort_surface_formats(
+            formats,
+            |v| v.format,
+            |format| is_exportable(&ly_instance, physical_device, format),
+        );
+        debug!("surface formats: {:?}", formats);
+    }
+    res
+}
+const _: vk::PFN_vkGetPhysicalDeviceSurfaceFormatsKHR = pwcap_vkGetPhysicalDeviceSurfaceFormatsKHR;
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkGetPhysicalDeviceSurfaceFormats2KHR(
+    physical_device: vk::PhysicalDevice,
+    p_surface_info: *const vk::PhysicalDeviceSurfaceInfo2KHR,
+    p_surface_format_count: *mut u32,
+    p_surface_formats: *mut vk::SurfaceFormat2KHR,
+) -> vk::Result {
+    let ly_instance = match PHY_TO_INSTANCE_MAP
+        .get(&physical_device)
+        .and_then(|instance| INSTANCE_MAP.get(&*instance))
+    {
+        Some(v) => v,
+        None => return vk::Result::ERROR_INITIALIZATION_FAILED,
+    };
+
+    let get_formats = ly_instance
+        .khr_surface_caps2
+        .fp()
+        .get_physical_device_surface_formats2_khr;
+    let res = get_formats(
+        physical_device,
+        p_surface_info,
+        p_surface_format_count,
+        p_surface_formats,
+    );
+    if matches!(res, vk::Result::SUCCESS | vk::Result::INCOMPLETE) && !p_surface_formats.is_null() {
+        let formats = slice::from_raw_parts_mut(p_surface_formats, *p_surface_format_count as _);
+        sort_surface_formats(
+            formats,
+            |v| v.surface_format.format,
+            |format| is_exportable(&ly_instance, physical_device, format),
+        );
+        debug!(
+            "surface formats: {:?}",
+            formats.iter().map(|v| v.surface_format).collect::<Vec<_>>()
+        );
+    }
+    res
+}
+const _: vk::PFN_vkGetPhysicalDeviceSurfaceFormats2KHR =
+    pwcap_vkGetPhysicalDeviceSurfaceFormats2KHR;
+
+#[named]
+unsafe fn on_fixate_format(
     device: vk::Device,
     swapchain: vk::SwapchainKHR,
     info: client::EnumFormatInfo,
@@ -847,8 +1831,18 @@ unsafe fn on_fixate_format(
         .get_mut(&swapchain)
         .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
 
+    let export_extent = get_export_extent(ly_swapchain.extent);
+    let is_nv12 = info.formats[0] == client::Format::NV12;
     let transfer = vk_format_get_transfer(ly_swapchain.format);
-    let format_info = client_format_get_info(info.formats[0], transfer);
+    let format_info = if is_nv12 {
+        VkFormatInfo {
+            format: client::Format::NV12,
+            vk_format: NV12_FORMAT,
+            transfer,
+        }
+    } else {
+        client_format_get_info(info.formats[0], transfer)
+    };
     if format_info.vk_format == vk::Format::UNDEFINED {
         return Err(anyhow!(
             "format not supported: {:?} {:?}",
@@ -857,18 +1851,72 @@ unsafe fn on_fixate_format(
         ));
     }
 
-    let (modifier, num_planes) = if !info.modifiers.is_empty() {
-        let modifiers = get_supported_modifiers(
+    // NV12 is converted by a pipeline of its own
+    let copy_modes = if is_nv12 {
+        vec![]
+    } else {
+        get_copy_modes(
             &ly_instance_valid.khr_phy_props2,
             ly_device.phy_device,
+            ly_swapchain.format,
             format_info.vk_format,
-            vk::ImageUsageFlags::empty(),
-            vk::FormatFeatureFlags::TRANSFER_DST,
-        )?;
-        let modifiers = modifiers
-            .into_iter()
-            .filter(|props| info.modifiers.contains(&props.drm_format_modifier))
-            .collect::<Vec<_>>();
+            export_extent != ly_swapchain.extent,
+        )
+    };
+    let linear_features = {
+        let mut props = vk::FormatProperties2KHR::default();
+        ly_instance_valid
+            .khr_phy_props2
+            .get_physical_device_format_properties2(
+                ly_device.phy_device,
+                format_info.vk_format,
+                &mut props,
+            );
+        props.format_properties.linear_tiling_features
+    };
+
+    let (modifier, num_planes, copy_mode) = if !info.modifiers.is_empty() {
+        let modifier_filter =
+            ModifierFilter::for_device(&ly_instance_valid.khr_phy_props2, ly_device.phy_device);
+        let compatible = |modifiers: Vec<vk::DrmFormatModifierPropertiesEXT>| {
+            modifiers
+                .into_iter()
+                .filter(|props| {
+                    info.modifiers.contains(&props.drm_format_modifier)
+                        && modifier_filter.allows(props.drm_format_modifier)
+                })
+                .collect::<Vec<_>>()
+        };
+        let (modifiers, copy_mode) = if is_nv12 {
+            let modifiers = get_supported_modifiers(
+                &ly_instance_valid.khr_phy_props2,
+                ly_device.phy_device,
+                format_info.vk_format,
+                vk::ImageUsageFlags::STORAGE,
+                vk::FormatFeatureFlags::empty(),
+                NV12_IMAGE_FLAGS,
+                &NV12_VIEW_FORMATS,
+            )?;
+            (compatible(modifiers), None)
+        } else {
+            let mut res = (vec![], None);
+            for &(mode, features) in &copy_modes {
+                let modifiers = compatible(get_supported_modifiers(
+                    &ly_instance_valid.khr_phy_props2,
+                    ly_device.phy_device,
+                    format_info.vk_format,
+                    vk::ImageUsageFlags::empty(),
+                    features,
+                    vk::ImageCreateFlags::empty(),
+                    &[],
+                )?);
+                if !modifiers.is_empty() {
+                    res = (modifiers, Some(mode));
+                    break;
+                }
+            }
+            res
+        };
 
         debug!("filtered modifiers: {:?}", modifiers);
 
@@ -878,12 +1926,41 @@ unsafe fn on_fixate_format(
         (
             Some(modifier.drm_format_modifier),
             modifier.drm_format_modifier_plane_count,
+            copy_mode,
         )
     } else {
-        todo!("memfd")
+        // host image
+        let copy_mode = copy_modes
+            .iter()
+            .find(|(_, features)| linear_features.contains(*features))
+            .map(|&(mode, _)| mode);
+        (None, 1, copy_mode)
     };
+    if !is_nv12 && copy_mode.is_none() {
+        return Err(anyhow!(
+            "can not copy {:?} into {:?}",
+            ly_swapchain.format,
+            format_info.vk_format
+        ));
+    }
+    debug!("copy mode: {:?}", copy_mode);
 
-    let need_graphics = format_info.vk_format != ly_swapchain.format;
+    // consumers not importing DMA-BUFs get linear host images instead
+    let mem_fd_fallback = modifier.is_some()
+        && num_planes == 1
+        && copy_modes
+            .iter()
+            .any(|&(mode, features)| Some(mode) == copy_mode && linear_features.contains(features));
+
+    let use_indicator = client::indicator_enabled()
+        && ly_swapchain
+            .image_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_DST);
+    let need_depth = depth_capture().is_some();
+    // blits, clears and barriers of depth attachments are not available on
+    // transfer only queues
+    let need_graphics = use_indicator || need_depth || copy_mode == Some(CopyMode::Blit);
+    let need_compute = is_nv12 || matches!(copy_mode, Some(CopyMode::Swizzle(_)));
     let mut command_queue: Option<(vk::Queue, u32)> = None;
 
     for queue in &ly_device.queues {
@@ -892,7 +1969,17 @@ unsafe fn on_fixate_format(
         } else {
             continue;
         };
-        if need_graphics {
+        if need_compute {
+            let flags = if use_indicator || need_depth {
+                vk::QueueFlags::COMPUTE | vk::QueueFlags::GRAPHICS
+            } else {
+                vk::QueueFlags::COMPUTE
+            };
+            if ly_queue.family_props.queue_flags.contains(flags) {
+                command_queue = Some((*queue, ly_queue.family_index));
+                break;
+            }
+        } else if need_graphics {
             if ly_queue
                 .family_props
                 .queue_flags
@@ -917,50 +2004,158 @@ unsafe fn on_fixate_format(
         }
     }
     let (queue, queue_family_index) = command_queue.ok_or(anyhow!("no compatible queue"))?;
-
-    let (command_pool, command_buffers) = 'outer: {
-        if let Some(data) = ly_swapchain.export_data.take() {
-            if data.queue == queue && data.command_buffers.len() >= ly_swapchain.images.len() {
-                break 'outer (data.command_pool, data.command_buffers);
+    let timestamp_valid_bits = QUEUE_MAP
+        .get(&queue)
+        .map_or(0, |ly_queue| ly_queue.family_props.timestamp_valid_bits);
+
+    let copy_cache = 'outer: {
+        if let Some(mut data) = ly_swapchain.export_data.take() {
+            if let Some(converter) = data.nv12.take() {
+                // conversion might still be in flight
+                let _ = ly_device.ash_device.queue_wait_idle(data.queue);
+                converter.destroy(&ly_device.ash_device);
             }
-            ly_device
-                .ash_device
-                .free_command_buffers(data.command_pool, &data.command_buffers);
-            ly_device
-                .ash_device
-                .destroy_command_pool(data.command_pool, None);
+            if let Some(converter) = data.swizzle.take() {
+                let _ = ly_device.ash_device.queue_wait_idle(data.queue);
+                converter.destroy(&ly_device.ash_device);
+            }
+            if let Some(post_processor) = data.post_processor.take() {
+                let _ = ly_device.ash_device.queue_wait_idle(data.queue);
+                post_processor.destroy(&ly_device.ash_device);
+            }
+            if let Some(indicator) = data.indicator.take() {
+                // drawing might still be in flight
+                let _ = ly_device.ash_device.queue_wait_idle(data.queue);
+                indicator.destroy(&ly_device.ash_device);
+            }
+            if let Some(copier) = data.depth_copier.take() {
+                let _ = ly_device.ash_device.queue_wait_idle(data.queue);
+                copier.destroy(&ly_device.ash_device);
+            }
+            // recorded commands refer to converters of the previous format
+            let mut copy_cache = data.copy_cache.into_inner().unwrap();
+            copy_cache.invalidate();
+            if data.queue == queue {
+                break 'outer copy_cache;
+            }
+            // copies might still be in flight
+            let _ = ly_device.ash_device.queue_wait_idle(data.queue);
+            copy_cache.destroy(&ly_device.ash_device);
         }
-        let cmd_pool_info = vk::CommandPoolCreateInfo::builder()
-            .queue_family_index(queue_family_index)
-            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
-        let cmd_pool = ly_device
-            .ash_device
-            .create_command_pool(&cmd_pool_info, None)?;
-        let cmd_buffers_info = vk::CommandBufferAllocateInfo::builder()
-            .command_pool(cmd_pool)
-            .level(vk::CommandBufferLevel::PRIMARY)
-            .command_buffer_count(ly_swapchain.images.len() as _);
-        let cmd_buffers = ly_device
-            .ash_device
-            .allocate_command_buffers(&cmd_buffers_info)?;
-        break 'outer (cmd_pool, cmd_buffers);
+        let copy_cache = CopyCache::new(
+            &ly_device.ash_device,
+            queue_family_index,
+            COPY_COMMANDS_PER_IMAGE,
+            ly_device.allocator,
+        )?;
+        ly_device.set_object_name(copy_cache.command_pool(), COPY_COMMANDS_NAME);
+        break 'outer copy_cache;
+    };
+
+    let nv12 = if is_nv12 {
+        Some(Nv12Converter::new(
+            &ly_instance.ash_instance,
+            &ly_device.ash_device,
+            ly_device.phy_device,
+            ly_swapchain.format,
+            ly_swapchain.extent.width,
+            ly_swapchain.extent.height,
+            MAX_BUFFERS,
+            ly_device.allocator,
+        )?)
+    } else {
+        None
+    };
+
+    let swizzle = match copy_mode {
+        Some(CopyMode::Swizzle(swizzle)) => Some(SwizzleConverter::new(
+            &ly_instance.ash_instance,
+            &ly_device.ash_device,
+            ly_device.phy_device,
+            swizzle,
+            ly_swapchain.extent.width,
+            ly_swapchain.extent.height,
+            ly_device.allocator,
+        )?),
+        _ => None,
+    };
+
+    let export_region = get_export_region(ly_swapchain.extent);
+    let post_processor = match client::post_process() {
+        Some(_) if nv12.is_some() => {
+            warn!("frames exported as NV12 are not post-processed");
+            None
+        }
+        Some(config) => PostProcessor::new(
+            &ly_instance.ash_instance,
+            &ly_device.ash_device,
+            ly_device.phy_device,
+            config,
+            format_info.vk_format,
+            export_region,
+            queue_family_index,
+            ly_swapchain.images.len() as _,
+            ly_device.allocator,
+        )
+        .map_err(|e| warn!("frames not post-processed: {e:?}"))
+        .ok(),
+        None => None,
+    };
+
+    let indicator = if use_indicator {
+        Indicator::new(
+            &ly_instance.ash_instance,
+            &ly_device.ash_device,
+            ly_device.phy_device,
+            ly_swapchain.format,
+            queue_family_index,
+            ly_swapchain.images.len() as _,
+            ly_device.allocator,
+        )
+        .map_err(|e| warn!("capture indicator not available: {e:?}"))
+        .ok()
+    } else {
+        None
+    };
+
+    let depth_copier = if need_depth {
+        DepthCopier::new(
+            &ly_device.ash_device,
+            queue_family_index,
+            ly_swapchain.images.len(),
+            ly_device.allocator,
+        )
+        .map_err(|e| warn!("depth capture not available: {e:?}"))
+        .ok()
+    } else {
+        None
     };
 
     info!("stream format fixated: {:?}", format_info);
 
     ly_swapchain.export_data = Some(ExportData {
+        src_format: ly_swapchain.format,
+        extent: ly_swapchain.extent,
+        export_extent,
+        export_region,
         format: format_info.vk_format,
         queue,
         queue_family_index,
-        command_pool,
-        command_buffers,
+        timestamp_valid_bits,
+        copy_cache: Mutex::new(copy_cache),
         modifier,
         num_planes,
+        nv12,
+        swizzle,
+        post_processor,
+        indicator,
+        depth_copier,
     });
 
     Ok(client::FixateFormat {
         modifier,
         num_planes,
+        mem_fd_fallback,
     })
 }
 
@@ -968,8 +2163,9 @@ unsafe fn on_fixate_format(
 unsafe fn on_add_buffer(
     device: vk::Device,
     swapchain: vk::SwapchainKHR,
+    data_type: client::BufferDataType,
 ) -> Result<client::BufferInfo> {
-    debug!("on_add_buffer");
+    debug!("on_add_buffer, {:?}", data_type);
     let ly_device = DEVICE_MAP
         .get(&device)
         .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
@@ -987,18 +2183,62 @@ unsafe fn on_add_buffer(
         .ok_or(anyhow!("no format fixated"))?;
     let export_format = export_data.format;
 
-    if let Some(modifier) = export_data.modifier {
+    let (usage, flags, view_formats) = if export_data.nv12.is_some() {
+        (
+            vk::ImageUsageFlags::STORAGE,
+            NV12_IMAGE_FLAGS,
+            &NV12_VIEW_FORMATS[..],
+        )
+    } else {
+        (
+            vk::ImageUsageFlags::empty(),
+            vk::ImageCreateFlags::empty(),
+            &[][..],
+        )
+    };
+
+    let modifier = match (export_data.modifier, data_type) {
+        (Some(modifier), client::BufferDataType::DmaBuf) => Some(modifier),
+        _ if export_data.nv12.is_some() => {
+            return Err(anyhow!("NV12 is only exported as DMA-BUF"));
+        }
+        _ => None,
+    };
+
+    if let Some(modifier) = modifier {
         let (image, memory, fds) = create_target_image(
             &ly_instance.ash_instance,
             &ly_device.ash_device,
             &ly_device_valid.khr_memfd,
             ly_device.phy_device,
             export_format,
-            ly_swapchain.extent.width,
-            ly_swapchain.extent.height,
+            export_data.export_extent.width,
+            export_data.export_extent.height,
             modifier,
             export_data.num_planes,
+            usage,
+            flags,
+            view_formats,
+            ly_device.allocator.callbacks(),
         )?;
+        ly_device.set_object_name(image, EXPORT_IMAGE_NAME);
+        ly_device.set_object_name(memory, EXPORT_MEMORY_NAME);
+
+        let nv12_target = match export_data.nv12.as_ref() {
+            Some(converter) => match converter.create_target(&ly_device.ash_device, image) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    let allocator = ly_device.allocator.callbacks();
+                    ly_device.ash_device.destroy_image(image, allocator);
+                    for (fd, _) in fds {
+                        libc::close(fd);
+                    }
+                    ly_device.ash_device.free_memory(memory, allocator);
+                    return Err(e);
+                }
+            },
+            None => None,
+        };
 
         let plane_size = fds[0].1.size;
         assert!(plane_size > 0);
@@ -1023,6 +2263,12 @@ unsafe fn on_add_buffer(
                 memory,
                 fds,
                 src_image: (vk::Image::null(), 0),
+                present_pts: None,
+                timestamp_pool: create_timestamp_pool(&ly_device),
+                sync_file_attached: false,
+                nv12_target,
+                host_map: None,
+                frame_seq: None,
             },
         );
 
@@ -1032,10 +2278,73 @@ unsafe fn on_add_buffer(
             user_handle: client::BufferUserHandle::VkImage(image),
         })
     } else {
-        todo!()
+        let (host_image, memfd) = create_host_image(
+            &ly_instance.ash_instance,
+            &ly_device.ash_device,
+            ly_device.phy_device,
+            export_format,
+            export_data.export_extent.width,
+            export_data.export_extent.height,
+            ly_device.allocator.callbacks(),
+        )?;
+        let HostImage {
+            image,
+            memory,
+            layout,
+            map,
+        } = host_image;
+        debug!("memfd: {}, layout: {:?}", memfd, layout);
+        ly_device.set_object_name(image, EXPORT_IMAGE_NAME);
+        ly_device.set_object_name(memory, EXPORT_MEMORY_NAME);
+
+        let planes = vec![client::BufferPlaneInfo {
+            fd: memfd as _,
+            offset: 0,
+            size: layout.size as _,
+            stride: layout.row_pitch as _,
+        }];
+
+        ly_swapchain.export_images.insert(
+            image,
+            ExportImage {
+                format: export_format,
+                image,
+                memory,
+                fds: vec![(memfd, layout)],
+                src_image: (vk::Image::null(), 0),
+                present_pts: None,
+                timestamp_pool: create_timestamp_pool(&ly_device),
+                sync_file_attached: false,
+                nv12_target: None,
+                host_map: Some(map),
+                frame_seq: None,
+            },
+        );
+
+        Ok(client::BufferInfo {
+            is_dma_buf: false,
+            planes,
+            user_handle: client::BufferUserHandle::VkImage(image),
+        })
     }
 }
 
+/// Query pool of a single timestamp, if GPU timestamps can be calibrated
+#[named]
+unsafe fn create_timestamp_pool(ly_device: &LayerDevice) -> Option<vk::QueryPool> {
+    ly_device.valid.as_ref()?.gpu_clock.as_ref()?;
+    let create_info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::TIMESTAMP)
+        .query_count(1);
+    let query_pool = ly_device
+        .ash_device
+        .create_query_pool(&create_info, ly_device.allocator.callbacks())
+        .map_err(|e| warn!("failed to create timestamp query pool: {e:?}"))
+        .ok()?;
+    ly_device.set_object_name(query_pool, TIMESTAMP_POOL_NAME);
+    Some(query_pool)
+}
+
 #[named]
 unsafe fn on_remove_buffer(
     device: vk::Device,
@@ -1056,18 +2365,44 @@ unsafe fn on_remove_buffer(
         .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
 
     let ExportImage {
-        image, memory, fds, ..
+        image,
+        memory,
+        fds,
+        nv12_target,
+        host_map,
+        timestamp_pool,
+        ..
     } = ly_swapchain
         .export_images
         .remove(&image)
         .ok_or(vk::Result::ERROR_UNKNOWN)?
         .1;
+    if let Some(data) = ly_swapchain.export_data.as_ref() {
+        data.copy_cache.lock().unwrap().forget(image);
+    }
+
+    if let Some(target) = nv12_target {
+        if let Some(converter) = ly_swapchain
+            .export_data
+            .as_ref()
+            .and_then(|data| data.nv12.as_ref())
+        {
+            converter.destroy_target(&ly_device.ash_device, target);
+        }
+    }
 
-    ly_device.ash_device.destroy_image(image, None);
+    let allocator = ly_device.allocator.callbacks();
+    ly_device.ash_device.destroy_image(image, allocator);
+    if let Some(pool) = timestamp_pool {
+        ly_device.ash_device.destroy_query_pool(pool, allocator);
+    }
+    if let Some(map) = host_map {
+        map.unmap_memfd();
+    }
     for (fd, _) in fds {
         libc::close(fd);
     }
-    ly_device.ash_device.free_memory(memory, None);
+    ly_device.ash_device.free_memory(memory, allocator);
 
     Ok(())
 }
@@ -1093,10 +2428,16 @@ unsafe fn on_process_buffer(
         .get(&swapchain)
         .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
 
-    let (src_image, seq) = {
+    let (src_image, seq, present_pts, frame_seq, sync_file_attached) = {
         let export_image = ly_swapchain.export_images.get(&image);
         if let Some(v) = export_image {
-            v.src_image
+            (
+                v.src_image.0,
+                v.src_image.1,
+                v.present_pts,
+                v.frame_seq,
+                v.sync_file_attached,
+            )
         } else {
             debug!("buffer already removed");
             return Ok(());
@@ -1121,42 +2462,149 @@ unsafe fn on_process_buffer(
                 atomic::Ordering::AcqRel,
                 atomic::Ordering::Acquire,
             );
+            let export_region = match ly_swapchain.export_data.as_ref() {
+                Some(data) => data.export_region,
+                None => get_export_region(ly_swapchain.extent),
+            };
             snap.as_cursor_info(old_serial != snap.serial())
+                .map(|info| {
+                    info.scale_position(
+                        (ly_swapchain.extent.width, ly_swapchain.extent.height),
+                        client::Region {
+                            x: export_region.offset.x as _,
+                            y: export_region.offset.y as _,
+                            width: export_region.extent.width,
+                            height: export_region.extent.height,
+                        },
+                    )
+                })
                 .map(add_cursor);
         }
     }
 
-    let mut data = ly_swapchain
-        .image_datas
-        .get_mut(&src_image)
-        .ok_or(anyhow!("src image removed"))?;
+    if let (Some(add_crop), Some(data)) = (add_meta_cbs.add_crop, ly_swapchain.export_data.as_ref())
+    {
+        // frames are shown without letterboxing and parts outside the surface
+        let region = data.export_region;
+        let (extent, visible) = (ly_swapchain.extent, ly_swapchain.visible_extent);
+        let crop = client::Region {
+            x: region.offset.x as _,
+            y: region.offset.y as _,
+            width: region.extent.width,
+            height: region.extent.height,
+        }
+        .visible(
+            (extent.width, extent.height),
+            (visible.width, visible.height),
+        );
+        if (crop.width, crop.height) != (data.export_extent.width, data.export_extent.height) {
+            add_crop(crop);
+        }
+    }
+
+    if let (Some(set_pts), Some(present_pts)) = (add_meta_cbs.set_pts, present_pts) {
+        set_pts(present_pts);
+    }
+    if let (Some(set_seq), Some(frame_seq)) = (add_meta_cbs.set_seq, frame_seq) {
+        set_seq(frame_seq);
+    }
+
+    // consumer waits for the copy on GPU
+    if sync_file_attached {
+        return Ok(());
+    }
+
+    // images of a retired swapchain are gone along with their copies
+    let data = match ly_swapchain.image_datas.get(&src_image) {
+        Some(v) => v,
+        None => return Ok(()),
+    };
 
-    trace!("src image seq: {}, export image seq: {}", data.seq, seq);
-    if seq == data.seq {
-        data.fence.wait_and_reset(&ly_device.ash_device)?;
+    trace!(
+        "src image seq: {}, export image seq: {seq}",
+        data.sync.seq()
+    );
+    // the worker waited for the copy before queuing the frame, never block
+    // the data thread on it
+    if !data.sync.is_complete(&ly_device.ash_device, seq)? {
+        debug!("copy into {image:?} still pending");
     }
 
     Ok(())
 }
 
+fn get_export_extent(extent: vk::Extent2D) -> vk::Extent2D {
+    let (width, height) = client::Scale::global().apply(extent.width, extent.height);
+    vk::Extent2D { width, height }
+}
+
+fn get_export_region(extent: vk::Extent2D) -> vk::Rect2D {
+    let region = client::Scale::global().region(extent.width, extent.height);
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: region.x as _,
+            y: region.y as _,
+        },
+        extent: vk::Extent2D {
+            width: region.width,
+            height: region.height,
+        },
+    }
+}
+
+/// How swapchain images get copied into export images
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CopyMode {
+    Copy,
+    /// Converts format and scales
+    Blit,
+    /// Swaps channels of formats the device can not blit between
+    Swizzle(Swizzle),
+}
+
+/// Ways swapchain images of `src_format` can be copied into export images of
+/// `format` in order of preference, along with format features export images
+/// require for each
+unsafe fn get_copy_modes(
+    khr_phy_props2: &khr::GetPhysicalDeviceProperties2,
+    phy_device: vk::PhysicalDevice,
+    src_format: vk::Format,
+    format: vk::Format,
+    scaled: bool,
+) -> Vec<(CopyMode, vk::FormatFeatureFlags)> {
+    if src_format == format && !scaled {
+        return vec![(CopyMode::Copy, vk::FormatFeatureFlags::TRANSFER_DST)];
+    }
+    let mut modes = vec![];
+    let mut props = vk::FormatProperties2KHR::default();
+    khr_phy_props2.get_physical_device_format_properties2(phy_device, src_format, &mut props);
+    if props
+        .format_properties
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::BLIT_SRC)
+    {
+        modes.push((CopyMode::Blit, vk::FormatFeatureFlags::BLIT_DST));
+    }
+    if let Some(swizzle) = get_swizzle(src_format, format).filter(|_| !scaled) {
+        modes.push((
+            CopyMode::Swizzle(swizzle),
+            vk::FormatFeatureFlags::TRANSFER_DST,
+        ));
+    }
+    modes
+}
+
 #[named]
-unsafe fn create_stream(
+unsafe fn get_enum_formats(
     khr_phy_props2: &khr::GetPhysicalDeviceProperties2,
     phy_device: vk::PhysicalDevice,
-    device: vk::Device,
-    swapchain: vk::SwapchainKHR,
     swapchain_format: vk::Format,
-    width: u32,
-    height: u32,
-) -> Result<client::Stream> {
+    extent: vk::Extent2D,
+    host_export: bool,
+) -> Result<Vec<client::EnumFormatInfo>> {
     let src_format_info = vk_format_get_info(swapchain_format);
     // TODO: check if swapchain format is valid, e.g. supports TRANSFER_SRC
 
-    info!(
-        "creating stream, extent: {}x{} format: {:?}",
-        width, height, src_format_info
-    );
-
     let formats: Vec<VkFormatInfo> = if src_format_info.format == client::Format::UNKNOWN {
         VK_FORMAT_INFO_TABLE
             .iter()
@@ -1174,32 +2622,76 @@ unsafe fn create_stream(
         core::iter::once(src_format_info).chain(it).collect()
     };
 
-    // XXX: support for YUV formats with shader conversion?
+    let scaled = get_export_extent(extent) != extent;
+    let alpha_mode = AlphaMode::global();
+
+    // only formats frames can be copied into are offered
+    let copy_modes = formats
+        .iter()
+        .map(|format_info| {
+            get_copy_modes(
+                khr_phy_props2,
+                phy_device,
+                swapchain_format,
+                format_info.vk_format,
+                scaled,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // copied through linear images, offered without modifiers
+    let host_formats = formats
+        .iter()
+        .zip(&copy_modes)
+        .filter(|(format_info, copy_modes)| {
+            let mut props = vk::FormatProperties2KHR::default();
+            khr_phy_props2.get_physical_device_format_properties2(
+                phy_device,
+                format_info.vk_format,
+                &mut props,
+            );
+            copy_modes.iter().any(|&(_, features)| {
+                props
+                    .format_properties
+                    .linear_tiling_features
+                    .contains(features)
+            })
+        })
+        .map(|(format_info, _)| format_info)
+        .map(|format_info| alpha_mode.apply(format_info.format))
+        .collect::<Vec<_>>();
+    debug!("host formats, {:?}", host_formats);
+    let host_enum_format = (!host_formats.is_empty()).then_some(client::EnumFormatInfo {
+        formats: host_formats,
+        modifiers: vec![],
+    });
+
+    if host_export {
+        return Ok(host_enum_format.into_iter().collect());
+    }
 
+    let modifier_filter = ModifierFilter::for_device(khr_phy_props2, phy_device);
     let mut enum_formats = Vec::<client::EnumFormatInfo>::new();
 
-    'outer: for format_info in &formats {
-        let (usage, features) = if src_format_info.vk_format == format_info.vk_format {
-            (
-                vk::ImageUsageFlags::TRANSFER_DST,
-                vk::FormatFeatureFlags::TRANSFER_DST,
-            )
-        } else {
-            (
+    'outer: for (format_info, copy_modes) in formats.iter().zip(&copy_modes) {
+        let mut modifiers = Vec::<u64>::new();
+        for &(_, features) in copy_modes {
+            let supported = get_supported_modifiers(
+                khr_phy_props2,
+                phy_device,
+                format_info.vk_format,
                 vk::ImageUsageFlags::TRANSFER_DST,
-                vk::FormatFeatureFlags::BLIT_DST,
-            )
-        };
-        let mut modifiers = get_supported_modifiers(
-            khr_phy_props2,
-            phy_device,
-            format_info.vk_format,
-            usage,
-            features,
-        )?
-        .into_iter()
-        .map(|props| props.drm_format_modifier)
-        .collect::<Vec<_>>();
+                features,
+                vk::ImageCreateFlags::empty(),
+                &[],
+            )?;
+            for props in supported {
+                let modifier = props.drm_format_modifier;
+                if modifier_filter.allows(modifier) && !modifiers.contains(&modifier) {
+                    modifiers.push(modifier);
+                }
+            }
+        }
 
         if modifiers.is_empty() {
             debug!("does not support export modifier, {:?}", format_info);
@@ -1215,59 +2707,430 @@ unsafe fn create_stream(
             modifiers.insert(0, default);
         }
 
+        let format = alpha_mode.apply(format_info.format);
         for enum_format in &mut enum_formats {
             if enum_format.modifiers == modifiers {
-                enum_format.formats.push(format_info.format);
+                enum_format.formats.push(format);
                 continue 'outer;
             }
         }
 
         let enum_format = client::EnumFormatInfo {
-            formats: vec![format_info.format],
+            formats: vec![format],
             modifiers,
         };
         enum_formats.push(enum_format);
     }
 
-    for _format_info in &formats {
-        // TODO: memfd or linear dma-buf
+    // converted with compute shader, chroma planes are subsampled
+    if nv12_src_swap_rb(swapchain_format).is_some()
+        && !scaled
+        && extent.width % 2 == 0
+        && extent.height % 2 == 0
+    {
+        let mut modifiers = get_supported_modifiers(
+            khr_phy_props2,
+            phy_device,
+            NV12_FORMAT,
+            vk::ImageUsageFlags::STORAGE,
+            vk::FormatFeatureFlags::empty(),
+            NV12_IMAGE_FLAGS,
+            &NV12_VIEW_FORMATS,
+        )?
+        .into_iter()
+        .map(|props| props.drm_format_modifier)
+        .filter(|&modifier| modifier_filter.allows(modifier))
+        .collect::<Vec<_>>();
+
+        if let Some(idx) = modifiers.iter().position(|&modifier| modifier == 0) {
+            let default = modifiers.remove(idx);
+            modifiers.insert(0, default);
+        }
+        if modifiers.is_empty() {
+            debug!("does not support exporting NV12");
+        } else {
+            enum_formats.push(client::EnumFormatInfo {
+                formats: vec![client::Format::NV12],
+                modifiers,
+            });
+        }
     }
 
+    // last resort of consumers not importing DMA-BUFs
+    enum_formats.extend(host_enum_format);
+
     debug!("added formats, {:?}", enum_formats);
 
-    let stream_info = client::StreamInfo {
-        width,
-        height,
+    Ok(enum_formats)
+}
+
+#[named]
+unsafe fn create_stream(
+    khr_phy_props2: &khr::GetPhysicalDeviceProperties2,
+    phy_device: vk::PhysicalDevice,
+    device: vk::Device,
+    target: StreamTarget,
+    streaming: Arc<AtomicBool>,
+    swapchain_format: vk::Format,
+    color_space: vk::ColorSpaceKHR,
+    transform: client::Transform,
+    width: u32,
+    height: u32,
+    max_buffers: u32,
+    host_export: bool,
+    props: Vec<(String, String)>,
+) -> Result<client::Stream> {
+    info!(
+        "creating stream, extent: {}x{} format: {:?}",
+        width,
+        height,
+        vk_format_get_info(swapchain_format)
+    );
+
+    let enum_formats = get_enum_formats(
+        khr_phy_props2,
+        phy_device,
+        swapchain_format,
+        vk::Extent2D { width, height },
+        host_export,
+    )?;
+
+    let colorimetry = vk_color_space_get_colorimetry(color_space);
+    debug!(
+        "color space: {:?}, colorimetry: {:?}",
+        color_space, colorimetry
+    );
+
+    let stream_info = client::StreamInfo {
+        width,
+        height,
         enum_formats,
-        max_buffers: MAX_BUFFERS,
-        fixate_format: Box::new(move |format| {
-            on_fixate_format(device, swapchain, format)
-                .map_err(|e| map_err!(e))
-                .ok()
+        colorimetry,
+        transform,
+        scale: client::Scale::global(),
+        max_buffers,
+        format_preference: client::FormatPreference::global(),
+        node_class: client::NodeClass::global(),
+        props,
+        fixate_format: Box::new({
+            let target = target.clone();
+            move |format| {
+                on_fixate_format(device, target.get(), format)
+                    .map_err(|e| map_err!(e))
+                    .ok()
+            }
         }),
-        add_buffer: Box::new(move || {
-            on_add_buffer(device, swapchain)
-                .map_err(|e| map_err!(e))
-                .ok()
+        add_buffer: Box::new({
+            let target = target.clone();
+            move |data_type| on_add_buffer(device, target.get(), data_type)
+        }),
+        remove_buffer: Box::new({
+            let target = target.clone();
+            move |user_handle| {
+                let _ =
+                    on_remove_buffer(device, target.get(), user_handle).map_err(|e| map_err!(e));
+            }
         }),
-        remove_buffer: Box::new(move |user_handle| {
-            let _ = on_remove_buffer(device, swapchain, user_handle).map_err(|e| map_err!(e));
+        process_buffer: Box::new({
+            let target = target.clone();
+            move |user_handle, add_meta_cbs| {
+                let _ = on_process_buffer(device, target.get(), user_handle, add_meta_cbs)
+                    .map_err(|e| map_err!(e));
+            }
         }),
-        process_buffer: Box::new(move |user_handle, add_meta_cbs| {
-            let _ = on_process_buffer(device, swapchain, user_handle, add_meta_cbs)
-                .map_err(|e| map_err!(e));
+        state_changed: Box::new(move |state, node_id| {
+            info!("{:?} stream node {:?}: {:?}", target.get(), node_id, state);
+            streaming.store(
+                state == client::StreamState::Streaming,
+                atomic::Ordering::Release,
+            );
         }),
     };
 
     let stream = CLIENT
         .as_ref()
         .ok_or(anyhow!("failed to get client"))?
-        .proxy()
-        .try_create_stream(stream_info)???;
+        .create_stream(stream_info)?;
 
     Ok(stream)
 }
 
+#[named]
+unsafe fn on_add_depth_buffer(
+    device: vk::Device,
+    swapchain: vk::SwapchainKHR,
+    buffers: &DashMap<vk::Buffer, DepthBuffer>,
+    data_type: client::BufferDataType,
+) -> Result<client::BufferInfo> {
+    debug!("on_add_depth_buffer, {:?}", data_type);
+    if data_type != client::BufferDataType::MemFd {
+        return Err(anyhow!("depth is only exported in shared memory"));
+    }
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let ly_instance = INSTANCE_MAP
+        .get(&ly_device.instance)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let extent = SWAPCHAIN_MAP
+        .get(&swapchain)
+        .ok_or(vk::Result::ERROR_UNKNOWN)?
+        .extent;
+
+    let depth_buffer = DepthBuffer::new(
+        &ly_instance.ash_instance,
+        &ly_device.ash_device,
+        ly_device.phy_device,
+        extent,
+        ly_device.allocator.callbacks(),
+    )?;
+    let buffer = depth_buffer.buffer;
+    let planes = vec![client::BufferPlaneInfo {
+        fd: depth_buffer.memfd as _,
+        offset: 0,
+        size: depth_buffer.size() as _,
+        stride: depth_buffer.stride(),
+    }];
+    buffers.insert(buffer, depth_buffer);
+
+    Ok(client::BufferInfo {
+        is_dma_buf: false,
+        planes,
+        user_handle: client::BufferUserHandle::VkBuffer(buffer),
+    })
+}
+
+#[named]
+unsafe fn on_remove_depth_buffer(
+    device: vk::Device,
+    buffers: &DashMap<vk::Buffer, DepthBuffer>,
+    user_handle: client::BufferUserHandle,
+) -> Result<()> {
+    debug!("on_remove_depth_buffer");
+    let buffer = match user_handle {
+        client::BufferUserHandle::VkBuffer(buffer) => buffer,
+        _ => unreachable!(),
+    };
+
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let (_, depth_buffer) = buffers.remove(&buffer).ok_or(vk::Result::ERROR_UNKNOWN)?;
+    depth_buffer.destroy(&ly_device.ash_device, ly_device.allocator.callbacks());
+    Ok(())
+}
+
+fn on_process_depth_buffer(
+    buffers: &DashMap<vk::Buffer, DepthBuffer>,
+    user_handle: client::BufferUserHandle,
+    add_meta_cbs: client::AddBufferMetaCbs,
+) {
+    let buffer = match user_handle {
+        client::BufferUserHandle::VkBuffer(buffer) => buffer,
+        _ => unreachable!(),
+    };
+    let Some(depth_buffer) = buffers.get(&buffer) else {
+        return;
+    };
+    if let (Some(set_pts), Some(pts)) = (add_meta_cbs.set_pts, depth_buffer.pts) {
+        set_pts(pts);
+    }
+    if let Some(set_seq) = add_meta_cbs.set_seq {
+        set_seq(depth_buffer.seq);
+    }
+}
+
+/// Depth is exported in shared memory only, converted on the CPU
+fn get_depth_enum_formats() -> Vec<client::EnumFormatInfo> {
+    vec![client::EnumFormatInfo {
+        formats: vec![client::Format::GRAY16_LE],
+        modifiers: vec![],
+    }]
+}
+
+/// Creates the stream depth attachments of the swapchain `target` are
+/// exported on, as `GRAY16_LE` frames in shared memory
+#[named]
+unsafe fn create_depth_stream(
+    device: vk::Device,
+    target: StreamTarget,
+    transform: client::Transform,
+    extent: vk::Extent2D,
+    max_buffers: u32,
+    mut props: Vec<(String, String)>,
+) -> Result<DepthStream> {
+    info!(
+        "creating depth stream, extent: {}x{}",
+        extent.width, extent.height
+    );
+
+    let streaming = Arc::new(AtomicBool::new(false));
+    let buffers = Arc::new(DashMap::new());
+    props.push((PROP_DEPTH.into(), "true".into()));
+
+    let stream_info = client::StreamInfo {
+        width: extent.width,
+        height: extent.height,
+        enum_formats: get_depth_enum_formats(),
+        colorimetry: client::Colorimetry::default(),
+        transform,
+        // depth is copied as is
+        scale: client::Scale::Native,
+        max_buffers,
+        format_preference: client::FormatPreference::global(),
+        node_class: client::NodeClass::global(),
+        props,
+        fixate_format: Box::new(|_| {
+            Some(client::FixateFormat {
+                modifier: None,
+                num_planes: 1,
+                mem_fd_fallback: false,
+            })
+        }),
+        add_buffer: Box::new({
+            let target = target.clone();
+            let buffers = buffers.clone();
+            move |data_type| on_add_depth_buffer(device, target.get(), &buffers, data_type)
+        }),
+        remove_buffer: Box::new({
+            let buffers = buffers.clone();
+            move |user_handle| {
+                let _ =
+                    on_remove_depth_buffer(device, &buffers, user_handle).map_err(|e| map_err!(e));
+            }
+        }),
+        process_buffer: Box::new({
+            let buffers = buffers.clone();
+            move |user_handle, add_meta_cbs| {
+                on_process_depth_buffer(&buffers, user_handle, add_meta_cbs)
+            }
+        }),
+        state_changed: Box::new({
+            let streaming = streaming.clone();
+            move |state, node_id| {
+                info!(
+                    "{:?} depth stream node {:?}: {:?}",
+                    target.get(),
+                    node_id,
+                    state
+                );
+                streaming.store(
+                    state == client::StreamState::Streaming,
+                    atomic::Ordering::Release,
+                );
+            }
+        }),
+    };
+
+    let stream = CLIENT
+        .as_ref()
+        .ok_or(anyhow!("failed to get client"))?
+        .create_stream(stream_info)?;
+
+    Ok(DepthStream {
+        stream,
+        streaming,
+        buffers,
+        next_seq: AtomicU64::new(0),
+    })
+}
+
+struct StreamHandover {
+    stream: client::Stream,
+    stream_target: StreamTarget,
+    streaming: Arc<AtomicBool>,
+    buffer_demand: client::BufferDemand,
+    format: vk::Format,
+    color_space: vk::ColorSpaceKHR,
+    extent: vk::Extent2D,
+    export_data: Option<ExportData>,
+    export_images: DashMap<vk::Image, ExportImage>,
+    depth: Option<DepthStream>,
+}
+
+/// Takes stream and exported buffers away from retired swapchain
+#[named]
+unsafe fn take_stream_handover(
+    ash_device: &ash::Device,
+    old_swapchain: vk::SwapchainKHR,
+) -> Option<StreamHandover> {
+    if old_swapchain == vk::SwapchainKHR::null() {
+        return None;
+    }
+    // frames still queued by the worker refer to the old swapchain
+    let worker = SWAPCHAIN_MAP.get(&old_swapchain)?.stream.as_ref()?.worker();
+    worker.flush();
+
+    let mut ly_old = SWAPCHAIN_MAP.get_mut(&old_swapchain)?;
+    let stream = ly_old.stream.take()?;
+    // command buffers are recorded per image index and get reused for images
+    // of the new swapchain, however many there are
+    for mut data in ly_old.image_datas.iter_mut() {
+        if let Err(e) = data.sync.wait(ash_device) {
+            warn!("failed to wait for copies of retired swapchain: {e}");
+        }
+    }
+    let buffer_demand = mem::replace(
+        ly_old.buffer_demand.get_mut().unwrap(),
+        client::BufferDemand::new(MAX_BUFFERS),
+    );
+    Some(StreamHandover {
+        stream,
+        stream_target: ly_old.stream_target.clone(),
+        streaming: ly_old.streaming.clone(),
+        buffer_demand,
+        format: ly_old.format,
+        color_space: ly_old.color_space,
+        extent: ly_old.extent,
+        export_data: ly_old.export_data.take(),
+        export_images: mem::take(&mut ly_old.export_images),
+        depth: ly_old.depth.take(),
+    })
+}
+
+#[named]
+unsafe fn renegotiate_stream(
+    khr_phy_props2: &khr::GetPhysicalDeviceProperties2,
+    phy_device: vk::PhysicalDevice,
+    swapchain: vk::SwapchainKHR,
+    host_export: bool,
+) -> Result<()> {
+    let (stream, depth_stream, format, color_space, extent) = {
+        let ly_swapchain = SWAPCHAIN_MAP
+            .get(&swapchain)
+            .ok_or(vk::Result::ERROR_UNKNOWN)?;
+        match ly_swapchain.stream.as_ref() {
+            Some(v) => (
+                v.proxy(),
+                ly_swapchain.depth.as_ref().map(|v| v.stream.proxy()),
+                ly_swapchain.format,
+                ly_swapchain.color_space,
+                ly_swapchain.extent,
+            ),
+            None => return Ok(()),
+        }
+    };
+
+    info!(
+        "renegotiating stream, extent: {}x{} format: {:?}",
+        extent.width,
+        extent.height,
+        vk_format_get_info(format)
+    );
+    let enum_formats = get_enum_formats(khr_phy_props2, phy_device, format, extent, host_export)?;
+    let colorimetry = vk_color_space_get_colorimetry(color_space);
+    stream.update_format(extent.width, extent.height, enum_formats, colorimetry)?;
+    if let Some(depth_stream) = depth_stream {
+        depth_stream.update_format(
+            extent.width,
+            extent.height,
+            get_depth_enum_formats(),
+            client::Colorimetry::default(),
+        )?;
+    }
+    Ok(())
+}
+
 #[named]
 unsafe fn create_swapchain_khr(
     device: vk::Device,
@@ -1283,11 +3146,37 @@ unsafe fn create_swapchain_khr(
         .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
 
     let mut create_info = p_create_info.read();
-    create_info.image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+    // protected images must not be copied to unprotected exported ones,
+    // neither is the indicator drawn onto them
+    let protected = create_info
+        .flags
+        .contains(vk::SwapchainCreateFlagsKHR::PROTECTED);
+    if !protected {
+        create_info.image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+    }
+    let surface_caps = ly_instance
+        .khr_surface
+        .get_physical_device_surface_capabilities(ly_device.phy_device, create_info.surface)
+        .map_err(|e| warn!("failed to get surface capabilities: {e:?}"))
+        .ok();
+    if client::indicator_enabled() && !protected {
+        // indicator is blitted onto swapchain images
+        let supported = surface_caps
+            .map(|caps| caps.supported_usage_flags)
+            .unwrap_or_default();
+        if supported.contains(vk::ImageUsageFlags::TRANSFER_DST) {
+            create_info.image_usage |= vk::ImageUsageFlags::TRANSFER_DST;
+        } else {
+            warn!("surface does not support drawing capture indicator");
+        }
+    }
 
     let vk::SwapchainCreateInfoKHR {
         image_format,
+        image_color_space,
         image_extent,
+        image_usage,
+        image_sharing_mode,
         ..
     } = create_info;
 
@@ -1304,46 +3193,223 @@ unsafe fn create_swapchain_khr(
         swapchain, create_info.old_swapchain
     );
 
+    // presents scaled to the surface show all of the images
+    let scaled = find_in_chain::<vk::SwapchainPresentScalingCreateInfoEXT>(
+        create_info.p_next,
+        vk::StructureType::SWAPCHAIN_PRESENT_SCALING_CREATE_INFO_EXT,
+    )
+    .map_or(false, |info| {
+        !info.scaling_behavior.is_empty()
+            && info.scaling_behavior != vk::PresentScalingFlagsEXT::ONE_TO_ONE
+    });
+    let visible_extent = match surface_caps {
+        Some(caps) if !scaled => {
+            vk_surface_visible_extent(image_extent, &caps, create_info.pre_transform)
+        }
+        _ => image_extent,
+    };
+    if visible_extent != image_extent {
+        debug!("surface shows {visible_extent:?} of {image_extent:?}");
+    }
+
+    let refresh_duration = ly_device.get_refresh_cycle_duration.and_then(|pfn| {
+        let mut properties = vk::RefreshCycleDurationGOOGLE::default();
+        pfn(device, swapchain, &mut properties)
+            .result()
+            .ok()
+            .map(|()| Duration::from_nanos(properties.refresh_duration))
+    });
+    let pacer = FramePacer::new(create_info.present_mode, refresh_duration);
+    if let Some(pacer) = pacer.as_ref() {
+        debug!(
+            "pacing captures of {:?} at {:?}",
+            create_info.present_mode,
+            pacer.interval()
+        );
+    }
+    let mut shared_present = match create_info.present_mode {
+        vk::PresentModeKHR::SHARED_DEMAND_REFRESH
+        | vk::PresentModeKHR::SHARED_CONTINUOUS_REFRESH => {
+            debug!(
+                "swapchain {:?} shares its image with presentation, {:?}",
+                swapchain, create_info.present_mode
+            );
+            Some(SharedPresent {
+                continuous: create_info.present_mode
+                    == vk::PresentModeKHR::SHARED_CONTINUOUS_REFRESH,
+                queue: Mutex::new(None),
+                semaphore: vk::Semaphore::null(),
+            })
+        }
+        _ => None,
+    };
+    if AlphaMode::global().is_translucent(create_info.composite_alpha) {
+        info!(
+            "swapchain {:?} presents with {:?} composite alpha, captures may be translucent",
+            swapchain, create_info.composite_alpha
+        );
+    }
+
     let images = ly_device
         .khr_swapchain
         .get_swapchain_images(swapchain)
         .unwrap_or_default();
 
     let image_datas = DashMap::new();
-
-    let stream = if let Some(valid) = &ly_instance.valid {
-        if ly_device.valid.is_some() {
+    let mut present_semaphores = SemaphorePool::default();
+    let mut export_data = None;
+    let mut export_images = DashMap::new();
+    let mut stream_target = StreamTarget::new(swapchain);
+    let mut streaming = Arc::new(AtomicBool::new(false));
+    let mut buffer_demand = client::BufferDemand::new(MAX_BUFFERS);
+    let mut depth = None;
+    let mut renegotiate = false;
+    let platform = SURFACE_MAP.get(&create_info.surface).map(|v| v.platform);
+    // pre-rotated images are exported as is, consumers correct orientation
+    let transform = vk_surface_transform_get_transform(create_info.pre_transform);
+
+    let stream = if protected {
+        info!("swapchain {:?} is protected, not capturing", swapchain);
+        None
+    } else if let Some(valid) = &ly_instance.valid {
+        if let Some(ly_device_valid) = &ly_device.valid {
+            let allocator = ly_device.allocator.callbacks();
+            present_semaphores = SemaphorePool::new(
+                &ly_device.ash_device,
+                images.len(),
+                MAX_FRAMES_IN_FLIGHT,
+                ly_device.allocator,
+            )?;
             for &image in images.iter() {
-                let semaphore_info = vk::SemaphoreCreateInfo::builder();
-                let semaphore = ly_device
-                    .ash_device
-                    .create_semaphore(&semaphore_info, None)?;
+                let sync_file_semaphore = if ly_device_valid.khr_semaphore_fd.is_some() {
+                    let mut export_info = vk::ExportSemaphoreCreateInfo::builder()
+                        .handle_types(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+                    let semaphore_info =
+                        vk::SemaphoreCreateInfo::builder().push_next(&mut export_info);
+                    let semaphore = ly_device
+                        .ash_device
+                        .create_semaphore(&semaphore_info, allocator)?;
+                    ly_device.set_object_name(semaphore, SYNC_FILE_SEMAPHORE_NAME);
+                    Some(semaphore)
+                } else {
+                    None
+                };
                 let data = ImageData {
-                    semaphores: vec![semaphore],
-                    fence: FenceState::new(&ly_device.ash_device)?,
-                    seq: 0,
+                    sync_file_semaphore,
+                    sync: ImageSync::new(ly_device.allocator),
                 };
 
                 image_datas.insert(image, data);
             }
 
-            create_stream(
-                &valid.khr_phy_props2,
-                ly_device.phy_device,
-                device,
-                swapchain,
-                image_format,
-                image_extent.width,
-                image_extent.height,
-            )
-            .map_err(|e| error!("failed to create stream: {e:?}"))
-            .ok()
+            if let Some(handover) =
+                take_stream_handover(&ly_device.ash_device, create_info.old_swapchain)
+            {
+                debug!("stream handover from {:?}", create_info.old_swapchain);
+                renegotiate = handover.format != image_format
+                    || handover.color_space != image_color_space
+                    || handover.extent != image_extent;
+
+                // source images of old swapchain are going away
+                for mut export_image in handover.export_images.iter_mut() {
+                    export_image.src_image = (vk::Image::null(), 0);
+                    export_image.present_pts = None;
+                }
+                export_images = handover.export_images;
+                export_data = handover.export_data;
+                if let Some(data) = export_data.as_mut() {
+                    // images of the new swapchain may get handles of old ones
+                    data.copy_cache.get_mut().unwrap().invalidate();
+                    if let Some(post_processor) = data.post_processor.as_mut() {
+                        post_processor.reserve(&ly_device.ash_device, images.len())?;
+                    }
+                    if let Some(indicator) = data.indicator.as_mut() {
+                        indicator.reserve(&ly_device.ash_device, images.len())?;
+                    }
+                    if let Some(copier) = data.depth_copier.as_mut() {
+                        copier.reserve(&ly_device.ash_device, images.len())?;
+                    }
+                }
+                stream_target = handover.stream_target;
+                streaming = handover.streaming;
+                buffer_demand = handover.buffer_demand;
+                let _ = handover.stream.proxy().update_transform(transform);
+                depth = handover.depth;
+                if let Some(depth) = depth.as_ref() {
+                    let _ = depth.stream.proxy().update_transform(transform);
+                }
+                Some(handover.stream)
+            } else if !ly_instance.enabled {
+                debug!("capture disabled by layer settings or app filter");
+                None
+            } else if !SwapchainFilter::global().matches(platform, image_extent) {
+                debug!("swapchain {:?} filtered out, not capturing", swapchain);
+                None
+            } else {
+                let mut props = ly_instance.app_props.clone();
+                match platform {
+                    Some(SurfacePlatform::Display) => {
+                        props.push((PROP_DIRECT_DISPLAY.into(), "true".into()));
+                    }
+                    Some(SurfacePlatform::Headless) => {
+                        props.push((PROP_HEADLESS.into(), "true".into()));
+                    }
+                    _ => (),
+                }
+                // frames in shared memory are not bound to a device
+                match ly_device.drm_devices.first() {
+                    Some(drm_device) if !ly_device.host_export => {
+                        props.extend(drm_device.as_props());
+                    }
+                    _ => (),
+                }
+                let stream = create_stream(
+                    &valid.khr_phy_props2,
+                    ly_device.phy_device,
+                    device,
+                    stream_target.clone(),
+                    streaming.clone(),
+                    image_format,
+                    image_color_space,
+                    transform,
+                    image_extent.width,
+                    image_extent.height,
+                    buffer_demand.current(),
+                    ly_device.host_export,
+                    props.clone(),
+                )
+                .map_err(|e| error!("failed to create stream: {e:?}"))
+                .ok();
+                if stream.is_some() && ly_device.depth_images.is_some() {
+                    depth = create_depth_stream(
+                        device,
+                        stream_target.clone(),
+                        transform,
+                        image_extent,
+                        buffer_demand.current(),
+                        props,
+                    )
+                    .map_err(|e| error!("failed to create depth stream: {e:?}"))
+                    .ok();
+                }
+                stream
+            }
         } else {
             None
         }
     } else {
         None
     };
+    if let Some(shared) = shared_present.as_mut().filter(|v| v.continuous) {
+        if stream.is_some() {
+            let semaphore_info = vk::SemaphoreCreateInfo::builder();
+            shared.semaphore = ly_device
+                .ash_device
+                .create_semaphore(&semaphore_info, ly_device.allocator.callbacks())?;
+            ly_device.set_object_name(shared.semaphore, SHARED_PRESENT_SEMAPHORE_NAME);
+            CONTINUOUS_SWAPCHAINS.fetch_add(1, atomic::Ordering::Relaxed);
+        }
+    }
 
     SWAPCHAIN_MAP.insert(
         swapchain,
@@ -1351,15 +3417,45 @@ unsafe fn create_swapchain_khr(
             device,
             surface: create_info.surface,
             format: image_format,
+            color_space: image_color_space,
             extent: image_extent,
+            visible_extent,
+            image_usage,
+            sharing_mode: image_sharing_mode,
             images,
-            export_data: None,
+            export_data,
             image_datas,
+            present_semaphores: Mutex::new(present_semaphores),
             stream,
-            export_images: DashMap::new(),
+            stream_target: stream_target.clone(),
+            streaming,
+            buffer_demand: Mutex::new(buffer_demand),
+            export_images,
             cursor_serial: AtomicU64::new(0),
+            window_serial: AtomicU64::new(0),
+            window_suspended: AtomicBool::new(false),
+            present_id: AtomicU64::new(0),
+            stale: StaleState::default(),
+            present_mode: Mutex::new(create_info.present_mode),
+            refresh_duration,
+            pacer: Mutex::new(pacer),
+            shared_present,
+            depth,
         },
     );
+    stream_target.set(swapchain);
+
+    if renegotiate {
+        if let Some(valid) = &ly_instance.valid {
+            let _ = renegotiate_stream(
+                &valid.khr_phy_props2,
+                ly_device.phy_device,
+                swapchain,
+                ly_device.host_export,
+            )
+            .map_err(|e| error!("failed to renegotiate stream: {e:?}"));
+        }
+    }
 
     Ok(())
 }
@@ -1391,9 +3487,19 @@ unsafe fn destroy_swapchain_khr(
 
     if let Some(ly_swapchain) = SWAPCHAIN_MAP.get(&swapchain) {
         if let Some(stream) = &ly_swapchain.stream {
-            let stream = stream.proxy();
+            let (stream, worker) = (stream.proxy(), stream.worker());
+            let depth_stream = ly_swapchain.depth.as_ref().map(|v| v.stream.proxy());
             drop(ly_swapchain);
-            let _ = stream.try_terminate().map_err(|e| map_err!(e));
+            // queued frames wait on fences destroyed below
+            worker.flush();
+            if let Err(e) = stream.drain_and_terminate() {
+                error!("{:?}", e);
+            }
+            if let Some(depth_stream) = depth_stream {
+                if let Err(e) = depth_stream.drain_and_terminate() {
+                    error!("{:?}", e);
+                }
+            }
         }
     }
     let ly_swapchain = SWAPCHAIN_MAP.remove(&swapchain);
@@ -1403,19 +3509,69 @@ unsafe fn destroy_swapchain_khr(
         .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
 
     if let Some((_, ly_swapchain)) = ly_swapchain {
+        let allocator = ly_device.allocator.callbacks();
         for image_data in &ly_swapchain.image_datas {
-            image_data.fence.destroy(&ly_device.ash_device);
-            for &s in &image_data.semaphores {
-                ly_device.ash_device.destroy_semaphore(s, None);
+            image_data.sync.destroy(&ly_device.ash_device);
+            if let Some(s) = image_data.sync_file_semaphore {
+                ly_device.ash_device.destroy_semaphore(s, allocator);
             }
         }
+        ly_swapchain
+            .present_semaphores
+            .lock()
+            .unwrap()
+            .destroy(&ly_device.ash_device);
         if let Some(export_data) = ly_swapchain.export_data {
-            ly_device
-                .ash_device
-                .free_command_buffers(export_data.command_pool, &export_data.command_buffers);
-            ly_device
-                .ash_device
-                .destroy_command_pool(export_data.command_pool, None);
+            if let Some(converter) = export_data.nv12.as_ref() {
+                let _ = ly_device.ash_device.queue_wait_idle(export_data.queue);
+                for (_, export_image) in ly_swapchain.export_images {
+                    if let Some(target) = export_image.nv12_target {
+                        converter.destroy_target(&ly_device.ash_device, target);
+                    }
+                }
+                converter.destroy(&ly_device.ash_device);
+            }
+            if let Some(converter) = export_data.swizzle.as_ref() {
+                let _ = ly_device.ash_device.queue_wait_idle(export_data.queue);
+                converter.destroy(&ly_device.ash_device);
+            }
+            if let Some(post_processor) = export_data.post_processor.as_ref() {
+                let _ = ly_device.ash_device.queue_wait_idle(export_data.queue);
+                post_processor.destroy(&ly_device.ash_device);
+            }
+            if let Some(indicator) = export_data.indicator.as_ref() {
+                let _ = ly_device.ash_device.queue_wait_idle(export_data.queue);
+                indicator.destroy(&ly_device.ash_device);
+            }
+            if let Some(copier) = export_data.depth_copier.as_ref() {
+                let _ = ly_device.ash_device.queue_wait_idle(export_data.queue);
+                copier.destroy(&ly_device.ash_device);
+            }
+            export_data
+                .copy_cache
+                .lock()
+                .unwrap()
+                .destroy(&ly_device.ash_device);
+        }
+        if let Some(depth) = ly_swapchain.depth {
+            // buffers are usually removed as the stream terminates
+            let buffers: Vec<_> = depth.buffers.iter().map(|v| *v.key()).collect();
+            for buffer in buffers {
+                if let Some((_, depth_buffer)) = depth.buffers.remove(&buffer) {
+                    depth_buffer.destroy(&ly_device.ash_device, allocator);
+                }
+            }
+        }
+        if let Some(shared) = ly_swapchain.shared_present {
+            if shared.semaphore != vk::Semaphore::null() {
+                if let Some(queue) = *shared.queue.lock().unwrap() {
+                    let _ = ly_device.ash_device.queue_wait_idle(queue);
+                }
+                ly_device
+                    .ash_device
+                    .destroy_semaphore(shared.semaphore, allocator);
+                CONTINUOUS_SWAPCHAINS.fetch_sub(1, atomic::Ordering::Relaxed);
+            }
         }
     }
 
@@ -1434,6 +3590,44 @@ unsafe extern "system" fn pwcap_vkDestroySwapchainKHR(
 }
 const _: vk::PFN_vkDestroySwapchainKHR = pwcap_vkDestroySwapchainKHR;
 
+/// Present ids of each swapchain in `present_info`, assigned by the layer
+/// unless the app chained its own `VkPresentIdKHR`, 0 for no id. Returns
+/// whether ids were assigned by the layer.
+unsafe fn get_present_ids(present_info: &vk::PresentInfoKHR) -> (Vec<u64>, bool) {
+    let count = present_info.swapchain_count as usize;
+    let swapchains = slice::from_raw_parts(present_info.p_swapchains, count);
+    let app_present_id =
+        find_in_chain::<vk::PresentIdKHR>(present_info.p_next, vk::StructureType::PRESENT_ID_KHR);
+    if let Some(app_present_id) = app_present_id {
+        if app_present_id.p_present_ids.is_null() {
+            return (vec![0; count], false);
+        }
+        let ids = slice::from_raw_parts(app_present_id.p_present_ids, count).to_vec();
+        // ids assigned by the layer later on must keep increasing
+        for (swapchain, &id) in swapchains.iter().zip(&ids) {
+            if let Some(ly_swapchain) = SWAPCHAIN_MAP.get(swapchain) {
+                ly_swapchain
+                    .present_id
+                    .fetch_max(id, atomic::Ordering::Relaxed);
+            }
+        }
+        return (ids, false);
+    }
+    let ids = swapchains
+        .iter()
+        .map(|swapchain| match SWAPCHAIN_MAP.get(swapchain) {
+            Some(ly_swapchain) => {
+                ly_swapchain
+                    .present_id
+                    .fetch_add(1, atomic::Ordering::Relaxed)
+                    + 1
+            }
+            None => 0,
+        })
+        .collect();
+    (ids, true)
+}
+
 unsafe fn queue_present_khr(
     queue: vk::Queue,
     p_present_info: *const vk::PresentInfoKHR,
@@ -1445,8 +3639,64 @@ unsafe fn queue_present_khr(
 
     let mut present_info = p_present_info.read();
 
-    let _wait_semaphores_new = if ly_device.valid.is_some() {
-        let res = capture(&ly_device.ash_device, ly_queue.family_index, &present_info);
+    let present_timing = matches!(
+        &ly_device.valid,
+        Some(LayerDeviceValid {
+            khr_present_wait: Some(_),
+            ..
+        })
+    );
+    let present_ids = if present_timing {
+        Some(get_present_ids(&present_info))
+    } else {
+        None
+    };
+    let present_id_info = match &present_ids {
+        Some((ids, true)) => Some(vk::PresentIdKHR {
+            p_next: present_info.p_next,
+            swapchain_count: ids.len() as _,
+            p_present_ids: ids.as_ptr(),
+            ..Default::default()
+        }),
+        _ => None,
+    };
+    if let Some(info) = present_id_info.as_ref() {
+        present_info.p_next = info as *const _ as _;
+    }
+
+    // apps may switch present modes per present, captures are paced for the
+    // mode presented with
+    if let Some(info) = find_in_chain::<vk::SwapchainPresentModeInfoEXT>(
+        present_info.p_next,
+        vk::StructureType::SWAPCHAIN_PRESENT_MODE_INFO_EXT,
+    ) {
+        let swapchains =
+            slice::from_raw_parts(present_info.p_swapchains, present_info.swapchain_count as _);
+        let present_modes = slice::from_raw_parts(info.p_present_modes, info.swapchain_count as _);
+        for (swapchain, &present_mode) in swapchains.iter().zip(present_modes) {
+            if let Some(ly_swapchain) = SWAPCHAIN_MAP.get(swapchain) {
+                ly_swapchain.set_present_mode(present_mode);
+            }
+        }
+    }
+
+    // present fences of the app stay in the chain, they signal once the
+    // present no longer waits on the semaphores of the layer it got instead
+    let _wait_semaphores_new = if let Some(valid) = &ly_device.valid {
+        let res = capture(
+            &ly_device.ash_device,
+            valid.khr_semaphore_fd.as_ref(),
+            queue,
+            ly_queue.family_index,
+            &present_info,
+            valid
+                .khr_present_wait
+                .as_ref()
+                .zip(present_ids.as_ref().map(|(ids, _)| &ids[..])),
+            ly_device.timeline.as_ref(),
+            valid.gpu_clock.as_ref(),
+            ly_device.depth_images.as_ref(),
+        );
         if !res.is_empty() {
             present_info.wait_semaphore_count = res.len() as _;
             present_info.p_wait_semaphores = res.as_ptr();
@@ -1456,27 +3706,37 @@ unsafe fn queue_present_khr(
         None
     };
 
+    // results of each swapchain tell which of them went stale
+    let swapchain_count = present_info.swapchain_count as usize;
+    let mut results = vec![vk::Result::SUCCESS; swapchain_count];
+    let app_results = present_info.p_results;
+    if app_results.is_null() {
+        present_info.p_results = results.as_mut_ptr();
+    }
+
     let res = (ly_device.khr_swapchain.fp().queue_present_khr)(queue, &present_info);
+
+    let results = if app_results.is_null() {
+        &results[..]
+    } else {
+        slice::from_raw_parts(app_results, swapchain_count)
+    };
+    let swapchains = slice::from_raw_parts(present_info.p_swapchains, swapchain_count);
+    for (swapchain, &result) in swapchains.iter().zip(results) {
+        if let Some(ly_swapchain) = SWAPCHAIN_MAP.get(swapchain) {
+            ly_swapchain.stale.mark(result);
+            if let Some(shared) = ly_swapchain.shared_present.as_ref() {
+                *shared.queue.lock().unwrap() = Some(queue);
+            }
+        }
+    }
+
     match res {
         vk::Result::SUCCESS | vk::Result::SUBOPTIMAL_KHR => Ok(res),
         _ => Err(anyhow!(res)),
     }
 }
 
-unsafe fn ly_swapchain_wait_image(
-    ly_device: &LayerDevice,
-    ly_swapchain: &LayerSwapchain,
-    image_index: usize,
-) -> Result<()> {
-    let image = ly_swapchain.images[image_index];
-    let mut data = ly_swapchain
-        .image_datas
-        .get_mut(&image)
-        .ok_or(anyhow!("image removed"))?;
-    data.fence.wait_and_reset(&ly_device.ash_device)?;
-    Ok(())
-}
-
 unsafe fn acquire_next_image_khr(
     device: vk::Device,
     swapchain: vk::SwapchainKHR,
@@ -1500,15 +3760,19 @@ unsafe fn acquire_next_image_khr(
         fence,
         p_image_index,
     );
+    ly_swapchain.stale.mark(res);
+    if matches!(res, vk::Result::SUCCESS | vk::Result::SUBOPTIMAL_KHR) {
+        // presents of the image waited on their semaphores meanwhile
+        ly_swapchain
+            .present_semaphores
+            .lock()
+            .unwrap()
+            .acquired(*p_image_index as _);
+    }
     match res {
-        vk::Result::SUCCESS | vk::Result::SUBOPTIMAL_KHR => (),
-        _ => return Err(anyhow!(res)),
-    };
-
-    if ly_device.valid.is_some() {
-        ly_swapchain_wait_image(&ly_device, &ly_swapchain, *p_image_index as _)?;
+        vk::Result::SUCCESS | vk::Result::SUBOPTIMAL_KHR => Ok(res),
+        _ => Err(anyhow!(res)),
     }
-    Ok(res)
 }
 
 unsafe fn acquire_next_image2_khr(
@@ -1529,81 +3793,959 @@ unsafe fn acquire_next_image2_khr(
         p_acquire_info,
         p_image_index,
     );
+    ly_swapchain.stale.mark(res);
+    if matches!(res, vk::Result::SUCCESS | vk::Result::SUBOPTIMAL_KHR) {
+        // presents of the image waited on their semaphores meanwhile
+        ly_swapchain
+            .present_semaphores
+            .lock()
+            .unwrap()
+            .acquired(*p_image_index as _);
+    }
     match res {
-        vk::Result::SUCCESS | vk::Result::SUBOPTIMAL_KHR => (),
-        _ => return Err(anyhow!(res)),
+        vk::Result::SUCCESS | vk::Result::SUBOPTIMAL_KHR => Ok(res),
+        _ => Err(anyhow!(res)),
+    }
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkAcquireNextImageKHR(
+    device: vk::Device,
+    swapchain: vk::SwapchainKHR,
+    timeout: u64,
+    semaphore: vk::Semaphore,
+    fence: vk::Fence,
+    p_image_index: *mut u32,
+) -> vk::Result {
+    acquire_next_image_khr(device, swapchain, timeout, semaphore, fence, p_image_index)
+        .unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkAcquireNextImageKHR = pwcap_vkAcquireNextImageKHR;
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkAcquireNextImage2KHR(
+    device: vk::Device,
+    p_acquire_info: *const vk::AcquireNextImageInfoKHR,
+    p_image_index: *mut u32,
+) -> vk::Result {
+    acquire_next_image2_khr(device, p_acquire_info, p_image_index).unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkAcquireNextImage2KHR = pwcap_vkAcquireNextImage2KHR;
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkQueuePresentKHR(
+    queue: vk::Queue,
+    p_present_info: *const vk::PresentInfoKHR,
+) -> vk::Result {
+    queue_present_khr(queue, p_present_info).unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkQueuePresentKHR = pwcap_vkQueuePresentKHR;
+
+unsafe fn create_semaphore(
+    device: vk::Device,
+    p_create_info: *const vk::SemaphoreCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_semaphore: *mut vk::Semaphore,
+) -> Result<vk::Result> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+
+    let res = (ly_device.ash_device.fp_v1_0().create_semaphore)(
+        device,
+        p_create_info,
+        p_allocator,
+        p_semaphore,
+    );
+    if let (vk::Result::SUCCESS, Some(timeline)) = (res, ly_device.timeline.as_ref()) {
+        timeline.add_semaphore(*p_semaphore, (*p_create_info).p_next);
+    }
+    Ok(res)
+}
+
+unsafe fn destroy_semaphore(
+    device: vk::Device,
+    semaphore: vk::Semaphore,
+    p_allocator: *const vk::AllocationCallbacks,
+) -> Result<()> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+
+    if let Some(timeline) = ly_device.timeline.as_ref() {
+        timeline.remove_semaphore(semaphore);
+    }
+    (ly_device.ash_device.fp_v1_0().destroy_semaphore)(device, semaphore, p_allocator);
+    Ok(())
+}
+
+unsafe fn queue_submit(
+    queue: vk::Queue,
+    submit_count: u32,
+    p_submits: *const vk::SubmitInfo,
+    fence: vk::Fence,
+) -> Result<vk::Result> {
+    let ly_queue = QUEUE_MAP.get(&queue).ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let ly_device = DEVICE_MAP
+        .get(&ly_queue.device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+
+    if let Some(timeline) = ly_device.timeline.as_ref() {
+        for submit in raw_slice(p_submits, submit_count) {
+            let wait_semaphores = raw_slice(submit.p_wait_semaphores, submit.wait_semaphore_count);
+            let wait_values = match find_in_chain::<vk::TimelineSemaphoreSubmitInfo>(
+                submit.p_next,
+                vk::StructureType::TIMELINE_SEMAPHORE_SUBMIT_INFO,
+            ) {
+                Some(info) => raw_slice(
+                    info.p_wait_semaphore_values,
+                    info.wait_semaphore_value_count,
+                ),
+                None => &[],
+            };
+            let waits = wait_semaphores
+                .iter()
+                .enumerate()
+                .map(|(i, &semaphore)| (semaphore, wait_values.get(i).copied().unwrap_or(0)));
+            let signals = raw_slice(submit.p_signal_semaphores, submit.signal_semaphore_count);
+            timeline.record_submit(waits, signals.iter().copied());
+        }
+    }
+
+    let res = (ly_device.ash_device.fp_v1_0().queue_submit)(queue, submit_count, p_submits, fence);
+    if res == vk::Result::SUCCESS {
+        capture_continuous(&ly_device, queue, ly_queue.family_index);
+    }
+    Ok(res)
+}
+
+unsafe fn queue_submit2(
+    queue: vk::Queue,
+    submit_count: u32,
+    p_submits: *const vk::SubmitInfo2,
+    fence: vk::Fence,
+) -> Result<vk::Result> {
+    let ly_queue = QUEUE_MAP.get(&queue).ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let ly_device = DEVICE_MAP
+        .get(&ly_queue.device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let queue_submit2 = ly_device
+        .queue_submit2
+        .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+
+    if let Some(timeline) = ly_device.timeline.as_ref() {
+        for submit in raw_slice(p_submits, submit_count) {
+            let waits = raw_slice(
+                submit.p_wait_semaphore_infos,
+                submit.wait_semaphore_info_count,
+            );
+            let signals = raw_slice(
+                submit.p_signal_semaphore_infos,
+                submit.signal_semaphore_info_count,
+            );
+            timeline.record_submit(
+                waits.iter().map(|info| (info.semaphore, info.value)),
+                signals.iter().map(|info| info.semaphore),
+            );
+        }
+    }
+
+    let res = queue_submit2(queue, submit_count, p_submits, fence);
+    if res == vk::Result::SUCCESS {
+        capture_continuous(&ly_device, queue, ly_queue.family_index);
+    }
+    Ok(res)
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkCreateSemaphore(
+    device: vk::Device,
+    p_create_info: *const vk::SemaphoreCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_semaphore: *mut vk::Semaphore,
+) -> vk::Result {
+    create_semaphore(device, p_create_info, p_allocator, p_semaphore)
+        .unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkCreateSemaphore = pwcap_vkCreateSemaphore;
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkDestroySemaphore(
+    device: vk::Device,
+    semaphore: vk::Semaphore,
+    p_allocator: *const vk::AllocationCallbacks,
+) {
+    let _ = map_result!(destroy_semaphore(device, semaphore, p_allocator));
+}
+const _: vk::PFN_vkDestroySemaphore = pwcap_vkDestroySemaphore;
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkQueueSubmit(
+    queue: vk::Queue,
+    submit_count: u32,
+    p_submits: *const vk::SubmitInfo,
+    fence: vk::Fence,
+) -> vk::Result {
+    queue_submit(queue, submit_count, p_submits, fence).unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkQueueSubmit = pwcap_vkQueueSubmit;
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkQueueSubmit2(
+    queue: vk::Queue,
+    submit_count: u32,
+    p_submits: *const vk::SubmitInfo2,
+    fence: vk::Fence,
+) -> vk::Result {
+    queue_submit2(queue, submit_count, p_submits, fence).unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkQueueSubmit2 = pwcap_vkQueueSubmit2;
+
+unsafe fn create_image(
+    device: vk::Device,
+    p_create_info: *const vk::ImageCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_image: *mut vk::Image,
+) -> Result<vk::Result> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let create_image = ly_device.ash_device.fp_v1_0().create_image;
+
+    let create_info = &*p_create_info;
+    let (depth_images, usage) = match (
+        ly_device.depth_images.as_ref(),
+        DepthImages::capture_usage(create_info),
+    ) {
+        (Some(depth_images), Some(usage)) => (depth_images, usage),
+        _ => return Ok(create_image(device, p_create_info, p_allocator, p_image)),
     };
+    // depth attachments get copied from
+    let ly_instance = INSTANCE_MAP
+        .get(&ly_device.instance)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let format_props = ly_instance
+        .ash_instance
+        .get_physical_device_format_properties(ly_device.phy_device, create_info.format);
+    let features = match create_info.tiling {
+        vk::ImageTiling::LINEAR => format_props.linear_tiling_features,
+        _ => format_props.optimal_tiling_features,
+    };
+    if !features.contains(vk::FormatFeatureFlags::TRANSFER_SRC) {
+        return Ok(create_image(device, p_create_info, p_allocator, p_image));
+    }
 
-    if ly_device.valid.is_some() {
-        ly_swapchain_wait_image(&ly_device, &ly_swapchain, *p_image_index as _)?;
+    let mut capture_info = *create_info;
+    capture_info.usage = usage;
+    let res = create_image(device, &capture_info, p_allocator, p_image);
+    if res == vk::Result::SUCCESS {
+        depth_images.add_image(*p_image, &capture_info);
+    }
+    Ok(res)
+}
+
+unsafe fn destroy_image(
+    device: vk::Device,
+    image: vk::Image,
+    p_allocator: *const vk::AllocationCallbacks,
+) -> Result<()> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+
+    if let Some(depth_images) = ly_device.depth_images.as_ref() {
+        // copy of the last captured frame might still be in flight
+        if let Some(queue) = depth_images.remove_image(image) {
+            let _ = ly_device.ash_device.queue_wait_idle(queue);
+        }
+    }
+    (ly_device.ash_device.fp_v1_0().destroy_image)(device, image, p_allocator);
+    Ok(())
+}
+
+unsafe fn set_debug_utils_object_name(
+    device: vk::Device,
+    p_name_info: *const vk::DebugUtilsObjectNameInfoEXT,
+) -> Result<vk::Result> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let set_debug_utils_object_name = ly_device
+        .set_debug_utils_object_name
+        .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+
+    let name_info = &*p_name_info;
+    if let (vk::ObjectType::IMAGE, Some(depth_images)) =
+        (name_info.object_type, ly_device.depth_images.as_ref())
+    {
+        if !name_info.p_object_name.is_null() {
+            let name = CStr::from_ptr(name_info.p_object_name).to_string_lossy();
+            depth_images.set_name(vk::Image::from_raw(name_info.object_handle), &name);
+        }
+    }
+    Ok(set_debug_utils_object_name(device, p_name_info))
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkCreateImage(
+    device: vk::Device,
+    p_create_info: *const vk::ImageCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_image: *mut vk::Image,
+) -> vk::Result {
+    create_image(device, p_create_info, p_allocator, p_image).unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkCreateImage = pwcap_vkCreateImage;
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkDestroyImage(
+    device: vk::Device,
+    image: vk::Image,
+    p_allocator: *const vk::AllocationCallbacks,
+) {
+    let _ = map_result!(destroy_image(device, image, p_allocator));
+}
+const _: vk::PFN_vkDestroyImage = pwcap_vkDestroyImage;
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkSetDebugUtilsObjectNameEXT(
+    device: vk::Device,
+    p_name_info: *const vk::DebugUtilsObjectNameInfoEXT,
+) -> vk::Result {
+    set_debug_utils_object_name(device, p_name_info).unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkSetDebugUtilsObjectNameEXT = pwcap_vkSetDebugUtilsObjectNameEXT;
+
+/// Next layer of the device `command_buffer` got allocated from, found
+/// through the dispatch table the loader puts first in dispatchable handles
+unsafe fn command_buffer_dispatch(command_buffer: vk::CommandBuffer) -> Result<DeviceDispatch> {
+    let key = *(command_buffer.as_raw() as *const usize);
+    let dispatch = DISPATCH_MAP
+        .get(&key)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    Ok(*dispatch)
+}
+
+/// Records the layout an image barrier of the app leaves a depth attachment
+/// in, skipped if the device is not tracked
+fn track_depth_barrier(
+    device: vk::Device,
+    image: vk::Image,
+    new_layout: vk::ImageLayout,
+    src_queue_family_index: u32,
+    dst_queue_family_index: u32,
+) {
+    let Some(ly_device) = DEVICE_MAP.get(&device) else {
+        return;
+    };
+    if let Some(depth_images) = ly_device.depth_images.as_ref() {
+        let queue_family = match src_queue_family_index {
+            v if v == dst_queue_family_index => vk::QUEUE_FAMILY_IGNORED,
+            _ => dst_queue_family_index,
+        };
+        depth_images.barrier(image, new_layout, queue_family);
+    }
+}
+
+unsafe fn create_image_view(
+    device: vk::Device,
+    p_create_info: *const vk::ImageViewCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_view: *mut vk::ImageView,
+) -> Result<vk::Result> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let res = (ly_device.ash_device.fp_v1_0().create_image_view)(
+        device,
+        p_create_info,
+        p_allocator,
+        p_view,
+    );
+    if let (vk::Result::SUCCESS, Some(depth_images)) = (res, ly_device.depth_images.as_ref()) {
+        depth_images.add_view(*p_view, (*p_create_info).image);
+    }
+    Ok(res)
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkCreateImageView(
+    device: vk::Device,
+    p_create_info: *const vk::ImageViewCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_view: *mut vk::ImageView,
+) -> vk::Result {
+    create_image_view(device, p_create_info, p_allocator, p_view).unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkCreateImageView = pwcap_vkCreateImageView;
+
+unsafe fn destroy_image_view(
+    device: vk::Device,
+    view: vk::ImageView,
+    p_allocator: *const vk::AllocationCallbacks,
+) -> Result<()> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    if let Some(depth_images) = ly_device.depth_images.as_ref() {
+        depth_images.remove_view(view);
+    }
+    (ly_device.ash_device.fp_v1_0().destroy_image_view)(device, view, p_allocator);
+    Ok(())
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkDestroyImageView(
+    device: vk::Device,
+    view: vk::ImageView,
+    p_allocator: *const vk::AllocationCallbacks,
+) {
+    let _ = map_result!(destroy_image_view(device, view, p_allocator));
+}
+const _: vk::PFN_vkDestroyImageView = pwcap_vkDestroyImageView;
+
+unsafe fn create_render_pass(
+    device: vk::Device,
+    p_create_info: *const vk::RenderPassCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_render_pass: *mut vk::RenderPass,
+) -> Result<vk::Result> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let res = (ly_device.ash_device.fp_v1_0().create_render_pass)(
+        device,
+        p_create_info,
+        p_allocator,
+        p_render_pass,
+    );
+    if let (vk::Result::SUCCESS, Some(depth_images)) = (res, ly_device.depth_images.as_ref()) {
+        let create_info = &*p_create_info;
+        let attachments = raw_slice(create_info.p_attachments, create_info.attachment_count);
+        let final_layouts = attachments.iter().map(|v| v.final_layout).collect();
+        depth_images.add_render_pass(*p_render_pass, final_layouts);
+    }
+    Ok(res)
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkCreateRenderPass(
+    device: vk::Device,
+    p_create_info: *const vk::RenderPassCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_render_pass: *mut vk::RenderPass,
+) -> vk::Result {
+    create_render_pass(device, p_create_info, p_allocator, p_render_pass)
+        .unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkCreateRenderPass = pwcap_vkCreateRenderPass;
+
+unsafe fn create_render_pass2(
+    device: vk::Device,
+    p_create_info: *const vk::RenderPassCreateInfo2,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_render_pass: *mut vk::RenderPass,
+) -> Result<vk::Result> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let create_render_pass2 = ly_device
+        .create_render_pass2
+        .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+    let res = create_render_pass2(device, p_create_info, p_allocator, p_render_pass);
+    if let (vk::Result::SUCCESS, Some(depth_images)) = (res, ly_device.depth_images.as_ref()) {
+        let create_info = &*p_create_info;
+        let attachments = raw_slice(create_info.p_attachments, create_info.attachment_count);
+        let final_layouts = attachments.iter().map(|v| v.final_layout).collect();
+        depth_images.add_render_pass(*p_render_pass, final_layouts);
+    }
+    Ok(res)
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkCreateRenderPass2(
+    device: vk::Device,
+    p_create_info: *const vk::RenderPassCreateInfo2,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_render_pass: *mut vk::RenderPass,
+) -> vk::Result {
+    create_render_pass2(device, p_create_info, p_allocator, p_render_pass)
+        .unwrap_or_else(|e| map_err!(e))
+}
+const _: vk::PFN_vkCreateRenderPass2 = pwcap_vkCreateRenderPass2;
+
+unsafe fn destroy_render_pass(
+    device: vk::Device,
+    render_pass: vk::RenderPass,
+    p_allocator: *const vk::AllocationCallbacks,
+) -> Result<()> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    if let Some(depth_images) = ly_device.depth_images.as_ref() {
+        depth_images.remove_render_pass(render_pass);
+    }
+    (ly_device.ash_device.fp_v1_0().destroy_render_pass)(device, render_pass, p_allocator);
+    Ok(())
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkDestroyRenderPass(
+    device: vk::Device,
+    render_pass: vk::RenderPass,
+    p_allocator: *const vk::AllocationCallbacks,
+) {
+    let _ = map_result!(destroy_render_pass(device, render_pass, p_allocator));
+}
+const _: vk::PFN_vkDestroyRenderPass = pwcap_vkDestroyRenderPass;
+
+unsafe fn create_framebuffer(
+    device: vk::Device,
+    p_create_info: *const vk::FramebufferCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_framebuffer: *mut vk::Framebuffer,
+) -> Result<vk::Result> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    let res = (ly_device.ash_device.fp_v1_0().create_framebuffer)(
+        device,
+        p_create_info,
+        p_allocator,
+        p_framebuffer,
+    );
+    let create_info = &*p_create_info;
+    // attachments of imageless framebuffers are given as render passes begin
+    let imageless = create_info
+        .flags
+        .contains(vk::FramebufferCreateFlags::IMAGELESS);
+    if let (vk::Result::SUCCESS, Some(depth_images), false) =
+        (res, ly_device.depth_images.as_ref(), imageless)
+    {
+        let attachments = raw_slice(create_info.p_attachments, create_info.attachment_count);
+        depth_images.add_framebuffer(*p_framebuffer, attachments);
     }
     Ok(res)
 }
 
 #[no_mangle]
 #[named]
-unsafe extern "system" fn pwcap_vkAcquireNextImageKHR(
+unsafe extern "system" fn pwcap_vkCreateFramebuffer(
     device: vk::Device,
-    swapchain: vk::SwapchainKHR,
-    timeout: u64,
-    semaphore: vk::Semaphore,
-    fence: vk::Fence,
-    p_image_index: *mut u32,
+    p_create_info: *const vk::FramebufferCreateInfo,
+    p_allocator: *const vk::AllocationCallbacks,
+    p_framebuffer: *mut vk::Framebuffer,
 ) -> vk::Result {
-    acquire_next_image_khr(device, swapchain, timeout, semaphore, fence, p_image_index)
+    create_framebuffer(device, p_create_info, p_allocator, p_framebuffer)
         .unwrap_or_else(|e| map_err!(e))
 }
-const _: vk::PFN_vkAcquireNextImageKHR = pwcap_vkAcquireNextImageKHR;
+const _: vk::PFN_vkCreateFramebuffer = pwcap_vkCreateFramebuffer;
+
+unsafe fn destroy_framebuffer(
+    device: vk::Device,
+    framebuffer: vk::Framebuffer,
+    p_allocator: *const vk::AllocationCallbacks,
+) -> Result<()> {
+    let ly_device = DEVICE_MAP
+        .get(&device)
+        .ok_or(vk::Result::ERROR_DEVICE_LOST)?;
+    if let Some(depth_images) = ly_device.depth_images.as_ref() {
+        depth_images.remove_framebuffer(framebuffer);
+    }
+    (ly_device.ash_device.fp_v1_0().destroy_framebuffer)(device, framebuffer, p_allocator);
+    Ok(())
+}
 
 #[no_mangle]
 #[named]
-unsafe extern "system" fn pwcap_vkAcquireNextImage2KHR(
+unsafe extern "system" fn pwcap_vkDestroyFramebuffer(
     device: vk::Device,
-    p_acquire_info: *const vk::AcquireNextImageInfoKHR,
-    p_image_index: *mut u32,
-) -> vk::Result {
-    acquire_next_image2_khr(device, p_acquire_info, p_image_index).unwrap_or_else(|e| map_err!(e))
+    framebuffer: vk::Framebuffer,
+    p_allocator: *const vk::AllocationCallbacks,
+) {
+    let _ = map_result!(destroy_framebuffer(device, framebuffer, p_allocator));
+}
+const _: vk::PFN_vkDestroyFramebuffer = pwcap_vkDestroyFramebuffer;
+
+/// Records layouts depth attachments are left in by barriers of the app, as
+/// command buffers get recorded rather than submitted
+unsafe fn cmd_pipeline_barrier(
+    command_buffer: vk::CommandBuffer,
+    src_stage_mask: vk::PipelineStageFlags,
+    dst_stage_mask: vk::PipelineStageFlags,
+    dependency_flags: vk::DependencyFlags,
+    memory_barrier_count: u32,
+    p_memory_barriers: *const vk::MemoryBarrier,
+    buffer_memory_barrier_count: u32,
+    p_buffer_memory_barriers: *const vk::BufferMemoryBarrier,
+    image_memory_barrier_count: u32,
+    p_image_memory_barriers: *const vk::ImageMemoryBarrier,
+) -> Result<()> {
+    let dispatch = command_buffer_dispatch(command_buffer)?;
+    for barrier in raw_slice(p_image_memory_barriers, image_memory_barrier_count) {
+        track_depth_barrier(
+            dispatch.device,
+            barrier.image,
+            barrier.new_layout,
+            barrier.src_queue_family_index,
+            barrier.dst_queue_family_index,
+        );
+    }
+    (dispatch.cmd_pipeline_barrier)(
+        command_buffer,
+        src_stage_mask,
+        dst_stage_mask,
+        dependency_flags,
+        memory_barrier_count,
+        p_memory_barriers,
+        buffer_memory_barrier_count,
+        p_buffer_memory_barriers,
+        image_memory_barrier_count,
+        p_image_memory_barriers,
+    );
+    Ok(())
 }
-const _: vk::PFN_vkAcquireNextImage2KHR = pwcap_vkAcquireNextImage2KHR;
 
 #[no_mangle]
 #[named]
-unsafe extern "system" fn pwcap_vkQueuePresentKHR(
-    queue: vk::Queue,
-    p_present_info: *const vk::PresentInfoKHR,
-) -> vk::Result {
-    queue_present_khr(queue, p_present_info).unwrap_or_else(|e| map_err!(e))
+unsafe extern "system" fn pwcap_vkCmdPipelineBarrier(
+    command_buffer: vk::CommandBuffer,
+    src_stage_mask: vk::PipelineStageFlags,
+    dst_stage_mask: vk::PipelineStageFlags,
+    dependency_flags: vk::DependencyFlags,
+    memory_barrier_count: u32,
+    p_memory_barriers: *const vk::MemoryBarrier,
+    buffer_memory_barrier_count: u32,
+    p_buffer_memory_barriers: *const vk::BufferMemoryBarrier,
+    image_memory_barrier_count: u32,
+    p_image_memory_barriers: *const vk::ImageMemoryBarrier,
+) {
+    let _ = map_result!(cmd_pipeline_barrier(
+        command_buffer,
+        src_stage_mask,
+        dst_stage_mask,
+        dependency_flags,
+        memory_barrier_count,
+        p_memory_barriers,
+        buffer_memory_barrier_count,
+        p_buffer_memory_barriers,
+        image_memory_barrier_count,
+        p_image_memory_barriers,
+    ));
+}
+const _: vk::PFN_vkCmdPipelineBarrier = pwcap_vkCmdPipelineBarrier;
+
+unsafe fn cmd_pipeline_barrier2(
+    command_buffer: vk::CommandBuffer,
+    p_dependency_info: *const vk::DependencyInfo,
+) -> Result<()> {
+    let dispatch = command_buffer_dispatch(command_buffer)?;
+    // only hooked if the next layer has it
+    let cmd_pipeline_barrier2 = dispatch
+        .cmd_pipeline_barrier2
+        .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+    let dependency_info = &*p_dependency_info;
+    let barriers = raw_slice(
+        dependency_info.p_image_memory_barriers,
+        dependency_info.image_memory_barrier_count,
+    );
+    for barrier in barriers {
+        track_depth_barrier(
+            dispatch.device,
+            barrier.image,
+            barrier.new_layout,
+            barrier.src_queue_family_index,
+            barrier.dst_queue_family_index,
+        );
+    }
+    cmd_pipeline_barrier2(command_buffer, p_dependency_info);
+    Ok(())
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkCmdPipelineBarrier2(
+    command_buffer: vk::CommandBuffer,
+    p_dependency_info: *const vk::DependencyInfo,
+) {
+    let _ = map_result!(cmd_pipeline_barrier2(command_buffer, p_dependency_info));
+}
+const _: vk::PFN_vkCmdPipelineBarrier2 = pwcap_vkCmdPipelineBarrier2;
+
+/// Records final layouts depth attachments are left in by render passes of
+/// the app, skipped if the device is not tracked
+unsafe fn begin_depth_render_pass(device: vk::Device, begin_info: &vk::RenderPassBeginInfo) {
+    let Some(ly_device) = DEVICE_MAP.get(&device) else {
+        return;
+    };
+    let Some(depth_images) = ly_device.depth_images.as_ref() else {
+        return;
+    };
+    let attachments = find_in_chain::<vk::RenderPassAttachmentBeginInfo>(
+        begin_info.p_next,
+        vk::StructureType::RENDER_PASS_ATTACHMENT_BEGIN_INFO,
+    )
+    .map(|v| raw_slice(v.p_attachments, v.attachment_count));
+    depth_images.begin_render_pass(begin_info.render_pass, begin_info.framebuffer, attachments);
+}
+
+unsafe fn cmd_begin_render_pass(
+    command_buffer: vk::CommandBuffer,
+    p_render_pass_begin: *const vk::RenderPassBeginInfo,
+    contents: vk::SubpassContents,
+) -> Result<()> {
+    let dispatch = command_buffer_dispatch(command_buffer)?;
+    begin_depth_render_pass(dispatch.device, &*p_render_pass_begin);
+    (dispatch.cmd_begin_render_pass)(command_buffer, p_render_pass_begin, contents);
+    Ok(())
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkCmdBeginRenderPass(
+    command_buffer: vk::CommandBuffer,
+    p_render_pass_begin: *const vk::RenderPassBeginInfo,
+    contents: vk::SubpassContents,
+) {
+    let _ = map_result!(cmd_begin_render_pass(
+        command_buffer,
+        p_render_pass_begin,
+        contents
+    ));
+}
+const _: vk::PFN_vkCmdBeginRenderPass = pwcap_vkCmdBeginRenderPass;
+
+unsafe fn cmd_begin_render_pass2(
+    command_buffer: vk::CommandBuffer,
+    p_render_pass_begin: *const vk::RenderPassBeginInfo,
+    p_subpass_begin_info: *const vk::SubpassBeginInfo,
+) -> Result<()> {
+    let dispatch = command_buffer_dispatch(command_buffer)?;
+    // only hooked if the next layer has it
+    let cmd_begin_render_pass2 = dispatch
+        .cmd_begin_render_pass2
+        .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)?;
+    begin_depth_render_pass(dispatch.device, &*p_render_pass_begin);
+    cmd_begin_render_pass2(command_buffer, p_render_pass_begin, p_subpass_begin_info);
+    Ok(())
+}
+
+#[no_mangle]
+#[named]
+unsafe extern "system" fn pwcap_vkCmdBeginRenderPass2(
+    command_buffer: vk::CommandBuffer,
+    p_render_pass_begin: *const vk::RenderPassBeginInfo,
+    p_subpass_begin_info: *const vk::SubpassBeginInfo,
+) {
+    let _ = map_result!(cmd_begin_render_pass2(
+        command_buffer,
+        p_render_pass_begin,
+        p_subpass_begin_info
+    ));
+}
+const _: vk::PFN_vkCmdBeginRenderPass2 = pwcap_vkCmdBeginRenderPass2;
+
+/// Publishes title and app id of the window once changed, so consumers can
+/// tell streams of the app apart, and whether it is hidden
+#[named]
+unsafe fn update_window_props(swapchain: vk::SwapchainKHR) -> Result<()> {
+    let (stream, info) = {
+        let ly_swapchain = SWAPCHAIN_MAP
+            .get(&swapchain)
+            .ok_or(vk::Result::ERROR_UNKNOWN)?;
+        let stream = match ly_swapchain.stream.as_ref() {
+            Some(v) => v.proxy(),
+            None => return Ok(()),
+        };
+        let ly_surface = match SURFACE_MAP.get(&ly_swapchain.surface) {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let serial = ly_swapchain.window_serial.load(atomic::Ordering::Acquire);
+        let info = if ly_surface.wl_cursor_manager > 0 {
+            me_eh5_pw_capture_wl_window_info(ly_surface.wl_cursor_manager, serial)
+        } else {
+            ly_surface
+                .cursor_manager
+                .as_ref()
+                .and_then(|m| m.window_info(serial))
+        };
+        let info = match info {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        ly_swapchain
+            .window_serial
+            .store(info.serial, atomic::Ordering::Release);
+        ly_swapchain
+            .window_suspended
+            .store(info.suspended, atomic::Ordering::Release);
+        (stream, info)
+    };
+    debug!("window changed: {:?}", info);
+    stream.update_window_suspended(info.suspended)?;
+    Ok(stream.update_props(info.as_props())?)
 }
-const _: vk::PFN_vkQueuePresentKHR = pwcap_vkQueuePresentKHR;
 
 #[named]
 unsafe fn capture_swapchain(
     ash_device: &ash::Device,
+    khr_semaphore_fd: Option<&khr::ExternalSemaphoreFd>,
     swapchain: vk::SwapchainKHR,
     image_index: usize,
+    present_queue: vk::Queue,
     src_queue_family_index: u32,
     wait_semaphores: &[vk::Semaphore],
+    timeline_waits: Option<(&Arc<TimelineSemaphores>, &[(vk::Semaphore, u64)])>,
+    present_wait: Option<(&khr::PresentWait, u64)>,
+    gpu_clock: Option<&Arc<GpuClock>>,
+    depth_images: Option<&DepthImages>,
 ) -> Result<Option<Vec<vk::Semaphore>>> {
-    let stream = {
+    // streams are disconnected meanwhile, except those of the obs backend
+    if !client::capture_enabled() {
+        return Ok(None);
+    }
+    if let Err(e) = update_window_props(swapchain) {
+        warn!("failed to update window properties: {e:?}");
+    }
+
+    let (stream, worker, streaming, depth) = {
         let ly_swapchain = SWAPCHAIN_MAP
             .get(&swapchain)
             .ok_or(vk::Result::ERROR_UNKNOWN)?;
+        // buffers of handed over stream are stale until renegotiated
+        if let Some(data) = ly_swapchain.export_data.as_ref() {
+            if data.src_format != ly_swapchain.format || data.extent != ly_swapchain.extent {
+                return Ok(None);
+            }
+        }
+        // frames of hidden windows are stale or garbage
+        if ly_swapchain
+            .window_suspended
+            .load(atomic::Ordering::Acquire)
+        {
+            trace!("window suspended, skipping frame");
+            return Ok(None);
+        }
+        // stream gets renegotiated once the swapchain got recreated
+        if ly_swapchain.stale.skip_frame() {
+            trace!("skipping frame of stale swapchain");
+            return Ok(None);
+        }
+        if let Some(pacer) = ly_swapchain.pacer.lock().unwrap().as_ref() {
+            if !pacer.pace(Instant::now()) {
+                trace!("skipping frame presented within refresh cycle");
+                return Ok(None);
+            }
+        }
+        let depth = match (
+            ly_swapchain.depth.as_ref(),
+            ly_swapchain.export_data.as_ref(),
+        ) {
+            (Some(depth), Some(data))
+                if data.depth_copier.is_some()
+                    && depth.streaming.load(atomic::Ordering::Acquire) =>
+            {
+                Some((
+                    depth.stream.proxy(),
+                    depth.buffers.clone(),
+                    ly_swapchain.extent,
+                    data.queue,
+                    data.queue_family_index,
+                ))
+            }
+            _ => None,
+        };
         match ly_swapchain.stream.as_ref() {
-            Some(v) => v.proxy(),
+            Some(v) => (
+                v.proxy(),
+                v.worker(),
+                ly_swapchain.streaming.load(atomic::Ordering::Acquire),
+                depth,
+            ),
             None => return Ok(None),
         }
     };
 
     let start = Instant::now();
 
-    let (buffer, user_handle) = match stream.try_dequeue_buffer()?? {
+    let dequeued = if streaming {
+        stream.dequeue_buffer()?
+    } else {
+        None
+    };
+    // applied while no buffer is held as consumers renegotiate on change
+    let max_buffers = match SWAPCHAIN_MAP.get(&swapchain) {
+        Some(ly_swapchain) => ly_swapchain
+            .buffer_demand
+            .lock()
+            .unwrap()
+            .update(dequeued.is_some(), start),
+        None => None,
+    };
+    let (buffer, user_handle) = match dequeued {
         Some(v) => v,
-        None => return Ok(None),
+        None => {
+            if let Some(max_buffers) = max_buffers {
+                stream.update_max_buffers(max_buffers)?;
+            }
+            // skipped frames are still being watched
+            if streaming {
+                return draw_indicator(
+                    ash_device,
+                    swapchain,
+                    image_index,
+                    present_queue,
+                    src_queue_family_index,
+                    wait_semaphores,
+                );
+            }
+            return Ok(None);
+        }
     };
     let export_image = match user_handle {
         client::BufferUserHandle::VkImage(image) => image,
         _ => unreachable!(),
     };
+    // depth is only copied along with a frame
+    let depth_frame = match (depth, depth_images, depth_capture()) {
+        (
+            Some((depth_stream, depth_buffers, extent, queue, queue_family_index)),
+            Some(depth_images),
+            Some(selector),
+        ) => match depth_images.select(
+            selector,
+            extent,
+            queue,
+            queue_family_index,
+            src_queue_family_index,
+        ) {
+            Some(target) => depth_stream.dequeue_buffer()?.map(|(buffer, user_handle)| {
+                let depth_buffer = match user_handle {
+                    client::BufferUserHandle::VkBuffer(buffer) => buffer,
+                    _ => unreachable!(),
+                };
+                (depth_stream, depth_buffers, buffer, depth_buffer, target)
+            }),
+            None => None,
+        },
+        _ => None,
+    };
     let duration = start.elapsed();
     trace!("dequeue time: {:?}", duration);
 
@@ -1615,8 +4757,9 @@ unsafe fn capture_swapchain(
         .export_data
         .as_ref()
         .ok_or(anyhow!("no format fixated"))?;
+    let src_queue_family_index =
+        ly_swapchain.owner_queue_family(src_queue_family_index, export_data.queue_family_index);
 
-    let vk::Extent2D { width, height } = ly_swapchain.extent;
     let src_image = ly_swapchain.images[image_index];
 
     let mut export_image_data = ly_swapchain
@@ -1625,60 +4768,362 @@ unsafe fn capture_swapchain(
         .ok_or(anyhow!("buffer image not found"))?;
     let export_format = export_image_data.format;
 
-    let need_blit = export_format != ly_swapchain.format;
+    let need_blit =
+        export_format != ly_swapchain.format || export_data.export_extent != ly_swapchain.extent;
 
     let mut data = ly_swapchain
         .image_datas
         .get_mut(&src_image)
         .ok_or(anyhow!("src image data removed"))?;
-    data.fence.wait_and_reset(ash_device)?;
+    // command buffers of the image are submitted or recorded again
+    data.sync.wait(ash_device)?;
 
-    let command_buffer = export_data.command_buffers[image_index];
-    ash_device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+    let valid_bits = export_data.timestamp_valid_bits;
+    let timestamp = gpu_clock
+        .zip(export_image_data.timestamp_pool)
+        .filter(|_| valid_bits > 0);
 
-    record_copy_image(
-        ash_device,
-        command_buffer,
+    let key = CopyKey {
         src_image,
         export_image,
-        src_queue_family_index,
-        export_data.queue_family_index,
-        width,
-        height,
-        need_blit,
-    )?;
+        src_queue_family: src_queue_family_index,
+        timestamp: timestamp.is_some(),
+    };
+    let mut copy_cache = export_data.copy_cache.lock().unwrap();
+    let (command_buffer, recorded) = copy_cache.get(ash_device, image_index, key)?;
+    if !recorded {
+        match (
+            export_data.nv12.as_ref(),
+            export_image_data.nv12_target.as_ref(),
+            export_data.swizzle.as_ref(),
+        ) {
+            (Some(converter), Some(target), _) => converter.record(
+                ash_device,
+                command_buffer,
+                src_image,
+                ly_swapchain.present_layout(),
+                export_image,
+                target,
+                src_queue_family_index,
+                export_data.queue_family_index,
+                timestamp.map(|(_, pool)| pool),
+            )?,
+            (_, _, Some(converter)) => converter.record(
+                ash_device,
+                command_buffer,
+                src_image,
+                ly_swapchain.present_layout(),
+                export_image,
+                src_queue_family_index,
+                export_data.queue_family_index,
+                export_image_data.host_map.is_some(),
+                timestamp.map(|(_, pool)| pool),
+            )?,
+            _ => record_copy_image(
+                ash_device,
+                command_buffer,
+                src_image,
+                ly_swapchain.present_layout(),
+                export_image,
+                src_queue_family_index,
+                export_data.queue_family_index,
+                ly_swapchain.extent,
+                export_data.export_extent,
+                export_data.export_region,
+                need_blit,
+                export_image_data.host_map.is_some(),
+                timestamp.map(|(_, pool)| pool),
+            )?,
+        }
+        copy_cache.recorded(image_index, command_buffer, key);
+    }
+    drop(copy_cache);
+    let post_process_command_buffer = match export_data.post_processor.as_ref() {
+        Some(post_processor) => Some(post_processor.record(
+            ash_device,
+            image_index,
+            export_image,
+            export_image_data.host_map.is_some(),
+        )?),
+        None => None,
+    };
+    let indicator_command_buffer = match export_data.indicator.as_ref() {
+        Some(indicator) => indicator.record(
+            ash_device,
+            image_index,
+            src_image,
+            ly_swapchain.present_layout(),
+            ly_swapchain.extent,
+            src_queue_family_index,
+            export_data.queue_family_index,
+        )?,
+        None => None,
+    };
 
-    let command_buffers = &[command_buffer];
+    let frame_seq = ly_swapchain
+        .depth
+        .as_ref()
+        .map(|depth| depth.next_seq.fetch_add(1, atomic::Ordering::Relaxed));
+    let depth_command_buffer = match (depth_frame.as_ref(), export_data.depth_copier.as_ref()) {
+        (Some((_, depth_buffers, _, depth_buffer, target)), Some(copier)) => {
+            let command_buffer = copier.record(ash_device, image_index, target, *depth_buffer)?;
+            if let Some(mut v) = depth_buffers.get_mut(depth_buffer) {
+                v.encoding = DepthEncoding::from_format(target.format);
+                v.seq = frame_seq.unwrap_or_default();
+                v.pts = None;
+            }
+            Some(command_buffer)
+        }
+        _ => None,
+    };
+
+    let host_map = export_image_data.host_map;
+    let sync_file = match (khr_semaphore_fd, data.sync_file_semaphore) {
+        (Some(khr_semaphore_fd), Some(semaphore))
+            if client::sync_file_enabled() && host_map.is_none() =>
+        {
+            Some((khr_semaphore_fd, semaphore))
+        }
+        _ => None,
+    };
+    let present_semaphore = ly_swapchain
+        .present_semaphores
+        .lock()
+        .unwrap()
+        .next_semaphore(ash_device, present_queue, image_index)?
+        .ok_or(anyhow!("no present semaphore"))?;
+    let mut signal_semaphores = vec![present_semaphore];
+    if let Some((_, semaphore)) = sync_file {
+        signal_semaphores.push(semaphore);
+    }
+
+    // indicator is drawn after the copy so captured frames never contain it
+    let mut command_buffers = vec![command_buffer];
+    command_buffers.extend(post_process_command_buffer);
+    command_buffers.extend(depth_command_buffer);
+    command_buffers.extend(indicator_command_buffer);
     let wait_stages = &[vk::PipelineStageFlags::TRANSFER];
     let submit_info = vk::SubmitInfo::builder()
-        .command_buffers(command_buffers)
+        .command_buffers(&command_buffers)
         .wait_semaphores(wait_semaphores)
-        .signal_semaphores(&data.semaphores)
+        .signal_semaphores(&signal_semaphores)
         .wait_dst_stage_mask(wait_stages)
         .build();
 
-    ash_device.queue_submit(export_data.queue, &[submit_info], data.fence.use_fence())?;
-    data.seq += 1;
-    export_image_data.src_image = (src_image, data.seq);
+    let seq = data.sync.submit(ash_device, |fence| {
+        ash_device.queue_submit(export_data.queue, &[submit_info], fence)
+    })?;
+    export_image_data.src_image = (src_image, seq);
+    export_image_data.present_pts = None;
+    export_image_data.frame_seq = frame_seq;
+    export_image_data.sync_file_attached = match sync_file {
+        Some((khr_semaphore_fd, semaphore)) => {
+            // planes share the same DMA-BUF
+            let dma_buf = export_image_data.fds[0].0;
+            match attach_sync_file(
+                ash_device,
+                khr_semaphore_fd,
+                export_data.queue,
+                semaphore,
+                dma_buf,
+            ) {
+                Ok(()) => true,
+                Err(e) => {
+                    debug!("failed to attach sync file: {e}");
+                    false
+                }
+            }
+        }
+        None => false,
+    };
 
-    let res = data.semaphores.clone();
+    let res = vec![present_semaphore];
+    // depth gets converted on the CPU once copied
+    let copy_fence = match export_image_data.sync_file_attached && depth_frame.is_none() {
+        true => None,
+        false => data.sync.fence(seq),
+    };
+    let depth_copied = depth_command_buffer.is_some();
     drop(data);
     drop(export_image_data);
     drop(ly_swapchain);
 
-    let start = Instant::now();
-    stream.try_queue_buffer_process(buffer)???;
-    let duration = start.elapsed();
-    trace!("process time: {:?}", duration);
+    // consumers would block on a copy waiting for timeline values the app may
+    // signal from this very thread later on, so the worker waits for them
+    let timeline_waits = timeline_waits.map(|(timeline, waits)| (timeline.clone(), waits.to_vec()));
+    let present_wait = present_wait.map(|(khr_present_wait, id)| (khr_present_wait.clone(), id));
+    let timestamp = timestamp.map(|(gpu_clock, pool)| (gpu_clock.clone(), pool, valid_bits));
+    let ash_device = ash_device.clone();
+    // waits happen here rather than when the consumer processes the frame,
+    // blocking its data thread would make the graph miss deadlines
+    let queue_buffer = move || -> Result<()> {
+        if let Some((timeline, waits)) = timeline_waits {
+            trace!("waiting for timeline semaphores");
+            timeline.wait_reached(&waits);
+        }
+        // fence is held so it does not get reused meanwhile
+        if let Some(fence) = copy_fence {
+            match ash_device.wait_for_fences(&[*fence], true, COPY_WAIT_TIMEOUT) {
+                Ok(()) => {
+                    if let Some(host_map) = host_map {
+                        host_map.copy();
+                    }
+                    if let Some((_, depth_buffers, _, depth_buffer, _)) =
+                        depth_frame.as_ref().filter(|_| depth_copied)
+                    {
+                        // held so the buffer is not removed meanwhile
+                        if let Some(v) = depth_buffers.get(depth_buffer) {
+                            if let Some(encoding) = v.encoding {
+                                v.map.convert(encoding);
+                            }
+                        }
+                    }
+                }
+                Err(e) => trace!("failed to wait for copy: {e:?}"),
+            }
+        }
+        // copy started once the app finished rendering, the frame gets
+        // presented shortly after
+        let copy_pts = timestamp.and_then(|(gpu_clock, pool, valid_bits)| {
+            let mut ticks = [0u64];
+            match ash_device.get_query_pool_results(
+                pool,
+                0,
+                1,
+                &mut ticks,
+                vk::QueryResultFlags::TYPE_64,
+            ) {
+                Ok(()) => gpu_clock.to_monotonic(ticks[0], valid_bits),
+                Err(e) => {
+                    trace!("timestamp of copy not available: {e:?}");
+                    None
+                }
+            }
+        });
+        // stamps the frame with the time it actually got presented
+        let present_pts = present_wait.and_then(|(khr_present_wait, present_id)| {
+            match khr_present_wait.wait_for_present(swapchain, present_id, PRESENT_WAIT_TIMEOUT) {
+                Ok(()) => Some(client::get_pts_nanos()),
+                Err(e) => {
+                    trace!("failed to wait for present {present_id}: {e:?}");
+                    None
+                }
+            }
+        });
+        if let Some(pts) = present_pts.or(copy_pts) {
+            if let Some(ly_swapchain) = SWAPCHAIN_MAP.get(&swapchain) {
+                if let Some(mut export_image_data) =
+                    ly_swapchain.export_images.get_mut(&export_image)
+                {
+                    export_image_data.present_pts = Some(pts);
+                }
+            }
+            if let Some((_, depth_buffers, _, depth_buffer, _)) = depth_frame.as_ref() {
+                if let Some(mut v) = depth_buffers.get_mut(depth_buffer) {
+                    v.pts = Some(pts);
+                }
+            }
+        }
+
+        let start = Instant::now();
+        stream.queue_buffer_process(buffer)?;
+        let duration = start.elapsed();
+        trace!("process time: {:?}", duration);
+        if let Some((depth_stream, _, depth_handle, _, _)) = depth_frame {
+            depth_stream.queue_buffer_process(depth_handle)?;
+        }
+
+        if let Some(max_buffers) = max_buffers {
+            stream.update_max_buffers(max_buffers)?;
+        }
+        Ok(())
+    };
+    worker.submit(move || {
+        if let Err(e) = queue_buffer() {
+            error!("failed to queue frame: {e:?}");
+        }
+    });
 
     Ok(Some(res))
 }
 
+/// Draws the capture indicator onto a presented image that is not captured
+unsafe fn draw_indicator(
+    ash_device: &ash::Device,
+    swapchain: vk::SwapchainKHR,
+    image_index: usize,
+    present_queue: vk::Queue,
+    src_queue_family_index: u32,
+    wait_semaphores: &[vk::Semaphore],
+) -> Result<Option<Vec<vk::Semaphore>>> {
+    let ly_swapchain = SWAPCHAIN_MAP
+        .get(&swapchain)
+        .ok_or(vk::Result::ERROR_UNKNOWN)?;
+    let export_data = match ly_swapchain.export_data.as_ref() {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let indicator = match export_data.indicator.as_ref() {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let src_queue_family_index =
+        ly_swapchain.owner_queue_family(src_queue_family_index, export_data.queue_family_index);
+
+    let src_image = ly_swapchain.images[image_index];
+    let mut data = ly_swapchain
+        .image_datas
+        .get_mut(&src_image)
+        .ok_or(anyhow!("src image data removed"))?;
+    data.sync.wait(ash_device)?;
+
+    let command_buffer = match indicator.record(
+        ash_device,
+        image_index,
+        src_image,
+        ly_swapchain.present_layout(),
+        ly_swapchain.extent,
+        src_queue_family_index,
+        export_data.queue_family_index,
+    )? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    let present_semaphore = ly_swapchain
+        .present_semaphores
+        .lock()
+        .unwrap()
+        .next_semaphore(ash_device, present_queue, image_index)?
+        .ok_or(anyhow!("no present semaphore"))?;
+    let command_buffers = &[command_buffer];
+    let signal_semaphores = &[present_semaphore];
+    let wait_stages = &[vk::PipelineStageFlags::TRANSFER];
+    let submit_info = vk::SubmitInfo::builder()
+        .command_buffers(command_buffers)
+        .wait_semaphores(wait_semaphores)
+        .signal_semaphores(signal_semaphores)
+        .wait_dst_stage_mask(wait_stages)
+        .build();
+
+    data.sync.submit(ash_device, |fence| {
+        ash_device.queue_submit(export_data.queue, &[submit_info], fence)
+    })?;
+
+    Ok(Some(vec![present_semaphore]))
+}
+
 #[named]
 unsafe fn capture(
     ash_device: &ash::Device,
+    khr_semaphore_fd: Option<&khr::ExternalSemaphoreFd>,
+    queue: vk::Queue,
     src_queue_family_index: u32,
     present_info: &vk::PresentInfoKHR,
+    present_wait: Option<(&khr::PresentWait, &[u64])>,
+    timeline: Option<&Arc<TimelineSemaphores>>,
+    gpu_clock: Option<&Arc<GpuClock>>,
+    depth_images: Option<&DepthImages>,
 ) -> Vec<vk::Semaphore> {
     let &vk::PresentInfoKHR {
         p_swapchains,
@@ -1693,15 +5138,42 @@ unsafe fn capture(
     let image_indices = slice::from_raw_parts(p_image_indices, swapchain_count as _);
     let wait_semaphores_old = slice::from_raw_parts(p_wait_semaphores, wait_semaphore_count as _);
 
+    // frames depending on timeline values not signaled yet are handed over
+    // to consumers once those got reached
+    let pending_waits = match timeline {
+        Some(timeline) => {
+            let waits = timeline.take_waits(wait_semaphores_old);
+            match timeline.is_reached(&waits) {
+                Ok(true) => None,
+                Ok(false) => Some((timeline, waits)),
+                Err(e) => {
+                    error!("failed to query timeline semaphores: {e:?}");
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
     let mut wait_semaphores_new = vec![];
 
     for i in 0..swapchains.len() {
         let res = capture_swapchain(
             ash_device,
+            khr_semaphore_fd,
             swapchains[i],
             image_indices[i] as _,
+            queue,
             src_queue_family_index,
             wait_semaphores_old,
+            pending_waits
+                .as_ref()
+                .map(|(timeline, waits)| (*timeline, &waits[..])),
+            present_wait
+                .map(|(khr_present_wait, ids)| (khr_present_wait, ids[i]))
+                .filter(|&(_, id)| id > 0),
+            gpu_clock,
+            depth_images,
         );
         match res {
             Ok(Some(v)) => wait_semaphores_new.extend(&v),
@@ -1715,3 +5187,84 @@ unsafe fn capture(
 
     wait_semaphores_new
 }
+
+/// Captures `SHARED_CONTINUOUS_REFRESH_KHR` swapchains presented from `queue`
+/// after the app submitted to it, as their image may be updated without ever
+/// being presented again
+#[named]
+unsafe fn capture_continuous(ly_device: &LayerDevice, queue: vk::Queue, queue_family_index: u32) {
+    if CONTINUOUS_SWAPCHAINS.load(atomic::Ordering::Relaxed) == 0 {
+        return;
+    }
+    let Some(valid) = ly_device.valid.as_ref() else {
+        return;
+    };
+
+    let now = Instant::now();
+    let due: Vec<_> = SWAPCHAIN_MAP
+        .iter()
+        .filter_map(|ly_swapchain| {
+            let shared = ly_swapchain.shared_present.as_ref()?;
+            let due = shared.continuous
+                && *shared.queue.lock().unwrap() == Some(queue)
+                && ly_swapchain.streaming.load(atomic::Ordering::Acquire)
+                && ly_swapchain
+                    .pacer
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map_or(true, |p| p.is_due(now));
+            due.then_some((*ly_swapchain.key(), shared.semaphore))
+        })
+        .collect();
+
+    for (swapchain, semaphore) in due {
+        // copy waits for the work submitted so far
+        let signal_semaphores = &[semaphore];
+        let submit_info = vk::SubmitInfo::builder()
+            .signal_semaphores(signal_semaphores)
+            .build();
+        if let Err(e) = ly_device
+            .ash_device
+            .queue_submit(queue, &[submit_info], vk::Fence::null())
+        {
+            error!("failed to signal submitted work: {e:?}");
+            continue;
+        }
+
+        let res = capture_swapchain(
+            &ly_device.ash_device,
+            valid.khr_semaphore_fd.as_ref(),
+            swapchain,
+            0,
+            queue,
+            queue_family_index,
+            &[semaphore],
+            None,
+            None,
+            valid.gpu_clock.as_ref(),
+            ly_device.depth_images.as_ref(),
+        );
+        let wait_semaphores = match res {
+            Ok(Some(v)) => v,
+            Ok(None) => vec![semaphore],
+            Err(e) => {
+                error!("failed to capture swapchain: {e:?}");
+                vec![semaphore]
+            }
+        };
+        // no present waits for these, while later work of the app must not
+        // update the image before it got copied
+        let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .build();
+        if let Err(e) = ly_device
+            .ash_device
+            .queue_submit(queue, &[submit_info], vk::Fence::null())
+        {
+            error!("failed to wait for capture: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Alpha channel of exported frames
+
+use crate::utils::*;
+
+use ash::vk;
+use function_name::named;
+use once_cell::sync::Lazy;
+use pw_capture_client::{setting, Format};
+
+static ALPHA_MODE: Lazy<AlphaMode> = Lazy::new(AlphaMode::from_env);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Alpha is exported as rendered
+    #[default]
+    Keep,
+    /// Frames are exported opaque
+    Opaque,
+}
+
+impl AlphaMode {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "keep" => Some(Self::Keep),
+            "opaque" => Some(Self::Opaque),
+            _ => None,
+        }
+    }
+
+    #[named]
+    fn from_env() -> Self {
+        let Some(value) = setting("PW_CAPTURE_ALPHA") else {
+            return Self::default();
+        };
+        let mode = Self::parse(&value).unwrap_or_else(|| {
+            warn!("invalid PW_CAPTURE_ALPHA {value:?}");
+            Self::default()
+        });
+        debug!("alpha mode: {mode:?}");
+        mode
+    }
+
+    pub fn global() -> Self {
+        *ALPHA_MODE
+    }
+
+    /// Format offered for frames of `format`
+    pub fn apply(self, format: Format) -> Format {
+        match self {
+            Self::Keep => format,
+            Self::Opaque => format.opaque(),
+        }
+    }
+
+    /// Whether captures of swapchains presenting with `composite_alpha` may
+    /// turn out translucent
+    pub fn is_translucent(self, composite_alpha: vk::CompositeAlphaFlagsKHR) -> bool {
+        self == Self::Keep
+            && matches!(
+                composite_alpha,
+                vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED
+                    | vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(AlphaMode::parse("keep"), Some(AlphaMode::Keep));
+        assert_eq!(AlphaMode::parse(" Opaque "), Some(AlphaMode::Opaque));
+        assert_eq!(AlphaMode::parse("premultiplied"), None);
+    }
+
+    #[test]
+    fn apply() {
+        assert_eq!(AlphaMode::Keep.apply(Format::BGRA), Format::BGRA);
+        assert_eq!(AlphaMode::Opaque.apply(Format::BGRA), Format::BGRx);
+        assert_eq!(AlphaMode::Opaque.apply(Format::RGBA_F16), Format::RGBA_F16);
+        assert!(AlphaMode::Keep.is_translucent(vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED));
+        assert!(!AlphaMode::Opaque.is_translucent(vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED));
+        assert!(!AlphaMode::Keep.is_translucent(vk::CompositeAlphaFlagsKHR::OPAQUE));
+    }
+}
@@ -0,0 +1,201 @@
+//! Reusing recorded copy commands
+
+use crate::utils::*;
+
+use ash::prelude::VkResult;
+use ash::vk;
+
+/// What copy commands were recorded for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CopyKey {
+    pub src_image: vk::Image,
+    pub export_image: vk::Image,
+    /// Queue family the swapchain image is released from
+    pub src_queue_family: u32,
+    pub timestamp: bool,
+}
+
+/// Copy command buffers of a swapchain, allocated as needed
+pub struct CopyCache {
+    command_pool: vk::CommandPool,
+    /// Command buffers by swapchain image index, least recently used first,
+    /// along with what they hold commands for
+    slots: Vec<Vec<(vk::CommandBuffer, Option<CopyKey>)>>,
+    /// Command buffers kept per swapchain image at most
+    limit: usize,
+    allocator: Allocator,
+}
+
+impl CopyCache {
+    pub unsafe fn new(
+        ash_device: &ash::Device,
+        queue_family_index: u32,
+        limit: usize,
+        allocator: Allocator,
+    ) -> VkResult<Self> {
+        let cmd_pool_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool = ash_device.create_command_pool(&cmd_pool_info, allocator.callbacks())?;
+        Ok(Self {
+            command_pool,
+            slots: Vec::new(),
+            limit,
+            allocator,
+        })
+    }
+
+    pub fn command_pool(&self) -> vk::CommandPool {
+        self.command_pool
+    }
+
+    /// Command buffer of swapchain image `image_index` for `key`, and whether
+    /// it already holds the commands. Command buffers to record into are
+    /// reset, and only reused once [`recorded`](Self::recorded) got called.
+    ///
+    /// Previous submits of the swapchain image are expected to have completed.
+    pub unsafe fn get(
+        &mut self,
+        ash_device: &ash::Device,
+        image_index: usize,
+        key: CopyKey,
+    ) -> VkResult<(vk::CommandBuffer, bool)> {
+        if let Some(command_buffer) = self.find(image_index, &key) {
+            return Ok((command_buffer, true));
+        }
+        let command_buffer = match self.pick(image_index) {
+            Some(index) => {
+                let slots = &mut self.slots[image_index];
+                let (command_buffer, _) = slots.remove(index);
+                slots.push((command_buffer, None));
+                ash_device
+                    .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+                command_buffer
+            }
+            None => {
+                let cmd_buffers_info = vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(self.command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1);
+                let command_buffer = ash_device.allocate_command_buffers(&cmd_buffers_info)?[0];
+                self.slots[image_index].push((command_buffer, None));
+                command_buffer
+            }
+        };
+        Ok((command_buffer, false))
+    }
+
+    /// Marks `command_buffer` of swapchain image `image_index` as holding
+    /// commands for `key`
+    pub fn recorded(
+        &mut self,
+        image_index: usize,
+        command_buffer: vk::CommandBuffer,
+        key: CopyKey,
+    ) {
+        let slot = self.slots[image_index]
+            .iter_mut()
+            .find(|(v, _)| *v == command_buffer);
+        if let Some((_, slot_key)) = slot {
+            *slot_key = Some(key);
+        }
+    }
+
+    /// Forgets commands copying into `export_image`
+    pub fn forget(&mut self, export_image: vk::Image) {
+        for (_, key) in self.slots.iter_mut().flatten() {
+            if matches!(key, Some(v) if v.export_image == export_image) {
+                *key = None;
+            }
+        }
+    }
+
+    /// Forgets all commands, e.g. once swapchain images or the format
+    /// changed
+    pub fn invalidate(&mut self) {
+        for (_, key) in self.slots.iter_mut().flatten() {
+            *key = None;
+        }
+    }
+
+    pub unsafe fn destroy(&self, ash_device: &ash::Device) {
+        // command buffers are freed along with the pool
+        ash_device.destroy_command_pool(self.command_pool, self.allocator.callbacks());
+    }
+
+    /// Command buffer holding commands for `key`, marked most recently used
+    fn find(&mut self, image_index: usize, key: &CopyKey) -> Option<vk::CommandBuffer> {
+        if self.slots.len() <= image_index {
+            self.slots.resize_with(image_index + 1, Vec::new);
+        }
+        let slots = &mut self.slots[image_index];
+        let index = slots.iter().position(|(_, v)| v.as_ref() == Some(key))?;
+        let slot = slots.remove(index);
+        slots.push(slot);
+        Some(slot.0)
+    }
+
+    /// Index of a command buffer to record into, one holding nothing or the
+    /// least recently used one, `None` if another should be allocated
+    fn pick(&self, image_index: usize) -> Option<usize> {
+        let slots = &self.slots[image_index];
+        match slots.iter().position(|(_, key)| key.is_none()) {
+            Some(index) => Some(index),
+            None if slots.len() < self.limit => None,
+            None => Some(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ash::vk::Handle;
+
+    fn key(src_image: u64, export_image: u64) -> CopyKey {
+        CopyKey {
+            src_image: vk::Image::from_raw(src_image),
+            export_image: vk::Image::from_raw(export_image),
+            src_queue_family: 0,
+            timestamp: false,
+        }
+    }
+
+    fn cache(slots: Vec<Vec<(vk::CommandBuffer, Option<CopyKey>)>>) -> CopyCache {
+        CopyCache {
+            command_pool: vk::CommandPool::null(),
+            slots,
+            limit: 2,
+            allocator: Allocator::default(),
+        }
+    }
+
+    #[test]
+    fn reuse_commands() {
+        let cb = vk::CommandBuffer::from_raw;
+        let mut cache = cache(vec![vec![
+            (cb(1), Some(key(1, 10))),
+            (cb(2), Some(key(1, 11))),
+        ]]);
+
+        assert_eq!(cache.find(0, &key(1, 10)), Some(cb(1)));
+        // pair of another swapchain image
+        assert_eq!(cache.find(1, &key(1, 10)), None);
+        assert_eq!(cache.pick(1), None);
+        // least recently used one gets recorded again
+        assert_eq!(cache.find(0, &key(1, 12)), None);
+        assert_eq!(cache.pick(0), Some(0));
+        assert_eq!(cache.slots[0][0].0, cb(2));
+
+        cache.forget(vk::Image::from_raw(10));
+        assert_eq!(cache.find(0, &key(1, 10)), None);
+        assert_eq!(cache.pick(0), Some(1));
+        cache.recorded(0, cb(1), key(2, 10));
+        assert_eq!(cache.find(0, &key(2, 10)), Some(cb(1)));
+
+        cache.invalidate();
+        assert_eq!(cache.find(0, &key(1, 11)), None);
+        assert_eq!(cache.pick(0), Some(0));
+    }
+}
@@ -0,0 +1,841 @@
+//! Depth buffer of the app copied along with captured frames to a stream of
+//! its own
+
+use crate::utils::*;
+
+use core::slice;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use ash::prelude::VkResult;
+use ash::vk;
+use function_name::named;
+use once_cell::sync::Lazy;
+use pw_capture_client::setting;
+
+/// Bytes per texel of buffers depth gets copied into, enough for any format
+const MAX_DEPTH_TEXEL_SIZE: usize = 4;
+
+static DEPTH_SELECTOR: Lazy<Option<DepthSelector>> = Lazy::new(DepthSelector::from_env);
+
+/// How the depth attachment to capture is picked
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DepthSelector {
+    /// Last created one of the swapchain size
+    Auto,
+    /// Last one the app gave this debug name
+    Named(String),
+}
+
+impl DepthSelector {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "" | "0" => None,
+            "1" => Some(Self::Auto),
+            name => Some(Self::Named(name.to_owned())),
+        }
+    }
+
+    #[named]
+    fn from_env() -> Option<Self> {
+        let value = setting("PW_CAPTURE_DEPTH")?;
+        let selector = Self::parse(&value);
+        debug!("depth capture: {selector:?}");
+        selector
+    }
+}
+
+/// Set by `PW_CAPTURE_DEPTH`, `None` if depth is not captured
+pub fn depth_capture() -> Option<&'static DepthSelector> {
+    DEPTH_SELECTOR.as_ref()
+}
+
+/// Encoding of texels copied out of the depth aspect of a format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthEncoding {
+    Unorm16,
+    /// Lower 24 bits of 32, upper ones are undefined
+    Unorm24,
+    Float32,
+}
+
+impl DepthEncoding {
+    pub fn from_format(format: vk::Format) -> Option<Self> {
+        match format {
+            vk::Format::D16_UNORM | vk::Format::D16_UNORM_S8_UINT => Some(Self::Unorm16),
+            vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D24_UNORM_S8_UINT => Some(Self::Unorm24),
+            vk::Format::D32_SFLOAT | vk::Format::D32_SFLOAT_S8_UINT => Some(Self::Float32),
+            _ => None,
+        }
+    }
+
+    fn texel_size(self) -> usize {
+        match self {
+            Self::Unorm16 => 2,
+            Self::Unorm24 | Self::Float32 => 4,
+        }
+    }
+
+    fn to_unorm16(self, texel: &[u8]) -> u16 {
+        match self {
+            Self::Unorm16 => u16::from_le_bytes([texel[0], texel[1]]),
+            Self::Unorm24 => {
+                let v = u32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]]);
+                ((v & 0xff_ffff) >> 8) as u16
+            }
+            Self::Float32 => {
+                let v = f32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]]);
+                // NaN ends up as 0
+                (v.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+            }
+        }
+    }
+
+    /// Converts tightly packed texels of `src` into `GRAY16_LE` in `dst`
+    pub fn convert(self, src: &[u8], dst: &mut [u8]) {
+        for (texel, out) in src
+            .chunks_exact(self.texel_size())
+            .zip(dst.chunks_exact_mut(2))
+        {
+            out.copy_from_slice(&self.to_unorm16(texel).to_le_bytes());
+        }
+    }
+}
+
+/// Aspects barriers on images of `format` cover, both of combined
+/// depth/stencil formats
+fn barrier_aspects(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
+
+/// Depth attachment picked for a capture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DepthTarget {
+    pub image: vk::Image,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub sharing_mode: vk::SharingMode,
+    /// Layout the app left the image in
+    pub layout: vk::ImageLayout,
+    /// Queue family the app last transferred ownership of the image to
+    pub queue_family: Option<u32>,
+}
+
+impl DepthTarget {
+    /// Whether copies on `queue_family` may read the image without taking
+    /// ownership of it, images never transferred being owned by
+    /// `present_queue_family`
+    pub fn readable_from(&self, queue_family: u32, present_queue_family: u32) -> bool {
+        self.sharing_mode == vk::SharingMode::CONCURRENT
+            || self.queue_family.unwrap_or(present_queue_family) == queue_family
+    }
+}
+
+#[derive(Clone, Debug)]
+struct DepthImage {
+    target: DepthTarget,
+    name: Option<String>,
+    /// Queue the image was last copied on
+    captured_on: Option<vk::Queue>,
+    /// Set once a barrier or render pass of the app transitioned the image
+    layout_known: bool,
+}
+
+/// Tracks depth attachments of a device
+#[derive(Debug, Default)]
+pub struct DepthImages {
+    /// In order of creation
+    images: Mutex<Vec<DepthImage>>,
+    /// Image of each view of a tracked image
+    views: Mutex<HashMap<vk::ImageView, vk::Image>>,
+    /// Final layout of each attachment of render passes
+    render_passes: Mutex<HashMap<vk::RenderPass, Vec<vk::ImageLayout>>>,
+    /// Tracked image of each attachment of framebuffers having one
+    framebuffers: Mutex<HashMap<vk::Framebuffer, Vec<Option<vk::Image>>>>,
+}
+
+impl DepthImages {
+    /// Usage an image of `create_info` gets created with so it can be
+    /// captured, `None` if it is not a depth attachment that can be
+    pub fn capture_usage(create_info: &vk::ImageCreateInfo) -> Option<vk::ImageUsageFlags> {
+        let capturable = create_info
+            .usage
+            .contains(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+            && create_info.image_type == vk::ImageType::TYPE_2D
+            // multisampled images can not be copied
+            && create_info.samples == vk::SampleCountFlags::TYPE_1
+            && !create_info.flags.contains(vk::ImageCreateFlags::PROTECTED)
+            && DepthEncoding::from_format(create_info.format).is_some();
+        capturable.then_some(create_info.usage | vk::ImageUsageFlags::TRANSFER_SRC)
+    }
+
+    pub fn add_image(&self, image: vk::Image, create_info: &vk::ImageCreateInfo) {
+        let target = DepthTarget {
+            image,
+            format: create_info.format,
+            extent: vk::Extent2D {
+                width: create_info.extent.width,
+                height: create_info.extent.height,
+            },
+            sharing_mode: create_info.sharing_mode,
+            layout: create_info.initial_layout,
+            queue_family: None,
+        };
+        self.images.lock().unwrap().push(DepthImage {
+            target,
+            name: None,
+            captured_on: None,
+            layout_known: false,
+        });
+    }
+
+    /// Forgets `image`, returns the queue it was last copied on
+    pub fn remove_image(&self, image: vk::Image) -> Option<vk::Queue> {
+        let mut images = self.images.lock().unwrap();
+        let index = images.iter().position(|v| v.target.image == image)?;
+        images.remove(index).captured_on
+    }
+
+    pub fn set_name(&self, image: vk::Image, name: &str) {
+        let mut images = self.images.lock().unwrap();
+        if let Some(v) = images.iter_mut().find(|v| v.target.image == image) {
+            v.name = Some(name.to_owned());
+        }
+    }
+
+    pub fn add_view(&self, view: vk::ImageView, image: vk::Image) {
+        let images = self.images.lock().unwrap();
+        if images.iter().any(|v| v.target.image == image) {
+            self.views.lock().unwrap().insert(view, image);
+        }
+    }
+
+    pub fn remove_view(&self, view: vk::ImageView) {
+        self.views.lock().unwrap().remove(&view);
+    }
+
+    pub fn add_render_pass(
+        &self,
+        render_pass: vk::RenderPass,
+        final_layouts: Vec<vk::ImageLayout>,
+    ) {
+        if !final_layouts.is_empty() {
+            let mut render_passes = self.render_passes.lock().unwrap();
+            render_passes.insert(render_pass, final_layouts);
+        }
+    }
+
+    pub fn remove_render_pass(&self, render_pass: vk::RenderPass) {
+        self.render_passes.lock().unwrap().remove(&render_pass);
+    }
+
+    /// Records tracked images among `attachments` of `framebuffer`, imageless
+    /// framebuffers get their attachments as render passes begin
+    pub fn add_framebuffer(&self, framebuffer: vk::Framebuffer, attachments: &[vk::ImageView]) {
+        let images = self.attachment_images(attachments);
+        if images.iter().any(Option::is_some) {
+            self.framebuffers
+                .lock()
+                .unwrap()
+                .insert(framebuffer, images);
+        }
+    }
+
+    pub fn remove_framebuffer(&self, framebuffer: vk::Framebuffer) {
+        self.framebuffers.lock().unwrap().remove(&framebuffer);
+    }
+
+    fn attachment_images(&self, attachments: &[vk::ImageView]) -> Vec<Option<vk::Image>> {
+        let views = self.views.lock().unwrap();
+        attachments.iter().map(|v| views.get(v).copied()).collect()
+    }
+
+    /// Records a barrier of the app on `image` transitioning it into
+    /// `layout` and transferring it to `queue_family` if not ignored
+    pub fn barrier(&self, image: vk::Image, layout: vk::ImageLayout, queue_family: u32) {
+        let mut images = self.images.lock().unwrap();
+        if let Some(v) = images.iter_mut().find(|v| v.target.image == image) {
+            v.target.layout = layout;
+            v.layout_known = true;
+            if queue_family != vk::QUEUE_FAMILY_IGNORED {
+                v.target.queue_family = Some(queue_family);
+            }
+        }
+    }
+
+    /// Records a render pass of the app transitioning tracked attachments of
+    /// `framebuffer`, or `attachments` of imageless ones, into their final
+    /// layouts
+    pub fn begin_render_pass(
+        &self,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        attachments: Option<&[vk::ImageView]>,
+    ) {
+        let final_layouts = match self.render_passes.lock().unwrap().get(&render_pass) {
+            Some(v) => v.clone(),
+            None => return,
+        };
+        let images = match attachments {
+            Some(v) => self.attachment_images(v),
+            None => match self.framebuffers.lock().unwrap().get(&framebuffer) {
+                Some(v) => v.clone(),
+                None => return,
+            },
+        };
+        for (image, layout) in images.into_iter().zip(final_layouts) {
+            if let Some(image) = image {
+                self.barrier(image, layout, vk::QUEUE_FAMILY_IGNORED);
+            }
+        }
+    }
+
+    /// Depth attachment to copy along with a frame of `extent` on `queue`,
+    /// `None` if its layout is not known or leaves its content undefined
+    pub fn select(
+        &self,
+        selector: &DepthSelector,
+        extent: vk::Extent2D,
+        queue: vk::Queue,
+        queue_family: u32,
+        present_queue_family: u32,
+    ) -> Option<DepthTarget> {
+        let mut images = self.images.lock().unwrap();
+        let image = images.iter_mut().rev().find(|v| {
+            v.target.extent == extent
+                && match selector {
+                    DepthSelector::Auto => true,
+                    DepthSelector::Named(name) => v.name.as_ref() == Some(name),
+                }
+        })?;
+        let undefined = matches!(
+            image.target.layout,
+            vk::ImageLayout::UNDEFINED | vk::ImageLayout::PREINITIALIZED
+        );
+        if !image.layout_known || undefined {
+            return None;
+        }
+        // ownership can't be taken without a release of the app
+        if !image
+            .target
+            .readable_from(queue_family, present_queue_family)
+        {
+            return None;
+        }
+        image.captured_on = Some(queue);
+        Some(image.target)
+    }
+}
+
+/// Mappings of the buffer depth gets copied into and of the memfd it gets
+/// converted into, as addresses so they can be converted on stream workers
+#[derive(Clone, Copy, Debug)]
+pub struct DepthMap {
+    src: usize,
+    dst: usize,
+    texels: usize,
+}
+
+impl DepthMap {
+    /// Converts the frame, the copy into the buffer must have completed
+    pub unsafe fn convert(&self, encoding: DepthEncoding) {
+        let src = slice::from_raw_parts(self.src as *const u8, self.texels * encoding.texel_size());
+        let dst = slice::from_raw_parts_mut(self.dst as *mut u8, self.texels * 2);
+        encoding.convert(src, dst);
+    }
+}
+
+/// Host buffer a depth frame gets copied into, exported as memfd
+pub struct DepthBuffer {
+    pub buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    pub extent: vk::Extent2D,
+    pub memfd: i32,
+    pub map: DepthMap,
+    /// Encoding of the depth last copied
+    pub encoding: Option<DepthEncoding>,
+    /// Sequence number of the frame last copied
+    pub seq: u64,
+    pub pts: Option<i64>,
+}
+
+impl DepthBuffer {
+    pub unsafe fn new(
+        ash_instance: &ash::Instance,
+        ash_device: &ash::Device,
+        phy_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        allocator: Option<&vk::AllocationCallbacks>,
+    ) -> Result<Self> {
+        let texels = extent.width as usize * extent.height as usize;
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size((texels * MAX_DEPTH_TEXEL_SIZE) as _)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = ash_device.create_buffer(&buffer_info, allocator)?;
+
+        let requirements = ash_device.get_buffer_memory_requirements(buffer);
+        let host_flags =
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        // cached memory is way faster to read from
+        let index = get_memory_type_indices(
+            ash_instance,
+            phy_device,
+            host_flags | vk::MemoryPropertyFlags::HOST_CACHED,
+            requirements,
+        )
+        .into_iter()
+        .chain(get_memory_type_indices(
+            ash_instance,
+            phy_device,
+            host_flags,
+            requirements,
+        ))
+        .next();
+        let Some(index) = index else {
+            ash_device.destroy_buffer(buffer, allocator);
+            return Err(anyhow!("no host memory type for depth buffer"));
+        };
+        let memory_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(index);
+        let memory = match ash_device.allocate_memory(&memory_info, allocator) {
+            Ok(v) => v,
+            Err(e) => {
+                ash_device.destroy_buffer(buffer, allocator);
+                return Err(e.into());
+            }
+        };
+
+        let res = (|| -> Result<Self> {
+            ash_device.bind_buffer_memory(buffer, memory, 0)?;
+            let src =
+                ash_device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())?;
+            let (memfd, dst) = create_memfd(texels * 2)?;
+            Ok(Self {
+                buffer,
+                memory,
+                extent,
+                memfd,
+                map: DepthMap {
+                    src: src as usize,
+                    dst: dst as usize,
+                    texels,
+                },
+                encoding: None,
+                seq: 0,
+                pts: None,
+            })
+        })();
+        if res.is_err() {
+            ash_device.destroy_buffer(buffer, allocator);
+            ash_device.free_memory(memory, allocator);
+        }
+        res
+    }
+
+    /// Row stride of the exported `GRAY16_LE` frame
+    pub fn stride(&self) -> u32 {
+        self.extent.width * 2
+    }
+
+    pub fn size(&self) -> usize {
+        self.map.texels * 2
+    }
+
+    pub unsafe fn destroy(
+        self,
+        ash_device: &ash::Device,
+        allocator: Option<&vk::AllocationCallbacks>,
+    ) {
+        libc::munmap(self.map.dst as _, self.size());
+        libc::close(self.memfd);
+        ash_device.destroy_buffer(self.buffer, allocator);
+        ash_device.free_memory(self.memory, allocator);
+    }
+}
+
+/// Copies depth attachments into depth buffers
+///
+/// Commands are submitted along with the copy of the frame, on a queue of
+/// `queue_family_index` which must support graphics as depth attachments
+/// can not be synchronized on transfer only queues.
+pub struct DepthCopier {
+    pub queue_family_index: u32,
+    command_pool: vk::CommandPool,
+    /// One per swapchain image
+    command_buffers: Vec<vk::CommandBuffer>,
+    allocator: Allocator,
+}
+
+impl DepthCopier {
+    pub unsafe fn new(
+        ash_device: &ash::Device,
+        queue_family_index: u32,
+        num_images: usize,
+        allocator: Allocator,
+    ) -> VkResult<Self> {
+        let cmd_pool_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        let command_pool = ash_device.create_command_pool(&cmd_pool_info, allocator.callbacks())?;
+        let mut copier = Self {
+            queue_family_index,
+            command_pool,
+            command_buffers: vec![],
+            allocator,
+        };
+        if let Err(e) = copier.reserve(ash_device, num_images) {
+            copier.destroy(ash_device);
+            return Err(e);
+        }
+        Ok(copier)
+    }
+
+    /// Allocates command buffers for swapchains of up to `num_images` images
+    pub unsafe fn reserve(&mut self, ash_device: &ash::Device, num_images: usize) -> VkResult<()> {
+        if self.command_buffers.len() >= num_images {
+            return Ok(());
+        }
+        let cmd_buffers_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count((num_images - self.command_buffers.len()) as _);
+        let cmd_buffers = ash_device.allocate_command_buffers(&cmd_buffers_info)?;
+        self.command_buffers.extend(cmd_buffers);
+        Ok(())
+    }
+
+    /// Records copying depth of `target` into `buffer` along with the frame
+    /// of swapchain image `image_index`, `target` must be readable from
+    /// `queue_family_index` and is left in the layout it was tracked in
+    pub unsafe fn record(
+        &self,
+        ash_device: &ash::Device,
+        image_index: usize,
+        target: &DepthTarget,
+        buffer: vk::Buffer,
+    ) -> VkResult<vk::CommandBuffer> {
+        let command_buffer = self.command_buffers[image_index];
+        ash_device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        ash_device.begin_command_buffer(command_buffer, &begin_info)?;
+
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(barrier_aspects(target.format))
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let src_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(target.layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(target.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build();
+
+        // transfer stage chains with the present semaphores the copy waits on
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS | vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[src_barrier],
+        );
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: target.extent.width,
+                height: target.extent.height,
+                depth: 1,
+            })
+            .build();
+        ash_device.cmd_copy_image_to_buffer(
+            command_buffer,
+            target.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[region],
+        );
+
+        // depth writes of the next frame wait for the copy to read
+        let dst_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(target.layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(target.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                    | vk::AccessFlags::TRANSFER_WRITE,
+            )
+            .build();
+        let host_barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::ALL_COMMANDS | vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[host_barrier],
+            &[dst_barrier],
+        );
+
+        ash_device.end_command_buffer(command_buffer)?;
+
+        Ok(command_buffer)
+    }
+
+    pub unsafe fn destroy(&self, ash_device: &ash::Device) {
+        if !self.command_buffers.is_empty() {
+            ash_device.free_command_buffers(self.command_pool, &self.command_buffers);
+        }
+        ash_device.destroy_command_pool(self.command_pool, self.allocator.callbacks());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ash::vk::Handle;
+
+    #[test]
+    fn parse() {
+        assert_eq!(DepthSelector::parse("0"), None);
+        assert_eq!(DepthSelector::parse(""), None);
+        assert_eq!(DepthSelector::parse("1"), Some(DepthSelector::Auto));
+        assert_eq!(
+            DepthSelector::parse(" SceneDepth "),
+            Some(DepthSelector::Named("SceneDepth".into()))
+        );
+    }
+
+    #[test]
+    fn convert() {
+        let mut dst = [0u8; 4];
+
+        DepthEncoding::Unorm16.convert(&[0x34, 0x12, 0xff, 0xff], &mut dst);
+        assert_eq!(dst, [0x34, 0x12, 0xff, 0xff]);
+
+        let src = [0x00, 0x34, 0x12, 0xaa, 0xff, 0xff, 0xff, 0x00];
+        DepthEncoding::Unorm24.convert(&src, &mut dst);
+        assert_eq!(dst, [0x34, 0x12, 0xff, 0xff]);
+
+        let src = [
+            [0.5f32.to_le_bytes(), (-1.0f32).to_le_bytes()].concat(),
+            2.0f32.to_le_bytes().to_vec(),
+        ]
+        .concat();
+        let mut dst = [0u8; 6];
+        DepthEncoding::Float32.convert(&src, &mut dst);
+        assert_eq!(dst, [0x00, 0x80, 0x00, 0x00, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn capture_usage() {
+        let mut create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::D32_SFLOAT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+            .build();
+        assert_eq!(
+            DepthImages::capture_usage(&create_info),
+            Some(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+        );
+        create_info.samples = vk::SampleCountFlags::TYPE_4;
+        assert_eq!(DepthImages::capture_usage(&create_info), None);
+        create_info.samples = vk::SampleCountFlags::TYPE_1;
+        create_info.format = vk::Format::S8_UINT;
+        assert_eq!(DepthImages::capture_usage(&create_info), None);
+        create_info.format = vk::Format::D24_UNORM_S8_UINT;
+        create_info.usage = vk::ImageUsageFlags::SAMPLED;
+        assert_eq!(DepthImages::capture_usage(&create_info), None);
+    }
+
+    #[test]
+    fn readable() {
+        let mut target = DepthTarget {
+            image: vk::Image::from_raw(1),
+            format: vk::Format::D32_SFLOAT,
+            extent: vk::Extent2D {
+                width: 64,
+                height: 64,
+            },
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            queue_family: None,
+        };
+        assert!(target.readable_from(0, 0));
+        assert!(!target.readable_from(1, 0));
+        target.queue_family = Some(1);
+        assert!(target.readable_from(1, 0));
+        assert!(!target.readable_from(0, 0));
+        target.sharing_mode = vk::SharingMode::CONCURRENT;
+        assert!(target.readable_from(0, 0));
+    }
+
+    #[test]
+    fn select() {
+        let queue = vk::Queue::from_raw(1);
+        let extent = vk::Extent2D {
+            width: 1920,
+            height: 1080,
+        };
+        let create_info = |width, height| {
+            vk::ImageCreateInfo::builder()
+                .format(vk::Format::D32_SFLOAT)
+                .extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })
+                .build()
+        };
+        let [a, b, c] = [1, 2, 3].map(vk::Image::from_raw);
+
+        let images = DepthImages::default();
+        images.add_image(a, &create_info(1920, 1080));
+        images.add_image(b, &create_info(1920, 1080));
+        images.add_image(c, &create_info(256, 256));
+        images.set_name(a, "SceneDepth");
+        // layout not known yet
+        assert_eq!(
+            images.select(&DepthSelector::Auto, extent, queue, 0, 0),
+            None
+        );
+        let layout = vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL;
+        for image in [a, b, c] {
+            images.barrier(image, layout, vk::QUEUE_FAMILY_IGNORED);
+        }
+
+        let selected = images.select(&DepthSelector::Auto, extent, queue, 0, 0);
+        assert_eq!(selected.map(|v| v.image), Some(b));
+        let named = DepthSelector::Named("SceneDepth".into());
+        assert_eq!(
+            images.select(&named, extent, queue, 0, 0).map(|v| v.image),
+            Some(a)
+        );
+        let other = DepthSelector::Named("ShadowMap".into());
+        assert_eq!(images.select(&other, extent, queue, 0, 0), None);
+
+        assert_eq!(images.remove_image(b), Some(queue));
+        assert_eq!(images.remove_image(c), None);
+        assert_eq!(
+            images
+                .select(&DepthSelector::Auto, extent, queue, 0, 0)
+                .map(|v| v.image),
+            Some(a)
+        );
+    }
+
+    #[test]
+    fn track_layout() {
+        let queue = vk::Queue::from_raw(1);
+        let extent = vk::Extent2D {
+            width: 64,
+            height: 64,
+        };
+        let create_info = vk::ImageCreateInfo::builder()
+            .format(vk::Format::D32_SFLOAT)
+            .extent(vk::Extent3D {
+                width: 64,
+                height: 64,
+                depth: 1,
+            })
+            .build();
+        let image = vk::Image::from_raw(1);
+        let [color_view, depth_view] = [1, 2].map(vk::ImageView::from_raw);
+        let render_pass = vk::RenderPass::from_raw(1);
+        let framebuffer = vk::Framebuffer::from_raw(1);
+        let select = |images: &DepthImages| {
+            images
+                .select(&DepthSelector::Auto, extent, queue, 2, 2)
+                .map(|v| (v.layout, v.queue_family))
+        };
+
+        let images = DepthImages::default();
+        images.add_image(image, &create_info);
+        images.add_view(depth_view, image);
+        images.add_framebuffer(framebuffer, &[color_view, depth_view]);
+        images.add_render_pass(
+            render_pass,
+            vec![
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ],
+        );
+
+        images.barrier(image, vk::ImageLayout::UNDEFINED, vk::QUEUE_FAMILY_IGNORED);
+        assert_eq!(select(&images), None);
+        images.begin_render_pass(render_pass, framebuffer, None);
+        assert_eq!(
+            select(&images),
+            Some((vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL, None))
+        );
+        images.barrier(image, vk::ImageLayout::GENERAL, 2);
+        assert_eq!(select(&images), Some((vk::ImageLayout::GENERAL, Some(2))));
+        // owned by another queue family
+        images.barrier(image, vk::ImageLayout::GENERAL, 3);
+        assert_eq!(select(&images), None);
+        images.barrier(image, vk::ImageLayout::GENERAL, 2);
+        // imageless framebuffer
+        images.begin_render_pass(
+            render_pass,
+            vk::Framebuffer::null(),
+            Some(&[color_view, depth_view]),
+        );
+        assert_eq!(
+            select(&images),
+            Some((vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL, Some(2)))
+        );
+
+        images.remove_view(depth_view);
+        images.remove_framebuffer(framebuffer);
+        images.remove_render_pass(render_pass);
+        images.begin_render_pass(render_pass, framebuffer, None);
+        assert!(images.views.lock().unwrap().is_empty());
+    }
+}
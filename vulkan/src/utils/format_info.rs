@@ -1,8 +1,11 @@
 use std::fmt::Debug;
+use std::mem;
 
 use ash::vk;
 use concat_idents::concat_idents;
-use pw_capture_client::{Format, Transfer};
+use pw_capture_client::{
+    ColorPrimaries, Colorimetry, Format, Transfer, TransferFunction, Transform,
+};
 
 #[derive(Clone, Copy, Debug)]
 pub struct VkFormatInfo {
@@ -136,9 +139,10 @@ pub fn vk_format_get_info(vk_format: vk::Format) -> VkFormatInfo {
     }
 }
 
+/// Formats without alpha map to the image format of their alpha variant
 pub fn client_format_get_info(format: Format, transfer: Transfer) -> VkFormatInfo {
     for info in VK_FORMAT_INFO_TABLE {
-        if info.format == format && info.transfer == transfer {
+        if (info.format == format || info.format.opaque() == format) && info.transfer == transfer {
             return *info;
         }
     }
@@ -149,11 +153,84 @@ pub fn client_format_get_info(format: Format, transfer: Transfer) -> VkFormatInf
     }
 }
 
+pub fn vk_color_space_get_colorimetry(color_space: vk::ColorSpaceKHR) -> Colorimetry {
+    use ColorPrimaries as P;
+    use TransferFunction as T;
+    match color_space {
+        vk::ColorSpaceKHR::SRGB_NONLINEAR => Colorimetry::SRGB,
+        vk::ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT => Colorimetry::rgb(P::BT709, T::SRGB),
+        vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Colorimetry::rgb(P::BT709, T::GAMMA10),
+        vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT => Colorimetry::rgb(P::SMPTEEG432, T::SRGB),
+        vk::ColorSpaceKHR::DISPLAY_P3_LINEAR_EXT => Colorimetry::rgb(P::SMPTEEG432, T::GAMMA10),
+        // DCI-P3 uses gamma 2.6 which has no SPA equivalent
+        vk::ColorSpaceKHR::DCI_P3_NONLINEAR_EXT => Colorimetry::rgb(P::SMPTERP431, T::UNKNOWN),
+        vk::ColorSpaceKHR::BT709_NONLINEAR_EXT => Colorimetry::rgb(P::BT709, T::BT709),
+        vk::ColorSpaceKHR::BT709_LINEAR_EXT => Colorimetry::rgb(P::BT709, T::GAMMA10),
+        vk::ColorSpaceKHR::BT2020_LINEAR_EXT => Colorimetry::rgb(P::BT2020, T::GAMMA10),
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT | vk::ColorSpaceKHR::DOLBYVISION_EXT => {
+            Colorimetry::rgb(P::BT2020, T::SMPTE2084)
+        }
+        vk::ColorSpaceKHR::HDR10_HLG_EXT => Colorimetry::rgb(P::BT2020, T::ARIB_STD_B67),
+        vk::ColorSpaceKHR::ADOBERGB_NONLINEAR_EXT => Colorimetry::rgb(P::ADOBERGB, T::ADOBERGB),
+        vk::ColorSpaceKHR::ADOBERGB_LINEAR_EXT => Colorimetry::rgb(P::ADOBERGB, T::GAMMA10),
+        _ => Colorimetry::default(),
+    }
+}
+
+/// Vulkan rotates clockwise while SPA, like Wayland, rotates counter-clockwise
+pub fn vk_surface_transform_get_transform(transform: vk::SurfaceTransformFlagsKHR) -> Transform {
+    type F = vk::SurfaceTransformFlagsKHR;
+    match transform {
+        F::ROTATE_90 => Transform::Rotate270,
+        F::ROTATE_180 => Transform::Rotate180,
+        F::ROTATE_270 => Transform::Rotate90,
+        F::HORIZONTAL_MIRROR => Transform::Flipped,
+        F::HORIZONTAL_MIRROR_ROTATE_90 => Transform::Flipped270,
+        F::HORIZONTAL_MIRROR_ROTATE_180 => Transform::Flipped180,
+        F::HORIZONTAL_MIRROR_ROTATE_270 => Transform::Flipped90,
+        // INHERIT leaves it to platform specific means we cannot query
+        _ => Transform::Normal,
+    }
+}
+
+/// Part of swapchain images of `extent` the surface shows as of
+/// `capabilities`, e.g. less while the window shrank without the swapchain
+/// getting recreated
+pub fn vk_surface_visible_extent(
+    extent: vk::Extent2D,
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    pre_transform: vk::SurfaceTransformFlagsKHR,
+) -> vk::Extent2D {
+    type F = vk::SurfaceTransformFlagsKHR;
+    let mut current = capabilities.current_extent;
+    // surface size is determined by the swapchain
+    if current.width == u32::MAX && current.height == u32::MAX {
+        return extent;
+    }
+    // surface extent is in the orientation of the display, swapchain images
+    // are rotated by the presentation engine unless pre-transformed
+    let rotated = |transform: F| {
+        transform.intersects(
+            F::ROTATE_90
+                | F::ROTATE_270
+                | F::HORIZONTAL_MIRROR_ROTATE_90
+                | F::HORIZONTAL_MIRROR_ROTATE_270,
+        )
+    };
+    if rotated(capabilities.current_transform) != rotated(pre_transform) {
+        mem::swap(&mut current.width, &mut current.height);
+    }
+    vk::Extent2D {
+        width: extent.width.min(current.width),
+        height: extent.height.min(current.height),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::*;
     use ash::vk;
-    use pw_capture_client::Transfer;
+    use pw_capture_client::{ColorPrimaries, Colorimetry, Transfer, TransferFunction, Transform};
 
     #[test]
     fn get_transfer() {
@@ -175,4 +252,71 @@ mod tests {
             vk_format_get_transfer(vk::Format::G12X4_B12X4_R12X4_3PLANE_420_UNORM_3PACK16);
         assert_eq!(Transfer::UNORM, transfer);
     }
+
+    #[test]
+    fn color_space_colorimetry() {
+        let colorimetry = vk_color_space_get_colorimetry(vk::ColorSpaceKHR::SRGB_NONLINEAR);
+        assert_eq!(Colorimetry::SRGB, colorimetry);
+        let colorimetry = vk_color_space_get_colorimetry(vk::ColorSpaceKHR::HDR10_ST2084_EXT);
+        assert_eq!(ColorPrimaries::BT2020, colorimetry.primaries);
+        assert_eq!(TransferFunction::SMPTE2084, colorimetry.transfer);
+        let colorimetry = vk_color_space_get_colorimetry(vk::ColorSpaceKHR::PASS_THROUGH_EXT);
+        assert_eq!(Colorimetry::default(), colorimetry);
+    }
+
+    #[test]
+    fn surface_transform() {
+        type F = vk::SurfaceTransformFlagsKHR;
+        let transform = vk_surface_transform_get_transform(F::IDENTITY);
+        assert_eq!(Transform::Normal, transform);
+        let transform = vk_surface_transform_get_transform(F::INHERIT);
+        assert_eq!(Transform::Normal, transform);
+        let transform = vk_surface_transform_get_transform(F::ROTATE_90);
+        assert_eq!(Transform::Rotate270, transform);
+        let transform = vk_surface_transform_get_transform(F::HORIZONTAL_MIRROR_ROTATE_90);
+        assert_eq!(Transform::Flipped270, transform);
+    }
+
+    #[test]
+    fn surface_visible_extent() {
+        type F = vk::SurfaceTransformFlagsKHR;
+        let extent = |width, height| vk::Extent2D { width, height };
+        let caps = |current_extent, current_transform| vk::SurfaceCapabilitiesKHR {
+            current_extent,
+            current_transform,
+            ..Default::default()
+        };
+        let visible = vk_surface_visible_extent(
+            extent(1920, 1080),
+            &caps(extent(u32::MAX, u32::MAX), F::IDENTITY),
+            F::IDENTITY,
+        );
+        assert_eq!(extent(1920, 1080), visible);
+        let visible = vk_surface_visible_extent(
+            extent(1920, 1080),
+            &caps(extent(1280, 720), F::IDENTITY),
+            F::IDENTITY,
+        );
+        assert_eq!(extent(1280, 720), visible);
+        let visible = vk_surface_visible_extent(
+            extent(1920, 1080),
+            &caps(extent(2560, 1440), F::IDENTITY),
+            F::IDENTITY,
+        );
+        assert_eq!(extent(1920, 1080), visible);
+        // pre-rotated images match the surface
+        let visible = vk_surface_visible_extent(
+            extent(1080, 2400),
+            &caps(extent(1080, 2400), F::ROTATE_90),
+            F::ROTATE_90,
+        );
+        assert_eq!(extent(1080, 2400), visible);
+        // rotated by the presentation engine
+        let visible = vk_surface_visible_extent(
+            extent(2400, 1080),
+            &caps(extent(1080, 2000), F::ROTATE_90),
+            F::IDENTITY,
+        );
+        assert_eq!(extent(2000, 1080), visible);
+    }
 }
@@ -0,0 +1,156 @@
+//! Pacing of captures for swapchains presenting faster than the display
+
+use crate::utils::*;
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ash::vk;
+use function_name::named;
+use once_cell::sync::Lazy;
+use pw_capture_client::setting;
+
+static PACING_FPS: Lazy<Option<f64>> = Lazy::new(pacing_fps_from_env);
+
+#[named]
+fn pacing_fps_from_env() -> Option<f64> {
+    let value = setting("PW_CAPTURE_PACING_FPS")?;
+    match value.trim().parse::<f64>() {
+        Ok(fps) if fps >= 0.0 => {
+            debug!("pacing fps: {fps}");
+            Some(fps)
+        }
+        _ => {
+            warn!("invalid PW_CAPTURE_PACING_FPS {value:?}");
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FramePacer {
+    interval: Duration,
+    /// Earliest time the next frame gets captured
+    next: Mutex<Option<Instant>>,
+}
+
+impl FramePacer {
+    /// Pacer for swapchains of `present_mode`, if any, `refresh_duration` of
+    /// the display is used unless a rate is configured, none if it is unknown
+    pub fn new(
+        present_mode: vk::PresentModeKHR,
+        refresh_duration: Option<Duration>,
+    ) -> Option<Self> {
+        Self::with_fps(present_mode, refresh_duration, *PACING_FPS)
+    }
+
+    fn with_fps(
+        present_mode: vk::PresentModeKHR,
+        refresh_duration: Option<Duration>,
+        fps: Option<f64>,
+    ) -> Option<Self> {
+        let continuous = present_mode == vk::PresentModeKHR::SHARED_CONTINUOUS_REFRESH;
+        if !continuous
+            && !matches!(
+                present_mode,
+                vk::PresentModeKHR::MAILBOX | vk::PresentModeKHR::IMMEDIATE
+            )
+        {
+            return None;
+        }
+        let fps = fps.filter(|&fps| !(continuous && fps == 0.0));
+        let interval = match fps {
+            Some(fps) if fps == 0.0 => return None,
+            Some(fps) => Duration::from_secs_f64(1.0 / fps),
+            None => refresh_duration.filter(|v| !v.is_zero())?,
+        };
+        Some(Self {
+            interval,
+            next: Mutex::new(None),
+        })
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Whether a frame at `now` would get captured, without accounting for it
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next.lock().unwrap().map_or(true, |next| now >= next)
+    }
+
+    /// Whether frame presented at `now` gets captured
+    ///
+    /// Uncapped `MAILBOX` and `IMMEDIATE` apps present frames that are never
+    /// shown, capturing more than one per interval is wasted work.
+    pub fn pace(&self, now: Instant) -> bool {
+        let mut next = self.next.lock().unwrap();
+        let base = match *next {
+            Some(next) if now < next => return false,
+            // keep cadence unless a whole interval got skipped
+            Some(next) if now - next < self.interval => next,
+            _ => now,
+        };
+        *next = Some(base + self.interval);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn present_modes() {
+        let refresh = Some(Duration::from_millis(10));
+        assert!(FramePacer::with_fps(vk::PresentModeKHR::FIFO, refresh, None).is_none());
+        assert!(FramePacer::with_fps(vk::PresentModeKHR::MAILBOX, refresh, Some(0.0)).is_none());
+        let pacer = FramePacer::with_fps(vk::PresentModeKHR::IMMEDIATE, refresh, None).unwrap();
+        assert_eq!(pacer.interval(), Duration::from_millis(10));
+        let pacer = FramePacer::with_fps(vk::PresentModeKHR::MAILBOX, None, Some(50.0)).unwrap();
+        assert_eq!(pacer.interval(), Duration::from_millis(20));
+        let pacer = FramePacer::with_fps(
+            vk::PresentModeKHR::SHARED_CONTINUOUS_REFRESH,
+            refresh,
+            Some(0.0),
+        )
+        .unwrap();
+        assert_eq!(pacer.interval(), Duration::from_millis(10));
+        assert!(
+            FramePacer::with_fps(vk::PresentModeKHR::SHARED_DEMAND_REFRESH, refresh, None)
+                .is_none()
+        );
+        // refresh rate unknown
+        assert!(FramePacer::with_fps(vk::PresentModeKHR::MAILBOX, None, None).is_none());
+        assert!(FramePacer::with_fps(
+            vk::PresentModeKHR::SHARED_CONTINUOUS_REFRESH,
+            Some(Duration::ZERO),
+            None
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn pace() {
+        let pacer = FramePacer::with_fps(
+            vk::PresentModeKHR::MAILBOX,
+            Some(Duration::from_millis(10)),
+            None,
+        )
+        .unwrap();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(pacer.is_due(at(0)));
+        assert!(pacer.pace(at(0)));
+        assert!(!pacer.is_due(at(3)));
+        assert!(!pacer.pace(at(3)));
+        assert!(!pacer.pace(at(9)));
+        assert!(pacer.pace(at(12)));
+        // cadence kept, next one due at 20
+        assert!(pacer.pace(at(20)));
+        // long pause restarts from there
+        assert!(pacer.pace(at(100)));
+        assert!(!pacer.pace(at(105)));
+        assert!(pacer.pace(at(110)));
+    }
+}
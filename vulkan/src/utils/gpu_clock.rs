@@ -0,0 +1,164 @@
+//! GPU timestamps on `CLOCK_MONOTONIC`
+
+use crate::utils::*;
+
+use core::ffi::{c_void, CStr};
+use std::mem;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ash::prelude::VkResult;
+use ash::vk;
+use function_name::named;
+
+// GPU and CPU clocks drift apart
+const RECALIBRATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time domains correlated, in order of `Calibration` fields
+const TIME_DOMAINS: [vk::TimeDomainEXT; 2] = [
+    vk::TimeDomainEXT::DEVICE,
+    vk::TimeDomainEXT::CLOCK_MONOTONIC,
+];
+
+/// GPU ticks and `CLOCK_MONOTONIC` sampled at the same time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Calibration {
+    gpu_ticks: u64,
+    monotonic_ns: i64,
+}
+
+impl Calibration {
+    /// `CLOCK_MONOTONIC` nanoseconds of `ticks`, of which only `valid_bits`
+    /// low bits are valid, ticking every `period` nanoseconds
+    fn to_monotonic(self, ticks: u64, valid_bits: u32, period: f32) -> i64 {
+        let mask = match valid_bits {
+            64.. => u64::MAX,
+            bits => (1 << bits) - 1,
+        };
+        // timestamps may wrap around or be taken before the calibration
+        let delta = ticks.wrapping_sub(self.gpu_ticks) & mask;
+        let delta = if delta > mask / 2 {
+            -(((mask - delta) as i128) + 1)
+        } else {
+            delta as i128
+        };
+        self.monotonic_ns + (delta as f64 * period as f64) as i64
+    }
+}
+
+/// Converts GPU timestamps of a device to `CLOCK_MONOTONIC`, so frames are
+/// stamped with the time copies started rather than the jittery time of the
+/// present call
+pub struct GpuClock {
+    device: vk::Device,
+    get_calibrated_timestamps: vk::PFN_vkGetCalibratedTimestampsEXT,
+    /// Nanoseconds per tick
+    timestamp_period: f32,
+    calibration: Mutex<Option<(Calibration, Instant)>>,
+}
+
+impl GpuClock {
+    /// Whether `time_domains` reported by
+    /// `vkGetPhysicalDeviceCalibrateableTimeDomainsEXT` can be correlated
+    pub fn supports(time_domains: &[vk::TimeDomainEXT]) -> bool {
+        TIME_DOMAINS
+            .iter()
+            .all(|domain| time_domains.contains(domain))
+    }
+
+    /// Loads `vkGetCalibratedTimestampsEXT` of a device with
+    /// `VK_EXT_calibrated_timestamps` enabled
+    pub unsafe fn load(
+        device: vk::Device,
+        load_fn: impl FnOnce(&CStr) -> *const c_void,
+        timestamp_period: f32,
+    ) -> Option<Self> {
+        let name = CStr::from_bytes_with_nul_unchecked(b"vkGetCalibratedTimestampsEXT\0");
+        let pfn = load_fn(name);
+        if pfn.is_null() || timestamp_period <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            device,
+            get_calibrated_timestamps: mem::transmute(pfn),
+            timestamp_period,
+            calibration: Mutex::new(None),
+        })
+    }
+
+    unsafe fn calibrate(&self) -> VkResult<Calibration> {
+        let infos = TIME_DOMAINS.map(|time_domain| vk::CalibratedTimestampInfoEXT {
+            time_domain,
+            ..Default::default()
+        });
+        let mut timestamps = [0u64; TIME_DOMAINS.len()];
+        let mut max_deviation = 0u64;
+        (self.get_calibrated_timestamps)(
+            self.device,
+            infos.len() as _,
+            infos.as_ptr(),
+            timestamps.as_mut_ptr(),
+            &mut max_deviation,
+        )
+        .result()?;
+        Ok(Calibration {
+            gpu_ticks: timestamps[0],
+            monotonic_ns: timestamps[1] as _,
+        })
+    }
+
+    /// `CLOCK_MONOTONIC` nanoseconds of timestamp `ticks` written by a queue
+    /// with `valid_bits` timestamp bits
+    #[named]
+    pub unsafe fn to_monotonic(&self, ticks: u64, valid_bits: u32) -> Option<i64> {
+        let mut calibration = self.calibration.lock().unwrap();
+        let now = Instant::now();
+        let calibration = match *calibration {
+            Some((v, at)) if now - at < RECALIBRATE_INTERVAL => v,
+            _ => match self.calibrate() {
+                Ok(v) => {
+                    trace!("calibrated GPU clock: {v:?}");
+                    *calibration = Some((v, now));
+                    v
+                }
+                Err(e) => {
+                    debug!("failed to calibrate GPU clock: {e:?}");
+                    return None;
+                }
+            },
+        };
+        Some(calibration.to_monotonic(ticks, valid_bits, self.timestamp_period))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_monotonic() {
+        let calibration = Calibration {
+            gpu_ticks: 1000,
+            monotonic_ns: 5_000_000,
+        };
+        assert_eq!(calibration.to_monotonic(1000, 64, 1.0), 5_000_000);
+        assert_eq!(calibration.to_monotonic(1500, 64, 1.0), 5_000_500);
+        assert_eq!(calibration.to_monotonic(500, 64, 1.0), 4_999_500);
+        assert_eq!(calibration.to_monotonic(1010, 64, 52.08), 5_000_520);
+
+        // wrapped around after calibration
+        let calibration = Calibration {
+            gpu_ticks: (1 << 36) - 10,
+            monotonic_ns: 5_000_000,
+        };
+        assert_eq!(calibration.to_monotonic(10, 36, 1.0), 5_000_020);
+        // calibrated after wrapping around
+        let calibration = Calibration {
+            gpu_ticks: 10,
+            monotonic_ns: 5_000_000,
+        };
+        assert_eq!(calibration.to_monotonic((1 << 36) - 10, 36, 1.0), 4_999_980);
+        // invalid high bits are ignored
+        assert_eq!(calibration.to_monotonic((1 << 40) | 20, 36, 1.0), 5_000_010);
+    }
+}
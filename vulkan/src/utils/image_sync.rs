@@ -0,0 +1,178 @@
+//! Tracking submits of the layer on swapchain images
+
+use crate::utils::*;
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use ash::prelude::VkResult;
+use ash::vk;
+
+/// Fence of a submit, held while waited on
+pub type SubmitFence = Arc<vk::Fence>;
+
+/// Submits of the layer on a swapchain image
+pub struct ImageSync {
+    /// Submits not known to have completed, oldest first
+    pending: VecDeque<(u64, SubmitFence)>,
+    /// Unsignaled fences ready for reuse
+    free: Vec<vk::Fence>,
+    /// Sequence number of the last submit, zero if none
+    seq: u64,
+    allocator: Allocator,
+}
+
+impl ImageSync {
+    pub fn new(allocator: Allocator) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            free: Vec::new(),
+            seq: 0,
+            allocator,
+        }
+    }
+
+    /// Sequence number of the last submit
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Fence of submit `seq`, `None` if it is known to have completed
+    pub fn fence(&self, seq: u64) -> Option<SubmitFence> {
+        self.pending
+            .iter()
+            .find(|(v, _)| *v == seq)
+            .map(|(_, fence)| fence.clone())
+    }
+
+    /// Submits with a fence of its own through `submit`, returns the sequence
+    /// number of the submit
+    pub unsafe fn submit(
+        &mut self,
+        device: &ash::Device,
+        submit: impl FnOnce(vk::Fence) -> VkResult<()>,
+    ) -> VkResult<u64> {
+        self.reclaim(device, |fence| device.get_fence_status(fence))?;
+        let fence = match self.free.pop() {
+            Some(v) => v,
+            None => {
+                let fence_info = vk::FenceCreateInfo::builder();
+                device.create_fence(&fence_info, self.allocator.callbacks())?
+            }
+        };
+        self.push(fence, submit(fence))
+    }
+
+    /// Whether submit `seq` completed, never blocks
+    pub unsafe fn is_complete(&self, device: &ash::Device, seq: u64) -> VkResult<bool> {
+        match self.fence(seq) {
+            Some(fence) => device.get_fence_status(*fence),
+            None => Ok(true),
+        }
+    }
+
+    /// Waits for all submits, so command buffers recorded for the image can
+    /// be recorded again
+    pub unsafe fn wait(&mut self, device: &ash::Device) -> VkResult<()> {
+        let fences: Vec<_> = self.pending.iter().map(|(_, fence)| **fence).collect();
+        if !fences.is_empty() {
+            device.wait_for_fences(&fences, true, u64::MAX)?;
+        }
+        self.reclaim(device, |_| Ok(true))
+    }
+
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        let pending = self.pending.iter().map(|(_, fence)| **fence);
+        for fence in pending.chain(self.free.iter().copied()) {
+            device.destroy_fence(fence, self.allocator.callbacks());
+        }
+    }
+
+    /// Records the fence of a submit, taken back unless it succeeded
+    fn push(&mut self, fence: vk::Fence, res: VkResult<()>) -> VkResult<u64> {
+        if let Err(e) = res {
+            self.free.push(fence);
+            return Err(e);
+        }
+        self.seq += 1;
+        self.pending.push_back((self.seq, Arc::new(fence)));
+        Ok(self.seq)
+    }
+
+    /// Resets fences of completed submits nobody waits on anymore
+    unsafe fn reclaim(
+        &mut self,
+        device: &ash::Device,
+        signaled: impl FnMut(vk::Fence) -> VkResult<bool>,
+    ) -> VkResult<()> {
+        self.reclaim_with(signaled, |fence| device.reset_fences(&[fence]))
+    }
+
+    fn reclaim_with(
+        &mut self,
+        mut signaled: impl FnMut(vk::Fence) -> VkResult<bool>,
+        mut reset: impl FnMut(vk::Fence) -> VkResult<()>,
+    ) -> VkResult<()> {
+        let mut index = 0;
+        while index < self.pending.len() {
+            let fence = &self.pending[index].1;
+            if Arc::strong_count(fence) > 1 || !signaled(**fence)? {
+                index += 1;
+                continue;
+            }
+            let fence = **fence;
+            reset(fence)?;
+            self.pending.remove(index);
+            self.free.push(fence);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ash::vk::Handle;
+
+    fn fence(raw: u64) -> vk::Fence {
+        vk::Fence::from_raw(raw)
+    }
+
+    #[test]
+    fn reclaim_fences() {
+        let mut sync = ImageSync::new(Allocator::default());
+        assert_eq!(sync.push(fence(1), Ok(())), Ok(1));
+        assert_eq!(sync.push(fence(2), Ok(())), Ok(2));
+        assert_eq!(
+            sync.push(fence(3), Err(vk::Result::ERROR_DEVICE_LOST)),
+            Err(vk::Result::ERROR_DEVICE_LOST)
+        );
+        assert_eq!(sync.seq(), 2);
+        assert_eq!(sync.free, [fence(3)]);
+
+        // fence of the first copy is still waited on by the stream worker
+        let held = sync.fence(1).unwrap();
+        let mut resets = Vec::new();
+        sync.reclaim_with(
+            |_| Ok(true),
+            |fence| {
+                resets.push(fence);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(resets, [fence(2)]);
+        assert!(sync.fence(1).is_some());
+        assert!(sync.fence(2).is_none());
+
+        // pending submits are left alone
+        drop(held);
+        sync.reclaim_with(|v| Ok(v != fence(1)), |_| unreachable!())
+            .unwrap();
+        assert!(sync.fence(1).is_some());
+        sync.reclaim_with(|_| Ok(true), |_| Ok(())).unwrap();
+        assert!(sync.pending.is_empty());
+        assert_eq!(sync.free, [fence(3), fence(2), fence(1)]);
+    }
+}
@@ -0,0 +1,305 @@
+use crate::utils::*;
+
+use anyhow::{anyhow, Result};
+use ash::prelude::VkResult;
+use ash::vk;
+use pw_capture_client::indicator_rect;
+
+const INDICATOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+const INDICATOR_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+/// Draws the capture indicator onto swapchain images
+///
+/// A single red texel is cleared and blitted onto the image in command
+/// buffers submitted right after the copy into the export image.
+pub struct Indicator {
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    command_pool: vk::CommandPool,
+    /// One per swapchain image
+    command_buffers: Vec<vk::CommandBuffer>,
+    allocator: Allocator,
+}
+
+impl Indicator {
+    pub unsafe fn new(
+        ash_instance: &ash::Instance,
+        ash_device: &ash::Device,
+        phy_device: vk::PhysicalDevice,
+        dst_format: vk::Format,
+        queue_family_index: u32,
+        num_images: u32,
+        allocator: Allocator,
+    ) -> Result<Self> {
+        let props = ash_instance.get_physical_device_format_properties(phy_device, dst_format);
+        if !props
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::BLIT_DST)
+        {
+            return Err(anyhow!("can not blit to {:?}", dst_format));
+        }
+
+        let mut indicator = Self {
+            image: vk::Image::null(),
+            memory: vk::DeviceMemory::null(),
+            command_pool: vk::CommandPool::null(),
+            command_buffers: vec![],
+            allocator,
+        };
+        // frees partially created objects on error
+        if let Err(e) = indicator.init(
+            ash_instance,
+            ash_device,
+            phy_device,
+            queue_family_index,
+            num_images,
+        ) {
+            indicator.destroy(ash_device);
+            return Err(e);
+        }
+        Ok(indicator)
+    }
+
+    unsafe fn init(
+        &mut self,
+        ash_instance: &ash::Instance,
+        ash_device: &ash::Device,
+        phy_device: vk::PhysicalDevice,
+        queue_family_index: u32,
+        num_images: u32,
+    ) -> Result<()> {
+        let allocator = self.allocator.callbacks();
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(INDICATOR_FORMAT)
+            .extent(vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        self.image = ash_device.create_image(&image_info, allocator)?;
+
+        let requirements = ash_device.get_image_memory_requirements(self.image);
+        let index = get_memory_type_indices(
+            ash_instance,
+            phy_device,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            requirements,
+        )
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("no memory type for indicator image"))?;
+        let memory_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(index);
+        self.memory = ash_device.allocate_memory(&memory_info, allocator)?;
+        ash_device.bind_image_memory(self.image, self.memory, 0)?;
+
+        let cmd_pool_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        self.command_pool = ash_device.create_command_pool(&cmd_pool_info, allocator)?;
+        let cmd_buffers_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(num_images);
+        self.command_buffers = ash_device.allocate_command_buffers(&cmd_buffers_info)?;
+
+        Ok(())
+    }
+
+    /// Allocates command buffers for swapchains of up to `num_images` images
+    pub unsafe fn reserve(&mut self, ash_device: &ash::Device, num_images: usize) -> VkResult<()> {
+        if self.command_buffers.len() >= num_images {
+            return Ok(());
+        }
+        let cmd_buffers_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count((num_images - self.command_buffers.len()) as _);
+        let cmd_buffers = ash_device.allocate_command_buffers(&cmd_buffers_info)?;
+        self.command_buffers.extend(cmd_buffers);
+        Ok(())
+    }
+
+    /// Records drawing the indicator onto swapchain image `image_index`, the
+    /// image is expected in `dst_layout` and is left in it.
+    /// Returns `None` if `dst_extent` is too small to fit the indicator.
+    pub unsafe fn record(
+        &self,
+        ash_device: &ash::Device,
+        image_index: usize,
+        dst_image: vk::Image,
+        dst_layout: vk::ImageLayout,
+        dst_extent: vk::Extent2D,
+        mut src_queue_family: u32,
+        mut dst_queue_family: u32,
+    ) -> VkResult<Option<vk::CommandBuffer>> {
+        let rect = match indicator_rect(dst_extent.width, dst_extent.height) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        if src_queue_family == dst_queue_family {
+            src_queue_family = vk::QUEUE_FAMILY_IGNORED;
+            dst_queue_family = vk::QUEUE_FAMILY_IGNORED;
+        }
+        let blit_layout =
+            swapchain_transfer_layout(dst_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+
+        let command_buffer = self.command_buffers[image_index];
+        ash_device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        ash_device.begin_command_buffer(command_buffer, &begin_info)?;
+
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let dst_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(dst_layout)
+            .new_layout(blit_layout)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .image(dst_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::MEMORY_READ)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .build();
+
+        let clear_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[dst_barrier, clear_barrier],
+        );
+
+        let color = vk::ClearColorValue {
+            float32: INDICATOR_COLOR,
+        };
+        ash_device.cmd_clear_color_image(
+            command_buffer,
+            self.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &color,
+            &[subresource],
+        );
+
+        let src_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[src_barrier],
+        );
+
+        let subresource_layer = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+        let image_blit = vk::ImageBlit::builder()
+            .src_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D { x: 1, y: 1, z: 1 },
+            ])
+            .src_subresource(subresource_layer)
+            .dst_offsets([
+                vk::Offset3D {
+                    x: rect.x as _,
+                    y: rect.y as _,
+                    z: 0,
+                },
+                vk::Offset3D {
+                    x: (rect.x + rect.size) as _,
+                    y: (rect.y + rect.size) as _,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(subresource_layer)
+            .build();
+        ash_device.cmd_blit_image(
+            command_buffer,
+            self.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            dst_image,
+            blit_layout,
+            &[image_blit],
+            vk::Filter::NEAREST,
+        );
+
+        let dst_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(blit_layout)
+            .new_layout(dst_layout)
+            .src_queue_family_index(dst_queue_family)
+            .dst_queue_family_index(src_queue_family)
+            .image(dst_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[dst_barrier],
+        );
+
+        ash_device.end_command_buffer(command_buffer)?;
+
+        Ok(Some(command_buffer))
+    }
+
+    pub unsafe fn destroy(&self, ash_device: &ash::Device) {
+        if !self.command_buffers.is_empty() {
+            ash_device.free_command_buffers(self.command_pool, &self.command_buffers);
+        }
+        let allocator = self.allocator.callbacks();
+        ash_device.destroy_command_pool(self.command_pool, allocator);
+        ash_device.destroy_image(self.image, allocator);
+        ash_device.free_memory(self.memory, allocator);
+    }
+}
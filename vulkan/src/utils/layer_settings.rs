@@ -0,0 +1,281 @@
+//! Layer settings
+
+use crate::utils::*;
+
+use core::ffi::{c_char, c_void, CStr};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use ash::vk;
+use function_name::named;
+use pw_capture_client::LogFilter;
+
+const LAYER_NAME: &[u8] = b"VK_LAYER_EH5_pwcapture";
+const FILE_PREFIX: &str = "eh5_pwcapture.";
+const SETTINGS_FILE: &str = "vk_layer_settings.txt";
+
+// VK_EXT_layer_settings is newer than the headers ash got generated from
+const STRUCTURE_TYPE_LAYER_SETTINGS_CREATE_INFO_EXT: vk::StructureType =
+    vk::StructureType::from_raw(1000496000);
+const LAYER_SETTING_TYPE_BOOL32: i32 = 0;
+const LAYER_SETTING_TYPE_INT32: i32 = 1;
+const LAYER_SETTING_TYPE_INT64: i32 = 2;
+const LAYER_SETTING_TYPE_UINT32: i32 = 3;
+const LAYER_SETTING_TYPE_UINT64: i32 = 4;
+const LAYER_SETTING_TYPE_FLOAT32: i32 = 5;
+const LAYER_SETTING_TYPE_FLOAT64: i32 = 6;
+const LAYER_SETTING_TYPE_STRING: i32 = 7;
+
+#[repr(C)]
+struct LayerSettingsCreateInfoEXT {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    setting_count: u32,
+    p_settings: *const LayerSettingEXT,
+}
+
+#[repr(C)]
+struct LayerSettingEXT {
+    p_layer_name: *const c_char,
+    p_setting_name: *const c_char,
+    ty: i32,
+    value_count: u32,
+    p_values: *const c_void,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LayerSettings {
+    /// Settings in order of precedence, later ones win
+    settings: Vec<(String, String)>,
+}
+
+impl LayerSettings {
+    /// Parses `prefix.key = value` lines, `#` starts a comment
+    pub fn parse_file(content: &str) -> Self {
+        let settings = content
+            .lines()
+            .map(|line| line.split_once('#').map_or(line, |(line, _)| line))
+            .filter_map(|line| line.split_once('='))
+            .filter_map(|(key, value)| {
+                let key = key.trim().strip_prefix(FILE_PREFIX)?;
+                Some((key.to_owned(), value.trim().to_owned()))
+            })
+            .collect();
+        Self { settings }
+    }
+
+    fn file_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("VK_LAYER_SETTINGS_PATH") {
+            let path = PathBuf::from(path);
+            return Some(match path.is_dir() {
+                true => path.join(SETTINGS_FILE),
+                false => path,
+            });
+        }
+        let local = PathBuf::from(SETTINGS_FILE);
+        if local.is_file() {
+            return Some(local);
+        }
+        let data_home = env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+        Some(data_home.join("vulkan/settings.d").join(SETTINGS_FILE))
+    }
+
+    unsafe fn parse_create_info(p_next: *const c_void) -> Vec<(String, String)> {
+        let info = find_in_chain::<LayerSettingsCreateInfoEXT>(
+            p_next,
+            STRUCTURE_TYPE_LAYER_SETTINGS_CREATE_INFO_EXT,
+        );
+        let info = match info {
+            Some(v) => v,
+            None => return vec![],
+        };
+        raw_slice(info.p_settings, info.setting_count)
+            .iter()
+            .filter(|setting| {
+                !setting.p_layer_name.is_null()
+                    && CStr::from_ptr(setting.p_layer_name).to_bytes() == LAYER_NAME
+                    && !setting.p_setting_name.is_null()
+            })
+            .filter_map(|setting| {
+                let key = CStr::from_ptr(setting.p_setting_name).to_string_lossy();
+                let value = Self::format_values(setting)?;
+                Some((key.into_owned(), value))
+            })
+            .collect()
+    }
+
+    /// Joins values of a setting with `,` as array settings are in the file
+    unsafe fn format_values(setting: &LayerSettingEXT) -> Option<String> {
+        let count = setting.value_count;
+        let p_values = setting.p_values;
+        let values: Vec<String> = match setting.ty {
+            LAYER_SETTING_TYPE_BOOL32 => raw_slice(p_values as *const vk::Bool32, count)
+                .iter()
+                .map(|&v| (v != vk::FALSE).to_string())
+                .collect(),
+            LAYER_SETTING_TYPE_INT32 => raw_slice(p_values as *const i32, count)
+                .iter()
+                .map(i32::to_string)
+                .collect(),
+            LAYER_SETTING_TYPE_INT64 => raw_slice(p_values as *const i64, count)
+                .iter()
+                .map(i64::to_string)
+                .collect(),
+            LAYER_SETTING_TYPE_UINT32 => raw_slice(p_values as *const u32, count)
+                .iter()
+                .map(u32::to_string)
+                .collect(),
+            LAYER_SETTING_TYPE_UINT64 => raw_slice(p_values as *const u64, count)
+                .iter()
+                .map(u64::to_string)
+                .collect(),
+            LAYER_SETTING_TYPE_FLOAT32 => raw_slice(p_values as *const f32, count)
+                .iter()
+                .map(f32::to_string)
+                .collect(),
+            LAYER_SETTING_TYPE_FLOAT64 => raw_slice(p_values as *const f64, count)
+                .iter()
+                .map(f64::to_string)
+                .collect(),
+            LAYER_SETTING_TYPE_STRING => raw_slice(p_values as *const *const c_char, count)
+                .iter()
+                .filter(|v| !v.is_null())
+                .map(|&v| CStr::from_ptr(v).to_string_lossy().into_owned())
+                .collect(),
+            _ => return None,
+        };
+        Some(values.join(","))
+    }
+
+    /// Loads settings of the file and of the instance create info `p_next`
+    #[named]
+    pub unsafe fn load(p_next: *const c_void) -> Self {
+        let mut settings = match Self::file_path() {
+            Some(path) => match fs::read_to_string(&path) {
+                Ok(content) => {
+                    debug!("loaded layer settings from {path:?}");
+                    Self::parse_file(&content)
+                }
+                Err(_) => Self::default(),
+            },
+            None => Self::default(),
+        };
+        settings.settings.extend(Self::parse_create_info(p_next));
+        settings
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.settings
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Whether swapchains of the instance get captured
+    pub fn enabled(&self) -> bool {
+        !matches!(self.get("enable"), Some("false" | "0"))
+    }
+
+    pub fn log_filter(&self) -> Option<LogFilter> {
+        LogFilter::parse(self.get("log_level")?).ok()
+    }
+
+    /// Applies log level and hands the remaining settings to the client, what
+    /// is set in the environment still wins
+    #[named]
+    pub fn apply(&self) {
+        if let (Some(value), None) = (self.get("log_level"), env::var_os("PW_CAPTURE_LOG")) {
+            match self.log_filter() {
+                Some(filter) => pw_capture_client::set_log_filter(filter),
+                None => warn!("invalid log_level {value:?}"),
+            }
+        }
+        let defaults = self
+            .settings
+            .iter()
+            .filter(|(key, _)| key != "enable" && key != "log_level")
+            .map(|(key, value)| {
+                let name = format!("PW_CAPTURE_{}", key.to_ascii_uppercase());
+                debug!("{name}={value:?} from layer settings");
+                (name, value.clone())
+            });
+        pw_capture_client::set_setting_defaults(defaults);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::ptr;
+
+    #[test]
+    fn parse_file() {
+        let settings = LayerSettings::parse_file(
+            "# pw-capture\n\
+             eh5_pwcapture.enable = false\n\
+             eh5_pwcapture.scale = 1080p # half\n\
+             khronos_validation.enables = VK_VALIDATION_FEATURE_ENABLE_SYNCHRONIZATION_VALIDATION_EXT\n\
+             eh5_pwcapture.log_level=warn\n",
+        );
+        assert!(!settings.enabled());
+        assert_eq!(settings.get("scale"), Some("1080p"));
+        assert_eq!(settings.get("enables"), None);
+        assert_eq!(settings.log_filter(), Some(log::LevelFilter::Warn.into()));
+    }
+
+    #[test]
+    fn parse_create_info() {
+        let enable = vk::FALSE;
+        let scale = b"0.5\0".as_ptr() as *const c_char;
+        let setting = |name: &'static [u8], ty, p_values| LayerSettingEXT {
+            p_layer_name: b"VK_LAYER_EH5_pwcapture\0".as_ptr() as _,
+            p_setting_name: name.as_ptr() as _,
+            ty,
+            value_count: 1,
+            p_values,
+        };
+        let settings = [
+            setting(
+                b"enable\0",
+                LAYER_SETTING_TYPE_BOOL32,
+                &enable as *const _ as _,
+            ),
+            setting(
+                b"scale\0",
+                LAYER_SETTING_TYPE_STRING,
+                &scale as *const _ as _,
+            ),
+        ];
+        let info = LayerSettingsCreateInfoEXT {
+            s_type: STRUCTURE_TYPE_LAYER_SETTINGS_CREATE_INFO_EXT,
+            p_next: ptr::null(),
+            setting_count: settings.len() as _,
+            p_settings: settings.as_ptr(),
+        };
+        let settings = LayerSettings {
+            settings: unsafe { LayerSettings::parse_create_info(&info as *const _ as _) },
+        };
+        assert!(!settings.enabled());
+        assert_eq!(settings.get("scale"), Some("0.5"));
+    }
+
+    #[test]
+    fn apply_later_wins() {
+        let settings = LayerSettings {
+            settings: vec![
+                ("test_precedence".into(), "file".into()),
+                ("test_precedence".into(), "app".into()),
+            ],
+        };
+        settings.apply();
+        assert_eq!(
+            pw_capture_client::setting("PW_CAPTURE_TEST_PRECEDENCE").as_deref(),
+            Some("app")
+        );
+    }
+}
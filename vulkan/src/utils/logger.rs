@@ -1,33 +1,7 @@
-use log::Log;
-
-struct SimpleLogger;
-
-impl SimpleLogger {
-    fn init() {
-        let _ = log::set_boxed_logger(Box::new(SimpleLogger))
-            .map(|_| log::set_max_level(log::LevelFilter::Debug));
-    }
-}
-
-impl Log for SimpleLogger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        true
-    }
-    fn log(&self, record: &log::Record) {
-        eprintln!(
-            "[pw-capture-vk] {:>5} [{}:{}] [{}] {}",
-            record.level(),
-            record.file().unwrap_or_default(),
-            record.line().unwrap_or_default(),
-            record.target(),
-            record.args()
-        );
-    }
-    fn flush(&self) {}
-}
+use pw_capture_client as client;
 
 pub fn init_logger() {
-    SimpleLogger::init()
+    client::init_logger("pw-capture-vk")
 }
 
 #[macro_export]
@@ -1,13 +1,47 @@
+mod alpha_mode;
+mod copy_cache;
+mod depth;
 mod format_info;
+mod frame_pacer;
+mod gpu_clock;
+mod image_sync;
+mod indicator;
+mod layer_settings;
 mod logger;
+mod modifier_filter;
+mod post_process;
+mod stale_state;
+mod surface_formats;
+mod swapchain_filter;
+mod swizzle;
+mod timeline;
 mod vk_helper;
+mod yuv;
 
+pub use alpha_mode::*;
+pub use copy_cache::*;
+pub use depth::*;
 pub use format_info::*;
+pub use frame_pacer::*;
+pub use gpu_clock::*;
+pub use image_sync::*;
+pub use indicator::*;
+pub use layer_settings::*;
 pub use logger::*;
+pub use modifier_filter::*;
+pub use post_process::*;
+pub use stale_state::*;
+pub use surface_formats::*;
+pub use swapchain_filter::*;
+pub use swizzle::*;
+pub use timeline::*;
 pub use vk_helper::*;
+pub use yuv::*;
 
 use core::ffi::{c_ulong, c_void};
 
+use ash::vk;
+
 #[derive(Clone, Copy, Debug)]
 pub enum SurfaceRawHandle {
     Xlib {
@@ -22,4 +56,11 @@ pub enum SurfaceRawHandle {
         display: *mut c_void,
         surface: *mut c_void,
     },
+    /// Plane of a display, presented to without a window system
+    Display {
+        mode: vk::DisplayModeKHR,
+        plane_index: u32,
+    },
+    /// `VK_EXT_headless_surface`, presented to without any output
+    Headless,
 }
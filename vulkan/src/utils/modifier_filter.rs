@@ -0,0 +1,173 @@
+//! Modifiers known to produce corrupted frames on some drivers
+
+use crate::utils::*;
+
+use ash::extensions::khr;
+use ash::vk;
+use function_name::named;
+use once_cell::sync::Lazy;
+use pw_capture_client::setting;
+
+const VENDOR_ID_AMD: u32 = 0x1002;
+
+/// `AMD_FMT_MOD` vendor code in the top byte
+const AMD_FMT_MOD: u64 = 0x02 << 56;
+const AMD_FMT_MOD_VENDOR_MASK: u64 = 0xff << 56;
+/// `AMD_FMT_MOD_DCC`
+const AMD_FMT_MOD_DCC: u64 = 1 << 13;
+
+static BUILTIN_RULES: &[ModifierRule] = &[
+    // DCC compressed images copied into with transfer commands show block
+    // artifacts to consumers
+    ModifierRule {
+        vendor_id: Some(VENDOR_ID_AMD),
+        device_id: None,
+        mask: AMD_FMT_MOD_VENDOR_MASK | AMD_FMT_MOD_DCC,
+        value: AMD_FMT_MOD | AMD_FMT_MOD_DCC,
+    },
+];
+
+static ENV_RULES: Lazy<Vec<ModifierRule>> = Lazy::new(rules_from_env);
+
+#[named]
+fn rules_from_env() -> Vec<ModifierRule> {
+    let value = match setting("PW_CAPTURE_MODIFIER_BLACKLIST") {
+        Some(v) => v,
+        None => return vec![],
+    };
+    match ModifierRule::parse_list(&value) {
+        Some(rules) => {
+            debug!("modifier blacklist: {:?}", rules);
+            rules
+        }
+        None => {
+            warn!("invalid PW_CAPTURE_MODIFIER_BLACKLIST {value:?}");
+            vec![]
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModifierRule {
+    /// Matches all vendors if unset
+    pub vendor_id: Option<u32>,
+    /// Matches all devices of the vendor if unset
+    pub device_id: Option<u32>,
+    /// Modifiers with `modifier & mask == value` are excluded
+    pub mask: u64,
+    pub value: u64,
+}
+
+fn parse_hex(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u64::from_str_radix(digits, 16).ok()
+}
+
+impl ModifierRule {
+    fn parse(value: &str) -> Option<Self> {
+        let (ids, modifier) = match value.split_once('=') {
+            Some((ids, modifier)) => (Some(ids), modifier),
+            None => (None, value),
+        };
+        let (vendor_id, device_id) = match ids.map(|ids| ids.split_once(':')) {
+            None => (None, None),
+            Some(None) => (Some(parse_hex(ids?)?), None),
+            Some(Some((vendor, device))) => (Some(parse_hex(vendor)?), Some(parse_hex(device)?)),
+        };
+        Some(Self {
+            vendor_id: vendor_id.map(u32::try_from).transpose().ok()?,
+            device_id: device_id.map(u32::try_from).transpose().ok()?,
+            mask: u64::MAX,
+            value: parse_hex(modifier)?,
+        })
+    }
+
+    pub fn parse_list(value: &str) -> Option<Vec<Self>> {
+        value
+            .split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(Self::parse)
+            .collect()
+    }
+
+    fn applies_to(&self, vendor_id: u32, device_id: u32) -> bool {
+        self.vendor_id.map_or(true, |v| v == vendor_id)
+            && self.device_id.map_or(true, |v| v == device_id)
+    }
+}
+
+/// Blacklisted modifiers of a physical device
+#[derive(Clone, Debug, Default)]
+pub struct ModifierFilter {
+    rules: Vec<ModifierRule>,
+}
+
+impl ModifierFilter {
+    pub fn new(vendor_id: u32, device_id: u32) -> Self {
+        let rules = BUILTIN_RULES
+            .iter()
+            .chain(ENV_RULES.iter())
+            .filter(|rule| rule.applies_to(vendor_id, device_id))
+            .copied()
+            .collect();
+        Self { rules }
+    }
+
+    pub unsafe fn for_device(
+        khr_phy_props2: &khr::GetPhysicalDeviceProperties2,
+        phy_device: vk::PhysicalDevice,
+    ) -> Self {
+        let mut props = vk::PhysicalDeviceProperties2KHR::default();
+        khr_phy_props2.get_physical_device_properties2(phy_device, &mut props);
+        Self::new(props.properties.vendor_id, props.properties.device_id)
+    }
+
+    pub fn allows(&self, modifier: u64) -> bool {
+        !self
+            .rules
+            .iter()
+            .any(|rule| modifier & rule.mask == rule.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_list() {
+        assert_eq!(
+            ModifierRule::parse_list("0x1002:0x73bf=0x200000000402b01, 0x1"),
+            Some(vec![
+                ModifierRule {
+                    vendor_id: Some(0x1002),
+                    device_id: Some(0x73bf),
+                    mask: u64::MAX,
+                    value: 0x200000000402b01,
+                },
+                ModifierRule {
+                    vendor_id: None,
+                    device_id: None,
+                    mask: u64::MAX,
+                    value: 1,
+                },
+            ])
+        );
+        assert_eq!(ModifierRule::parse_list("8086=ffx"), None);
+        assert_eq!(ModifierRule::parse_list(""), Some(vec![]));
+    }
+
+    #[test]
+    fn builtin_rules() {
+        let dcc = AMD_FMT_MOD | AMD_FMT_MOD_DCC | 0x401;
+        let amd = ModifierFilter::new(VENDOR_ID_AMD, 0x73bf);
+        assert!(!amd.allows(dcc));
+        assert!(amd.allows(AMD_FMT_MOD | 0x401));
+        assert!(amd.allows(0));
+        assert!(ModifierFilter::new(0x8086, 0x9a49).allows(dcc));
+    }
+}
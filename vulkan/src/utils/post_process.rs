@@ -0,0 +1,279 @@
+use crate::utils::*;
+
+use anyhow::{anyhow, Result};
+use ash::prelude::VkResult;
+use ash::vk;
+use pw_capture_client::{encode_texel, PostProcess};
+
+/// Applies masks and the watermark of [`PostProcess`] to export images
+///
+/// A host-visible buffer holds a row of black texels and the watermark
+/// encoded in the export format, which are copied row by row onto export
+/// images in command buffers submitted right after the copy into them.
+pub struct PostProcessor {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    regions: Vec<vk::BufferImageCopy>,
+    command_pool: vk::CommandPool,
+    /// One per swapchain image
+    command_buffers: Vec<vk::CommandBuffer>,
+    allocator: Allocator,
+}
+
+impl PostProcessor {
+    /// Post-processor for export images of `format`, frames being copied
+    /// to `region` of them
+    pub unsafe fn new(
+        ash_instance: &ash::Instance,
+        ash_device: &ash::Device,
+        phy_device: vk::PhysicalDevice,
+        config: &PostProcess,
+        format: vk::Format,
+        region: vk::Rect2D,
+        queue_family_index: u32,
+        num_images: u32,
+        allocator: Allocator,
+    ) -> Result<Self> {
+        let info = vk_format_get_info(format);
+        let black = encode_texel(info.format, [0, 0, 0, 255])
+            .ok_or(anyhow!("can not post-process {:?}", format))?;
+        let texel_size = black.len() as u64;
+        // buffer offsets of copies are multiples of 4
+        if texel_size % 4 != 0 {
+            return Err(anyhow!("can not post-process {:?}", format));
+        }
+
+        let vk::Extent2D { width, height } = region.extent;
+        let mut data = black.repeat(width as usize);
+        let mut regions = Vec::new();
+        let copy_region = |offset: u64, x: u32, y: u32, len: u32| {
+            vk::BufferImageCopy::builder()
+                .buffer_offset(offset)
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_offset(vk::Offset3D {
+                    x: region.offset.x + x as i32,
+                    y: region.offset.y + y as i32,
+                    z: 0,
+                })
+                .image_extent(vk::Extent3D {
+                    width: len,
+                    height: 1,
+                    depth: 1,
+                })
+                .build()
+        };
+        for rect in config.mask_rects(width, height) {
+            for y in rect.y..rect.y + rect.height {
+                regions.push(copy_region(0, rect.x, y, rect.width));
+            }
+        }
+        if let (Some(watermark), Some(rect)) =
+            (&config.watermark, config.watermark_rect(width, height))
+        {
+            let encoded = watermark
+                .encode(info.format)
+                .ok_or(anyhow!("can not post-process {:?}", format))?;
+            let base = data.len() as u64;
+            data.extend(encoded);
+            for run in watermark.runs() {
+                let offset = base + (run.y * watermark.width + run.x) as u64 * texel_size;
+                regions.push(copy_region(offset, rect.x + run.x, rect.y + run.y, run.len));
+            }
+        }
+        if regions.is_empty() {
+            return Err(anyhow!(
+                "nothing to post-process in {width}x{height} frames"
+            ));
+        }
+
+        let mut post_processor = Self {
+            buffer: vk::Buffer::null(),
+            memory: vk::DeviceMemory::null(),
+            regions,
+            command_pool: vk::CommandPool::null(),
+            command_buffers: vec![],
+            allocator,
+        };
+        // frees partially created objects on error
+        if let Err(e) = post_processor.init(
+            ash_instance,
+            ash_device,
+            phy_device,
+            &data,
+            queue_family_index,
+            num_images,
+        ) {
+            post_processor.destroy(ash_device);
+            return Err(e);
+        }
+        Ok(post_processor)
+    }
+
+    unsafe fn init(
+        &mut self,
+        ash_instance: &ash::Instance,
+        ash_device: &ash::Device,
+        phy_device: vk::PhysicalDevice,
+        data: &[u8],
+        queue_family_index: u32,
+        num_images: u32,
+    ) -> Result<()> {
+        let allocator = self.allocator.callbacks();
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(data.len() as _)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        self.buffer = ash_device.create_buffer(&buffer_info, allocator)?;
+
+        let requirements = ash_device.get_buffer_memory_requirements(self.buffer);
+        let index = get_memory_type_indices(
+            ash_instance,
+            phy_device,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            requirements,
+        )
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("no memory type for post-process buffer"))?;
+        let memory_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(index);
+        self.memory = ash_device.allocate_memory(&memory_info, allocator)?;
+        ash_device.bind_buffer_memory(self.buffer, self.memory, 0)?;
+
+        // written once, host writes are visible to submits made afterwards
+        let ptr =
+            ash_device.map_memory(self.memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())?;
+        std::ptr::copy_nonoverlapping(data.as_ptr(), ptr.cast(), data.len());
+        ash_device.unmap_memory(self.memory);
+
+        let cmd_pool_info = vk::CommandPoolCreateInfo::builder()
+            .queue_family_index(queue_family_index)
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        self.command_pool = ash_device.create_command_pool(&cmd_pool_info, allocator)?;
+        let cmd_buffers_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(num_images);
+        self.command_buffers = ash_device.allocate_command_buffers(&cmd_buffers_info)?;
+
+        Ok(())
+    }
+
+    /// Allocates command buffers for swapchains of up to `num_images` images
+    pub unsafe fn reserve(&mut self, ash_device: &ash::Device, num_images: usize) -> VkResult<()> {
+        if self.command_buffers.len() >= num_images {
+            return Ok(());
+        }
+        let cmd_buffers_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count((num_images - self.command_buffers.len()) as _);
+        let cmd_buffers = ash_device.allocate_command_buffers(&cmd_buffers_info)?;
+        self.command_buffers.extend(cmd_buffers);
+        Ok(())
+    }
+
+    /// Records post-processing `export_image` copied to from swapchain image
+    /// `image_index`, to be submitted after the copy. The image is expected
+    /// in `GENERAL` layout and is left in it, `host_read` as of
+    /// `record_copy_image`.
+    pub unsafe fn record(
+        &self,
+        ash_device: &ash::Device,
+        image_index: usize,
+        export_image: vk::Image,
+        host_read: bool,
+    ) -> VkResult<vk::CommandBuffer> {
+        let command_buffer = self.command_buffers[image_index];
+        ash_device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        ash_device.begin_command_buffer(command_buffer, &begin_info)?;
+
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        // after the copy of the same submit
+        let dst_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(export_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[dst_barrier],
+        );
+
+        ash_device.cmd_copy_buffer_to_image(
+            command_buffer,
+            self.buffer,
+            export_image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &self.regions,
+        );
+
+        let dst_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(export_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(match host_read {
+                true => vk::AccessFlags::HOST_READ,
+                false => vk::AccessFlags::empty(),
+            })
+            .build();
+
+        let dst_stage = match host_read {
+            true => vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::HOST,
+            false => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        };
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[dst_barrier],
+        );
+
+        ash_device.end_command_buffer(command_buffer)?;
+
+        Ok(command_buffer)
+    }
+
+    pub unsafe fn destroy(&self, ash_device: &ash::Device) {
+        if !self.command_buffers.is_empty() {
+            ash_device.free_command_buffers(self.command_pool, &self.command_buffers);
+        }
+        let allocator = self.allocator.callbacks();
+        ash_device.destroy_command_pool(self.command_pool, allocator);
+        ash_device.destroy_buffer(self.buffer, allocator);
+        ash_device.free_memory(self.memory, allocator);
+    }
+}
@@ -0,0 +1,68 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use ash::vk;
+
+/// Presented frames of a suboptimal or out-of-date swapchain not captured,
+/// apps ignoring it keep being captured afterwards
+const MAX_STALE_FRAMES: u32 = 30;
+
+/// Tracks whether a swapchain currently no longer matches its surface
+#[derive(Debug, Default)]
+pub struct StaleState {
+    /// Frames presented since the swapchain went stale, 0 while it matches
+    /// its surface
+    frames: AtomicU32,
+}
+
+impl StaleState {
+    /// Marks the swapchain stale if `res` of acquiring or presenting tells it
+    /// no longer matches the surface, the app is expected to recreate it,
+    /// `SUCCESS` marks it fresh again
+    pub fn mark(&self, res: vk::Result) {
+        match res {
+            vk::Result::SUCCESS => self.frames.store(0, Ordering::Relaxed),
+            vk::Result::SUBOPTIMAL_KHR | vk::Result::ERROR_OUT_OF_DATE_KHR => {
+                let _ = self
+                    .frames
+                    .compare_exchange(0, 1, Ordering::Relaxed, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether current frame should not be captured as the swapchain is about
+    /// to be replaced by one of the new surface extent
+    pub fn skip_frame(&self) -> bool {
+        let frames = self.frames.load(Ordering::Relaxed);
+        if frames == 0 || frames > MAX_STALE_FRAMES {
+            return false;
+        }
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_fresh_stale() {
+        let state = StaleState::default();
+        assert!(!state.skip_frame());
+
+        state.mark(vk::Result::SUBOPTIMAL_KHR);
+        for _ in 0..MAX_STALE_FRAMES {
+            assert!(state.skip_frame());
+        }
+        assert!(!state.skip_frame());
+
+        state.mark(vk::Result::SUCCESS);
+        assert!(!state.skip_frame());
+
+        state.mark(vk::Result::ERROR_OUT_OF_DATE_KHR);
+        assert!(state.skip_frame());
+        state.mark(vk::Result::SUCCESS);
+        assert!(!state.skip_frame());
+    }
+}
@@ -0,0 +1,63 @@
+//! Order of surface formats reported to apps
+
+use crate::utils::*;
+
+use ash::vk;
+use once_cell::sync::Lazy;
+use pw_capture_client::{setting, Format};
+
+static PREFER_EXPORT_FORMATS: Lazy<bool> = Lazy::new(|| {
+    matches!(
+        setting("PW_CAPTURE_PREFER_EXPORT_FORMATS").as_deref(),
+        Some("1")
+    )
+});
+
+pub fn prefer_export_formats_enabled() -> bool {
+    *PREFER_EXPORT_FORMATS
+}
+
+/// Sorts `formats` so ones frames can be exported in without conversion come
+/// first, keeping the order reported otherwise. `exportable` tells whether
+/// the device can export images of a format.
+pub fn sort_surface_formats<T>(
+    formats: &mut [T],
+    format: impl Fn(&T) -> vk::Format,
+    exportable: impl Fn(vk::Format) -> bool,
+) {
+    formats.sort_by_cached_key(|v| {
+        let format = format(v);
+        let as_is = vk_format_get_info(format).format != Format::UNKNOWN && exportable(format);
+        // false sorts first
+        !as_is
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort() {
+        let mut formats = [
+            vk::Format::B10G11R11_UFLOAT_PACK32,
+            vk::Format::B8G8R8A8_SRGB,
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::Format::B8G8R8A8_UNORM,
+        ];
+        sort_surface_formats(
+            &mut formats,
+            |&v| v,
+            |v| v != vk::Format::R16G16B16A16_SFLOAT,
+        );
+        assert_eq!(
+            formats,
+            [
+                vk::Format::B8G8R8A8_SRGB,
+                vk::Format::B8G8R8A8_UNORM,
+                vk::Format::B10G11R11_UFLOAT_PACK32,
+                vk::Format::R16G16B16A16_SFLOAT,
+            ]
+        );
+    }
+}
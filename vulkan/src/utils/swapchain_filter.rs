@@ -0,0 +1,158 @@
+use crate::utils::*;
+
+use ash::vk;
+use function_name::named;
+use once_cell::sync::Lazy;
+use pw_capture_client::setting;
+
+static SWAPCHAIN_FILTER: Lazy<SwapchainFilter> = Lazy::new(SwapchainFilter::from_env);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SurfacePlatform {
+    X11,
+    Wayland,
+    /// VK_KHR_display, e.g. VR compositors and kiosk apps
+    Display,
+    /// VK_EXT_headless_surface, e.g. automated tests and cloud streaming
+    Headless,
+}
+
+impl SurfacePlatform {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "x11" | "xlib" | "xcb" => Some(Self::X11),
+            "wayland" => Some(Self::Wayland),
+            "display" => Some(Self::Display),
+            "headless" => Some(Self::Headless),
+            _ => None,
+        }
+    }
+}
+
+impl From<SurfaceRawHandle> for SurfacePlatform {
+    fn from(handle: SurfaceRawHandle) -> Self {
+        match handle {
+            SurfaceRawHandle::Xlib { .. } | SurfaceRawHandle::Xcb { .. } => Self::X11,
+            SurfaceRawHandle::Wayland { .. } => Self::Wayland,
+            SurfaceRawHandle::Display { .. } => Self::Display,
+            SurfaceRawHandle::Headless => Self::Headless,
+        }
+    }
+}
+
+/// Decides which swapchains get a capture stream
+///
+/// Launchers and splash screens tend to create tiny or off-screen swapchains
+/// that are not worth a PipeWire node each.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SwapchainFilter {
+    pub min_width: u32,
+    pub min_height: u32,
+    /// Platforms to capture, all if empty
+    pub platforms: Vec<SurfacePlatform>,
+}
+
+impl Default for SwapchainFilter {
+    fn default() -> Self {
+        Self {
+            min_width: 64,
+            min_height: 64,
+            platforms: vec![],
+        }
+    }
+}
+
+impl SwapchainFilter {
+    /// Parses `<width>x<height>` minimum size
+    fn parse_min_size(value: &str) -> Option<(u32, u32)> {
+        let (width, height) = value.trim().split_once('x')?;
+        Some((width.parse().ok()?, height.parse().ok()?))
+    }
+
+    /// Parses comma separated platforms
+    fn parse_platforms(value: &str) -> Option<Vec<SurfacePlatform>> {
+        value.split(',').map(SurfacePlatform::parse).collect()
+    }
+
+    #[named]
+    fn from_env() -> Self {
+        let mut filter = Self::default();
+        if let Some(value) = setting("PW_CAPTURE_MIN_SIZE") {
+            match Self::parse_min_size(&value) {
+                Some((width, height)) => {
+                    filter.min_width = width;
+                    filter.min_height = height;
+                }
+                None => warn!("invalid PW_CAPTURE_MIN_SIZE {value:?}"),
+            }
+        }
+        if let Some(value) = setting("PW_CAPTURE_PLATFORMS") {
+            match Self::parse_platforms(&value) {
+                Some(platforms) => filter.platforms = platforms,
+                None => warn!("invalid PW_CAPTURE_PLATFORMS {value:?}"),
+            }
+        }
+        debug!("swapchain filter: {:?}", filter);
+        filter
+    }
+
+    /// Filter set by `PW_CAPTURE_MIN_SIZE` and `PW_CAPTURE_PLATFORMS`
+    pub fn global() -> &'static Self {
+        &SWAPCHAIN_FILTER
+    }
+
+    /// Whether swapchain of `extent` on a surface of `platform` is captured,
+    /// surfaces not created through the layer have no known platform
+    pub fn matches(&self, platform: Option<SurfacePlatform>, extent: vk::Extent2D) -> bool {
+        if extent.width < self.min_width || extent.height < self.min_height {
+            return false;
+        }
+        match platform {
+            Some(platform) => self.platforms.is_empty() || self.platforms.contains(&platform),
+            None => self.platforms.is_empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32) -> vk::Extent2D {
+        vk::Extent2D { width, height }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(SwapchainFilter::parse_min_size("32x16"), Some((32, 16)));
+        assert_eq!(SwapchainFilter::parse_min_size(" 0x0 "), Some((0, 0)));
+        assert_eq!(SwapchainFilter::parse_min_size("32"), None);
+        assert_eq!(
+            SwapchainFilter::parse_platforms("wayland, X11,display,headless"),
+            Some(vec![
+                SurfacePlatform::Wayland,
+                SurfacePlatform::X11,
+                SurfacePlatform::Display,
+                SurfacePlatform::Headless
+            ])
+        );
+        assert_eq!(SwapchainFilter::parse_platforms("wayland,win32"), None);
+    }
+
+    #[test]
+    fn matches() {
+        let filter = SwapchainFilter::default();
+        assert!(filter.matches(Some(SurfacePlatform::X11), extent(1920, 1080)));
+        assert!(filter.matches(None, extent(64, 64)));
+        assert!(!filter.matches(Some(SurfacePlatform::X11), extent(32, 32)));
+        assert!(!filter.matches(Some(SurfacePlatform::Wayland), extent(1, 1)));
+
+        let filter = SwapchainFilter {
+            platforms: vec![SurfacePlatform::Wayland],
+            ..Default::default()
+        };
+        assert!(filter.matches(Some(SurfacePlatform::Wayland), extent(1920, 1080)));
+        assert!(!filter.matches(Some(SurfacePlatform::X11), extent(1920, 1080)));
+        assert!(!filter.matches(None, extent(1920, 1080)));
+    }
+}
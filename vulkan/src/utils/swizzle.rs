@@ -0,0 +1,515 @@
+use crate::utils::*;
+
+use core::ffi::CStr;
+use std::io::Cursor;
+
+use anyhow::{anyhow, Result};
+use ash::prelude::VkResult;
+use ash::vk;
+use pw_capture_client::Format;
+
+// assembled from swap_channels.spvasm, e.g. with `spirv-as --target-env vulkan1.0`
+const SWAP_CHANNELS_SPV: &[u8] = include_bytes!("../shaders/swap_channels.spv");
+const LOCAL_SIZE: u32 = 8;
+
+/// Texels of 32-bit formats are copied through an image of this format, which
+/// is size-compatible with all of them and always usable as storage image
+const TEXEL_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+/// Two channels of 32-bit texels to swap, `mask` masking the one at the
+/// lowest bits and `shift` being the offset of the other
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Swizzle {
+    pub mask: u32,
+    pub shift: u32,
+}
+
+/// Channels to swap converting `src_format` to `dst_format` if both only
+/// differ in channel order, e.g. BGRA and RGBA
+pub fn get_swizzle(src_format: vk::Format, dst_format: vk::Format) -> Option<Swizzle> {
+    let src = vk_format_get_info(src_format);
+    let dst = vk_format_get_info(dst_format);
+    if src.transfer != dst.transfer {
+        return None;
+    }
+    match (src.format, dst.format) {
+        (Format::BGRA, Format::RGBA) | (Format::RGBA, Format::BGRA) => Some(Swizzle {
+            mask: 0xff,
+            shift: 16,
+        }),
+        (Format::BGRA_102LE, Format::RGBA_102LE) | (Format::RGBA_102LE, Format::BGRA_102LE) => {
+            Some(Swizzle {
+                mask: 0x3ff,
+                shift: 20,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Compute pipeline swapping channels of swapchain images, for format pairs
+/// the device can not blit between
+///
+/// Swapchain image is copied to an intermediate storage image, swizzled in
+/// place and copied to the export image, so export images only need to be
+/// copyable as they would be without conversion.
+pub struct SwizzleConverter {
+    shader: vk::ShaderModule,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    width: u32,
+    height: u32,
+    allocator: Allocator,
+}
+
+impl SwizzleConverter {
+    pub unsafe fn new(
+        ash_instance: &ash::Instance,
+        ash_device: &ash::Device,
+        phy_device: vk::PhysicalDevice,
+        swizzle: Swizzle,
+        width: u32,
+        height: u32,
+        allocator: Allocator,
+    ) -> Result<Self> {
+        let mut converter = Self {
+            shader: vk::ShaderModule::null(),
+            set_layout: vk::DescriptorSetLayout::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            image: vk::Image::null(),
+            memory: vk::DeviceMemory::null(),
+            view: vk::ImageView::null(),
+            width,
+            height,
+            allocator,
+        };
+        // frees partially created objects on error
+        if let Err(e) = converter.init(ash_instance, ash_device, phy_device, swizzle) {
+            converter.destroy(ash_device);
+            return Err(e);
+        }
+        Ok(converter)
+    }
+
+    unsafe fn init(
+        &mut self,
+        ash_instance: &ash::Instance,
+        ash_device: &ash::Device,
+        phy_device: vk::PhysicalDevice,
+        swizzle: Swizzle,
+    ) -> Result<()> {
+        let allocator = self.allocator.callbacks();
+        let code = ash::util::read_spv(&mut Cursor::new(SWAP_CHANNELS_SPV))?;
+        let shader_info = vk::ShaderModuleCreateInfo::builder().code(&code);
+        self.shader = ash_device.create_shader_module(&shader_info, allocator)?;
+
+        let bindings = [vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build()];
+        let set_layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        self.set_layout = ash_device.create_descriptor_set_layout(&set_layout_info, allocator)?;
+
+        let set_layouts = [self.set_layout];
+        let pipeline_layout_info =
+            vk::PipelineLayoutCreateInfo::builder().set_layouts(&set_layouts);
+        self.pipeline_layout =
+            ash_device.create_pipeline_layout(&pipeline_layout_info, allocator)?;
+
+        let spec_data = [swizzle.mask, swizzle.shift];
+        let map_entries = [0, 1].map(|constant_id| vk::SpecializationMapEntry {
+            constant_id,
+            offset: constant_id * std::mem::size_of::<u32>() as u32,
+            size: std::mem::size_of::<u32>(),
+        });
+        let spec_info = vk::SpecializationInfo::builder()
+            .map_entries(&map_entries)
+            .data(std::slice::from_raw_parts(
+                spec_data.as_ptr() as *const u8,
+                std::mem::size_of_val(&spec_data),
+            ));
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(self.shader)
+            .name(CStr::from_bytes_with_nul_unchecked(b"main\0"))
+            .specialization_info(&spec_info)
+            .build();
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(self.pipeline_layout)
+            .build();
+        self.pipeline = ash_device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], allocator)
+            .map_err(|(_, e)| e)?[0];
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: 1,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        self.descriptor_pool = ash_device.create_descriptor_pool(&pool_info, allocator)?;
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(TEXEL_FORMAT)
+            .extent(vk::Extent3D {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(
+                vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST
+                    | vk::ImageUsageFlags::STORAGE,
+            )
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        self.image = ash_device.create_image(&image_info, allocator)?;
+
+        let requirements = ash_device.get_image_memory_requirements(self.image);
+        let index = get_memory_type_indices(
+            ash_instance,
+            phy_device,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            requirements,
+        )
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("no memory type for intermediate image"))?;
+        let memory_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(index);
+        self.memory = ash_device.allocate_memory(&memory_info, allocator)?;
+        ash_device.bind_image_memory(self.image, self.memory, 0)?;
+
+        self.view = create_view(
+            ash_device,
+            self.image,
+            TEXEL_FORMAT,
+            vk::ImageAspectFlags::COLOR,
+            allocator,
+        )?;
+
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        self.descriptor_set = ash_device.allocate_descriptor_sets(&alloc_info)?[0];
+        let image_info = [vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: self.view,
+            image_layout: vk::ImageLayout::GENERAL,
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .image_info(&image_info)
+            .build();
+        ash_device.update_descriptor_sets(&[write], &[]);
+
+        Ok(())
+    }
+
+    /// Records copy of `src_image` and conversion into `export_image`, mirrors
+    /// barriers, `host_read` and `timestamp` of `record_copy_image`
+    pub unsafe fn record(
+        &self,
+        ash_device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        src_image: vk::Image,
+        src_layout: vk::ImageLayout,
+        export_image: vk::Image,
+        mut src_queue_family: u32,
+        mut dst_queue_family: u32,
+        host_read: bool,
+        timestamp: Option<vk::QueryPool>,
+    ) -> VkResult<()> {
+        if src_queue_family == dst_queue_family {
+            src_queue_family = vk::QUEUE_FAMILY_IGNORED;
+            dst_queue_family = vk::QUEUE_FAMILY_IGNORED;
+        }
+        let copy_layout =
+            swapchain_transfer_layout(src_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+        // submitted again on later presents
+        let begin_info = vk::CommandBufferBeginInfo::builder();
+        ash_device.begin_command_buffer(command_buffer, &begin_info)?;
+        if let Some(query_pool) = timestamp {
+            record_timestamp(ash_device, command_buffer, query_pool);
+        }
+
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+        let subresource_layer = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+        // formats are size-compatible, channels are swapped in shader
+        let image_copy = vk::ImageCopy::builder()
+            .extent(vk::Extent3D {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            })
+            .src_subresource(subresource_layer)
+            .dst_subresource(subresource_layer)
+            .build();
+
+        let src_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(src_layout)
+            .new_layout(copy_layout)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .image(src_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::MEMORY_READ)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build();
+
+        // previous conversion might still be reading it
+        let texel_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[src_barrier, texel_barrier],
+        );
+
+        ash_device.cmd_copy_image(
+            command_buffer,
+            src_image,
+            copy_layout,
+            self.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[image_copy],
+        );
+
+        let src_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(copy_layout)
+            .new_layout(src_layout)
+            .src_queue_family_index(dst_queue_family)
+            .dst_queue_family_index(src_queue_family)
+            .image(src_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+            .build();
+
+        let texel_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[src_barrier, texel_barrier],
+        );
+
+        ash_device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline,
+        );
+        ash_device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+            0,
+            &[self.descriptor_set],
+            &[],
+        );
+        ash_device.cmd_dispatch(
+            command_buffer,
+            (self.width + LOCAL_SIZE - 1) / LOCAL_SIZE,
+            (self.height + LOCAL_SIZE - 1) / LOCAL_SIZE,
+            1,
+        );
+
+        let texel_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build();
+
+        let dst_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(export_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[texel_barrier, dst_barrier],
+        );
+
+        ash_device.cmd_copy_image(
+            command_buffer,
+            self.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            export_image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[image_copy],
+        );
+
+        let dst_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(export_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(match host_read {
+                true => vk::AccessFlags::HOST_READ,
+                false => vk::AccessFlags::empty(),
+            })
+            .build();
+
+        let dst_stage = match host_read {
+            true => vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::HOST,
+            false => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        };
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[dst_barrier],
+        );
+
+        ash_device.end_command_buffer(command_buffer)?;
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&self, ash_device: &ash::Device) {
+        let allocator = self.allocator.callbacks();
+        ash_device.destroy_image_view(self.view, allocator);
+        ash_device.destroy_image(self.image, allocator);
+        ash_device.free_memory(self.memory, allocator);
+        // frees its descriptor set
+        ash_device.destroy_descriptor_pool(self.descriptor_pool, allocator);
+        ash_device.destroy_pipeline(self.pipeline, allocator);
+        ash_device.destroy_pipeline_layout(self.pipeline_layout, allocator);
+        ash_device.destroy_descriptor_set_layout(self.set_layout, allocator);
+        ash_device.destroy_shader_module(self.shader, allocator);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swizzle_shader() {
+        let code = ash::util::read_spv(&mut Cursor::new(SWAP_CHANNELS_SPV)).unwrap();
+        // magic and SPIR-V 1.0
+        assert_eq!(code[0], 0x07230203);
+        assert_eq!(code[1], 0x00010000);
+    }
+
+    #[test]
+    fn swizzles() {
+        let rb8 = Some(Swizzle {
+            mask: 0xff,
+            shift: 16,
+        });
+        assert_eq!(
+            get_swizzle(vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM),
+            rb8
+        );
+        assert_eq!(
+            get_swizzle(vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_SRGB),
+            rb8
+        );
+        assert_eq!(
+            get_swizzle(
+                vk::Format::A2R10G10B10_UNORM_PACK32,
+                vk::Format::A2B10G10R10_UNORM_PACK32
+            ),
+            Some(Swizzle {
+                mask: 0x3ff,
+                shift: 20,
+            })
+        );
+        // transfer differs
+        assert_eq!(
+            get_swizzle(vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_UNORM),
+            None
+        );
+        // size differs
+        assert_eq!(
+            get_swizzle(vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8_UNORM),
+            None
+        );
+        assert_eq!(
+            get_swizzle(vk::Format::B8G8R8A8_UNORM, vk::Format::B8G8R8A8_UNORM),
+            None
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use crate::utils::*;
+
+use core::ffi::{c_void, CStr};
+use std::sync::RwLock;
+
+use ash::prelude::VkResult;
+use ash::vk;
+use dashmap::{DashMap, DashSet};
+use function_name::named;
+
+// bounds how long device destruction waits for stream workers
+const POLL_TIMEOUT: u64 = 100_000_000;
+
+/// Timeline semaphore values to wait for
+pub type TimelineWaits = Vec<(vk::Semaphore, u64)>;
+
+/// Tracks timeline semaphores of a device
+///
+/// Presents can only wait on binary semaphores, those are in turn signaled by
+/// submits that may wait on timeline values the app signals from host later
+/// on. Timeline waits of submits are recorded on the binary semaphores they
+/// signal so presents can tell whether their frames depend on such values.
+pub struct TimelineSemaphores {
+    device: vk::Device,
+    fp: vk::KhrTimelineSemaphoreFn,
+    /// Cleared once the device got destroyed, held while waiting on it
+    alive: RwLock<bool>,
+    timeline_semaphores: DashSet<vk::Semaphore>,
+    binary_waits: DashMap<vk::Semaphore, TimelineWaits>,
+}
+
+impl TimelineSemaphores {
+    /// Loads entry points of Vulkan 1.2 or `VK_KHR_timeline_semaphore`
+    pub unsafe fn load(
+        device: vk::Device,
+        mut load_fn: impl FnMut(&CStr) -> *const c_void,
+    ) -> Self {
+        let fp = vk::KhrTimelineSemaphoreFn::load(|name| {
+            let pfn = load_fn(name);
+            if !pfn.is_null() {
+                return pfn;
+            }
+            let core_name = name.to_bytes_with_nul();
+            let core_name = match core_name.strip_suffix(b"KHR\0") {
+                Some(v) => [v, b"\0"].concat(),
+                None => return pfn,
+            };
+            load_fn(CStr::from_bytes_with_nul_unchecked(&core_name))
+        });
+        Self {
+            device,
+            fp,
+            alive: RwLock::new(true),
+            timeline_semaphores: DashSet::new(),
+            binary_waits: DashMap::new(),
+        }
+    }
+
+    pub unsafe fn add_semaphore(&self, semaphore: vk::Semaphore, p_next: *const c_void) {
+        let type_info = find_in_chain::<vk::SemaphoreTypeCreateInfo>(
+            p_next,
+            vk::StructureType::SEMAPHORE_TYPE_CREATE_INFO,
+        );
+        if matches!(type_info, Some(info) if info.semaphore_type == vk::SemaphoreType::TIMELINE) {
+            self.timeline_semaphores.insert(semaphore);
+        }
+    }
+
+    pub fn remove_semaphore(&self, semaphore: vk::Semaphore) {
+        self.timeline_semaphores.remove(&semaphore);
+        self.binary_waits.remove(&semaphore);
+    }
+
+    /// Records a submit waiting on `waits` and signaling `signals`, values of
+    /// binary semaphores are ignored
+    pub fn record_submit(
+        &self,
+        waits: impl IntoIterator<Item = (vk::Semaphore, u64)>,
+        signals: impl IntoIterator<Item = vk::Semaphore>,
+    ) {
+        let mut timeline_waits = vec![];
+        for (semaphore, value) in waits {
+            if self.timeline_semaphores.contains(&semaphore) {
+                timeline_waits.push((semaphore, value));
+            } else if let Some((_, waits)) = self.binary_waits.remove(&semaphore) {
+                // waiting unsignals binary semaphores, propagate what they depend on
+                timeline_waits.extend(waits);
+            }
+        }
+        for semaphore in signals {
+            if self.timeline_semaphores.contains(&semaphore) {
+                continue;
+            }
+            if timeline_waits.is_empty() {
+                self.binary_waits.remove(&semaphore);
+            } else {
+                self.binary_waits.insert(semaphore, timeline_waits.clone());
+            }
+        }
+    }
+
+    /// Timeline values presents waiting on binary `semaphores` depend on
+    pub fn take_waits(&self, semaphores: &[vk::Semaphore]) -> TimelineWaits {
+        semaphores
+            .iter()
+            .filter_map(|semaphore| self.binary_waits.remove(semaphore))
+            .flat_map(|(_, waits)| waits)
+            .collect()
+    }
+
+    pub unsafe fn is_reached(&self, waits: &[(vk::Semaphore, u64)]) -> VkResult<bool> {
+        for &(semaphore, value) in waits {
+            let mut current = 0;
+            (self.fp.get_semaphore_counter_value_khr)(self.device, semaphore, &mut current)
+                .result()?;
+            if current < value {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    unsafe fn wait(&self, waits: &[(vk::Semaphore, u64)], timeout: u64) -> VkResult<bool> {
+        let (semaphores, values): (Vec<_>, Vec<_>) = waits.iter().copied().unzip();
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values);
+        match (self.fp.wait_semaphores_khr)(self.device, &*wait_info, timeout) {
+            vk::Result::SUCCESS => Ok(true),
+            vk::Result::TIMEOUT => Ok(false),
+            res => Err(res),
+        }
+    }
+
+    /// Blocks until `waits` got reached, or the device got lost or destroyed
+    #[named]
+    pub fn wait_reached(&self, waits: &[(vk::Semaphore, u64)]) {
+        loop {
+            let alive = self.alive.read().unwrap();
+            if !*alive {
+                break;
+            }
+            match unsafe { self.wait(waits, POLL_TIMEOUT) } {
+                Ok(false) => continue,
+                Ok(true) => break,
+                Err(e) => {
+                    warn!("failed to wait for timeline semaphores: {e:?}");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Stops waiting on the device, called before it gets destroyed
+    pub fn destroy(&self) {
+        *self.alive.write().unwrap() = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ash::vk::Handle;
+    use core::ptr;
+
+    fn semaphore(raw: u64) -> vk::Semaphore {
+        vk::Semaphore::from_raw(raw)
+    }
+
+    #[test]
+    fn propagate_waits() {
+        let timelines = unsafe { TimelineSemaphores::load(vk::Device::null(), |_| ptr::null()) };
+        let type_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .build();
+        unsafe { timelines.add_semaphore(semaphore(1), &type_info as *const _ as _) };
+        unsafe { timelines.add_semaphore(semaphore(2), ptr::null()) };
+
+        // render submit waits on timeline, signals binary 2 and timeline
+        timelines.record_submit([(semaphore(1), 5)], [semaphore(2), semaphore(1)]);
+        // copy submit waits on binary 2, signals binary 3
+        timelines.record_submit([(semaphore(2), 0)], [semaphore(3)]);
+        assert!(timelines.take_waits(&[semaphore(2)]).is_empty());
+        assert_eq!(timelines.take_waits(&[semaphore(3)]), [(semaphore(1), 5)]);
+        assert!(timelines.take_waits(&[semaphore(3)]).is_empty());
+
+        // signals without timeline waits clear previous dependencies
+        timelines.record_submit([(semaphore(1), 6)], [semaphore(2)]);
+        timelines.record_submit([], [semaphore(2)]);
+        assert!(timelines.take_waits(&[semaphore(2)]).is_empty());
+    }
+}
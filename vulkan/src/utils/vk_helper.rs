@@ -1,40 +1,173 @@
 use crate::utils::*;
 
-use anyhow::Result;
+use core::ffi::c_void;
+use core::ptr;
+use core::slice;
+
+use anyhow::{anyhow, Result};
 use ash::extensions::khr;
 use ash::prelude::VkResult;
 use ash::vk;
 use function_name::named;
+use pw_capture_client::{dma_buf_import_sync_file, DrmDevice};
+
+/// Finds structure of `s_type` in `p_next` chain
+pub unsafe fn find_in_chain<'a, T>(
+    mut p_next: *const c_void,
+    s_type: vk::StructureType,
+) -> Option<&'a T> {
+    while !p_next.is_null() {
+        let base = &*(p_next as *const vk::BaseInStructure);
+        if base.s_type == s_type {
+            return Some(&*(p_next as *const T));
+        }
+        p_next = base.p_next as _;
+    }
+    None
+}
 
-pub struct FenceState {
-    fence: vk::Fence,
-    busy: bool,
+/// Slice of `len` elements at `data`, which may be null if empty
+pub unsafe fn raw_slice<'a, T>(data: *const T, len: u32) -> &'a [T] {
+    if data.is_null() || len == 0 {
+        return &[];
+    }
+    slice::from_raw_parts(data, len as _)
 }
 
-impl FenceState {
-    pub unsafe fn new(device: &ash::Device) -> VkResult<Self> {
-        let fence_info = vk::FenceCreateInfo::builder();
-        let fence = device.create_fence(&fence_info, None)?;
-        Ok(Self { fence, busy: false })
+/// Allocation callbacks the app created a device with, objects the layer
+/// creates on the device are allocated through them as well
+#[derive(Clone, Copy, Default)]
+pub struct Allocator(Option<vk::AllocationCallbacks>);
+
+// callbacks are required to be callable from any thread
+unsafe impl Send for Allocator {}
+unsafe impl Sync for Allocator {}
+
+impl Allocator {
+    /// Copies callbacks at `p_allocator`, which may be null
+    pub unsafe fn from_raw(p_allocator: *const vk::AllocationCallbacks) -> Self {
+        Self(p_allocator.as_ref().copied())
+    }
+
+    pub fn callbacks(&self) -> Option<&vk::AllocationCallbacks> {
+        self.0.as_ref()
+    }
+}
+
+/// Semaphores signaled by submits of the layer and waited on by presents
+///
+/// Presents give no notice once their waits completed, a semaphore may only be
+/// signaled again once the image presented with it got acquired again, which
+/// implies the present waited on it. Presents of `MAILBOX` or `IMMEDIATE`
+/// swapchains may be replaced and their images not acquired for a while, more
+/// semaphores are created then, up to twice the initial count, before waiting
+/// for the present queue to go idle.
+#[derive(Default)]
+pub struct SemaphorePool {
+    slots: Vec<PresentSlot>,
+    /// Times each swapchain image got acquired
+    acquires: Vec<u64>,
+    max_len: usize,
+    allocator: Allocator,
+}
+
+fn acquire_count(acquires: &[u64], image_index: usize) -> u64 {
+    acquires.get(image_index).copied().unwrap_or_default()
+}
+
+struct PresentSlot {
+    semaphore: vk::Semaphore,
+    /// Image presented with the semaphore and its acquire count back then
+    presented: Option<(usize, u64)>,
+}
+
+impl SemaphorePool {
+    pub unsafe fn new(
+        device: &ash::Device,
+        image_count: usize,
+        frames_in_flight: usize,
+        allocator: Allocator,
+    ) -> VkResult<Self> {
+        let len = image_count + frames_in_flight;
+        let mut pool = Self {
+            slots: Vec::with_capacity(len),
+            acquires: vec![0; image_count],
+            max_len: len * 2,
+            allocator,
+        };
+        let semaphore_info = vk::SemaphoreCreateInfo::builder();
+        for _ in 0..len {
+            match device.create_semaphore(&semaphore_info, allocator.callbacks()) {
+                Ok(semaphore) => pool.slots.push(PresentSlot {
+                    semaphore,
+                    presented: None,
+                }),
+                Err(e) => {
+                    pool.destroy(device);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(pool)
+    }
+
+    /// Records the app acquired image at `image_index`
+    pub fn acquired(&mut self, image_index: usize) {
+        if let Some(acquires) = self.acquires.get_mut(image_index) {
+            *acquires += 1;
+        }
     }
 
-    pub unsafe fn use_fence(&mut self) -> vk::Fence {
-        assert!(!self.busy);
-        self.busy = true;
-        self.fence
+    /// Takes a semaphore no present waits on anymore for presenting image at
+    /// `image_index`
+    fn take_free(&mut self, image_index: usize) -> Option<vk::Semaphore> {
+        let acquires = &self.acquires;
+        let slot = self.slots.iter_mut().find(|slot| match slot.presented {
+            Some((image, seq)) => acquire_count(acquires, image) > seq,
+            None => true,
+        })?;
+        slot.presented = Some((image_index, acquire_count(acquires, image_index)));
+        Some(slot.semaphore)
     }
 
-    pub unsafe fn wait_and_reset(&mut self, device: &ash::Device) -> VkResult<()> {
-        if self.busy {
-            device.wait_for_fences(&[self.fence], true, u64::MAX)?;
-            device.reset_fences(&[self.fence])?;
-            self.busy = false;
+    /// Semaphore for the next submit presenting image at `image_index` from
+    /// `queue`, `None` if the pool is empty
+    ///
+    /// `queue` must be externally synchronized, it is waited on if all
+    /// semaphores may still be waited on by presents.
+    pub unsafe fn next_semaphore(
+        &mut self,
+        device: &ash::Device,
+        queue: vk::Queue,
+        image_index: usize,
+    ) -> VkResult<Option<vk::Semaphore>> {
+        if self.slots.is_empty() {
+            return Ok(None);
+        }
+        if let Some(semaphore) = self.take_free(image_index) {
+            return Ok(Some(semaphore));
         }
-        Ok(())
+        if self.slots.len() < self.max_len {
+            let semaphore_info = vk::SemaphoreCreateInfo::builder();
+            let semaphore = device.create_semaphore(&semaphore_info, self.allocator.callbacks())?;
+            self.slots.push(PresentSlot {
+                semaphore,
+                presented: Some((image_index, acquire_count(&self.acquires, image_index))),
+            });
+            return Ok(Some(semaphore));
+        }
+        // waits of presents from `queue` completed once it is idle
+        device.queue_wait_idle(queue)?;
+        for slot in &mut self.slots {
+            slot.presented = None;
+        }
+        Ok(self.take_free(image_index))
     }
 
     pub unsafe fn destroy(&self, device: &ash::Device) {
-        device.destroy_fence(self.fence, None);
+        for slot in &self.slots {
+            device.destroy_semaphore(slot.semaphore, self.allocator.callbacks());
+        }
     }
 }
 
@@ -45,6 +178,8 @@ pub unsafe fn get_supported_modifiers(
     format: vk::Format,
     usage: vk::ImageUsageFlags,
     features: vk::FormatFeatureFlags,
+    flags: vk::ImageCreateFlags,
+    view_formats: &[vk::Format],
 ) -> Result<Vec<vk::DrmFormatModifierPropertiesEXT>> {
     let mut modifier_props_list = vk::DrmFormatModifierPropertiesListEXT::builder().build();
     let mut props = vk::FormatProperties2KHR::builder()
@@ -73,14 +208,20 @@ pub unsafe fn get_supported_modifiers(
                 .drm_format_modifier(props.drm_format_modifier)
                 .sharing_mode(vk::SharingMode::EXCLUSIVE)
                 .build();
-            let image_format_info = vk::PhysicalDeviceImageFormatInfo2KHR::builder()
+            let mut format_list = vk::ImageFormatListCreateInfo::builder()
+                .view_formats(view_formats)
+                .build();
+            let mut image_format_info = vk::PhysicalDeviceImageFormatInfo2KHR::builder()
                 .push_next(&mut external_info)
                 .push_next(&mut modifier_info)
                 .format(format)
                 .ty(vk::ImageType::TYPE_2D)
                 .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
                 .usage(usage)
-                .flags(vk::ImageCreateFlags::empty());
+                .flags(flags);
+            if !view_formats.is_empty() {
+                image_format_info = image_format_info.push_next(&mut format_list);
+            }
             let mut image_format_props = vk::ImageFormatProperties2KHR::builder();
             khr_phy_props2
                 .get_physical_device_image_format_properties2(
@@ -131,6 +272,10 @@ pub unsafe fn create_target_image(
     height: u32,
     modifier: u64,
     num_planes: u32,
+    usage: vk::ImageUsageFlags,
+    flags: vk::ImageCreateFlags,
+    view_formats: &[vk::Format],
+    allocator: Option<&vk::AllocationCallbacks>,
 ) -> Result<(
     vk::Image,
     vk::DeviceMemory,
@@ -142,9 +287,13 @@ pub unsafe fn create_target_image(
     let mut external_info = vk::ExternalMemoryImageCreateInfo::builder()
         .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT)
         .build();
-    let image_info = vk::ImageCreateInfo::builder()
+    let mut format_list = vk::ImageFormatListCreateInfo::builder()
+        .view_formats(view_formats)
+        .build();
+    let mut image_info = vk::ImageCreateInfo::builder()
         .push_next(&mut external_info)
         .push_next(&mut modidier_list)
+        .flags(flags)
         .image_type(vk::ImageType::TYPE_2D)
         .format(format)
         .extent(vk::Extent3D {
@@ -156,11 +305,14 @@ pub unsafe fn create_target_image(
         .array_layers(1)
         .samples(vk::SampleCountFlags::TYPE_1)
         .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
-        .usage(vk::ImageUsageFlags::TRANSFER_DST)
+        .usage(vk::ImageUsageFlags::TRANSFER_DST | usage)
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    if !view_formats.is_empty() {
+        image_info = image_info.push_next(&mut format_list);
+    }
 
-    let image = ash_device.create_image(&image_info, None)?;
+    let image = ash_device.create_image(&image_info, allocator)?;
 
     let requirements = ash_device.get_image_memory_requirements(image);
 
@@ -177,7 +329,7 @@ pub unsafe fn create_target_image(
         let memory_info = vk::MemoryAllocateInfo::builder()
             .allocation_size(requirements.size)
             .memory_type_index(i);
-        memory = ash_device.allocate_memory(&memory_info, None);
+        memory = ash_device.allocate_memory(&memory_info, allocator);
         if memory.is_ok() {
             break;
         }
@@ -219,25 +371,316 @@ pub unsafe fn create_target_image(
     Ok((image, memory, fds))
 }
 
+/// Primary and render node of `phy_device`, render node first, requires
+/// `VK_EXT_physical_device_drm` to be supported
+pub unsafe fn get_drm_devices(
+    khr_phy_props2: &khr::GetPhysicalDeviceProperties2,
+    phy_device: vk::PhysicalDevice,
+) -> Vec<DrmDevice> {
+    let mut drm_props = vk::PhysicalDeviceDrmPropertiesEXT::default();
+    let mut props = vk::PhysicalDeviceProperties2KHR::builder()
+        .push_next(&mut drm_props)
+        .build();
+    khr_phy_props2.get_physical_device_properties2(phy_device, &mut props);
+
+    let mut devices = vec![];
+    if drm_props.has_render == vk::TRUE {
+        let (major, minor) = (drm_props.render_major, drm_props.render_minor);
+        devices.push(DrmDevice::new(major as _, minor as _));
+    }
+    if drm_props.has_primary == vk::TRUE {
+        let (major, minor) = (drm_props.primary_major, drm_props.primary_minor);
+        devices.push(DrmDevice::new(major as _, minor as _));
+    }
+    devices
+}
+
+/// Host visible linear image frames get copied into before being copied to
+/// shared memory consumers on other devices can read
+pub struct HostImage {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub layout: vk::SubresourceLayout,
+    pub map: HostMap,
+}
+
+/// Mappings of a host image and of the memfd it gets copied to, as addresses
+/// so they can be copied on stream workers
+#[derive(Clone, Copy, Debug)]
+pub struct HostMap {
+    src: usize,
+    dst: usize,
+    size: usize,
+}
+
+impl HostMap {
+    /// Copies the frame, the copy into the host image must have completed
+    pub unsafe fn copy(&self) {
+        ptr::copy_nonoverlapping(self.src as *const u8, self.dst as *mut u8, self.size);
+    }
+
+    pub unsafe fn unmap_memfd(&self) {
+        libc::munmap(self.dst as _, self.size);
+    }
+}
+
+/// Creates a host image along with a memfd of the same row pitch, returns the
+/// memfd
+pub unsafe fn create_host_image(
+    ash_instance: &ash::Instance,
+    ash_device: &ash::Device,
+    phy_device: vk::PhysicalDevice,
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    allocator: Option<&vk::AllocationCallbacks>,
+) -> Result<(HostImage, i32)> {
+    let image_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(format)
+        .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::LINEAR)
+        .usage(vk::ImageUsageFlags::TRANSFER_DST)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let image = ash_device.create_image(&image_info, allocator)?;
+
+    let requirements = ash_device.get_image_memory_requirements(image);
+    let host_flags = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+    // cached memory is way faster to read from
+    let indices = get_memory_type_indices(
+        ash_instance,
+        phy_device,
+        host_flags | vk::MemoryPropertyFlags::HOST_CACHED,
+        requirements,
+    )
+    .into_iter()
+    .chain(get_memory_type_indices(
+        ash_instance,
+        phy_device,
+        host_flags,
+        requirements,
+    ));
+
+    let mut memory: VkResult<vk::DeviceMemory> = Err(vk::Result::ERROR_UNKNOWN);
+    for i in indices {
+        let memory_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(i);
+        memory = ash_device.allocate_memory(&memory_info, allocator);
+        if memory.is_ok() {
+            break;
+        }
+    }
+    let memory = match memory {
+        Ok(v) => v,
+        Err(e) => {
+            ash_device.destroy_image(image, allocator);
+            return Err(e.into());
+        }
+    };
+
+    let res = (|| -> Result<(HostImage, i32)> {
+        ash_device.bind_image_memory(image, memory, 0)?;
+        let subresource = vk::ImageSubresource::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .array_layer(0)
+            .build();
+        let mut layout = ash_device.get_image_subresource_layout(image, subresource);
+        let src = ash_device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())?;
+        let size = layout.row_pitch as usize * height as usize;
+        let (fd, dst) = create_memfd(size)?;
+        let map = HostMap {
+            src: src as usize + layout.offset as usize,
+            dst: dst as usize,
+            size,
+        };
+        // memfd holds just the rows
+        layout.offset = 0;
+        layout.size = size as _;
+        let host_image = HostImage {
+            image,
+            memory,
+            layout,
+            map,
+        };
+        Ok((host_image, fd))
+    })();
+    if res.is_err() {
+        ash_device.destroy_image(image, allocator);
+        ash_device.free_memory(memory, allocator);
+    }
+    res
+}
+
+/// Creates a memfd of `size` bytes along with a shared mapping of it
+pub unsafe fn create_memfd(size: usize) -> Result<(i32, *mut c_void)> {
+    let fd = libc::memfd_create(b"pw-capture-vk\0".as_ptr() as _, libc::MFD_CLOEXEC);
+    if fd < 0 {
+        return Err(anyhow!(
+            "failed to create memfd: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    if libc::ftruncate(fd, size as _) < 0 {
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        return Err(anyhow!("failed to resize memfd: {err}"));
+    }
+    let map = libc::mmap(
+        ptr::null_mut(),
+        size,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        fd,
+        0,
+    );
+    if map == libc::MAP_FAILED {
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        return Err(anyhow!("failed to map memfd: {err}"));
+    }
+    Ok((fd, map))
+}
+
+/// 2D view of the single level and layer of `image`
+pub unsafe fn create_view(
+    ash_device: &ash::Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+    allocator: Option<&vk::AllocationCallbacks>,
+) -> VkResult<vk::ImageView> {
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspect_mask)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build();
+    let view_info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .subresource_range(subresource);
+    ash_device.create_image_view(&view_info, allocator)
+}
+
+/// Exports pending signal of `semaphore` as sync file and attaches it to
+/// `dma_buf`, the semaphore is unsignaled afterwards. Failing to export, the
+/// signal is waited on by `queue` instead so the next copy can signal it again.
+pub unsafe fn attach_sync_file(
+    ash_device: &ash::Device,
+    khr_semaphore_fd: &khr::ExternalSemaphoreFd,
+    queue: vk::Queue,
+    semaphore: vk::Semaphore,
+    dma_buf: i32,
+) -> Result<()> {
+    let fd_info = vk::SemaphoreGetFdInfoKHR::builder()
+        .semaphore(semaphore)
+        .handle_type(vk::ExternalSemaphoreHandleTypeFlags::SYNC_FD);
+    let sync_file = match khr_semaphore_fd.get_semaphore_fd(&fd_info) {
+        Ok(v) => v,
+        Err(e) => {
+            let wait_semaphores = [semaphore];
+            let wait_stages = [vk::PipelineStageFlags::ALL_COMMANDS];
+            let submit_info = vk::SubmitInfo::builder()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .build();
+            ash_device
+                .queue_submit(queue, &[submit_info], vk::Fence::null())
+                .map_err(|e| anyhow!("failed to wait on unexported semaphore: {e}"))?;
+            return Err(e.into());
+        }
+    };
+    // already signaled
+    if sync_file < 0 {
+        return Ok(());
+    }
+    let res = dma_buf_import_sync_file(dma_buf, sync_file);
+    libc::close(sync_file);
+    Ok(res?)
+}
+
+/// Resets and writes the only query of timestamp `query_pool` at the transfer
+/// stage, i.e. once semaphores waited for by transfers got signaled
+pub unsafe fn record_timestamp(
+    ash_device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    query_pool: vk::QueryPool,
+) {
+    ash_device.cmd_reset_query_pool(command_buffer, query_pool, 0, 1);
+    ash_device.cmd_write_timestamp(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        query_pool,
+        0,
+    );
+}
+
+/// Layout to transfer a swapchain image expected in `layout` in, shared
+/// presentable images may be scanned out meanwhile and are never transitioned
+/// out of `SHARED_PRESENT_KHR`, which transfers are allowed in
+pub fn swapchain_transfer_layout(
+    layout: vk::ImageLayout,
+    transfer: vk::ImageLayout,
+) -> vk::ImageLayout {
+    match layout {
+        vk::ImageLayout::SHARED_PRESENT_KHR => layout,
+        _ => transfer,
+    }
+}
+
+/// Copies presented `src_image` into exportable `export_image`
+///
+/// Swapchain images cannot be exported without a copy: their memory is owned
+/// by the WSI implementation and never exposed as `VkDeviceMemory`, which
+/// `vkGetMemoryFdKHR` requires, and they are handed back to the app for
+/// rendering while consumers would still be reading them.
+///
+/// `src_image` is left in `src_layout` it is expected in, `PRESENT_SRC_KHR`
+/// or `SHARED_PRESENT_KHR` of shared presentable images, which are copied
+/// without a layout transition.
+/// `host_read` makes the copy visible to host reads of `export_image`.
+/// `timestamp` gets written once the copy starts, see `record_timestamp`.
+/// Blits land in `dst_region`, the rest of `export_image` is cleared to black
+/// if letterboxed.
 pub unsafe fn record_copy_image(
     ash_device: &ash::Device,
     command_buffer: vk::CommandBuffer,
     src_image: vk::Image,
+    src_layout: vk::ImageLayout,
     export_image: vk::Image,
     mut src_queue_family: u32,
     mut dst_queue_family: u32,
-    width: u32,
-    height: u32,
+    src_extent: vk::Extent2D,
+    dst_extent: vk::Extent2D,
+    dst_region: vk::Rect2D,
     need_blit: bool,
+    host_read: bool,
+    timestamp: Option<vk::QueryPool>,
 ) -> VkResult<()> {
     if src_queue_family == dst_queue_family {
         src_queue_family = vk::QUEUE_FAMILY_IGNORED;
         dst_queue_family = vk::QUEUE_FAMILY_IGNORED;
     }
+    let copy_layout = swapchain_transfer_layout(src_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
 
-    let begin_info =
-        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    // submitted again on later presents
+    let begin_info = vk::CommandBufferBeginInfo::builder();
     ash_device.begin_command_buffer(command_buffer, &begin_info)?;
+    if let Some(query_pool) = timestamp {
+        record_timestamp(ash_device, command_buffer, query_pool);
+    }
 
     let subresource = vk::ImageSubresourceRange::builder()
         .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -248,8 +691,8 @@ pub unsafe fn record_copy_image(
         .build();
 
     let src_barrier = vk::ImageMemoryBarrier::builder()
-        .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .old_layout(src_layout)
+        .new_layout(copy_layout)
         .src_queue_family_index(src_queue_family)
         .dst_queue_family_index(dst_queue_family)
         .image(src_image)
@@ -301,21 +744,64 @@ pub unsafe fn record_copy_image(
             .layer_count(1)
             .build();
 
+        if dst_region.extent != dst_extent {
+            let clear_color = vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 1.0],
+            };
+            ash_device.cmd_clear_color_image(
+                command_buffer,
+                export_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &clear_color,
+                &[subresource],
+            );
+            // blit after the clear
+            let barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(export_image)
+                .subresource_range(subresource)
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .build();
+            ash_device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+        }
+
+        // scaling
+        let filter = if src_extent != dst_region.extent {
+            vk::Filter::LINEAR
+        } else {
+            vk::Filter::NEAREST
+        };
         let image_blit = vk::ImageBlit::builder()
             .src_offsets([
                 vk::Offset3D { x: 0, y: 0, z: 0 },
                 vk::Offset3D {
-                    x: width as _,
-                    y: height as _,
+                    x: src_extent.width as _,
+                    y: src_extent.height as _,
                     z: 1,
                 },
             ])
             .src_subresource(src_subresource)
             .dst_offsets([
-                vk::Offset3D { x: 0, y: 0, z: 0 },
                 vk::Offset3D {
-                    x: width as _,
-                    y: height as _,
+                    x: dst_region.offset.x,
+                    y: dst_region.offset.y,
+                    z: 0,
+                },
+                vk::Offset3D {
+                    x: dst_region.offset.x + dst_region.extent.width as i32,
+                    y: dst_region.offset.y + dst_region.extent.height as i32,
                     z: 1,
                 },
             ])
@@ -325,17 +811,17 @@ pub unsafe fn record_copy_image(
         ash_device.cmd_blit_image(
             command_buffer,
             src_image,
-            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            copy_layout,
             export_image,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             &[image_blit],
-            vk::Filter::NEAREST,
+            filter,
         )
     } else {
         let image_copy = vk::ImageCopy::builder()
             .extent(vk::Extent3D {
-                width,
-                height,
+                width: src_extent.width,
+                height: src_extent.height,
                 depth: 1,
             })
             .src_subresource(subresource_layer)
@@ -344,7 +830,7 @@ pub unsafe fn record_copy_image(
         ash_device.cmd_copy_image(
             command_buffer,
             src_image,
-            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            copy_layout,
             export_image,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             &[image_copy],
@@ -352,8 +838,8 @@ pub unsafe fn record_copy_image(
     }
 
     let src_barrier = vk::ImageMemoryBarrier::builder()
-        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .old_layout(copy_layout)
+        .new_layout(src_layout)
         .src_queue_family_index(dst_queue_family)
         .dst_queue_family_index(src_queue_family)
         .image(src_image)
@@ -370,13 +856,20 @@ pub unsafe fn record_copy_image(
         .image(export_image)
         .subresource_range(subresource)
         .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-        .dst_access_mask(vk::AccessFlags::empty())
+        .dst_access_mask(match host_read {
+            true => vk::AccessFlags::HOST_READ,
+            false => vk::AccessFlags::empty(),
+        })
         .build();
 
+    let dst_stage = match host_read {
+        true => vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::HOST,
+        false => vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+    };
     ash_device.cmd_pipeline_barrier(
         command_buffer,
         vk::PipelineStageFlags::TRANSFER,
-        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        dst_stage,
         vk::DependencyFlags::empty(),
         &[],
         &[],
@@ -387,3 +880,38 @@ pub unsafe fn record_copy_image(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ash::vk::Handle;
+
+    fn pool(image_count: usize, len: usize) -> SemaphorePool {
+        SemaphorePool {
+            slots: (0..len)
+                .map(|i| PresentSlot {
+                    semaphore: vk::Semaphore::from_raw(i as u64 + 1),
+                    presented: None,
+                })
+                .collect(),
+            acquires: vec![0; image_count],
+            max_len: len,
+            allocator: Allocator::default(),
+        }
+    }
+
+    #[test]
+    fn semaphore_reused_after_acquire() {
+        let mut pool = pool(2, 2);
+        pool.acquired(0);
+        let first = pool.take_free(0).unwrap();
+        pool.acquired(1);
+        assert_ne!(pool.take_free(1), Some(first));
+        // neither image got acquired again, e.g. presents got replaced
+        assert_eq!(pool.take_free(1), None);
+
+        pool.acquired(0);
+        assert_eq!(pool.take_free(0), Some(first));
+    }
+}
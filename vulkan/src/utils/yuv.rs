@@ -0,0 +1,497 @@
+use crate::utils::*;
+
+use core::ffi::CStr;
+use std::io::Cursor;
+
+use anyhow::{anyhow, Result};
+use ash::prelude::VkResult;
+use ash::vk;
+
+// assembled from rgb_to_nv12.spvasm, e.g. with `spirv-as --target-env vulkan1.0`
+const RGB_TO_NV12_SPV: &[u8] = include_bytes!("../shaders/rgb_to_nv12.spv");
+const LOCAL_SIZE: u32 = 8;
+
+pub const NV12_FORMAT: vk::Format = vk::Format::G8_B8R8_2PLANE_420_UNORM;
+const NV12_PLANE_FORMATS: [vk::Format; 2] = [vk::Format::R8_UNORM, vk::Format::R8G8_UNORM];
+/// Formats NV12 export images are created with, one view per plane
+pub const NV12_VIEW_FORMATS: [vk::Format; 3] =
+    [NV12_FORMAT, NV12_PLANE_FORMATS[0], NV12_PLANE_FORMATS[1]];
+pub const NV12_IMAGE_FLAGS: vk::ImageCreateFlags = vk::ImageCreateFlags::from_raw(
+    vk::ImageCreateFlags::MUTABLE_FORMAT.as_raw() | vk::ImageCreateFlags::EXTENDED_USAGE.as_raw(),
+);
+
+/// Returns whether red and blue channels are swapped, i.e. BGRA, if `format`
+/// can be converted to NV12
+pub fn nv12_src_swap_rb(format: vk::Format) -> Option<bool> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => Some(false),
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => Some(true),
+        _ => None,
+    }
+}
+
+/// Per export image descriptors of the NV12 planes
+pub struct Nv12Target {
+    views: [vk::ImageView; 2],
+    descriptor_set: vk::DescriptorSet,
+}
+
+/// Compute pipeline converting swapchain images to NV12
+///
+/// Swapchain image is copied to an intermediate RGBA storage image first, as
+/// swapchain images can not be used as storage images in general.
+pub struct Nv12Converter {
+    shader: vk::ShaderModule,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    descriptor_pool: vk::DescriptorPool,
+    image: vk::Image,
+    memory: vk::DeviceMemory,
+    view: vk::ImageView,
+    width: u32,
+    height: u32,
+    allocator: Allocator,
+}
+
+impl Nv12Converter {
+    pub unsafe fn new(
+        ash_instance: &ash::Instance,
+        ash_device: &ash::Device,
+        phy_device: vk::PhysicalDevice,
+        src_format: vk::Format,
+        width: u32,
+        height: u32,
+        max_targets: u32,
+        allocator: Allocator,
+    ) -> Result<Self> {
+        let swap_rb = nv12_src_swap_rb(src_format)
+            .ok_or(anyhow!("can not convert {:?} to NV12", src_format))?;
+
+        let mut converter = Self {
+            shader: vk::ShaderModule::null(),
+            set_layout: vk::DescriptorSetLayout::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            image: vk::Image::null(),
+            memory: vk::DeviceMemory::null(),
+            view: vk::ImageView::null(),
+            width,
+            height,
+            allocator,
+        };
+        // frees partially created objects on error
+        if let Err(e) = converter.init(ash_instance, ash_device, phy_device, swap_rb, max_targets) {
+            converter.destroy(ash_device);
+            return Err(e);
+        }
+        Ok(converter)
+    }
+
+    unsafe fn init(
+        &mut self,
+        ash_instance: &ash::Instance,
+        ash_device: &ash::Device,
+        phy_device: vk::PhysicalDevice,
+        swap_rb: bool,
+        max_targets: u32,
+    ) -> Result<()> {
+        let allocator = self.allocator.callbacks();
+        let code = ash::util::read_spv(&mut Cursor::new(RGB_TO_NV12_SPV))?;
+        let shader_info = vk::ShaderModuleCreateInfo::builder().code(&code);
+        self.shader = ash_device.create_shader_module(&shader_info, allocator)?;
+
+        let bindings = (0..3)
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let set_layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        self.set_layout = ash_device.create_descriptor_set_layout(&set_layout_info, allocator)?;
+
+        let set_layouts = [self.set_layout];
+        let pipeline_layout_info =
+            vk::PipelineLayoutCreateInfo::builder().set_layouts(&set_layouts);
+        self.pipeline_layout =
+            ash_device.create_pipeline_layout(&pipeline_layout_info, allocator)?;
+
+        let swap_rb = swap_rb as vk::Bool32;
+        let map_entries = [vk::SpecializationMapEntry {
+            constant_id: 0,
+            offset: 0,
+            size: std::mem::size_of::<vk::Bool32>(),
+        }];
+        let spec_info = vk::SpecializationInfo::builder()
+            .map_entries(&map_entries)
+            .data(std::slice::from_raw_parts(
+                &swap_rb as *const _ as *const u8,
+                std::mem::size_of::<vk::Bool32>(),
+            ));
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(self.shader)
+            .name(CStr::from_bytes_with_nul_unchecked(b"main\0"))
+            .specialization_info(&spec_info)
+            .build();
+        let pipeline_info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(self.pipeline_layout)
+            .build();
+        self.pipeline = ash_device
+            .create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], allocator)
+            .map_err(|(_, e)| e)?[0];
+
+        let pool_sizes = [vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: max_targets * 3,
+        }];
+        let pool_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(max_targets)
+            .pool_sizes(&pool_sizes);
+        self.descriptor_pool = ash_device.create_descriptor_pool(&pool_info, allocator)?;
+
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R8G8B8A8_UNORM)
+            .extent(vk::Extent3D {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::STORAGE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        self.image = ash_device.create_image(&image_info, allocator)?;
+
+        let requirements = ash_device.get_image_memory_requirements(self.image);
+        let index = get_memory_type_indices(
+            ash_instance,
+            phy_device,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            requirements,
+        )
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("no memory type for intermediate image"))?;
+        let memory_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(index);
+        self.memory = ash_device.allocate_memory(&memory_info, allocator)?;
+        ash_device.bind_image_memory(self.image, self.memory, 0)?;
+
+        self.view = create_view(
+            ash_device,
+            self.image,
+            vk::Format::R8G8B8A8_UNORM,
+            vk::ImageAspectFlags::COLOR,
+            allocator,
+        )?;
+
+        Ok(())
+    }
+
+    /// Creates plane views of NV12 `image` and binds them to a descriptor set
+    pub unsafe fn create_target(
+        &self,
+        ash_device: &ash::Device,
+        image: vk::Image,
+    ) -> Result<Nv12Target> {
+        let allocator = self.allocator.callbacks();
+        let y_view = create_view(
+            ash_device,
+            image,
+            NV12_PLANE_FORMATS[0],
+            vk::ImageAspectFlags::PLANE_0,
+            allocator,
+        )?;
+        let uv_view = match create_view(
+            ash_device,
+            image,
+            NV12_PLANE_FORMATS[1],
+            vk::ImageAspectFlags::PLANE_1,
+            allocator,
+        ) {
+            Ok(v) => v,
+            Err(e) => {
+                ash_device.destroy_image_view(y_view, allocator);
+                return Err(e.into());
+            }
+        };
+
+        let set_layouts = [self.set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_set = match ash_device.allocate_descriptor_sets(&alloc_info) {
+            Ok(v) => v[0],
+            Err(e) => {
+                ash_device.destroy_image_view(y_view, allocator);
+                ash_device.destroy_image_view(uv_view, allocator);
+                return Err(e.into());
+            }
+        };
+
+        let image_infos = [self.view, y_view, uv_view].map(|view| {
+            [vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: view,
+                image_layout: vk::ImageLayout::GENERAL,
+            }]
+        });
+        let writes = image_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding as _)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(info)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        ash_device.update_descriptor_sets(&writes, &[]);
+
+        Ok(Nv12Target {
+            views: [y_view, uv_view],
+            descriptor_set,
+        })
+    }
+
+    pub unsafe fn destroy_target(&self, ash_device: &ash::Device, target: Nv12Target) {
+        let _ = ash_device.free_descriptor_sets(self.descriptor_pool, &[target.descriptor_set]);
+        for view in target.views {
+            ash_device.destroy_image_view(view, self.allocator.callbacks());
+        }
+    }
+
+    /// Records copy of `src_image` and conversion into `export_image`, mirrors
+    /// barriers and `timestamp` of `record_copy_image`
+    pub unsafe fn record(
+        &self,
+        ash_device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        src_image: vk::Image,
+        src_layout: vk::ImageLayout,
+        export_image: vk::Image,
+        target: &Nv12Target,
+        mut src_queue_family: u32,
+        mut dst_queue_family: u32,
+        timestamp: Option<vk::QueryPool>,
+    ) -> VkResult<()> {
+        if src_queue_family == dst_queue_family {
+            src_queue_family = vk::QUEUE_FAMILY_IGNORED;
+            dst_queue_family = vk::QUEUE_FAMILY_IGNORED;
+        }
+        let copy_layout =
+            swapchain_transfer_layout(src_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+        // submitted again on later presents
+        let begin_info = vk::CommandBufferBeginInfo::builder();
+        ash_device.begin_command_buffer(command_buffer, &begin_info)?;
+        if let Some(query_pool) = timestamp {
+            record_timestamp(ash_device, command_buffer, query_pool);
+        }
+
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        let src_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(src_layout)
+            .new_layout(copy_layout)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .image(src_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::MEMORY_READ)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build();
+
+        // previous conversion might still be reading it
+        let rgba_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[src_barrier, rgba_barrier],
+        );
+
+        let subresource_layer = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        // formats are size-compatible, channel order is handled in shader
+        let image_copy = vk::ImageCopy::builder()
+            .extent(vk::Extent3D {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            })
+            .src_subresource(subresource_layer)
+            .dst_subresource(subresource_layer)
+            .build();
+        ash_device.cmd_copy_image(
+            command_buffer,
+            src_image,
+            copy_layout,
+            self.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[image_copy],
+        );
+
+        let src_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(copy_layout)
+            .new_layout(src_layout)
+            .src_queue_family_index(dst_queue_family)
+            .dst_queue_family_index(src_queue_family)
+            .image(src_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+            .build();
+
+        let rgba_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build();
+
+        let dst_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(export_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[src_barrier, rgba_barrier, dst_barrier],
+        );
+
+        ash_device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline,
+        );
+        ash_device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+            0,
+            &[target.descriptor_set],
+            &[],
+        );
+        // one invocation per 2x2 block
+        let block = LOCAL_SIZE * 2;
+        ash_device.cmd_dispatch(
+            command_buffer,
+            (self.width + block - 1) / block,
+            (self.height + block - 1) / block,
+            1,
+        );
+
+        let dst_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(export_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::empty())
+            .build();
+
+        ash_device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[dst_barrier],
+        );
+
+        ash_device.end_command_buffer(command_buffer)?;
+
+        Ok(())
+    }
+
+    pub unsafe fn destroy(&self, ash_device: &ash::Device) {
+        let allocator = self.allocator.callbacks();
+        ash_device.destroy_image_view(self.view, allocator);
+        ash_device.destroy_image(self.image, allocator);
+        ash_device.free_memory(self.memory, allocator);
+        ash_device.destroy_descriptor_pool(self.descriptor_pool, allocator);
+        ash_device.destroy_pipeline(self.pipeline, allocator);
+        ash_device.destroy_pipeline_layout(self.pipeline_layout, allocator);
+        ash_device.destroy_descriptor_set_layout(self.set_layout, allocator);
+        ash_device.destroy_shader_module(self.shader, allocator);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::*;
+    use ash::vk;
+
+    #[test]
+    fn nv12_shader() {
+        let code = ash::util::read_spv(&mut std::io::Cursor::new(super::RGB_TO_NV12_SPV)).unwrap();
+        // magic and SPIR-V 1.0
+        assert_eq!(code[0], 0x07230203);
+        assert_eq!(code[1], 0x00010000);
+    }
+
+    #[test]
+    fn nv12_src_formats() {
+        assert_eq!(nv12_src_swap_rb(vk::Format::B8G8R8A8_SRGB), Some(true));
+        assert_eq!(nv12_src_swap_rb(vk::Format::R8G8B8A8_UNORM), Some(false));
+        assert_eq!(nv12_src_swap_rb(vk::Format::A2B10G10R10_UNORM_PACK32), None);
+    }
+}